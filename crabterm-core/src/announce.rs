@@ -0,0 +1,254 @@
+//! crabterm's own messages: the announcement template, and the colors that
+//! set them apart from device output on the local terminal.
+
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use crate::keybind::config::SettingValue;
+
+/// Template used for announcements unless `announce-template` is set.
+pub const DEFAULT_TEMPLATE: &str = "MSG-%s: %t %m\r\n";
+
+pub const SETTING_COLOR: &str = "color";
+pub const SETTING_THEME: &str = "color-theme";
+
+/// What a message of crabterm is about
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageKind {
+    /// Announcements and replies, e.g. "Filter timestamp on"
+    Info,
+    /// A device connected
+    Status,
+    /// Something went wrong, e.g. a device was lost
+    Error,
+}
+
+impl MessageKind {
+    /// The kind of the state change `msg` of a device: connecting is good
+    /// news, anything else, e.g. "/dev/ttyUSB0: No such file or directory",
+    /// is not.
+    pub fn of_status(msg: &str) -> Self {
+        if msg.contains(": Connected") {
+            MessageKind::Status
+        } else {
+            MessageKind::Error
+        }
+    }
+}
+
+/// SGR parameters of each kind of message, e.g. "1;31" for bold red
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Theme {
+    pub info: String,
+    pub status: String,
+    pub error: String,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme {
+            info: "36".to_string(),
+            status: "32".to_string(),
+            error: "1;31".to_string(),
+        }
+    }
+}
+
+impl Theme {
+    /// The default theme with the colors of `spec` instead, e.g.
+    /// "info=2 error=1;37;41".
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut theme = Theme::default();
+        for entry in spec.split([' ', ',']).filter(|e| !e.is_empty()) {
+            let (kind, sgr) = entry
+                .split_once('=')
+                .ok_or_else(|| format!("Invalid color: {} (KIND=SGR)", entry))?;
+            if !sgr.bytes().all(|b| b.is_ascii_digit() || b == b';') {
+                return Err(format!("Invalid color: {} (e.g. 1;31)", entry));
+            }
+            let slot = match kind {
+                "info" => &mut theme.info,
+                "status" => &mut theme.status,
+                "error" => &mut theme.error,
+                _ => {
+                    return Err(format!(
+                        "Unknown message kind: {} (info, status, error)",
+                        kind
+                    ));
+                }
+            };
+            *slot = sgr.to_string();
+        }
+        Ok(theme)
+    }
+
+    /// The theme of the config settings, None when colors are off: with
+    /// `set color off`, or when `NO_COLOR` is set.
+    pub fn from_settings(settings: &HashMap<String, SettingValue>) -> Result<Option<Self>, String> {
+        let off = settings.get(SETTING_COLOR).and_then(|v| v.as_bool()) == Some(false)
+            || std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+        if off {
+            return Ok(None);
+        }
+        match settings.get(SETTING_THEME).and_then(|v| v.as_str()) {
+            Some(spec) => Theme::parse(spec)
+                .map(Some)
+                .map_err(|e| format!("Invalid {}: {}", SETTING_THEME, e)),
+            None => Ok(Some(Theme::default())),
+        }
+    }
+
+    fn sgr(&self, kind: MessageKind) -> &str {
+        match kind {
+            MessageKind::Info => &self.info,
+            MessageKind::Status => &self.status,
+            MessageKind::Error => &self.error,
+        }
+    }
+}
+
+/// Colors of the messages on the local terminal; none until set
+static THEME: RwLock<Option<Theme>> = RwLock::new(None);
+
+/// Color the messages on the local terminal with `theme`, or not at all.
+pub fn set_theme(theme: Option<Theme>) {
+    *THEME.write().unwrap_or_else(|e| e.into_inner()) = theme;
+}
+
+/// `text` in the color of `kind`. The line end stays uncolored, so a
+/// background color does not fill the next line.
+pub fn colorize(kind: MessageKind, text: &str) -> String {
+    let theme = THEME.read().unwrap_or_else(|e| e.into_inner());
+    let Some(sgr) = theme
+        .as_ref()
+        .map(|t| t.sgr(kind))
+        .filter(|s| !s.is_empty())
+    else {
+        return text.to_string();
+    };
+    let body = text.trim_end_matches(['\r', '\n']);
+    format!("\x1b[{}m{}\x1b[0m{}", sgr, body, &text[body.len()..])
+}
+
+/// A message for the local terminal: the template expanded, in the color of
+/// `kind`.
+pub fn format_message(template: &str, source: &str, msg: &str, kind: MessageKind) -> String {
+    colorize(kind, &expand_template(template, source, msg))
+}
+
+pub fn expand_template(template: &str, source: &str, msg: &str) -> String {
+    let now = chrono::Local::now();
+    let mut expanded = String::new();
+    let mut chars = template.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c == '%' {
+            match chars.peek() {
+                Some('%') => {
+                    expanded.push('%');
+                    chars.next();
+                }
+                Some('m') => {
+                    expanded.push_str(msg);
+                    chars.next();
+                }
+                Some('s') => {
+                    expanded.push_str(source);
+                    chars.next();
+                }
+                Some('t') => {
+                    expanded.push_str(&now.format("%H:%M:%S").to_string());
+                    chars.next();
+                }
+                Some('d') => {
+                    expanded.push_str(&now.format("%Y-%m-%d").to_string());
+                    chars.next();
+                }
+                _ => expanded.push('%'),
+            }
+        } else {
+            expanded.push(c);
+        }
+    }
+    expanded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_template_simple() {
+        let template = "MSG-%s: %m";
+        let source = "Local";
+        let msg = "Hello world";
+        let expanded = expand_template(template, source, msg);
+        assert!(expanded.starts_with("MSG-Local: Hello world"));
+    }
+
+    #[test]
+    fn test_expand_template_with_time() {
+        let template = "MSG-%s: %t %m";
+        let source = "127.0.0.1:4000";
+        let msg = "Connected";
+        let expanded = expand_template(template, source, msg);
+        // MSG-127.0.0.1:4000: 14:55:01 Connected
+        assert!(expanded.contains("MSG-127.0.0.1:4000: "));
+        assert!(expanded.contains(" Connected"));
+        // Check for HH:MM:SS format (at least 2 colons in the time part)
+        // Total colons should be 1 (in source) + 1 (after source) + 2 (in time) = 4
+        assert!(expanded.chars().filter(|&c| c == ':').count() >= 4);
+    }
+
+    #[test]
+    fn test_expand_template_percent() {
+        let template = "%% %m";
+        let source = "Local";
+        let msg = "Message";
+        let expanded = expand_template(template, source, msg);
+        assert_eq!(expanded, "% Message");
+    }
+
+    #[test]
+    fn test_theme() {
+        let theme = Theme::parse("info=2, error=1;37;41").unwrap();
+        assert_eq!(theme.info, "2");
+        assert_eq!(theme.status, Theme::default().status);
+        assert_eq!(theme.error, "1;37;41");
+        assert!(Theme::parse("warning=33").is_err());
+        assert!(Theme::parse("info=red").is_err());
+
+        let mut settings = HashMap::new();
+        settings.insert(SETTING_COLOR.to_string(), SettingValue::Bool(false));
+        assert_eq!(Theme::from_settings(&settings), Ok(None));
+
+        assert_eq!(
+            MessageKind::of_status("/dev/ttyUSB0: Connected"),
+            MessageKind::Status
+        );
+        assert_eq!(
+            MessageKind::of_status("/dev/ttyUSB0: No such file or directory"),
+            MessageKind::Error
+        );
+    }
+
+    #[test]
+    fn test_colorize() {
+        set_theme(Some(Theme::default()));
+        assert_eq!(
+            format_message("[%m]\r\n", "Local", "lost", MessageKind::Error),
+            "\x1b[1;31m[lost]\x1b[0m\r\n"
+        );
+        set_theme(None);
+        assert_eq!(colorize(MessageKind::Info, "ok\r\n"), "ok\r\n");
+    }
+
+    #[test]
+    fn test_expand_template_multiple() {
+        let template = "%s %s %m %m";
+        let source = "S";
+        let msg = "M";
+        let expanded = expand_template(template, source, msg);
+        assert_eq!(expanded, "S S M M");
+    }
+}
@@ -0,0 +1,138 @@
+//! Greeting of TCP clients (`--banner`, `banner-file`): lines sent once to
+//! each client that connects, before the device output, so a remote user
+//! knows what they attached to. Unlike announcements they are sent whatever
+//! `--announce` says.
+//!
+//! ```text
+//! set banner "Lab board %l (%a, %b baud), %r\n"
+//! set banner-file "/etc/crabterm/motd"
+//! ```
+//!
+//! The template has `%l` (the label of the device), `%a` (its address), `%b`
+//! (its baudrate, `-` when it has none), `%r` (the rules for the client, e.g.
+//! `read-only`), `%v` (the version of crabterm) and `%%`. The file, e.g. a
+//! message of the day, follows it, and is read again for every client so it
+//! can be changed while crabterm runs.
+
+use log::warn;
+use std::path::PathBuf;
+
+pub const SETTING: &str = "banner";
+pub const SETTING_FILE: &str = "banner-file";
+
+/// What the banner tells a client about
+#[derive(Debug, Clone, Copy)]
+pub struct BannerInfo<'a> {
+    pub label: &'a str,
+    pub addr: &'a str,
+    pub baudrate: Option<u32>,
+    pub rules: &'a [&'a str],
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Banner {
+    template: Option<String>,
+    file: Option<PathBuf>,
+}
+
+impl Banner {
+    /// None when there is neither a template nor a file.
+    pub fn new(template: Option<String>, file: Option<PathBuf>) -> Option<Self> {
+        (template.is_some() || file.is_some()).then_some(Banner { template, file })
+    }
+
+    /// The banner for a client, each line ended with `line_end`.
+    pub fn render(&self, info: &BannerInfo, line_end: &str) -> String {
+        let mut text = self
+            .template
+            .as_deref()
+            .map(|t| expand(t, info))
+            .unwrap_or_default();
+        if let Some(path) = &self.file {
+            match std::fs::read_to_string(path) {
+                Ok(motd) => {
+                    if !text.is_empty() && !text.ends_with('\n') {
+                        text.push('\n');
+                    }
+                    text.push_str(&motd);
+                }
+                Err(e) => warn!("{} {}: {}", SETTING_FILE, path.display(), e),
+            }
+        }
+        text.lines()
+            .map(|line| format!("{}{}", line.trim_end_matches('\r'), line_end))
+            .collect()
+    }
+}
+
+fn expand(template: &str, info: &BannerInfo) -> String {
+    let mut expanded = String::new();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('%', Some('l')) => expanded.push_str(info.label),
+            ('%', Some('a')) => expanded.push_str(info.addr),
+            ('%', Some('b')) => match info.baudrate {
+                Some(baud) => expanded.push_str(&baud.to_string()),
+                None => expanded.push('-'),
+            },
+            ('%', Some('r')) => expanded.push_str(&info.rules.join(", ")),
+            ('%', Some('v')) => expanded.push_str(env!("CARGO_PKG_VERSION")),
+            ('%', Some('%')) => expanded.push('%'),
+            // From the command line, where the shell keeps it as it is
+            ('\\', Some('n')) => expanded.push('\n'),
+            _ => {
+                expanded.push(c);
+                continue;
+            }
+        }
+        chars.next();
+    }
+    expanded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INFO: BannerInfo = BannerInfo {
+        label: "usb0",
+        addr: "/dev/ttyUSB0",
+        baudrate: Some(115200),
+        rules: &["read-only", "write lock"],
+    };
+
+    #[test]
+    fn test_template() {
+        let banner = Banner::new(Some("Board %l (%a, %b baud): %r\\n100%%".into()), None).unwrap();
+        assert_eq!(
+            banner.render(&INFO, "\r\n"),
+            "Board usb0 (/dev/ttyUSB0, 115200 baud): read-only, write lock\r\n100%\r\n"
+        );
+        let info = BannerInfo {
+            baudrate: None,
+            ..INFO
+        };
+        assert_eq!(
+            Banner::new(Some("%b %x".into()), None)
+                .unwrap()
+                .render(&info, "\n"),
+            "- %x\n"
+        );
+        assert!(Banner::new(None, None).is_none());
+    }
+
+    #[test]
+    fn test_file() {
+        let path = std::env::temp_dir().join(format!("crabterm_motd_{}", std::process::id()));
+        std::fs::write(&path, "Reserved for the nightly tests\n").unwrap();
+        let banner = Banner::new(Some("Board %l".into()), Some(path.clone())).unwrap();
+        assert_eq!(
+            banner.render(&INFO, "\r\n"),
+            "Board usb0\r\nReserved for the nightly tests\r\n"
+        );
+        let _ = std::fs::remove_file(&path);
+        // Gone, the template is still sent
+        assert_eq!(banner.render(&INFO, "\r\n"), "Board usb0\r\n");
+    }
+}
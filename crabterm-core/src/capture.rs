@@ -0,0 +1,613 @@
+//! Capture of device output to a file, as text or, for files named
+//! `*.pcapng`, as the traffic in both directions (see [`crate::pcapng`]).
+
+use log::{error, info};
+use regex::Regex;
+use std::fs::{File, OpenOptions};
+use std::io::{Result, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::clock;
+use crate::iofilter::FilterChain;
+use crate::pcapng::{Direction, Pcapng};
+
+/// A compressed capture is written in frames (gzip members, zstd frames) of
+/// at most this much time, so a crash loses at most this much output.
+pub const FRAME_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How capture files are compressed (`--capture-compress`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "none" => Some(Compression::None),
+            "gzip" => Some(Compression::Gzip),
+            "zstd" => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+
+    /// File name extension, e.g. ".gz"
+    pub fn extension(self) -> &'static str {
+        match self {
+            Compression::None => "",
+            Compression::Gzip => ".gz",
+            Compression::Zstd => ".zst",
+        }
+    }
+}
+
+/// What a capture file holds (`--capture-format`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    /// The device output, with the capture filters applied
+    #[default]
+    Text,
+    /// The raw traffic in both directions, for Wireshark
+    Pcapng,
+}
+
+impl Format {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "text" => Some(Format::Text),
+            "pcapng" => Some(Format::Pcapng),
+            _ => None,
+        }
+    }
+
+    /// The format of a capture to `path`: pcapng for `*.pcapng`, also
+    /// compressed, e.g. `x.pcapng.gz`.
+    pub fn of(path: &Path) -> Self {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let name = name
+            .strip_suffix(Compression::Gzip.extension())
+            .or_else(|| name.strip_suffix(Compression::Zstd.extension()))
+            .unwrap_or(&name);
+        if name.ends_with(".pcapng") {
+            Format::Pcapng
+        } else {
+            Format::Text
+        }
+    }
+
+    /// File name extension, e.g. ".log"
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Text => ".log",
+            Format::Pcapng => ".pcapng",
+        }
+    }
+}
+
+enum Frame {
+    Gzip(flate2::write::GzEncoder<Vec<u8>>),
+    Zstd(zstd::Encoder<'static, Vec<u8>>),
+}
+
+/// The capture file. Compressed output is collected in a frame, which is
+/// appended to the file when it is finished; the frames of a file
+/// decompress as one stream (`zcat`, `zstdcat`).
+struct Output {
+    file: File,
+    compression: Compression,
+    frame: Option<(Frame, Instant)>,
+}
+
+impl Output {
+    fn write_all(&mut self, buf: &[u8]) -> Result<()> {
+        if self.compression == Compression::None {
+            return self.file.write_all(buf);
+        }
+        let (frame, _) = match &mut self.frame {
+            Some(f) => f,
+            None => {
+                let frame = match self.compression {
+                    Compression::Gzip => Frame::Gzip(flate2::write::GzEncoder::new(
+                        Vec::new(),
+                        flate2::Compression::default(),
+                    )),
+                    _ => Frame::Zstd(zstd::Encoder::new(Vec::new(), 0)?),
+                };
+                self.frame.insert((frame, clock::now()))
+            }
+        };
+        match frame {
+            Frame::Gzip(e) => e.write_all(buf),
+            Frame::Zstd(e) => e.write_all(buf),
+        }
+    }
+
+    /// Finish the open frame, if any, and append it to the file.
+    fn finish_frame(&mut self) -> Result<()> {
+        let data = match self.frame.take() {
+            Some((Frame::Gzip(e), _)) => e.finish()?,
+            Some((Frame::Zstd(e), _)) => e.finish()?,
+            None => return Ok(()),
+        };
+        self.file.write_all(&data)
+    }
+}
+
+/// Appends everything read from the device to a file.
+pub struct Capture {
+    out: Output,
+    /// Set for pcapng captures
+    pcapng: Option<Pcapng>,
+    path: PathBuf,
+    bytes: u64,
+    filter: Option<FilterChain>,
+    // Filtered output, reused between writes
+    filtered: Vec<u8>,
+    /// The last output written ended a line, for input records
+    at_line_start: bool,
+}
+
+impl Capture {
+    /// Open `path` for appending, creating it if needed. The format
+    /// follows the name, see [`Format::of`].
+    pub fn new(path: &Path) -> Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        info!("Capture started: {}", path.display());
+        Ok(Capture {
+            out: Output {
+                file,
+                compression: Compression::None,
+                frame: None,
+            },
+            pcapng: (Format::of(path) == Format::Pcapng).then(Pcapng::new),
+            path: path.to_path_buf(),
+            bytes: 0,
+            filter: None,
+            filtered: Vec::new(),
+            at_line_start: true,
+        })
+    }
+
+    /// Pass device output through `filter` before it is written.
+    pub fn with_filter(mut self, filter: FilterChain) -> Self {
+        self.filter = Some(filter);
+        self
+    }
+
+    /// Compress what is written from now on.
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.finish_frame();
+        self.out.compression = compression;
+        self
+    }
+
+    /// When the open frame of a compressed capture is to be finished
+    pub fn next_flush(&self) -> Option<Instant> {
+        self.out.frame.as_ref().map(|(_, t)| *t + FRAME_INTERVAL)
+    }
+
+    /// Finish the open frame if it is due, see [`FRAME_INTERVAL`].
+    pub fn tick(&mut self) {
+        if self.next_flush().is_some_and(|t| t <= clock::now()) {
+            self.finish_frame();
+        }
+    }
+
+    fn finish_frame(&mut self) {
+        if let Err(e) = self.out.finish_frame() {
+            error!("Capture {}: write error: {}", self.path.display(), e);
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Bytes written since the capture was started.
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    /// Output of the device labelled `device`: `raw` as read, and `shown`
+    /// as the clients get it, e.g. with a device prefix on each line.
+    pub fn write_output(&mut self, device: &str, raw: &[u8], shown: &[u8]) {
+        if self.pcapng.is_some() {
+            self.write_packet(device, Direction::Rx, raw);
+        } else {
+            self.write(shown);
+        }
+    }
+
+    /// Input written to the device labelled `device`; only pcapng captures
+    /// record it all.
+    pub fn write_tx(&mut self, device: &str, buf: &[u8]) {
+        if self.pcapng.is_some() {
+            self.write_packet(device, Direction::Tx, buf);
+        }
+    }
+
+    fn write_packet(&mut self, device: &str, direction: Direction, data: &[u8]) {
+        let Some(pcapng) = &mut self.pcapng else {
+            return;
+        };
+        if data.is_empty() {
+            return;
+        }
+        self.filtered.clear();
+        pcapng.packet(
+            &mut self.filtered,
+            device,
+            direction,
+            SystemTime::now(),
+            data,
+        );
+        match self.out.write_all(&self.filtered) {
+            Ok(()) => self.bytes += self.filtered.len() as u64,
+            Err(e) => error!("Capture {}: write error: {}", self.path.display(), e),
+        }
+    }
+
+    /// Device output for a text capture.
+    pub fn write(&mut self, buf: &[u8]) {
+        let buf = match &mut self.filter {
+            Some(f) => {
+                self.filtered.clear();
+                f.filter_out_into(buf, &mut self.filtered);
+                &self.filtered
+            }
+            None => buf,
+        };
+        if let Some(&last) = buf.last() {
+            self.at_line_start = last == b'\n';
+        }
+        match self.out.write_all(buf) {
+            Ok(()) => self.bytes += buf.len() as u64,
+            Err(e) => error!("Capture {}: write error: {}", self.path.display(), e),
+        }
+    }
+
+    /// Record input sent to the device by `source` on a line of its own,
+    /// e.g. `[input 12:00:01.250 127.0.0.1:5000] "reboot\r"`. Not for
+    /// pcapng captures, which have all input, see [`Self::write_tx`].
+    pub fn write_input(&mut self, source: &str, buf: &[u8]) {
+        if self.pcapng.is_some() {
+            return;
+        }
+        let record = format!(
+            "{}[input {} {}] {}\n",
+            if self.at_line_start { "" } else { "\n" },
+            chrono::Local::now().format("%H:%M:%S%.3f"),
+            source,
+            escape_input(buf)
+        );
+        self.at_line_start = true;
+        match self.out.write_all(record.as_bytes()) {
+            Ok(()) => self.bytes += record.len() as u64,
+            Err(e) => error!("Capture {}: write error: {}", self.path.display(), e),
+        }
+    }
+
+    /// Record a note on a line of its own, e.g.
+    /// `=== NOTE 2024-05-01 12:00:01: started test 42 ===`. Not for pcapng
+    /// captures; the log has the note too.
+    pub fn write_note(&mut self, text: &str) {
+        self.write_mark("NOTE", text);
+    }
+
+    /// Record a marker of external tooling, like [`Self::write_note`].
+    pub fn write_marker(&mut self, text: &str) {
+        self.write_mark("MARKER", text);
+    }
+
+    fn write_mark(&mut self, kind: &str, text: &str) {
+        if self.pcapng.is_some() {
+            return;
+        }
+        let record = format!(
+            "{}{}\n",
+            if self.at_line_start { "" } else { "\n" },
+            mark_line(kind, text)
+        );
+        self.at_line_start = true;
+        match self.out.write_all(record.as_bytes()) {
+            Ok(()) => self.bytes += record.len() as u64,
+            Err(e) => error!("Capture {}: write error: {}", self.path.display(), e),
+        }
+    }
+}
+
+/// `text` as a line of its own among the output, with the time, e.g.
+/// `=== MARKER 2024-05-01 12:00:01: flashing build 1234 ===`.
+pub fn mark_line(kind: &str, text: &str) -> String {
+    format!(
+        "=== {} {}: {} ===",
+        kind,
+        chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
+        text
+    )
+}
+
+/// The file `--capture-auto` starts in `dir` when the device labelled
+/// `label` connects at `time`: `<label>-<YYYYmmdd-HHMMSS>.log` (or
+/// `.pcapng`), with the extension of `compression` added.
+pub fn auto_path(
+    dir: &Path,
+    label: &str,
+    time: &chrono::DateTime<chrono::Local>,
+    format: Format,
+    compression: Compression,
+) -> PathBuf {
+    let label: String = label
+        .chars()
+        .map(|c| {
+            if c == '/' || c.is_whitespace() {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect();
+    dir.join(format!(
+        "{}-{}{}{}",
+        label,
+        time.format("%Y%m%d-%H%M%S"),
+        format.extension(),
+        compression.extension()
+    ))
+}
+
+/// The file a capture is split into on boot `boot` of the device labelled
+/// `label` (`--capture-split`): `<label>-boot<N>-<YYYYmmdd-HHMMSS>.log`.
+pub fn boot_path(
+    dir: &Path,
+    label: &str,
+    boot: u64,
+    time: &chrono::DateTime<chrono::Local>,
+    format: Format,
+    compression: Compression,
+) -> PathBuf {
+    auto_path(
+        dir,
+        &format!("{}-boot{}", label, boot),
+        time,
+        format,
+        compression,
+    )
+}
+
+/// Longest line matched against the boot marker; the rest is ignored
+const MAX_MARKER_LINE: usize = 1024;
+
+/// Finds the line of device output where a boot starts, e.g. the one with
+/// "U-Boot SPL", so the capture can be split there (`--capture-split`).
+pub struct BootMarker {
+    pattern: Regex,
+    /// The line so far
+    line: Vec<u8>,
+    /// The line has matched already
+    matched: bool,
+}
+
+impl BootMarker {
+    pub fn new(pattern: Regex) -> Self {
+        BootMarker {
+            pattern,
+            line: Vec::new(),
+            matched: false,
+        }
+    }
+
+    /// Feed device output. Returns where in `buf` the line with the marker
+    /// starts (0 if it started in earlier output), the first time the
+    /// marker is seen in a line. Matched as the line comes in, so the split
+    /// does not wait for the end of the line.
+    pub fn feed(&mut self, buf: &[u8]) -> Option<usize> {
+        let mut found = None;
+        let mut start = 0;
+        for segment in buf.split_inclusive(|&b| b == b'\n') {
+            let room = MAX_MARKER_LINE.saturating_sub(self.line.len());
+            self.line.extend(
+                segment
+                    .iter()
+                    .take(room)
+                    .filter(|&&b| b != b'\r' && b != b'\n'),
+            );
+            if !self.matched && self.pattern.is_match(&String::from_utf8_lossy(&self.line)) {
+                self.matched = true;
+                found = found.or(Some(start));
+            }
+            if segment.ends_with(b"\n") {
+                self.line.clear();
+                self.matched = false;
+            }
+            start += segment.len();
+        }
+        found
+    }
+}
+
+/// Input as a quoted string with control characters escaped, as it is
+/// recorded in captures and the log.
+pub fn escape_input(buf: &[u8]) -> String {
+    format!("{:?}", String::from_utf8_lossy(buf))
+}
+
+impl Drop for Capture {
+    fn drop(&mut self) {
+        self.finish_frame();
+        let _ = self.out.file.flush();
+        info!(
+            "Capture stopped: {} ({} bytes)",
+            self.path.display(),
+            self.bytes
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_auto_path() {
+        let time = chrono::Local.with_ymd_and_hms(2026, 3, 7, 9, 5, 1).unwrap();
+        assert_eq!(
+            auto_path(
+                Path::new("/tmp/caps"),
+                "usb0",
+                &time,
+                Format::Text,
+                Compression::None
+            ),
+            PathBuf::from("/tmp/caps/usb0-20260307-090501.log")
+        );
+        assert_eq!(
+            auto_path(
+                Path::new("caps"),
+                "a/b c",
+                &time,
+                Format::Text,
+                Compression::Zstd
+            ),
+            PathBuf::from("caps/a_b_c-20260307-090501.log.zst")
+        );
+        assert_eq!(
+            auto_path(
+                Path::new("caps"),
+                "usb0",
+                &time,
+                Format::Pcapng,
+                Compression::Gzip
+            ),
+            PathBuf::from("caps/usb0-20260307-090501.pcapng.gz")
+        );
+    }
+
+    #[test]
+    fn test_boot_marker() {
+        let mut m = BootMarker::new(Regex::new("U-Boot SPL|Booting Linux").unwrap());
+        assert_eq!(m.feed(b"login: \r\nU-Boot"), None);
+        // The line started in the earlier output
+        assert_eq!(m.feed(b" SPL 2024.01\r\nmore"), Some(0));
+        assert_eq!(m.feed(b" SPL\r\n"), None);
+        assert_eq!(
+            m.feed(b"x\r\nStarting kernel\r\n[0.0] Booting Linux on"),
+            Some(20)
+        );
+        // Once per line
+        assert_eq!(m.feed(b" cpu 0, Booting Linux"), None);
+        assert_eq!(m.feed(b"\r\nU-Boot SPL\nU-Boot SPL\n"), Some(2));
+    }
+
+    #[test]
+    fn test_boot_path() {
+        let time = chrono::Local.with_ymd_and_hms(2026, 3, 7, 9, 5, 1).unwrap();
+        assert_eq!(
+            boot_path(
+                Path::new("caps"),
+                "usb0",
+                3,
+                &time,
+                Format::Text,
+                Compression::Gzip
+            ),
+            PathBuf::from("caps/usb0-boot3-20260307-090501.log.gz")
+        );
+    }
+
+    /// Write two frames (and so two captures appending to one file) and
+    /// decompress the file as a whole.
+    fn compressed(compression: Compression) -> Vec<u8> {
+        let path = std::env::temp_dir().join(format!(
+            "crabterm-capture-{}{}",
+            std::process::id(),
+            compression.extension()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let mut c = Capture::new(&path).unwrap().with_compression(compression);
+        c.write(b"first ");
+        assert!(c.next_flush().is_some());
+        c.finish_frame();
+        assert!(c.next_flush().is_none());
+        c.write(b"second ");
+        drop(c);
+        let mut c = Capture::new(&path).unwrap().with_compression(compression);
+        c.write(b"third");
+        drop(c);
+        let data = std::fs::read(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        data
+    }
+
+    #[test]
+    fn test_gzip() {
+        use std::io::Read;
+        let mut out = Vec::new();
+        flate2::read::MultiGzDecoder::new(&compressed(Compression::Gzip)[..])
+            .read_to_end(&mut out)
+            .unwrap();
+        assert_eq!(out, b"first second third");
+    }
+
+    #[test]
+    fn test_zstd() {
+        let out = zstd::decode_all(&compressed(Compression::Zstd)[..]).unwrap();
+        assert_eq!(out, b"first second third");
+    }
+
+    #[test]
+    fn test_note() {
+        let path =
+            std::env::temp_dir().join(format!("crabterm-capture-{}-note.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut c = Capture::new(&path).unwrap();
+        c.write(b"U-Boot> ");
+        c.write_note("started test 42");
+        c.write(b"boot\r\n");
+        drop(c);
+        let text = std::fs::read_to_string(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        let (before, note) = text.split_once('\n').unwrap();
+        assert_eq!(before, "U-Boot> ");
+        assert!(note.starts_with("=== NOTE "), "{}", note);
+        assert!(
+            note.ends_with(": started test 42 ===\nboot\r\n"),
+            "{}",
+            note
+        );
+    }
+
+    #[test]
+    fn test_format_of() {
+        assert_eq!(Format::of(Path::new("/tmp/a.log")), Format::Text);
+        assert_eq!(Format::of(Path::new("a.pcapng")), Format::Pcapng);
+        assert_eq!(Format::of(Path::new("a.pcapng.zst")), Format::Pcapng);
+        assert_eq!(Format::of(Path::new("pcapng")), Format::Text);
+    }
+
+    #[test]
+    fn test_pcapng() {
+        let path =
+            std::env::temp_dir().join(format!("crabterm-capture-{}.pcapng", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut c = Capture::new(&path).unwrap();
+        c.write_output("usb0", b"raw", b"[usb0] raw");
+        c.write_input("127.0.0.1:5000", b"ls\r");
+        c.write_tx("usb0", b"ls\r");
+        drop(c);
+        let data = std::fs::read(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        // The section header, the interface and two packets
+        assert_eq!(&data[..4], &[0x0a, 0x0d, 0x0d, 0x0a]);
+        let find = |needle: &[u8]| data.windows(needle.len()).any(|w| w == needle);
+        assert!(find(b"usb0"));
+        assert!(find(b"raw"));
+        assert!(!find(b"[usb0] raw"));
+        assert_eq!(data.windows(3).filter(|w| w == b"ls\r").count(), 1);
+    }
+}
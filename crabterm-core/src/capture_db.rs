@@ -0,0 +1,335 @@
+//! Capture to a SQLite database (`--capture-db`): every line of device
+//! output is stored with its time, its device and its boot cycle, so a soak
+//! test of a month can be searched (`crabterm search`) instead of grepped.
+//!
+//! A boot cycle starts when crabterm starts, and at every line matching
+//! `--capture-split`. Boot cycles are numbered over all runs that used the
+//! database.
+
+use chrono::{DateTime, Local, TimeZone};
+use log::{error, info};
+use regex::Regex;
+use rusqlite::functions::FunctionFlags;
+use rusqlite::{Connection, params};
+use std::collections::HashMap;
+use std::io::Result;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::clock;
+
+pub const SETTING: &str = "capture-db";
+
+/// Longest line stored; longer ones are stored in pieces
+const MAX_LINE: usize = 4096;
+/// Lines are inserted in one transaction at most this long after the first
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+/// ... or when this many are waiting
+const MAX_PENDING: usize = 1000;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS boots (
+        id INTEGER PRIMARY KEY,
+        device TEXT NOT NULL,
+        time INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS lines (
+        id INTEGER PRIMARY KEY,
+        time INTEGER NOT NULL,
+        device TEXT NOT NULL,
+        boot INTEGER NOT NULL,
+        line TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS lines_time ON lines (time);
+    CREATE INDEX IF NOT EXISTS lines_boot ON lines (boot);
+";
+
+fn io_error(e: rusqlite::Error) -> std::io::Error {
+    std::io::Error::other(e)
+}
+
+/// Milliseconds since the epoch, as times are stored
+fn now_ms() -> i64 {
+    chrono::Utc::now().timestamp_millis()
+}
+
+/// The line in progress of one device
+struct DeviceLines {
+    boot: i64,
+    line: Vec<u8>,
+    /// When the first byte of the line came
+    started: i64,
+}
+
+struct Row {
+    time: i64,
+    device: String,
+    boot: i64,
+    line: String,
+}
+
+pub struct CaptureDb {
+    conn: Connection,
+    path: PathBuf,
+    devices: HashMap<String, DeviceLines>,
+    pending: Vec<Row>,
+    /// When the first of `pending` came
+    pending_since: Option<Instant>,
+}
+
+impl CaptureDb {
+    /// Open `path`, creating the database if needed.
+    pub fn open(path: &Path) -> Result<Self> {
+        let conn = Connection::open(path).map_err(io_error)?;
+        conn.execute_batch(SCHEMA).map_err(io_error)?;
+        info!("Capture database: {}", path.display());
+        Ok(CaptureDb {
+            conn,
+            path: path.to_path_buf(),
+            devices: HashMap::new(),
+            pending: Vec::new(),
+            pending_since: None,
+        })
+    }
+
+    /// Start a new boot cycle of `device`. The line in progress goes to the
+    /// new cycle. Returns its number.
+    pub fn boot(&mut self, device: &str) -> i64 {
+        let id = match self.conn.execute(
+            "INSERT INTO boots (device, time) VALUES (?1, ?2)",
+            params![device, now_ms()],
+        ) {
+            Ok(_) => self.conn.last_insert_rowid(),
+            Err(e) => {
+                error!("Capture database {}: {}", self.path.display(), e);
+                0
+            }
+        };
+        self.devices
+            .entry(device.to_string())
+            .or_insert_with(|| DeviceLines {
+                boot: 0,
+                line: Vec::new(),
+                started: 0,
+            })
+            .boot = id;
+        id
+    }
+
+    /// Device output of `device`.
+    pub fn write(&mut self, device: &str, buf: &[u8]) {
+        if buf.is_empty() {
+            return;
+        }
+        if !self.devices.contains_key(device) {
+            self.boot(device);
+        }
+        let lines = self.devices.get_mut(device).expect("booted");
+        for segment in buf.split_inclusive(|&b| b == b'\n') {
+            for &b in segment {
+                if b == b'\r' || b == b'\n' {
+                    continue;
+                }
+                if lines.line.is_empty() {
+                    lines.started = now_ms();
+                }
+                lines.line.push(b);
+                if lines.line.len() == MAX_LINE {
+                    self.pending.push(take_line(device, lines));
+                }
+            }
+            if segment.ends_with(b"\n") {
+                self.pending.push(take_line(device, lines));
+            }
+        }
+        if !self.pending.is_empty() {
+            self.pending_since.get_or_insert_with(Instant::now);
+        }
+        if self.pending.len() >= MAX_PENDING {
+            self.flush();
+        }
+    }
+
+    /// When lines are waiting to be inserted
+    pub fn next_flush(&self) -> Option<Instant> {
+        self.pending_since.map(|t| t + FLUSH_INTERVAL)
+    }
+
+    /// Insert the waiting lines if they are due.
+    pub fn tick(&mut self) {
+        if self.next_flush().is_some_and(|t| t <= clock::now()) {
+            self.flush();
+        }
+    }
+
+    fn flush(&mut self) {
+        self.pending_since = None;
+        let rows = std::mem::take(&mut self.pending);
+        if let Err(e) = self.insert(&rows) {
+            error!(
+                "Capture database {}: {} lines lost: {}",
+                self.path.display(),
+                rows.len(),
+                e
+            );
+        }
+    }
+
+    fn insert(&mut self, rows: &[Row]) -> rusqlite::Result<()> {
+        let tx = self.conn.transaction()?;
+        {
+            let mut stmt = tx.prepare_cached(
+                "INSERT INTO lines (time, device, boot, line) VALUES (?1, ?2, ?3, ?4)",
+            )?;
+            for row in rows {
+                stmt.execute(params![row.time, row.device, row.boot, row.line])?;
+            }
+        }
+        tx.commit()
+    }
+}
+
+fn take_line(device: &str, lines: &mut DeviceLines) -> Row {
+    let row = Row {
+        time: lines.started,
+        device: device.to_string(),
+        boot: lines.boot,
+        line: String::from_utf8_lossy(&lines.line).into_owned(),
+    };
+    lines.line.clear();
+    row
+}
+
+impl Drop for CaptureDb {
+    fn drop(&mut self) {
+        // Lines without a line end, e.g. a prompt
+        let partial: Vec<Row> = self
+            .devices
+            .iter_mut()
+            .filter(|(_, lines)| !lines.line.is_empty())
+            .map(|(device, lines)| take_line(device, lines))
+            .collect();
+        self.pending.extend(partial);
+        self.flush();
+    }
+}
+
+/// What `crabterm search` looks for
+pub struct Query {
+    pub pattern: Regex,
+    pub device: Option<String>,
+    pub boot: Option<i64>,
+}
+
+/// A line found by [`search`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Match {
+    pub time: DateTime<Local>,
+    pub device: String,
+    pub boot: i64,
+    pub line: String,
+}
+
+/// Call `found` for each line of the database at `path` that matches
+/// `query`, oldest first. Returns the number of lines found.
+pub fn search(path: &Path, query: &Query, mut found: impl FnMut(Match)) -> Result<usize> {
+    let conn = Connection::open_with_flags(path, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .map_err(io_error)?;
+    let pattern = query.pattern.clone();
+    conn.create_scalar_function(
+        "matches",
+        1,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        move |ctx| Ok(pattern.is_match(&ctx.get::<String>(0)?)),
+    )
+    .map_err(io_error)?;
+    let mut stmt = conn
+        .prepare(
+            "SELECT time, device, boot, line FROM lines
+             WHERE matches(line) AND (?1 IS NULL OR device = ?1) AND (?2 IS NULL OR boot = ?2)
+             ORDER BY id",
+        )
+        .map_err(io_error)?;
+    let mut rows = stmt
+        .query(params![query.device, query.boot])
+        .map_err(io_error)?;
+    let mut count = 0;
+    while let Some(row) = rows.next().map_err(io_error)? {
+        let ms: i64 = row.get(0).map_err(io_error)?;
+        found(Match {
+            time: Local.timestamp_millis_opt(ms).single().unwrap_or_default(),
+            device: row.get(1).map_err(io_error)?,
+            boot: row.get(2).map_err(io_error)?,
+            line: row.get(3).map_err(io_error)?,
+        });
+        count += 1;
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_db(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "crabterm_capture_db_{}_{}.db",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn find(path: &Path, pattern: &str, boot: Option<i64>) -> Vec<(String, i64, String)> {
+        let query = Query {
+            pattern: Regex::new(pattern).unwrap(),
+            device: None,
+            boot,
+        };
+        let mut found = Vec::new();
+        search(path, &query, |m| found.push((m.device, m.boot, m.line))).unwrap();
+        found
+    }
+
+    #[test]
+    fn test_lines_and_boots() {
+        let path = temp_db("boots");
+        {
+            let mut db = CaptureDb::open(&path).unwrap();
+            db.write("usb0", b"U-Boot 2024.01\r\nStarting ker");
+            db.write("usb1", b"other\r\n");
+            db.write("usb0", b"nel\r\n");
+            assert!(db.next_flush().is_some());
+            assert_eq!(db.boot("usb0"), 3);
+            db.write("usb0", b"U-Boot 2024.01\r\nlogin: ");
+        }
+        assert_eq!(
+            find(&path, "^U-Boot|login", None),
+            vec![
+                ("usb0".to_string(), 1, "U-Boot 2024.01".to_string()),
+                ("usb0".to_string(), 3, "U-Boot 2024.01".to_string()),
+                ("usb0".to_string(), 3, "login: ".to_string()),
+            ]
+        );
+        assert_eq!(find(&path, "kernel", None)[0].2, "Starting kernel");
+        assert_eq!(find(&path, ".", Some(2)).len(), 1);
+
+        // A second run goes on numbering the boots
+        CaptureDb::open(&path).unwrap().write("usb0", b"again\n");
+        assert_eq!(find(&path, "again", None)[0].1, 4);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_long_line() {
+        let path = temp_db("long");
+        CaptureDb::open(&path)
+            .unwrap()
+            .write("echo", &[b'x'; MAX_LINE + 10]);
+        let found = find(&path, "x", None);
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[1].2.len(), 10);
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,355 @@
+//! `crabterm bench DEVICE`: send a pseudo-random pattern to a device that
+//! echoes it (the echo device, a loopback plug, or another crabterm in echo
+//! mode), check what comes back, and report throughput, round-trip latency
+//! and errors, e.g. to validate a cable or a USB adapter.
+
+use mio::Token;
+use std::collections::VecDeque;
+use std::io::ErrorKind;
+use std::time::{Duration, Instant};
+
+use crate::poll::{Events, Poll};
+use crate::stats::format_duration;
+use crate::traits::{IoInstance, IoResult};
+
+const TOKEN: Token = Token(0);
+/// Chunks sent ahead of the echo, so a loopback without flow control is
+/// not overrun
+const IN_FLIGHT: usize = 4;
+/// Time allowed for a greeting before the first byte is sent, and for
+/// stray bytes after the last expected one
+const SETTLE: Duration = Duration::from_millis(200);
+
+pub struct Options {
+    /// Bytes to send
+    pub bytes: usize,
+    /// Bytes per write; latency is measured per chunk
+    pub chunk: usize,
+    /// Give up when nothing has come back for this long
+    pub timeout: Duration,
+}
+
+#[derive(Debug, Default)]
+pub struct Report {
+    /// Bytes that came before anything was sent, e.g. the announcements of
+    /// a remote crabterm; ignored
+    pub greeting: usize,
+    pub sent: usize,
+    /// Bytes of the pattern that came back, right or wrong
+    pub received: usize,
+    /// Bytes that came back different
+    pub corrupted: usize,
+    /// Offset of the first corrupted byte
+    pub first_error: Option<usize>,
+    /// Bytes after the end of the pattern
+    pub extra: usize,
+    pub elapsed: Duration,
+    /// Round trip of each chunk, from its write to its last byte coming back
+    pub latencies: Vec<Duration>,
+}
+
+impl Report {
+    pub fn missing(&self) -> usize {
+        self.sent.saturating_sub(self.received)
+    }
+
+    pub fn ok(&self) -> bool {
+        self.corrupted == 0 && self.missing() == 0 && self.extra == 0
+    }
+
+    /// Bytes per second that came back.
+    pub fn throughput(&self) -> f64 {
+        self.received as f64 / self.elapsed.as_secs_f64().max(1e-9)
+    }
+
+    pub fn print(&self, device: &str) {
+        println!("crabterm bench {}", device);
+        if self.greeting > 0 {
+            println!(
+                "  skipped     {} bytes received before sending",
+                self.greeting
+            );
+        }
+        println!(
+            "  sent        {} bytes, {} received in {}",
+            self.sent,
+            self.received,
+            format_elapsed(self.elapsed)
+        );
+        println!(
+            "  throughput  {:.0} bytes/s ({:.0} baud at 10 bits per byte)",
+            self.throughput(),
+            self.throughput() * 10.0
+        );
+        let mut sorted = self.latencies.clone();
+        sorted.sort();
+        if let Some(max) = sorted.last() {
+            println!(
+                "  latency     p50 {}, p90 {}, p99 {}, max {}",
+                format_ms(percentile(&sorted, 50.0)),
+                format_ms(percentile(&sorted, 90.0)),
+                format_ms(percentile(&sorted, 99.0)),
+                format_ms(*max)
+            );
+        }
+        print!(
+            "  errors      {} corrupted, {} missing, {} extra",
+            self.corrupted,
+            self.missing(),
+            self.extra
+        );
+        match self.first_error {
+            Some(offset) => println!(" (first at byte {})", offset),
+            None => println!(),
+        }
+        println!("{}", if self.ok() { "PASS" } else { "FAIL" });
+    }
+}
+
+fn format_ms(d: Duration) -> String {
+    format!("{:.3} ms", d.as_secs_f64() * 1000.0)
+}
+
+fn format_elapsed(d: Duration) -> String {
+    if d < Duration::from_secs(10) {
+        format!("{:.2} s", d.as_secs_f64())
+    } else {
+        format_duration(d)
+    }
+}
+
+/// The nearest-rank percentile `p` of `sorted`.
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// `len` bytes of xorshift32 output: every byte value, reproducible.
+fn pattern(len: usize) -> Vec<u8> {
+    let mut x: u32 = 0x2545_f491;
+    (0..len)
+        .map(|_| {
+            x ^= x << 13;
+            x ^= x >> 17;
+            x ^= x << 5;
+            (x >> 24) as u8
+        })
+        .collect()
+}
+
+/// Compares the echo with the pattern, byte by byte.
+struct Verifier<'a> {
+    expected: &'a [u8],
+    received: usize,
+    corrupted: usize,
+    first_error: Option<usize>,
+    extra: usize,
+}
+
+impl<'a> Verifier<'a> {
+    fn new(expected: &'a [u8]) -> Self {
+        Verifier {
+            expected,
+            received: 0,
+            corrupted: 0,
+            first_error: None,
+            extra: 0,
+        }
+    }
+
+    fn feed(&mut self, data: &[u8]) {
+        for &b in data {
+            match self.expected.get(self.received) {
+                Some(&e) => {
+                    if e != b {
+                        self.corrupted += 1;
+                        self.first_error.get_or_insert(self.received);
+                    }
+                    self.received += 1;
+                }
+                None => self.extra += 1,
+            }
+        }
+    }
+
+    fn done(&self) -> bool {
+        self.received == self.expected.len()
+    }
+}
+
+/// Connect `device`, waiting for connections in progress.
+fn connect(poll: &mut Poll, device: &mut dyn IoInstance, timeout: Duration) -> Result<(), String> {
+    let mut events = Events::with_capacity(16);
+    let deadline = Instant::now() + timeout;
+    loop {
+        match device.connect(poll, TOKEN) {
+            Ok(()) if device.connected() => return Ok(()),
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+            Err(e) => return Err(format!("connect: {}", e)),
+        }
+        if Instant::now() > deadline {
+            return Err("connect: timeout".to_string());
+        }
+        poll.poll(&mut events, Some(Duration::from_millis(50)))
+            .map_err(|e| format!("poll: {}", e))?;
+    }
+}
+
+/// Read all available data into `sink`. Returns true if any came.
+fn read_echo(device: &mut dyn IoInstance, mut sink: impl FnMut(&[u8])) -> Result<bool, String> {
+    let mut any = false;
+    loop {
+        match device.read() {
+            Ok(IoResult::Data(d)) => {
+                sink(&d);
+                any = true;
+            }
+            Ok(_) => return Ok(any),
+            Err(e) => return Err(format!("read: {}", e)),
+        }
+    }
+}
+
+/// Read for `SETTLE` into `sink`.
+fn settle(
+    poll: &mut Poll,
+    device: &mut dyn IoInstance,
+    mut sink: impl FnMut(&[u8]),
+) -> Result<(), String> {
+    let mut events = Events::with_capacity(16);
+    let deadline = Instant::now() + SETTLE;
+    loop {
+        read_echo(device, &mut sink)?;
+        let now = Instant::now();
+        if now >= deadline {
+            return Ok(());
+        }
+        poll.poll(&mut events, Some(deadline - now))
+            .map_err(|e| format!("poll: {}", e))?;
+    }
+}
+
+pub fn run(device: &mut dyn IoInstance, opts: &Options) -> Result<Report, String> {
+    let mut poll = Poll::new().map_err(|e| format!("poll: {}", e))?;
+    connect(&mut poll, device, opts.timeout)?;
+    let mut greeting = 0;
+    settle(&mut poll, device, |d| greeting += d.len())?;
+
+    let data = pattern(opts.bytes);
+    let mut verifier = Verifier::new(&data);
+    let mut events = Events::with_capacity(16);
+    let mut sent = 0;
+    // End offset and write time of the chunks not fully echoed yet
+    let mut pending: VecDeque<(usize, Instant)> = VecDeque::new();
+    let mut latencies = Vec::new();
+    let started = Instant::now();
+    let mut last_echo = started;
+
+    while !verifier.done() {
+        while sent < data.len() && sent.saturating_sub(verifier.received) < opts.chunk * IN_FLIGHT {
+            let end = (sent + opts.chunk).min(data.len());
+            let n = device.write_all(&data[sent..end]);
+            if n == 0 {
+                // Backpressure, try again after the next poll
+                break;
+            }
+            sent += n;
+            pending.push_back((sent, Instant::now()));
+        }
+
+        poll.poll(&mut events, Some(Duration::from_millis(50)))
+            .map_err(|e| format!("poll: {}", e))?;
+        let now = Instant::now();
+        if read_echo(device, |d| verifier.feed(d))? {
+            last_echo = now;
+        }
+        while let Some(&(end, at)) = pending.front()
+            && end <= verifier.received
+        {
+            latencies.push(now - at);
+            pending.pop_front();
+        }
+        if now - last_echo > opts.timeout {
+            break;
+        }
+    }
+    let elapsed = started.elapsed();
+
+    // Anything after the pattern is an error too
+    if verifier.done() {
+        settle(&mut poll, device, |d| verifier.feed(d))?;
+    }
+    device.disconnect(&mut poll);
+
+    Ok(Report {
+        greeting,
+        sent,
+        received: verifier.received,
+        corrupted: verifier.corrupted,
+        first_error: verifier.first_error,
+        extra: verifier.extra,
+        elapsed,
+        latencies,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::EchoDevice;
+
+    #[test]
+    fn test_pattern() {
+        let p = pattern(4096);
+        assert_eq!(p, pattern(4096));
+        let mut seen = [false; 256];
+        for &b in &p {
+            seen[b as usize] = true;
+        }
+        assert!(seen.iter().all(|&s| s));
+    }
+
+    #[test]
+    fn test_verifier() {
+        let expected = pattern(8);
+        let mut v = Verifier::new(&expected);
+        v.feed(&expected[..3]);
+        let mut rest = expected[3..].to_vec();
+        rest[2] ^= 0x01;
+        rest.push(b'x');
+        v.feed(&rest);
+        assert!(v.done());
+        assert_eq!((v.corrupted, v.first_error, v.extra), (1, Some(5), 1));
+    }
+
+    #[test]
+    fn test_percentile() {
+        let ms = |n| Duration::from_millis(n);
+        let sorted: Vec<Duration> = (1..=10).map(ms).collect();
+        assert_eq!(percentile(&sorted, 50.0), ms(5));
+        assert_eq!(percentile(&sorted, 90.0), ms(9));
+        assert_eq!(percentile(&sorted, 99.0), ms(10));
+        assert_eq!(percentile(&[], 50.0), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_echo_device() {
+        let mut echo = EchoDevice::new().unwrap();
+        let report = run(
+            &mut echo,
+            &Options {
+                bytes: 10_000,
+                chunk: 256,
+                timeout: Duration::from_secs(2),
+            },
+        )
+        .unwrap();
+        assert!(report.ok(), "{:?}", report);
+        assert_eq!(report.received, 10_000);
+        assert_eq!(report.latencies.len(), 40);
+    }
+}
@@ -0,0 +1,89 @@
+//! Errors that end crabterm. Each kind has its own exit code, so wrapper
+//! scripts can tell a typo from a missing device or a port in use.
+
+use std::fmt;
+
+use crate::outcome;
+
+#[derive(Debug)]
+pub enum CrabtermError {
+    /// Invalid or conflicting command line arguments
+    BadArgs(String),
+    /// A device could not be opened
+    DeviceOpen(String, std::io::Error),
+    /// A port (or session socket) could not be listened on
+    Bind(String, std::io::Error),
+    /// Invalid setting in the config file
+    Config(String),
+    /// Anything else, e.g. an I/O error while running
+    Io(std::io::Error),
+    /// What was run did not pass, e.g. the self-test or the failure pattern
+    Failed(String),
+    /// `search` found nothing; like grep, said by the exit code alone
+    NoMatch,
+}
+
+impl CrabtermError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            CrabtermError::Failed(_) | CrabtermError::NoMatch => 1,
+            // Same as clap uses for usage errors
+            CrabtermError::BadArgs(_) => 2,
+            CrabtermError::DeviceOpen(..) => 3,
+            CrabtermError::Bind(..) => 4,
+            CrabtermError::Config(_) => 5,
+            CrabtermError::Io(_) => 6,
+        }
+    }
+}
+
+impl fmt::Display for CrabtermError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CrabtermError::BadArgs(msg)
+            | CrabtermError::Config(msg)
+            | CrabtermError::Failed(msg) => write!(f, "{}", msg),
+            CrabtermError::DeviceOpen(dev, e) => write!(f, "{}: {}", dev, e),
+            CrabtermError::Bind(what, e) => write!(f, "{}: {}", what, e),
+            CrabtermError::Io(e) => write!(f, "{}", e),
+            CrabtermError::NoMatch => write!(f, "no match"),
+        }
+    }
+}
+
+impl std::error::Error for CrabtermError {}
+
+impl From<std::io::Error> for CrabtermError {
+    /// The failure pattern ends the run of the hub with an I/O error too
+    fn from(e: std::io::Error) -> Self {
+        if let Some(failed) = e
+            .get_ref()
+            .and_then(|e| e.downcast_ref::<outcome::Failed>())
+        {
+            return CrabtermError::Failed(failed.0.clone());
+        }
+        CrabtermError::Io(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_codes() {
+        let e = || std::io::Error::other("x");
+        assert_eq!(CrabtermError::BadArgs("x".into()).exit_code(), 2);
+        assert_eq!(CrabtermError::DeviceOpen("echo".into(), e()).exit_code(), 3);
+        assert_eq!(CrabtermError::Bind("port 1".into(), e()).exit_code(), 4);
+        assert_eq!(CrabtermError::Config("x".into()).exit_code(), 5);
+        assert_eq!(CrabtermError::Io(e()).exit_code(), 6);
+        assert_eq!(CrabtermError::Failed("x".into()).exit_code(), 1);
+        assert_eq!(CrabtermError::NoMatch.exit_code(), 1);
+
+        // The failure pattern, not an I/O error
+        let failed = std::io::Error::other(outcome::Failed("usb0: failure: FAIL".into()));
+        assert_eq!(CrabtermError::from(failed).exit_code(), 1);
+        assert_eq!(CrabtermError::from(e()).exit_code(), 6);
+    }
+}
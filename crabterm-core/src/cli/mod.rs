@@ -0,0 +1,2648 @@
+//! The `crabterm` command line: the devices, servers and console that the
+//! arguments and the config file ask for, wired up around an [`IoHub`].
+//! The binary parses the arguments with [`command`] and calls [`main`].
+
+use clap::{Arg, ArgMatches, Command, value_parser};
+use flexi_logger::{DeferredNow, FileSpec, LevelFilter, Logger, Record, WriteMode};
+use log::info;
+use mio_serial::{DataBits, Parity, StopBits};
+
+fn log_format(
+    w: &mut dyn std::io::Write,
+    now: &mut DeferredNow,
+    record: &Record,
+) -> std::io::Result<()> {
+    let module = record.module_path().unwrap_or("?");
+    let module_short = module.strip_prefix("crabterm_core::").unwrap_or(module);
+    let t = now.now();
+    let micros = t.timestamp_subsec_micros();
+    write!(
+        w,
+        "{}.{:03}.{:03} {} [{}:{}] {}",
+        t.format("%y-%m-%d %H:%M:%S"),
+        micros / 1000,
+        micros % 1000,
+        record.level(),
+        module_short,
+        record.line().unwrap_or(0),
+        record.args()
+    )
+}
+
+/// Log format for console/stderr output - uses \r\n for raw terminal mode
+/// compatibility. The \r is only emitted while stderr is a terminal in raw
+/// mode, so redirected logs stay clean.
+fn log_format_console(
+    w: &mut dyn std::io::Write,
+    now: &mut DeferredNow,
+    record: &Record,
+) -> std::io::Result<()> {
+    let module = record.module_path().unwrap_or("?");
+    let module_short = module.strip_prefix("crabterm_core::").unwrap_or(module);
+    let t = now.now();
+    let micros = t.timestamp_subsec_micros();
+    let cr = if raw_mode_active() && stderr_is_tty() {
+        "\r"
+    } else {
+        ""
+    };
+    write!(
+        w,
+        "{}.{:03}.{:03} {} [{}:{}] {}{}",
+        t.format("%y-%m-%d %H:%M:%S"),
+        micros / 1000,
+        micros % 1000,
+        record.level(),
+        module_short,
+        record.line().unwrap_or(0),
+        record.args(),
+        cr
+    )
+}
+/// Collects the structured key/values of a log record into a JSON object.
+struct JsonFields(serde_json::Map<String, serde_json::Value>);
+
+impl<'kvs> log::kv::VisitSource<'kvs> for JsonFields {
+    fn visit_pair(
+        &mut self,
+        key: log::kv::Key<'kvs>,
+        value: log::kv::Value<'kvs>,
+    ) -> Result<(), log::kv::Error> {
+        let value = if let Some(v) = value.to_u64() {
+            serde_json::Value::from(v)
+        } else if let Some(v) = value.to_i64() {
+            serde_json::Value::from(v)
+        } else if let Some(v) = value.to_bool() {
+            serde_json::Value::from(v)
+        } else {
+            serde_json::Value::from(value.to_string())
+        };
+        self.0.insert(key.to_string(), value);
+        Ok(())
+    }
+}
+
+fn write_json(
+    w: &mut dyn std::io::Write,
+    now: &mut DeferredNow,
+    record: &Record,
+    cr: &str,
+) -> std::io::Result<()> {
+    let module = record.module_path().unwrap_or("?");
+    let module_short = module.strip_prefix("crabterm_core::").unwrap_or(module);
+    let mut obj = serde_json::json!({
+        "timestamp": now.now().to_rfc3339_opts(chrono::SecondsFormat::Micros, false),
+        "level": record.level().as_str(),
+        "module": module_short,
+        "line": record.line().unwrap_or(0),
+        "message": record.args().to_string(),
+    });
+    let mut fields = JsonFields(serde_json::Map::new());
+    let _ = record.key_values().visit(&mut fields);
+    if !fields.0.is_empty() {
+        obj["fields"] = serde_json::Value::Object(fields.0);
+    }
+    write!(w, "{}{}", obj, cr)
+}
+
+/// One JSON object per line, for log ingestion (Loki, Elastic, ...).
+fn log_format_json(
+    w: &mut dyn std::io::Write,
+    now: &mut DeferredNow,
+    record: &Record,
+) -> std::io::Result<()> {
+    write_json(w, now, record, "")
+}
+
+/// JSON log format for console/stderr output, see `log_format_console`.
+fn log_format_json_console(
+    w: &mut dyn std::io::Write,
+    now: &mut DeferredNow,
+    record: &Record,
+) -> std::io::Result<()> {
+    let cr = if raw_mode_active() && stderr_is_tty() {
+        "\r"
+    } else {
+        ""
+    };
+    write_json(w, now, record, cr)
+}
+
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+mod bench;
+pub mod error;
+mod selftest;
+
+pub use error::CrabtermError;
+
+use crate::announce::{self, MessageKind};
+use crate::banner::{self, Banner};
+use crate::capture::{Compression, Format};
+use crate::capture_db::{self, CaptureDb};
+#[cfg(unix)]
+use crate::ctl::{self, CtlServer};
+use crate::device::DeviceUri;
+use crate::device::uri::{self, Baud, QemuEndpoint};
+use crate::events::EventStream;
+use crate::health::HealthConfig;
+use crate::hexdump;
+use crate::http::HttpServer;
+#[cfg(unix)]
+use crate::hub::device_label;
+use crate::hub::{DEFAULT_DRAIN_TIMEOUT, LogChange};
+#[cfg(unix)]
+use crate::io::device_lock::{self, DeviceLock, LockError};
+use crate::io::listener::{self, ListenSpec, ListenTarget};
+use crate::io::read_buffer;
+use crate::io::rs485::{self, Rs485};
+use crate::io::serial_device::DEFAULT_QUARANTINE;
+use crate::io::tcp_server::{ClientPolicy, SlowClientPolicy};
+use crate::io::{
+    Console, EchoDevice, FailoverDevice, QemuDevice, RemoteDevice, SerialDevice, TcpDevice,
+    TcpServer, TelnetDevice,
+};
+#[cfg(unix)]
+use crate::io::{UnixDevice, UnixServer};
+use crate::iofilter::{CharmapFilter, SETTING_ENABLED, charmap, hex, idle, timestamp};
+use crate::mdns::{self, MdnsResponder};
+use crate::metrics::MetricsServer;
+use crate::monitor::DeviceMonitor;
+use crate::notify::Notifier;
+use crate::outcome::ExitPatterns;
+use crate::pipe::{self, PipeTo};
+use crate::poll::Backend;
+use crate::power::PowerControl;
+#[cfg(unix)]
+use crate::session;
+#[cfg(unix)]
+use crate::traits::TOKEN_CTL_CLIENT_START;
+use crate::traits::{
+    IoInstance, TOKEN_HTTP_CLIENT_START, TOKEN_METRICS_CLIENT_START, TOKEN_MONITOR_CLIENT_START,
+};
+use crate::watchdog::SilenceAction;
+use crate::{FilterChain, IoHub};
+
+use crate::keybind::KeybindConfig;
+use crate::keybind::config::SettingValue;
+use crate::keybind::config::parse_escape_char;
+use crate::keybind::key::KeyEvent;
+use crate::term::{disable_raw_mode, raw_mode_active, stderr_is_tty, stdout_is_tty};
+
+/// Set by `--events-json`: stdout is for the events, so messages go to
+/// stderr
+static EVENTS_ON_STDOUT: AtomicBool = AtomicBool::new(false);
+
+macro_rules! raw_print {
+    ($($arg:tt)*) => {
+        if EVENTS_ON_STDOUT.load(Ordering::Relaxed) {
+            eprint!("{}", format!($($arg)*));
+        } else {
+            print!("{}", format!($($arg)*));
+        }
+    };
+}
+
+/// Options of serial devices
+#[derive(Debug, Clone)]
+struct SerialOptions {
+    baudrate: Baudrate,
+    rs485: Option<Rs485>,
+    mark_errors: bool,
+    quarantine: Duration,
+    keep_quarantined: bool,
+}
+
+/// `--baudrate`: a rate, or `auto` to detect it, optionally by a pattern
+#[derive(Debug, Clone, PartialEq)]
+enum Baudrate {
+    Fixed(u32),
+    Auto(Option<String>),
+}
+
+fn parse_baudrate(val: &str) -> Result<Baudrate, String> {
+    if val == "auto" {
+        return Ok(Baudrate::Auto(None));
+    }
+    match val.parse() {
+        Ok(rate) if rate > 0 => Ok(Baudrate::Fixed(rate)),
+        _ => Err(format!("invalid baudrate: {} (a number or auto)", val)),
+    }
+}
+
+/// Environment variable marking the background process of `--detach`.
+#[cfg(unix)]
+const DETACHED_ENV: &str = "CRABTERM_DETACHED";
+
+/// What needs unix sockets: sessions, the control socket, unix devices and
+/// listeners.
+#[cfg(windows)]
+fn no_unix_sockets(what: &str) -> CrabtermError {
+    CrabtermError::BadArgs(format!(
+        "{}: unix sockets are not supported on Windows",
+        what
+    ))
+}
+
+/// Re-run this command in the background, in its own session so it survives
+/// the terminal, and wait until it listens on the session socket.
+#[cfg(unix)]
+fn detach(name: &str, path: &std::path::Path, announce_template: &str) -> std::io::Result<()> {
+    use std::os::unix::process::CommandExt;
+    use std::process::Stdio;
+
+    if std::os::unix::net::UnixStream::connect(path).is_ok() {
+        return Err(std::io::Error::other(format!(
+            "session {} is already running",
+            name
+        )));
+    }
+    // Left behind by a session that did not exit cleanly
+    let _ = std::fs::remove_file(path);
+
+    let mut cmd = std::process::Command::new(std::env::current_exe()?);
+    cmd.args(std::env::args_os().skip(1))
+        .env(DETACHED_ENV, "1")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    // SAFETY: setsid() is async-signal-safe
+    unsafe {
+        cmd.pre_exec(|| {
+            libc::setsid();
+            Ok(())
+        });
+    }
+    let mut child = cmd.spawn()?;
+
+    let deadline = std::time::Instant::now() + Duration::from_secs(5);
+    while !path.exists() {
+        if let Some(status) = child.try_wait()? {
+            return Err(std::io::Error::other(format!(
+                "session exited during start-up ({}), see the log file",
+                status
+            )));
+        }
+        if std::time::Instant::now() > deadline {
+            return Err(std::io::Error::other("timeout waiting for the session"));
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+
+    raw_print!(
+        "{}",
+        announce::format_message(
+            announce_template,
+            "Local",
+            &format!(
+                "Session {} detached (pid {}), attach with: crabterm attach {}",
+                name,
+                child.id(),
+                name
+            ),
+            MessageKind::Info
+        )
+    );
+    Ok(())
+}
+
+/// Connect the local console to a detached session. Quitting only detaches;
+/// the session keeps running.
+#[cfg(unix)]
+fn attach(
+    name: Option<&String>,
+    config: KeybindConfig,
+    announce_template: &str,
+) -> Result<(), CrabtermError> {
+    let sessions = session::list()?;
+    let name = match (name, sessions.as_slice()) {
+        (Some(name), _) if sessions.contains(name) => name.clone(),
+        (None, [only]) => only.clone(),
+        (_, []) => return Err(CrabtermError::BadArgs("no detached sessions".to_string())),
+        (Some(name), _) => {
+            return Err(CrabtermError::BadArgs(format!(
+                "no session {}, sessions: {}",
+                name,
+                sessions.join(", ")
+            )));
+        }
+        (None, _) => {
+            return Err(CrabtermError::BadArgs(format!(
+                "several sessions, name one: {}",
+                sessions.join(", ")
+            )));
+        }
+    };
+
+    let device = UnixDevice::new(session::socket_path(&name)?);
+    let mut hub = IoHub::builder(Box::new(device))
+        .announce_template(announce_template)
+        .build()?;
+    let filter_chain = FilterChain::new(&config.settings);
+    hub.add(Box::new(Console::new(config, filter_chain)?))?;
+
+    while !hub.is_quit_requested() {
+        hub.run()?;
+    }
+    raw_print!(
+        "{}",
+        announce::format_message(
+            announce_template,
+            "Local",
+            &format!("Detached from session {}", name),
+            MessageKind::Info
+        )
+    );
+    Ok(())
+}
+
+#[cfg(windows)]
+fn attach(_: Option<&String>, _: KeybindConfig, _: &str) -> Result<(), CrabtermError> {
+    Err(no_unix_sockets("attach"))
+}
+
+/// Use the TCP port of another crabterm as the device. The console sends
+/// `remote` commands to that crabterm in-band, and its announcements come
+/// framed, apart from the data.
+fn connect(
+    addr: &str,
+    config: KeybindConfig,
+    announce_template: &str,
+) -> Result<(), CrabtermError> {
+    let device = open_device(
+        &DeviceUri::Tcp {
+            addr: addr.to_string(),
+            nodelay: false,
+            telnet: false,
+        },
+        &SerialOptions {
+            baudrate: Baudrate::Fixed(0),
+            rs485: None,
+            mark_errors: false,
+            quarantine: DEFAULT_QUARANTINE,
+            keep_quarantined: false,
+        },
+        announce_template,
+    )?;
+    let mut hub = IoHub::builder(Box::new(RemoteDevice::new(device)))
+        .announce_template(announce_template)
+        .build()?;
+    let filter_chain = FilterChain::new(&config.settings);
+    let mut console = Console::new(config, filter_chain)?;
+    console.set_remote_control(true);
+    // The console is all there is, a slow terminal loses output rather than
+    // the session
+    console.set_client_policy(ClientPolicy {
+        slow: SlowClientPolicy::DropData,
+        ..ClientPolicy::default()
+    });
+    hub.add(Box::new(console))?;
+
+    while !hub.is_quit_requested() {
+        hub.run()?;
+    }
+    Ok(())
+}
+
+/// Slow client policy and buffer limit from the command line, or else the
+/// configuration.
+fn client_policy(
+    matches: &clap::ArgMatches,
+    config: &KeybindConfig,
+) -> Result<ClientPolicy, CrabtermError> {
+    let mut policy = ClientPolicy::default();
+    let slow = matches
+        .get_one::<String>("slow-client-policy")
+        .map(|s| s.as_str())
+        .or_else(|| {
+            config
+                .settings
+                .get("slow-client-policy")
+                .and_then(|v| v.as_str())
+        });
+    if let Some(slow) = slow {
+        policy.slow = SlowClientPolicy::parse(slow).ok_or_else(|| {
+            CrabtermError::Config(format!("slow-client-policy: unknown policy {}", slow))
+        })?;
+    }
+    if let Some(limit) = matches
+        .get_one::<usize>("client-buffer-limit")
+        .copied()
+        .or_else(|| {
+            config
+                .settings
+                .get("client-buffer-limit")
+                .and_then(|v| v.as_str())
+                .and_then(|s| s.parse().ok())
+        })
+    {
+        policy.buffer_limit = limit;
+    }
+    let rate = match matches.get_one::<usize>("client-rate-limit") {
+        Some(&rate) => Some(rate),
+        None => match config
+            .settings
+            .get("client-rate-limit")
+            .and_then(|v| v.as_str())
+        {
+            Some(s) => Some(read_buffer::parse_size(s).ok_or_else(|| {
+                CrabtermError::Config(format!("client-rate-limit: invalid rate {}", s))
+            })?),
+            None => None,
+        },
+    };
+    if rate.is_some() {
+        policy.rate_limit = rate;
+    }
+    Ok(policy)
+}
+
+fn parse_io_buffer(val: &str) -> Result<usize, String> {
+    read_buffer::parse_size(val).ok_or_else(|| format!("invalid size: {}", val))
+}
+
+/// `--io-buffer` or the io-buffer setting
+fn io_buffer_size(
+    matches: &clap::ArgMatches,
+    config: &KeybindConfig,
+) -> Result<usize, CrabtermError> {
+    if let Some(&size) = matches.get_one::<usize>("io-buffer") {
+        return Ok(size);
+    }
+    match config
+        .settings
+        .get(read_buffer::SETTING)
+        .and_then(|v| v.as_str())
+    {
+        Some(s) => parse_io_buffer(s)
+            .map_err(|e| CrabtermError::Config(format!("{}: {}", read_buffer::SETTING, e))),
+        None => Ok(read_buffer::DEFAULT_SIZE),
+    }
+}
+
+/// `--rs485` and its options, or the rs485 settings
+fn rs485_options(
+    matches: &clap::ArgMatches,
+    config: &KeybindConfig,
+) -> Result<Option<Rs485>, CrabtermError> {
+    let setting = |name: &str| config.settings.get(name).and_then(|v| v.as_str());
+    let enabled = matches.get_flag("rs485")
+        || config
+            .settings
+            .get("rs485")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
+    if !enabled {
+        return Ok(None);
+    }
+    let delay = |name: &str| -> Result<Duration, CrabtermError> {
+        let ms = match matches.get_one::<u64>(name) {
+            Some(&ms) => ms,
+            None => match setting(name) {
+                Some(s) => s.parse().map_err(|_| {
+                    CrabtermError::Config(format!("{}: invalid milliseconds: {}", name, s))
+                })?,
+                None => 0,
+            },
+        };
+        Ok(Duration::from_millis(ms))
+    };
+    let rts_low_on_send = match matches.get_one::<bool>("rs485-rts") {
+        Some(&low) => low,
+        None => match setting("rs485-rts") {
+            Some(s) => rs485::parse_rts(s)
+                .map_err(|e| CrabtermError::Config(format!("rs485-rts: {}", e)))?,
+            None => false,
+        },
+    };
+    Ok(Some(Rs485 {
+        delay_before: delay("rs485-delay-before")?,
+        delay_after: delay("rs485-delay-after")?,
+        rts_low_on_send,
+    }))
+}
+
+/// `--quarantine` or the quarantine setting, where "off" is 0
+fn quarantine(
+    matches: &clap::ArgMatches,
+    config: &KeybindConfig,
+) -> Result<Duration, CrabtermError> {
+    if let Some(&ms) = matches.get_one::<u64>("quarantine") {
+        return Ok(Duration::from_millis(ms));
+    }
+    match config.settings.get("quarantine") {
+        Some(SettingValue::Bool(false)) => Ok(Duration::ZERO),
+        Some(SettingValue::String(s)) => s
+            .parse()
+            .map(Duration::from_millis)
+            .map_err(|_| CrabtermError::Config(format!("quarantine: invalid milliseconds: {}", s))),
+        _ => Ok(DEFAULT_QUARANTINE),
+    }
+}
+
+/// `crabterm bench`: fails if the echo was not a faithful copy.
+fn bench(matches: &clap::ArgMatches, announce_template: &str) -> Result<(), CrabtermError> {
+    let dev = matches.get_one::<DeviceUri>("device").expect("required");
+    let timeout = Duration::try_from_secs_f64(*matches.get_one::<f64>("timeout").expect("default"))
+        .ok()
+        .filter(|t| !t.is_zero())
+        .ok_or_else(|| CrabtermError::BadArgs("timeout must be positive".to_string()))?;
+    let mut device = open_device(
+        dev,
+        &SerialOptions {
+            baudrate: Baudrate::Fixed(*matches.get_one::<u32>("baudrate").expect("default")),
+            rs485: None,
+            mark_errors: false,
+            quarantine: DEFAULT_QUARANTINE,
+            keep_quarantined: false,
+        },
+        announce_template,
+    )?;
+    let options = bench::Options {
+        bytes: *matches.get_one::<usize>("bytes").expect("default"),
+        chunk: *matches.get_one::<usize>("chunk").expect("default"),
+        timeout,
+    };
+    let report = bench::run(&mut *device, &options)
+        .map_err(|e| CrabtermError::DeviceOpen(dev.addr(), std::io::Error::other(e)))?;
+    report.print(&device.addr_as_string());
+    if report.ok() {
+        Ok(())
+    } else {
+        Err(CrabtermError::Io(std::io::Error::other(
+            "the echo differs from what was sent",
+        )))
+    }
+}
+
+/// Print the lines of the capture database at `path` that match, like grep:
+/// [`CrabtermError::NoMatch`] if there are none.
+fn search(matches: &clap::ArgMatches, path: &Path) -> Result<(), CrabtermError> {
+    let pattern = matches.get_one::<String>("pattern").expect("required");
+    let query = capture_db::Query {
+        pattern: regex::Regex::new(pattern)
+            .map_err(|e| CrabtermError::BadArgs(format!("Invalid pattern: {}", e)))?,
+        device: matches.get_one::<String>("device").cloned(),
+        boot: matches.get_one::<i64>("boot").copied(),
+    };
+    if !path.exists() {
+        return Err(CrabtermError::BadArgs(format!(
+            "{}: no such database",
+            path.display()
+        )));
+    }
+    let found = capture_db::search(path, &query, |m| {
+        println!(
+            "{} {} boot {}: {}",
+            m.time.format("%Y-%m-%d %H:%M:%S%.3f"),
+            m.device,
+            m.boot,
+            m.line
+        );
+    })
+    .map_err(|e| {
+        CrabtermError::Io(std::io::Error::new(
+            e.kind(),
+            format!("{}: {}", path.display(), e),
+        ))
+    })?;
+    if found == 0 {
+        return Err(CrabtermError::NoMatch);
+    }
+    Ok(())
+}
+
+/// Run one command on the control socket of a running crabterm, and print
+/// its output.
+#[cfg(unix)]
+fn ctl_client(path: &Path, command: &str) -> Result<(), CrabtermError> {
+    let failed = |e: std::io::Error| {
+        CrabtermError::Io(std::io::Error::new(
+            e.kind(),
+            format!("{}: {}", path.display(), e),
+        ))
+    };
+    match ctl::send(path, command).map_err(failed)? {
+        Ok(lines) => {
+            for line in lines {
+                println!("{}", line);
+            }
+            Ok(())
+        }
+        Err(e) => Err(CrabtermError::Io(std::io::Error::other(e))),
+    }
+}
+
+#[cfg(windows)]
+fn ctl_client(_: &Path, _: &str) -> Result<(), CrabtermError> {
+    Err(no_unix_sockets("ctl"))
+}
+
+fn bind_error(port: u16, e: std::io::Error) -> CrabtermError {
+    CrabtermError::Bind(format!("port {}", port), e)
+}
+
+/// The filter flags (`--timestamp`, `--hex`, `--imap`, ...) as settings,
+/// overriding those of the config file.
+fn filter_flags(matches: &clap::ArgMatches, settings: &mut HashMap<String, SettingValue>) {
+    let mut enabled = Vec::new();
+    let abs = matches.get_flag("timestamp");
+    let rel = matches.get_flag("timestamp-rel");
+    if abs || rel {
+        settings.insert(timestamp::SETTING_ABS.to_string(), SettingValue::Bool(abs));
+        settings.insert(timestamp::SETTING_REL.to_string(), SettingValue::Bool(rel));
+        enabled.push(timestamp::NAME);
+    }
+    if matches.get_flag("hex") {
+        enabled.push(hex::NAME);
+    }
+    for (flag, setting) in [
+        ("imap", charmap::SETTING_IMAP),
+        ("omap", charmap::SETTING_OMAP),
+    ] {
+        if let Some(value) = matches.get_one::<String>(flag) {
+            settings.insert(setting.to_string(), SettingValue::String(value.clone()));
+        }
+    }
+    if enabled.is_empty() {
+        return;
+    }
+    let mut names: Vec<String> = settings
+        .get(SETTING_ENABLED)
+        .and_then(|v| v.as_str())
+        .map(|v| v.split(',').map(|s| s.trim().to_string()).collect())
+        .unwrap_or_default();
+    names.extend(enabled.iter().map(|s| s.to_string()));
+    settings.insert(
+        SETTING_ENABLED.to_string(),
+        SettingValue::String(names.join(",")),
+    );
+}
+
+fn parse_map(val: &str) -> Result<(DeviceUri, u16), String> {
+    let (dev, port) = val
+        .rsplit_once('=')
+        .ok_or_else(|| String::from("Invalid mapping. Use DEVICE=PORT"))?;
+    let port = port
+        .parse()
+        .map_err(|_| format!("Invalid port in mapping: {}", port))?;
+    Ok((uri::parse(dev)?, port))
+}
+
+fn parse_channel(val: &str) -> Result<(String, DeviceUri), String> {
+    let (name, dev) = val
+        .split_once('=')
+        .filter(|(name, _)| !name.is_empty())
+        .ok_or_else(|| String::from("Invalid channel. Use NAME=DEVICE"))?;
+    Ok((name.to_string(), uri::parse(dev)?))
+}
+
+/// TXT records of `--mdns`: the devices and their baudrates.
+fn mdns_txt(devices: &[(&DeviceUri, Option<u16>)], baudrate: &Baudrate) -> Vec<String> {
+    let rate = |dev: &DeviceUri| match dev {
+        DeviceUri::Serial { params, .. } => match (params.baud, baudrate) {
+            (Some(Baud::Rate(rate)), _) | (None, &Baudrate::Fixed(rate)) => rate.to_string(),
+            (Some(Baud::Auto), _) | (None, Baudrate::Auto(_)) => "auto".to_string(),
+        },
+        _ => "-".to_string(),
+    };
+    let list = |f: &dyn Fn(&DeviceUri) -> String| {
+        devices
+            .iter()
+            .map(|(d, _)| f(d))
+            .collect::<Vec<_>>()
+            .join(",")
+    };
+    vec![
+        format!("device={}", list(&|d| d.addr())),
+        format!("baudrate={}", list(&rate)),
+        format!("version={}", env!("CARGO_PKG_VERSION")),
+    ]
+}
+
+/// Factory for filter chains with exactly the filters listed in `setting`
+/// (e.g. "timestamp,charmap") enabled.
+fn filter_factory(
+    settings: &HashMap<String, SettingValue>,
+    setting: &str,
+) -> Result<impl Fn() -> FilterChain + 'static, String> {
+    let names: Vec<String> = settings
+        .get(setting)
+        .and_then(|v| v.as_str())
+        .map(|s| {
+            s.split(',')
+                .map(|n| n.trim().to_string())
+                .filter(|n| !n.is_empty())
+                .collect()
+        })
+        .unwrap_or_default();
+    let settings = settings.clone();
+    let create = move || {
+        let mut chain = FilterChain::new(&settings);
+        let names: Vec<&str> = names.iter().map(|n| n.as_str()).collect();
+        chain.set_enabled(&names).map(|_| chain)
+    };
+    create().map_err(|e| format!("{}: {}", setting, e))?;
+    Ok(move || create().unwrap_or_default())
+}
+
+/// Lock the serial devices against other instances, see
+/// [`DeviceLock`]. With `steal` the owner of a lock is asked to release it.
+/// The locks go with the index of their device, for the hub to hold.
+#[cfg(unix)]
+fn lock_devices(
+    device_modes: &[(&DeviceUri, Option<u16>)],
+    config: &KeybindConfig,
+    control: Option<&Path>,
+    steal: bool,
+) -> Result<Vec<(usize, DeviceLock)>, CrabtermError> {
+    let dir = config
+        .settings
+        .get(device_lock::SETTING_DIR)
+        .and_then(|v| v.as_str())
+        .unwrap_or(device_lock::DEFAULT_DIR);
+    if dir == "off" {
+        return Ok(Vec::new());
+    }
+    let mut locks = Vec::new();
+    for (idx, (dev, _)) in device_modes.iter().enumerate() {
+        let DeviceUri::Serial { path, .. } = dev else {
+            continue;
+        };
+        let path = Path::new(path);
+        let lock = match steal {
+            true => DeviceLock::steal(Path::new(dir), path, control),
+            false => DeviceLock::acquire(Path::new(dir), path, control),
+        };
+        match lock {
+            Ok(lock) => locks.extend(lock.map(|lock| (idx, lock))),
+            Err(LockError::InUse(owner)) => {
+                let hint = match (&owner.control, steal) {
+                    (Some(_), false) => ", --steal asks it to release it",
+                    (Some(_), true) => ", it did not release it",
+                    (None, _) => "",
+                };
+                return Err(CrabtermError::DeviceOpen(
+                    dev.addr(),
+                    std::io::Error::new(
+                        std::io::ErrorKind::ResourceBusy,
+                        format!("in use by {}{}", owner, hint),
+                    ),
+                ));
+            }
+            Err(LockError::Io(e)) => return Err(CrabtermError::DeviceOpen(dev.addr(), e)),
+        }
+    }
+    Ok(locks)
+}
+
+fn open_device(
+    dev: &DeviceUri,
+    serial: &SerialOptions,
+    announce_template: &str,
+) -> Result<Box<dyn IoInstance>, CrabtermError> {
+    let failed = |e| CrabtermError::DeviceOpen(dev.addr(), e);
+    Ok(match dev {
+        DeviceUri::Serial { path, params } => {
+            // A baud parameter of the device overrides --baudrate
+            let baudrate = match (params.baud, &serial.baudrate) {
+                (Some(Baud::Rate(rate)), _) => Baudrate::Fixed(rate),
+                (Some(Baud::Auto), Baudrate::Auto(pattern)) => Baudrate::Auto(pattern.clone()),
+                (Some(Baud::Auto), Baudrate::Fixed(_)) => Baudrate::Auto(None),
+                (None, baudrate) => baudrate.clone(),
+            };
+            let mut device = match &baudrate {
+                Baudrate::Fixed(rate) => SerialDevice::new(path.clone(), *rate).map_err(failed)?,
+                Baudrate::Auto(pattern) => SerialDevice::new(path.clone(), 0)
+                    .map_err(failed)?
+                    .autobaud(pattern.as_ref().map(|p| p.as_bytes().to_vec())),
+            };
+            device = device.framing(
+                params.data_bits.unwrap_or(DataBits::Eight),
+                params.parity.unwrap_or(Parity::None),
+                params.stop_bits.unwrap_or(StopBits::One),
+            );
+            if let Some(rs485) = &serial.rs485 {
+                device = device.rs485(rs485.clone());
+            }
+            if serial.mark_errors {
+                device = device.mark_errors();
+            }
+            device = device.quarantine(serial.quarantine, serial.keep_quarantined);
+            Box::new(device)
+        }
+        DeviceUri::Tcp {
+            addr,
+            nodelay,
+            telnet,
+        } => {
+            raw_print!(
+                "{}",
+                announce::format_message(
+                    announce_template,
+                    "Local",
+                    &format!(
+                        "{} device: {}",
+                        if *telnet { "Telnet" } else { "TCP" },
+                        addr
+                    ),
+                    MessageKind::Info
+                )
+            );
+
+            let addr: SocketAddr = addr
+                .to_socket_addrs()
+                .and_then(|mut addrs| {
+                    addrs
+                        .next()
+                        .ok_or_else(|| std::io::Error::other("no address found"))
+                })
+                .map_err(failed)?;
+            let device = TcpDevice::new(addr).map_err(failed)?.nodelay(*nodelay);
+            if *telnet {
+                Box::new(TelnetDevice::new(Box::new(device)))
+            } else {
+                Box::new(device)
+            }
+        }
+        #[cfg(unix)]
+        DeviceUri::Unix { path } => {
+            raw_print!(
+                "{}",
+                announce::format_message(
+                    announce_template,
+                    "Local",
+                    &format!("Unix device: {}", path.display()),
+                    MessageKind::Info
+                )
+            );
+            Box::new(UnixDevice::new(path.clone()))
+        }
+        #[cfg(windows)]
+        DeviceUri::Unix { .. } => return Err(no_unix_sockets(&dev.addr())),
+        DeviceUri::Echo => {
+            raw_print!(
+                "{}",
+                announce::format_message(
+                    announce_template,
+                    "Local",
+                    "Echo mode",
+                    MessageKind::Info
+                )
+            );
+            Box::new(EchoDevice::new().map_err(failed)?)
+        }
+        DeviceUri::Qemu { endpoint, mux } => {
+            raw_print!(
+                "{}",
+                announce::format_message(
+                    announce_template,
+                    "Local",
+                    &format!("QEMU device: {}", dev.addr()),
+                    MessageKind::Info
+                )
+            );
+            let inner: Box<dyn IoInstance> = match endpoint {
+                #[cfg(unix)]
+                QemuEndpoint::Unix(path) => Box::new(UnixDevice::new(path.clone())),
+                #[cfg(windows)]
+                QemuEndpoint::Unix(_) => return Err(no_unix_sockets(&dev.addr())),
+                QemuEndpoint::Tcp(addr) => {
+                    let addr: SocketAddr = addr
+                        .to_socket_addrs()
+                        .and_then(|mut addrs| {
+                            addrs
+                                .next()
+                                .ok_or_else(|| std::io::Error::other("no address found"))
+                        })
+                        .map_err(failed)?;
+                    Box::new(TcpDevice::new(addr).map_err(failed)?.nodelay(true))
+                }
+                // A pty has no line speed, and QEMU sends no stale bytes
+                QemuEndpoint::Pty(path) => Box::new(
+                    SerialDevice::new(path.clone(), 115200)
+                        .map_err(failed)?
+                        .quarantine(Duration::ZERO, false),
+                ),
+            };
+            Box::new(QemuDevice::new(inner, dev.addr(), *mux))
+        }
+    })
+}
+
+/// The command line of crabterm, for [`main`]; the binary adds its version.
+pub fn command() -> Command {
+    let dev_help = "Device - /dev/rs232-device|(ip-address|hostname):port|echo, or a \
+                    serial://, tcp://, unix:// URI with parameters";
+    Command::new("crabterm")
+        .author("Allan W. Nielsen")
+        .about("A terminal (uart) server and client")
+        .args_conflicts_with_subcommands(true)
+        .subcommand(
+            Command::new("attach")
+                .about("Attach the console to a session started with --detach")
+                .arg(
+                    Arg::new("name")
+                        .value_name("NAME")
+                        .help("Session to attach to (may be omitted if there is only one)"),
+                ),
+        )
+        .subcommand(
+            Command::new("connect")
+                .about("Connect the console to another crabterm's TCP port, with remote commands")
+                .arg(
+                    Arg::new("address")
+                        .value_name("HOST:PORT")
+                        .required(true)
+                        .help("Address of the crabterm server"),
+                ),
+        )
+        .subcommand(
+            Command::new("bench")
+                .about("Send a pattern to a device that echoes it, and report throughput, latency and errors")
+                .arg(
+                    Arg::new("device")
+                        .value_name("DEVICE")
+                        .required(true)
+                        .help("Device with a loopback: a serial port with TX tied to RX, echo, or another crabterm in echo mode")
+                        .value_parser(uri::parse),
+                )
+                .arg(
+                    Arg::new("baudrate")
+                        .short('b')
+                        .long("baudrate")
+                        .value_name("BAUDRATE")
+                        .help("Baudrate of a serial device")
+                        .default_value("115200")
+                        .value_parser(value_parser!(u32).range(1..)),
+                )
+                .arg(
+                    Arg::new("bytes")
+                        .long("bytes")
+                        .value_name("SIZE")
+                        .help("Bytes to send, e.g. 1M")
+                        .default_value("64K")
+                        .value_parser(parse_io_buffer),
+                )
+                .arg(
+                    Arg::new("chunk")
+                        .long("chunk")
+                        .value_name("SIZE")
+                        .help("Bytes per write; latency is measured per chunk")
+                        .default_value("256")
+                        .value_parser(parse_io_buffer),
+                )
+                .arg(
+                    Arg::new("timeout")
+                        .long("timeout")
+                        .value_name("SECONDS")
+                        .help("Give up when nothing has come back for this long")
+                        .default_value("2")
+                        .value_parser(value_parser!(f64)),
+                ),
+        )
+        .subcommand(
+            Command::new("ctl")
+                .about("Run a command on the control socket of a running crabterm")
+                .arg(
+                    Arg::new("control")
+                        .long("control")
+                        .value_name("PATH")
+                        .help("Control socket (default: the control setting)")
+                        .value_parser(clap::value_parser!(PathBuf)),
+                )
+                .arg(
+                    Arg::new("command")
+                        .value_name("CMD")
+                        .required(true)
+                        .num_args(1..)
+                        .help("clients, kick ID, stats, health, baud RATE, filter NAME, capture start|stop, marker TEXT, release DEVICE, ..."),
+                ),
+        )
+        .subcommand(
+            Command::new("search")
+                .about("Search the lines stored by --capture-db")
+                .arg(
+                    Arg::new("pattern")
+                        .value_name("PATTERN")
+                        .required(true)
+                        .help("Regular expression the line has to match"),
+                )
+                .arg(
+                    Arg::new("db")
+                        .long("db")
+                        .value_name("FILE")
+                        .help("Database (default: the capture-db setting)")
+                        .value_parser(clap::value_parser!(PathBuf)),
+                )
+                .arg(
+                    Arg::new("device")
+                        .long("device")
+                        .value_name("NAME")
+                        .help("Only lines of this device, e.g. usb0"),
+                )
+                .arg(
+                    Arg::new("boot")
+                        .long("boot")
+                        .value_name("N")
+                        .help("Only lines of this boot cycle")
+                        .value_parser(value_parser!(i64)),
+                ),
+        )
+        .subcommand(
+            Command::new("self-test")
+                .about("Exercise echo device, filters, key parser and TCP loop in-process"),
+        )
+        .arg(
+            Arg::new("config")
+                .short('c')
+                .long("config")
+                .value_name("CONFIG_PATH")
+                .help("Path to config file (default: ~/.crabterm)")
+                .value_parser(clap::value_parser!(PathBuf))
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("port")
+                .short('p')
+                .long("port")
+                .value_name("[ROLES:]PORT|unix:PATH")
+                .help(
+                    "TCP port or unix socket to listen on (may be repeated); \
+                     roles: ro (read-only), auth (password), quiet (no announcements), \
+                     crlf, lf (line ends of the output), gdb (raw, with priority), \
+                     rate= (bytes per second to each client); unix sockets: mode=, owner=, group=, allow-user=, allow-group=",
+                )
+                .value_parser(listener::parse)
+                .action(clap::ArgAction::Append),
+        )
+        .arg(
+            Arg::new("device-monitor-port")
+                .long("device-monitor-port")
+                .value_name("PORT")
+                .help("TCP port for device monitoring")
+                .value_parser(value_parser!(u16)),
+        )
+        .arg(
+            Arg::new("device-monitor-template")
+                .long("device-monitor-template")
+                .value_name("TEMPLATE")
+                .help("Template for device monitoring")
+                .num_args(1),
+        )
+
+        .arg(
+            Arg::new("baudrate")
+                .short('b')
+                .long("baudrate")
+                .value_name("BAUDRATE")
+                .help("Baudrate, or auto to detect it")
+                .default_value("115200")
+                .value_parser(parse_baudrate),
+        )
+        .arg(
+            Arg::new("quarantine")
+                .long("quarantine")
+                .value_name("MS")
+                .help("Hold back what a serial device sends this long after opening, 0 to disable [default: 10]")
+                .value_parser(value_parser!(u64))
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("quarantine-keep")
+                .long("quarantine-keep")
+                .help("Pass on what was held back in the quarantine instead of dropping it")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("mark-errors")
+                .long("mark-errors")
+                .help("Show bytes received with a parity or framing error as an inverse ?")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("rs485")
+                .long("rs485")
+                .help("RS-485 half-duplex: switch the transmitter with RTS while sending")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("rs485-delay-before")
+                .long("rs485-delay-before")
+                .value_name("MS")
+                .help("With --rs485, time between RTS and sending [default: 0]")
+                .value_parser(value_parser!(u64))
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("rs485-delay-after")
+                .long("rs485-delay-after")
+                .value_name("MS")
+                .help("With --rs485, time between the last byte and releasing RTS [default: 0]")
+                .value_parser(value_parser!(u64))
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("rs485-rts")
+                .long("rs485-rts")
+                .value_name("LEVEL")
+                .help("With --rs485, the RTS level while sending: high or low [default: high]")
+                .value_parser(rs485::parse_rts)
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("autobaud-pattern")
+                .long("autobaud-pattern")
+                .value_name("TEXT")
+                .help("With --baudrate auto, take the rate at which TEXT is received [default: when text is received]")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("headless")
+                .long("headless")
+                .help("Headless/daemon mode - IO not printed locally (only useful along with -p)")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("events-json")
+                .long("events-json")
+                .help("Print the session as JSON events, one per line, instead of the console")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("devicepos")
+                .index(1)
+                .value_name("DEVICE")
+                .conflicts_with("device")
+                .help(dev_help)
+                .value_parser(uri::parse)
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("device")
+                .short('d')
+                .long("device")
+                .value_name("DEVICE")
+                .help(format!(
+                    "{} (repeat to attach several devices to one hub)",
+                    dev_help
+                ))
+                .value_parser(uri::parse)
+                .action(clap::ArgAction::Append)
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("map")
+                .long("map")
+                .value_name("DEVICE=PORT")
+                .help("Attach a device and expose it alone on its own TCP port (may be repeated)")
+                .value_parser(parse_map)
+                .action(clap::ArgAction::Append)
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("channel")
+                .long("channel")
+                .value_name("NAME=DEVICE")
+                .help("Open DEVICE as another channel of the device before it, e.g. a QEMU monitor (may be repeated)")
+                .value_parser(parse_channel)
+                .action(clap::ArgAction::Append)
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("detach")
+                .long("detach")
+                .help("Keep running in the background as a session; connect with 'crabterm attach'")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("session")
+                .long("session")
+                .value_name("NAME")
+                .requires("detach")
+                .help("Name of the detached session [default: device name, e.g. usb0]"),
+        )
+        .arg(
+            Arg::new("fallback")
+                .long("fallback")
+                .value_name("DEVICE")
+                .help("Device to fail over to when the device can not be connected (may be repeated)")
+                .value_parser(uri::parse)
+                .action(clap::ArgAction::Append)
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("slow-client-policy")
+                .long("slow-client-policy")
+                .value_name("POLICY")
+                .help("What to do when a TCP client falls behind: drop-data, disconnect or block [default: disconnect]")
+                .value_parser(["drop-data", "disconnect", "block"])
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("client-buffer-limit")
+                .long("client-buffer-limit")
+                .value_name("BYTES")
+                .help("Output buffered per TCP client before the slow client policy applies [default: 65536]")
+                .value_parser(value_parser!(usize))
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("client-rate-limit")
+                .long("client-rate-limit")
+                .value_name("BYTES")
+                .help("Bytes per second written to each TCP client and the terminal, e.g. 16k [default: unlimited]")
+                .value_parser(parse_io_buffer)
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("write-lock")
+                .long("write-lock")
+                .help("One TCP client at a time may write, the others are queued read-only for the write lock")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("client-keybinds")
+                .long("client-keybinds")
+                .help("Handle the prefix keys of TCP clients too, e.g. Ctrl+a q disconnects the client")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("escape-char")
+                .long("escape-char")
+                .value_name("KEY")
+                .help("Prefix key instead of Ctrl+a, e.g. b for Ctrl+b")
+                .value_parser(parse_escape_char)
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("no-escape")
+                .long("no-escape")
+                .help("No prefix key or keybinds: every key goes to the device unchanged")
+                .conflicts_with("escape-char")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("io-buffer")
+                .long("io-buffer")
+                .value_name("SIZE")
+                .help("Bytes read at a time from devices and clients, e.g. 64K [default: 16K]")
+                .value_parser(parse_io_buffer)
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("write-coalesce")
+                .long("write-coalesce")
+                .value_name("MS")
+                .help("Collect device writes for this long after a write, e.g. 2 for pastes to USB serial [default: 0, off]")
+                .value_parser(value_parser!(u64))
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("frame-gap")
+                .long("frame-gap")
+                .value_name("MS")
+                .help("Deliver device output in frames ended by this much quiet, for binary protocols [default: 0, off]")
+                .value_parser(value_parser!(u64))
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("banner")
+                .long("banner")
+                .value_name("TEMPLATE")
+                .help("Greet TCP clients that connect: %l device, %a address, %b baudrate, %r rules, %v version")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("banner-file")
+                .long("banner-file")
+                .value_name("PATH")
+                .help("Send this file, e.g. a message of the day, to TCP clients that connect, after --banner")
+                .value_parser(value_parser!(PathBuf))
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("replay-lines")
+                .long("replay-lines")
+                .value_name("LINES")
+                .help("Replay this many lines of recent device output to TCP clients that connect [default: 0, off]")
+                .value_parser(value_parser!(usize))
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("silence-timeout")
+                .long("silence-timeout")
+                .value_name("SECONDS")
+                .help("Fire --silence-action when a device has no output for this long [default: off]")
+                .value_parser(value_parser!(f64))
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("silence-action")
+                .long("silence-action")
+                .value_name("ACTION")
+                .help("What a silent device triggers: announce, exec, reconnect or exit [default: announce]")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("silence-exec")
+                .long("silence-exec")
+                .value_name("COMMAND")
+                .help("Shell command run by --silence-action exec")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("success-pattern")
+                .long("success-pattern")
+                .value_name("REGEX")
+                .help("Exit with status 0 when a line of device output matches REGEX")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("failure-pattern")
+                .long("failure-pattern")
+                .value_name("REGEX")
+                .help("Exit with status 1 when a line of device output matches REGEX")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("health-prompt")
+                .long("health-prompt")
+                .value_name("REGEX")
+                .help("Probe the devices with Enter and announce when this prompt stops or starts coming back [default: off]")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("health-interval")
+                .long("health-interval")
+                .value_name("SECONDS")
+                .help("Time between health probes [default: 30]")
+                .value_parser(value_parser!(f64))
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("health-timeout")
+                .long("health-timeout")
+                .value_name("SECONDS")
+                .help("Time the prompt has to come back in [default: 5]")
+                .value_parser(value_parser!(f64))
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("drain-timeout")
+                .long("drain-timeout")
+                .value_name("SECONDS")
+                .help("Time to write what is still queued when quitting, 0 to drop it [default: 2]")
+                .value_parser(value_parser!(f64))
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("failover-timeout")
+                .long("failover-timeout")
+                .value_name("SECONDS")
+                .help("Time a device may be unavailable before failing over [default: 10]")
+                .value_parser(value_parser!(f64))
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("log-file")
+                .short('l')
+                .long("log-file")
+                .value_name("LOG_PATH")
+                .help("Enable logging and write logs to the specified file")
+                .value_parser(clap::value_parser!(PathBuf))
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("log-level")
+                .short('L')
+                .long("log-level")
+                .value_name("LOG_LEVEL")
+                .help("Set the log level (error, warn, info, debug, trace)")
+                .value_parser(clap::value_parser!(LevelFilter))
+                .default_value("info")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("log-format")
+                .long("log-format")
+                .value_name("FORMAT")
+                .help("Log line format: text, or json for one JSON object per line")
+                .value_parser(["text", "json"])
+                .default_value("text")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("no-announce")
+                .long("no-announce")
+                .help("Suppress informational messages (connect/disconnect) to clients")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("metrics-port")
+                .long("metrics-port")
+                .value_name("PORT")
+                .help("TCP port serving Prometheus metrics at /metrics")
+                .value_parser(value_parser!(u16)),
+        )
+        .arg(
+            Arg::new("http-port")
+                .long("http-port")
+                .value_name("PORT")
+                .help("TCP port serving a status page and JSON API (/status, /clients, /send, /disconnect-client)")
+                .value_parser(value_parser!(u16)),
+        )
+        .arg(
+            Arg::new("http-bind")
+                .long("http-bind")
+                .value_name("ADDR")
+                .help("Address of the status page and JSON API (default: 127.0.0.1)")
+                .value_parser(value_parser!(IpAddr)),
+        )
+        .arg(
+            Arg::new("http-token")
+                .long("http-token")
+                .value_name("TOKEN")
+                .help("Bearer token for the POST requests of the JSON API (default: listen-password)")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("http-read-only")
+                .long("http-read-only")
+                .help("Refuse POST /send of the JSON API")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("control")
+                .long("control")
+                .value_name("PATH")
+                .help("Unix socket taking administration commands, see crabterm ctl")
+                .value_parser(clap::value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("steal")
+                .long("steal")
+                .help("Ask the crabterm using the serial device to release it, through its control socket")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("mdns")
+                .long("mdns")
+                .value_name("NAME")
+                .help("Advertise the first -p port as NAME._crabterm._tcp with mDNS/DNS-SD")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("notify-url")
+                .long("notify-url")
+                .value_name("URL")
+                .help("POST JSON to this http:// URL on device events and notify-pattern matches")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("timestamp")
+                .long("timestamp")
+                .help("Start with the timestamp filter on")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("timestamp-rel")
+                .long("timestamp-rel")
+                .help("Start with the timestamp filter on, showing the time since the previous line")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("hex")
+                .long("hex")
+                .help("Start with the hex filter on, showing the output as hex bytes")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("imap")
+                .long("imap")
+                .value_name("LIST")
+                .help("Character mappings of the input, e.g. crlf,delbs")
+                .value_parser(CharmapFilter::check_mappings)
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("omap")
+                .long("omap")
+                .value_name("LIST")
+                .help("Character mappings of the output, e.g. lfcrlf")
+                .value_parser(CharmapFilter::check_mappings)
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("trace-io")
+                .long("trace-io")
+                .help("Log every buffer read from or written to the device as a hex dump (at trace level)")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("backend")
+                .long("backend")
+                .value_name("BACKEND")
+                .help("Event loop: mio (epoll), or io_uring when built with the io-uring feature")
+                .value_parser(["mio", "io_uring"])
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("capture-auto")
+                .long("capture-auto")
+                .value_name("DIR")
+                .help("Capture into DIR/<device>-<YYYYmmdd-HHMMSS>.log, a new file on every device connect")
+                .value_parser(value_parser!(PathBuf))
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("capture-split")
+                .long("capture-split")
+                .value_name("REGEX")
+                .help("With --capture-auto, also start a new file, numbered by boot, at output lines matching REGEX, e.g. 'U-Boot SPL'; with --capture-db, a new boot cycle")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("capture-db")
+                .long("capture-db")
+                .value_name("FILE")
+                .help("Store every line of device output in the SQLite database FILE, for crabterm search")
+                .value_parser(value_parser!(PathBuf))
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("pipe-to")
+                .long("pipe-to")
+                .value_name("COMMAND")
+                .help("Write the lines of device output to the stdin of COMMAND, started again when it exits")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("capture-compress")
+                .long("capture-compress")
+                .value_name("METHOD")
+                .help("Compress captures: none, gzip or zstd")
+                .value_parser(["none", "gzip", "zstd"])
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("capture-format")
+                .long("capture-format")
+                .value_name("FORMAT")
+                .help("Format of --capture-auto files: text, or pcapng for both directions in Wireshark")
+                .value_parser(["text", "pcapng"])
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("audit-input")
+                .long("audit-input")
+                .help("Record which client sent each input to the device, in the log and the capture")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("verbose")
+                .short('v')
+                .long("verbose")
+                .help("Enable console logging (-v=error, -vv=warn, -vvv=info, -vvvv=debug, -vvvvv=trace)")
+                .action(clap::ArgAction::Count),
+        )
+}
+
+/// Run crabterm as the parsed command line says. An error is shown on the
+/// console and returned, for the binary to exit with its code.
+pub fn main(matches: &ArgMatches) -> Result<(), CrabtermError> {
+    // The announce template is only known once the config is loaded
+    let mut announce_template = announce::DEFAULT_TEMPLATE.to_string();
+    let result = run(matches, &mut announce_template);
+    if let Err(e) = &result
+        && !matches!(e, CrabtermError::NoMatch)
+    {
+        let _ = disable_raw_mode();
+        raw_print!(
+            "{}",
+            announce::format_message(
+                &announce_template,
+                "Local",
+                &format!("Error: {}", e),
+                MessageKind::Error
+            )
+        );
+    }
+    result
+}
+
+/// Open the devices, servers and console the command line asks for, and run
+/// the hub until it quits; `template_out` gets the announce template.
+pub fn run(matches: &ArgMatches, template_out: &mut String) -> Result<(), CrabtermError> {
+    // The command line, for the log
+    let args: Vec<String> = std::env::args().collect();
+
+    EVENTS_ON_STDOUT.store(matches.get_flag("events-json"), Ordering::Relaxed);
+
+    if let Some(("self-test", _)) = matches.subcommand() {
+        return match selftest::run() {
+            true => Ok(()),
+            false => Err(CrabtermError::Failed("self-test failed".to_string())),
+        };
+    }
+
+    // Handle verbose flag - map count to log level
+    let verbose_count = matches.get_count("verbose");
+    let verbose_level = match verbose_count {
+        0 => None,
+        1 => Some(LevelFilter::Error),
+        2 => Some(LevelFilter::Warn),
+        3 => Some(LevelFilter::Info),
+        4 => Some(LevelFilter::Debug),
+        _ => Some(LevelFilter::Trace),
+    };
+
+    type LogFormat = fn(&mut dyn std::io::Write, &mut DeferredNow, &Record) -> std::io::Result<()>;
+    let (file_format, console_format): (LogFormat, LogFormat) =
+        match matches.get_one::<String>("log-format").map(|s| s.as_str()) {
+            Some("json") => (log_format_json, log_format_json_console),
+            _ => (log_format, log_format_console),
+        };
+
+    // --trace-io dumps are logged at trace level on their own target, so they
+    // show up without enabling trace logging for everything else.
+    let trace_io = matches.get_flag("trace-io");
+    let log_spec = move |level: LevelFilter| {
+        if trace_io {
+            format!("{}, {}=trace", level.as_str(), hexdump::TRACE_TARGET)
+        } else {
+            level.as_str().to_string()
+        }
+    };
+
+    // Configure logging; the handle and level are kept for changing the
+    // level at runtime
+    let mut logger_handle = None;
+    if let Some(path) = matches.get_one::<PathBuf>("log-file") {
+        let file_level = matches.get_one::<LevelFilter>("log-level").unwrap();
+
+        // If verbose is enabled, use the more verbose of the two levels
+        let effective_level = if let Some(vlevel) = verbose_level {
+            std::cmp::max(*file_level, vlevel)
+        } else {
+            *file_level
+        };
+
+        let log_file = FileSpec::try_from(path)
+            .map_err(|e| CrabtermError::BadArgs(format!("{}: {}", path.display(), e)))?;
+        let mut logger = Logger::try_with_str(log_spec(effective_level))
+            .unwrap()
+            .log_to_file(log_file)
+            .format_for_files(file_format)
+            .append()
+            .write_mode(WriteMode::Direct);
+
+        // If verbose is enabled, also duplicate to stderr with console format
+        if verbose_level.is_some() {
+            logger = logger
+                .duplicate_to_stderr(flexi_logger::Duplicate::All)
+                .format_for_stderr(console_format);
+        }
+
+        let handle = logger.start().map_err(|e| {
+            CrabtermError::Io(std::io::Error::other(format!(
+                "log file {}: {}",
+                path.display(),
+                e
+            )))
+        })?;
+        logger_handle = Some((handle, effective_level));
+    } else if let Some(vlevel) = verbose_level {
+        // No log file, but verbose is enabled - log to stderr with console format
+        let handle = Logger::try_with_str(log_spec(vlevel))
+            .unwrap()
+            .format(console_format)
+            .write_mode(WriteMode::Direct)
+            .start()
+            .unwrap();
+        logger_handle = Some((handle, vlevel));
+    }
+
+    info!("Starting crabterm");
+    info!("Command line: {}", args.join(" "));
+
+    let mut config = KeybindConfig::load(matches.get_one::<PathBuf>("config").cloned())
+        .map_err(CrabtermError::Config)?;
+    filter_flags(matches, &mut config.settings);
+    if let Some(key) = matches.get_one::<KeyEvent>("escape-char") {
+        config.set_escape_char(*key);
+    }
+    if matches.get_flag("no-escape") {
+        config.disable_escape();
+    }
+    let announce_template = config
+        .settings
+        .get("announce-template")
+        .and_then(|v| v.as_str())
+        .unwrap_or(announce::DEFAULT_TEMPLATE)
+        .to_string();
+    template_out.clone_from(&announce_template);
+    read_buffer::set_size(io_buffer_size(matches, &config)?);
+    // The filter only warns, it is configured again at run time
+    idle::gap(&config.settings).map_err(CrabtermError::Config)?;
+    if stdout_is_tty() {
+        announce::set_theme(
+            announce::Theme::from_settings(&config.settings).map_err(CrabtermError::Config)?,
+        );
+    }
+
+    if let Some(("attach", sub)) = matches.subcommand() {
+        return attach(sub.get_one::<String>("name"), config, &announce_template);
+    }
+    if let Some(("connect", sub)) = matches.subcommand() {
+        let addr = sub.get_one::<String>("address").expect("required");
+        return connect(addr, config, &announce_template);
+    }
+    let control_path = |m: &clap::ArgMatches| {
+        m.get_one::<PathBuf>("control").cloned().or_else(|| {
+            config
+                .settings
+                .get("control")
+                .and_then(|v| v.as_str())
+                .map(PathBuf::from)
+        })
+    };
+    if let Some(("bench", sub)) = matches.subcommand() {
+        return bench(sub, &announce_template);
+    }
+    if let Some(("search", sub)) = matches.subcommand() {
+        let path = sub
+            .get_one::<PathBuf>("db")
+            .cloned()
+            .or_else(|| {
+                config
+                    .settings
+                    .get(capture_db::SETTING)
+                    .and_then(|v| v.as_str())
+                    .map(PathBuf::from)
+            })
+            .ok_or_else(|| CrabtermError::BadArgs("No database, use --db FILE".to_string()))?;
+        return search(sub, &path);
+    }
+    if let Some(("ctl", sub)) = matches.subcommand() {
+        let path = control_path(sub).ok_or_else(|| {
+            CrabtermError::BadArgs("No control socket, use --control PATH".to_string())
+        })?;
+        let words: Vec<&str> = sub
+            .get_many::<String>("command")
+            .expect("required")
+            .map(|s| s.as_str())
+            .collect();
+        return ctl_client(&path, &words.join(" "));
+    }
+
+    // Devices with the port they are mapped to, if any
+    let mut device_modes: Vec<(&DeviceUri, Option<u16>)> = matches
+        .get_many::<DeviceUri>("device")
+        .or_else(|| matches.get_many::<DeviceUri>("devicepos"))
+        .into_iter()
+        .flatten()
+        .map(|d| (d, None))
+        .collect();
+    for (dev, port) in matches
+        .get_many::<(DeviceUri, u16)>("map")
+        .into_iter()
+        .flatten()
+    {
+        match device_modes.iter_mut().find(|(d, _)| *d == dev) {
+            Some(entry) => entry.1 = Some(*port),
+            None => device_modes.push((dev, Some(*port))),
+        }
+    }
+    if device_modes.is_empty() {
+        return Err(CrabtermError::BadArgs("No device specified".to_string()));
+    }
+    // Each channel belongs to the device given last before it
+    let device_indices: Vec<usize> = matches
+        .indices_of("device")
+        .or_else(|| matches.indices_of("devicepos"))
+        .into_iter()
+        .flatten()
+        .collect();
+    let channels: Vec<(usize, &String, &DeviceUri)> = matches
+        .get_many::<(String, DeviceUri)>("channel")
+        .into_iter()
+        .flatten()
+        .zip(matches.indices_of("channel").into_iter().flatten())
+        .map(|((name, dev), at)| {
+            let idx = device_indices.iter().rposition(|&d| d < at).unwrap_or(0);
+            (idx, name, dev)
+        })
+        .collect();
+
+    // With --detach this process only starts the session in the background,
+    // and that process is told apart by DETACHED_ENV.
+    #[cfg(unix)]
+    let mut session_server: Option<UnixServer> = None;
+    #[cfg(windows)]
+    if matches.get_flag("detach") {
+        return Err(no_unix_sockets("--detach"));
+    }
+    #[cfg(unix)]
+    if matches.get_flag("detach") {
+        let name = matches
+            .get_one::<String>("session")
+            .cloned()
+            .unwrap_or_else(|| session::name_from_label(&device_label(&device_modes[0].0.addr())));
+        let path = session::socket_path(&name)?;
+        match std::env::var(DETACHED_ENV) {
+            Ok(_) => {
+                info!("Session {} at {}", name, path.display());
+                session_server = Some(
+                    UnixServer::new(&path)
+                        .map_err(|e| CrabtermError::Bind(path.display().to_string(), e))?,
+                );
+            }
+            Err(_) => {
+                detach(&name, &path, &announce_template)?;
+                return Ok(());
+            }
+        }
+    }
+
+    let client_policy = client_policy(matches, &config)?;
+    let client_keybinds = matches.get_flag("client-keybinds")
+        || config
+            .settings
+            .get("client-keybinds")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
+    let mut servers: Vec<TcpServer> = Vec::new();
+    #[cfg(unix)]
+    let mut unix_servers: Vec<UnixServer> = Vec::new();
+    for spec in matches.get_many::<ListenSpec>("port").into_iter().flatten() {
+        match &spec.target {
+            ListenTarget::Tcp(port) => {
+                raw_print!(
+                    "{}",
+                    announce::format_message(
+                        &announce_template,
+                        "Local",
+                        &format!("Listning at port: {}", port),
+                        MessageKind::Info
+                    )
+                );
+                let mut s = TcpServer::new(*port).map_err(|e| bind_error(*port, e))?;
+                s.set_client_policy(ClientPolicy {
+                    rate_limit: spec.rate_limit.or(client_policy.rate_limit),
+                    ..client_policy
+                });
+                if client_keybinds {
+                    s.set_client_keybinds(config.clone());
+                }
+                s.set_role(spec.role);
+                servers.push(s);
+            }
+            #[cfg(unix)]
+            ListenTarget::Unix(path) => {
+                raw_print!(
+                    "{}",
+                    announce::format_message(
+                        &announce_template,
+                        "Local",
+                        &format!("Listening at socket: {}", path.display()),
+                        MessageKind::Info
+                    )
+                );
+                let mut s = UnixServer::with_access(path, spec.access.clone())
+                    .map_err(|e| CrabtermError::Bind(path.display().to_string(), e))?;
+                s.set_role(spec.role);
+                unix_servers.push(s);
+            }
+            #[cfg(windows)]
+            ListenTarget::Unix(path) => return Err(no_unix_sockets(&path.display().to_string())),
+        }
+    }
+    let password = config
+        .settings
+        .get("listen-password")
+        .and_then(|v| v.as_str())
+        .map(String::from);
+    let needs_password = matches
+        .get_many::<ListenSpec>("port")
+        .into_iter()
+        .flatten()
+        .any(|s| s.role.auth);
+    if needs_password && password.is_none() {
+        return Err(CrabtermError::Config(
+            "auth listeners need the listen-password setting".to_string(),
+        ));
+    }
+
+    let mut baudrate = matches.get_one::<Baudrate>("baudrate").unwrap().clone();
+    if let Baudrate::Auto(pattern) = &mut baudrate {
+        *pattern = matches
+            .get_one::<String>("autobaud-pattern")
+            .cloned()
+            .or_else(|| {
+                config
+                    .settings
+                    .get("autobaud-pattern")
+                    .and_then(|v| v.as_str())
+                    .map(String::from)
+            });
+    }
+    let serial = SerialOptions {
+        baudrate,
+        rs485: rs485_options(matches, &config)?,
+        mark_errors: matches.get_flag("mark-errors")
+            || config
+                .settings
+                .get("mark-errors")
+                .and_then(|v| v.as_bool())
+                .unwrap_or(false),
+        quarantine: quarantine(matches, &config)?,
+        keep_quarantined: matches.get_flag("quarantine-keep")
+            || config
+                .settings
+                .get("quarantine-keep")
+                .and_then(|v| v.as_bool())
+                .unwrap_or(false),
+    };
+    // Held by the hub until crabterm exits or releases the device. Windows
+    // opens serial ports exclusively, with no lock files to tell the owner.
+    #[cfg(windows)]
+    if matches.get_flag("steal") {
+        return Err(no_unix_sockets("--steal"));
+    }
+    #[cfg(unix)]
+    let locks = lock_devices(
+        &device_modes,
+        &config,
+        control_path(matches).as_deref(),
+        matches.get_flag("steal"),
+    )?;
+    let txt = mdns_txt(&device_modes, &serial.baudrate);
+    let mut devices: Vec<Box<dyn IoInstance>> = Vec::new();
+    let mut device_servers: Vec<(usize, TcpServer)> = Vec::new();
+    for (idx, (dev, port)) in device_modes.into_iter().enumerate() {
+        devices.push(open_device(dev, &serial, &announce_template)?);
+        if let Some(port) = port {
+            raw_print!(
+                "{}",
+                announce::format_message(
+                    &announce_template,
+                    "Local",
+                    &format!(
+                        "Listning at port: {} for {}",
+                        port,
+                        devices[idx].addr_as_string()
+                    ),
+                    MessageKind::Info
+                )
+            );
+            let mut s = TcpServer::new(port).map_err(|e| bind_error(port, e))?;
+            s.set_client_policy(client_policy);
+            if client_keybinds {
+                s.set_client_keybinds(config.clone());
+            }
+            device_servers.push((idx, s));
+        }
+    }
+
+    if let Some(fallbacks) = matches.get_many::<DeviceUri>("fallback") {
+        if devices.len() > 1 {
+            return Err(CrabtermError::BadArgs(
+                "--fallback can only be used with a single device".to_string(),
+            ));
+        }
+        for dev in fallbacks {
+            devices.push(open_device(dev, &serial, &announce_template)?);
+        }
+        let timeout = matches
+            .get_one::<f64>("failover-timeout")
+            .copied()
+            .or_else(|| {
+                config
+                    .settings
+                    .get("failover-timeout")
+                    .and_then(|v| v.as_str())
+                    .and_then(|s| s.parse().ok())
+            })
+            .unwrap_or(10.0);
+        let timeout = Duration::try_from_secs_f64(timeout).map_err(|_| {
+            CrabtermError::BadArgs(format!("failover-timeout: invalid seconds: {}", timeout))
+        })?;
+        let failover = FailoverDevice::new(devices, timeout)?;
+        devices = vec![Box::new(failover)];
+    }
+
+    let events_json = matches.get_flag("events-json");
+    #[cfg(unix)]
+    let detached = session_server.is_some();
+    #[cfg(windows)]
+    let detached = false;
+    let headless = matches.get_flag("headless") || detached || events_json;
+    // A CI run that waits for a pattern needs no clients
+    let exit_pattern = ["success-pattern", "failure-pattern"]
+        .iter()
+        .any(|name| matches.contains_id(name) || config.settings.contains_key(*name));
+
+    let serving = !servers.is_empty() || !device_servers.is_empty();
+    #[cfg(unix)]
+    let serving = serving || !unix_servers.is_empty() || detached;
+    if headless && !exit_pattern && !events_json && !serving {
+        return Err(CrabtermError::BadArgs(
+            "--headless requires -p/--port or --map option".to_string(),
+        ));
+    }
+
+    let announce = !matches.get_flag("no-announce");
+
+    let monitor_port = matches
+        .get_one::<u16>("device-monitor-port")
+        .copied()
+        .or_else(|| {
+            config
+                .settings
+                .get("device-monitor-port")
+                .and_then(|v| v.as_str())
+                .and_then(|s| s.parse().ok())
+        });
+
+    let monitor_template = matches
+        .get_one::<String>("device-monitor-template")
+        .cloned()
+        .or_else(|| {
+            config
+                .settings
+                .get("device-monitor-template")
+                .and_then(|v| v.as_str())
+                .map(|s| s.to_string())
+        })
+        .unwrap_or_else(|| "%s@%t: %m\r\n".to_string());
+
+    let monitor = if let Some(port) = monitor_port {
+        raw_print!(
+            "{}",
+            announce::format_message(
+                &announce_template,
+                "Local",
+                &format!("Device monitor at port: {}", port),
+                MessageKind::Info
+            )
+        );
+        Some(
+            DeviceMonitor::new(port, monitor_template, TOKEN_MONITOR_CLIENT_START.0)
+                .map_err(|e| bind_error(port, e))?,
+        )
+    } else {
+        None
+    };
+
+    let notifier = Notifier::from_settings(
+        matches.get_one::<String>("notify-url").map(|s| s.as_str()),
+        &config.settings,
+    )
+    .map_err(CrabtermError::Config)?;
+    let power = PowerControl::from_settings(&config.settings).map_err(CrabtermError::Config)?;
+
+    let metrics_port = matches.get_one::<u16>("metrics-port").copied().or_else(|| {
+        config
+            .settings
+            .get("metrics-port")
+            .and_then(|v| v.as_str())
+            .and_then(|s| s.parse().ok())
+    });
+
+    let metrics = if let Some(port) = metrics_port {
+        raw_print!(
+            "{}",
+            announce::format_message(
+                &announce_template,
+                "Local",
+                &format!("Metrics at port: {}", port),
+                MessageKind::Info
+            )
+        );
+        Some(
+            MetricsServer::new(port, TOKEN_METRICS_CLIENT_START.0)
+                .map_err(|e| bind_error(port, e))?,
+        )
+    } else {
+        None
+    };
+
+    let http_port = matches.get_one::<u16>("http-port").copied().or_else(|| {
+        config
+            .settings
+            .get("http-port")
+            .and_then(|v| v.as_str())
+            .and_then(|s| s.parse().ok())
+    });
+    let http = if let Some(port) = http_port {
+        raw_print!(
+            "{}",
+            announce::format_message(
+                &announce_template,
+                "Local",
+                &format!("Status page at port: {}", port),
+                MessageKind::Info
+            )
+        );
+        let bind = match matches.get_one::<IpAddr>("http-bind") {
+            Some(&addr) => addr,
+            None => match config.settings.get("http-bind").and_then(|v| v.as_str()) {
+                Some(s) => s
+                    .parse()
+                    .map_err(|_| CrabtermError::Config(format!("Invalid http-bind: {}", s)))?,
+                None => IpAddr::V4(Ipv4Addr::LOCALHOST),
+            },
+        };
+        let token = matches
+            .get_one::<String>("http-token")
+            .cloned()
+            .or_else(|| {
+                config
+                    .settings
+                    .get("http-token")
+                    .and_then(|v| v.as_str())
+                    .map(String::from)
+            })
+            .or_else(|| password.clone());
+        let read_only = matches.get_flag("http-read-only")
+            || config
+                .settings
+                .get("http-read-only")
+                .and_then(|v| v.as_bool())
+                .unwrap_or(false);
+        let mut http =
+            HttpServer::new_with_addr(SocketAddr::new(bind, port), TOKEN_HTTP_CLIENT_START.0)
+                .map_err(|e| bind_error(port, e))?
+                .with_read_only(read_only);
+        if let Some(token) = token {
+            http = http.with_token(token);
+        }
+        Some(http)
+    } else {
+        None
+    };
+
+    #[cfg(unix)]
+    let ctl = match control_path(matches) {
+        Some(path) => Some(
+            CtlServer::new(&path, TOKEN_CTL_CLIENT_START.0)
+                .map_err(|e| CrabtermError::Bind(path.display().to_string(), e))?,
+        ),
+        None => None,
+    };
+    #[cfg(windows)]
+    if control_path(matches).is_some() {
+        return Err(no_unix_sockets("--control"));
+    }
+
+    let mdns_name = matches.get_one::<String>("mdns").cloned().or_else(|| {
+        config
+            .settings
+            .get("mdns")
+            .and_then(|v| v.as_str())
+            .map(String::from)
+    });
+    let mdns = match mdns_name {
+        Some(name) => {
+            let port = servers
+                .first()
+                .and_then(|s| s.local_addr().ok())
+                .ok_or_else(|| CrabtermError::BadArgs("--mdns requires -p PORT".to_string()))?
+                .port();
+            let failed = |e| CrabtermError::Bind("mDNS".to_string(), e);
+            let service = mdns::Service {
+                name,
+                host: mdns::hostname(),
+                addr: mdns::local_addr().map_err(failed)?,
+                port,
+                txt,
+            };
+            Some(MdnsResponder::new(service).map_err(failed)?)
+        }
+        None => None,
+    };
+
+    // Filters run by the hub for TCP clients and captures, raw by default
+    let filters = filter_factory(&config.settings, "client-filters").and_then(|client| {
+        filter_factory(&config.settings, "capture-filters").map(|capture| (client, capture))
+    });
+    let (client_filters, capture_filters) = filters.map_err(CrabtermError::Config)?;
+
+    let write_coalesce = matches
+        .get_one::<u64>("write-coalesce")
+        .copied()
+        .or_else(|| {
+            config
+                .settings
+                .get("write-coalesce")
+                .and_then(|v| v.as_str())
+                .and_then(|s| s.parse().ok())
+        })
+        .map(Duration::from_millis)
+        .unwrap_or(Duration::ZERO);
+    let frame_gap = matches
+        .get_one::<u64>("frame-gap")
+        .copied()
+        .or_else(|| {
+            config
+                .settings
+                .get("frame-gap")
+                .and_then(|v| v.as_str())
+                .and_then(|s| s.parse().ok())
+        })
+        .map(Duration::from_millis)
+        .unwrap_or(Duration::ZERO);
+    let replay_lines = matches
+        .get_one::<usize>("replay-lines")
+        .copied()
+        .or_else(|| {
+            config
+                .settings
+                .get("replay-lines")
+                .and_then(|v| v.as_str())
+                .and_then(|s| s.parse().ok())
+        })
+        .unwrap_or(0);
+    let banner = Banner::new(
+        matches.get_one::<String>("banner").cloned().or_else(|| {
+            config
+                .settings
+                .get(banner::SETTING)
+                .and_then(|v| v.as_str())
+                .map(String::from)
+        }),
+        matches
+            .get_one::<PathBuf>("banner-file")
+            .cloned()
+            .or_else(|| {
+                config
+                    .settings
+                    .get(banner::SETTING_FILE)
+                    .and_then(|v| v.as_str())
+                    .map(PathBuf::from)
+            }),
+    );
+    let keepalive = config
+        .settings
+        .get("device-keepalive")
+        .and_then(|v| v.as_str())
+        .map(|s| s.as_bytes().to_vec());
+    let keepalive_interval = match config.settings.get("device-keepalive-interval") {
+        Some(v) => v
+            .as_str()
+            .and_then(|s| s.parse::<f64>().ok())
+            .and_then(|s| Duration::try_from_secs_f64(s).ok())
+            .filter(|d| !d.is_zero())
+            .ok_or_else(|| {
+                CrabtermError::Config(
+                    "device-keepalive-interval must be a number of seconds".to_string(),
+                )
+            })?,
+        None => Duration::from_secs(30),
+    };
+    let setting = |name: &str| {
+        matches.get_one::<String>(name).cloned().or_else(|| {
+            config
+                .settings
+                .get(name)
+                .and_then(|v| v.as_str())
+                .map(String::from)
+        })
+    };
+    let silence_timeout = matches
+        .get_one::<f64>("silence-timeout")
+        .copied()
+        .or_else(|| {
+            config
+                .settings
+                .get("silence-timeout")
+                .and_then(|v| v.as_str())
+                .and_then(|s| s.parse().ok())
+        })
+        .map(|secs: f64| {
+            Duration::try_from_secs_f64(secs).map_err(|_| {
+                CrabtermError::BadArgs(format!("silence-timeout: invalid seconds: {}", secs))
+            })
+        })
+        .transpose()?;
+    let silence_action = SilenceAction::parse(
+        setting("silence-action").as_deref().unwrap_or("announce"),
+        setting("silence-exec").as_deref(),
+    )
+    .map_err(CrabtermError::BadArgs)?;
+    let seconds_setting = |name: &str| -> Result<Option<f64>, CrabtermError> {
+        match matches.get_one::<f64>(name) {
+            Some(&secs) => Ok(Some(secs)),
+            None => config
+                .settings
+                .get(name)
+                .map(|v| {
+                    v.as_str()
+                        .and_then(|s| s.parse::<f64>().ok())
+                        .ok_or_else(|| {
+                            CrabtermError::Config(format!("{} must be a number of seconds", name))
+                        })
+                })
+                .transpose(),
+        }
+    };
+    let seconds = |name: &str, default: f64| -> Result<Duration, CrabtermError> {
+        Duration::try_from_secs_f64(seconds_setting(name)?.unwrap_or(default))
+            .ok()
+            .filter(|d| !d.is_zero())
+            .ok_or_else(|| CrabtermError::BadArgs(format!("{} must be positive", name)))
+    };
+    // Zero quits without waiting
+    let drain_timeout = match seconds_setting("drain-timeout")? {
+        Some(s) => Duration::try_from_secs_f64(s).map_err(|_| {
+            CrabtermError::BadArgs("drain-timeout must not be negative".to_string())
+        })?,
+        None => DEFAULT_DRAIN_TIMEOUT,
+    };
+    let health = match setting("health-prompt") {
+        Some(prompt) => Some(HealthConfig {
+            prompt: regex::Regex::new(&prompt)
+                .map_err(|e| CrabtermError::BadArgs(format!("Invalid health-prompt: {}", e)))?,
+            probe: config
+                .settings
+                .get("health-probe")
+                .and_then(|v| v.as_str())
+                .map_or(b"\r".to_vec(), |s| s.as_bytes().to_vec()),
+            interval: seconds("health-interval", 30.0)?,
+            timeout: seconds("health-timeout", 5.0)?,
+        }),
+        None => None,
+    };
+    let audit_input = matches.get_flag("audit-input")
+        || config
+            .settings
+            .get("audit-input")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
+    let backend = matches
+        .get_one::<String>("backend")
+        .map(|s| s.as_str())
+        .or_else(|| config.settings.get("backend").and_then(|v| v.as_str()))
+        .map(|s| s.parse::<Backend>().map_err(CrabtermError::Config))
+        .transpose()?
+        .unwrap_or_default();
+    let mut devices = devices.into_iter();
+    let mut builder = IoHub::builder(devices.next().unwrap())
+        .backend(backend)
+        .client_filters(client_filters)
+        .capture_filters(capture_filters)
+        .announce(announce)
+        .announce_template(announce_template.clone())
+        .trace_io(trace_io)
+        .audit_input(audit_input)
+        .write_coalesce(write_coalesce)
+        .frame_gap(frame_gap)
+        .replay_lines(replay_lines)
+        .write_lock(
+            matches.get_flag("write-lock")
+                || config
+                    .settings
+                    .get("write-lock")
+                    .and_then(|v| v.as_bool())
+                    .unwrap_or(false),
+        )
+        .drain_timeout(drain_timeout);
+    if let Some(timeout) = silence_timeout.filter(|t| !t.is_zero()) {
+        builder = builder.silence(timeout, silence_action);
+    }
+    if let Some(banner) = banner {
+        builder = builder.banner(banner);
+    }
+    if let Some(bytes) = keepalive {
+        builder = builder.keepalive(bytes, keepalive_interval);
+    }
+    if let Some(h) = health {
+        builder = builder.health(h);
+    }
+    let pattern = |name: &str| {
+        setting(name)
+            .map(|p| {
+                regex::Regex::new(&p)
+                    .map_err(|e| CrabtermError::BadArgs(format!("Invalid {}: {}", name, e)))
+            })
+            .transpose()
+    };
+    let exit_patterns = ExitPatterns {
+        success: pattern("success-pattern")?,
+        failure: pattern("failure-pattern")?,
+    };
+    if exit_patterns.success.is_some() || exit_patterns.failure.is_some() {
+        builder = builder.exit_patterns(exit_patterns);
+    }
+    if let Some(marker) = config
+        .settings
+        .get("latency-marker")
+        .and_then(|v| v.as_str())
+    {
+        builder = builder.latency_marker(marker.as_bytes().to_vec());
+    }
+    for d in devices {
+        builder = builder.device(d);
+    }
+    for (idx, name, dev) in channels {
+        builder = builder.channel(idx, name, open_device(dev, &serial, &announce_template)?);
+    }
+    let capture_auto = matches
+        .get_one::<PathBuf>("capture-auto")
+        .cloned()
+        .or_else(|| {
+            config
+                .settings
+                .get("capture-auto")
+                .and_then(|v| v.as_str())
+                .map(PathBuf::from)
+        });
+    let capture_compress = matches
+        .get_one::<String>("capture-compress")
+        .map(|s| s.as_str())
+        .or_else(|| {
+            config
+                .settings
+                .get("capture-compress")
+                .and_then(|v| v.as_str())
+        });
+    if let Some(method) = capture_compress {
+        let compression = Compression::parse(method).ok_or_else(|| {
+            CrabtermError::Config(format!("capture-compress: unknown method {}", method))
+        })?;
+        builder = builder.capture_compress(compression);
+    }
+    let capture_format = matches
+        .get_one::<String>("capture-format")
+        .map(|s| s.as_str())
+        .or_else(|| {
+            config
+                .settings
+                .get("capture-format")
+                .and_then(|v| v.as_str())
+        });
+    if let Some(format) = capture_format {
+        let format = Format::parse(format).ok_or_else(|| {
+            CrabtermError::Config(format!("capture-format: unknown format {}", format))
+        })?;
+        builder = builder.capture_format(format);
+    }
+    let capture_split = matches
+        .get_one::<String>("capture-split")
+        .map(|s| s.as_str())
+        .or_else(|| {
+            config
+                .settings
+                .get("capture-split")
+                .and_then(|v| v.as_str())
+        });
+    let capture_db = matches
+        .get_one::<PathBuf>("capture-db")
+        .cloned()
+        .or_else(|| {
+            config
+                .settings
+                .get(capture_db::SETTING)
+                .and_then(|v| v.as_str())
+                .map(PathBuf::from)
+        });
+    if let Some(pattern) = capture_split {
+        if capture_auto.is_none() && capture_db.is_none() {
+            return Err(CrabtermError::BadArgs(
+                "capture-split needs --capture-auto or --capture-db".to_string(),
+            ));
+        }
+        let pattern = regex::Regex::new(pattern)
+            .map_err(|e| CrabtermError::BadArgs(format!("Invalid capture-split: {}", e)))?;
+        builder = builder.capture_split(pattern);
+    }
+    if let Some(dir) = capture_auto {
+        std::fs::create_dir_all(&dir)
+            .map_err(|e| CrabtermError::Config(format!("capture-auto {}: {}", dir.display(), e)))?;
+        builder = builder.capture_auto(dir);
+    }
+    if let Some(path) = capture_db {
+        let db = CaptureDb::open(&path)
+            .map_err(|e| CrabtermError::Config(format!("capture-db {}: {}", path.display(), e)))?;
+        builder = builder.capture_db(db);
+    }
+    let pipe_to = matches
+        .get_one::<String>("pipe-to")
+        .map(|s| s.as_str())
+        .or_else(|| config.settings.get(pipe::SETTING).and_then(|v| v.as_str()));
+    if let Some(command) = pipe_to {
+        let pipe = PipeTo::new(command)
+            .map_err(|e| CrabtermError::Config(format!("pipe-to {:?}: {}", command, e)))?;
+        builder = builder.pipe_to(pipe);
+    }
+    for s in servers {
+        builder = builder.server(s);
+    }
+    #[cfg(unix)]
+    for s in unix_servers {
+        builder = builder.unix_server(s);
+    }
+    if let Some(p) = password {
+        builder = builder.password(p);
+    }
+    for (idx, s) in device_servers {
+        builder = builder.device_server(idx, s);
+    }
+    #[cfg(unix)]
+    for (idx, lock) in locks {
+        builder = builder.device_lock(idx, lock);
+    }
+    #[cfg(unix)]
+    if let Some(s) = session_server {
+        builder = builder.session(s);
+    }
+    if let Some(m) = monitor {
+        builder = builder.monitor(m);
+    }
+    if let Some(p) = power {
+        builder = builder.power(p);
+    }
+    if let Some(n) = notifier {
+        builder = builder.notifier(n);
+    }
+    if events_json {
+        builder = builder.events(EventStream::stdout());
+    }
+    if let Some(m) = mdns {
+        builder = builder.mdns(m);
+    }
+    if let Some(h) = http {
+        builder = builder.http(h);
+    }
+    #[cfg(unix)]
+    if let Some(c) = ctl {
+        builder = builder.ctl(c);
+    }
+    if let Some(m) = metrics {
+        builder = builder.metrics(m);
+    }
+    // SIGUSR2 raises the log level to debug, or to trace if it already is
+    // debug, and back; the log-level action sets it
+    if let Some((mut handle, mut level)) = logger_handle {
+        builder = builder.log_control(Box::new(move |change| {
+            let result = match change {
+                LogChange::Raise => {
+                    let raised = if level >= LevelFilter::Debug {
+                        LevelFilter::Trace
+                    } else {
+                        LevelFilter::Debug
+                    };
+                    handle.parse_and_push_temp_spec(log_spec(raised))
+                }
+                LogChange::Restore => {
+                    handle.pop_temp_spec();
+                    Ok(())
+                }
+                LogChange::Set(new_level) => {
+                    level = new_level;
+                    handle.parse_new_spec(&log_spec(new_level))
+                }
+            };
+            result.map_err(|e| format!("Log level: {}", e))
+        }));
+    }
+    let mut hub = builder.build()?;
+
+    if !headless {
+        let filter_chain = FilterChain::new(&config.settings);
+        let mut console = Console::new(config, filter_chain)?;
+        console.set_client_policy(client_policy);
+        hub.add(Box::new(console))?;
+    }
+
+    loop {
+        info!("Main loop: checking quit status");
+        if hub.is_quit_requested() {
+            info!("Main loop: quit requested, breaking");
+            break;
+        }
+        info!("Main loop: calling hub.run()");
+        hub.run()?;
+        info!("Main loop: hub.run() returned");
+    }
+
+    info!("Main loop exited, shutting down");
+    let summary = hub.stats_summary();
+    // Restores the terminal, and makes stdout blocking again for print!()
+    drop(hub);
+    for line in summary {
+        info!("Stats: {}", line);
+        if announce {
+            raw_print!(
+                "{}",
+                announce::format_message(&announce_template, "Local", &line, MessageKind::Info)
+            );
+        }
+    }
+    Ok(())
+}
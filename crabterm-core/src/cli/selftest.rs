@@ -0,0 +1,254 @@
+//! `crabterm self-test`: exercise the main building blocks in-process so a
+//! build can be verified on a new platform without any hardware attached.
+
+use mio::Token;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::{Duration, Instant};
+
+use crate::io::{EchoDevice, TcpDevice, TcpServer};
+use crate::iofilter::{FilterChain, charmap};
+use crate::keybind::config::SettingValue;
+use crate::keybind::key::{Key, KeyEvent, Modifiers};
+use crate::keybind::parser::{KeyParser, ParseResult};
+use crate::keybind::processor::key_event_to_bytes;
+use crate::poll::{Events, Poll};
+use crate::traits::{IoInstance, IoResult};
+
+const TIMEOUT: Duration = Duration::from_secs(2);
+const TOKEN_A: Token = Token(0);
+const TOKEN_B: Token = Token(1);
+const TOKEN_LISTENER: Token = Token(2);
+
+type CheckResult = Result<String, String>;
+type Check = (&'static str, fn() -> CheckResult);
+
+/// Run all checks, print a report and return true if everything passed.
+pub fn run() -> bool {
+    let checks: [Check; 4] = [
+        ("echo device", check_echo_device),
+        ("filter chain", check_filter_chain),
+        ("key parser round-trip", check_key_parser),
+        ("tcp server/client loop", check_tcp_loop),
+    ];
+
+    println!("crabterm self-test");
+    let mut failed = 0;
+    for (name, check) in checks {
+        let started = Instant::now();
+        match check() {
+            Ok(detail) => println!(
+                "  {:<24} ok    ({}, {:.1} ms)",
+                name,
+                detail,
+                started.elapsed().as_secs_f64() * 1000.0
+            ),
+            Err(e) => {
+                failed += 1;
+                println!("  {:<24} FAIL  ({})", name, e);
+            }
+        }
+    }
+
+    if failed == 0 {
+        println!("All checks passed");
+    } else {
+        println!("{} check(s) failed", failed);
+    }
+    failed == 0
+}
+
+/// Poll until `expected` bytes have been read from `instance`, or timeout.
+fn read_exact(
+    poll: &mut Poll,
+    instance: &mut dyn IoInstance,
+    expected: usize,
+) -> Result<Vec<u8>, String> {
+    let mut events = Events::with_capacity(16);
+    let mut received = Vec::new();
+    let deadline = Instant::now() + TIMEOUT;
+
+    while received.len() < expected {
+        if Instant::now() > deadline {
+            return Err(format!(
+                "timeout, got {} of {} bytes",
+                received.len(),
+                expected
+            ));
+        }
+        loop {
+            match instance.read() {
+                Ok(IoResult::Data(d)) => received.extend_from_slice(&d),
+                Ok(_) => break,
+                Err(e) => return Err(format!("read: {}", e)),
+            }
+        }
+        if received.len() < expected {
+            poll.poll(&mut events, Some(Duration::from_millis(50)))
+                .map_err(|e| format!("poll: {}", e))?;
+        }
+    }
+    Ok(received)
+}
+
+fn check_echo_device() -> CheckResult {
+    let mut poll = Poll::new().map_err(|e| e.to_string())?;
+    let mut echo = EchoDevice::new().map_err(|e| e.to_string())?;
+    echo.connect(&mut poll, TOKEN_A)
+        .map_err(|e| format!("connect: {}", e))?;
+
+    let payload = b"crabterm self-test\r\n";
+    if echo.write_all(payload) != payload.len() {
+        return Err("short write".to_string());
+    }
+    let received = read_exact(&mut poll, &mut echo, payload.len())?;
+    echo.disconnect(&mut poll);
+
+    if received != payload {
+        return Err(format!("echoed {:?}", String::from_utf8_lossy(&received)));
+    }
+    Ok(format!("{} bytes echoed", payload.len()))
+}
+
+fn check_filter_chain() -> CheckResult {
+    let mut settings = HashMap::new();
+    settings.insert(
+        charmap::SETTING_IMAP.to_string(),
+        SettingValue::String("crlf".to_string()),
+    );
+    settings.insert(
+        charmap::SETTING_OMAP.to_string(),
+        SettingValue::String("lfcrlf".to_string()),
+    );
+    let mut chain = FilterChain::new(&settings);
+
+    let out = chain.filter_out(b"a\rb");
+    if out != b"a\nb" {
+        return Err(format!("charmap out: {:?}", out));
+    }
+    let input = chain.filter_in(b"x\n");
+    if input != b"x\r\n" {
+        return Err(format!("charmap in: {:?}", input));
+    }
+
+    if !chain.toggle("timestamp") {
+        return Err("timestamp filter missing".to_string());
+    }
+    let stamped = chain.filter_out(b"line\n");
+    if stamped.len() <= b"line\n".len() || !stamped.ends_with(b"line\n") {
+        return Err(format!(
+            "timestamp out: {:?}",
+            String::from_utf8_lossy(&stamped)
+        ));
+    }
+    Ok("charmap, timestamp".to_string())
+}
+
+fn check_key_parser() -> CheckResult {
+    let mut keys = vec![
+        KeyEvent::char('a'),
+        KeyEvent::char('Z'),
+        KeyEvent::ctrl_char('a'),
+        KeyEvent::ctrl_char('q'),
+        KeyEvent::ctrl_char(']'),
+        KeyEvent::ctrl_char('_'),
+        KeyEvent::new(Key::Char('x'), Modifiers::alt()),
+        KeyEvent::new(Key::Escape, Modifiers::none()),
+        KeyEvent::new(Key::Enter, Modifiers::none()),
+        KeyEvent::new(Key::Tab, Modifiers::none()),
+        KeyEvent::new(Key::Backspace, Modifiers::none()),
+    ];
+    for key in [
+        Key::Up,
+        Key::Down,
+        Key::Left,
+        Key::Right,
+        Key::Home,
+        Key::End,
+        Key::PageUp,
+        Key::PageDown,
+        Key::Insert,
+        Key::Delete,
+    ] {
+        keys.push(KeyEvent::new(key, Modifiers::none()));
+    }
+    for n in 1..=24 {
+        keys.push(KeyEvent::new(Key::F(n), Modifiers::none()));
+    }
+    for c in "0123456789\r+-*/.,=".chars() {
+        keys.push(KeyEvent::new(Key::Keypad(c), Modifiers::none()));
+    }
+
+    for key in &keys {
+        let bytes = key_event_to_bytes(key).ok_or_else(|| format!("{} has no encoding", key))?;
+        let mut parser = KeyParser::new();
+        parser.push(&bytes);
+        let parsed = match parser.parse_next() {
+            // A lone ESC is ambiguous until the escape timeout expires
+            ParseResult::NeedMore => parser.force_parse_first(),
+            r => Some(r),
+        };
+        match parsed {
+            Some(ParseResult::Key(k, n)) if k == *key && n == bytes.len() => {}
+            other => return Err(format!("{} ({:02x?}) parsed as {:?}", key, bytes, other)),
+        }
+    }
+    Ok(format!("{} keys", keys.len()))
+}
+
+fn check_tcp_loop() -> CheckResult {
+    let mut poll = Poll::new().map_err(|e| e.to_string())?;
+    let mut server = TcpServer::new_with_addr(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0))
+        .map_err(|e| format!("bind: {}", e))?;
+    server
+        .register(&mut poll, TOKEN_LISTENER)
+        .map_err(|e| e.to_string())?;
+    let addr = server.local_addr().map_err(|e| e.to_string())?;
+
+    let mut device = TcpDevice::new(addr).map_err(|e| e.to_string())?;
+    let mut events = Events::with_capacity(16);
+    let deadline = Instant::now() + TIMEOUT;
+    let mut client = None;
+
+    // Drive the non-blocking connect and accept until both ends are up
+    while client.is_none() || !device.connected() {
+        if Instant::now() > deadline {
+            return Err("timeout connecting".to_string());
+        }
+        if !device.connected() {
+            match device.connect(&mut poll, TOKEN_A) {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+                Err(e) => return Err(format!("connect: {}", e)),
+            }
+        }
+        if client.is_none()
+            && let Some(mut c) = server.accept()
+        {
+            c.connect(&mut poll, TOKEN_B)
+                .map_err(|e| format!("register client: {}", e))?;
+            client = Some(c);
+        }
+        poll.poll(&mut events, Some(Duration::from_millis(50)))
+            .map_err(|e| format!("poll: {}", e))?;
+    }
+    let mut client = client.unwrap();
+
+    let to_server = b"hello from device side";
+    device.write_all(to_server);
+    let got = read_exact(&mut poll, &mut *client, to_server.len())?;
+    if got != to_server {
+        return Err(format!("client got {:?}", String::from_utf8_lossy(&got)));
+    }
+
+    let to_device = b"hello from client side";
+    client.write_all(to_device);
+    let got = read_exact(&mut poll, &mut device, to_device.len())?;
+    if got != to_device {
+        return Err(format!("device got {:?}", String::from_utf8_lossy(&got)));
+    }
+
+    client.disconnect(&mut poll);
+    device.disconnect(&mut poll);
+    Ok(format!("via {}", addr))
+}
@@ -0,0 +1,52 @@
+//! The time of the hub's timers: timeouts, keepalives, reconnects and the
+//! like. Normally the monotonic clock of the system; with the `testing`
+//! feature a test moves it forward with [`advance`], so a timeout fires at
+//! once instead of after a real wait.
+//!
+//! The offset belongs to the thread, like the hub that reads it.
+
+#[cfg(feature = "testing")]
+use std::cell::Cell;
+#[cfg(feature = "testing")]
+use std::time::Duration;
+use std::time::Instant;
+
+#[cfg(feature = "testing")]
+thread_local! {
+    /// How far [`advance`] moved the clock of this thread
+    static OFFSET: Cell<Duration> = const { Cell::new(Duration::ZERO) };
+}
+
+/// The current time of the timers
+#[cfg(not(feature = "testing"))]
+#[inline]
+pub fn now() -> Instant {
+    Instant::now()
+}
+
+/// The current time of the timers, moved forward by [`advance`]
+#[cfg(feature = "testing")]
+pub fn now() -> Instant {
+    Instant::now() + OFFSET.get()
+}
+
+/// Move the clock of this thread forward by `by`.
+#[cfg(feature = "testing")]
+pub fn advance(by: Duration) {
+    OFFSET.set(OFFSET.get() + by);
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_advance() {
+        let before = now();
+        advance(Duration::from_secs(60));
+        assert!(now().duration_since(before) >= Duration::from_secs(60));
+        // Other threads keep their own time
+        let other = std::thread::spawn(now).join().unwrap();
+        assert!(other < before + Duration::from_secs(60));
+    }
+}
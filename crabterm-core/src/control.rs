@@ -0,0 +1,303 @@
+//! In-band control of a crabterm server by `crabterm connect`.
+//!
+//! A client sends commands to the server as APC strings in its input,
+//! `ESC _ crabterm <command> ESC \`. The commands are those of the ":"
+//! prompt (see [`crate::keybind::parse_command`]), the server answers with
+//! announcements. Everything else in the input goes to the device as before.
+//!
+//! A client that sends the [`FRAMES`] command gets its output framed: the
+//! server answers with the same control string, and after it sends typed
+//! frames, `kind, length (u32, big endian), payload`, so announcements and
+//! device state changes are not mixed into the device data. Older servers
+//! answer with an error announcement and go on unframed.
+
+/// Start of a control string
+pub const START: &[u8] = b"\x1b_crabterm ";
+/// String terminator
+pub const END: &[u8] = b"\x1b\\";
+/// Longer strings are not control strings and are passed through
+pub const MAX_COMMAND: usize = 256;
+
+/// The bytes that send `command` to the server.
+pub fn encode(command: &str) -> Vec<u8> {
+    let mut out = Vec::with_capacity(START.len() + command.len() + END.len());
+    out.extend_from_slice(START);
+    out.extend_from_slice(command.as_bytes());
+    out.extend_from_slice(END);
+    out
+}
+
+/// The command asking for framed output, and the server's answer
+pub const FRAMES: &str = "frames";
+
+/// Bytes before the payload of a frame
+const FRAME_HEADER: usize = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum FrameKind {
+    /// Device output
+    Data = b'D',
+    /// An announcement, without the template applied
+    Announce = b'A',
+    /// A device state change, e.g. that it connected
+    State = b'S',
+}
+
+/// A frame of `kind` around `payload`.
+pub fn frame(kind: FrameKind, payload: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(FRAME_HEADER + payload.len());
+    out.push(kind as u8);
+    out.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    out.extend_from_slice(payload);
+    out
+}
+
+/// Server output, as decoded by the client
+#[derive(Debug, PartialEq)]
+pub enum Frame {
+    Data(Vec<u8>),
+    Announce(String),
+    State(String),
+}
+
+/// Decodes the output of a server asked for [`FRAMES`]. Until the server
+/// answers, its output is data; a frame may be split over several reads.
+#[derive(Debug, Default)]
+pub struct FrameDecoder {
+    framed: bool,
+    held: Vec<u8>,
+}
+
+impl FrameDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// True once the server has answered
+    pub fn framed(&self) -> bool {
+        self.framed
+    }
+
+    pub fn feed(&mut self, buf: &[u8]) -> Vec<Frame> {
+        let mut input = std::mem::take(&mut self.held);
+        input.extend_from_slice(buf);
+
+        let mut out = Vec::new();
+        let mut rest = &input[..];
+        if !self.framed {
+            let answer = encode(FRAMES);
+            match find(rest, &answer) {
+                Some(i) => {
+                    if i > 0 {
+                        out.push(Frame::Data(rest[..i].to_vec()));
+                    }
+                    self.framed = true;
+                    rest = &rest[i + answer.len()..];
+                }
+                None => {
+                    // Hold back what may be the start of the answer, but
+                    // not a lone ESC
+                    let keep = (2..answer.len())
+                        .rev()
+                        .find(|&n| rest.ends_with(&answer[..n]))
+                        .unwrap_or(0);
+                    let (data, held) = rest.split_at(rest.len() - keep);
+                    if !data.is_empty() {
+                        out.push(Frame::Data(data.to_vec()));
+                    }
+                    self.held = held.to_vec();
+                    return out;
+                }
+            }
+        }
+
+        while rest.len() >= FRAME_HEADER {
+            let len = u32::from_be_bytes([rest[1], rest[2], rest[3], rest[4]]) as usize;
+            let Some(payload) = rest.get(FRAME_HEADER..FRAME_HEADER + len) else {
+                break;
+            };
+            let text = || String::from_utf8_lossy(payload).into_owned();
+            match rest[0] {
+                b'D' => out.push(Frame::Data(payload.to_vec())),
+                b'A' => out.push(Frame::Announce(text())),
+                b'S' => out.push(Frame::State(text())),
+                // From a newer server, skipped
+                _ => {}
+            }
+            rest = &rest[FRAME_HEADER + len..];
+        }
+        self.held = rest.to_vec();
+        out
+    }
+}
+
+/// Client input, split into device data and control commands
+#[derive(Debug, PartialEq)]
+pub enum Input {
+    Data(Vec<u8>),
+    Command(String),
+}
+
+/// Finds control strings in a client's input. A string may be split over
+/// several reads, the start of one is held back until it is complete.
+#[derive(Debug, Default)]
+pub struct ControlParser {
+    held: Vec<u8>,
+}
+
+impl ControlParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn feed(&mut self, buf: &[u8]) -> Vec<Input> {
+        let mut input = std::mem::take(&mut self.held);
+        input.extend_from_slice(buf);
+
+        let mut out = Vec::new();
+        let mut data = Vec::new();
+        let mut rest = &input[..];
+        while let Some(i) = rest.iter().position(|&b| b == 0x1b) {
+            data.extend_from_slice(&rest[..i]);
+            let candidate = &rest[i..];
+            // A lone ESC is a key press, only ESC _ is held back
+            if candidate.len() < START.len() {
+                if candidate.len() > 1 && START.starts_with(candidate) {
+                    self.held = candidate.to_vec();
+                    rest = &[];
+                    break;
+                }
+            } else if candidate.starts_with(START) {
+                let body = &candidate[START.len()..];
+                match find(body, END) {
+                    Some(j) if j <= MAX_COMMAND => {
+                        if !data.is_empty() {
+                            out.push(Input::Data(std::mem::take(&mut data)));
+                        }
+                        let command = String::from_utf8_lossy(&body[..j]);
+                        out.push(Input::Command(command.trim().to_string()));
+                        rest = &body[j + END.len()..];
+                        continue;
+                    }
+                    None if body.len() < MAX_COMMAND + END.len() => {
+                        self.held = candidate.to_vec();
+                        rest = &[];
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+            data.push(0x1b);
+            rest = &candidate[1..];
+        }
+        data.extend_from_slice(rest);
+        if !data.is_empty() {
+            out.push(Input::Data(data));
+        }
+        out
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data(s: &[u8]) -> Input {
+        Input::Data(s.to_vec())
+    }
+
+    fn command(s: &str) -> Input {
+        Input::Command(s.to_string())
+    }
+
+    #[test]
+    fn test_commands_between_data() {
+        let mut p = ControlParser::new();
+        let mut buf = b"ab".to_vec();
+        buf.extend(encode("stats"));
+        buf.extend(b"c\x1b[A");
+        assert_eq!(
+            p.feed(&buf),
+            vec![data(b"ab"), command("stats"), data(b"c\x1b[A")]
+        );
+        assert_eq!(p.feed(b"\x1b"), vec![data(b"\x1b")]);
+    }
+
+    #[test]
+    fn test_split_command() {
+        let mut p = ControlParser::new();
+        let buf = encode("filter toggle timestamp");
+        assert_eq!(p.feed(&buf[..1]), vec![data(b"\x1b")]);
+        let mut p = ControlParser::new();
+        assert_eq!(p.feed(&buf[..5]), vec![]);
+        assert_eq!(p.feed(&buf[5..20]), vec![]);
+        assert_eq!(p.feed(&buf[20..]), vec![command("filter toggle timestamp")]);
+    }
+
+    #[test]
+    fn test_frames() {
+        let mut d = FrameDecoder::new();
+        let mut buf = b"hello".to_vec();
+        buf.extend(encode(FRAMES));
+        buf.extend(frame(FrameKind::Data, b"ab\x1b_"));
+        buf.extend(frame(FrameKind::State, b"/dev/ttyUSB0: Connected"));
+        buf.extend(frame(FrameKind::Announce, b"Log level debug"));
+        buf.extend(frame(FrameKind::Data, b"cd"));
+
+        // Split everywhere, even in the answer
+        let mut out = d.feed(&buf[..7]);
+        assert!(!d.framed());
+        for i in 7..buf.len() {
+            out.extend(d.feed(&buf[i..i + 1]));
+        }
+        assert!(d.framed());
+        let data: Vec<u8> = out
+            .iter()
+            .filter_map(|f| match f {
+                Frame::Data(d) => Some(d.clone()),
+                _ => None,
+            })
+            .flatten()
+            .collect();
+        assert_eq!(data, b"helloab\x1b_cd");
+        assert!(out.contains(&Frame::State("/dev/ttyUSB0: Connected".to_string())));
+        assert!(out.contains(&Frame::Announce("Log level debug".to_string())));
+    }
+
+    #[test]
+    fn test_frames_not_answered() {
+        let mut d = FrameDecoder::new();
+        assert_eq!(d.feed(b"a\x1b"), vec![Frame::Data(b"a\x1b".to_vec())]);
+        assert_eq!(d.feed(b"b\x1b_cr"), vec![Frame::Data(b"b".to_vec())]);
+        assert_eq!(d.feed(b"x"), vec![Frame::Data(b"\x1b_crx".to_vec())]);
+        assert!(!d.framed());
+
+        // Unknown kinds are skipped
+        let mut d = FrameDecoder::new();
+        let mut buf = encode(FRAMES);
+        buf.extend(frame(FrameKind::Data, b"1"));
+        buf.extend([b'Z', 0, 0, 0, 1, b'?']);
+        buf.extend(frame(FrameKind::Data, b"2"));
+        assert_eq!(
+            d.feed(&buf),
+            vec![Frame::Data(b"1".to_vec()), Frame::Data(b"2".to_vec())]
+        );
+    }
+
+    #[test]
+    fn test_not_a_command() {
+        let mut p = ControlParser::new();
+        assert_eq!(p.feed(b"\x1b_other\x1b\\"), vec![data(b"\x1b_other\x1b\\")]);
+
+        // Unterminated and too long
+        let mut buf = START.to_vec();
+        buf.extend(vec![b'x'; MAX_COMMAND + 10]);
+        let out = p.feed(&buf);
+        assert_eq!(out, vec![Input::Data(buf)]);
+    }
+}
@@ -0,0 +1,228 @@
+//! Control socket for administering a running crabterm (`--control PATH`).
+//!
+//! Clients send one command per line, e.g. `stats`, `baud 9600` or
+//! `kick 7`. Each command is answered with lines of output followed by `OK`,
+//! or by `ERROR: message`. The hub runs the commands (see
+//! `IoHub::ctl_command`); `crabterm ctl` is the client.
+
+use log::{debug, error, info};
+use mio::net::{UnixListener, UnixStream};
+use mio::{Interest, Token};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read, Result, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use crate::poll::Poll;
+
+/// A command line longer than this closes the connection
+const MAX_LINE: usize = 4096;
+
+/// Last line of a successful reply
+pub const OK: &str = "OK";
+/// Start of the last line of a failed reply
+pub const ERROR: &str = "ERROR: ";
+
+struct Connection {
+    stream: UnixStream,
+    input: Vec<u8>,
+    /// The client is done, closed once its commands are answered
+    closed: bool,
+}
+
+pub struct CtlServer {
+    listener: UnixListener,
+    path: PathBuf,
+    connections: HashMap<Token, Connection>,
+    token_start: usize,
+}
+
+impl CtlServer {
+    /// Listen on `path`, accessible to the owner only. A stale socket is
+    /// replaced; one that somebody listens on is an error.
+    pub fn new(path: &Path, token_start: usize) -> Result<Self> {
+        if path.exists() {
+            if std::os::unix::net::UnixStream::connect(path).is_ok() {
+                return Err(Error::new(
+                    ErrorKind::AddrInUse,
+                    format!("{} is in use", path.display()),
+                ));
+            }
+            std::fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+        info!("Control socket at {}", path.display());
+        Ok(CtlServer {
+            listener,
+            path: path.to_path_buf(),
+            connections: HashMap::new(),
+            token_start,
+        })
+    }
+
+    pub fn register(&mut self, poll: &mut Poll, token: Token) -> Result<()> {
+        poll.registry()
+            .register(&mut self.listener, token, Interest::READABLE)
+    }
+
+    /// True if `token` belongs to one of our connections.
+    pub fn owns(&self, token: Token) -> bool {
+        self.connections.contains_key(&token)
+    }
+
+    pub fn accept(&mut self, poll: &mut Poll) -> Result<()> {
+        loop {
+            match self.listener.accept() {
+                Ok((mut stream, _)) => {
+                    let token = self.alloc_token();
+                    debug!("Ctl({:?}): connected", token);
+                    poll.registry()
+                        .register(&mut stream, token, Interest::READABLE)?;
+                    self.connections.insert(
+                        token,
+                        Connection {
+                            stream,
+                            input: Vec::new(),
+                            closed: false,
+                        },
+                    );
+                }
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => {
+                    error!("Ctl: accept error: {}", e);
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    fn alloc_token(&self) -> Token {
+        let mut token_id = self.token_start;
+        while self.connections.contains_key(&Token(token_id)) {
+            token_id += 1;
+        }
+        Token(token_id)
+    }
+
+    /// Read from a connection; the complete command lines received, to be
+    /// answered with [`Self::reply`] before calling [`Self::finish`].
+    pub fn read(&mut self, token: Token) -> Vec<String> {
+        let Some(conn) = self.connections.get_mut(&token) else {
+            return Vec::new();
+        };
+        let mut tmp = [0u8; 1024];
+        conn.closed = loop {
+            match conn.stream.read(&mut tmp) {
+                Ok(0) => break true,
+                Ok(n) => conn.input.extend_from_slice(&tmp[..n]),
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => break false,
+                Err(_) => break true,
+            }
+        };
+        let mut lines = Vec::new();
+        while let Some(end) = conn.input.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = conn.input.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line).trim().to_string();
+            if !line.is_empty() {
+                lines.push(line);
+            }
+        }
+        if conn.input.len() > MAX_LINE {
+            conn.closed = true;
+        }
+        lines
+    }
+
+    /// Close the connection if the client is done.
+    pub fn finish(&mut self, poll: &mut Poll, token: Token) {
+        if self.connections.get(&token).is_some_and(|conn| conn.closed)
+            && let Some(mut conn) = self.connections.remove(&token)
+        {
+            let _ = poll.registry().deregister(&mut conn.stream);
+        }
+    }
+
+    /// Answer a command with its output, or its error.
+    pub fn reply(&mut self, token: Token, result: std::result::Result<Vec<String>, String>) {
+        let Some(conn) = self.connections.get_mut(&token) else {
+            return;
+        };
+        let mut out = String::new();
+        match result {
+            Ok(lines) => {
+                for line in lines {
+                    out.push_str(line.trim_end());
+                    out.push('\n');
+                }
+                out.push_str(OK);
+            }
+            Err(e) => {
+                out.push_str(ERROR);
+                out.push_str(&e);
+            }
+        }
+        out.push('\n');
+        // Replies are small, a blocked client only loses its own replies
+        let _ = conn.stream.write_all(out.as_bytes());
+    }
+}
+
+impl Drop for CtlServer {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Send `command` to the control socket at `path`, and return the output
+/// lines, or the error of the command.
+pub fn send(path: &Path, command: &str) -> Result<std::result::Result<Vec<String>, String>> {
+    let mut stream = std::os::unix::net::UnixStream::connect(path)?;
+    writeln!(stream, "{}", command)?;
+    let mut lines = Vec::new();
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line == OK {
+            return Ok(Ok(lines));
+        }
+        if let Some(e) = line.strip_prefix(ERROR) {
+            return Ok(Err(e.to_string()));
+        }
+        lines.push(line);
+    }
+    Err(Error::new(ErrorKind::UnexpectedEof, "no reply"))
+}
+
+/// Split a command into its name and arguments: `kick 7` is ("kick", "7").
+pub fn split(line: &str) -> (&str, &str) {
+    let line = line.trim();
+    line.split_once(char::is_whitespace)
+        .map(|(name, rest)| (name, rest.trim()))
+        .unwrap_or((line, ""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split() {
+        assert_eq!(split("kick 7"), ("kick", "7"));
+        assert_eq!(split(" stats "), ("stats", ""));
+        assert_eq!(
+            split("capture start  /tmp/x.log"),
+            ("capture", "start  /tmp/x.log")
+        );
+    }
+
+    #[test]
+    fn test_socket_permissions() {
+        let path = std::env::temp_dir().join(format!("crabterm-ctl-{}.sock", std::process::id()));
+        let server = CtlServer::new(&path, 7001).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert!(CtlServer::new(&path, 7001).is_err());
+        drop(server);
+        assert!(!path.exists());
+    }
+}
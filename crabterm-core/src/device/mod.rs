@@ -0,0 +1,5 @@
+//! Describing devices before they are opened.
+
+pub mod uri;
+
+pub use uri::DeviceUri;
@@ -0,0 +1,412 @@
+//! Device addresses, with per-device options as URI parameters:
+//!
+//! ```text
+//! serial:///dev/ttyUSB0?baud=9600&parity=even
+//! tcp://host:4000?nodelay=1
+//! telnet://termserver:7001
+//! unix:///run/console.sock
+//! echo://
+//! qemu:unix:/tmp/vm.sock?mux=1
+//! ```
+//!
+//! The short forms `/dev/ttyUSB0`, `host:port` and `echo` remain valid. On
+//! Windows serial ports are `COM3`, or `\\.\COM12`.
+
+use mio_serial::{DataBits, Parity, StopBits};
+use std::path::PathBuf;
+
+/// `baud=`: a rate, or `auto` to detect it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Baud {
+    Rate(u32),
+    Auto,
+}
+
+/// Options of a serial device; unset ones take the command line defaults
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SerialParams {
+    pub baud: Option<Baud>,
+    pub data_bits: Option<DataBits>,
+    pub parity: Option<Parity>,
+    pub stop_bits: Option<StopBits>,
+}
+
+/// A QEMU chardev endpoint, as given to `-serial` or `-chardev`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QemuEndpoint {
+    Unix(PathBuf),
+    Tcp(String),
+    /// The symlink of `-chardev pty,path=...`
+    Pty(String),
+}
+
+impl QemuEndpoint {
+    fn spec(&self) -> String {
+        match self {
+            QemuEndpoint::Unix(path) => format!("unix:{}", path.display()),
+            QemuEndpoint::Tcp(addr) => format!("tcp:{}", addr),
+            QemuEndpoint::Pty(path) => format!("pty:{}", path),
+        }
+    }
+}
+
+/// A device address. `mux` of `Qemu`: the chardev is shared with the QEMU
+/// monitor (`mux=on`, `-serial mon:...`), so Ctrl-a is escaped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceUri {
+    Serial {
+        path: String,
+        params: SerialParams,
+    },
+    /// `telnet`: the server speaks telnet (`telnet://`)
+    Tcp {
+        addr: String,
+        nodelay: bool,
+        telnet: bool,
+    },
+    Unix {
+        path: PathBuf,
+    },
+    Echo,
+    Qemu {
+        endpoint: QemuEndpoint,
+        mux: bool,
+    },
+}
+
+impl DeviceUri {
+    /// The address the device will report, before it is opened.
+    pub fn addr(&self) -> String {
+        match self {
+            DeviceUri::Serial { path, .. } => path.clone(),
+            DeviceUri::Tcp { addr, .. } => addr.clone(),
+            DeviceUri::Unix { path } => path.display().to_string(),
+            DeviceUri::Echo => "Echo".to_string(),
+            DeviceUri::Qemu { endpoint, .. } => format!("qemu:{}", endpoint.spec()),
+        }
+    }
+}
+
+const USAGE: &str = "Invalid device format. Use /dev/ttyUSB0, COM3, hostname:port, echo, \
+                     serial://, tcp://, telnet://, unix:// URIs, or qemu:unix:PATH, qemu:tcp:HOST:PORT, \
+                     qemu:pty:PATH";
+
+pub fn parse(val: &str) -> Result<DeviceUri, String> {
+    if let Some(spec) = val.strip_prefix("qemu:") {
+        return parse_qemu(val, spec);
+    }
+    let Some((scheme, rest)) = val.split_once("://") else {
+        return parse_short(val);
+    };
+    let (target, query) = rest.split_once('?').unwrap_or((rest, ""));
+    let params = parse_query(query)?;
+    match scheme {
+        "serial" => {
+            if !target.starts_with('/') && !is_com_port(target) {
+                return Err(format!("serial:// needs a device path: {}", val));
+            }
+            let mut p = SerialParams::default();
+            for (key, value) in params {
+                match key {
+                    "baud" => p.baud = Some(parse_baud(value)?),
+                    "databits" => p.data_bits = Some(parse_data_bits(value)?),
+                    "parity" => p.parity = Some(parse_parity(value)?),
+                    "stopbits" => p.stop_bits = Some(parse_stop_bits(value)?),
+                    _ => return Err(unknown(scheme, key)),
+                }
+            }
+            Ok(DeviceUri::Serial {
+                path: target.to_string(),
+                params: p,
+            })
+        }
+        "tcp" | "telnet" => {
+            if !is_host_port(target) {
+                return Err(format!("{}:// needs host:port: {}", scheme, val));
+            }
+            let mut nodelay = false;
+            for (key, value) in params {
+                match key {
+                    "nodelay" => nodelay = parse_bool(key, value)?,
+                    _ => return Err(unknown(scheme, key)),
+                }
+            }
+            Ok(DeviceUri::Tcp {
+                addr: target.to_string(),
+                nodelay,
+                telnet: scheme == "telnet",
+            })
+        }
+        "unix" => {
+            if target.is_empty() {
+                return Err(format!("unix:// needs a socket path: {}", val));
+            }
+            if let Some((key, _)) = params.first() {
+                return Err(unknown(scheme, key));
+            }
+            Ok(DeviceUri::Unix {
+                path: PathBuf::from(target),
+            })
+        }
+        "echo" => Ok(DeviceUri::Echo),
+        _ => Err(format!("Unknown device scheme: {}://", scheme)),
+    }
+}
+
+/// `qemu:unix:PATH`, `qemu:tcp:HOST:PORT` or `qemu:pty:PATH`, as QEMU
+/// takes them; QEMU options after a comma (`,server=on,wait=off`) are
+/// ignored, so the endpoint can be copied from the QEMU command line.
+fn parse_qemu(val: &str, spec: &str) -> Result<DeviceUri, String> {
+    let (spec, query) = spec.split_once('?').unwrap_or((spec, ""));
+    let spec = spec.split(',').next().unwrap_or_default();
+    let mut mux = false;
+    for (key, value) in parse_query(query)? {
+        match key {
+            "mux" => mux = parse_bool(key, value)?,
+            _ => return Err(format!("Unknown parameter for qemu:: {}", key)),
+        }
+    }
+    let (kind, target) = spec.split_once(':').unwrap_or((spec, ""));
+    let endpoint = match kind {
+        "unix" if !target.is_empty() => QemuEndpoint::Unix(PathBuf::from(target)),
+        // `tcp::4444` is localhost, as in QEMU
+        "tcp" if target.starts_with(':') => QemuEndpoint::Tcp(format!("localhost{}", target)),
+        "tcp" if is_host_port(target) => QemuEndpoint::Tcp(target.to_string()),
+        "pty" if !target.is_empty() => QemuEndpoint::Pty(target.to_string()),
+        "unix" | "tcp" | "pty" => return Err(format!("Invalid QEMU endpoint: {}", val)),
+        _ => {
+            return Err(format!(
+                "Unknown QEMU endpoint: {} (unix:PATH, tcp:HOST:PORT or pty:PATH)",
+                val
+            ));
+        }
+    };
+    Ok(DeviceUri::Qemu { endpoint, mux })
+}
+
+/// `/dev/ttyUSB0`, `COM3`, `echo` or `host:port`
+fn parse_short(val: &str) -> Result<DeviceUri, String> {
+    if val.starts_with("/dev/") || is_com_port(val) {
+        return Ok(DeviceUri::Serial {
+            path: val.to_string(),
+            params: SerialParams::default(),
+        });
+    }
+    if val.starts_with("echo") {
+        return Ok(DeviceUri::Echo);
+    }
+    if is_host_port(val) {
+        return Ok(DeviceUri::Tcp {
+            addr: val.to_string(),
+            nodelay: false,
+            telnet: false,
+        });
+    }
+    Err(USAGE.to_string())
+}
+
+/// A Windows serial port: `COM3`, or `\\.\COM10` as Windows names those
+/// above COM9.
+fn is_com_port(val: &str) -> bool {
+    let name = val.strip_prefix(r"\\.\").unwrap_or(val);
+    name.get(..3)
+        .is_some_and(|com| com.eq_ignore_ascii_case("com"))
+        && name.len() > 3
+        && name[3..].bytes().all(|b| b.is_ascii_digit())
+}
+
+fn is_host_port(val: &str) -> bool {
+    val.rsplit_once(':')
+        .is_some_and(|(host, port)| !host.is_empty() && !port.is_empty())
+}
+
+fn parse_query(query: &str) -> Result<Vec<(&str, &str)>, String> {
+    query
+        .split('&')
+        .filter(|p| !p.is_empty())
+        .map(|p| {
+            p.split_once('=')
+                .ok_or_else(|| format!("Invalid device parameter (KEY=VALUE): {}", p))
+        })
+        .collect()
+}
+
+fn unknown(scheme: &str, key: &str) -> String {
+    format!("Unknown parameter for {}://: {}", scheme, key)
+}
+
+fn parse_bool(key: &str, value: &str) -> Result<bool, String> {
+    match value {
+        "1" | "on" | "true" | "yes" => Ok(true),
+        "0" | "off" | "false" | "no" => Ok(false),
+        _ => Err(format!("Invalid {}: {} (on or off)", key, value)),
+    }
+}
+
+fn parse_baud(value: &str) -> Result<Baud, String> {
+    if value == "auto" {
+        return Ok(Baud::Auto);
+    }
+    match value.parse() {
+        Ok(rate) if rate > 0 => Ok(Baud::Rate(rate)),
+        _ => Err(format!("Invalid baud: {} (a number or auto)", value)),
+    }
+}
+
+fn parse_data_bits(value: &str) -> Result<DataBits, String> {
+    match value {
+        "5" => Ok(DataBits::Five),
+        "6" => Ok(DataBits::Six),
+        "7" => Ok(DataBits::Seven),
+        "8" => Ok(DataBits::Eight),
+        _ => Err(format!("Invalid databits: {} (5 to 8)", value)),
+    }
+}
+
+fn parse_parity(value: &str) -> Result<Parity, String> {
+    match value {
+        "none" => Ok(Parity::None),
+        "even" => Ok(Parity::Even),
+        "odd" => Ok(Parity::Odd),
+        _ => Err(format!("Invalid parity: {} (none, even or odd)", value)),
+    }
+}
+
+fn parse_stop_bits(value: &str) -> Result<StopBits, String> {
+    match value {
+        "1" => Ok(StopBits::One),
+        "2" => Ok(StopBits::Two),
+        _ => Err(format!("Invalid stopbits: {} (1 or 2)", value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_short_forms() {
+        assert_eq!(
+            parse("/dev/ttyUSB0"),
+            Ok(DeviceUri::Serial {
+                path: "/dev/ttyUSB0".to_string(),
+                params: SerialParams::default(),
+            })
+        );
+        assert_eq!(
+            parse("localhost:4000"),
+            Ok(DeviceUri::Tcp {
+                addr: "localhost:4000".to_string(),
+                nodelay: false,
+                telnet: false,
+            })
+        );
+        assert_eq!(parse("echo"), Ok(DeviceUri::Echo));
+        assert!(parse("ttyUSB0").is_err());
+    }
+
+    #[test]
+    fn test_com_ports() {
+        for port in ["COM3", "com4", r"\\.\COM12"] {
+            assert_eq!(
+                parse(port),
+                Ok(DeviceUri::Serial {
+                    path: port.to_string(),
+                    params: SerialParams::default(),
+                })
+            );
+        }
+        let uri = parse("serial://COM3?baud=9600").unwrap();
+        assert_eq!(uri.addr(), "COM3");
+        assert!(parse("COM").is_err());
+        assert!(parse("COMA").is_err());
+    }
+
+    #[test]
+    fn test_serial() {
+        assert_eq!(
+            parse("serial:///dev/ttyUSB0?baud=9600&parity=even&databits=7&stopbits=2"),
+            Ok(DeviceUri::Serial {
+                path: "/dev/ttyUSB0".to_string(),
+                params: SerialParams {
+                    baud: Some(Baud::Rate(9600)),
+                    data_bits: Some(DataBits::Seven),
+                    parity: Some(Parity::Even),
+                    stop_bits: Some(StopBits::Two),
+                },
+            })
+        );
+        let uri = parse("serial:///dev/ttyACM0?baud=auto").unwrap();
+        assert_eq!(uri.addr(), "/dev/ttyACM0");
+        assert!(matches!(
+            uri,
+            DeviceUri::Serial { params, .. } if params.baud == Some(Baud::Auto)
+        ));
+
+        assert!(parse("serial://dev/ttyUSB0").is_err());
+        assert!(parse("serial:///dev/ttyUSB0?parity=mark").is_err());
+        assert_eq!(
+            parse("serial:///dev/ttyUSB0?speed=9600"),
+            Err("Unknown parameter for serial://: speed".to_string())
+        );
+    }
+
+    #[test]
+    fn test_other_schemes() {
+        assert_eq!(
+            parse("tcp://10.0.0.5:4000?nodelay=1"),
+            Ok(DeviceUri::Tcp {
+                addr: "10.0.0.5:4000".to_string(),
+                nodelay: true,
+                telnet: false,
+            })
+        );
+        assert!(parse("tcp://10.0.0.5").is_err());
+        assert_eq!(
+            parse("unix:///run/console.sock"),
+            Ok(DeviceUri::Unix {
+                path: PathBuf::from("/run/console.sock"),
+            })
+        );
+        assert_eq!(parse("echo://"), Ok(DeviceUri::Echo));
+        assert_eq!(
+            parse("telnet://host:23"),
+            Ok(DeviceUri::Tcp {
+                addr: "host:23".to_string(),
+                nodelay: false,
+                telnet: true,
+            })
+        );
+        assert!(parse("telnet://host").is_err());
+        assert!(parse("ftp://host").is_err());
+        assert!(parse("tcp://host:1?nodelay").is_err());
+    }
+
+    #[test]
+    fn test_qemu() {
+        let uri = parse("qemu:unix:/tmp/vm.sock,server=on,wait=off?mux=1").unwrap();
+        assert_eq!(
+            uri,
+            DeviceUri::Qemu {
+                endpoint: QemuEndpoint::Unix(PathBuf::from("/tmp/vm.sock")),
+                mux: true,
+            }
+        );
+        assert_eq!(uri.addr(), "qemu:unix:/tmp/vm.sock");
+        assert_eq!(
+            parse("qemu:tcp::4444"),
+            Ok(DeviceUri::Qemu {
+                endpoint: QemuEndpoint::Tcp("localhost:4444".to_string()),
+                mux: false,
+            })
+        );
+        assert_eq!(
+            parse("qemu:pty:/tmp/vm.pty").unwrap().addr(),
+            "qemu:pty:/tmp/vm.pty"
+        );
+        assert!(parse("qemu:tcp:host").is_err());
+        assert!(parse("qemu:unix:").is_err());
+        assert!(parse("qemu:stdio").is_err());
+        assert!(parse("qemu:pty:/tmp/p?telnet=1").is_err());
+    }
+}
@@ -0,0 +1,112 @@
+//! JSON event stream (`--events-json`): the session as crabterm sees it,
+//! one JSON object per line, for tools that drive crabterm from a script.
+//!
+//! ```text
+//! {"type":"connect","ts":1760000000.123,"device":"/dev/ttyUSB0"}
+//! {"type":"data","ts":1760000000.456,"device":"/dev/ttyUSB0","bytes":[111,107,13,10],"text":"ok\r\n"}
+//! {"type":"client_join","ts":1760000001.001,"client":"127.0.0.1:50312"}
+//! ```
+//!
+//! `bytes` is the exact output of the device; `text` is the same decoded as
+//! UTF-8, with invalid sequences replaced.
+
+use log::warn;
+use serde_json::{Value, json};
+use std::io::Write;
+
+pub struct EventStream {
+    out: Box<dyn Write>,
+    broken: bool,
+}
+
+impl EventStream {
+    pub fn new(out: Box<dyn Write>) -> Self {
+        EventStream { out, broken: false }
+    }
+
+    /// Events on stdout.
+    pub fn stdout() -> Self {
+        Self::new(Box::new(std::io::stdout()))
+    }
+
+    fn emit(&mut self, kind: &str, mut event: Value) {
+        if self.broken {
+            return;
+        }
+        event["type"] = json!(kind);
+        event["ts"] = json!(chrono::Utc::now().timestamp_millis() as f64 / 1000.0);
+        let result = writeln!(self.out, "{}", event).and_then(|_| self.out.flush());
+        if let Err(e) = result {
+            warn!("Events: write failed, stream stopped: {}", e);
+            self.broken = true;
+        }
+    }
+
+    pub fn data(&mut self, device: &str, buf: &[u8]) {
+        self.emit(
+            "data",
+            json!({
+                "device": device,
+                "bytes": buf,
+                "text": String::from_utf8_lossy(buf),
+            }),
+        );
+    }
+
+    pub fn connect(&mut self, device: &str) {
+        self.emit("connect", json!({ "device": device }));
+    }
+
+    pub fn disconnect(&mut self, device: &str, reason: &str) {
+        self.emit("disconnect", json!({ "device": device, "reason": reason }));
+    }
+
+    pub fn client_join(&mut self, client: &str) {
+        self.emit("client_join", json!({ "client": client }));
+    }
+
+    pub fn client_leave(&mut self, client: &str) {
+        self.emit("client_leave", json!({ "client": client }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_lines() {
+        let out = Shared::default();
+        let mut events = EventStream::new(Box::new(out.clone()));
+        events.connect("echo");
+        events.data("echo", b"ok\r\n\xff");
+        events.client_join("127.0.0.1:5000");
+
+        let text = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<Value> = text
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0]["type"], "connect");
+        assert_eq!(lines[0]["device"], "echo");
+        assert!(lines[0]["ts"].as_f64().unwrap() > 1e9);
+        assert_eq!(lines[1]["type"], "data");
+        assert_eq!(lines[1]["bytes"], json!([111, 107, 13, 10, 255]));
+        assert_eq!(lines[1]["text"], "ok\r\n\u{fffd}");
+        assert_eq!(lines[2]["client"], "127.0.0.1:5000");
+    }
+}
@@ -0,0 +1,229 @@
+//! Health check of managed boards (`--health-prompt`): now and then a probe
+//! (Enter by default) is written to the device, and the board counts as up
+//! if its prompt comes back within the timeout. A prompt in the normal
+//! output counts too, and postpones the next probe.
+
+use regex::Regex;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Output kept for matching the prompt
+const MAX_SEEN: usize = 1024;
+
+#[derive(Debug, Clone)]
+pub struct HealthConfig {
+    /// Matched against the output since the probe
+    pub prompt: Regex,
+    /// Written to the device to get a prompt
+    pub probe: Vec<u8>,
+    /// Time from a good check to the next probe
+    pub interval: Duration,
+    /// Time the prompt has to come back in
+    pub timeout: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Health {
+    /// Not checked since the device connected
+    Unknown,
+    Up,
+    Down,
+}
+
+impl fmt::Display for Health {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Health::Unknown => "unknown",
+            Health::Up => "up",
+            Health::Down => "down",
+        })
+    }
+}
+
+/// What [`HealthCheck::tick`] wants done
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tick {
+    Nothing,
+    /// Write the probe to the device
+    Probe,
+    /// The state changed
+    Changed(Health),
+}
+
+/// The health check of one device
+#[derive(Debug, Clone)]
+pub struct HealthCheck {
+    config: HealthConfig,
+    state: Health,
+    next_probe: Instant,
+    /// Set while waiting for the prompt
+    deadline: Option<Instant>,
+    seen: Vec<u8>,
+    last_up: Option<Instant>,
+    /// Probes sent
+    pub checks: u64,
+    /// Probes not answered in time
+    pub failures: u64,
+}
+
+impl HealthCheck {
+    pub fn new(config: HealthConfig, now: Instant) -> Self {
+        HealthCheck {
+            config,
+            state: Health::Unknown,
+            next_probe: now,
+            deadline: None,
+            seen: Vec::new(),
+            last_up: None,
+            checks: 0,
+            failures: 0,
+        }
+    }
+
+    pub fn state(&self) -> Health {
+        self.state
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.config.timeout
+    }
+
+    pub fn probe(&self) -> &[u8] {
+        &self.config.probe
+    }
+
+    /// When the prompt was last seen.
+    pub fn last_up(&self) -> Option<Instant> {
+        self.last_up
+    }
+
+    /// Start over, e.g. when the device (re)connects: unknown, and a probe
+    /// at once.
+    pub fn reset(&mut self, now: Instant) {
+        self.state = Health::Unknown;
+        self.next_probe = now;
+        self.deadline = None;
+        self.seen.clear();
+    }
+
+    /// When [`Self::tick`] has something to do.
+    pub fn due(&self) -> Instant {
+        self.deadline.unwrap_or(self.next_probe)
+    }
+
+    pub fn tick(&mut self, now: Instant) -> Tick {
+        match self.deadline {
+            Some(deadline) if deadline <= now => {
+                self.deadline = None;
+                self.failures += 1;
+                self.next_probe = now + self.config.interval;
+                self.set(Health::Down)
+            }
+            None if self.next_probe <= now => {
+                self.deadline = Some(now + self.config.timeout);
+                self.seen.clear();
+                self.checks += 1;
+                Tick::Probe
+            }
+            _ => Tick::Nothing,
+        }
+    }
+
+    /// Look for the prompt in output of the device.
+    pub fn output(&mut self, data: &[u8], now: Instant) -> Tick {
+        self.seen.extend_from_slice(data);
+        if self.seen.len() > MAX_SEEN {
+            self.seen.drain(..self.seen.len() - MAX_SEEN);
+        }
+        if !self
+            .config
+            .prompt
+            .is_match(&String::from_utf8_lossy(&self.seen))
+        {
+            return Tick::Nothing;
+        }
+        self.seen.clear();
+        self.deadline = None;
+        self.next_probe = now + self.config.interval;
+        self.last_up = Some(now);
+        self.set(Health::Up)
+    }
+
+    fn set(&mut self, state: Health) -> Tick {
+        if self.state == state {
+            return Tick::Nothing;
+        }
+        self.state = state;
+        Tick::Changed(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(now: Instant) -> HealthCheck {
+        HealthCheck::new(
+            HealthConfig {
+                prompt: Regex::new(r"[#$] $").unwrap(),
+                probe: b"\r".to_vec(),
+                interval: Duration::from_secs(30),
+                timeout: Duration::from_secs(5),
+            },
+            now,
+        )
+    }
+
+    #[test]
+    fn test_probe_and_prompt() {
+        let t0 = Instant::now();
+        let mut h = check(t0);
+        assert_eq!(h.state(), Health::Unknown);
+        assert_eq!(h.tick(t0), Tick::Probe);
+        assert_eq!(h.due(), t0 + Duration::from_secs(5));
+        assert_eq!(h.tick(t0 + Duration::from_secs(1)), Tick::Nothing);
+
+        // The prompt split over two reads
+        let t1 = t0 + Duration::from_secs(1);
+        assert_eq!(h.output(b"\r\nroot@board:~", t1), Tick::Nothing);
+        assert_eq!(h.output(b"# ", t1), Tick::Changed(Health::Up));
+        assert_eq!(h.due(), t1 + Duration::from_secs(30));
+        assert_eq!(h.last_up(), Some(t1));
+
+        // Another prompt changes nothing, but postpones the probe
+        let t2 = t1 + Duration::from_secs(10);
+        assert_eq!(h.output(b"ls\r\n$ ", t2), Tick::Nothing);
+        assert_eq!(h.due(), t2 + Duration::from_secs(30));
+        assert_eq!((h.checks, h.failures), (1, 0));
+    }
+
+    #[test]
+    fn test_timeout() {
+        let t0 = Instant::now();
+        let mut h = check(t0);
+        assert_eq!(h.tick(t0), Tick::Probe);
+        let t1 = t0 + Duration::from_secs(5);
+        assert_eq!(h.output(b"Kernel panic\r\n", t1), Tick::Nothing);
+        assert_eq!(h.tick(t1), Tick::Changed(Health::Down));
+        assert_eq!(h.due(), t1 + Duration::from_secs(30));
+
+        // Still down after the next probe, which is not a change
+        let t2 = t1 + Duration::from_secs(30);
+        assert_eq!(h.tick(t2), Tick::Probe);
+        assert_eq!(h.tick(t2 + Duration::from_secs(5)), Tick::Nothing);
+        assert_eq!((h.state(), h.checks, h.failures), (Health::Down, 2, 2));
+
+        h.reset(t2);
+        assert_eq!(h.state(), Health::Unknown);
+        assert_eq!(h.tick(t2), Tick::Probe);
+    }
+
+    #[test]
+    fn test_seen_is_bounded() {
+        let t0 = Instant::now();
+        let mut h = check(t0);
+        h.output(&[b'x'; 4000], t0);
+        assert_eq!(h.seen.len(), MAX_SEEN);
+        assert_eq!(h.output(b"# ", t0), Tick::Changed(Health::Up));
+    }
+}
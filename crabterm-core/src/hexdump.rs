@@ -0,0 +1,98 @@
+//! Hex + ASCII dump used by `--trace-io`, and parsing of typed hex.
+
+use std::fmt::Write;
+
+/// Log target for I/O traces, enabled at trace level by `--trace-io`.
+pub const TRACE_TARGET: &str = "crabterm::iotrace";
+
+const BYTES_PER_LINE: usize = 16;
+
+/// Format `buf` as lines of "offset  hex bytes  |ascii|".
+pub fn hexdump(buf: &[u8]) -> String {
+    let mut out = String::new();
+    for (i, chunk) in buf.chunks(BYTES_PER_LINE).enumerate() {
+        if i > 0 {
+            out.push('\n');
+        }
+        let _ = write!(out, "{:04x}: ", i * BYTES_PER_LINE);
+        for j in 0..BYTES_PER_LINE {
+            match chunk.get(j) {
+                Some(b) => {
+                    let _ = write!(out, "{:02x} ", b);
+                }
+                None => out.push_str("   "),
+            }
+            if j == 7 {
+                out.push(' ');
+            }
+        }
+        out.push('|');
+        for &b in chunk {
+            out.push(if b.is_ascii_graphic() || b == b' ' {
+                b as char
+            } else {
+                '.'
+            });
+        }
+        out.push('|');
+    }
+    out
+}
+
+/// Parse hex typed by the user, e.g. "1b 5b 41" or "0xDEADBEEF". Each word
+/// is a run of hex digits, optionally prefixed by "0x"; a word with an odd
+/// number of digits gets a leading zero.
+pub fn parse_hex(s: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    for word in s.split_whitespace() {
+        let digits = word
+            .strip_prefix("0x")
+            .or_else(|| word.strip_prefix("0X"))
+            .unwrap_or(word);
+        if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(format!("Invalid hex: {}", word));
+        }
+        let padded;
+        let digits = if digits.len() % 2 == 1 {
+            padded = format!("0{}", digits);
+            &padded
+        } else {
+            digits
+        };
+        for i in (0..digits.len()).step_by(2) {
+            bytes.push(u8::from_str_radix(&digits[i..i + 2], 16).unwrap());
+        }
+    }
+    if bytes.is_empty() {
+        return Err("No bytes given".to_string());
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hexdump() {
+        assert_eq!(
+            hexdump(b"Hello\r\n"),
+            "0000: 48 65 6c 6c 6f 0d 0a                             |Hello..|"
+        );
+        let dump = hexdump(&[0x41; 17]);
+        let lines: Vec<&str> = dump.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].ends_with(&format!("|{}|", "A".repeat(16))));
+        assert!(lines[1].starts_with("0010: 41 "));
+    }
+
+    #[test]
+    fn test_parse_hex() {
+        assert_eq!(parse_hex("1b 5b 41"), Ok(vec![0x1b, 0x5b, 0x41]));
+        assert_eq!(parse_hex(" 0xDEADBEEF "), Ok(vec![0xde, 0xad, 0xbe, 0xef]));
+        assert_eq!(parse_hex("a 0X123"), Ok(vec![0x0a, 0x01, 0x23]));
+        assert!(parse_hex("").is_err());
+        assert!(parse_hex("0x").is_err());
+        assert!(parse_hex("1g").is_err());
+    }
+}
@@ -0,0 +1,513 @@
+//! HTTP status page and JSON API (`--http-port`).
+//!
+//! A minimal HTTP server running on the hub's poll loop, like the metrics
+//! endpoint. It only reads requests and writes responses; the hub answers
+//! them (see `IoHub::http_request`). The connection is closed after each
+//! response.
+//!
+//! It listens on 127.0.0.1 unless given another address. POST requests must
+//! carry `Authorization: Bearer <token>`, and are refused when there is no
+//! token; a request from a browser page of another origin is refused too.
+
+use log::{debug, error, info};
+use mio::net::{TcpListener, TcpStream};
+use mio::{Interest, Token};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io::{ErrorKind, Read, Result, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+use crate::poll::Poll;
+
+const MAX_REQUEST: usize = 64 * 1024;
+/// Largest request body, e.g. input for `/send`
+const MAX_BODY: usize = 16 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+    pub method: String,
+    /// Without the query
+    pub path: String,
+    pub query: Vec<(String, String)>,
+    /// Names in lower case
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    /// Parse a complete request, None while more is to come; Err with the
+    /// answer to a request that can not be served.
+    fn parse(raw: &[u8]) -> std::result::Result<Option<Request>, Response> {
+        let Some(end) = raw.windows(4).position(|w| w == b"\r\n\r\n") else {
+            return Ok(None);
+        };
+        let head = String::from_utf8_lossy(&raw[..end]);
+        let mut lines = head.split("\r\n");
+        let mut words = lines.next().unwrap_or("").split_whitespace();
+        let (method, target) = (words.next().unwrap_or(""), words.next().unwrap_or(""));
+        let headers: Vec<(String, String)> = lines
+            .filter_map(|l| l.split_once(':'))
+            .map(|(name, value)| (name.trim().to_ascii_lowercase(), value.trim().to_string()))
+            .collect();
+        let length = match headers.iter().find(|(name, _)| name == "content-length") {
+            Some((_, value)) => value
+                .parse::<usize>()
+                .map_err(|_| Response::error(400, "invalid content-length"))?,
+            None => 0,
+        };
+        if length > MAX_BODY {
+            return Err(Response::error(413, "request too large"));
+        }
+        let start = end + 4;
+        let Some(body) = start
+            .checked_add(length)
+            .and_then(|stop| raw.get(start..stop))
+        else {
+            return Ok(None);
+        };
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        Ok(Some(Request {
+            method: method.to_string(),
+            path: path.to_string(),
+            query: query
+                .split('&')
+                .filter(|p| !p.is_empty())
+                .map(|p| {
+                    let (k, v) = p.split_once('=').unwrap_or((p, ""));
+                    (k.to_string(), v.to_string())
+                })
+                .collect(),
+            headers,
+            body: body.to_vec(),
+        }))
+    }
+
+    /// The header `name`, in lower case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// The query parameter `name`.
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.query
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl Response {
+    pub fn json(status: u16, value: Value) -> Self {
+        Response {
+            status,
+            content_type: "application/json",
+            body: value.to_string(),
+        }
+    }
+
+    /// `{"error": message}`
+    pub fn error(status: u16, message: &str) -> Self {
+        Self::json(status, serde_json::json!({ "error": message }))
+    }
+
+    pub fn html(body: String) -> Self {
+        Response {
+            status: 200,
+            content_type: "text/html; charset=utf-8",
+            body,
+        }
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let reason = match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            401 => "Unauthorized",
+            403 => "Forbidden",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            409 => "Conflict",
+            413 => "Payload Too Large",
+            503 => "Service Unavailable",
+            _ => "",
+        };
+        format!(
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n\
+             Connection: close\r\n\r\n{}",
+            self.status,
+            reason,
+            self.content_type,
+            self.body.len(),
+            self.body
+        )
+        .into_bytes()
+    }
+}
+
+struct Connection {
+    stream: TcpStream,
+    request: Vec<u8>,
+    /// The response, and how much of it is written; the connection is
+    /// closed once all of it is
+    response: Option<(Vec<u8>, usize)>,
+}
+
+pub struct HttpServer {
+    listener: TcpListener,
+    connections: HashMap<Token, Connection>,
+    token_start: usize,
+    /// Bearer token of POST requests; without one they are refused
+    auth_token: Option<String>,
+    read_only: bool,
+}
+
+impl HttpServer {
+    /// On 127.0.0.1
+    pub fn new(port: u16, token_start: usize) -> Result<Self> {
+        Self::new_with_addr(
+            SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port),
+            token_start,
+        )
+    }
+
+    pub fn new_with_addr(addr: SocketAddr, token_start: usize) -> Result<Self> {
+        Ok(HttpServer {
+            listener: TcpListener::bind(addr)?,
+            connections: HashMap::new(),
+            token_start,
+            auth_token: None,
+            read_only: false,
+        })
+    }
+
+    /// Let POST requests in that carry `Authorization: Bearer <token>`.
+    pub fn with_token(mut self, token: impl Into<String>) -> Self {
+        self.auth_token = Some(token.into());
+        self
+    }
+
+    /// Refuse `/send`, as for the clients of `ro` listeners.
+    pub fn with_read_only(mut self, on: bool) -> Self {
+        self.read_only = on;
+        self
+    }
+
+    pub fn read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// The answer to `request` when it may not be served: from a page of
+    /// another origin, or a POST without the token.
+    pub fn check(&self, request: &Request) -> Option<Response> {
+        // Browsers send the origin of the page along, so another site can
+        // not use the API of a browser on the same machine
+        if let Some(origin) = request.header("origin") {
+            let host = origin.split_once("://").map_or(origin, |(_, host)| host);
+            if request.header("host") != Some(host) {
+                return Some(Response::error(403, "foreign origin"));
+            }
+        }
+        if request.method == "GET" {
+            return None;
+        }
+        let Some(token) = &self.auth_token else {
+            return Some(Response::error(403, "no http-token is set"));
+        };
+        let given = request
+            .header("authorization")
+            .and_then(|a| a.strip_prefix("Bearer "));
+        (given != Some(token.as_str())).then(|| Response::error(401, "invalid token"))
+    }
+
+    pub fn register(&mut self, poll: &mut Poll, token: Token) -> Result<()> {
+        poll.registry()
+            .register(&mut self.listener, token, Interest::READABLE)
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// True if `token` belongs to one of our HTTP connections.
+    pub fn owns(&self, token: Token) -> bool {
+        self.connections.contains_key(&token)
+    }
+
+    pub fn accept(&mut self, poll: &mut Poll) -> Result<()> {
+        loop {
+            match self.listener.accept() {
+                Ok((mut stream, addr)) => {
+                    let token = self.alloc_token();
+                    debug!("Http({:?}): {} connected", token, addr);
+                    poll.registry()
+                        .register(&mut stream, token, Interest::READABLE)?;
+                    self.connections.insert(
+                        token,
+                        Connection {
+                            stream,
+                            request: Vec::new(),
+                            response: None,
+                        },
+                    );
+                }
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => {
+                    error!("Http: accept error: {}", e);
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    fn alloc_token(&self) -> Token {
+        let mut token_id = self.token_start;
+        while self.connections.contains_key(&Token(token_id)) {
+            token_id += 1;
+        }
+        Token(token_id)
+    }
+
+    /// Read from a connection; the request once it is complete, to be
+    /// answered with [`Self::respond`].
+    pub fn read(&mut self, poll: &mut Poll, token: Token) -> Option<Request> {
+        let conn = self.connections.get_mut(&token)?;
+        if conn.response.is_some() {
+            return None;
+        }
+        let mut tmp = [0u8; 4096];
+        loop {
+            match conn.stream.read(&mut tmp) {
+                Ok(0) => break,
+                Ok(n) => {
+                    conn.request.extend_from_slice(&tmp[..n]);
+                    match Request::parse(&conn.request) {
+                        Ok(Some(request)) => {
+                            info!("Http: {} {}", request.method, request.path);
+                            return Some(request);
+                        }
+                        Ok(None) if conn.request.len() <= MAX_REQUEST => {}
+                        Ok(None) => {
+                            self.respond(poll, token, Response::error(413, "request too large"));
+                            return None;
+                        }
+                        Err(response) => {
+                            self.respond(poll, token, response);
+                            return None;
+                        }
+                    }
+                }
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => return None,
+                Err(_) => break,
+            }
+        }
+        self.close(poll, token);
+        None
+    }
+
+    /// Send `response` and close the connection, once the client took all
+    /// of it, see [`Self::write`].
+    pub fn respond(&mut self, poll: &mut Poll, token: Token, response: Response) {
+        let Some(conn) = self.connections.get_mut(&token) else {
+            return;
+        };
+        conn.response = Some((response.to_bytes(), 0));
+        if let Err(e) = poll.registry().reregister(
+            &mut conn.stream,
+            token,
+            Interest::READABLE | Interest::WRITABLE,
+        ) {
+            debug!("Http({:?}): {}", token, e);
+            self.close(poll, token);
+            return;
+        }
+        self.write(poll, token);
+    }
+
+    /// Write what is left of the response of a connection, when it is
+    /// writable again.
+    pub fn write(&mut self, poll: &mut Poll, token: Token) {
+        let Some(conn) = self.connections.get_mut(&token) else {
+            return;
+        };
+        let Some((bytes, written)) = &mut conn.response else {
+            return;
+        };
+        while *written < bytes.len() {
+            match conn.stream.write(&bytes[*written..]) {
+                Ok(0) => break,
+                Ok(n) => *written += n,
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => return,
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => {
+                    debug!("Http({:?}): {}", token, e);
+                    break;
+                }
+            }
+        }
+        self.close(poll, token);
+    }
+
+    fn close(&mut self, poll: &mut Poll, token: Token) {
+        if let Some(mut conn) = self.connections.remove(&token) {
+            let _ = poll.registry().deregister(&mut conn.stream);
+        }
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// The status page, from the `GET /status` document and the client list.
+pub fn status_page(status: &Value, clients: &Value) -> String {
+    let text = |v: &Value| match v {
+        Value::String(s) => escape(s),
+        Value::Null => String::new(),
+        v => escape(&v.to_string()),
+    };
+    let mut out = String::from(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\">\
+         <meta http-equiv=\"refresh\" content=\"5\"><title>crabterm</title></head><body>\n",
+    );
+    let _ = writeln!(out, "<h1>crabterm {}</h1>", text(&status["version"]));
+    out.push_str(
+        "<h2>Devices</h2>\n<table>\n<tr><th>Device</th><th>Connected</th><th>Status</th></tr>\n",
+    );
+    for d in status["devices"].as_array().into_iter().flatten() {
+        let _ = writeln!(
+            out,
+            "<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
+            text(&d["addr"]),
+            text(&d["connected"]),
+            text(&d["status"])
+        );
+    }
+    out.push_str("</table>\n<h2>Clients</h2>\n<table>\n<tr><th>Id</th><th>Client</th></tr>\n");
+    for c in clients.as_array().into_iter().flatten() {
+        let _ = writeln!(
+            out,
+            "<tr><td>{}</td><td>{}</td></tr>",
+            text(&c["id"]),
+            text(&c["peer"])
+        );
+    }
+    out.push_str("</table>\n<h2>Statistics</h2>\n<pre>\n");
+    for line in status["summary"].as_array().into_iter().flatten() {
+        let _ = writeln!(out, "{}", text(line));
+    }
+    out.push_str("</pre>\n</body></html>\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_request() {
+        assert_eq!(
+            Request::parse(b"GET /status HTTP/1.1\r\nHost: x\r\n"),
+            Ok(None)
+        );
+        let r = Request::parse(b"GET /status HTTP/1.1\r\nHost: x\r\n\r\n")
+            .unwrap()
+            .unwrap();
+        assert_eq!((r.method.as_str(), r.path.as_str()), ("GET", "/status"));
+        assert_eq!(r.header("host"), Some("x"));
+        assert!(r.body.is_empty());
+
+        let raw = b"POST /send?device=1&x HTTP/1.1\r\ncontent-length: 5\r\n\r\nreb";
+        assert_eq!(Request::parse(raw), Ok(None));
+        let raw = b"POST /send?device=1&x HTTP/1.1\r\ncontent-length: 5\r\n\r\nboot\r";
+        let r = Request::parse(raw).unwrap().unwrap();
+        assert_eq!(r.path, "/send");
+        assert_eq!(r.param("device"), Some("1"));
+        assert_eq!(r.param("x"), Some(""));
+        assert_eq!(r.param("y"), None);
+        assert_eq!(r.body, b"boot\r");
+
+        let status = |raw: &[u8]| Request::parse(raw).unwrap_err().status;
+        let huge = format!("POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n", usize::MAX);
+        assert_eq!(status(huge.as_bytes()), 413);
+        assert_eq!(
+            status(b"POST / HTTP/1.1\r\nContent-Length: 99999\r\n\r\n"),
+            413
+        );
+        assert_eq!(
+            status(b"POST / HTTP/1.1\r\nContent-Length: -1\r\n\r\n"),
+            400
+        );
+    }
+
+    #[test]
+    fn test_check() {
+        let server = HttpServer::new(0, 1).unwrap();
+        let request = |raw: &str| Request::parse(raw.as_bytes()).unwrap().unwrap();
+        let status = |server: &HttpServer, raw: &str| server.check(&request(raw)).map(|r| r.status);
+        assert_eq!(status(&server, "GET / HTTP/1.1\r\n\r\n"), None);
+        assert_eq!(status(&server, "POST /marker HTTP/1.1\r\n\r\n"), Some(403));
+
+        let server = server.with_token("s3cret");
+        let post = "POST /marker HTTP/1.1\r\nHost: localhost:8080\r\n";
+        assert_eq!(status(&server, &format!("{}\r\n", post)), Some(401));
+        let auth = format!("{}Authorization: Bearer s3cret\r\n", post);
+        assert_eq!(status(&server, &format!("{}\r\n", auth)), None);
+        assert_eq!(
+            status(
+                &server,
+                &format!("{}Origin: http://localhost:8080\r\n\r\n", auth)
+            ),
+            None
+        );
+        assert_eq!(
+            status(
+                &server,
+                &format!("{}Origin: https://evil.example\r\n\r\n", auth)
+            ),
+            Some(403)
+        );
+        assert_eq!(
+            status(
+                &server,
+                "GET / HTTP/1.1\r\nHost: a:1\r\nOrigin: http://b:1\r\n\r\n"
+            ),
+            Some(403)
+        );
+    }
+
+    #[test]
+    fn test_response() {
+        let r = Response::error(404, "no such client");
+        let bytes = String::from_utf8(r.to_bytes()).unwrap();
+        assert!(bytes.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(bytes.ends_with("\r\n\r\n{\"error\":\"no such client\"}"));
+    }
+
+    #[test]
+    fn test_status_page() {
+        let status = json!({
+            "version": "0.1.0",
+            "devices": [{"addr": "/dev/ttyUSB0", "connected": true, "status": "<up>"}],
+            "summary": ["Clients served: 1"],
+        });
+        let clients = json!([{"id": 6, "peer": "10.0.0.2:5000"}]);
+        let page = status_page(&status, &clients);
+        assert!(page.contains("<td>/dev/ttyUSB0</td><td>true</td><td>&lt;up&gt;</td>"));
+        assert!(page.contains("<td>6</td><td>10.0.0.2:5000</td>"));
+        assert!(page.contains("Clients served: 1\n"));
+    }
+}
@@ -0,0 +1,3957 @@
+use log::{LevelFilter, debug, error, info, trace, warn};
+#[cfg(unix)]
+use mio::Interest;
+use mio::Token;
+use regex::Regex;
+#[cfg(unix)]
+use signal_hook::consts::signal::{SIGCONT, SIGINT, SIGTERM, SIGTSTP, SIGUSR1, SIGUSR2, SIGWINCH};
+#[cfg(unix)]
+use signal_hook_mio::v1_0::Signals;
+use std::collections::HashMap;
+use std::io::{IoSlice, Result};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::announce::DEFAULT_TEMPLATE;
+use crate::banner::{Banner, BannerInfo};
+use crate::capture::{self, BootMarker, Capture, Compression, Format, escape_input};
+use crate::capture_db::CaptureDb;
+use crate::clock;
+#[cfg(unix)]
+use crate::ctl::{self, CtlServer};
+use crate::events::EventStream;
+use crate::health::{self, HealthCheck, HealthConfig};
+use crate::hexdump::{TRACE_TARGET, hexdump};
+use crate::http::{self, HttpServer, Request, Response};
+use crate::io::TcpServer;
+#[cfg(unix)]
+use crate::io::UnixServer;
+#[cfg(target_os = "linux")]
+use crate::io::by_id::Hotplug;
+#[cfg(unix)]
+use crate::io::device_lock::DeviceLock;
+use crate::io::listener::{ListenerRole, Newline};
+use crate::io::scrollback::Scrollback;
+use crate::iofilter::{FilterChain, FilterChainFactory, charmap};
+use crate::keybind::action::level_name;
+use crate::keybind::{Action, KeybindConfig, KeybindProcessor, KeybindResult, parse_command};
+use crate::latency::{self, Latency};
+use crate::mdns::MdnsResponder;
+use crate::metrics::{MetricsServer, MetricsSnapshot};
+use crate::monitor::DeviceMonitor;
+use crate::notify::Notifier;
+use crate::outcome::{self, ExitPatterns, Outcome, OutcomeMatcher};
+use crate::pipe::PipeTo;
+use crate::poll::{Backend, Event, Events, Poll};
+use crate::power::{PowerControl, PowerOp};
+use crate::stats::HubStats;
+#[cfg(unix)]
+use crate::stats::format_duration;
+use crate::term::terminal_size;
+#[cfg(unix)]
+use crate::term::{refresh_terminal_size, resume, suspend};
+#[cfg(target_os = "linux")]
+use crate::traits::TOKEN_HOTPLUG;
+use crate::traits::{
+    IoInstance, IoResult, TOKEN_DEVICE_SERVER_START, TOKEN_DEVICE_START, TOKEN_DYNAMIC_START,
+    TOKEN_HTTP_SERVER, TOKEN_LISTENER_START, TOKEN_MDNS, TOKEN_METRICS_SERVER,
+    TOKEN_MONITOR_SERVER,
+};
+#[cfg(unix)]
+use crate::traits::{TOKEN_CTL_SERVER, TOKEN_SESSION_SERVER, TOKEN_SIGNAL};
+use crate::watchdog::{self, SilenceAction};
+use crate::write_queue::WriteQueue;
+
+/// How often a device that is not connected is retried
+const RECONNECT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How often a hotplug device is retried while the hub is told when nodes
+/// appear, for the errors no new node ends, e.g. permissions
+const HOTPLUG_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Client data queued for one vectored device write
+const MAX_BATCH: usize = 64 * 1024;
+
+/// Device output collected into one frame at most (`frame_gap`)
+const MAX_FRAME: usize = 64 * 1024;
+
+/// Input of a client on an `auth` listener before the password is rejected
+const MAX_PASSWORD: usize = 256;
+
+/// Input of the other clients waits this long after input of a `gdb`
+/// client, so it is not mixed into a debugger exchange
+const PRIORITY_HOLD: Duration = Duration::from_millis(500);
+
+/// Time to write what is still queued when quitting
+pub const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// A listener for clients (`-p`)
+enum Listener {
+    Tcp(TcpServer),
+    #[cfg(unix)]
+    Unix(UnixServer),
+}
+
+impl Listener {
+    fn register(&mut self, poll: &mut Poll, token: Token) -> Result<()> {
+        match self {
+            Listener::Tcp(s) => s.register(poll, token),
+            #[cfg(unix)]
+            Listener::Unix(s) => s.register(poll, token),
+        }
+    }
+
+    fn role(&self) -> ListenerRole {
+        match self {
+            Listener::Tcp(s) => s.role(),
+            #[cfg(unix)]
+            Listener::Unix(s) => s.role(),
+        }
+    }
+
+    /// Accept all pending clients, with the keybinds they get.
+    fn accept_all(&mut self) -> Vec<(Box<dyn IoInstance>, Option<KeybindConfig>)> {
+        let mut clients = Vec::new();
+        match self {
+            Listener::Tcp(s) => {
+                while let Some(c) = s.accept() {
+                    clients.push((c, s.client_keybinds().cloned()));
+                }
+            }
+            #[cfg(unix)]
+            Listener::Unix(s) => {
+                while let Some(c) = s.accept() {
+                    clients.push((c, None));
+                }
+            }
+        }
+        clients
+    }
+}
+
+/// A device attached to the hub, with its own connection and write state.
+struct DeviceSlot {
+    device: Box<dyn IoInstance>,
+    token: Token,
+
+    /// Short name used to prefix output when there are several devices
+    label: String,
+
+    /// When true the device's send buffer is full.  We stop reading from
+    /// clients so that TCP backpressure propagates all the way to the
+    /// senders.  Cleared when the device fires a WRITABLE event.
+    write_blocked: bool,
+
+    /// Bytes that could not be written to the device during a partial write.
+    /// Flushed first when the device becomes writable again.
+    pending_write: Vec<u8>,
+
+    /// Client data read while unblocking, written after `pending_write` in
+    /// the same vectored write
+    queued: Vec<Vec<u8>>,
+    queued_len: usize,
+
+    /// End of the write coalescing window: writes until then are queued
+    coalesce_until: Option<Instant>,
+
+    /// Output of a frame that is not over (`frame_gap`), delivered when the
+    /// device is still quiet at `frame_until`
+    frame: Vec<u8>,
+    frame_until: Option<Instant>,
+
+    /// Last data read from or written to the device (`keepalive`)
+    last_activity: Instant,
+
+    /// Last output of the device, and whether the silence watchdog has
+    /// fired since
+    last_rx: Instant,
+    silence_fired: bool,
+
+    /// Prompt detection, if enabled (`health`)
+    health: Option<HealthCheck>,
+
+    /// Success and failure patterns (`exit_patterns`)
+    outcome: Option<OutcomeMatcher>,
+
+    /// Boot marker of `capture_split`, and the boots seen
+    boot_marker: Option<BootMarker>,
+    boots: u64,
+
+    /// Last status message for the device (e.g. Connected or Error)
+    last_status_msg: Option<String>,
+
+    /// True when the next byte read from the device starts a new line
+    at_line_start: bool,
+
+    /// Recent output, replayed to clients that connect (`replay_lines`)
+    replay: Option<Scrollback>,
+
+    /// True once the device has been connected, to tell reconnects apart
+    ever_connected: bool,
+
+    /// Listener for clients that only talk to this device (`--map`)
+    server: Option<TcpServer>,
+
+    /// The device this slot is a channel of (`add_channel`), and whether
+    /// its output is kept from the clients that see all devices because
+    /// another channel of the device is shown
+    channel_of: Option<usize>,
+    background: bool,
+
+    /// Lock file of the port, dropped when it is released
+    #[cfg(unix)]
+    lock: Option<DeviceLock>,
+    /// Given up to another crabterm (`release`): not connected again
+    released: bool,
+}
+
+impl DeviceSlot {
+    fn new(device: Box<dyn IoInstance>, token: Token) -> Self {
+        DeviceSlot {
+            label: device_label(&device.addr_as_string()),
+            device,
+            token,
+            write_blocked: false,
+            pending_write: Vec::new(),
+            queued: Vec::new(),
+            queued_len: 0,
+            coalesce_until: None,
+            frame: Vec::new(),
+            frame_until: None,
+            last_activity: clock::now(),
+            last_rx: clock::now(),
+            silence_fired: false,
+            health: None,
+            outcome: None,
+            boot_marker: None,
+            boots: 0,
+            last_status_msg: None,
+            at_line_start: true,
+            replay: None,
+            ever_connected: false,
+            server: None,
+            channel_of: None,
+            background: false,
+            #[cfg(unix)]
+            lock: None,
+            released: false,
+        }
+    }
+}
+
+/// Short name for a device address, e.g. "usb0" for "/dev/ttyUSB0".
+pub fn device_label(addr: &str) -> String {
+    let base = addr.rsplit('/').next().unwrap_or(addr);
+    let base = base.strip_prefix("tty").unwrap_or(base);
+    base.to_lowercase()
+}
+
+/// Insert "[label] " at the start of every line of `buf`.
+fn prefix_lines(label: &str, at_line_start: &mut bool, buf: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(buf.len() + label.len() + 3);
+    for &b in buf {
+        if *at_line_start {
+            out.push(b'[');
+            out.extend_from_slice(label.as_bytes());
+            out.extend_from_slice(b"] ");
+        }
+        out.push(b);
+        *at_line_start = b == b'\n';
+    }
+    out
+}
+
+/// Bridges one or more devices to any number of clients, see the crate
+/// documentation.
+pub struct IoHub {
+    poll: Poll,
+    instances: HashMap<Token, Box<dyn IoInstance>>,
+
+    // The devices are special, which is why we do not want them as part of
+    // the instances (despite they have a compatible type). Output from all
+    // devices goes to all clients; input from clients goes to the active one.
+    devices: Vec<DeviceSlot>,
+
+    active_device: usize,
+
+    /// Clients accepted on a device's own listener, and the device they are
+    /// bound to. Other clients see all devices and talk to the active one.
+    bound_clients: HashMap<Token, usize>,
+
+    /// Listeners for clients, on TOKEN_LISTENER_START onwards
+    listeners: Vec<Listener>,
+
+    /// Roles of the clients accepted on listeners, the remote clients
+    client_roles: HashMap<Token, ListenerRole>,
+    /// One remote client at a time may write to a board, see
+    /// [`IoHub::set_write_lock`]; the queues are by bound device, None for
+    /// the clients of all devices
+    write_lock: bool,
+    write_queues: HashMap<Option<usize>, WriteQueue>,
+    /// Clients of `auth` listeners that have not sent the password, with
+    /// their input so far
+    auth_pending: HashMap<Token, Vec<u8>>,
+    /// Password for `auth` listeners
+    password: Option<String>,
+
+    /// Unix socket of a detached session, for `crabterm attach`
+    #[cfg(unix)]
+    session: Option<UnixServer>,
+
+    monitor: Option<DeviceMonitor>,
+
+    notifier: Option<Notifier>,
+
+    /// `--events-json`
+    events: Option<EventStream>,
+
+    /// Power and reset hooks of the board
+    power: Option<PowerControl>,
+
+    /// Command that gets the output lines (`pipe-to`)
+    pipe: Option<PipeTo>,
+
+    metrics: Option<MetricsServer>,
+
+    /// Advertises the server on the local network
+    mdns: Option<MdnsResponder>,
+
+    /// Status page and JSON API
+    http: Option<HttpServer>,
+
+    /// Control socket for administering the hub
+    #[cfg(unix)]
+    ctl: Option<CtlServer>,
+
+    /// Device output is appended to this file while set
+    capture: Option<Capture>,
+
+    /// The file of the capture last stopped, continued by `set-option
+    /// capture on`
+    capture_stopped: Option<PathBuf>,
+
+    /// Filters for captures started with the capture-start action
+    capture_filters: Option<FilterChainFactory>,
+
+    /// A new capture is started in this directory on every device connect
+    capture_auto: Option<PathBuf>,
+
+    /// ... and on every line of device output matching this
+    capture_split: Option<Regex>,
+
+    /// Every line of device output is stored here, see
+    /// [`IoHubBuilder::capture_db`]
+    capture_db: Option<CaptureDb>,
+
+    /// Compression of the captures the hub starts
+    capture_compress: Compression,
+
+    /// Format of the captures of `capture_auto`
+    capture_format: Format,
+
+    /// Filters for each TCP client; clients without one get the raw output
+    client_filters: Option<FilterChainFactory>,
+    client_chains: HashMap<Token, FilterChain>,
+    /// Keybinds of TCP clients of servers with client keybinds
+    client_keybinds: HashMap<Token, KeybindProcessor>,
+    // Per-client filter output, reused between clients and reads
+    filter_buf: Vec<u8>,
+
+    stats: HubStats,
+
+    /// When the hub was created, for the session statistics
+    started: Instant,
+
+    /// Dump all device I/O at trace level
+    trace_io: bool,
+
+    /// Record who sent each input in the log and the capture
+    audit_input: bool,
+
+    /// Written to the device by the latency action
+    latency_marker: Vec<u8>,
+    /// Latency measurement in progress: who asked, and of which device
+    latency: Option<(Token, usize, Latency)>,
+
+    /// Changes the log level, on SIGUSR2 and the log-level action
+    log_control: Option<LogControl>,
+    log_raised: bool,
+
+    #[cfg(unix)]
+    signals: Signals,
+
+    /// Wakes the hub when device nodes appear, watched from the first
+    /// hotplug device that is not there on; and whether that failed
+    #[cfg(target_os = "linux")]
+    hotplug: Option<Hotplug>,
+    #[cfg(target_os = "linux")]
+    hotplug_failed: bool,
+
+    quit_requested: bool,
+
+    /// Devices are not read while a client is backlogged (block policy)
+    device_reads_paused: bool,
+
+    /// Input of clients other than `gdb` ones waits until then
+    priority_until: Option<Instant>,
+
+    /// Device writes are queued, to be written together (see `flush_queued`)
+    batch_writes: bool,
+
+    /// Window after a device write in which further writes are coalesced
+    write_coalesce: Duration,
+
+    /// Time to write what is still queued when quitting
+    drain_timeout: Duration,
+
+    /// Quiet time that ends a frame of device output
+    frame_gap: Duration,
+
+    /// Lines of device output replayed to TCP clients that connect
+    replay_lines: usize,
+
+    /// Greeting of TCP clients that connect
+    banner: Option<Banner>,
+
+    /// Bytes written to a device after it has been idle for the interval
+    keepalive: Option<(Vec<u8>, Duration)>,
+
+    /// Silence watchdog: the action when a device has no output for the time
+    silence: Option<(Duration, SilenceAction)>,
+    /// Set by the `exit` action of the silence watchdog and the failure
+    /// pattern, returned by run()
+    exit_error: Option<std::io::Error>,
+
+    /// Success and failure patterns, also given to devices added later
+    exit_patterns: Option<ExitPatterns>,
+
+    /// Health check of the devices, also given to devices added later
+    health: Option<HealthConfig>,
+
+    announce: bool,
+
+    /// Template for announcements (e.g. "MSG-%m")
+    announce_template: String,
+}
+
+/// A change of the log level, see [`IoHub::set_log_control`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogChange {
+    /// Make the log more verbose for a while (SIGUSR2)
+    Raise,
+    /// Back to the level before [`LogChange::Raise`]
+    Restore,
+    /// The `log-level` action
+    Set(LevelFilter),
+}
+
+/// Changes the level of the running logger, see [`IoHub::set_log_control`]
+pub type LogControl = Box<dyn FnMut(LogChange) -> std::result::Result<(), String>>;
+
+/// Builder for [`IoHub`]. Only the device is mandatory; announcements are
+/// enabled with the default template unless configured otherwise.
+pub struct IoHubBuilder {
+    devices: Vec<Box<dyn IoInstance>>,
+    listeners: Vec<Listener>,
+    password: Option<String>,
+    device_servers: Vec<(usize, TcpServer)>,
+    #[cfg(unix)]
+    device_locks: Vec<(usize, DeviceLock)>,
+    #[cfg(unix)]
+    session: Option<UnixServer>,
+    monitor: Option<DeviceMonitor>,
+    notifier: Option<Notifier>,
+    events: Option<EventStream>,
+    power: Option<PowerControl>,
+    pipe: Option<PipeTo>,
+    metrics: Option<MetricsServer>,
+    mdns: Option<MdnsResponder>,
+    http: Option<HttpServer>,
+    #[cfg(unix)]
+    ctl: Option<CtlServer>,
+    client_filters: Option<FilterChainFactory>,
+    capture_filters: Option<FilterChainFactory>,
+    capture_auto: Option<PathBuf>,
+    capture_split: Option<Regex>,
+    capture_db: Option<CaptureDb>,
+    capture_compress: Compression,
+    capture_format: Format,
+    exit_patterns: Option<ExitPatterns>,
+    announce: bool,
+    announce_template: String,
+    trace_io: bool,
+    audit_input: bool,
+    latency_marker: Option<Vec<u8>>,
+    log_control: Option<LogControl>,
+    write_coalesce: Duration,
+    drain_timeout: Duration,
+    frame_gap: Duration,
+    replay_lines: usize,
+    banner: Option<Banner>,
+    write_lock: bool,
+    keepalive: Option<(Vec<u8>, Duration)>,
+    silence: Option<(Duration, SilenceAction)>,
+    health: Option<HealthConfig>,
+    channels: Vec<(usize, String, Box<dyn IoInstance>)>,
+    backend: Backend,
+}
+
+impl IoHubBuilder {
+    /// Attach an additional device. Output lines are prefixed with the
+    /// device label when more than one device is attached.
+    pub fn device(mut self, device: Box<dyn IoInstance>) -> Self {
+        self.devices.push(device);
+        self
+    }
+
+    /// Accept TCP clients on this server. May be called several times,
+    /// e.g. for servers with different roles.
+    pub fn server(mut self, server: TcpServer) -> Self {
+        self.listeners.push(Listener::Tcp(server));
+        self
+    }
+
+    /// Accept clients on this unix socket, like the clients of a TCP
+    /// server.
+    #[cfg(unix)]
+    pub fn unix_server(mut self, server: UnixServer) -> Self {
+        self.listeners.push(Listener::Unix(server));
+        self
+    }
+
+    /// Password that clients of servers with the `auth` role must send
+    /// first.
+    pub fn password(mut self, password: impl Into<String>) -> Self {
+        self.password = Some(password.into());
+        self
+    }
+
+    /// Accept TCP clients on `server` that only talk to device `idx` (in the
+    /// order the devices were added, starting at 0).
+    pub fn device_server(mut self, idx: usize, server: TcpServer) -> Self {
+        self.device_servers.push((idx, server));
+        self
+    }
+
+    /// Hold `lock` on the port of device `idx` for as long as the hub has
+    /// it, so the `release` control command can give it up.
+    #[cfg(unix)]
+    pub fn device_lock(mut self, idx: usize, lock: DeviceLock) -> Self {
+        self.device_locks.push((idx, lock));
+        self
+    }
+
+    /// Attach `device` as channel `name` of device `idx`, e.g. the monitor
+    /// socket of a QEMU VM next to its serial port. See
+    /// [`IoHub::add_channel`].
+    pub fn channel(
+        mut self,
+        idx: usize,
+        name: impl Into<String>,
+        device: Box<dyn IoInstance>,
+    ) -> Self {
+        self.channels.push((idx, name.into(), device));
+        self
+    }
+
+    /// Accept clients attaching to a detached session on this socket.
+    #[cfg(unix)]
+    pub fn session(mut self, session: UnixServer) -> Self {
+        self.session = Some(session);
+        self
+    }
+
+    /// Mirror device traffic to a device monitor.
+    pub fn monitor(mut self, monitor: DeviceMonitor) -> Self {
+        self.monitor = Some(monitor);
+        self
+    }
+
+    /// Send webhook notifications on device events.
+    pub fn notifier(mut self, notifier: Notifier) -> Self {
+        self.notifier = Some(notifier);
+        self
+    }
+
+    /// Write the session as JSON events, e.g. to stdout.
+    pub fn events(mut self, events: EventStream) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Power and reset the board with these hooks (`power`, `reset`).
+    pub fn power(mut self, power: PowerControl) -> Self {
+        self.power = Some(power);
+        self
+    }
+
+    /// Write the lines of device output to a command as well.
+    pub fn pipe_to(mut self, pipe: PipeTo) -> Self {
+        self.pipe = Some(pipe);
+        self
+    }
+
+    /// Serve Prometheus metrics.
+    pub fn metrics(mut self, metrics: MetricsServer) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Advertise the server with multicast DNS.
+    pub fn mdns(mut self, mdns: MdnsResponder) -> Self {
+        self.mdns = Some(mdns);
+        self
+    }
+
+    /// Serve the status page and JSON API.
+    pub fn http(mut self, http: HttpServer) -> Self {
+        self.http = Some(http);
+        self
+    }
+
+    /// Take commands on a control socket.
+    #[cfg(unix)]
+    pub fn ctl(mut self, ctl: CtlServer) -> Self {
+        self.ctl = Some(ctl);
+        self
+    }
+
+    /// Give each TCP client its own filter chain from `factory`. Output to
+    /// the client and input from it pass the chain.
+    pub fn client_filters(mut self, factory: impl Fn() -> FilterChain + 'static) -> Self {
+        self.client_filters = Some(Box::new(factory));
+        self
+    }
+
+    /// Filter chain for captures started at run time.
+    pub fn capture_filters(mut self, factory: impl Fn() -> FilterChain + 'static) -> Self {
+        self.capture_filters = Some(Box::new(factory));
+        self
+    }
+
+    /// Send device status announcements to clients (default: on).
+    pub fn announce(mut self, announce: bool) -> Self {
+        self.announce = announce;
+        self
+    }
+
+    /// Template for announcements, see [`crate::announce::expand_template`].
+    pub fn announce_template(mut self, template: impl Into<String>) -> Self {
+        self.announce_template = template.into();
+        self
+    }
+
+    /// Dump all device I/O at trace level.
+    pub fn trace_io(mut self, trace_io: bool) -> Self {
+        self.trace_io = trace_io;
+        self
+    }
+
+    /// Capture into a new file in `dir` every time a device connects, named
+    /// `<device>-<YYYYmmdd-HHMMSS>.log`, so each boot cycle of a board gets
+    /// its own file.
+    pub fn capture_auto(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.capture_auto = Some(dir.into());
+        self
+    }
+
+    /// With [`Self::capture_auto`], also start a new file when a line of
+    /// device output matches `pattern`, e.g. "U-Boot SPL", named
+    /// `<device>-boot<N>-<YYYYmmdd-HHMMSS>.log`, so a reboot loop gives one
+    /// file per boot even when the device stays connected.
+    pub fn capture_split(mut self, pattern: Regex) -> Self {
+        self.capture_split = Some(pattern);
+        self
+    }
+
+    /// Store every line of device output in a database, with its time,
+    /// device and boot cycle; a line matching [`Self::capture_split`] starts
+    /// a new cycle.
+    pub fn capture_db(mut self, db: CaptureDb) -> Self {
+        self.capture_db = Some(db);
+        self
+    }
+
+    /// End the run when a line of device output matches `success` (run()
+    /// returns Ok) or `failure` (run() returns an error), e.g. for a CI job
+    /// that waits for a board to boot. The output is announced like other
+    /// device events before the hub shuts down.
+    pub fn exit_patterns(mut self, patterns: ExitPatterns) -> Self {
+        self.exit_patterns = Some(patterns);
+        self
+    }
+
+    /// Compress the captures started by capture-start and
+    /// [`Self::capture_auto`].
+    pub fn capture_compress(mut self, compression: Compression) -> Self {
+        self.capture_compress = compression;
+        self
+    }
+
+    /// Write the captures of [`Self::capture_auto`] in `format`; captures
+    /// started by capture-start follow the file name.
+    pub fn capture_format(mut self, format: Format) -> Self {
+        self.capture_format = format;
+        self
+    }
+
+    /// Record the source of device input, see [`IoHub::set_audit_input`].
+    pub fn audit_input(mut self, audit_input: bool) -> Self {
+        self.audit_input = audit_input;
+        self
+    }
+
+    /// Change the log level at runtime, see [`IoHub::set_log_control`].
+    pub fn log_control(mut self, control: LogControl) -> Self {
+        self.log_control = Some(control);
+        self
+    }
+
+    /// Probe with other bytes, see [`IoHub::set_latency_marker`].
+    pub fn latency_marker(mut self, marker: Vec<u8>) -> Self {
+        self.latency_marker = Some(marker);
+        self
+    }
+
+    /// Coalesce device writes, see [`IoHub::set_write_coalesce`].
+    pub fn write_coalesce(mut self, window: Duration) -> Self {
+        self.write_coalesce = window;
+        self
+    }
+
+    /// Bound the drain on quit, see [`IoHub::set_drain_timeout`].
+    pub fn drain_timeout(mut self, timeout: Duration) -> Self {
+        self.drain_timeout = timeout;
+        self
+    }
+
+    /// Frame device output, see [`IoHub::set_frame_gap`].
+    pub fn frame_gap(mut self, gap: Duration) -> Self {
+        self.frame_gap = gap;
+        self
+    }
+
+    /// Replay recent output to new clients, see [`IoHub::set_replay_lines`].
+    pub fn replay_lines(mut self, lines: usize) -> Self {
+        self.replay_lines = lines;
+        self
+    }
+
+    /// Greet TCP clients that connect, see [`IoHub::set_banner`].
+    pub fn banner(mut self, banner: Banner) -> Self {
+        self.banner = Some(banner);
+        self
+    }
+
+    /// Queue remote clients for writing, see [`IoHub::set_write_lock`].
+    pub fn write_lock(mut self, on: bool) -> Self {
+        self.write_lock = on;
+        self
+    }
+
+    /// Keep idle device links up, see [`IoHub::set_keepalive`].
+    pub fn keepalive(mut self, bytes: Vec<u8>, interval: Duration) -> Self {
+        self.keepalive = Some((bytes, interval));
+        self
+    }
+
+    /// Watch for silent devices, see [`IoHub::set_silence`].
+    pub fn silence(mut self, timeout: Duration, action: SilenceAction) -> Self {
+        self.silence = Some((timeout, action));
+        self
+    }
+
+    /// Check that the devices answer with a prompt, see
+    /// [`IoHub::set_health`].
+    pub fn health(mut self, config: HealthConfig) -> Self {
+        self.health = Some(config);
+        self
+    }
+
+    /// Event loop of the hub (`--backend`), mio unless set.
+    pub fn backend(mut self, backend: Backend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    /// Create the hub and register all sources with its poll instance. Clients
+    /// are added afterwards with [`IoHub::add`].
+    pub fn build(self) -> Result<IoHub> {
+        let mut devices = self.devices.into_iter();
+        let mut hub = IoHub::with_poll(
+            Poll::with_backend(self.backend)?,
+            devices.next().expect("IoHubBuilder always has a device"),
+            None,
+            self.monitor,
+            self.announce,
+            self.announce_template,
+        )?;
+        if let Some(n) = self.notifier {
+            hub.set_notifier(n);
+        }
+        if let Some(e) = self.events {
+            hub.set_events(e);
+        }
+        if let Some(m) = self.metrics {
+            hub.set_metrics(m)?;
+        }
+        if let Some(m) = self.mdns {
+            hub.set_mdns(m)?;
+        }
+        if let Some(h) = self.http {
+            hub.set_http(h)?;
+        }
+        #[cfg(unix)]
+        if let Some(c) = self.ctl {
+            hub.set_ctl(c)?;
+        }
+        for listener in self.listeners {
+            hub.add_listener(listener)?;
+        }
+        hub.password = self.password;
+        hub.set_trace_io(self.trace_io);
+        hub.set_audit_input(self.audit_input);
+        if let Some(marker) = self.latency_marker {
+            hub.set_latency_marker(marker);
+        }
+        if let Some(control) = self.log_control {
+            hub.set_log_control(control);
+        }
+        hub.set_write_coalesce(self.write_coalesce);
+        hub.set_drain_timeout(self.drain_timeout);
+        hub.set_frame_gap(self.frame_gap);
+        hub.set_replay_lines(self.replay_lines);
+        hub.set_banner(self.banner);
+        hub.set_write_lock(self.write_lock);
+        if let Some((bytes, interval)) = self.keepalive {
+            hub.set_keepalive(bytes, interval);
+        }
+        if let Some((timeout, action)) = self.silence {
+            hub.set_silence(timeout, action);
+        }
+        if let Some(config) = self.health {
+            hub.set_health(config);
+        }
+        hub.client_filters = self.client_filters;
+        hub.capture_filters = self.capture_filters;
+        hub.capture_auto = self.capture_auto;
+        if let Some(pattern) = self.capture_split {
+            hub.set_capture_split(pattern);
+        }
+        hub.capture_db = self.capture_db;
+        hub.power = self.power;
+        hub.pipe = self.pipe;
+        if let Some(patterns) = self.exit_patterns {
+            hub.set_exit_patterns(patterns);
+        }
+        hub.capture_compress = self.capture_compress;
+        hub.capture_format = self.capture_format;
+        for d in devices {
+            hub.add_device(d);
+        }
+        for (idx, name, device) in self.channels {
+            hub.add_channel(idx, &name, device)?;
+        }
+        #[cfg(unix)]
+        if let Some(s) = self.session {
+            hub.set_session(s)?;
+        }
+        for (idx, server) in self.device_servers {
+            hub.set_device_server(idx, server)?;
+        }
+        #[cfg(unix)]
+        for (idx, lock) in self.device_locks {
+            hub.set_device_lock(idx, lock)?;
+        }
+        Ok(hub)
+    }
+}
+
+impl IoHub {
+    pub fn builder(device: Box<dyn IoInstance>) -> IoHubBuilder {
+        IoHubBuilder {
+            devices: vec![device],
+            listeners: Vec::new(),
+            password: None,
+            device_servers: Vec::new(),
+            #[cfg(unix)]
+            device_locks: Vec::new(),
+            #[cfg(unix)]
+            session: None,
+            monitor: None,
+            notifier: None,
+            events: None,
+            power: None,
+            pipe: None,
+            metrics: None,
+            mdns: None,
+            http: None,
+            #[cfg(unix)]
+            ctl: None,
+            client_filters: None,
+            capture_filters: None,
+            capture_auto: None,
+            capture_split: None,
+            capture_db: None,
+            capture_compress: Compression::None,
+            capture_format: Format::Text,
+            exit_patterns: None,
+            announce: true,
+            announce_template: DEFAULT_TEMPLATE.to_string(),
+            trace_io: false,
+            audit_input: false,
+            latency_marker: None,
+            log_control: None,
+            write_coalesce: Duration::ZERO,
+            drain_timeout: DEFAULT_DRAIN_TIMEOUT,
+            frame_gap: Duration::ZERO,
+            replay_lines: 0,
+            banner: None,
+            write_lock: false,
+            keepalive: None,
+            silence: None,
+            health: None,
+            channels: Vec::new(),
+            backend: Backend::default(),
+        }
+    }
+
+    pub fn new(
+        device: Box<dyn IoInstance>,
+        server: Option<TcpServer>,
+        monitor: Option<DeviceMonitor>,
+        announce: bool,
+        announce_template: String,
+    ) -> Result<Self> {
+        Self::with_poll(
+            Poll::new()?,
+            device,
+            server,
+            monitor,
+            announce,
+            announce_template,
+        )
+    }
+
+    fn with_poll(
+        poll: Poll,
+        device: Box<dyn IoInstance>,
+        server: Option<TcpServer>,
+        monitor: Option<DeviceMonitor>,
+        announce: bool,
+        announce_template: String,
+    ) -> Result<Self> {
+        #[cfg(unix)]
+        let mut signals = Signals::new([
+            SIGINT, SIGTERM, SIGWINCH, SIGTSTP, SIGCONT, SIGUSR1, SIGUSR2,
+        ])?;
+        #[cfg(unix)]
+        poll.registry()
+            .register_indirect(&mut signals, TOKEN_SIGNAL, Interest::READABLE)?;
+
+        let mut io_hub = IoHub {
+            poll,
+            instances: HashMap::new(),
+            devices: vec![DeviceSlot::new(device, TOKEN_DEVICE_START)],
+            active_device: 0,
+            bound_clients: HashMap::new(),
+            listeners: Vec::new(),
+            client_roles: HashMap::new(),
+            write_lock: false,
+            write_queues: HashMap::new(),
+            auth_pending: HashMap::new(),
+            password: None,
+            #[cfg(unix)]
+            session: None,
+            monitor,
+            notifier: None,
+            events: None,
+            power: None,
+            pipe: None,
+            metrics: None,
+            mdns: None,
+            http: None,
+            #[cfg(unix)]
+            ctl: None,
+            capture: None,
+            capture_stopped: None,
+            capture_filters: None,
+            capture_auto: None,
+            capture_split: None,
+            capture_db: None,
+            capture_compress: Compression::None,
+            capture_format: Format::Text,
+            client_filters: None,
+            client_chains: HashMap::new(),
+            client_keybinds: HashMap::new(),
+            filter_buf: Vec::new(),
+            stats: HubStats::default(),
+            started: clock::now(),
+            trace_io: false,
+            audit_input: false,
+            latency_marker: latency::DEFAULT_MARKER.to_vec(),
+            latency: None,
+            log_control: None,
+            log_raised: false,
+            #[cfg(unix)]
+            signals,
+            #[cfg(target_os = "linux")]
+            hotplug: None,
+            #[cfg(target_os = "linux")]
+            hotplug_failed: false,
+            quit_requested: false,
+            device_reads_paused: false,
+            priority_until: None,
+            batch_writes: false,
+            write_coalesce: Duration::ZERO,
+            drain_timeout: DEFAULT_DRAIN_TIMEOUT,
+            frame_gap: Duration::ZERO,
+            replay_lines: 0,
+            banner: None,
+            keepalive: None,
+            silence: None,
+            exit_error: None,
+            exit_patterns: None,
+            health: None,
+            announce,
+            announce_template,
+        };
+
+        if let Some(s) = server {
+            io_hub.add_server(s)?;
+        }
+
+        if let Some(m) = &mut io_hub.monitor {
+            m.register(&mut io_hub.poll, TOKEN_MONITOR_SERVER)?;
+        }
+
+        Ok(io_hub)
+    }
+
+    /// Accept TCP clients on `server`, in addition to the servers so far.
+    pub fn add_server(&mut self, server: TcpServer) -> Result<()> {
+        self.add_listener(Listener::Tcp(server))
+    }
+
+    /// Accept clients on the unix socket `server`, like TCP clients.
+    #[cfg(unix)]
+    pub fn add_unix_server(&mut self, server: UnixServer) -> Result<()> {
+        self.add_listener(Listener::Unix(server))
+    }
+
+    fn add_listener(&mut self, mut listener: Listener) -> Result<()> {
+        let token = Token(TOKEN_LISTENER_START.0 + self.listeners.len());
+        listener.register(&mut self.poll, token)?;
+        self.listeners.push(listener);
+        Ok(())
+    }
+
+    fn listener_index(&self, token: Token) -> Option<usize> {
+        token
+            .0
+            .checked_sub(TOKEN_LISTENER_START.0)
+            .filter(|&i| i < self.listeners.len())
+    }
+
+    pub fn set_notifier(&mut self, notifier: Notifier) {
+        self.notifier = Some(notifier);
+    }
+
+    pub fn set_events(&mut self, events: EventStream) {
+        self.events = Some(events);
+    }
+
+    pub fn set_metrics(&mut self, mut metrics: MetricsServer) -> Result<()> {
+        metrics.register(&mut self.poll, TOKEN_METRICS_SERVER)?;
+        self.metrics = Some(metrics);
+        Ok(())
+    }
+
+    pub fn set_mdns(&mut self, mut mdns: MdnsResponder) -> Result<()> {
+        mdns.register(&mut self.poll, TOKEN_MDNS)?;
+        self.mdns = Some(mdns);
+        Ok(())
+    }
+
+    pub fn set_http(&mut self, mut http: HttpServer) -> Result<()> {
+        http.register(&mut self.poll, TOKEN_HTTP_SERVER)?;
+        self.http = Some(http);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    pub fn set_ctl(&mut self, mut ctl: CtlServer) -> Result<()> {
+        ctl.register(&mut self.poll, TOKEN_CTL_SERVER)?;
+        self.ctl = Some(ctl);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    pub fn set_session(&mut self, mut session: UnixServer) -> Result<()> {
+        session.register(&mut self.poll, TOKEN_SESSION_SERVER)?;
+        self.session = Some(session);
+        Ok(())
+    }
+
+    /// Attach an additional device. It is connected by `run()` like the first.
+    pub fn add_device(&mut self, device: Box<dyn IoInstance>) {
+        let token = Token(TOKEN_DEVICE_START.0 + self.devices.len());
+        let mut slot = DeviceSlot::new(device, token);
+        slot.health = self
+            .health
+            .clone()
+            .map(|config| HealthCheck::new(config, clock::now()));
+        slot.outcome = self.exit_patterns.clone().map(OutcomeMatcher::new);
+        slot.boot_marker = self.capture_split.clone().map(BootMarker::new);
+        self.devices.push(slot);
+    }
+
+    /// Attach `device` as another channel of device `idx`, labelled `name`.
+    /// Clients that see all devices are shown one channel of a device at a
+    /// time, initially the device itself, and switch with the
+    /// `channel-next` action; the output of the others is still captured.
+    pub fn add_channel(
+        &mut self,
+        idx: usize,
+        name: &str,
+        device: Box<dyn IoInstance>,
+    ) -> Result<()> {
+        if self.devices.get(idx).is_none_or(|s| s.channel_of.is_some()) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("no device {}", idx),
+            ));
+        }
+        self.add_device(device);
+        let slot = self.devices.last_mut().expect("just added");
+        slot.label = name.to_string();
+        slot.channel_of = Some(idx);
+        slot.background = true;
+        Ok(())
+    }
+
+    /// The device slot `idx` belongs to: itself, or the device it is a
+    /// channel of.
+    fn device_of(&self, idx: usize) -> usize {
+        self.devices[idx].channel_of.unwrap_or(idx)
+    }
+
+    /// Accept TCP clients on `server` that only talk to device `idx`: they
+    /// see its output without line prefixes, and their input always goes to
+    /// it regardless of the active device.
+    pub fn set_device_server(&mut self, idx: usize, mut server: TcpServer) -> Result<()> {
+        let Some(slot) = self.devices.get_mut(idx) else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("no device {}", idx),
+            ));
+        };
+        server.register(&mut self.poll, Token(TOKEN_DEVICE_SERVER_START.0 + idx))?;
+        slot.server = Some(server);
+        Ok(())
+    }
+
+    /// Hold `lock` on the port of device `idx` until it is released.
+    #[cfg(unix)]
+    pub fn set_device_lock(&mut self, idx: usize, lock: DeviceLock) -> Result<()> {
+        let Some(slot) = self.devices.get_mut(idx) else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("no device {}", idx),
+            ));
+        };
+        slot.lock = Some(lock);
+        Ok(())
+    }
+
+    /// `release`: give the port of `device` up to another crabterm
+    /// (`--steal`). It is disconnected, its lock dropped and it is not
+    /// connected again; the other devices carry on.
+    #[cfg(unix)]
+    fn release_device(&mut self, device: &str) -> std::result::Result<Vec<String>, String> {
+        let idx = self
+            .devices
+            .iter()
+            .position(|slot| {
+                slot.lock
+                    .as_ref()
+                    .is_some_and(|lock| lock.covers(Path::new(device)))
+            })
+            .ok_or_else(|| format!("Not locked here: {}", device))?;
+        if self.devices[idx].device.connected() {
+            self.reset_device(idx);
+        }
+        let slot = &mut self.devices[idx];
+        slot.released = true;
+        slot.lock = None;
+        let addr = slot.device.addr_as_string();
+        info!("Released {} to another crabterm", addr);
+        self.device_status(idx, format!("{}: Taken over by another crabterm", addr));
+        Ok(vec![format!("Released {}", device)])
+    }
+
+    fn device_index(&self, token: Token) -> Option<usize> {
+        token
+            .0
+            .checked_sub(TOKEN_DEVICE_START.0)
+            .filter(|&i| i < self.devices.len())
+    }
+
+    /// Show the next channel of the active device, and send input to it.
+    fn channel_next(&mut self) -> String {
+        let device = self.device_of(self.active_device);
+        let channels: Vec<usize> = (0..self.devices.len())
+            .filter(|&i| self.device_of(i) == device)
+            .collect();
+        let Some(at) = channels.iter().position(|&i| i == self.active_device) else {
+            return "No other channel".to_string();
+        };
+        let next = channels[(at + 1) % channels.len()];
+        if next == self.active_device {
+            return "No other channel".to_string();
+        }
+        self.devices[self.active_device].background = true;
+        self.devices[next].background = false;
+        self.active_device = next;
+        let msg = format!(
+            "Channel [{}] {}",
+            self.devices[next].label,
+            self.devices[next].device.addr_as_string()
+        );
+        info!("{}", msg);
+        msg
+    }
+
+    fn device_server_index(&self, token: Token) -> Option<usize> {
+        token
+            .0
+            .checked_sub(TOKEN_DEVICE_SERVER_START.0)
+            .filter(|&i| i < self.devices.len())
+    }
+
+    /// The device that input from client `token` goes to.
+    fn target_device(&self, token: Token) -> usize {
+        self.bound_clients
+            .get(&token)
+            .copied()
+            .unwrap_or(self.active_device)
+    }
+
+    fn target_blocked(&self, token: Token) -> bool {
+        let slot = &self.devices[self.target_device(token)];
+        slot.write_blocked || slot.queued_len >= MAX_BATCH || self.held_for_priority(token)
+    }
+
+    /// True when input of client `token` waits for a `gdb` client.
+    fn held_for_priority(&self, token: Token) -> bool {
+        self.priority_until.is_some_and(|t| t > clock::now())
+            && !self.client_roles.get(&token).is_some_and(|r| r.gdb)
+    }
+
+    /// Read the clients that waited for a `gdb` client, once it is quiet.
+    fn release_priority(&mut self) {
+        if self.priority_until.is_some_and(|t| t <= clock::now()) {
+            self.priority_until = None;
+            if !self.active_blocked() {
+                self.drain_pending_client_data();
+            }
+        }
+    }
+
+    fn active_blocked(&self) -> bool {
+        self.devices[self.active_device].write_blocked
+    }
+
+    fn next_free_token(&self) -> Token {
+        let mut token_id = TOKEN_DYNAMIC_START.0;
+
+        loop {
+            let token = Token(token_id);
+            if !self.instances.contains_key(&token) {
+                return token;
+            }
+            token_id += 1;
+        }
+    }
+
+    pub fn add(&mut self, instance: Box<dyn IoInstance>) -> Result<()> {
+        self.add_client(instance, None, None).map(|_| ())
+    }
+
+    /// Add a client accepted on a TCP server, with its own filter chain if
+    /// client filters are configured, and its own keybinds if the server has
+    /// client keybinds.
+    pub(crate) fn add_tcp_client(
+        &mut self,
+        instance: Box<dyn IoInstance>,
+        bound: Option<usize>,
+        keybinds: Option<KeybindConfig>,
+        role: ListenerRole,
+    ) -> Result<()> {
+        self.stats.client_connections += 1;
+        let token = self.add_client(instance, bound, Some(role))?;
+        if role.gdb {
+            // Raw both ways
+            return Ok(());
+        }
+        if let Some(factory) = &self.client_filters {
+            self.client_chains.insert(token, factory());
+        }
+        if let Some(newline) = role.newline {
+            // In place of the charmap of the client filters
+            let chain = self.client_chains.entry(token).or_default();
+            chain.register(Box::new(newline.charmap()));
+        }
+        if let Some(config) = keybinds {
+            self.client_keybinds
+                .insert(token, KeybindProcessor::new(config));
+        }
+        if !role.auth {
+            self.join_write_queue(token);
+            self.replay(token, self.replay_lines);
+        }
+        Ok(())
+    }
+
+    /// Add a client, optionally bound to device `bound`, with the role of
+    /// the listener it was accepted on; local clients have none.
+    fn add_client(
+        &mut self,
+        mut instance: Box<dyn IoInstance>,
+        bound: Option<usize>,
+        role: Option<ListenerRole>,
+    ) -> Result<Token> {
+        let token = self.next_free_token();
+        let addr = instance.addr_as_string();
+
+        if let Err(e) = instance.connect(&mut self.poll, token) {
+            error!("Hub({:?}): {} Failed to register {}", token, addr, e);
+            return Err(e);
+        }
+
+        self.instances.insert(token, instance);
+        if let Some(idx) = bound {
+            self.bound_clients.insert(token, idx);
+        }
+
+        info!(
+            event = "client_connect",
+            token = token.0,
+            addr = addr.as_str();
+            "Hub({:?}): {} registered", token, addr
+        );
+        if let Some(e) = &mut self.events {
+            e.client_join(&addr);
+        }
+
+        if let Some(role) = role {
+            self.client_roles.insert(token, role);
+        }
+        if role.is_some_and(|r| r.auth) {
+            self.auth_pending.insert(token, Vec::new());
+            self.reply(token, "Password required");
+        } else {
+            if role.is_some() {
+                self.send_banner(token);
+            }
+            self.announce_status(token);
+        }
+
+        Ok(token)
+    }
+
+    /// Greet the new TCP client `token` with the banner: its device, and
+    /// whether it may write.
+    fn send_banner(&mut self, token: Token) {
+        let role = self.client_roles.get(&token).copied().unwrap_or_default();
+        let Some(banner) = self.banner.as_ref().filter(|_| !role.gdb) else {
+            return;
+        };
+        let slot = match self.bound_clients.get(&token) {
+            Some(&idx) => &self.devices[idx],
+            None => &self.devices[self.active_device],
+        };
+        let mut rules = vec![if role.read_only {
+            "read-only"
+        } else {
+            "read-write"
+        }];
+        if self.write_lock && !role.read_only {
+            rules.push("write lock");
+        }
+        let line_end = match role.newline {
+            Some(Newline::Lf) => "\n",
+            _ => "\r\n",
+        };
+        let text = banner.render(
+            &BannerInfo {
+                label: &slot.label,
+                addr: &slot.device.addr_as_string(),
+                baudrate: slot.device.baudrate(),
+                rules: &rules,
+            },
+            line_end,
+        );
+        if let Some(client) = self.instances.get_mut(&token) {
+            client.write_all(text.as_bytes());
+        }
+    }
+
+    /// Send the last status of the devices to the new client `token`.
+    fn announce_status(&mut self, token: Token) {
+        let role = self.client_roles.get(&token).copied().unwrap_or_default();
+        if !self.announce || !role.announce {
+            return;
+        }
+        let bound = self.bound_clients.get(&token).copied();
+        if let Some(client) = self.instances.get_mut(&token) {
+            let addr = client.addr_as_string();
+            for msg in self
+                .devices
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| bound.is_none_or(|b| b == *i))
+                .filter_map(|(_, d)| d.last_status_msg.as_ref())
+            {
+                client.write_status(&self.announce_template, &addr, msg);
+            }
+        }
+    }
+
+    /// Send up to `lines` lines of recent output of its device to the TCP
+    /// client `token`, between two replies that mark it as a replay. False
+    /// when there is nothing to replay.
+    fn replay(&mut self, token: Token, lines: usize) -> bool {
+        let idx = match self.bound_clients.get(&token) {
+            Some(&idx) => idx,
+            None => self.active_device,
+        };
+        let slot = &self.devices[idx];
+        let Some(replay) = slot.replay.as_ref().filter(|r| !r.is_empty()) else {
+            return false;
+        };
+        let lines = replay.len().min(lines);
+        let text = replay.last_lines_raw(lines);
+        let label = slot.label.clone();
+
+        self.reply(
+            token,
+            &format!("Replay of the last {} lines of {}", lines, label),
+        );
+        let out: Arc<[u8]> = match self.client_chains.get_mut(&token) {
+            Some(chain) => {
+                let mut filtered = Vec::new();
+                chain.filter_out_into(&text, &mut filtered);
+                filtered.into()
+            }
+            None => text.into(),
+        };
+        if let Some(client) = self.instances.get_mut(&token) {
+            client.write_shared(&out);
+            // The replay may end in the middle of a line, e.g. a prompt
+            if !out.ends_with(b"\n") {
+                client.write_shared(&Arc::from(&b"\r\n"[..]));
+            }
+        }
+        self.reply(token, "End of replay");
+        true
+    }
+
+    /// Queue the new remote client `token` for the write lock of its board,
+    /// if it may write.
+    fn join_write_queue(&mut self, token: Token) {
+        let writer = self
+            .client_roles
+            .get(&token)
+            .is_some_and(|r| !r.read_only && !r.gdb);
+        if !self.write_lock || !writer {
+            return;
+        }
+        let board = self.bound_clients.get(&token).copied();
+        let position = self.write_queues.entry(board).or_default().join(token);
+        self.tell_write_position(token, position);
+    }
+
+    fn tell_write_position(&mut self, token: Token, position: usize) {
+        let msg = match position {
+            0 => "You have the write lock".to_string(),
+            n => format!("Read-only, number {} in the queue for the write lock", n),
+        };
+        self.reply(token, &msg);
+    }
+
+    /// Tell the clients of the write queue of `board` where they are now,
+    /// and `holder` that it got the lock.
+    fn tell_write_queue(&mut self, board: Option<usize>, holder: Option<Token>) {
+        if let Some(holder) = holder {
+            info!("Hub({:?}): got the write lock", holder);
+            self.tell_write_position(holder, 0);
+        }
+        let waiting: Vec<Token> = self
+            .write_queues
+            .get(&board)
+            .map(|q| q.waiting().collect())
+            .unwrap_or_default();
+        for (i, token) in waiting.into_iter().enumerate() {
+            self.tell_write_position(token, i + 1);
+        }
+    }
+
+    /// The board whose write queue `token` is in
+    fn write_board(&self, token: Token) -> Option<Option<usize>> {
+        self.write_queues
+            .iter()
+            .find(|(_, q)| q.position(token).is_some())
+            .map(|(&board, _)| board)
+    }
+
+    /// True while `token` waits for the write lock, and its input is dropped
+    fn write_waiting(&self, token: Token) -> bool {
+        self.write_queues
+            .values()
+            .any(|q| q.position(token).is_some_and(|p| p > 0))
+    }
+
+    fn leave_write_queue(&mut self, token: Token) {
+        let Some(board) = self.write_board(token) else {
+            return;
+        };
+        let queue = self.write_queues.entry(board).or_default();
+        let was_holder = queue.holder() == Some(token);
+        let holder = queue.leave(token);
+        if was_holder || queue.waiting().next().is_some() {
+            self.tell_write_queue(board, holder);
+        }
+    }
+
+    /// `write-release`: the holder `token` passes the write lock on.
+    fn release_write_lock(&mut self, token: Token) {
+        let board = self.write_board(token);
+        let queue = board.and_then(|b| self.write_queues.get_mut(&b));
+        match queue {
+            Some(q) if q.holder() == Some(token) => match q.release(token) {
+                Some(next) => self.tell_write_queue(board.flatten(), Some(next)),
+                None => self.reply(token, "Nobody is waiting for the write lock"),
+            },
+            _ => self.reply(token, "You do not have the write lock"),
+        }
+    }
+
+    /// Input of a client that has not sent the password yet: a line with the
+    /// password lets it in, anything else disconnects it.
+    fn authenticate(&mut self, token: Token, bytes: &[u8]) {
+        let Some(buf) = self.auth_pending.get_mut(&token) else {
+            return;
+        };
+        buf.extend_from_slice(bytes);
+        let Some(end) = buf.iter().position(|&b| b == b'\r' || b == b'\n') else {
+            if buf.len() > MAX_PASSWORD {
+                self.auth_pending.remove(&token);
+                self.reject(token);
+            }
+            return;
+        };
+        let buf = self.auth_pending.remove(&token).unwrap_or_default();
+        let (line, rest) = buf.split_at(end);
+        let rest: Vec<u8> = rest
+            .iter()
+            .copied()
+            .skip_while(|&b| b == b'\r' || b == b'\n')
+            .collect();
+        if self.password.as_ref().is_none_or(|p| p.as_bytes() != line) {
+            self.reject(token);
+            return;
+        }
+        info!(
+            event = "client_auth",
+            token = token.0;
+            "Hub({:?}): authenticated", token
+        );
+        self.reply(token, "Authenticated");
+        self.send_banner(token);
+        self.announce_status(token);
+        self.join_write_queue(token);
+        self.replay(token, self.replay_lines);
+        if !rest.is_empty() {
+            self.handle_read_result(token, IoResult::Data(rest.into()));
+        }
+    }
+
+    fn reject(&mut self, token: Token) {
+        let addr = self
+            .instances
+            .get(&token)
+            .map(|c| c.peer_as_string())
+            .unwrap_or_default();
+        warn!(
+            event = "client_auth_failed",
+            token = token.0,
+            addr = addr.as_str();
+            "Hub({:?}): {} authentication failed", token, addr
+        );
+        self.reply(token, "Authentication failed");
+        if let Some(client) = self.instances.get_mut(&token) {
+            client.flush();
+            client.disconnect(&mut self.poll);
+        }
+        self.remove_client(token);
+    }
+
+    /// Announce a status message of device `idx` to all clients, except
+    /// those bound to another device.
+    fn device_announce(&mut self, idx: usize, msg: &str) {
+        self.announce_to(Some(idx), msg);
+    }
+
+    /// Announce a state change of device `idx`, and keep it for clients that
+    /// connect later.
+    fn device_status(&mut self, idx: usize, msg: String) {
+        self.broadcast(Some(idx), &msg, true);
+        self.devices[idx].last_status_msg = Some(msg);
+    }
+
+    /// Announce `msg` to the clients that take announcements, and of those
+    /// bound to a device only the ones bound to `device`, if given.
+    fn announce_to(&mut self, device: Option<usize>, msg: &str) {
+        self.broadcast(device, msg, false);
+    }
+
+    /// [`Self::announce_to`], as a device state change if `status`.
+    fn broadcast(&mut self, device: Option<usize>, msg: &str, status: bool) {
+        info!("Announce: {}", msg.trim());
+        if self.announce {
+            for (token, client) in self.instances.iter_mut() {
+                if device
+                    .is_some_and(|idx| self.bound_clients.get(token).is_some_and(|&b| b != idx))
+                    || self.client_roles.get(token).is_some_and(|r| !r.announce)
+                    || self.auth_pending.contains_key(token)
+                {
+                    continue;
+                }
+                let addr = client.addr_as_string();
+                if status {
+                    client.write_status(&self.announce_template, &addr, msg);
+                } else {
+                    client.write_announce(&self.announce_template, &addr, msg);
+                }
+            }
+        }
+    }
+
+    /// Forward data from client `token` to its device.
+    fn forward_to_device(&mut self, token: Token, bytes: &[u8]) {
+        if self.client_roles.get(&token).is_some_and(|r| r.read_only) {
+            debug!("Hub({:?}): read-only, {} bytes dropped", token, bytes.len());
+            return;
+        }
+        if self.write_waiting(token) {
+            debug!(
+                "Hub({:?}): waiting for the write lock, {} bytes dropped",
+                token,
+                bytes.len()
+            );
+            return;
+        }
+        if self.client_roles.get(&token).is_some_and(|r| r.gdb) {
+            self.priority_until = Some(clock::now() + PRIORITY_HOLD);
+        }
+        if self.audit_input
+            && !bytes.is_empty()
+            && let Some(client) = self.instances.get(&token)
+        {
+            let addr = client.peer_as_string();
+            let input = escape_input(bytes);
+            info!(
+                event = "client_input",
+                token = token.0,
+                addr = addr.as_str(),
+                input = input.as_str();
+                "Input from {}: {}", addr, input
+            );
+            if let Some(c) = &mut self.capture {
+                c.write_input(&addr, bytes);
+            }
+        }
+        self.forward_to(self.target_device(token), bytes);
+    }
+
+    /// Forward data to device `idx`.  Sets `write_blocked` and registers
+    /// WRITABLE interest when the device cannot accept the data.  Unwritten
+    /// bytes are saved in `pending_write` to avoid data loss.
+    fn forward_to(&mut self, idx: usize, bytes: &[u8]) {
+        if let Some(m) = &mut self.monitor {
+            m.tx(bytes);
+        }
+        if let Some(c) = &mut self.capture {
+            c.write_tx(&self.devices[idx].label, bytes);
+        }
+        self.devices[idx].last_activity = clock::now();
+        if self.batch_writes || self.devices[idx].coalesce_until.is_some() {
+            let slot = &mut self.devices[idx];
+            slot.queued_len += bytes.len();
+            slot.queued.push(bytes.to_vec());
+            return;
+        }
+        let slot = &mut self.devices[idx];
+        let was_blocked = slot.write_blocked;
+        let pending_before = slot.pending_write.len();
+        Self::try_device_write(
+            &mut *slot.device,
+            &mut slot.pending_write,
+            &mut slot.write_blocked,
+            &mut self.poll,
+            bytes,
+        );
+        let written = bytes.len() - (slot.pending_write.len() - pending_before);
+        let now_blocked = slot.write_blocked;
+        self.trace_device_io(idx, "TX", &bytes[..written]);
+        self.stats.device_tx_bytes += written as u64;
+        if now_blocked && !was_blocked {
+            self.stats.backpressure_events += 1;
+        }
+        if !self.write_coalesce.is_zero() {
+            self.devices[idx].coalesce_until = Some(clock::now() + self.write_coalesce);
+        }
+    }
+
+    /// Write `pending_write` and the queued client data of device `idx` with
+    /// one vectored write. What the device does not take becomes the new
+    /// `pending_write`.
+    fn flush_queued(&mut self, idx: usize) {
+        let slot = &mut self.devices[idx];
+        if slot.pending_write.is_empty() && slot.queued.is_empty() {
+            return;
+        }
+        let pending = std::mem::take(&mut slot.pending_write);
+        let queued = std::mem::take(&mut slot.queued);
+        slot.queued_len = 0;
+        let chunks: Vec<&[u8]> = std::iter::once(pending.as_slice())
+            .chain(queued.iter().map(|q| q.as_slice()))
+            .filter(|c| !c.is_empty())
+            .collect();
+        let mut bufs: Vec<IoSlice> = chunks.iter().map(|c| IoSlice::new(c)).collect();
+        let written = slot.device.write_all_vectored(&mut bufs);
+
+        let mut skip = written;
+        for chunk in &chunks {
+            let n = skip.min(chunk.len());
+            slot.pending_write.extend_from_slice(&chunk[n..]);
+            skip -= n;
+        }
+        let blocked = !slot.pending_write.is_empty();
+        if blocked && !slot.write_blocked {
+            info!("Device write blocked — enabling backpressure");
+            slot.write_blocked = true;
+            if let Err(e) = slot.device.set_writable_interest(&mut self.poll, true) {
+                error!("Failed to set writable interest: {}", e);
+            }
+            self.stats.backpressure_events += 1;
+        }
+        self.stats.device_tx_bytes += written as u64;
+        if self.trace_io {
+            self.trace_device_io(idx, "TX", &chunks.concat()[..written]);
+        }
+    }
+
+    /// After a write to a device, queue further writes to it for `window`
+    /// and write them together, so e.g. a paste arriving a byte at a time
+    /// becomes a few larger writes. A write after a quiet spell, like a
+    /// single keystroke, is written at once. Zero (the default) disables it.
+    pub fn set_write_coalesce(&mut self, window: Duration) {
+        self.write_coalesce = window;
+    }
+
+    /// On quit, input is no longer read, and what is still queued for the
+    /// devices and the clients is written for at most `timeout` before
+    /// the hub returns. Zero drops it.
+    pub fn set_drain_timeout(&mut self, timeout: Duration) {
+        self.drain_timeout = timeout;
+    }
+
+    /// Deliver device output in frames: bytes are collected until the device
+    /// has been quiet for `gap` (like VTIME of termios), so the records of a
+    /// binary protocol reach the filters and clients whole rather than split
+    /// at arbitrary read boundaries. Zero (the default) disables it.
+    pub fn set_frame_gap(&mut self, gap: Duration) {
+        self.frame_gap = gap;
+    }
+
+    /// Let one remote client at a time write to a board: the first to
+    /// connect gets the write lock, the others are read-only and queued,
+    /// told their place in the queue, and get the lock in turn when the
+    /// holder disconnects or gives it up (`write-release`). Clients of a
+    /// device (`--map`) queue for that device. The local console and
+    /// `ro` clients are not queued.
+    pub fn set_write_lock(&mut self, on: bool) {
+        self.write_lock = on;
+    }
+
+    /// Send `banner` to each TCP client that connects, once authenticated,
+    /// before anything else; gdb clients get none.
+    pub fn set_banner(&mut self, banner: Option<Banner>) {
+        self.banner = banner;
+    }
+
+    /// Keep the last `lines` lines of output of each device, and replay them
+    /// to TCP clients when they connect, so someone attaching just after a
+    /// crash still sees the panic. Zero (the default) disables it.
+    pub fn set_replay_lines(&mut self, lines: usize) {
+        self.replay_lines = lines;
+        if lines == 0 {
+            for slot in &mut self.devices {
+                slot.replay = None;
+            }
+        }
+    }
+
+    /// Write `bytes` to a device when nothing has been read from or written
+    /// to it for `interval`, so console servers and modems that drop idle
+    /// sessions keep the link up. Empty bytes or a zero interval disable it.
+    pub fn set_keepalive(&mut self, bytes: Vec<u8>, interval: Duration) {
+        self.keepalive = (!bytes.is_empty() && !interval.is_zero()).then_some((bytes, interval));
+    }
+
+    /// When device `idx` is due a keepalive write.
+    fn keepalive_due(&self, idx: usize) -> Option<Instant> {
+        let slot = &self.devices[idx];
+        let (_, interval) = self.keepalive.as_ref()?;
+        slot.device
+            .connected()
+            .then(|| slot.last_activity + *interval)
+    }
+
+    /// Write the keepalive to the devices that have been idle long enough.
+    fn send_keepalives(&mut self) {
+        let now = clock::now();
+        for idx in 0..self.devices.len() {
+            if self.keepalive_due(idx).is_some_and(|t| t <= now)
+                && let Some((bytes, _)) = self.keepalive.clone()
+            {
+                debug!("{}: keepalive", self.devices[idx].device.addr_as_string());
+                self.forward_to(idx, &bytes);
+            }
+        }
+    }
+
+    /// Fire `action` when a device has produced no output for `timeout`,
+    /// e.g. to catch a hung board in a soak test. It fires once per silence;
+    /// the next output of the device re-arms it.
+    pub fn set_silence(&mut self, timeout: Duration, action: SilenceAction) {
+        self.silence = (!timeout.is_zero()).then_some((timeout, action));
+    }
+
+    /// When the silence watchdog of device `idx` fires.
+    fn silence_due(&self, idx: usize) -> Option<Instant> {
+        let slot = &self.devices[idx];
+        let (timeout, _) = self.silence.as_ref()?;
+        (slot.device.connected() && !slot.silence_fired).then(|| slot.last_rx + *timeout)
+    }
+
+    /// Fire the silence watchdog of the devices that have been quiet too long.
+    fn check_silence(&mut self) {
+        let now = clock::now();
+        for idx in 0..self.devices.len() {
+            if self.silence_due(idx).is_none_or(|t| t > now) {
+                continue;
+            }
+            let Some((timeout, action)) = self.silence.clone() else {
+                return;
+            };
+            let slot = &mut self.devices[idx];
+            slot.silence_fired = true;
+            let addr = slot.device.addr_as_string();
+            let msg = format!("{}: no output for {:.1} s", addr, timeout.as_secs_f64());
+            info!(
+                event = "device_silent",
+                device = addr.as_str(),
+                action = format!("{:?}", action).as_str();
+                "{}", msg
+            );
+            self.device_announce(idx, &msg);
+            match action {
+                SilenceAction::Announce => {}
+                SilenceAction::Exec(command) => watchdog::exec(&command, &addr),
+                SilenceAction::Reconnect => {
+                    self.reset_device(idx);
+                }
+                SilenceAction::Exit => {
+                    self.exit_error = Some(std::io::Error::new(std::io::ErrorKind::TimedOut, msg));
+                    self.quit_requested = true;
+                }
+            }
+        }
+    }
+
+    /// Write `config.probe` to the devices every `config.interval`, and
+    /// announce when a device stops or starts answering it with a prompt
+    /// matching `config.prompt` within `config.timeout`.
+    pub fn set_health(&mut self, config: HealthConfig) {
+        let now = clock::now();
+        for slot in &mut self.devices {
+            slot.health = Some(HealthCheck::new(config.clone(), now));
+        }
+        self.health = Some(config);
+    }
+
+    /// End the run when a line of device output matches a pattern: run()
+    /// returns Ok on the success pattern and an [`outcome::Failed`] error on
+    /// the failure pattern.
+    pub fn set_exit_patterns(&mut self, patterns: ExitPatterns) {
+        for slot in &mut self.devices {
+            slot.outcome = Some(OutcomeMatcher::new(patterns.clone()));
+        }
+        self.exit_patterns = Some(patterns);
+    }
+
+    /// The output of device `idx` matched an exit pattern.
+    fn outcome(&mut self, idx: usize, outcome: Outcome) {
+        if self.quit_requested {
+            return;
+        }
+        let addr = self.devices[idx].device.addr_as_string();
+        let msg = match &outcome {
+            Outcome::Success(line) => format!("{}: success: {}", addr, line),
+            Outcome::Failure(line) => format!("{}: failure: {}", addr, line),
+        };
+        info!(
+            event = "device_outcome",
+            device = addr.as_str();
+            "{}", msg
+        );
+        self.device_announce(idx, &msg);
+        if let Outcome::Failure(_) = outcome {
+            self.exit_error = Some(std::io::Error::other(outcome::Failed(msg)));
+        }
+        self.quit_requested = true;
+    }
+
+    /// Split the captures of [`IoHubBuilder::capture_auto`], and the boot
+    /// cycles of [`IoHubBuilder::capture_db`], at the lines of device output
+    /// matching `pattern`, see [`IoHubBuilder::capture_split`].
+    pub fn set_capture_split(&mut self, pattern: Regex) {
+        for slot in &mut self.devices {
+            slot.boot_marker = Some(BootMarker::new(pattern.clone()));
+        }
+        self.capture_split = Some(pattern);
+    }
+
+    /// When the health check of device `idx` has something to do.
+    fn health_due(&self, idx: usize) -> Option<Instant> {
+        let slot = &self.devices[idx];
+        let health = slot.health.as_ref()?;
+        slot.device.connected().then(|| health.due())
+    }
+
+    /// Send the health probes that are due, and fail the ones that timed out.
+    fn check_health(&mut self) {
+        let now = clock::now();
+        for idx in 0..self.devices.len() {
+            if self.health_due(idx).is_none_or(|t| t > now) {
+                continue;
+            }
+            let Some(h) = &mut self.devices[idx].health else {
+                continue;
+            };
+            match h.tick(now) {
+                health::Tick::Probe => {
+                    let probe = h.probe().to_vec();
+                    debug!(
+                        "{}: health probe",
+                        self.devices[idx].device.addr_as_string()
+                    );
+                    self.forward_to(idx, &probe);
+                }
+                health::Tick::Changed(state) => self.health_changed(idx, state),
+                health::Tick::Nothing => {}
+            }
+        }
+    }
+
+    fn health_changed(&mut self, idx: usize, state: health::Health) {
+        let slot = &self.devices[idx];
+        let addr = slot.device.addr_as_string();
+        let msg = match (state, &slot.health) {
+            (health::Health::Down, Some(h)) => format!(
+                "{}: no prompt within {:.1} s, board down",
+                addr,
+                h.timeout().as_secs_f64()
+            ),
+            _ => format!("{}: prompt seen, board up", addr),
+        };
+        if state == health::Health::Down {
+            self.stats.health_failures += 1;
+        }
+        info!(
+            event = "health",
+            device = addr.as_str(),
+            state = state.to_string().as_str();
+            "{}", msg
+        );
+        self.device_announce(idx, &msg);
+    }
+
+    /// The `health` command: one line per device.
+    #[cfg(unix)]
+    fn health_summary(&self) -> std::result::Result<Vec<String>, String> {
+        if self.health.is_none() {
+            return Err("Health checks are off, see --health-prompt".to_string());
+        }
+        let now = clock::now();
+        Ok(self
+            .devices
+            .iter()
+            .filter_map(|slot| {
+                let h = slot.health.as_ref()?;
+                let mut line = format!(
+                    "{}: {}, {} check(s), {} failure(s)",
+                    slot.label,
+                    h.state(),
+                    h.checks,
+                    h.failures
+                );
+                if let Some(t) = h.last_up() {
+                    line.push_str(&format!(
+                        ", prompt seen {} ago",
+                        format_duration(now.saturating_duration_since(t))
+                    ));
+                }
+                Some(line)
+            })
+            .collect())
+    }
+
+    /// Deliver the frames of device output that are over.
+    fn flush_frames(&mut self) {
+        let now = clock::now();
+        for idx in 0..self.devices.len() {
+            if self.devices[idx].frame_until.is_some_and(|t| t <= now) {
+                self.flush_frame(idx);
+            }
+        }
+    }
+
+    fn flush_frame(&mut self, idx: usize) {
+        let slot = &mut self.devices[idx];
+        slot.frame_until = None;
+        if !slot.frame.is_empty() {
+            let frame: Arc<[u8]> = slot.frame.as_slice().into();
+            slot.frame.clear();
+            self.deliver_output(idx, frame);
+        }
+    }
+
+    /// Write the data queued in coalescing windows that are over.
+    fn flush_coalesced(&mut self) {
+        let now = clock::now();
+        let mut drain = false;
+        for idx in 0..self.devices.len() {
+            let slot = &mut self.devices[idx];
+            if slot.coalesce_until.is_none_or(|t| t > now) {
+                continue;
+            }
+            if slot.queued.is_empty() {
+                slot.coalesce_until = None;
+                continue;
+            }
+            // Clients are not read while MAX_BATCH is queued
+            drain |= slot.queued_len >= MAX_BATCH;
+            // More is likely to follow, keep coalescing
+            slot.coalesce_until = Some(now + self.write_coalesce);
+            self.flush_queued(idx);
+        }
+        if drain {
+            self.drain_pending_client_data();
+        }
+    }
+
+    pub fn set_trace_io(&mut self, trace_io: bool) {
+        self.trace_io = trace_io;
+    }
+
+    /// Log the client (console, TCP client address, ...) that sent each
+    /// input to a device, and record it in the capture, so a shared session
+    /// can be audited later.
+    pub fn set_audit_input(&mut self, audit_input: bool) {
+        self.audit_input = audit_input;
+    }
+
+    /// Called with [`LogChange::Raise`] on SIGUSR2 to make the log more
+    /// verbose, with [`LogChange::Restore`] on the next, and with the level
+    /// of the `log-level` action. The hub does not own the logger, so this
+    /// is up to the application; without it the log level is fixed.
+    pub fn set_log_control(&mut self, control: LogControl) {
+        self.log_control = Some(control);
+    }
+
+    /// SIGUSR1: turn the announcements to clients off or back on.
+    #[cfg(unix)]
+    fn toggle_announce(&mut self) {
+        self.announce = !self.announce;
+        warn!(
+            "SIGUSR1: announcements {}",
+            if self.announce { "on" } else { "off" }
+        );
+    }
+
+    /// The `set-option` action: change an option that is otherwise set at
+    /// startup.
+    fn set_option(&mut self, name: &str, value: &str) -> std::result::Result<String, String> {
+        let on_off = |current: bool| match value {
+            "" | "toggle" => Ok(!current),
+            "on" => Ok(true),
+            "off" => Ok(false),
+            _ => Err(format!("{}: expected on, off or toggle", name)),
+        };
+        let on_off_name = |on: bool| if on { "on" } else { "off" };
+        let msg = match name {
+            "announce" => {
+                self.announce = on_off(self.announce)?;
+                format!("Announcements {}", on_off_name(self.announce))
+            }
+            "audit-input" => {
+                self.audit_input = on_off(self.audit_input)?;
+                format!("Input audit {}", on_off_name(self.audit_input))
+            }
+            "trace-io" => {
+                self.trace_io = on_off(self.trace_io)?;
+                format!("I/O trace {}", on_off_name(self.trace_io))
+            }
+            "write-coalesce" => {
+                let ms: u64 = value
+                    .parse()
+                    .map_err(|_| format!("{}: expected milliseconds", name))?;
+                self.set_write_coalesce(Duration::from_millis(ms));
+                format!("Write coalescing {} ms", ms)
+            }
+            "silence-timeout" => {
+                let timeout = match value {
+                    "off" => Duration::ZERO,
+                    _ => value
+                        .parse::<f64>()
+                        .ok()
+                        .and_then(|s| Duration::try_from_secs_f64(s).ok())
+                        .ok_or_else(|| format!("{}: expected seconds or off", name))?,
+                };
+                let action = self
+                    .silence
+                    .take()
+                    .map_or(SilenceAction::Announce, |(_, action)| action);
+                self.set_silence(timeout, action);
+                match &self.silence {
+                    Some((timeout, _)) => {
+                        format!("Silence watchdog {:.1} s", timeout.as_secs_f64())
+                    }
+                    None => "Silence watchdog off".to_string(),
+                }
+            }
+            "capture" => {
+                let path = match value {
+                    "off" => return Ok(self.stop_capture()),
+                    "" | "toggle" if self.capture.is_some() => return Ok(self.stop_capture()),
+                    "on" if self.capture.is_some() => return Ok("Capture running".to_string()),
+                    "" | "toggle" | "on" => self
+                        .capture_stopped
+                        .clone()
+                        .ok_or_else(|| format!("{}: no capture to continue, give a file", name))?,
+                    _ => PathBuf::from(value),
+                };
+                let msg = self.start_capture(&path);
+                if self.capture.as_ref().is_none_or(|c| c.path() != path) {
+                    return Err(msg);
+                }
+                msg
+            }
+            "quarantine" => {
+                let ms: u64 = match value {
+                    "off" => 0,
+                    _ => value
+                        .parse()
+                        .map_err(|_| format!("{}: expected milliseconds or off", name))?,
+                };
+                let duration = Duration::from_millis(ms);
+                let taken = self
+                    .devices
+                    .iter_mut()
+                    .map(|slot| slot.device.set_quarantine(duration))
+                    .filter(Result::is_ok)
+                    .count();
+                if taken == 0 {
+                    return Err(format!("{}: no serial device", name));
+                }
+                match ms {
+                    0 => "Quarantine off".to_string(),
+                    _ => format!("Quarantine {} ms after connecting", ms),
+                }
+            }
+            _ => {
+                return Err(format!(
+                    "Unknown option: {} (announce, audit-input, trace-io, write-coalesce, \
+                     silence-timeout, capture, quarantine)",
+                    name
+                ));
+            }
+        };
+        info!("{}", msg);
+        Ok(msg)
+    }
+
+    /// SIGUSR2: raise or lower the log level.
+    #[cfg(unix)]
+    fn toggle_log_level(&mut self) {
+        let Some(control) = &mut self.log_control else {
+            return;
+        };
+        // Logged at a level that shows either way
+        if self.log_raised {
+            warn!("SIGUSR2: log level lowered");
+            let _ = control(LogChange::Restore);
+            self.log_raised = false;
+        } else {
+            match control(LogChange::Raise) {
+                Ok(()) => {
+                    self.log_raised = true;
+                    warn!("SIGUSR2: log level raised");
+                }
+                Err(e) => warn!("SIGUSR2: {}", e),
+            }
+        }
+    }
+
+    /// The `log-level` action; replaces a level raised by SIGUSR2.
+    fn set_log_level(&mut self, level: LevelFilter) -> std::result::Result<String, String> {
+        let control = self
+            .log_control
+            .as_mut()
+            .ok_or("Not logging, see --log-file and -v")?;
+        if self.log_raised {
+            self.log_raised = false;
+            let _ = control(LogChange::Restore);
+        }
+        control(LogChange::Set(level))?;
+        let msg = format!("Log level {}", level_name(level));
+        warn!("{}", msg);
+        Ok(msg)
+    }
+
+    /// The bytes the latency action writes to the device, and times the
+    /// first output after. Something the device echoes without side
+    /// effects; by default a space and a backspace.
+    pub fn set_latency_marker(&mut self, marker: Vec<u8>) {
+        if !marker.is_empty() {
+            self.latency_marker = marker;
+        }
+    }
+
+    /// Start measuring the latency of the device of `token`.
+    fn start_latency(&mut self, token: Token, probes: usize) {
+        if self.latency.is_some() {
+            self.reply(token, "Latency measurement already running");
+            return;
+        }
+        let idx = self.target_device(token);
+        let msg = format!(
+            "{}: Measuring latency ({} probe(s))",
+            self.devices[idx].device.addr_as_string(),
+            probes
+        );
+        self.reply(token, &msg);
+        self.latency = Some((token, idx, Latency::new(probes, clock::now())));
+    }
+
+    /// `note`: write `text` from `by` to the log and the capture, between
+    /// the device output.
+    fn note(&mut self, text: &str, by: &str) -> String {
+        info!(
+            event = "note",
+            addr = by,
+            note = text;
+            "Note from {}: {}", by, text
+        );
+        match &mut self.capture {
+            Some(c) => {
+                c.write_note(text);
+                format!("Note written to the log and {}", c.path().display())
+            }
+            None => "Note written to the log".to_string(),
+        }
+    }
+
+    /// `marker`: put `text` from external tooling, e.g. a CI step, on a line
+    /// of its own in the output of the clients and in the capture; `gdb` and
+    /// `quiet` clients get none. Returns the number of clients that got it.
+    fn marker(&mut self, text: &str, by: &str) -> std::result::Result<usize, String> {
+        let text = text.trim();
+        let text = text
+            .strip_prefix('"')
+            .and_then(|t| t.strip_suffix('"'))
+            .unwrap_or(text);
+        if text.is_empty() {
+            return Err("marker requires a text".to_string());
+        }
+        if text.chars().any(char::is_control) {
+            return Err("marker must be one line of text".to_string());
+        }
+        info!(
+            event = "marker",
+            addr = by,
+            marker = text;
+            "Marker from {}: {}", by, text
+        );
+        if let Some(c) = &mut self.capture {
+            c.write_marker(text);
+        }
+        let line: Arc<[u8]> = format!("\r\n{}\r\n", capture::mark_line("MARKER", text))
+            .into_bytes()
+            .into();
+        let mut clients = 0;
+        for (token, client) in self.instances.iter_mut() {
+            // Not into the raw stream of a debugger, nor to clients that
+            // asked for the device output alone
+            if self.auth_pending.contains_key(token)
+                || self
+                    .client_roles
+                    .get(token)
+                    .is_some_and(|r| r.gdb || !r.announce)
+                || !client.connected()
+            {
+                continue;
+            }
+            client.write_shared(&line);
+            clients += 1;
+        }
+        Ok(clients)
+    }
+
+    /// Start power operation `op` on the board of the active device, asked
+    /// for by `by`, and tell everybody.
+    fn power(&mut self, op: PowerOp, by: &str) -> std::result::Result<String, String> {
+        let idx = self.device_of(self.active_device);
+        let label = self.devices[idx].label.clone();
+        let Some(power) = &mut self.power else {
+            return Err(format!("{}: no power control configured", op));
+        };
+        power.start(op, &label)?;
+        let msg = format!("{}: {} (by {})", label, op, by);
+        self.device_announce(idx, &msg);
+        Ok(msg)
+    }
+
+    /// Tell everybody how the power operations that are done went.
+    fn check_power(&mut self) {
+        let Some(power) = &mut self.power else {
+            return;
+        };
+        for (op, label, result) in power.finished() {
+            let msg = match result {
+                Ok(()) => format!("{}: {} done", label, op),
+                Err(e) => format!("{}: {} failed: {}", label, op, e),
+            };
+            match self.devices.iter().position(|d| d.label == label) {
+                Some(idx) => self.device_announce(idx, &msg),
+                None => self.announce_to(None, &msg),
+            }
+        }
+    }
+
+    /// Send the latency probes that are due, and report when done.
+    fn check_latency(&mut self) {
+        let Some((token, idx, l)) = &mut self.latency else {
+            return;
+        };
+        let (token, idx) = (*token, *idx);
+        if !self.instances.contains_key(&token) {
+            self.latency = None;
+            return;
+        }
+        if l.tick(clock::now()) {
+            let marker = self.latency_marker.clone();
+            self.forward_to(idx, &marker);
+        } else if l.done() {
+            let msg = format!(
+                "{}: {}",
+                self.devices[idx].device.addr_as_string(),
+                l.summary()
+            );
+            info!("{}", msg);
+            self.latency = None;
+            self.reply(token, &msg);
+        }
+    }
+
+    fn trace_device_io(&self, idx: usize, direction: &str, buf: &[u8]) {
+        if self.trace_io && !buf.is_empty() {
+            trace!(
+                target: TRACE_TARGET,
+                "{} {} {} bytes\n{}",
+                self.devices[idx].device.addr_as_string(),
+                direction,
+                buf.len(),
+                hexdump(buf)
+            );
+        }
+    }
+
+    /// Start capturing device output to `capture`, or stop with `None`.
+    pub fn set_capture(&mut self, capture: Option<Capture>) {
+        self.capture = capture;
+    }
+
+    /// Send `msg` to the instance `token` only. Used for replies to explicit
+    /// requests, so it is sent even with announcements disabled.
+    fn reply(&mut self, token: Token, msg: &str) {
+        if let Some(client) = self.instances.get_mut(&token) {
+            let addr = client.addr_as_string();
+            client.write_announce(&self.announce_template, &addr, msg);
+        }
+    }
+
+    /// Session statistics, one line per entry.
+    pub fn stats_summary(&self) -> Vec<String> {
+        let now = clock::now();
+        self.stats.summary(now.duration_since(self.started), now)
+    }
+
+    /// The `GET /status` document.
+    fn status_json(&self) -> serde_json::Value {
+        let now = clock::now();
+        let s = &self.stats;
+        serde_json::json!({
+            "version": env!("CARGO_PKG_VERSION"),
+            "uptime": now.duration_since(self.started).as_secs(),
+            "active_device": self.active_device,
+            "devices": self.devices.iter().enumerate().map(|(i, d)| serde_json::json!({
+                "index": i,
+                "label": d.label,
+                "channel_of": d.channel_of,
+                "addr": d.device.addr_as_string(),
+                "connected": d.device.connected(),
+                "status": d.last_status_msg.as_deref().map(str::trim),
+                "health": d.health.as_ref().map(|h| h.state().to_string()),
+            })).collect::<Vec<_>>(),
+            "clients": self.instances.len(),
+            "stats": {
+                "device_rx_bytes": s.device_rx_bytes,
+                "device_tx_bytes": s.device_tx_bytes,
+                "client_connections": s.client_connections,
+                "device_reconnects": s.device_reconnects,
+                "backpressure_events": s.backpressure_events,
+                "slow_clients_dropped": s.slow_clients_dropped,
+                "health_failures": s.health_failures,
+                "connected_seconds": s.connected_time(now).as_secs(),
+            },
+            "summary": self.stats_summary(),
+        })
+    }
+
+    /// A snapshot of the hub for `debug-dump`, for reports of stuck
+    /// sessions: the registered tokens, and the connection, buffer and
+    /// backpressure state of each. Also written to the log.
+    fn debug_dump(&self) -> Vec<String> {
+        let yes_no = |b: bool| if b { "yes" } else { "no" };
+        let now = clock::now();
+        let mut lines = vec![format!(
+            "Hub: active device {}, device reads paused: {}, gdb priority: {}, capture: {}",
+            self.active_device,
+            yes_no(self.device_reads_paused),
+            yes_no(self.priority_until.is_some_and(|t| t > now)),
+            self.capture
+                .as_ref()
+                .map_or("none".to_string(), |c| c.path().display().to_string()),
+        )];
+        for slot in &self.devices {
+            lines.push(format!(
+                "Token {} device [{}] {}: connected: {}, write blocked: {}, pending write: {} bytes, queued: {} bytes",
+                slot.token.0,
+                slot.label,
+                slot.device.addr_as_string(),
+                yes_no(slot.device.connected()),
+                yes_no(slot.write_blocked),
+                slot.pending_write.len(),
+                slot.queued_len,
+            ));
+        }
+        if !self.listeners.is_empty() {
+            lines.push(format!(
+                "Tokens {}-{}: {} listener(s)",
+                TOKEN_LISTENER_START.0,
+                TOKEN_LISTENER_START.0 + self.listeners.len() - 1,
+                self.listeners.len()
+            ));
+        }
+        let mut tokens: Vec<&Token> = self.instances.keys().collect();
+        tokens.sort();
+        for token in tokens {
+            let client = &self.instances[token];
+            let mut line = format!(
+                "Token {} client {}: connected: {}, output queued: {} bytes, backlogged: {}",
+                token.0,
+                client.peer_as_string(),
+                yes_no(client.connected()),
+                client.write_queued(),
+                yes_no(client.write_backlogged()),
+            );
+            if let Some(&idx) = self.bound_clients.get(token) {
+                line.push_str(&format!(", device [{}]", self.devices[idx].label));
+            }
+            if self.client_roles.get(token).is_some_and(|r| r.read_only) {
+                line.push_str(", read-only");
+            }
+            if self.auth_pending.contains_key(token) {
+                line.push_str(", unauthenticated");
+            }
+            if let Some(chain) = self.client_chains.get(token).or(client.filters()) {
+                let filters: Vec<String> = chain
+                    .names()
+                    .into_iter()
+                    .map(|name| {
+                        let state = if chain.enabled(name) { "on" } else { "off" };
+                        format!("{}={}", name, state)
+                    })
+                    .collect();
+                line.push_str(&format!(", filters: {}", filters.join(" ")));
+            }
+            lines.push(line);
+        }
+        for line in &lines {
+            info!("Debug dump: {}", line);
+        }
+        lines
+    }
+
+    /// The `GET /clients` document.
+    fn clients_json(&self) -> serde_json::Value {
+        let mut tokens: Vec<&Token> = self.instances.keys().collect();
+        tokens.sort();
+        tokens
+            .into_iter()
+            .map(|token| {
+                let client = &self.instances[token];
+                let role = self.client_roles.get(token);
+                serde_json::json!({
+                    "id": token.0,
+                    "peer": client.peer_as_string(),
+                    "local": role.is_none(),
+                    "device": self.bound_clients.get(token).map(|&i| &self.devices[i].label),
+                    "read_only": role.is_some_and(|r| r.read_only),
+                    "authenticated": !self.auth_pending.contains_key(token),
+                })
+            })
+            .collect()
+    }
+
+    /// Answer a request of the HTTP API.
+    fn http_request(&mut self, request: &Request) -> Response {
+        match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/") => {
+                Response::html(http::status_page(&self.status_json(), &self.clients_json()))
+            }
+            ("GET", "/status") => Response::json(200, self.status_json()),
+            ("GET", "/clients") => Response::json(200, self.clients_json()),
+            ("POST", "/send") => self.http_send(request),
+            ("POST", "/disconnect-client") => self.http_disconnect(request),
+            ("POST", "/marker") => {
+                let text = String::from_utf8_lossy(&request.body);
+                match self.marker(&text, "http") {
+                    Ok(clients) => Response::json(200, serde_json::json!({ "clients": clients })),
+                    Err(e) => Response::error(400, &e),
+                }
+            }
+            (_, "/" | "/status" | "/clients" | "/send" | "/disconnect-client" | "/marker") => {
+                Response::error(405, "method not allowed")
+            }
+            _ => Response::error(404, "not found"),
+        }
+    }
+
+    /// `POST /send[?device=N]`: the body goes to device N, or the active one.
+    /// Refused when the API is read-only, or a client holds the write lock
+    /// of the device.
+    fn http_send(&mut self, request: &Request) -> Response {
+        if self.http.as_ref().is_some_and(|h| h.read_only()) {
+            return Response::error(403, "read-only");
+        }
+        let idx = match request.param("device").map(|d| d.parse::<usize>()) {
+            None => self.active_device,
+            Some(Ok(idx)) if idx < self.devices.len() => idx,
+            Some(_) => return Response::error(404, "no such device"),
+        };
+        // The clients of all devices write to the active one
+        let boards = [Some(Some(idx)), (idx == self.active_device).then_some(None)];
+        if self.write_lock
+            && boards
+                .iter()
+                .flatten()
+                .any(|b| self.write_queues.get(b).and_then(|q| q.holder()).is_some())
+        {
+            return Response::error(409, "a client has the write lock");
+        }
+        if !self.devices[idx].device.connected() {
+            return Response::error(503, "device not connected");
+        }
+        let addr = self.devices[idx].device.addr_as_string();
+        if self.audit_input && !request.body.is_empty() {
+            let input = escape_input(&request.body);
+            info!(
+                event = "client_input",
+                addr = "http",
+                input = input.as_str();
+                "Input from http: {}", input
+            );
+            if let Some(c) = &mut self.capture {
+                c.write_input("http", &request.body);
+            }
+        }
+        info!("Http: sending {} bytes to {}", request.body.len(), addr);
+        self.forward_to(idx, &request.body);
+        Response::json(
+            200,
+            serde_json::json!({ "sent": request.body.len(), "device": addr }),
+        )
+    }
+
+    /// `POST /disconnect-client?id=N`, N as in `GET /clients`.
+    fn http_disconnect(&mut self, request: &Request) -> Response {
+        let Some(token) = request
+            .param("id")
+            .and_then(|id| id.parse().ok())
+            .map(Token)
+            .filter(|t| self.instances.contains_key(t))
+        else {
+            return Response::error(404, "no such client");
+        };
+        if !self.client_roles.contains_key(&token) {
+            return Response::error(403, "local clients can not be disconnected");
+        }
+        info!("Http: disconnecting client {:?}", token);
+        self.detach_client(token);
+        Response::json(200, serde_json::json!({ "disconnected": token.0 }))
+    }
+
+    fn handle_read_result(&mut self, token: Token, result: IoResult) {
+        if self.auth_pending.contains_key(&token) {
+            if let IoResult::Data(bytes) = result {
+                self.authenticate(token, &bytes);
+            }
+            return;
+        }
+        match result {
+            IoResult::Data(bytes) => match self.client_keybinds.get_mut(&token) {
+                Some(keybinds) => {
+                    let results = keybinds.process(&bytes);
+                    self.handle_client_keybinds(token, results);
+                }
+                None => self.forward_client_data(token, bytes),
+            },
+            IoResult::Action(action) => {
+                info!("Hub received action: {:?}", action);
+                self.handle_action(token, action);
+                info!(
+                    "Hub handle_action returned, quit_requested = {}",
+                    self.quit_requested
+                );
+            }
+            IoResult::None => {}
+        }
+        trace!("handle_read_result returning");
+    }
+
+    /// SIGWINCH: pick up the new terminal size and pass it on to the devices.
+    #[cfg(unix)]
+    fn handle_resize(&mut self) {
+        let old = terminal_size();
+        refresh_terminal_size();
+        let Some((cols, rows)) = terminal_size() else {
+            return;
+        };
+        if old == Some((cols, rows)) {
+            return;
+        }
+        info!("Terminal resized to {}x{}", cols, rows);
+        for slot in &mut self.devices {
+            if slot.device.connected() {
+                slot.device.resize(cols, rows);
+            }
+        }
+    }
+
+    fn handle_action(&mut self, token: Token, action: Action) {
+        match action {
+            Action::Quit => {
+                info!("Hub handling Quit action - setting quit_requested = true");
+                self.quit_requested = true;
+                info!("Hub quit_requested is now: {}", self.quit_requested);
+            }
+            Action::Send(bytes) => {
+                info!("Hub handling Send action with {} bytes", bytes.len());
+                self.forward_to_device(token, &bytes);
+            }
+            Action::Stats => {
+                for line in self.stats_summary() {
+                    self.reply(token, &line);
+                }
+            }
+            Action::DeviceNext => {
+                // The shown channel of the next device
+                let mut next = self.active_device;
+                loop {
+                    next = (next + 1) % self.devices.len();
+                    if !self.devices[next].background {
+                        break;
+                    }
+                }
+                self.active_device = next;
+                let msg = format!(
+                    "Input to [{}] {}",
+                    self.devices[self.active_device].label,
+                    self.devices[self.active_device].device.addr_as_string()
+                );
+                info!("{}", msg);
+                self.reply(token, &msg);
+                // Clients may have been paused by the previous device
+                if !self.active_blocked() {
+                    self.drain_pending_client_data();
+                }
+            }
+            Action::ChannelNext => {
+                let msg = self.channel_next();
+                self.reply(token, &msg);
+                if !self.active_blocked() {
+                    self.drain_pending_client_data();
+                }
+            }
+            Action::SetBaud(baud) => {
+                let (Ok(msg) | Err(msg)) = self.set_baud(self.target_device(token), baud);
+                self.reply(token, &msg);
+            }
+            Action::CaptureStart(path) => {
+                let msg = self.start_capture(&path);
+                self.reply(token, &msg);
+            }
+            Action::CaptureStop => {
+                let msg = self.stop_capture();
+                self.reply(token, &msg);
+            }
+            // Console filters are toggled in the console, this is a client
+            // of a server
+            Action::FilterToggle(name) => self.toggle_client_filter(token, &name),
+            Action::Charmap(preset) => self.set_client_charmap(token, preset.as_deref()),
+            Action::Remote(command) => self.remote_command(token, &command),
+            Action::Latency(probes) => self.start_latency(token, probes),
+            Action::Power(op) => {
+                let by = self
+                    .instances
+                    .get(&token)
+                    .map(|c| c.peer_as_string())
+                    .unwrap_or_default();
+                if let Err(msg) = self.power(op, &by) {
+                    self.reply(token, &msg);
+                }
+            }
+            Action::LogLevel(level) => {
+                let (Ok(msg) | Err(msg)) = self.set_log_level(level);
+                self.reply(token, &msg);
+            }
+            Action::SetOption(name, value) => {
+                let (Ok(msg) | Err(msg)) = self.set_option(&name, &value);
+                self.reply(token, &msg);
+            }
+            Action::DebugDump(show) => {
+                let lines = self.debug_dump();
+                if show {
+                    for line in &lines {
+                        self.reply(token, line);
+                    }
+                } else {
+                    self.reply(
+                        token,
+                        &format!("Hub state ({} lines) written to the log", lines.len()),
+                    );
+                }
+            }
+            Action::WriteRelease => self.release_write_lock(token),
+            Action::Note(Some(text)) => {
+                let by = self
+                    .instances
+                    .get(&token)
+                    .map(|c| c.peer_as_string())
+                    .unwrap_or_default();
+                let msg = self.note(&text, &by);
+                self.reply(token, &msg);
+            }
+            Action::Note(None) => self.reply(token, "note requires a text"),
+            Action::Command
+            | Action::PauseOutput
+            | Action::CopyOutput(_)
+            | Action::ReplayOutput(_)
+            | Action::HexInput
+            | Action::SendLine
+            | Action::LineEdit
+            | Action::MouseToggle
+            | Action::Suspend => {
+                // Handled locally in Console, should not reach hub
+                info!("Hub received {} (should be handled locally)", action);
+            }
+        }
+        trace!("handle_action returning");
+    }
+
+    fn forward_client_data(&mut self, token: Token, bytes: Arc<[u8]>) {
+        let bytes = match self.client_chains.get_mut(&token) {
+            Some(chain) => chain.filter_in(&bytes).into(),
+            None => bytes,
+        };
+        self.forward_to_device(token, &bytes);
+    }
+
+    fn handle_client_keybinds(&mut self, token: Token, results: Vec<KeybindResult>) {
+        for result in results {
+            match result {
+                KeybindResult::Passthrough(bytes) => self.forward_client_data(token, bytes.into()),
+                KeybindResult::Action(action) => self.client_action(token, action),
+                KeybindResult::Consumed => {}
+            }
+            // Detached by its quit key
+            if !self.instances.contains_key(&token) {
+                break;
+            }
+        }
+    }
+
+    /// Replace the running capture, if any, with one to `path`. Returns the
+    /// message for the user.
+    fn start_capture(&mut self, path: &Path) -> String {
+        match Capture::new(path) {
+            Ok(c) => {
+                let c = c.with_compression(self.capture_compress);
+                self.capture = Some(match &self.capture_filters {
+                    Some(factory) => c.with_filter(factory()),
+                    None => c,
+                });
+                format!("Capturing to {}", path.display())
+            }
+            Err(e) => format!("Capture {}: {}", path.display(), e),
+        }
+    }
+
+    /// Device `idx` booted: continue the capture in a new file.
+    fn split_capture(&mut self, idx: usize) {
+        let Some(dir) = &self.capture_auto else {
+            return;
+        };
+        let slot = &mut self.devices[idx];
+        slot.boots += 1;
+        let path = capture::boot_path(
+            dir,
+            &slot.label,
+            slot.boots,
+            &chrono::Local::now(),
+            self.capture_format,
+            self.capture_compress,
+        );
+        let msg = self.start_capture(&path);
+        info!("{}", msg);
+        self.device_announce(idx, &msg);
+    }
+
+    fn stop_capture(&mut self) -> String {
+        match self.capture.take() {
+            Some(c) => {
+                self.capture_stopped = Some(c.path().to_path_buf());
+                format!(
+                    "Capture stopped: {} ({} bytes)",
+                    c.path().display(),
+                    c.bytes()
+                )
+            }
+            None => "No capture running".to_string(),
+        }
+    }
+
+    /// Change the baudrate of device `idx`. The message for the user either
+    /// way.
+    fn set_baud(&mut self, idx: usize, baud: u32) -> std::result::Result<String, String> {
+        let addr = self.devices[idx].device.addr_as_string();
+        let result = match self.devices[idx].device.set_baudrate(baud) {
+            Ok(()) => Ok(format!("{}: Baudrate {}", addr, baud)),
+            Err(e) => Err(format!("{}: Baudrate {}: {}", addr, baud, e)),
+        };
+        let (Ok(msg) | Err(msg)) = &result;
+        info!("{}", msg);
+        result
+    }
+
+    /// Run a command of the control socket: the remote commands, `clients`
+    /// and `kick ID`, with the rights of the owner of the process.
+    #[cfg(unix)]
+    fn ctl_command(&mut self, line: &str) -> std::result::Result<Vec<String>, String> {
+        info!("Ctl: {}", line);
+        match ctl::split(line) {
+            ("clients", "") => {
+                let mut tokens: Vec<Token> = self.client_roles.keys().copied().collect();
+                tokens.sort();
+                Ok(tokens
+                    .into_iter()
+                    .map(|t| {
+                        let peer = self.instances[&t].peer_as_string();
+                        let mut line = format!("{} {}", t.0, peer);
+                        if let Some(&idx) = self.bound_clients.get(&t) {
+                            line.push_str(&format!(" device={}", self.devices[idx].label));
+                        }
+                        if self.client_roles[&t].read_only {
+                            line.push_str(" read-only");
+                        }
+                        match self
+                            .write_board(t)
+                            .and_then(|b| self.write_queues[&b].position(t))
+                        {
+                            Some(0) => line.push_str(" write-lock"),
+                            Some(n) => line.push_str(&format!(" queued={}", n)),
+                            None => {}
+                        }
+                        if self.auth_pending.contains_key(&t) {
+                            line.push_str(" unauthenticated");
+                        }
+                        line
+                    })
+                    .collect())
+            }
+            ("health", "") => self.health_summary(),
+            ("marker", text) => self
+                .marker(text, "control socket")
+                .map(|n| vec![format!("Marker shown to {} client(s)", n)]),
+            // Another crabterm wants the port (`--steal`)
+            ("release", device) => self.release_device(device),
+            ("kick", id) => {
+                let token = id
+                    .parse()
+                    .ok()
+                    .map(Token)
+                    .filter(|t| self.client_roles.contains_key(t))
+                    .ok_or_else(|| format!("No such client: {}", id))?;
+                self.detach_client(token);
+                Ok(vec![format!("Client {} disconnected", id)])
+            }
+            _ => match parse_command(line)? {
+                Action::Stats => Ok(self.stats_summary()),
+                Action::SetBaud(baud) => self.set_baud(self.active_device, baud).map(|m| vec![m]),
+                Action::CaptureStart(path) => Ok(vec![self.start_capture(&path)]),
+                Action::CaptureStop => Ok(vec![self.stop_capture()]),
+                Action::LogLevel(level) => self.set_log_level(level).map(|m| vec![m]),
+                Action::SetOption(name, value) => self.set_option(&name, &value).map(|m| vec![m]),
+                Action::FilterToggle(name) => {
+                    if !FilterChain::default().toggle(&name) {
+                        return Err(format!("Unknown filter: {}", name));
+                    }
+                    let tokens: Vec<Token> = self.client_roles.keys().copied().collect();
+                    for &token in &tokens {
+                        self.toggle_client_filter(token, &name);
+                    }
+                    Ok(vec![format!(
+                        "Filter {} toggled for {} client(s)",
+                        name,
+                        tokens.len()
+                    )])
+                }
+                Action::Charmap(preset) => {
+                    let msg = charmap::set_preset(&mut FilterChain::default(), preset.as_deref())?;
+                    let tokens: Vec<Token> = self.client_roles.keys().copied().collect();
+                    for &token in &tokens {
+                        self.set_client_charmap(token, preset.as_deref());
+                    }
+                    Ok(vec![format!("{} for {} client(s)", msg, tokens.len())])
+                }
+                Action::Send(bytes) => {
+                    self.forward_to(self.active_device, &bytes);
+                    Ok(vec![])
+                }
+                Action::ChannelNext => Ok(vec![self.channel_next()]),
+                Action::DebugDump(_) => Ok(self.debug_dump()),
+                Action::Power(op) => self.power(op, "control socket").map(|m| vec![m]),
+                Action::Note(Some(text)) => Ok(vec![self.note(&text, "control socket")]),
+                Action::Quit => {
+                    self.quit_requested = true;
+                    Ok(vec!["Quitting".to_string()])
+                }
+                action => Err(format!("{}: not available on the control socket", action)),
+            },
+        }
+    }
+
+    /// A command sent in-band by `crabterm connect`.
+    fn remote_command(&mut self, token: Token, command: &str) {
+        info!("Remote command from {:?}: {}", token, command);
+        match parse_command(command) {
+            Ok(action) => self.client_action(token, action),
+            Err(e) => self.reply(token, &e),
+        }
+    }
+
+    /// An action of a TCP client, from its keybinds or a remote command.
+    /// Only the actions that report, or concern this client alone, are run;
+    /// quit disconnects the client.
+    fn client_action(&mut self, token: Token, action: Action) {
+        match action {
+            Action::Quit => self.detach_client(token),
+            Action::WriteRelease => self.release_write_lock(token),
+            Action::ReplayOutput(lines) => {
+                if !self.replay(token, lines) {
+                    self.reply(token, "Nothing to replay (replay-lines is off)");
+                }
+            }
+            Action::Send(_)
+            | Action::Stats
+            | Action::Note(_)
+            | Action::FilterToggle(_)
+            | Action::Charmap(_)
+            | Action::Remote(_) => self.handle_action(token, action),
+            // Writes to the device, like input
+            Action::Latency(_) if !self.client_roles.get(&token).is_some_and(|r| r.read_only) => {
+                self.handle_action(token, action)
+            }
+            action => self.reply(token, &format!("{}: not allowed remotely", action)),
+        }
+    }
+
+    fn detach_client(&mut self, token: Token) {
+        self.reply(token, "Detached");
+        if let Some(client) = self.instances.get_mut(&token) {
+            client.flush();
+            client.disconnect(&mut self.poll);
+        }
+        self.remove_client(token);
+    }
+
+    fn remove_client(&mut self, token: Token) {
+        info!("Hub({:?}): Remove", token);
+        self.leave_write_queue(token);
+        if let Some(client) = self.instances.remove(&token)
+            && let Some(e) = &mut self.events
+        {
+            e.client_leave(&client.addr_as_string());
+        }
+        self.bound_clients.remove(&token);
+        self.client_chains.remove(&token);
+        self.client_keybinds.remove(&token);
+        self.client_roles.remove(&token);
+        self.auth_pending.remove(&token);
+    }
+
+    /// The filter chain of one client. Clients without one get the built-in
+    /// filters, all off.
+    fn client_chain(&mut self, token: Token) -> &mut FilterChain {
+        self.client_chains
+            .entry(token)
+            .or_insert_with(|| match &self.client_filters {
+                Some(factory) => factory(),
+                None => FilterChain::default(),
+            })
+    }
+
+    /// Toggle a filter on the output to one client.
+    fn toggle_client_filter(&mut self, token: Token, name: &str) {
+        let chain = self.client_chain(token);
+        let msg = if chain.toggle(name) {
+            let state = if chain.enabled(name) { "on" } else { "off" };
+            format!("Filter {} {}", name, state)
+        } else {
+            format!("Unknown filter: {}", name)
+        };
+        self.reply(token, &msg);
+    }
+
+    /// Switch the charmap of one client to a preset, or off.
+    fn set_client_charmap(&mut self, token: Token, preset: Option<&str>) {
+        let (Ok(msg) | Err(msg)) = charmap::set_preset(self.client_chain(token), preset);
+        self.reply(token, &msg);
+    }
+
+    /// Try to write `bytes` to the device, buffering any remainder.
+    /// Returns true if the device became blocked.
+    fn try_device_write(
+        device: &mut dyn IoInstance,
+        pending: &mut Vec<u8>,
+        blocked: &mut bool,
+        poll: &mut Poll,
+        bytes: &[u8],
+    ) -> bool {
+        let n = device.write_all(bytes);
+        if n < bytes.len() {
+            pending.extend_from_slice(&bytes[n..]);
+            if !*blocked {
+                info!("Device write blocked — enabling backpressure");
+                *blocked = true;
+                if let Err(e) = device.set_writable_interest(poll, true) {
+                    error!("Failed to set writable interest: {}", e);
+                }
+            }
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Read and forward data from a single client until WouldBlock or the
+    /// device becomes write-blocked.
+    fn drain_client(&mut self, token: Token) {
+        trace!("drain_client({:?}): starting", token);
+        loop {
+            trace!(
+                "drain_client({:?}): loop iteration, quit_requested={}",
+                token, self.quit_requested
+            );
+            let result = match self.instances.get_mut(&token) {
+                Some(client) if client.connected() => match client.read() {
+                    Ok(IoResult::None) => {
+                        trace!("drain_client({:?}): read returned None, breaking", token);
+                        break;
+                    }
+                    Ok(result) => result,
+                    Err(_) => {
+                        trace!("drain_client({:?}): read returned error, breaking", token);
+                        break;
+                    }
+                },
+                _ => {
+                    trace!(
+                        "drain_client({:?}): client not found or disconnected, breaking",
+                        token
+                    );
+                    break;
+                }
+            };
+            trace!("drain_client({:?}): calling handle_read_result", token);
+            self.handle_read_result(token, result);
+            trace!("drain_client({:?}): handle_read_result returned", token);
+            if self.target_blocked(token) {
+                trace!("drain_client({:?}): device write blocked, breaking", token);
+                break;
+            }
+            if self.quit_requested {
+                trace!("drain_client({:?}): quit_requested, breaking", token);
+                break;
+            }
+        }
+        trace!("drain_client({:?}): exiting", token);
+    }
+
+    /// Drain pending client data after backpressure is lifted.
+    ///
+    /// With edge-triggered epoll we will not get new READABLE events for data
+    /// that arrived while we were blocked, so we must explicitly read from
+    /// every client once the device can accept data again.
+    fn drain_pending_client_data(&mut self) {
+        let tokens: Vec<Token> = self.instances.keys().copied().collect();
+        for token in tokens {
+            if !self.target_blocked(token) {
+                self.drain_client(token);
+            }
+        }
+    }
+
+    fn handle_device_event(&mut self, idx: usize, event: &Event) -> Result<()> {
+        // Handle backpressure relief: device can accept writes again.
+        if event.is_writable() && self.devices[idx].write_blocked {
+            info!("Device write unblocked — flushing pending data");
+            let slot = &mut self.devices[idx];
+            slot.write_blocked = false;
+            slot.device.set_writable_interest(&mut self.poll, false)?;
+
+            // The bytes saved from a previous partial write go out together
+            // with the client data that piled up meanwhile, in one vectored
+            // write per device and batch.
+            self.batch_writes = true;
+            loop {
+                self.drain_pending_client_data();
+                let queued = self.devices.iter().any(|s| !s.queued.is_empty());
+                for i in 0..self.devices.len() {
+                    if i == idx || !self.devices[i].queued.is_empty() {
+                        self.flush_queued(i);
+                    }
+                }
+                if !queued || self.devices[idx].write_blocked || self.quit_requested {
+                    break;
+                }
+            }
+            self.batch_writes = false;
+        }
+
+        self.read_device(idx);
+        Ok(())
+    }
+
+    fn clients_backlogged(&self) -> bool {
+        self.instances.values().any(|c| c.write_backlogged())
+    }
+
+    /// Read device `idx` and send the output to the clients.
+    fn read_device(&mut self, idx: usize) {
+        // Must loop until WouldBlock because mio uses edge-triggered epoll.
+        // A single edge may signal multiple readable chunks.
+        loop {
+            // A blocked client holds up all output
+            if self.clients_backlogged() {
+                if !self.device_reads_paused {
+                    info!("Client output backlogged — pausing device reads");
+                    self.device_reads_paused = true;
+                }
+                break;
+            }
+            match self.devices[idx].device.read() {
+                Ok(IoResult::Data(buf)) => self.device_output(idx, buf),
+                Ok(IoResult::None) => break,
+                Ok(IoResult::Action(_)) => {}
+                Err(e) => {
+                    self.flush_frame(idx);
+                    let slot = &mut self.devices[idx];
+                    let addr = slot.device.addr_as_string();
+                    let msg = format!("{}: {}", addr, e);
+                    info!(
+                        event = "device_disconnect",
+                        device = addr.as_str(),
+                        error = e.to_string().as_str();
+                        "Device disconnected: {}", msg
+                    );
+                    if let Some(n) = &mut self.notifier {
+                        n.device_disconnected(&slot.label, &msg);
+                    }
+                    if let Some(ev) = &mut self.events {
+                        ev.disconnect(&slot.label, &e.to_string());
+                    }
+                    self.device_status(idx, msg);
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Handle output read from device `idx` (or returned by its tick).
+    fn device_output(&mut self, idx: usize, buf: Arc<[u8]>) {
+        self.trace_device_io(idx, "RX", &buf);
+        while let Some(msg) = self.devices[idx].device.take_announcement() {
+            self.device_announce(idx, &msg);
+        }
+        if let Some((_, dev, l)) = &mut self.latency
+            && *dev == idx
+        {
+            l.output(clock::now());
+        }
+        let slot = &mut self.devices[idx];
+        slot.last_activity = clock::now();
+        slot.last_rx = slot.last_activity;
+        slot.silence_fired = false;
+        if let Some(h) = &mut slot.health
+            && let health::Tick::Changed(state) = h.output(&buf, slot.last_rx)
+        {
+            self.health_changed(idx, state);
+        }
+        if let Some(outcome) = self.devices[idx]
+            .outcome
+            .as_mut()
+            .and_then(|m| m.feed(&buf))
+        {
+            self.outcome(idx, outcome);
+        }
+        let slot = &mut self.devices[idx];
+        if let Some(m) = &mut self.monitor {
+            m.rx(&buf);
+        }
+        if let Some(n) = &mut self.notifier {
+            n.rx(&slot.label, &buf);
+        }
+        if let Some(e) = &mut self.events {
+            e.data(&slot.label, &buf);
+        }
+        self.stats.device_rx_bytes += buf.len() as u64;
+        if self.frame_gap.is_zero() {
+            self.deliver_output(idx, buf);
+        } else {
+            slot.frame.extend_from_slice(&buf);
+            slot.frame_until = Some(clock::now() + self.frame_gap);
+            if slot.frame.len() >= MAX_FRAME {
+                self.flush_frame(idx);
+            }
+        }
+    }
+
+    /// Pass output of device `idx` to the capture and the clients.
+    fn deliver_output(&mut self, idx: usize, buf: Arc<[u8]>) {
+        let multiple = self.devices.len() > 1;
+        let slot = &mut self.devices[idx];
+        let background = slot.background;
+        if self.replay_lines > 0 {
+            slot.replay
+                .get_or_insert_with(|| Scrollback::new(self.replay_lines))
+                .push(&buf);
+        }
+        let boot = match &mut slot.boot_marker {
+            Some(marker) if self.capture_auto.is_some() || self.capture_db.is_some() => {
+                marker.feed(&buf)
+            }
+            _ => None,
+        };
+        if let Some(db) = &mut self.capture_db {
+            match boot {
+                Some(at) => {
+                    db.write(&slot.label, &buf[..at]);
+                    db.boot(&slot.label);
+                    db.write(&slot.label, &buf[at..]);
+                }
+                None => db.write(&slot.label, &buf),
+            }
+        }
+        // Bound clients get the raw output of their own device. `split` is
+        // where the boot starts in it.
+        let (shared, split): (Arc<[u8]>, usize) = match boot {
+            Some(at) if multiple => {
+                let mut shared = prefix_lines(&slot.label, &mut slot.at_line_start, &buf[..at]);
+                let split = shared.len();
+                shared.extend(prefix_lines(
+                    &slot.label,
+                    &mut slot.at_line_start,
+                    &buf[at..],
+                ));
+                (shared.into(), split)
+            }
+            Some(at) => (buf.clone(), at),
+            None if multiple => (
+                prefix_lines(&slot.label, &mut slot.at_line_start, &buf).into(),
+                0,
+            ),
+            None => (buf.clone(), 0),
+        };
+        if let Some(p) = &mut self.pipe {
+            p.write(&shared);
+        }
+        if let Some(at) = boot {
+            if let Some(c) = &mut self.capture {
+                c.write_output(&slot.label, &buf[..at], &shared[..split]);
+            }
+            self.split_capture(idx);
+            if let Some(c) = &mut self.capture {
+                c.write_output(&self.devices[idx].label, &buf[at..], &shared[split..]);
+            }
+        } else if let Some(c) = &mut self.capture {
+            c.write_output(&slot.label, &buf, &shared);
+        }
+        for (token, client) in self.instances.iter_mut() {
+            if self.auth_pending.contains_key(token) {
+                continue;
+            }
+            // gdb clients get the raw output of the device their input goes
+            // to, without the labels of several devices
+            let gdb = self.client_roles.get(token).is_some_and(|r| r.gdb);
+            let out = match self.bound_clients.get(token) {
+                Some(&b) if b == idx => &buf,
+                Some(_) => continue,
+                None if gdb && idx == self.active_device => &buf,
+                None if gdb || background => continue,
+                None => &shared,
+            };
+            let out = match self.client_chains.get_mut(token) {
+                Some(chain) => {
+                    self.filter_buf.clear();
+                    chain.filter_out_into(out, &mut self.filter_buf);
+                    self.filter_buf.as_slice().into()
+                }
+                None => out.clone(),
+            };
+            if client.connected() && client.write_shared(&out) < out.len() && !client.connected() {
+                self.stats.slow_clients_dropped += 1;
+            }
+        }
+    }
+
+    /// Resume reading the devices once no client is backlogged any more.
+    /// They are read explicitly, as no new READABLE edge comes for data that
+    /// arrived meanwhile.
+    fn resume_device_reads(&mut self) {
+        if !self.device_reads_paused || self.clients_backlogged() {
+            return;
+        }
+        info!("Client output caught up — resuming device reads");
+        self.device_reads_paused = false;
+        for idx in 0..self.devices.len() {
+            if self.devices[idx].device.connected() {
+                self.read_device(idx);
+            }
+        }
+    }
+
+    pub fn handle_event(&mut self, event: &Event) -> Result<()> {
+        let token_event = event.token();
+        trace!("handle_event");
+
+        if let Some(idx) = self.device_index(token_event) {
+            self.handle_device_event(idx, event)?;
+        } else if let Some(i) = self.listener_index(token_event) {
+            // Must loop until WouldBlock because mio uses edge-triggered epoll.
+            // A single edge may signal multiple pending connections.
+            let role = self.listeners[i].role();
+            for (c, keybinds) in self.listeners[i].accept_all() {
+                self.add_tcp_client(c, None, keybinds, role)?;
+            }
+        } else if self.handle_unix_event(token_event)? {
+            // The session and control sockets, or signals
+        } else if let Some(idx) = self.device_server_index(token_event) {
+            let mut new_clients = Vec::new();
+            let mut role = ListenerRole::default();
+            if let Some(s) = &mut self.devices[idx].server {
+                role = s.role();
+                while let Some(c) = s.accept() {
+                    new_clients.push((c, s.client_keybinds().cloned()));
+                }
+            }
+            for (c, keybinds) in new_clients {
+                self.add_tcp_client(c, Some(idx), keybinds, role)?;
+            }
+        } else if token_event == TOKEN_MONITOR_SERVER {
+            if let Some(m) = &mut self.monitor {
+                m.accept(&mut self.poll)?;
+            }
+        } else if token_event == TOKEN_HTTP_SERVER {
+            if let Some(h) = &mut self.http {
+                h.accept(&mut self.poll)?;
+            }
+        } else if let Some(h) = &mut self.http
+            && h.owns(token_event)
+        {
+            if event.is_writable() {
+                h.write(&mut self.poll, token_event);
+            } else if let Some(request) = h.read(&mut self.poll, token_event) {
+                let response = match h.check(&request) {
+                    Some(refused) => refused,
+                    None => self.http_request(&request),
+                };
+                if let Some(h) = &mut self.http {
+                    h.respond(&mut self.poll, token_event, response);
+                }
+            }
+        } else if token_event == TOKEN_MDNS {
+            if let Some(m) = &mut self.mdns {
+                m.handle();
+            }
+        } else if token_event == TOKEN_METRICS_SERVER {
+            if let Some(m) = &mut self.metrics {
+                m.accept(&mut self.poll)?;
+            }
+        } else if let Some(m) = &mut self.metrics
+            && m.owns(token_event)
+        {
+            let snapshot = MetricsSnapshot {
+                stats: &self.stats,
+                clients_connected: self.instances.len(),
+                devices_connected: self.devices.iter().filter(|d| d.device.connected()).count(),
+                devices_healthy: self.health.is_some().then(|| {
+                    self.devices
+                        .iter()
+                        .filter(|d| {
+                            d.health
+                                .as_ref()
+                                .is_some_and(|h| h.state() == health::Health::Up)
+                        })
+                        .count()
+                }),
+            };
+            m.handle(&mut self.poll, token_event, &snapshot);
+        } else if self.instances.contains_key(&token_event) {
+            // NOTICE: The 'console' is also a client
+            if event.is_writable()
+                && let Some(client) = self.instances.get_mut(&token_event)
+            {
+                client.flush();
+            }
+            if !self.target_blocked(token_event) {
+                self.drain_client(token_event);
+            }
+        } else {
+            // With edge-triggered epoll, stale events can arrive for tokens that were
+            // removed earlier in the same event batch. This is expected and harmless.
+            trace!("Ignoring event for unknown token: {}", token_event.0);
+        }
+
+        // Clean up all instances not connected ///////////////////////////////
+        let mut disconnected_tokens = Vec::new();
+        for (&t, client) in self.instances.iter_mut() {
+            if !client.connected() {
+                let addr = client.addr_as_string();
+                info!(
+                    event = "client_disconnect",
+                    token = t.0,
+                    addr = addr.as_str();
+                    "Hub({:?}): {}: disconnect()", t, addr
+                );
+                client.disconnect(&mut self.poll);
+                disconnected_tokens.push(t);
+            }
+        }
+
+        for t in disconnected_tokens {
+            self.remove_client(t);
+        }
+
+        self.resume_device_reads();
+
+        Ok(())
+    }
+
+    /// Events of the session socket, the control socket and the signals,
+    /// which only unix has. False for other tokens.
+    #[cfg(unix)]
+    fn handle_unix_event(&mut self, token_event: Token) -> Result<bool> {
+        #[cfg(target_os = "linux")]
+        if token_event == TOKEN_HOTPLUG {
+            // The devices that are not connected are tried at the start of
+            // the next turn
+            if let Some(h) = &mut self.hotplug {
+                h.drain();
+            }
+            return Ok(true);
+        }
+        if token_event == TOKEN_SESSION_SERVER {
+            let mut new_clients = Vec::new();
+            if let Some(s) = &mut self.session {
+                while let Some(c) = s.accept() {
+                    new_clients.push(c);
+                }
+            }
+            for c in new_clients {
+                self.stats.client_connections += 1;
+                self.add(c)?;
+            }
+        } else if token_event == TOKEN_CTL_SERVER {
+            if let Some(c) = &mut self.ctl {
+                c.accept(&mut self.poll)?;
+            }
+        } else if let Some(c) = &mut self.ctl
+            && c.owns(token_event)
+        {
+            for line in c.read(token_event) {
+                let result = self.ctl_command(&line);
+                if let Some(c) = &mut self.ctl {
+                    c.reply(token_event, result);
+                }
+            }
+            if let Some(c) = &mut self.ctl {
+                c.finish(&mut self.poll, token_event);
+            }
+        } else if token_event == TOKEN_SIGNAL {
+            for signal in self.signals.pending() {
+                if signal == SIGWINCH {
+                    self.handle_resize();
+                    continue;
+                }
+                if signal == SIGUSR1 {
+                    self.toggle_announce();
+                    continue;
+                }
+                if signal == SIGUSR2 {
+                    self.toggle_log_level();
+                    continue;
+                }
+                if signal == SIGTSTP {
+                    info!("Suspending");
+                    for instance in self.instances.values_mut() {
+                        instance.suspend();
+                    }
+                    suspend();
+                    continue;
+                }
+                if signal == SIGCONT {
+                    info!("Continuing");
+                    resume();
+                    for instance in self.instances.values_mut() {
+                        instance.redraw();
+                    }
+                    continue;
+                }
+                info!("Received signal {}, initiating graceful shutdown", signal);
+                self.quit_requested = true;
+            }
+        } else {
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    #[cfg(windows)]
+    fn handle_unix_event(&mut self, _: Token) -> Result<bool> {
+        Ok(false)
+    }
+
+    pub fn is_quit_requested(&self) -> bool {
+        self.quit_requested
+    }
+
+    /// Disconnect device `idx`; check_device() connects it again.
+    fn reset_device(&mut self, idx: usize) {
+        let slot = &mut self.devices[idx];
+        slot.device.disconnect(&mut self.poll);
+        self.stats.device_down(clock::now());
+        // Keep write_blocked set — clients stay blocked until the device
+        // reconnects and can accept data again.
+        // Discard pending data — the device connection is gone.
+        slot.pending_write.clear();
+        slot.queued.clear();
+        slot.queued_len = 0;
+        slot.coalesce_until = None;
+    }
+
+    /// Handle a pending disconnect of device `idx` and try to (re)connect it.
+    fn check_device(&mut self, idx: usize) {
+        if self.devices[idx].released {
+            return;
+        }
+        if self.devices[idx].device.disconnect_needed() {
+            self.reset_device(idx);
+        }
+        let slot = &mut self.devices[idx];
+
+        // This will ensure devices are re-connected. If a device cannot be connected right
+        // away, then print a message to warn the user that nothing is connected.
+        // If a device is dis-connected at a later point, then a message will be printed when
+        // disconnected.
+        // Always print once connected.
+        if slot.device.connected() {
+            return;
+        }
+        let addr = slot.device.addr_as_string();
+        let mut auto_capture = None;
+        let status_msg = match slot.device.connect(&mut self.poll, slot.token) {
+            Ok(()) => {
+                if let Some(dir) = &self.capture_auto {
+                    auto_capture = Some(capture::auto_path(
+                        dir,
+                        &slot.label,
+                        &chrono::Local::now(),
+                        self.capture_format,
+                        self.capture_compress,
+                    ));
+                }
+                slot.write_blocked = false;
+                slot.at_line_start = true;
+                slot.last_activity = clock::now();
+                slot.last_rx = slot.last_activity;
+                slot.silence_fired = false;
+                if let Some(h) = &mut slot.health {
+                    h.reset(slot.last_rx);
+                }
+                self.stats.device_up(clock::now(), slot.ever_connected);
+                slot.ever_connected = true;
+                info!(
+                    event = "device_connect",
+                    device = addr.as_str(),
+                    reconnects = self.stats.device_reconnects;
+                    "Device connected: {}", addr
+                );
+                if let Some(n) = &mut self.notifier {
+                    n.device_connected(&slot.label, &format!("{}: Connected", addr));
+                }
+                if let Some(e) = &mut self.events {
+                    e.connect(&slot.label);
+                }
+                if let Some((cols, rows)) = terminal_size() {
+                    slot.device.resize(cols, rows);
+                }
+                slot.device.connected_announcement()
+            }
+
+            Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                // Connection in progress - silently wait
+                None
+            }
+
+            Err(e) => Some(format!("{}: {}", addr, e)),
+        };
+
+        if let Some(msg) = status_msg
+            && Some(&msg) != slot.last_status_msg.as_ref()
+        {
+            self.device_status(idx, msg);
+        }
+
+        if let Some(path) = auto_capture {
+            let msg = self.start_capture(&path);
+            info!("{}", msg);
+            self.device_announce(idx, &msg);
+        }
+
+        #[cfg(target_os = "linux")]
+        if self.devices[idx].device.hotplug() && !self.devices[idx].device.connected() {
+            self.watch_hotplug();
+        }
+    }
+
+    /// Be woken when device nodes appear, for hotplug devices that are not
+    /// there. Without it they are retried like other devices.
+    #[cfg(target_os = "linux")]
+    fn watch_hotplug(&mut self) {
+        if self.hotplug.is_some() || self.hotplug_failed {
+            return;
+        }
+        let hotplug = Hotplug::new().and_then(|mut h| {
+            h.register(self.poll.registry(), TOKEN_HOTPLUG)?;
+            Ok(h)
+        });
+        match hotplug {
+            Ok(h) => self.hotplug = Some(h),
+            Err(e) => {
+                warn!("Hotplug: {}, devices are retried instead", e);
+                self.hotplug_failed = true;
+            }
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn hotplug_watched(&self) -> bool {
+        self.hotplug.is_some()
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn hotplug_watched(&self) -> bool {
+        false
+    }
+
+    /// How soon device `slot` is tried again while it is not connected
+    fn reconnect_interval(&self, slot: &DeviceSlot) -> Duration {
+        match slot.device.hotplug() && self.hotplug_watched() {
+            true => HOTPLUG_RETRY_INTERVAL,
+            false => RECONNECT_INTERVAL,
+        }
+    }
+
+    /// How long to sleep until a device is to be reconnected, a coalescing
+    /// window ends, a capture frame is due or an instance has a timeout.
+    /// None: until the next event.
+    fn poll_timeout(&self) -> Option<Duration> {
+        let now = clock::now();
+        let reconnect = self
+            .devices
+            .iter()
+            .filter(|slot| !slot.released)
+            .filter(|slot| !slot.device.connected() || slot.device.disconnect_needed())
+            .map(|slot| now + self.reconnect_interval(slot))
+            .min();
+        self.devices
+            .iter()
+            .flat_map(|slot| {
+                [
+                    slot.device.next_tick(),
+                    slot.coalesce_until,
+                    slot.frame_until,
+                ]
+            })
+            .flatten()
+            .chain(self.instances.values().filter_map(|c| c.next_tick()))
+            .chain(
+                self.client_keybinds
+                    .values()
+                    .filter_map(|k| k.next_timeout()),
+            )
+            .chain(self.capture.as_ref().and_then(|c| c.next_flush()))
+            .chain(self.capture_db.as_ref().and_then(|c| c.next_flush()))
+            .chain(self.mdns.as_ref().and_then(|m| m.next_tick()))
+            .chain((0..self.devices.len()).filter_map(|idx| self.keepalive_due(idx)))
+            .chain((0..self.devices.len()).filter_map(|idx| self.silence_due(idx)))
+            .chain((0..self.devices.len()).filter_map(|idx| self.health_due(idx)))
+            .chain(self.latency.as_ref().map(|(_, _, l)| l.due()))
+            .chain(self.priority_until)
+            .chain(self.power.as_ref().and_then(|p| p.next_tick()))
+            .chain(reconnect)
+            .min()
+            .map(|t| t.saturating_duration_since(now))
+    }
+
+    /// True when nothing is left to write to a device or a client.
+    fn drained(&self) -> bool {
+        self.devices.iter().all(|slot| {
+            !slot.device.connected() || (slot.pending_write.is_empty() && slot.queued.is_empty())
+        }) && self
+            .instances
+            .values()
+            .all(|client| !client.write_pending())
+    }
+
+    /// Quitting: say goodbye, and write what is still queued until it is
+    /// gone or the drain timeout is over. Input is no longer read; another
+    /// SIGINT or SIGTERM quits at once.
+    fn drain(&mut self, events: &mut Events) {
+        self.flush_frames();
+        self.announce_to(None, "Shutting down");
+        if self.drain_timeout.is_zero() {
+            return;
+        }
+        let deadline = clock::now() + self.drain_timeout;
+        loop {
+            for idx in 0..self.devices.len() {
+                if self.devices[idx].device.connected() {
+                    self.flush_queued(idx);
+                }
+            }
+            for client in self.instances.values_mut() {
+                client.flush();
+            }
+            if self.drained() {
+                return;
+            }
+            let now = clock::now();
+            if now >= deadline {
+                let bytes: usize = self
+                    .devices
+                    .iter()
+                    .map(|slot| slot.pending_write.len() + slot.queued_len)
+                    .sum();
+                warn!(
+                    "Drain timeout: {} byte(s) for the devices and the output of slow clients dropped",
+                    bytes
+                );
+                return;
+            }
+            match self.poll.poll(events, Some(deadline - now)) {
+                Ok(()) => {}
+                Err(ref e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => {
+                    error!("Drain: {}", e);
+                    return;
+                }
+            }
+            #[cfg(unix)]
+            if events.iter().any(|e| e.token() == TOKEN_SIGNAL)
+                && self.signals.pending().any(|s| s == SIGINT || s == SIGTERM)
+            {
+                info!("Drain interrupted");
+                return;
+            }
+        }
+    }
+
+    pub fn run(&mut self) -> std::io::Result<()> {
+        let mut events = Events::with_capacity(128);
+        while !self.turn(&mut events, true)? {}
+        match self.take_exit_error() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    /// One turn of the event loop: wait for events until the next timer is
+    /// due (or not at all without `wait`), handle them and the timers.
+    /// True when the hub has quit, see `take_exit_error` for how.
+    pub(crate) fn turn(&mut self, events: &mut Events, wait: bool) -> std::io::Result<bool> {
+        for idx in 0..self.devices.len() {
+            self.check_device(idx);
+        }
+
+        let timeout = if wait {
+            self.poll_timeout()
+        } else {
+            Some(Duration::ZERO)
+        };
+        match self.poll.poll(events, timeout) {
+            Ok(()) => {}
+            Err(ref e) if e.kind() == std::io::ErrorKind::Interrupted => {
+                // EINTR - signal received, loop will continue and signal
+                // will be processed on next poll iteration
+            }
+            Err(e) => return Err(e),
+        }
+
+        for event in events.iter() {
+            self.handle_event(event)?;
+        }
+        trace!("Finished processing {} events", events.iter().count());
+
+        self.flush_coalesced();
+        self.flush_frames();
+        self.send_keepalives();
+        self.check_silence();
+        self.check_health();
+        self.check_latency();
+        self.release_priority();
+        self.check_power();
+
+        // Let devices run their timers (e.g. failover probing)
+        for idx in 0..self.devices.len() {
+            let device = &mut self.devices[idx].device;
+            match device.tick() {
+                Ok(IoResult::Data(buf)) => self.device_output(idx, buf),
+                Ok(_) => {}
+                Err(e) => error!("{}: tick: {}", device.addr_as_string(), e),
+            }
+            while let Some(msg) = self.devices[idx].device.take_announcement() {
+                self.device_announce(idx, &msg);
+            }
+        }
+
+        // Process timeouts for all instances (e.g., keybind timeouts in Console)
+        let results: Vec<_> = self
+            .instances
+            .iter_mut()
+            .filter_map(|(&t, c)| c.tick().ok().map(|r| (t, r)))
+            .collect();
+        for (token, result) in results {
+            self.handle_read_result(token, result);
+        }
+        if let Some(c) = &mut self.capture {
+            c.tick();
+        }
+        if let Some(c) = &mut self.capture_db {
+            c.tick();
+        }
+        if let Some(m) = &mut self.mdns {
+            m.tick();
+        }
+
+        // A prefix key of a TCP client that was not followed by a command
+        let results: Vec<_> = self
+            .client_keybinds
+            .iter_mut()
+            .map(|(&t, k)| (t, k.tick()))
+            .filter(|(_, r)| !r.is_empty())
+            .collect();
+        for (token, results) in results {
+            self.handle_client_keybinds(token, results);
+        }
+        trace!("Finished processing timeouts");
+
+        // Check if quit was requested
+        trace!("Checking quit_requested: {}", self.quit_requested);
+        if self.quit_requested {
+            info!("Quit requested - exiting hub.run()");
+            self.drain(events);
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    /// Why the hub quit: Err for the `exit` of the silence watchdog and the
+    /// failure pattern.
+    pub(crate) fn take_exit_error(&mut self) -> Option<std::io::Error> {
+        self.exit_error.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_device_label() {
+        assert_eq!(device_label("/dev/ttyUSB0"), "usb0");
+        assert_eq!(device_label("/dev/serial/by-id/board-a"), "board-a");
+        assert_eq!(device_label("192.168.1.10:4000"), "192.168.1.10:4000");
+        assert_eq!(device_label("Echo"), "echo");
+    }
+
+    #[test]
+    fn test_prefix_lines() {
+        let mut at_line_start = true;
+        assert_eq!(
+            prefix_lines("usb0", &mut at_line_start, b"a\r\nb"),
+            b"[usb0] a\r\n[usb0] b"
+        );
+        assert!(!at_line_start);
+        assert_eq!(prefix_lines("usb0", &mut at_line_start, b"c\n"), b"c\n");
+        assert!(at_line_start);
+    }
+}
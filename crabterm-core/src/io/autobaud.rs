@@ -0,0 +1,156 @@
+//! Baudrate detection (`--baudrate auto`): try the common rates in turn
+//! until what the device sends looks like text, or contains an expected
+//! pattern.
+
+use std::time::{Duration, Instant};
+
+/// Rates tried, most common first
+pub const RATES: &[u32] = &[
+    115200, 9600, 57600, 38400, 19200, 230400, 460800, 921600, 4800, 2400, 1200,
+];
+
+/// Bytes needed to judge a rate
+const SAMPLE: usize = 64;
+/// Bytes looked through for the pattern before giving up on a rate
+const MAX_SAMPLE: usize = 1024;
+/// Share of the sample that must be printable text
+const MIN_PRINTABLE: f64 = 0.95;
+/// Time a rate is given to produce a sample
+const DWELL: Duration = Duration::from_secs(2);
+
+/// What the serial device should do next
+#[derive(Debug, PartialEq, Eq)]
+pub enum Step {
+    /// Keep listening at the current rate
+    Wait,
+    /// Switch to the next rate
+    Try(u32),
+    /// The current rate is right
+    Lock(u32),
+}
+
+#[derive(Debug)]
+pub struct Autobaud {
+    pattern: Option<Vec<u8>>,
+    idx: usize,
+    sample: Vec<u8>,
+    since: Instant,
+}
+
+impl Autobaud {
+    /// Without a pattern a rate is taken when its output is text.
+    pub fn new(pattern: Option<Vec<u8>>, now: Instant) -> Self {
+        Autobaud {
+            pattern: pattern.filter(|p| !p.is_empty()),
+            idx: 0,
+            sample: Vec::new(),
+            since: now,
+        }
+    }
+
+    /// The rate being tried.
+    pub fn rate(&self) -> u32 {
+        RATES[self.idx]
+    }
+
+    /// Start over at the current rate, e.g. after a reconnect.
+    pub fn restart(&mut self, now: Instant) {
+        self.sample.clear();
+        self.since = now;
+    }
+
+    /// Output read at the current rate.
+    pub fn feed(&mut self, buf: &[u8], now: Instant) -> Step {
+        self.sample.extend_from_slice(buf);
+        let verdict = match &self.pattern {
+            Some(p) if self.sample.windows(p.len()).any(|w| w == p.as_slice()) => Some(true),
+            Some(_) => (self.sample.len() >= MAX_SAMPLE).then_some(false),
+            None => (self.sample.len() >= SAMPLE).then(|| printable(&self.sample) >= MIN_PRINTABLE),
+        };
+        match verdict {
+            Some(true) => Step::Lock(self.rate()),
+            Some(false) => self.next(now),
+            None => Step::Wait,
+        }
+    }
+
+    /// The output read at the rate that was locked onto, to be shown.
+    pub fn take_sample(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.sample)
+    }
+
+    /// When the current rate has had its time.
+    pub fn deadline(&self) -> Instant {
+        self.since + DWELL
+    }
+
+    pub fn tick(&mut self, now: Instant) -> Step {
+        if now >= self.deadline() {
+            self.next(now)
+        } else {
+            Step::Wait
+        }
+    }
+
+    fn next(&mut self, now: Instant) -> Step {
+        self.idx = (self.idx + 1) % RATES.len();
+        self.restart(now);
+        Step::Try(self.rate())
+    }
+}
+
+/// Share of `buf` that is printable ASCII or common whitespace.
+fn printable(buf: &[u8]) -> f64 {
+    let n = buf
+        .iter()
+        .filter(|&&b| matches!(b, 0x20..=0x7e | b'\r' | b'\n' | b'\t'))
+        .count();
+    n as f64 / buf.len() as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text_locks() {
+        let now = Instant::now();
+        let mut a = Autobaud::new(None, now);
+        assert_eq!(a.rate(), 115200);
+        assert_eq!(
+            a.feed(&[0xf0, 0x80, 0x00, 0xfe].repeat(16), now),
+            Step::Try(9600)
+        );
+        assert_eq!(a.feed(b"U-Boot 2024.01 (Jan 1 2024)\r\n", now), Step::Wait);
+        assert_eq!(
+            a.feed(b"DRAM:  512 MiB\r\nCore:  42 devices\r\n", now),
+            Step::Lock(9600)
+        );
+        assert!(a.take_sample().starts_with(b"U-Boot"));
+    }
+
+    #[test]
+    fn test_pattern() {
+        let now = Instant::now();
+        let mut a = Autobaud::new(Some(b"login:".to_vec()), now);
+        assert_eq!(a.feed(b"Welcome\r\nlog", now), Step::Wait);
+        assert_eq!(a.feed(b"in: ", now), Step::Lock(115200));
+
+        let mut a = Autobaud::new(Some(b"login:".to_vec()), now);
+        assert_eq!(a.feed(&[b'x'; MAX_SAMPLE], now), Step::Try(9600));
+    }
+
+    #[test]
+    fn test_silent_rate() {
+        let now = Instant::now();
+        let mut a = Autobaud::new(None, now);
+        assert_eq!(a.tick(now + Duration::from_millis(100)), Step::Wait);
+        assert_eq!(a.tick(a.deadline()), Step::Try(9600));
+
+        // Wraps around after the last rate
+        for _ in 2..RATES.len() {
+            a.tick(a.deadline());
+        }
+        assert_eq!(a.tick(a.deadline()), Step::Try(115200));
+    }
+}
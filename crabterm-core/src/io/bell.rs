@@ -0,0 +1,105 @@
+//! `bell-on` rules: ring the terminal bell when a pattern appears in the
+//! device output, e.g. when a long flash finally prints "done".
+
+use crate::keybind::config::BellRule;
+
+const BEL: &[u8] = b"\x07";
+
+/// Watches device output for the patterns of the `bell-on` rules. A pattern
+/// is found even when it is split over several writes.
+#[derive(Debug, Default)]
+pub struct Bells {
+    rules: Vec<BellRule>,
+    /// The end of the output checked so far, too short to hold a pattern
+    tail: Vec<u8>,
+    window: Vec<u8>,
+}
+
+impl Bells {
+    pub fn new(rules: Vec<BellRule>) -> Self {
+        Bells {
+            rules,
+            ..Default::default()
+        }
+    }
+
+    /// Check the next device output. Returns what to write to the
+    /// terminal: a bell, and an OSC 9 notification for rules that ask for
+    /// one. Each rule fires once per call however often it matches.
+    pub fn check(&mut self, buf: &[u8]) -> Vec<u8> {
+        let mut alert = Vec::new();
+        if self.rules.is_empty() {
+            return alert;
+        }
+        self.window.clear();
+        self.window.extend_from_slice(&self.tail);
+        self.window.extend_from_slice(buf);
+
+        // The tail is shorter than any pattern, so a match always includes
+        // new output
+        for rule in &self.rules {
+            let pattern = rule.pattern.as_bytes();
+            if !self.window.windows(pattern.len()).any(|w| w == pattern) {
+                continue;
+            }
+            if alert.is_empty() {
+                alert.extend_from_slice(BEL);
+            }
+            if rule.notify {
+                alert
+                    .extend_from_slice(format!("\x1b]9;crabterm: {}\x07", rule.pattern).as_bytes());
+            }
+        }
+
+        let keep = self
+            .rules
+            .iter()
+            .map(|r| r.pattern.len() - 1)
+            .max()
+            .unwrap_or(0)
+            .min(self.window.len());
+        self.tail.clear();
+        self.tail
+            .extend_from_slice(&self.window[self.window.len() - keep..]);
+        alert
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(pattern: &str, notify: bool) -> BellRule {
+        BellRule {
+            pattern: pattern.to_string(),
+            notify,
+        }
+    }
+
+    #[test]
+    fn test_bell() {
+        let mut b = Bells::new(vec![rule("done", false)]);
+        assert_eq!(b.check(b"flashing...\r\n"), b"");
+        assert_eq!(b.check(b"done\r\n"), BEL);
+        assert_eq!(b.check(b"done done\r\n"), BEL);
+        // Not again for the tail of the previous output
+        assert_eq!(b.check(b"x"), b"");
+    }
+
+    #[test]
+    fn test_split_pattern() {
+        let mut b = Bells::new(vec![rule("done", false)]);
+        assert_eq!(b.check(b"all do"), b"");
+        assert_eq!(b.check(b"n"), b"");
+        assert_eq!(b.check(b"e"), BEL);
+    }
+
+    #[test]
+    fn test_notify() {
+        let mut b = Bells::new(vec![rule("login:", true), rule("Oops", false)]);
+        assert_eq!(
+            b.check(b"Oops\r\nlogin: "),
+            b"\x07\x1b]9;crabterm: login:\x07".to_vec()
+        );
+    }
+}
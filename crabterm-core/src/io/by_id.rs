@@ -0,0 +1,190 @@
+//! Stable names of USB serial adapters.
+//!
+//! A replugged adapter may come back as another `/dev/ttyUSBn`. udev keeps a
+//! link per adapter in `/dev/serial/by-id`, named after its vendor, product
+//! and serial number, which always points to its current node. On Linux,
+//! [`Hotplug`] tells the hub when nodes and links appear, so an adapter is
+//! reconnected when it is plugged in rather than on the next retry.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[cfg(target_os = "linux")]
+pub use self::linux::Hotplug;
+
+/// Where udev keeps the links
+pub const DIR: &str = "/dev/serial/by-id";
+
+/// The link in `dir` that points to the node `path` (or is `path`).
+pub fn find(dir: &Path, path: &Path) -> Option<PathBuf> {
+    let node = fs::canonicalize(path).ok()?;
+    let mut links: Vec<PathBuf> = fs::read_dir(dir)
+        .ok()?
+        .flatten()
+        .map(|e| e.path())
+        .collect();
+    links.sort();
+    links
+        .into_iter()
+        .find(|link| fs::canonicalize(link).is_ok_and(|n| n == node))
+}
+
+/// The node `link` points to now, None while the adapter is unplugged.
+pub fn resolve(link: &Path) -> Option<PathBuf> {
+    fs::canonicalize(link).ok()
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use super::*;
+    use crate::poll::{Fd, Registry};
+    use mio::{Interest, Token};
+    use std::ffi::CString;
+    use std::io::{Error, Result};
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    use std::os::unix::ffi::OsStrExt;
+
+    /// inotify on the directories of device nodes and their by-id links.
+    /// Readable when something was created in them, or its permissions were
+    /// set, which udev does after the node is there.
+    pub struct Hotplug {
+        fd: OwnedFd,
+        /// The first is always there; the others, the directories of the
+        /// links, udev creates with the first adapter and removes with the
+        /// last
+        dirs: Vec<PathBuf>,
+    }
+
+    impl Hotplug {
+        /// Watch /dev and /dev/serial/by-id
+        pub fn new() -> Result<Self> {
+            Self::watching(["/dev", "/dev/serial", DIR].map(PathBuf::from).to_vec())
+        }
+
+        pub(super) fn watching(dirs: Vec<PathBuf>) -> Result<Self> {
+            // SAFETY: no pointers; the descriptor is owned from here on
+            let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+            if fd < 0 {
+                return Err(Error::last_os_error());
+            }
+            let hotplug = Hotplug {
+                fd: unsafe { OwnedFd::from_raw_fd(fd) },
+                dirs,
+            };
+            hotplug.watch(&hotplug.dirs[0])?;
+            hotplug.watch_links();
+            Ok(hotplug)
+        }
+
+        fn watch(&self, dir: &Path) -> Result<()> {
+            let path = CString::new(dir.as_os_str().as_bytes()).map_err(Error::other)?;
+            let mask = libc::IN_CREATE | libc::IN_MOVED_TO | libc::IN_ATTRIB | libc::IN_ONLYDIR;
+            // SAFETY: a valid descriptor and string; watching a directory
+            // again only updates its mask
+            if unsafe { libc::inotify_add_watch(self.fd.as_raw_fd(), path.as_ptr(), mask) } < 0 {
+                return Err(Error::last_os_error());
+            }
+            Ok(())
+        }
+
+        /// Watch the directories of the links that are there now
+        fn watch_links(&self) {
+            for dir in &self.dirs[1..] {
+                let _ = self.watch(dir);
+            }
+        }
+
+        pub fn register(&mut self, registry: &Registry, token: Token) -> Result<()> {
+            registry.register(&mut Fd(self.fd.as_raw_fd()), token, Interest::READABLE)
+        }
+
+        /// Read the pending events, which only say to try the devices
+        /// again, and watch the directories of links that were created.
+        pub fn drain(&mut self) {
+            let mut buf = [0u8; 4096];
+            loop {
+                // SAFETY: reads into a buffer of the given size
+                let n =
+                    unsafe { libc::read(self.fd.as_raw_fd(), buf.as_mut_ptr().cast(), buf.len()) };
+                if n <= 0 {
+                    break;
+                }
+            }
+            self.watch_links();
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    #[test]
+    fn test_find() {
+        let tmp = std::env::temp_dir().join(format!("crabterm-by-id-{}", std::process::id()));
+        let dir = tmp.join("by-id");
+        fs::create_dir_all(&dir).unwrap();
+        let usb0 = tmp.join("ttyUSB0");
+        let usb1 = tmp.join("ttyUSB1");
+        fs::write(&usb0, "").unwrap();
+        fs::write(&usb1, "").unwrap();
+        let link = dir.join("usb-FTDI_FT232R_A1B2C3-if00-port0");
+        symlink(&usb1, &link).unwrap();
+
+        assert_eq!(find(&dir, &usb1), Some(link.clone()));
+        assert_eq!(find(&dir, &link), Some(link.clone()));
+        assert_eq!(find(&dir, &usb0), None);
+        assert_eq!(find(&tmp.join("missing"), &usb1), None);
+
+        // Replugged as another node
+        fs::remove_file(&link).unwrap();
+        assert_eq!(resolve(&link), None);
+        symlink(&usb0, &link).unwrap();
+        assert_eq!(resolve(&link), Some(fs::canonicalize(&usb0).unwrap()));
+
+        fs::remove_dir_all(&tmp).unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_hotplug() {
+        use crate::poll::{Events, Poll};
+        use mio::Token;
+        use std::time::Duration;
+
+        let tmp = std::env::temp_dir().join(format!("crabterm-hotplug-{}", std::process::id()));
+        let serial = tmp.join("serial");
+        let dir = serial.join("by-id");
+        fs::create_dir_all(&tmp).unwrap();
+        let mut hotplug =
+            Hotplug::watching(vec![tmp.clone(), serial.clone(), dir.clone()]).unwrap();
+        let mut poll = Poll::new().unwrap();
+        hotplug.register(poll.registry(), Token(1)).unwrap();
+        let mut events = Events::with_capacity(4);
+        let mut woken = |hotplug: &mut Hotplug| {
+            poll.poll(&mut events, Some(Duration::from_millis(200)))
+                .unwrap();
+            let woken = !events.is_empty();
+            hotplug.drain();
+            woken
+        };
+
+        fs::write(tmp.join("ttyUSB0"), "").unwrap();
+        assert!(woken(&mut hotplug));
+        // The directories of the links are watched once they are there
+        fs::create_dir(&serial).unwrap();
+        assert!(woken(&mut hotplug));
+        fs::create_dir(&dir).unwrap();
+        assert!(woken(&mut hotplug));
+        symlink(
+            tmp.join("ttyUSB0"),
+            dir.join("usb-FTDI_FT232R_A1B2C3-if00-port0"),
+        )
+        .unwrap();
+        assert!(woken(&mut hotplug));
+        assert!(!woken(&mut hotplug));
+
+        fs::remove_dir_all(&tmp).unwrap();
+    }
+}
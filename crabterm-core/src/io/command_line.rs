@@ -0,0 +1,358 @@
+//! Line editor for the console's prompts and line-edit mode.
+
+/// Result of feeding input to the [`CommandLine`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum CommandLineEvent {
+    /// Still editing
+    Pending,
+    /// Escape, Ctrl+C or backspace on an empty line
+    Cancel,
+    /// Enter was pressed
+    Submit(String),
+}
+
+/// Ctrl+R search through the history
+#[derive(Debug)]
+struct Search {
+    query: Vec<u8>,
+    /// Index of the matching history entry
+    found: Option<usize>,
+}
+
+/// A one-line editor drawn on the bottom row of the terminal.
+#[derive(Debug)]
+pub struct CommandLine {
+    prompt: &'static str,
+    line: Vec<u8>,
+    /// Position while browsing the history with Up/Down
+    history_pos: Option<usize>,
+    /// The line as it was before browsing the history
+    saved_line: Vec<u8>,
+    search: Option<Search>,
+}
+
+impl Default for CommandLine {
+    fn default() -> Self {
+        Self::with_prompt(":")
+    }
+}
+
+impl CommandLine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// An editor showing `prompt` instead of ":"
+    pub fn with_prompt(prompt: &'static str) -> Self {
+        CommandLine {
+            prompt,
+            line: Vec::new(),
+            history_pos: None,
+            saved_line: Vec::new(),
+            search: None,
+        }
+    }
+
+    /// The line typed so far
+    pub fn is_empty(&self) -> bool {
+        self.line.is_empty() && self.search.is_none()
+    }
+
+    /// Consume input until the line is submitted or cancelled. Returns the
+    /// event and how many bytes of `input` were used; the rest belongs to
+    /// whatever comes after the prompt.
+    pub fn feed(&mut self, input: &[u8]) -> (CommandLineEvent, usize) {
+        self.feed_with_history(input, &[])
+    }
+
+    /// Like [`Self::feed`], with Up/Down and Ctrl+R recalling lines from
+    /// `history` (oldest first).
+    pub fn feed_with_history(
+        &mut self,
+        input: &[u8],
+        history: &[String],
+    ) -> (CommandLineEvent, usize) {
+        let mut i = 0;
+        while i < input.len() {
+            let b = input[i];
+            i += 1;
+            // Escape sequences: Up/Down browse the history, others (arrow
+            // keys etc.) are ignored
+            if b == 0x1b && i < input.len() && matches!(input[i], b'[' | b'O') {
+                i += 1;
+                while i < input.len() && !(0x40..=0x7e).contains(&input[i]) {
+                    i += 1;
+                }
+                let key = input.get(i).copied();
+                i += 1;
+                self.end_search(history, true);
+                match key {
+                    Some(b'A') => self.history_up(history),
+                    Some(b'B') => self.history_down(history),
+                    _ => {}
+                }
+                continue;
+            }
+            if self.search.is_some() {
+                match b {
+                    b'\r' | b'\n' => {
+                        self.end_search(history, true);
+                        return (self.submit(), i);
+                    }
+                    // Escape keeps the match for editing, Ctrl+G drops it
+                    0x1b => self.end_search(history, true),
+                    0x07 => self.end_search(history, false),
+                    0x03 => return (CommandLineEvent::Cancel, i),
+                    0x12 => self.search_next(history, true),
+                    0x7f | 0x08 => {
+                        if let Some(search) = &mut self.search {
+                            pop_char(&mut search.query);
+                        }
+                        self.search_next(history, false);
+                    }
+                    b if b >= 0x20 => {
+                        if let Some(search) = &mut self.search {
+                            search.query.push(b);
+                        }
+                        self.search_next(history, false);
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+            match b {
+                b'\r' | b'\n' => return (self.submit(), i),
+                // Escape on its own cancels
+                0x1b | 0x03 => return (CommandLineEvent::Cancel, i),
+                0x7f | 0x08 => {
+                    if self.line.is_empty() {
+                        return (CommandLineEvent::Cancel, i);
+                    }
+                    pop_char(&mut self.line);
+                }
+                // Ctrl+U
+                0x15 => self.line.clear(),
+                // Ctrl+R
+                0x12 if !history.is_empty() => {
+                    self.search = Some(Search {
+                        query: Vec::new(),
+                        found: None,
+                    })
+                }
+                b if b >= 0x20 => self.line.push(b),
+                _ => {}
+            }
+        }
+        (CommandLineEvent::Pending, input.len().min(i))
+    }
+
+    fn submit(&self) -> CommandLineEvent {
+        CommandLineEvent::Submit(String::from_utf8_lossy(&self.line).into_owned())
+    }
+
+    fn history_up(&mut self, history: &[String]) {
+        let pos = match self.history_pos {
+            _ if history.is_empty() => return,
+            None => {
+                self.saved_line = self.line.clone();
+                history.len() - 1
+            }
+            Some(pos) => pos.saturating_sub(1),
+        };
+        self.history_pos = Some(pos);
+        self.line = history[pos].as_bytes().to_vec();
+    }
+
+    fn history_down(&mut self, history: &[String]) {
+        match self.history_pos {
+            Some(pos) if pos + 1 < history.len() => {
+                self.history_pos = Some(pos + 1);
+                self.line = history[pos + 1].as_bytes().to_vec();
+            }
+            Some(_) => {
+                self.history_pos = None;
+                self.line = std::mem::take(&mut self.saved_line);
+            }
+            None => {}
+        }
+    }
+
+    /// Find the newest entry containing the query, starting at the current
+    /// match, or before it when `older`.
+    fn search_next(&mut self, history: &[String], older: bool) {
+        let Some(search) = &mut self.search else {
+            return;
+        };
+        let start = match search.found {
+            Some(pos) if older => pos,
+            Some(pos) => pos + 1,
+            None => history.len(),
+        };
+        let query = String::from_utf8_lossy(&search.query);
+        if let Some(pos) = history[..start.min(history.len())]
+            .iter()
+            .rposition(|entry| entry.contains(query.as_ref()))
+        {
+            search.found = Some(pos);
+        } else if !older {
+            search.found = None;
+        }
+    }
+
+    /// Leave search mode, taking the match as the line when `accept`.
+    fn end_search(&mut self, history: &[String], accept: bool) {
+        if let Some(search) = self.search.take()
+            && accept
+            && let Some(pos) = search.found
+        {
+            self.history_pos = Some(pos);
+            self.saved_line = std::mem::take(&mut self.line);
+            self.line = history[pos].as_bytes().to_vec();
+        }
+    }
+
+    /// Draw the prompt on the bottom row, saving the cursor position.
+    pub fn render(&self) -> Vec<u8> {
+        self.render_with_history(&[])
+    }
+
+    /// Like [`Self::render`], showing the Ctrl+R search state.
+    pub fn render_with_history(&self, history: &[String]) -> Vec<u8> {
+        let mut out = b"\x1b7\x1b[999;1H\x1b[2K".to_vec();
+        match &self.search {
+            Some(search) => {
+                let failing = if search.found.is_none() && !search.query.is_empty() {
+                    "failing "
+                } else {
+                    ""
+                };
+                out.extend_from_slice(format!("({}reverse-i-search)`", failing).as_bytes());
+                out.extend_from_slice(&search.query);
+                out.extend_from_slice(b"': ");
+                if let Some(pos) = search.found {
+                    out.extend_from_slice(history[pos].as_bytes());
+                }
+            }
+            None => {
+                out.extend_from_slice(self.prompt.as_bytes());
+                out.extend_from_slice(&self.line);
+            }
+        }
+        out
+    }
+
+    /// Clear the prompt and restore the cursor saved by [`Self::render`].
+    pub fn clear() -> &'static [u8] {
+        b"\x1b[999;1H\x1b[2K\x1b8"
+    }
+}
+
+/// Remove the last UTF-8 character
+fn pop_char(line: &mut Vec<u8>) {
+    while let Some(c) = line.pop() {
+        if c & 0xc0 != 0x80 {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_submit() {
+        let mut cl = CommandLine::new();
+        assert_eq!(cl.feed(b"bau"), (CommandLineEvent::Pending, 3));
+        assert_eq!(
+            cl.feed(b"d 9600\rabc"),
+            (CommandLineEvent::Submit("baud 9600".to_string()), 7)
+        );
+    }
+
+    #[test]
+    fn test_editing() {
+        let mut cl = CommandLine::new();
+        cl.feed("stx\x7fats ø\x7f".as_bytes());
+        assert_eq!(cl.render(), b"\x1b7\x1b[999;1H\x1b[2K:stats ");
+        cl.feed(b"\x15st\x1b[Dat\x1b[Cs");
+        assert_eq!(
+            cl.feed(b"\n").0,
+            CommandLineEvent::Submit("stats".to_string())
+        );
+    }
+
+    #[test]
+    fn test_prompt() {
+        let mut cl = CommandLine::with_prompt("hex> ");
+        cl.feed(b"1b");
+        assert_eq!(cl.render(), b"\x1b7\x1b[999;1H\x1b[2Khex> 1b");
+    }
+
+    fn history() -> Vec<String> {
+        [
+            "reboot",
+            "printenv",
+            "setenv bootdelay 3",
+            "printenv bootcmd",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect()
+    }
+
+    #[test]
+    fn test_history_browse() {
+        let history = history();
+        let mut cl = CommandLine::new();
+        cl.feed_with_history(b"pr\x1b[A\x1b[A", &history);
+        assert_eq!(cl.line, b"setenv bootdelay 3");
+        // Up stops at the oldest entry
+        cl.feed_with_history(b"\x1b[A\x1b[A\x1b[A", &history);
+        assert_eq!(cl.line, b"reboot");
+        // Down past the newest entry restores the typed line
+        cl.feed_with_history(b"\x1bOB\x1b[B\x1b[B\x1b[B", &history);
+        assert_eq!(cl.line, b"pr");
+        assert_eq!(
+            cl.feed_with_history(b"\x1b[A\r", &history).0,
+            CommandLineEvent::Submit("printenv bootcmd".to_string())
+        );
+    }
+
+    #[test]
+    fn test_history_search() {
+        let history = history();
+        let mut cl = CommandLine::new();
+        cl.feed_with_history(b"\x12print", &history);
+        assert_eq!(
+            cl.render_with_history(&history),
+            b"\x1b7\x1b[999;1H\x1b[2K(reverse-i-search)`print': printenv bootcmd"
+        );
+        // Ctrl+R again finds the next older match
+        cl.feed_with_history(b"\x12", &history);
+        assert!(cl.render_with_history(&history).ends_with(b"': printenv"));
+        cl.feed_with_history(b"x", &history);
+        assert!(
+            cl.render_with_history(&history)
+                .ends_with(b"(failing reverse-i-search)`printx': ")
+        );
+        // Escape keeps the match for editing
+        let mut cl = CommandLine::new();
+        cl.feed_with_history(b"\x12boot\x1b", &history);
+        assert_eq!(cl.line, b"printenv bootcmd");
+        // Enter runs it
+        let mut cl = CommandLine::new();
+        assert_eq!(
+            cl.feed_with_history(b"\x12reb\rnext", &history),
+            (CommandLineEvent::Submit("reboot".to_string()), 5)
+        );
+    }
+
+    #[test]
+    fn test_cancel() {
+        let mut cl = CommandLine::new();
+        assert_eq!(cl.feed(b"ab\x1b"), (CommandLineEvent::Cancel, 3));
+        let mut cl = CommandLine::new();
+        assert_eq!(cl.feed(b"\x7fx"), (CommandLineEvent::Cancel, 1));
+    }
+}
@@ -0,0 +1,867 @@
+use log::{debug, info, warn};
+use mio::Token;
+use std::io::{ErrorKind, Result, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Instant;
+
+use super::bell::Bells;
+use super::command_line::{CommandLine, CommandLineEvent};
+use super::history::{self, History};
+use super::output_buffer::{OutputBuffer, Pushed};
+use super::read_buffer::ReadBuffer;
+use super::scrollback::{self, Scrollback};
+use super::stdio::Stdio;
+use super::tcp_server::{ClientPolicy, SlowClientPolicy};
+use crate::announce::{self, MessageKind};
+use crate::clock;
+use crate::control;
+use crate::hexdump::parse_hex;
+use crate::iofilter::{FilterChain, charmap};
+use crate::keybind::action::Action;
+use crate::keybind::processor::MouseMode;
+use crate::keybind::{KeybindConfig, KeybindProcessor, KeybindResult, parse_command};
+use crate::poll::{Poll, Registry};
+#[cfg(unix)]
+use crate::term::suspend;
+use crate::term::{
+    MOUSE_REPORTING_OFF, MOUSE_REPORTING_ON, disable_raw_mode, enable_raw_mode, osc52_copy,
+    stdin_is_tty, stdout_is_tty,
+};
+use crate::traits::{IoInstance, IoResult};
+
+/// Device output held back while the command prompt is open or output is
+/// paused is limited to this, the oldest output is dropped beyond it.
+const MAX_HELD_OUTPUT: usize = 1024 * 1024;
+
+const HEX_PROMPT: &str = "hex> ";
+const LINE_EDIT_PROMPT: &str = "> ";
+const SEND_LINE_PROMPT: &str = "send> ";
+const NOTE_PROMPT: &str = "note> ";
+
+/// Setting that starts the console in line-edit mode
+pub const SETTING_LINE_EDIT: &str = "line-edit";
+
+/// Setting with what ends the lines of line-edit mode and `send-line`
+pub const SETTING_LINE_TERMINATOR: &str = "line-terminator";
+
+/// Setting with the number of output lines kept for `copy-output` and
+/// `replay-output`
+pub const SETTING_SCROLLBACK: &str = "scrollback-lines";
+
+/// Setting choosing what happens when stdin is closed, see [`EofAction`]
+pub const SETTING_STDIN_EOF: &str = "stdin-eof";
+
+/// What to do when stdin reaches end of file (the terminal was closed or
+/// piped input ended)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EofAction {
+    Quit,
+    /// Drop the console and keep serving the other clients
+    Headless,
+}
+
+impl EofAction {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "quit" => Some(EofAction::Quit),
+            "headless" => Some(EofAction::Headless),
+            _ => None,
+        }
+    }
+}
+
+/// Setting turning off the cursor shape change while the prefix key awaits
+/// the next key
+pub const SETTING_PREFIX_INDICATOR: &str = "prefix-indicator";
+
+/// Cursor shapes (DECSCUSR): a steady underline while the prefix key awaits
+/// the next key, then the terminal's default again
+const PREFIX_CURSOR_ON: &[u8] = b"\x1b[4 q";
+const PREFIX_CURSOR_OFF: &[u8] = b"\x1b[0 q";
+
+/// Status shown on the bottom row while output is paused
+const PAUSED_STATUS: &[u8] = b"\x1b7\x1b[999;1H\x1b[2K-- PAUSED --\x1b8";
+
+/// What the open command line is used for
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Prompt {
+    Command,
+    Hex,
+    LineEdit,
+    /// One line, closed once sent
+    SendLine,
+    /// A note for the capture and the log
+    Note,
+}
+
+pub struct Console {
+    stdio: Stdio,
+    /// Output stdout did not take yet, written on WRITABLE; the slow client
+    /// policy of the TCP clients applies
+    out: OutputBuffer,
+    /// Output is being dropped (drop-data policy), logged once per episode
+    dropping: bool,
+    /// To register stdout for WRITABLE while `out` is not empty
+    registry: Option<(Registry, Token)>,
+    writable_interest: bool,
+    keybind_processor: KeybindProcessor,
+    pending_results: Vec<KeybindResult>,
+    /// The cursor shows that the prefix key was pressed; None when the
+    /// indicator is off
+    prefix_shown: Option<bool>,
+    filter_chain: FilterChain,
+
+    /// The ":" prompt, while open all input goes to it
+    command_line: Option<CommandLine>,
+    prompt: Prompt,
+
+    /// In line-edit mode lines are typed locally and sent on Enter
+    line_edit: bool,
+    /// Sent after each line of line-edit mode and `send-line`
+    line_terminator: Vec<u8>,
+    history: History,
+
+    /// Output held back while the prompt is open or output is paused
+    held_output: Vec<u8>,
+    /// Bytes dropped from `held_output` because it was full
+    dropped_output: usize,
+
+    /// Set by the pause-output action
+    paused: bool,
+
+    /// Filtered output, reused between writes
+    out_buf: Vec<u8>,
+
+    /// Recent output for `copy-output` and `replay-output`
+    scrollback: Scrollback,
+
+    /// `bell-on` rules
+    bells: Bells,
+
+    read_buf: ReadBuffer,
+
+    /// Mouse reporting was turned on by mouse-toggle, and is turned off on
+    /// exit
+    mouse_reporting: bool,
+
+    on_eof: EofAction,
+    /// The device is a crabterm server (`crabterm connect`), remote
+    /// commands are sent to it in-band
+    remote_control: bool,
+    /// stdin reached EOF, the hub removes the console
+    stdin_closed: bool,
+    /// The terminal fell behind under the disconnect policy, the hub
+    /// removes the console
+    stdout_closed: bool,
+}
+
+impl Console {
+    pub fn new(keybind_config: KeybindConfig, filter_chain: FilterChain) -> Result<Self> {
+        // Raw mode only makes sense (and only works) when stdin is a terminal.
+        // With stdin redirected the keybinds still work on whatever bytes
+        // arrive, they just are not delivered key-by-key.
+        if stdin_is_tty() {
+            enable_raw_mode()?;
+        } else {
+            info!("Console: stdin is not a TTY, not entering raw mode");
+        }
+
+        if !stdout_is_tty() {
+            info!("Console: stdout is not a TTY, output is passed through unmodified");
+        }
+
+        let stdio = Stdio::new()?;
+
+        let scrollback_lines = keybind_config
+            .settings
+            .get(SETTING_SCROLLBACK)
+            .and_then(|v| v.as_str())
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(scrollback::DEFAULT_LINES);
+        let line_edit = keybind_config
+            .settings
+            .get(SETTING_LINE_EDIT)
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
+        let line_terminator = keybind_config
+            .settings
+            .get(SETTING_LINE_TERMINATOR)
+            .and_then(|v| v.as_str())
+            .map_or(b"\r".to_vec(), |s| s.as_bytes().to_vec());
+        let history = match keybind_config
+            .settings
+            .get(history::SETTING_FILE)
+            .and_then(|v| v.as_str())
+            .map(PathBuf::from)
+            .or_else(history::default_path)
+        {
+            Some(path) => History::load(&path),
+            None => History::new(),
+        };
+
+        let on_eof = match keybind_config.settings.get(SETTING_STDIN_EOF) {
+            Some(value) => value
+                .as_str()
+                .and_then(EofAction::parse)
+                .unwrap_or_else(|| {
+                    warn!("{}: expected quit or headless", SETTING_STDIN_EOF);
+                    EofAction::Quit
+                }),
+            None => EofAction::Quit,
+        };
+
+        let prefix_indicator = keybind_config
+            .settings
+            .get(SETTING_PREFIX_INDICATOR)
+            .and_then(|v| v.as_bool())
+            .unwrap_or(true)
+            && stdout_is_tty();
+        let bells = Bells::new(keybind_config.bells.clone());
+        let keybind_processor = KeybindProcessor::new(keybind_config);
+        if keybind_processor.mouse_mode() == MouseMode::Local && stdout_is_tty() {
+            let _ = std::io::stdout().write_all(MOUSE_REPORTING_OFF);
+        }
+
+        Ok(Console {
+            stdio,
+            out: OutputBuffer::new(ClientPolicy::default()),
+            dropping: false,
+            registry: None,
+            writable_interest: false,
+            keybind_processor,
+            pending_results: Vec::new(),
+            prefix_shown: prefix_indicator.then_some(false),
+            filter_chain,
+            command_line: None,
+            prompt: Prompt::Command,
+            line_edit,
+            line_terminator,
+            history,
+            held_output: Vec::new(),
+            dropped_output: 0,
+            paused: false,
+            out_buf: Vec::new(),
+            scrollback: Scrollback::new(scrollback_lines),
+            bells,
+            read_buf: ReadBuffer::new(),
+            mouse_reporting: false,
+            on_eof,
+            remote_control: false,
+            stdin_closed: false,
+            stdout_closed: false,
+        })
+    }
+
+    /// What to do when the terminal does not keep up, as for TCP clients.
+    pub fn set_client_policy(&mut self, policy: ClientPolicy) {
+        self.out = OutputBuffer::new(policy);
+    }
+
+    /// Send `remote` actions to the device, which is a crabterm server.
+    pub fn set_remote_control(&mut self, on: bool) {
+        self.remote_control = on;
+    }
+
+    /// Run `input` through the command prompt while it is open, and the
+    /// keybind processor otherwise. Results are queued in order.
+    fn process_input(&mut self, mut input: &[u8]) {
+        let mut results = Vec::new();
+        while !input.is_empty() {
+            let Some(cl) = &mut self.command_line else {
+                results.extend(self.keybind_processor.process(input));
+                break;
+            };
+            let history: &[String] = match self.prompt {
+                Prompt::LineEdit | Prompt::SendLine => self.history.entries(),
+                _ => &[],
+            };
+            let (event, used) = cl.feed_with_history(input, history);
+            input = &input[used..];
+            match event {
+                CommandLineEvent::Pending => {
+                    let prompt = cl.render_with_history(history);
+                    self.write_stdout(&prompt);
+                }
+                CommandLineEvent::Cancel => self.close_command_line(),
+                CommandLineEvent::Submit(line)
+                    if matches!(self.prompt, Prompt::LineEdit | Prompt::SendLine) =>
+                {
+                    self.close_command_line();
+                    self.history.add(&line);
+                    let mut data = line.into_bytes();
+                    data.extend_from_slice(&self.line_terminator);
+                    let data = self.filter_chain.filter_in(&data);
+                    results.push(KeybindResult::Action(Action::Send(data)));
+                }
+                CommandLineEvent::Submit(line) if self.prompt == Prompt::Note => {
+                    self.close_command_line();
+                    let note = line.trim();
+                    if !note.is_empty() {
+                        results.push(KeybindResult::Action(Action::Note(Some(note.to_string()))));
+                    }
+                }
+                CommandLineEvent::Submit(line) => {
+                    self.close_command_line();
+                    let (parsed, prompt) = if self.prompt == Prompt::Hex {
+                        (parse_hex(&line).map(Action::Send), HEX_PROMPT)
+                    } else {
+                        (parse_command(&line), ":")
+                    };
+                    match parsed {
+                        Ok(action) => results.push(KeybindResult::Action(action)),
+                        Err(e) if line.trim().is_empty() => debug!("Empty command: {}", e),
+                        Err(e) => {
+                            self.write_stdout(format!("{}{}: {}\r\n", prompt, line, e).as_bytes())
+                        }
+                    }
+                }
+            }
+        }
+        for result in results.into_iter().rev() {
+            self.pending_results.push(result);
+        }
+        self.show_prefix();
+    }
+
+    /// Change the cursor shape when the prefix key was pressed, and back
+    /// when the key after it came or the wait timed out.
+    fn show_prefix(&mut self) {
+        let awaiting = self.keybind_processor.awaiting_prefix();
+        if let Some(shown) = self.prefix_shown
+            && shown != awaiting
+        {
+            self.prefix_shown = Some(awaiting);
+            self.write_stdout(if awaiting {
+                PREFIX_CURSOR_ON
+            } else {
+                PREFIX_CURSOR_OFF
+            });
+        }
+    }
+
+    fn open_command_line(&mut self, prompt: Prompt) {
+        let cl = match prompt {
+            Prompt::Command => CommandLine::new(),
+            Prompt::Hex => CommandLine::with_prompt(HEX_PROMPT),
+            Prompt::LineEdit => CommandLine::with_prompt(LINE_EDIT_PROMPT),
+            Prompt::SendLine => CommandLine::with_prompt(SEND_LINE_PROMPT),
+            Prompt::Note => CommandLine::with_prompt(NOTE_PROMPT),
+        };
+        self.write_stdout(&cl.render());
+        self.command_line = Some(cl);
+        self.prompt = prompt;
+    }
+
+    fn toggle_line_edit(&mut self) {
+        self.line_edit = !self.line_edit;
+        if !self.line_edit && self.prompt == Prompt::LineEdit && self.command_line.is_some() {
+            self.close_command_line();
+        }
+        let state = if self.line_edit { "on" } else { "off" };
+        info!("Console line-edit mode {}", state);
+        self.write_stdout(format!("[line-edit {}]\r\n", state).as_bytes());
+    }
+
+    /// Remove the prompt and write the output held back while it was open.
+    fn close_command_line(&mut self) {
+        self.command_line = None;
+        self.write_stdout(CommandLine::clear());
+        if self.paused {
+            self.write_stdout(PAUSED_STATUS);
+        } else {
+            self.flush_held_output();
+        }
+    }
+
+    fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+        info!(
+            "Console output {}",
+            if self.paused { "paused" } else { "resumed" }
+        );
+        // The prompt owns the bottom row while it is open
+        if self.command_line.is_some() {
+            return;
+        }
+        if self.paused {
+            self.write_stdout(PAUSED_STATUS);
+        } else {
+            self.write_stdout(CommandLine::clear());
+            self.flush_held_output();
+        }
+    }
+
+    fn copy_output(&mut self, lines: usize) {
+        let text = self.scrollback.last_lines(lines);
+        let copied = lines.min(self.scrollback.len());
+        info!(
+            "Console copying {} lines ({} bytes) to the clipboard",
+            copied,
+            text.len()
+        );
+        self.write_stdout(&osc52_copy(&text));
+        self.write_stdout(format!("[{} lines copied to the clipboard]\r\n", copied).as_bytes());
+    }
+
+    /// Print the last `lines` lines of output again, between separators,
+    /// e.g. after the screen was cleared.
+    fn replay_output(&mut self, lines: usize) {
+        let mut text = self.scrollback.last_lines_raw(lines);
+        let replayed = lines.min(self.scrollback.len());
+        if !text.is_empty() && !text.ends_with(b"\n") {
+            text.extend_from_slice(b"\r\n");
+        }
+        self.write_stdout(format!("\r\n[Replay of the last {} lines]\r\n", replayed).as_bytes());
+        self.write_stdout(&text);
+        self.write_stdout(b"[End of replay]\r\n");
+    }
+
+    fn toggle_mouse(&mut self) {
+        let (mode, sequence, msg) = match self.keybind_processor.mouse_mode() {
+            MouseMode::Local => (
+                MouseMode::Forward,
+                MOUSE_REPORTING_ON,
+                "[mouse forwarded to the device]",
+            ),
+            MouseMode::Forward => (MouseMode::Local, MOUSE_REPORTING_OFF, "[mouse local]"),
+        };
+        info!("Console mouse mode {:?}", mode);
+        self.keybind_processor.set_mouse_mode(mode);
+        self.mouse_reporting = mode == MouseMode::Forward;
+        self.write_stdout(sequence);
+        self.write_stdout(format!("{}\r\n", msg).as_bytes());
+    }
+
+    fn hold_output(&mut self, buf: &[u8]) {
+        let start = self.held_output.len();
+        self.filter_chain
+            .filter_out_into(buf, &mut self.held_output);
+        self.scrollback.push(&self.held_output[start..]);
+        if self.held_output.len() > MAX_HELD_OUTPUT {
+            let excess = self.held_output.len() - MAX_HELD_OUTPUT;
+            self.held_output.drain(..excess);
+            self.dropped_output += excess;
+        }
+    }
+
+    fn flush_held_output(&mut self) {
+        if self.dropped_output > 0 {
+            let msg = format!("[{} bytes of output dropped]\r\n", self.dropped_output);
+            self.write_stdout(msg.as_bytes());
+            self.dropped_output = 0;
+        }
+        let held = std::mem::take(&mut self.held_output);
+        self.write_stdout(&held);
+    }
+
+    fn write_stdout(&mut self, buf: &[u8]) {
+        let _ = self.send(buf);
+    }
+
+    /// Write `buf` to stdout, after what is queued; what stdout does not
+    /// take is queued, up to the buffer limit of the client policy.
+    fn send(&mut self, buf: &[u8]) -> Result<()> {
+        if self.stdout_closed {
+            return Err(ErrorKind::BrokenPipe.into());
+        }
+        self.write_out()?;
+        let mut written = 0;
+        if self.out.is_empty() && !self.out.rate_limited() {
+            match self.stdio.stdout().write(buf) {
+                Ok(n) => written = n,
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => {}
+                Err(e) => return Err(e),
+            }
+        }
+        if written == buf.len() {
+            return Ok(());
+        }
+
+        match self.out.push(Arc::from(buf), written) {
+            Pushed::All => {}
+            Pushed::Dropped(_) => {
+                if !self.dropping {
+                    warn!("Console: terminal too slow, dropping output");
+                    self.dropping = true;
+                }
+            }
+            Pushed::Overflow => {
+                warn!("Console: terminal too slow, closing the console");
+                self.stdout_closed = true;
+                return Err(std::io::Error::new(
+                    ErrorKind::WouldBlock,
+                    "console buffer limit exceeded",
+                ));
+            }
+        }
+        self.write_out()
+    }
+
+    /// Write as much of `out` as stdout takes, and be told when it takes
+    /// more only while there is something left.
+    fn write_out(&mut self) -> Result<()> {
+        self.out.write_to(&mut self.stdio.stdout())?;
+        if self.out.is_empty() {
+            self.dropping = false;
+        }
+
+        let writable = !self.out.is_empty();
+        if writable != self.writable_interest
+            && let Some((registry, token)) = &self.registry
+        {
+            match self
+                .stdio
+                .watch_writable(registry, writable.then_some(*token))
+            {
+                Ok(()) => self.writable_interest = writable,
+                Err(e) => debug!("Console: stdout interest: {}", e),
+            }
+        }
+        Ok(())
+    }
+
+    /// stdin is closed: stop reading it, and quit unless configured to go on
+    /// headless.
+    fn end_of_input(&mut self) -> IoResult {
+        self.stdin_closed = true;
+        match self.on_eof {
+            EofAction::Quit => {
+                info!("Console: stdin closed, quitting");
+                IoResult::Action(Action::Quit)
+            }
+            EofAction::Headless => {
+                info!("Console: stdin closed, continuing headless");
+                IoResult::None
+            }
+        }
+    }
+
+    fn next_pending_result(&mut self) -> Option<IoResult> {
+        while let Some(result) = self.pending_results.pop() {
+            if let Some(read_result) = self.keybind_result_to_read_result(result) {
+                return Some(read_result);
+            }
+        }
+        None
+    }
+
+    fn keybind_result_to_read_result(&mut self, result: KeybindResult) -> Option<IoResult> {
+        debug!("Console converting keybind result: {:?}", result);
+        let io_result = match result {
+            // Keys that were parsed before the prompt opened
+            KeybindResult::Passthrough(bytes) if self.command_line.is_some() => {
+                self.process_input(&bytes);
+                None
+            }
+            // In line-edit mode typing opens the editor, other keys (Enter,
+            // Ctrl+C, ...) still go straight to the device
+            KeybindResult::Passthrough(bytes)
+                if self.line_edit
+                    && let Some(start) = line_edit_start(&bytes) =>
+            {
+                self.open_command_line(Prompt::LineEdit);
+                self.process_input(&bytes[start..]);
+                (start > 0)
+                    .then(|| IoResult::Data(self.filter_chain.filter_in(&bytes[..start]).into()))
+            }
+            KeybindResult::Passthrough(bytes) => {
+                let filtered = self.filter_chain.filter_in(&bytes);
+                Some(IoResult::Data(filtered.into()))
+            }
+            KeybindResult::Action(Action::FilterToggle(name)) => {
+                if !self.filter_chain.toggle(&name) {
+                    self.write_stdout(format!("Unknown filter: {}\r\n", name).as_bytes());
+                }
+                None
+            }
+            KeybindResult::Action(Action::Charmap(preset)) => {
+                let (Ok(msg) | Err(msg)) =
+                    charmap::set_preset(&mut self.filter_chain, preset.as_deref());
+                info!("Console {}", msg);
+                self.write_stdout(format!("[{}]\r\n", msg).as_bytes());
+                None
+            }
+            KeybindResult::Action(Action::Command) => {
+                self.open_command_line(Prompt::Command);
+                None
+            }
+            KeybindResult::Action(Action::HexInput) => {
+                self.open_command_line(Prompt::Hex);
+                None
+            }
+            KeybindResult::Action(Action::LineEdit) => {
+                self.toggle_line_edit();
+                None
+            }
+            KeybindResult::Action(Action::SendLine) => {
+                self.open_command_line(Prompt::SendLine);
+                None
+            }
+            KeybindResult::Action(Action::Note(None)) => {
+                self.open_command_line(Prompt::Note);
+                None
+            }
+            // The hub redraws on SIGCONT
+            #[cfg(unix)]
+            KeybindResult::Action(Action::Suspend) => {
+                IoInstance::suspend(self);
+                suspend();
+                None
+            }
+            // No job control
+            #[cfg(windows)]
+            KeybindResult::Action(Action::Suspend) => {
+                self.write_stdout(b"[suspend: not on Windows]\r\n");
+                None
+            }
+            KeybindResult::Action(Action::MouseToggle) => {
+                self.toggle_mouse();
+                None
+            }
+            KeybindResult::Action(Action::PauseOutput) => {
+                self.toggle_pause();
+                None
+            }
+            KeybindResult::Action(Action::CopyOutput(lines)) => {
+                self.copy_output(lines);
+                None
+            }
+            KeybindResult::Action(Action::ReplayOutput(lines)) => {
+                self.replay_output(lines);
+                None
+            }
+            KeybindResult::Action(Action::Remote(command)) => {
+                if self.remote_control {
+                    Some(IoResult::Data(control::encode(&command).into()))
+                } else {
+                    self.write_stdout(b"remote: only with crabterm connect\r\n");
+                    None
+                }
+            }
+            KeybindResult::Action(action) => {
+                debug!("Console forwarding action to hub: {:?}", action);
+                Some(IoResult::Action(action))
+            }
+            KeybindResult::Consumed => None,
+        };
+        debug!("Console io_result: {:?}", io_result);
+        io_result
+    }
+}
+
+/// Where typing starts in `bytes`: a printable character, Ctrl+R or Up.
+fn line_edit_start(bytes: &[u8]) -> Option<usize> {
+    (0..bytes.len()).find(|&i| match bytes[i] {
+        0x12 => true,
+        0x1b => matches!(bytes.get(i + 1..i + 3), Some(b"[A" | b"OA")),
+        b => b >= 0x20 && b != 0x7f,
+    })
+}
+
+impl IoInstance for Console {
+    fn connect(&mut self, poll: &mut Poll, token: Token) -> Result<()> {
+        self.stdio.register(poll.registry(), token)?;
+        self.registry = Some((poll.registry().try_clone()?, token));
+        Ok(())
+    }
+
+    fn addr_as_string(&self) -> String {
+        "Local".to_owned()
+    }
+
+    fn connected(&self) -> bool {
+        !self.stdin_closed && !self.stdout_closed
+    }
+
+    fn disconnect(&mut self, poll: &mut Poll) {
+        // TODO, panic on error?
+        let _ = self.stdio.deregister(poll.registry());
+        if self.writable_interest {
+            let _ = self.stdio.watch_writable(poll.registry(), None);
+            self.writable_interest = false;
+        }
+        self.registry = None;
+    }
+
+    fn read(&mut self) -> Result<IoResult> {
+        // First, check if we have pending results from previous processing.
+        // Results handled locally produce nothing, keep going until one does
+        // so the rest are not left behind until the next input.
+        if let Some(read_result) = self.next_pending_result() {
+            return Ok(read_result);
+        }
+
+        // Taken out while the input is processed, which needs all of self
+        let mut read_buf = std::mem::take(&mut self.read_buf);
+        let result = self.stdio.read(read_buf.get());
+        if let Ok(n) = result
+            && n > 0
+        {
+            let input = &read_buf.get()[..n];
+            debug!("Console read {} bytes: {:02x?}", n, input);
+            // Process through the prompt or the keybind processor
+            self.process_input(input);
+        }
+        self.read_buf = read_buf;
+
+        match result {
+            Ok(0) => Ok(self.end_of_input()),
+
+            Ok(_) => {
+                // Return the first result
+                if let Some(read_result) = self.next_pending_result() {
+                    return Ok(read_result);
+                }
+
+                Ok(IoResult::None)
+            }
+
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
+                // Not ready yet — ignore and wait for next event
+                Ok(IoResult::None)
+            }
+
+            // A terminal that was hung up
+            Err(ref e) if e.raw_os_error() == Some(libc::EIO) => Ok(self.end_of_input()),
+
+            Err(e) => Err(e),
+        }
+    }
+
+    fn tick(&mut self) -> Result<IoResult> {
+        // Output the rate limit held back
+        if self.out.next_write().is_some_and(|t| t <= clock::now()) {
+            let _ = self.write_out();
+        }
+        // Check for timeout-triggered results (e.g., escape key timeout, prefix timeout)
+        let results = if self.command_line.is_some() {
+            Vec::new()
+        } else {
+            self.keybind_processor.tick()
+        };
+        self.show_prefix();
+
+        for result in results.into_iter().rev() {
+            self.pending_results.push(result);
+        }
+
+        if let Some(result) = self.pending_results.pop()
+            && let Some(read_result) = self.keybind_result_to_read_result(result)
+        {
+            return Ok(read_result);
+        }
+
+        Ok(IoResult::None)
+    }
+
+    fn next_tick(&self) -> Option<Instant> {
+        // One pending result is returned per tick
+        if !self.pending_results.is_empty() {
+            return Some(clock::now());
+        }
+        let keys = match self.command_line {
+            Some(_) => None,
+            None => self.keybind_processor.next_timeout(),
+        };
+        keys.into_iter().chain(self.out.next_write()).min()
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<IoResult> {
+        // Rings while the output is held back too
+        let alert = self.bells.check(buf);
+        if !alert.is_empty() {
+            self.write_stdout(&alert);
+        }
+        if self.command_line.is_some() || self.paused {
+            self.hold_output(buf);
+            return Ok(IoResult::Data(buf.into()));
+        }
+        let mut out = std::mem::take(&mut self.out_buf);
+        out.clear();
+        self.filter_chain.filter_out_into(buf, &mut out);
+        self.scrollback.push(&out);
+        let result = self.send(&out);
+        self.out_buf = out;
+        result.map(|()| IoResult::Data(buf.into()))
+    }
+
+    fn flush(&mut self) {
+        // Output of print!() elsewhere, then ours
+        let _ = std::io::stdout().flush();
+        let _ = self.write_out();
+    }
+
+    fn write_announce(&mut self, template: &str, source: &str, msg: &str) {
+        let formatted = announce::format_message(template, source, msg, MessageKind::Info);
+        self.write_all(formatted.as_bytes());
+        self.flush();
+    }
+
+    fn write_status(&mut self, template: &str, source: &str, msg: &str) {
+        let kind = MessageKind::of_status(msg);
+        let formatted = announce::format_message(template, source, msg, kind);
+        self.write_all(formatted.as_bytes());
+        self.flush();
+    }
+
+    fn write_backlogged(&self) -> bool {
+        self.out.policy().slow == SlowClientPolicy::Block && self.out.backlogged()
+    }
+
+    fn write_pending(&self) -> bool {
+        !self.stdout_closed && !self.out.is_empty()
+    }
+
+    fn write_queued(&self) -> usize {
+        self.out.len()
+    }
+
+    fn filters(&self) -> Option<&FilterChain> {
+        Some(&self.filter_chain)
+    }
+
+    /// Mouse reporting off, and stdin and stdout blocking again as the shell
+    /// expects them while we are stopped
+    #[cfg(unix)]
+    fn suspend(&mut self) {
+        if self.mouse_reporting {
+            self.write_stdout(MOUSE_REPORTING_OFF);
+        }
+        let _ = self.write_out();
+        self.stdio.restore_flags();
+    }
+
+    fn redraw(&mut self) {
+        // Undo suspend()
+        #[cfg(unix)]
+        self.stdio.set_nonblocking();
+        if self.mouse_reporting {
+            self.write_stdout(MOUSE_REPORTING_ON);
+        }
+        if let Some(cl) = &self.command_line {
+            let history: &[String] = match self.prompt {
+                Prompt::LineEdit | Prompt::SendLine => self.history.entries(),
+                _ => &[],
+            };
+            let prompt = cl.render_with_history(history);
+            self.write_stdout(&prompt);
+        } else if self.paused {
+            self.write_stdout(PAUSED_STATUS);
+        }
+    }
+}
+
+impl Drop for Console {
+    fn drop(&mut self) {
+        if self.mouse_reporting {
+            self.write_stdout(MOUSE_REPORTING_OFF);
+        }
+        if self.prefix_shown == Some(true) {
+            self.write_stdout(PREFIX_CURSOR_OFF);
+        }
+        // What the terminal has not taken by now is lost
+        let _ = self.write_out();
+        let _ = disable_raw_mode();
+    }
+}
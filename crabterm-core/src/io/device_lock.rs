@@ -0,0 +1,334 @@
+//! UUCP style lock files for serial devices, e.g. `/var/lock/LCK..ttyUSB0`,
+//! so a second crabterm (or minicom, picocom) on the same port is refused
+//! with the PID of the owner rather than a bare "Device or resource busy".
+//!
+//! The file has the PID of the owner as ten characters and a newline, as
+//! other tools expect; crabterm adds the path of its control socket on a
+//! second line, so `--steal` can ask it to release the port.
+
+use log::{info, warn};
+use std::fmt;
+use std::fs::File;
+use std::io::{Error, ErrorKind, Result};
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::clock;
+use crate::ctl;
+
+pub const SETTING_DIR: &str = "lock-dir";
+
+/// Where lock files go unless `lock-dir` says otherwise
+pub const DEFAULT_DIR: &str = "/var/lock";
+
+/// How long the owner gets to release the port when it is stolen
+const RELEASE_TIMEOUT: Duration = Duration::from_secs(5);
+const RELEASE_POLL: Duration = Duration::from_millis(50);
+
+/// The process holding a lock
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Owner {
+    pub pid: i32,
+    /// The program, from /proc where there is one
+    pub name: Option<String>,
+    /// The control socket of a crabterm owner
+    pub control: Option<PathBuf>,
+}
+
+impl fmt::Display for Owner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "pid {} ({})", self.pid, name),
+            None => write!(f, "pid {}", self.pid),
+        }
+    }
+}
+
+/// Why a lock could not be taken
+#[derive(Debug)]
+pub enum LockError {
+    /// Another live process holds it
+    InUse(Owner),
+    Io(Error),
+}
+
+impl fmt::Display for LockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LockError::InUse(owner) => write!(f, "in use by {}", owner),
+            LockError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<Error> for LockError {
+    fn from(e: Error) -> Self {
+        LockError::Io(e)
+    }
+}
+
+/// A lock held on a device, released when dropped.
+#[derive(Debug)]
+pub struct DeviceLock {
+    path: PathBuf,
+}
+
+/// The lock file of `device` in `dir`, named after the node a link such as
+/// /dev/serial/by-id/... points to, so both names share it.
+pub fn lock_path(dir: &Path, device: &Path) -> PathBuf {
+    let node = std::fs::canonicalize(device).unwrap_or_else(|_| device.to_path_buf());
+    let name = node
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| node.to_string_lossy().replace('/', "_"));
+    dir.join(format!("LCK..{}", name))
+}
+
+fn alive(pid: i32) -> bool {
+    // SAFETY: signal 0 only checks that the process exists
+    let ret = unsafe { libc::kill(pid, 0) };
+    ret == 0 || Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// The owner written in a lock file; None when it is not readable as one.
+fn read_owner(path: &Path) -> Result<Option<Owner>> {
+    let text = std::fs::read_to_string(path)?;
+    let mut lines = text.lines();
+    let Some(pid) = lines.next().and_then(|l| l.trim().parse().ok()) else {
+        return Ok(None);
+    };
+    let name = std::fs::read_to_string(format!("/proc/{}/comm", pid))
+        .ok()
+        .map(|n| n.trim().to_string());
+    let control = lines.next().filter(|l| !l.is_empty()).map(PathBuf::from);
+    Ok(Some(Owner { pid, name, control }))
+}
+
+/// Remove the stale lock at `path`, unless it was replaced since it was
+/// read. Takeovers hold flock() on the lock file, so of two processes that
+/// found the same stale lock only the first removes it; the second then finds
+/// that the path has a lock of its own, the new one.
+fn remove_stale(path: &Path) -> Result<()> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        // Gone already
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    // SAFETY: a valid descriptor; the lock goes with it when it is closed
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+        return Err(Error::last_os_error());
+    }
+    let opened = file.metadata()?;
+    let current = match std::fs::symlink_metadata(path) {
+        Ok(current) => current,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    // Lock files are linked into place whole, never rewritten, so the same
+    // file is the same stale lock
+    if (current.dev(), current.ino()) == (opened.dev(), opened.ino()) {
+        info!("Removing stale lock {}", path.display());
+        std::fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+impl DeviceLock {
+    /// Lock `device` with a file in `dir`, recording `control` for
+    /// `--steal`. A lock of a process that is gone is replaced. Ok(None),
+    /// with a warning, when `dir` is not there or not writable: the port is
+    /// still opened exclusively, but other programs are not told who has it.
+    pub fn acquire(
+        dir: &Path,
+        device: &Path,
+        control: Option<&Path>,
+    ) -> std::result::Result<Option<Self>, LockError> {
+        let path = lock_path(dir, device);
+        let pid = std::process::id() as i32;
+        let mut content = format!("{:>10}\n", pid);
+        if let Some(control) = control {
+            let control = std::path::absolute(control).unwrap_or_else(|_| control.to_path_buf());
+            content.push_str(&format!("{}\n", control.display()));
+        }
+        // Written in full under a name of its own, then linked into place,
+        // so nobody reads a half-written lock
+        let tmp = dir.join(format!(".LCK.{}", pid));
+        if let Err(e) = std::fs::write(&tmp, &content) {
+            warn!(
+                "{} is not locked, no lock file in {}: {}",
+                device.display(),
+                dir.display(),
+                e
+            );
+            return Ok(None);
+        }
+        let result = Self::link(&tmp, &path, pid);
+        let _ = std::fs::remove_file(&tmp);
+        result.map(Some)
+    }
+
+    fn link(tmp: &Path, path: &Path, pid: i32) -> std::result::Result<Self, LockError> {
+        // One retry, after removing a stale lock; when another process took it
+        // over meanwhile, the retry finds that one
+        for _ in 0..2 {
+            match std::fs::hard_link(tmp, path) {
+                Ok(()) => {
+                    info!("Locked with {}", path.display());
+                    return Ok(DeviceLock {
+                        path: path.to_path_buf(),
+                    });
+                }
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {}
+                Err(e) => return Err(e.into()),
+            }
+            match read_owner(path) {
+                Ok(Some(owner)) if owner.pid == pid => {
+                    // Ours, e.g. the device given twice
+                    return Ok(DeviceLock {
+                        path: path.to_path_buf(),
+                    });
+                }
+                Ok(Some(owner)) if alive(owner.pid) => return Err(LockError::InUse(owner)),
+                // Gone in the meantime
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                _ => remove_stale(path)?,
+            }
+        }
+        Err(Error::new(
+            ErrorKind::AlreadyExists,
+            format!("{} is in use", path.display()),
+        )
+        .into())
+    }
+
+    /// Ask the crabterm holding the lock of `device` to release it, over
+    /// its control socket, wait for it to exit and take the lock.
+    pub fn steal(
+        dir: &Path,
+        device: &Path,
+        control: Option<&Path>,
+    ) -> std::result::Result<Option<Self>, LockError> {
+        let owner = match Self::acquire(dir, device, control) {
+            Err(LockError::InUse(owner)) => owner,
+            result => return result,
+        };
+        let socket = owner.control.as_ref().ok_or_else(|| {
+            Error::other(format!(
+                "in use by {}, which has no control socket to release it",
+                owner
+            ))
+        })?;
+        info!("Asking {} to release {}", owner, device.display());
+        match ctl::send(socket, &format!("release {}", device.display())) {
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => return Err(Error::other(format!("{}: {}", owner, e)).into()),
+            Err(e) => return Err(Error::new(e.kind(), format!("{}: {}", owner, e)).into()),
+        }
+        let deadline = clock::now() + RELEASE_TIMEOUT;
+        while alive(owner.pid) && lock_path(dir, device).exists() {
+            if clock::now() >= deadline {
+                return Err(LockError::InUse(owner));
+            }
+            std::thread::sleep(RELEASE_POLL);
+        }
+        Self::acquire(dir, device, control)
+    }
+
+    /// True if this is the lock of `device`, by any name of it.
+    pub fn covers(&self, device: &Path) -> bool {
+        self.path
+            .parent()
+            .is_some_and(|dir| lock_path(dir, device) == self.path)
+    }
+}
+
+impl Drop for DeviceLock {
+    fn drop(&mut self) {
+        // Only if it is still ours
+        let pid = std::process::id() as i32;
+        if let Ok(Some(owner)) = read_owner(&self.path)
+            && owner.pid == pid
+        {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    fn lock_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("crabterm_lock_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_acquire_and_release() {
+        let dir = lock_dir("acquire");
+        let device = Path::new("/dev/ttyNONE0");
+        let path = lock_path(&dir, device);
+        assert_eq!(path, dir.join("LCK..ttyNONE0"));
+
+        let lock = DeviceLock::acquire(&dir, device, Some(Path::new("/run/ct.sock")))
+            .unwrap()
+            .unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(text, format!("{:>10}\n/run/ct.sock\n", std::process::id()));
+        let owner = read_owner(&path).unwrap().unwrap();
+        assert_eq!(owner.control, Some(PathBuf::from("/run/ct.sock")));
+        assert!(lock.covers(device));
+        assert!(!lock.covers(Path::new("/dev/ttyNONE9")));
+        drop(lock);
+        assert!(!path.exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_in_use_and_stale() {
+        let dir = lock_dir("in_use");
+        let device = Path::new("/dev/ttyNONE1");
+        let path = lock_path(&dir, device);
+
+        let mut other = Command::new("sleep").arg("10").spawn().unwrap();
+        std::fs::write(&path, format!("{:>10}\n", other.id())).unwrap();
+        match DeviceLock::acquire(&dir, device, None) {
+            Err(LockError::InUse(owner)) => {
+                assert_eq!(owner.pid, other.id() as i32);
+                assert_eq!(owner.control, None);
+                assert!(
+                    owner
+                        .to_string()
+                        .starts_with(&format!("pid {}", other.id()))
+                );
+            }
+            other => panic!("{:?}", other),
+        }
+        // Without a control socket, it can not be asked to release it
+        assert!(DeviceLock::steal(&dir, device, None).is_err());
+
+        // Its lock is stale once it is gone
+        other.kill().unwrap();
+        other.wait().unwrap();
+        let lock = DeviceLock::acquire(&dir, device, None).unwrap();
+        assert!(lock.is_some());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_no_lock_dir() {
+        let dir = Path::new("/nonexistent/lock");
+        assert!(
+            DeviceLock::acquire(dir, Path::new("/dev/ttyNONE2"), None)
+                .unwrap()
+                .is_none()
+        );
+    }
+}
@@ -1,13 +1,24 @@
 use log::info;
-use mio::unix::pipe::{Receiver, Sender};
-use mio::{Interest, Poll, Token};
+#[cfg(unix)]
+use mio::unix::pipe::{self, Receiver, Sender};
+use mio::{Interest, Token};
 use std::io::{ErrorKind, Read, Result, Write};
 
+#[cfg(windows)]
+use super::loopback as pipe;
+use super::read_buffer::ReadBuffer;
+use crate::poll::Poll;
 use crate::traits::{IoInstance, IoResult};
 
+#[cfg(windows)]
+type Sender = mio::net::TcpStream;
+#[cfg(windows)]
+type Receiver = mio::net::TcpStream;
+
 pub struct EchoDevice {
     sender: Option<Sender>,
     receiver: Option<Receiver>,
+    read_buf: ReadBuffer,
 }
 
 impl EchoDevice {
@@ -15,13 +26,14 @@ impl EchoDevice {
         Ok(EchoDevice {
             sender: None,
             receiver: None,
+            read_buf: ReadBuffer::new(),
         })
     }
 }
 
 impl IoInstance for EchoDevice {
     fn connect(&mut self, poll: &mut Poll, token: Token) -> Result<()> {
-        let (sender, mut receiver) = mio::unix::pipe::new()?;
+        let (sender, mut receiver) = pipe::new()?;
 
         poll.registry()
             .register(&mut receiver, token, Interest::READABLE)?;
@@ -52,13 +64,13 @@ impl IoInstance for EchoDevice {
     }
 
     fn read(&mut self) -> Result<IoResult> {
-        let mut tmp = [0u8; 1024];
+        let tmp = self.read_buf.get();
 
         if let Some(r) = &mut self.receiver {
-            match r.read(&mut tmp) {
+            match r.read(tmp) {
                 Ok(0) => Ok(IoResult::None),
 
-                Ok(n) => Ok(IoResult::Data(tmp[..n].to_vec())),
+                Ok(n) => Ok(IoResult::Data(tmp[..n].into())),
 
                 Err(ref e) if e.kind() == ErrorKind::WouldBlock => Ok(IoResult::None),
 
@@ -72,7 +84,7 @@ impl IoInstance for EchoDevice {
     fn write(&mut self, buf: &[u8]) -> Result<IoResult> {
         if let Some(s) = &mut self.sender {
             match s.write(buf) {
-                Ok(n) => Ok(IoResult::Data(buf[..n].to_vec())),
+                Ok(n) => Ok(IoResult::Data(buf[..n].into())),
                 Err(e) => Err(e),
             }
         } else {
@@ -0,0 +1,300 @@
+use log::info;
+use mio::Token;
+use std::io::{Error, ErrorKind, IoSlice, Result};
+use std::time::{Duration, Instant};
+
+use crate::clock;
+use crate::poll::Poll;
+use crate::traits::{IoInstance, IoResult};
+
+const PROBE_TOKEN: Token = Token(0);
+
+/// An ordered list of devices reaching the same target, e.g. a serial port
+/// and a network console of the same board.
+///
+/// The first device is the primary. When the current device can not be
+/// (re)connected within `timeout`, the next one in the list is used. While a
+/// fallback is in use the primary is probed every `timeout`, and once it can
+/// be connected again the hub is asked to disconnect so that the primary is
+/// used again.
+pub struct FailoverDevice {
+    devices: Vec<Box<dyn IoInstance>>,
+    current: usize,
+    timeout: Duration,
+
+    /// When the current device first failed to connect
+    down_since: Option<Instant>,
+
+    /// Private poll used to probe the primary without involving the hub
+    probe_poll: Poll,
+    last_probe: Option<Instant>,
+
+    /// The primary is back, switch to it at the next disconnect/connect
+    failback_pending: bool,
+}
+
+impl FailoverDevice {
+    pub fn new(devices: Vec<Box<dyn IoInstance>>, timeout: Duration) -> Result<Self> {
+        if devices.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidInput, "no devices"));
+        }
+        Ok(FailoverDevice {
+            devices,
+            current: 0,
+            timeout,
+            down_since: None,
+            probe_poll: Poll::new()?,
+            last_probe: None,
+            failback_pending: false,
+        })
+    }
+
+    /// Index of the device currently in use (0 is the primary).
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    fn connect_at(&mut self, poll: &mut Poll, token: Token, now: Instant) -> Result<()> {
+        let result = self.devices[self.current].connect(poll, token);
+        match &result {
+            Ok(()) => self.down_since = None,
+            Err(e) => {
+                let since = *self.down_since.get_or_insert(now);
+                if now.duration_since(since) >= self.timeout && self.devices.len() > 1 {
+                    let next = (self.current + 1) % self.devices.len();
+                    info!(
+                        "Failover: {} not connected for {:?} ({}), trying {}",
+                        self.devices[self.current].addr_as_string(),
+                        self.timeout,
+                        e,
+                        self.devices[next].addr_as_string()
+                    );
+                    self.current = next;
+                    self.down_since = Some(now);
+                    self.last_probe = Some(now);
+                }
+            }
+        }
+        result
+    }
+
+    /// While on a fallback, check whether the primary can be connected.
+    fn probe_at(&mut self, now: Instant) {
+        if self.current == 0 || self.failback_pending {
+            return;
+        }
+        if let Some(last) = self.last_probe
+            && now.duration_since(last) < self.timeout
+        {
+            return;
+        }
+        self.last_probe = Some(now);
+
+        let primary = &mut self.devices[0];
+        match primary.connect(&mut self.probe_poll, PROBE_TOKEN) {
+            Ok(()) => {
+                info!(
+                    "Failover: {} is back, failing back",
+                    primary.addr_as_string()
+                );
+                primary.disconnect(&mut self.probe_poll);
+                self.failback_pending = true;
+            }
+            // A connection in progress is completed by the next probe
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => {}
+            Err(_) => {
+                if primary.disconnect_needed() {
+                    primary.disconnect(&mut self.probe_poll);
+                }
+            }
+        }
+    }
+}
+
+impl IoInstance for FailoverDevice {
+    fn connect(&mut self, poll: &mut Poll, token: Token) -> Result<()> {
+        self.connect_at(poll, token, clock::now())
+    }
+
+    fn connected(&self) -> bool {
+        self.devices[self.current].connected()
+    }
+
+    fn disconnect_needed(&self) -> bool {
+        self.failback_pending || self.devices[self.current].disconnect_needed()
+    }
+
+    fn disconnect(&mut self, poll: &mut Poll) {
+        self.devices[self.current].disconnect(poll);
+        if self.failback_pending {
+            self.failback_pending = false;
+            self.current = 0;
+            self.down_since = None;
+        }
+    }
+
+    fn read(&mut self) -> Result<IoResult> {
+        self.devices[self.current].read()
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<IoResult> {
+        self.devices[self.current].write(buf)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> Result<usize> {
+        self.devices[self.current].write_vectored(bufs)
+    }
+
+    fn flush(&mut self) {
+        self.devices[self.current].flush()
+    }
+
+    fn addr_as_string(&self) -> String {
+        self.devices[self.current].addr_as_string()
+    }
+
+    fn connected_announcement(&self) -> Option<String> {
+        self.devices[self.current].connected_announcement()
+    }
+
+    fn take_announcement(&mut self) -> Option<String> {
+        self.devices[self.current].take_announcement()
+    }
+
+    fn tick(&mut self) -> Result<IoResult> {
+        self.probe_at(clock::now());
+        Ok(IoResult::None)
+    }
+
+    fn next_tick(&self) -> Option<Instant> {
+        if self.current == 0 || self.failback_pending {
+            return None;
+        }
+        Some(match self.last_probe {
+            Some(last) => last + self.timeout,
+            None => clock::now(),
+        })
+    }
+
+    fn baudrate(&self) -> Option<u32> {
+        self.devices[self.current].baudrate()
+    }
+
+    fn set_baudrate(&mut self, baudrate: u32) -> Result<()> {
+        self.devices[self.current].set_baudrate(baudrate)
+    }
+
+    fn set_quarantine(&mut self, duration: Duration) -> Result<()> {
+        // All of them, for the one failed over to; fine if one takes it
+        let results: Vec<Result<()>> = self
+            .devices
+            .iter_mut()
+            .map(|device| device.set_quarantine(duration))
+            .collect();
+        results.into_iter().reduce(|a, b| a.or(b)).unwrap_or(Ok(()))
+    }
+
+    fn resize(&mut self, cols: u16, rows: u16) {
+        // All of them, so the size is right after failing over
+        for device in &mut self.devices {
+            device.resize(cols, rows);
+        }
+    }
+
+    fn set_writable_interest(&mut self, poll: &mut Poll, writable: bool) -> Result<()> {
+        self.devices[self.current].set_writable_interest(poll, writable)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    /// A device that can be connected while `available` is set.
+    struct MockDevice {
+        name: &'static str,
+        available: Rc<Cell<bool>>,
+        connected: bool,
+    }
+
+    impl MockDevice {
+        fn new(name: &'static str, available: bool) -> (Box<Self>, Rc<Cell<bool>>) {
+            let flag = Rc::new(Cell::new(available));
+            let dev = MockDevice {
+                name,
+                available: flag.clone(),
+                connected: false,
+            };
+            (Box::new(dev), flag)
+        }
+    }
+
+    impl IoInstance for MockDevice {
+        fn connect(&mut self, _poll: &mut Poll, _token: Token) -> Result<()> {
+            if self.available.get() {
+                self.connected = true;
+                Ok(())
+            } else {
+                Err(Error::new(ErrorKind::NotFound, "not found"))
+            }
+        }
+        fn connected(&self) -> bool {
+            self.connected
+        }
+        fn disconnect_needed(&self) -> bool {
+            self.connected && !self.available.get()
+        }
+        fn disconnect(&mut self, _poll: &mut Poll) {
+            self.connected = false;
+        }
+        fn read(&mut self) -> Result<IoResult> {
+            Ok(IoResult::None)
+        }
+        fn write(&mut self, buf: &[u8]) -> Result<IoResult> {
+            Ok(IoResult::Data(buf.into()))
+        }
+        fn flush(&mut self) {}
+        fn addr_as_string(&self) -> String {
+            self.name.to_string()
+        }
+    }
+
+    #[test]
+    fn test_failover_and_failback() {
+        let mut poll = Poll::new().unwrap();
+        let t0 = clock::now();
+        let secs = |s| t0 + Duration::from_secs(s);
+
+        let (primary, primary_up) = MockDevice::new("A", false);
+        let (fallback, _) = MockDevice::new("B", true);
+        let mut dev = FailoverDevice::new(vec![primary, fallback], Duration::from_secs(5)).unwrap();
+
+        // The primary gets `timeout` to show up
+        assert!(dev.connect_at(&mut poll, Token(0), secs(0)).is_err());
+        assert!(dev.connect_at(&mut poll, Token(0), secs(4)).is_err());
+        assert_eq!(dev.current(), 0);
+        assert_eq!(dev.next_tick(), None);
+        assert!(dev.connect_at(&mut poll, Token(0), secs(5)).is_err());
+        assert_eq!(dev.current(), 1);
+        assert!(dev.connect_at(&mut poll, Token(0), secs(5)).is_ok());
+        assert_eq!(dev.addr_as_string(), "B");
+
+        // Primary probed every `timeout` while on the fallback
+        assert_eq!(dev.next_tick(), Some(secs(10)));
+        dev.probe_at(secs(8));
+        assert!(!dev.disconnect_needed());
+        primary_up.set(true);
+        dev.probe_at(secs(9));
+        assert!(!dev.disconnect_needed());
+        dev.probe_at(secs(10));
+        assert!(dev.disconnect_needed());
+        assert_eq!(dev.next_tick(), None);
+
+        dev.disconnect(&mut poll);
+        assert_eq!(dev.current(), 0);
+        assert!(dev.connect_at(&mut poll, Token(0), secs(10)).is_ok());
+        assert_eq!(dev.addr_as_string(), "A");
+    }
+}
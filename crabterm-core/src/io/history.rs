@@ -0,0 +1,115 @@
+//! History of lines sent in line-edit mode, persisted between sessions.
+
+use log::warn;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Setting overriding the history file, default ~/.crabterm_history
+pub const SETTING_FILE: &str = "history-file";
+
+/// Entries kept in memory; the file is trimmed to this on load.
+const MAX_ENTRIES: usize = 1000;
+
+pub fn default_path() -> Option<PathBuf> {
+    dirs::home_dir().map(|home| home.join(".crabterm_history"))
+}
+
+/// Oldest entry first.
+#[derive(Debug, Default)]
+pub struct History {
+    entries: Vec<String>,
+    path: Option<PathBuf>,
+}
+
+impl History {
+    /// A history that is not saved
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load the history from `path`, which need not exist yet. New entries
+    /// are appended to it.
+    pub fn load(path: &Path) -> Self {
+        let mut entries: Vec<String> = match fs::read_to_string(path) {
+            Ok(content) => content.lines().map(|l| l.to_string()).collect(),
+            Err(_) => Vec::new(),
+        };
+        if entries.len() > MAX_ENTRIES {
+            entries.drain(..entries.len() - MAX_ENTRIES);
+            let mut content = entries.join("\n");
+            content.push('\n');
+            if let Err(e) = fs::write(path, content) {
+                warn!("History {}: {}", path.display(), e);
+            }
+        }
+        History {
+            entries,
+            path: Some(path.to_path_buf()),
+        }
+    }
+
+    pub fn entries(&self) -> &[String] {
+        &self.entries
+    }
+
+    /// Add a line; empty lines and repeats of the last entry are skipped.
+    pub fn add(&mut self, line: &str) {
+        if line.trim().is_empty() || self.entries.last().is_some_and(|l| l == line) {
+            return;
+        }
+        self.entries.push(line.to_string());
+        if self.entries.len() > MAX_ENTRIES {
+            self.entries.remove(0);
+        }
+        if let Some(path) = &self.path {
+            let result = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .and_then(|mut f| writeln!(f, "{}", line));
+            if let Err(e) = result {
+                warn!("History {}: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_and_persist() {
+        let path = std::env::temp_dir().join(format!("crabterm-history-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut history = History::load(&path);
+        history.add("reboot");
+        history.add("reboot");
+        history.add("  ");
+        history.add("printenv");
+        assert_eq!(history.entries(), ["reboot", "printenv"]);
+
+        let history = History::load(&path);
+        assert_eq!(history.entries(), ["reboot", "printenv"]);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_trim_on_load() {
+        let path =
+            std::env::temp_dir().join(format!("crabterm-history-trim-{}", std::process::id()));
+        let lines: Vec<String> = (0..MAX_ENTRIES + 5).map(|i| i.to_string()).collect();
+        fs::write(&path, lines.join("\n")).unwrap();
+
+        let history = History::load(&path);
+        assert_eq!(history.entries().len(), MAX_ENTRIES);
+        assert_eq!(history.entries()[0], "5");
+        assert_eq!(
+            fs::read_to_string(&path).unwrap().lines().count(),
+            MAX_ENTRIES
+        );
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,130 @@
+//! BREAK conditions received on a serial line.
+//!
+//! A break reaches the reader as a plain `\0` byte, so it is detected by the
+//! break counter of the driver (TIOCGICOUNT) instead, and shown in the
+//! device output where it happened. On Windows ClearCommError tells whether
+//! there was a break since it was last asked.
+
+#[cfg(unix)]
+use std::io::{Error, Result};
+#[cfg(unix)]
+use std::os::unix::io::RawFd;
+#[cfg(windows)]
+use std::os::windows::io::AsRawHandle;
+#[cfg(windows)]
+use windows_sys::Win32::Devices::Communication::{CE_BREAK, ClearCommError};
+
+/// Inserted into the device output after a break
+pub const MESSAGE: &[u8] = b"\r\n*** BREAK received ***\r\n";
+
+/// `struct serial_icounter_struct` of linux/serial.h
+#[cfg(unix)]
+#[repr(C)]
+#[derive(Debug, Default)]
+struct SerialIcounter {
+    cts: i32,
+    dsr: i32,
+    rng: i32,
+    dcd: i32,
+    rx: i32,
+    tx: i32,
+    frame: i32,
+    overrun: i32,
+    parity: i32,
+    brk: i32,
+    buf_overrun: i32,
+    reserved: [i32; 9],
+}
+
+/// Breaks received by the port of `fd` since it was opened.
+#[cfg(unix)]
+fn break_count(fd: RawFd) -> Result<i32> {
+    let mut counters = SerialIcounter::default();
+    let res = unsafe { libc::ioctl(fd, libc::TIOCGICOUNT as _, &mut counters) };
+    if res < 0 {
+        Err(Error::last_os_error())
+    } else {
+        Ok(counters.brk)
+    }
+}
+
+/// Watches the break counter of a port. Drivers without counters are not
+/// asked again.
+#[derive(Debug, Default)]
+pub struct BreakDetector {
+    last: Option<i32>,
+    unsupported: bool,
+}
+
+impl BreakDetector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of breaks since the last call.
+    #[cfg(unix)]
+    pub fn check(&mut self, fd: RawFd) -> u32 {
+        if self.unsupported {
+            return 0;
+        }
+        match break_count(fd) {
+            Ok(count) => self.update(count),
+            Err(_) => {
+                self.unsupported = true;
+                0
+            }
+        }
+    }
+
+    /// Whether there were breaks since the last call, as 1 or 0.
+    #[cfg(windows)]
+    pub fn check(&mut self, port: &impl AsRawHandle) -> u32 {
+        if self.unsupported {
+            return 0;
+        }
+        let mut errors = 0;
+        if unsafe { ClearCommError(port.as_raw_handle(), &mut errors, std::ptr::null_mut()) } == 0 {
+            self.unsupported = true;
+            return 0;
+        }
+        let count = self.last.unwrap_or(0) + i32::from(errors & CE_BREAK != 0);
+        self.update(count)
+    }
+
+    fn update(&mut self, count: i32) -> u32 {
+        let new = match self.last {
+            Some(last) => count.wrapping_sub(last).max(0) as u32,
+            None => 0,
+        };
+        self.last = Some(count);
+        new
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counter() {
+        #[cfg(unix)]
+        assert_eq!(std::mem::size_of::<SerialIcounter>(), 80);
+
+        let mut d = BreakDetector::new();
+        // Breaks from before are not reported
+        assert_eq!(d.update(3), 0);
+        assert_eq!(d.update(3), 0);
+        assert_eq!(d.update(5), 2);
+        assert_eq!(d.update(5), 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unsupported() {
+        // Not a serial port
+        let (a, _b) = std::os::unix::net::UnixStream::pair().unwrap();
+        let mut d = BreakDetector::new();
+        assert_eq!(d.check(std::os::unix::io::AsRawFd::as_raw_fd(&a)), 0);
+        assert!(d.unsupported);
+    }
+}
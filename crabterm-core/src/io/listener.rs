@@ -0,0 +1,343 @@
+//! Listeners given with `-p`, as `[ROLES:]PORT` or `[ROLES:]unix:PATH`,
+//! e.g. `-p 4000 -p ro:4001 -p auth,quiet:unix:/run/crabterm.sock` or
+//! `-p gdb:3333`. Unix sockets also take who may use them, e.g.
+//! `-p mode=660,group=board1,allow-group=board1:unix:/run/crabterm/board1.sock`.
+//! TCP listeners take the output rate of their clients, e.g. `-p ro,rate=8k:4001`.
+
+use super::read_buffer;
+use crate::iofilter::CharmapFilter;
+
+#[cfg(unix)]
+use std::ffi::CString;
+use std::path::PathBuf;
+
+/// What the clients of a listener may do
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ListenerRole {
+    /// Input of the clients is dropped (`ro`)
+    pub read_only: bool,
+    /// Clients must send the password before they see or send anything
+    /// (`auth`)
+    pub auth: bool,
+    /// Clients get the announcements; off with `quiet`
+    pub announce: bool,
+    /// Line ends of the output to the clients (`crlf`, `lf`)
+    pub newline: Option<Newline>,
+    /// Raw passthrough for a debugger such as gdb (`gdb`): no filters,
+    /// keybinds, control strings or announcements, and while it talks to
+    /// the device the input of the other clients waits
+    pub gdb: bool,
+}
+
+/// Line ends a client wants, whatever the device sends
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Newline {
+    /// CR LF, e.g. for telnet
+    Crlf,
+    /// LF alone, e.g. for nc and scripts
+    Lf,
+}
+
+impl Newline {
+    /// The charmap that converts the output of the device: CRs are dropped,
+    /// and LFs made CR LF if wanted.
+    pub fn charmap(self) -> CharmapFilter {
+        match self {
+            Newline::Crlf => CharmapFilter::with_mappings("igncr,lfcrlf", ""),
+            Newline::Lf => CharmapFilter::with_mappings("igncr", ""),
+        }
+    }
+}
+
+impl Default for ListenerRole {
+    fn default() -> Self {
+        ListenerRole {
+            read_only: false,
+            auth: false,
+            announce: true,
+            newline: None,
+            gdb: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListenTarget {
+    Tcp(u16),
+    Unix(PathBuf),
+}
+
+/// Who may use the socket of a `unix:` listener
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SocketAccess {
+    /// Permission bits of the socket file (`mode=660`)
+    pub mode: Option<u32>,
+    /// Owner and group of the socket file (`owner=`, `group=`)
+    pub owner: Option<u32>,
+    pub group: Option<u32>,
+    /// The users and the members of the groups that may connect
+    /// (`allow-user=`, `allow-group=`); with neither, whoever can open the
+    /// socket file
+    pub allow_users: Vec<u32>,
+    pub allow_groups: Vec<u32>,
+}
+
+impl SocketAccess {
+    /// Whether the peer credentials are checked
+    pub fn restricted(&self) -> bool {
+        !self.allow_users.is_empty() || !self.allow_groups.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListenSpec {
+    pub target: ListenTarget,
+    pub role: ListenerRole,
+    pub access: SocketAccess,
+    /// Bytes per second written to each client (`rate=`), instead of
+    /// `client-rate-limit`
+    pub rate_limit: Option<usize>,
+}
+
+/// The uid of a user name or number.
+#[cfg(unix)]
+pub fn user_id(name: &str) -> Result<u32, String> {
+    if let Ok(uid) = name.parse() {
+        return Ok(uid);
+    }
+    let c_name = CString::new(name).map_err(|_| format!("Invalid user: {}", name))?;
+    // SAFETY: a NUL terminated string; the entry is read before any other
+    // call that may reuse it
+    let pw = unsafe { libc::getpwnam(c_name.as_ptr()) };
+    if pw.is_null() {
+        return Err(format!("Unknown user: {}", name));
+    }
+    Ok(unsafe { (*pw).pw_uid })
+}
+
+/// The gid of a group name or number.
+#[cfg(unix)]
+pub fn group_id(name: &str) -> Result<u32, String> {
+    if let Ok(gid) = name.parse() {
+        return Ok(gid);
+    }
+    let c_name = CString::new(name).map_err(|_| format!("Invalid group: {}", name))?;
+    // SAFETY: as in user_id
+    let gr = unsafe { libc::getgrnam(c_name.as_ptr()) };
+    if gr.is_null() {
+        return Err(format!("Unknown group: {}", name));
+    }
+    Ok(unsafe { (*gr).gr_gid })
+}
+
+/// Windows has no user names to look up, nor unix listeners to use them.
+#[cfg(windows)]
+pub fn user_id(name: &str) -> Result<u32, String> {
+    name.parse().map_err(|_| format!("Unknown user: {}", name))
+}
+
+#[cfg(windows)]
+pub fn group_id(name: &str) -> Result<u32, String> {
+    name.parse().map_err(|_| format!("Unknown group: {}", name))
+}
+
+/// Parse `KEY=VALUE` of a unix listener into `access`.
+fn parse_access(access: &mut SocketAccess, key: &str, value: &str) -> Result<(), String> {
+    match key {
+        "mode" => {
+            let mode = u32::from_str_radix(value, 8)
+                .ok()
+                .filter(|m| *m <= 0o777)
+                .ok_or_else(|| format!("Invalid mode: {} (octal, e.g. 660)", value))?;
+            access.mode = Some(mode);
+        }
+        "owner" => access.owner = Some(user_id(value)?),
+        "group" => access.group = Some(group_id(value)?),
+        "allow-user" => access.allow_users.push(user_id(value)?),
+        "allow-group" => access.allow_groups.push(group_id(value)?),
+        _ => {
+            return Err(format!(
+                "Unknown listener setting: {} (rate, mode, owner, group, allow-user, allow-group)",
+                key
+            ));
+        }
+    }
+    Ok(())
+}
+
+pub fn parse(val: &str) -> Result<ListenSpec, String> {
+    let (roles, target) = match val.split_once(':') {
+        Some(("unix", _)) | None => ("", val),
+        Some((roles, target)) => (roles, target),
+    };
+    let mut role = ListenerRole::default();
+    let mut access = SocketAccess::default();
+    let mut rate_limit = None;
+    for name in roles.split(',').filter(|r| !r.is_empty()) {
+        match name.split_once('=') {
+            Some(("rate", value)) => {
+                let rate = read_buffer::parse_size(value).ok_or_else(|| {
+                    format!("Invalid rate: {} (bytes per second, e.g. 8k)", value)
+                })?;
+                rate_limit = Some(rate);
+                continue;
+            }
+            Some((key, value)) => {
+                parse_access(&mut access, key, value)?;
+                continue;
+            }
+            None => {}
+        }
+        match name {
+            "ro" => role.read_only = true,
+            "auth" => role.auth = true,
+            "quiet" => role.announce = false,
+            "crlf" => role.newline = Some(Newline::Crlf),
+            "lf" => role.newline = Some(Newline::Lf),
+            "gdb" => {
+                role.gdb = true;
+                role.announce = false;
+            }
+            _ => {
+                return Err(format!(
+                    "Unknown listener role: {} (ro, auth, quiet, crlf, lf, gdb)",
+                    name
+                ));
+            }
+        }
+    }
+    if role.gdb && role.newline.is_some() {
+        return Err(format!("gdb clients get the raw output: {}", val));
+    }
+    let target = match target.strip_prefix("unix:") {
+        Some("") => return Err(format!("unix: needs a socket path: {}", val)),
+        Some(path) => ListenTarget::Unix(PathBuf::from(path)),
+        None => ListenTarget::Tcp(
+            target
+                .parse()
+                .map_err(|_| format!("Invalid port: {} (PORT or unix:PATH)", target))?,
+        ),
+    };
+    if matches!(target, ListenTarget::Tcp(_)) && access != SocketAccess::default() {
+        return Err(format!("Socket access is for unix: listeners: {}", val));
+    }
+    if matches!(target, ListenTarget::Unix(_)) && rate_limit.is_some() {
+        return Err(format!("rate is for TCP listeners: {}", val));
+    }
+    Ok(ListenSpec {
+        target,
+        role,
+        access,
+        rate_limit,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::iofilter::IoFilter;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse("4000"),
+            Ok(ListenSpec {
+                target: ListenTarget::Tcp(4000),
+                role: ListenerRole::default(),
+                access: SocketAccess::default(),
+                rate_limit: None,
+            })
+        );
+        assert_eq!(
+            parse("ro:4001"),
+            Ok(ListenSpec {
+                target: ListenTarget::Tcp(4001),
+                role: ListenerRole {
+                    read_only: true,
+                    ..Default::default()
+                },
+                access: SocketAccess::default(),
+                rate_limit: None,
+            })
+        );
+        assert_eq!(
+            parse("unix:/run/crabterm.sock"),
+            Ok(ListenSpec {
+                target: ListenTarget::Unix(PathBuf::from("/run/crabterm.sock")),
+                role: ListenerRole::default(),
+                access: SocketAccess::default(),
+                rate_limit: None,
+            })
+        );
+        assert_eq!(
+            parse("auth,quiet:unix:/tmp/c.sock"),
+            Ok(ListenSpec {
+                target: ListenTarget::Unix(PathBuf::from("/tmp/c.sock")),
+                role: ListenerRole {
+                    read_only: false,
+                    auth: true,
+                    announce: false,
+                    newline: None,
+                    gdb: false,
+                },
+                access: SocketAccess::default(),
+                rate_limit: None,
+            })
+        );
+        assert_eq!(
+            parse("ro,crlf:4002").map(|s| s.role.newline),
+            Ok(Some(Newline::Crlf))
+        );
+        let role = parse("gdb:3333").unwrap().role;
+        assert!(role.gdb && !role.announce);
+        let spec = parse("ro,rate=8k:4001").unwrap();
+        assert!(spec.role.read_only);
+        assert_eq!(spec.rate_limit, Some(8192));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_parse_access() {
+        let spec =
+            parse("ro,mode=660,group=0,allow-user=root,allow-group=0:unix:/tmp/c.sock").unwrap();
+        assert!(spec.role.read_only);
+        assert_eq!(
+            spec.access,
+            SocketAccess {
+                mode: Some(0o660),
+                owner: None,
+                group: Some(0),
+                allow_users: vec![0],
+                allow_groups: vec![0],
+            }
+        );
+        assert!(spec.access.restricted());
+        assert!(parse("mode=660:4000").is_err());
+        assert!(parse("mode=999:unix:/tmp/c.sock").is_err());
+        assert!(parse("allow-user=no-such-user-here:unix:/tmp/c.sock").is_err());
+        assert!(parse("size=1:unix:/tmp/c.sock").is_err());
+    }
+
+    #[test]
+    fn test_newline() {
+        let convert = |newline: Newline, buf: &[u8]| {
+            let mut out = Vec::new();
+            newline.charmap().filter_out(buf, &mut out);
+            out
+        };
+        assert_eq!(convert(Newline::Crlf, b"a\r\nb\nc"), b"a\r\nb\r\nc");
+        assert_eq!(convert(Newline::Lf, b"a\r\nb\nc"), b"a\nb\nc");
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(parse("port").is_err());
+        assert!(parse("ro:").is_err());
+        assert!(parse("unix:").is_err());
+        assert!(parse("rw:4000").is_err());
+        assert!(parse("gdb,lf:3333").is_err());
+        assert!(parse("70000").is_err());
+        assert!(parse("rate=fast:4000").is_err());
+        assert!(parse("rate=8k:unix:/tmp/c.sock").is_err());
+    }
+}
@@ -0,0 +1,205 @@
+use log::info;
+#[cfg(windows)]
+use mio::net::TcpStream as Stream;
+#[cfg(unix)]
+use mio::net::UnixStream as Stream;
+use mio::{Interest, Token};
+use std::io::{Error, ErrorKind, IoSlice, Read, Result, Write};
+
+#[cfg(windows)]
+use super::loopback;
+use super::read_buffer::ReadBuffer;
+use crate::poll::Poll;
+use crate::traits::{IoInstance, IoResult};
+
+/// The far end of [`LoopDevice::with_peer`]: a unix socket, or a loopback
+/// TCP socket on Windows.
+#[cfg(unix)]
+pub type PeerStream = std::os::unix::net::UnixStream;
+#[cfg(windows)]
+pub type PeerStream = std::net::TcpStream;
+
+/// One end of an in-process, connected device pair.
+///
+/// Whatever is written to one end can be read from the other, which makes it
+/// possible to test the hub (or anything else driving an [`IoInstance`])
+/// without PTYs or external tools. Either end can be used as the hub device
+/// or as a client. Once the peer is dropped, reads fail and the end can not
+/// be connected again.
+pub struct LoopDevice {
+    name: String,
+    stream: Option<Stream>,
+    registered: bool,
+    zombie: bool,
+    read_buf: ReadBuffer,
+}
+
+impl LoopDevice {
+    fn new(name: &str, stream: Stream) -> Self {
+        LoopDevice {
+            name: name.to_string(),
+            stream: Some(stream),
+            registered: false,
+            zombie: false,
+            read_buf: ReadBuffer::new(),
+        }
+    }
+
+    /// Create a connected pair named "Loop" and "Loop-peer".
+    pub fn pair() -> Result<(LoopDevice, LoopDevice)> {
+        #[cfg(unix)]
+        let (a, b) = Stream::pair()?;
+        #[cfg(windows)]
+        let (a, b) = loopback::new()?;
+        Ok((LoopDevice::new("Loop", a), LoopDevice::new("Loop-peer", b)))
+    }
+
+    /// Create a pair where the far end is a blocking std stream, convenient
+    /// for driving the device from a test thread.
+    pub fn with_peer() -> Result<(LoopDevice, PeerStream)> {
+        #[cfg(unix)]
+        let (a, b) = PeerStream::pair()?;
+        #[cfg(windows)]
+        let (a, b) = loopback::pair()?;
+        a.set_nonblocking(true)?;
+        Ok((LoopDevice::new("Loop", Stream::from_std(a)), b))
+    }
+
+    /// Rename this end, e.g. to tell several loop devices apart.
+    pub fn with_name(mut self, name: &str) -> Self {
+        self.name = name.to_string();
+        self
+    }
+
+    fn zombie(&mut self, err: Error) -> Error {
+        info!("{}: {} -> zombie", self.name, err);
+        self.zombie = true;
+        err
+    }
+}
+
+impl IoInstance for LoopDevice {
+    fn connect(&mut self, poll: &mut Poll, token: Token) -> Result<()> {
+        let Some(s) = &mut self.stream else {
+            return Err(Error::new(ErrorKind::NotConnected, "peer closed"));
+        };
+        poll.registry().register(s, token, Interest::READABLE)?;
+        self.registered = true;
+        info!("{}: connected", self.name);
+        Ok(())
+    }
+
+    fn addr_as_string(&self) -> String {
+        self.name.clone()
+    }
+
+    fn connected(&self) -> bool {
+        self.registered && !self.zombie
+    }
+
+    fn disconnect_needed(&self) -> bool {
+        self.zombie
+    }
+
+    fn disconnect(&mut self, poll: &mut Poll) {
+        if let Some(s) = &mut self.stream
+            && self.registered
+        {
+            let _ = poll.registry().deregister(s);
+        }
+        self.registered = false;
+        if self.zombie {
+            self.stream = None;
+            self.zombie = false;
+        }
+    }
+
+    fn read(&mut self) -> Result<IoResult> {
+        let tmp = self.read_buf.get();
+        let Some(s) = &mut self.stream else {
+            return Ok(IoResult::None);
+        };
+        match s.read(tmp) {
+            Ok(0) => Err(self.zombie(Error::new(ErrorKind::UnexpectedEof, "EOF"))),
+            Ok(n) => Ok(IoResult::Data(tmp[..n].into())),
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => Ok(IoResult::None),
+            Err(e) => Err(self.zombie(e)),
+        }
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<IoResult> {
+        let Some(s) = &mut self.stream else {
+            return Ok(IoResult::None);
+        };
+        match s.write(buf) {
+            Ok(n) => Ok(IoResult::Data(buf[..n].into())),
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
+                Ok(IoResult::Data(Vec::new().into()))
+            }
+            Err(e) => Err(self.zombie(e)),
+        }
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> Result<usize> {
+        let Some(s) = &mut self.stream else {
+            return Ok(0);
+        };
+        match s.write_vectored(bufs) {
+            Ok(n) => Ok(n),
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => Ok(0),
+            Err(e) => Err(self.zombie(e)),
+        }
+    }
+
+    fn flush(&mut self) {
+        if let Some(s) = &mut self.stream {
+            let _ = s.flush();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::poll::Events;
+    use std::time::Duration;
+
+    fn read_all(poll: &mut Poll, dev: &mut LoopDevice) -> Vec<u8> {
+        let mut events = Events::with_capacity(4);
+        poll.poll(&mut events, Some(Duration::from_millis(500)))
+            .unwrap();
+        let mut out = Vec::new();
+        while let Ok(IoResult::Data(d)) = dev.read() {
+            out.extend_from_slice(&d);
+        }
+        out
+    }
+
+    #[test]
+    fn test_pair() {
+        let mut poll = Poll::new().unwrap();
+        let (mut a, mut b) = LoopDevice::pair().unwrap();
+        a.connect(&mut poll, Token(0)).unwrap();
+        b.connect(&mut poll, Token(1)).unwrap();
+
+        assert_eq!(a.write_all(b"ping"), 4);
+        assert_eq!(read_all(&mut poll, &mut b), b"ping");
+        assert_eq!(b.write_all(b"pong"), 4);
+        assert_eq!(read_all(&mut poll, &mut a), b"pong");
+    }
+
+    #[test]
+    fn test_peer_closed() {
+        let mut poll = Poll::new().unwrap();
+        let (mut dev, peer) = LoopDevice::with_peer().unwrap();
+        dev.connect(&mut poll, Token(0)).unwrap();
+        drop(peer);
+
+        assert!(dev.read().is_err());
+        assert!(!dev.connected());
+        assert!(dev.disconnect_needed());
+        dev.disconnect(&mut poll);
+        assert!(!dev.connected());
+        assert!(dev.connect(&mut poll, Token(0)).is_err());
+    }
+}
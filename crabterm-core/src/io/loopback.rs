@@ -0,0 +1,36 @@
+//! Connected pairs of loopback TCP sockets, which stand in for pipes on
+//! Windows: mio polls sockets there, but not pipes or the console.
+
+use std::io::{Error, ErrorKind, Result};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+
+/// A connected pair of blocking sockets, what is written to the first is
+/// read from the second.
+pub fn pair() -> Result<(TcpStream, TcpStream)> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+    let writer = TcpStream::connect(listener.local_addr()?)?;
+    // Anything on the host can connect to the port, only take our own
+    for _ in 0..16 {
+        let (reader, peer) = listener.accept()?;
+        if peer == writer.local_addr()? {
+            writer.set_nodelay(true)?;
+            return Ok((writer, reader));
+        }
+    }
+    Err(Error::new(
+        ErrorKind::ConnectionRefused,
+        "loopback pair: connections from elsewhere",
+    ))
+}
+
+/// A non-blocking [`pair`] for the poll, like `mio::unix::pipe::new()`: the
+/// sender and the receiver.
+pub fn new() -> Result<(mio::net::TcpStream, mio::net::TcpStream)> {
+    let (sender, receiver) = pair()?;
+    sender.set_nonblocking(true)?;
+    receiver.set_nonblocking(true)?;
+    Ok((
+        mio::net::TcpStream::from_std(sender),
+        mio::net::TcpStream::from_std(receiver),
+    ))
+}
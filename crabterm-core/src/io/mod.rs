@@ -0,0 +1,46 @@
+pub mod autobaud;
+pub mod bell;
+pub mod by_id;
+pub mod command_line;
+pub mod console;
+#[cfg(unix)]
+pub mod device_lock;
+pub mod echo_device;
+pub mod failover_device;
+pub mod history;
+pub mod line_break;
+pub mod listener;
+pub mod loop_device;
+#[cfg(windows)]
+pub mod loopback;
+pub mod output_buffer;
+pub mod parmrk;
+pub mod qemu_device;
+pub mod read_buffer;
+pub mod remote_device;
+pub mod rs485;
+pub mod scrollback;
+pub mod serial_device;
+pub mod stdio;
+pub mod tcp_device;
+pub mod tcp_server;
+pub mod telnet_device;
+#[cfg(unix)]
+pub mod unix_device;
+#[cfg(unix)]
+pub mod unix_server;
+
+pub use console::Console;
+pub use echo_device::EchoDevice;
+pub use failover_device::FailoverDevice;
+pub use loop_device::LoopDevice;
+pub use qemu_device::QemuDevice;
+pub use remote_device::RemoteDevice;
+pub use serial_device::SerialDevice;
+pub use tcp_device::TcpDevice;
+pub use tcp_server::TcpServer;
+pub use telnet_device::TelnetDevice;
+#[cfg(unix)]
+pub use unix_device::UnixDevice;
+#[cfg(unix)]
+pub use unix_server::UnixServer;
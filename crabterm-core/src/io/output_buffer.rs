@@ -0,0 +1,366 @@
+//! Output for a client that its socket has not accepted yet.
+
+use std::collections::VecDeque;
+use std::io::{ErrorKind, Result, Write};
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::tcp_server::{ClientPolicy, SlowClientPolicy};
+use crate::clock;
+
+/// A rate limited buffer wakes up for at least this share of a second of
+/// output, rather than for every few bytes
+const RATE_WAKEUPS_PER_SEC: usize = 20;
+
+/// What [`OutputBuffer::push`] did with the data
+#[derive(Debug, PartialEq)]
+pub enum Pushed {
+    /// All of it is queued
+    All,
+    /// This many bytes did not fit and were dropped (drop-data)
+    Dropped(usize),
+    /// It did not fit, the client is to be disconnected (disconnect)
+    Overflow,
+}
+
+/// A queue of output bounded by the client policy's buffer limit. The limit
+/// is the high watermark: reaching it makes the buffer backlogged, and it
+/// stays so until drained to the low watermark (half the limit), so a blocked
+/// hub does not resume for every few bytes written.
+///
+/// Output is kept as ranges of shared chunks, so data broadcast to several
+/// clients is not copied for each of them.
+///
+/// With a rate limit, writing is paced by a token bucket holding up to a
+/// second of output; what is held back counts toward the buffer limit like
+/// output the socket did not take, so a flood meets the slow client policy.
+#[derive(Debug)]
+pub struct OutputBuffer {
+    chunks: VecDeque<(Arc<[u8]>, Range<usize>)>,
+    len: usize,
+    policy: ClientPolicy,
+    backlogged: bool,
+    /// Bytes that may be written now under the rate limit
+    allowance: usize,
+    /// When `allowance` was last topped up
+    refilled: Instant,
+}
+
+impl OutputBuffer {
+    pub fn new(policy: ClientPolicy) -> Self {
+        OutputBuffer {
+            chunks: VecDeque::new(),
+            len: 0,
+            policy,
+            backlogged: false,
+            allowance: policy.rate_limit.unwrap_or(0),
+            refilled: clock::now(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn policy(&self) -> ClientPolicy {
+        self.policy
+    }
+
+    /// Between reaching the high watermark and draining to the low one
+    pub fn backlogged(&self) -> bool {
+        self.backlogged
+    }
+
+    fn low_watermark(&self) -> usize {
+        self.policy.buffer_limit / 2
+    }
+
+    /// Output goes through the buffer to be paced, rather than straight to
+    /// the socket
+    pub fn rate_limited(&self) -> bool {
+        self.policy.rate_limit.is_some()
+    }
+
+    /// When held back output may be written again. None without a rate
+    /// limit, or when only the socket is holding it back.
+    pub fn next_write(&self) -> Option<Instant> {
+        let rate = self.policy.rate_limit?;
+        if self.is_empty() || self.allowance > 0 {
+            return None;
+        }
+        let bytes = self.len.min(rate / RATE_WAKEUPS_PER_SEC).max(1);
+        Some(self.refilled + bytes_time(bytes, rate))
+    }
+
+    /// Top up the allowance for the time since the last refill, up to a
+    /// second of output.
+    fn refill(&mut self, now: Instant) {
+        let Some(rate) = self.policy.rate_limit else {
+            return;
+        };
+        let elapsed = now.saturating_duration_since(self.refilled);
+        let earned = (elapsed.as_nanos() * rate as u128 / 1_000_000_000) as usize;
+        if self.allowance + earned >= rate {
+            self.allowance = rate;
+            self.refilled = now;
+        } else if earned > 0 {
+            // Only the time of whole bytes is used up, so frequent refills
+            // do not lose the fractions
+            self.allowance += earned;
+            self.refilled += bytes_time(earned, rate);
+        }
+    }
+
+    /// Queue `data[from..]`, applying the slow client policy to what does
+    /// not fit.
+    pub fn push(&mut self, data: Arc<[u8]>, from: usize) -> Pushed {
+        let size = data.len() - from;
+        let room = self.policy.buffer_limit.saturating_sub(self.len);
+        let (end, pushed) = if size <= room {
+            (data.len(), Pushed::All)
+        } else {
+            match self.policy.slow {
+                SlowClientPolicy::DropData => (from + room, Pushed::Dropped(size - room)),
+                // Goes over the limit, the hub stops reading the devices
+                // while backlogged so it is by at most one read
+                SlowClientPolicy::Block => (data.len(), Pushed::All),
+                SlowClientPolicy::Disconnect => return Pushed::Overflow,
+            }
+        };
+        self.queue(data, from..end);
+        pushed
+    }
+
+    /// Like [`Self::push`] for data that must not be cut, e.g. a frame:
+    /// with drop-data it is dropped whole, unless part of it has been
+    /// written already, then the rest is queued over the limit.
+    pub fn push_whole(&mut self, data: Arc<[u8]>, from: usize) -> Pushed {
+        if self.policy.slow != SlowClientPolicy::DropData {
+            return self.push(data, from);
+        }
+        let size = data.len() - from;
+        if from == 0 && size > self.policy.buffer_limit.saturating_sub(self.len) {
+            return Pushed::Dropped(size);
+        }
+        let end = data.len();
+        self.queue(data, from..end);
+        Pushed::All
+    }
+
+    fn queue(&mut self, data: Arc<[u8]>, range: Range<usize>) {
+        if !range.is_empty() {
+            self.len += range.len();
+            self.chunks.push_back((data, range));
+        }
+        if self.len > 0 && self.len >= self.policy.buffer_limit {
+            self.backlogged = true;
+        }
+    }
+
+    /// Write to `w` until the buffer is empty, `w` would block or the rate
+    /// limit is reached.
+    pub fn write_to(&mut self, w: &mut impl Write) -> Result<()> {
+        self.write_to_at(w, clock::now())
+    }
+
+    fn write_to_at(&mut self, w: &mut impl Write, now: Instant) -> Result<()> {
+        self.refill(now);
+        let limited = self.rate_limited();
+        while let Some((data, range)) = self.chunks.front_mut() {
+            let mut end = range.end;
+            if limited {
+                if self.allowance == 0 {
+                    break;
+                }
+                end = end.min(range.start + self.allowance);
+            }
+            match w.write(&data[range.start..end]) {
+                Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                Ok(n) => {
+                    range.start += n;
+                    self.len -= n;
+                    if limited {
+                        self.allowance -= n;
+                    }
+                    if range.start == range.end {
+                        self.chunks.pop_front();
+                    }
+                }
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            }
+        }
+        if self.len <= self.low_watermark() {
+            self.backlogged = false;
+        }
+        Ok(())
+    }
+}
+
+/// How long `bytes` take at `rate` bytes per second
+fn bytes_time(bytes: usize, rate: usize) -> Duration {
+    Duration::from_nanos((bytes as u128 * 1_000_000_000 / rate as u128) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Accepts up to `room` bytes, then would block
+    struct Sink {
+        data: Vec<u8>,
+        room: usize,
+    }
+
+    impl Write for Sink {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            if self.room == 0 {
+                return Err(ErrorKind::WouldBlock.into());
+            }
+            let n = buf.len().min(self.room);
+            self.data.extend_from_slice(&buf[..n]);
+            self.room -= n;
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    fn buffer(slow: SlowClientPolicy) -> OutputBuffer {
+        OutputBuffer::new(ClientPolicy {
+            slow,
+            buffer_limit: 8,
+            rate_limit: None,
+        })
+    }
+
+    fn chunk(data: &[u8]) -> Arc<[u8]> {
+        data.into()
+    }
+
+    #[test]
+    fn test_policies() {
+        let mut b = buffer(SlowClientPolicy::DropData);
+        assert_eq!(b.push(chunk(b"0123456789"), 0), Pushed::Dropped(2));
+        assert_eq!(b.len(), 8);
+
+        let mut b = buffer(SlowClientPolicy::Disconnect);
+        assert_eq!(b.push(chunk(b"0123"), 0), Pushed::All);
+        assert_eq!(b.push(chunk(b"45678"), 0), Pushed::Overflow);
+        assert_eq!(b.len(), 4);
+
+        let mut b = buffer(SlowClientPolicy::Block);
+        assert_eq!(b.push(chunk(b"0123456789"), 0), Pushed::All);
+        assert_eq!(b.len(), 10);
+    }
+
+    #[test]
+    fn test_push_whole() {
+        let mut b = buffer(SlowClientPolicy::DropData);
+        assert_eq!(b.push_whole(chunk(b"012345"), 0), Pushed::All);
+        assert_eq!(b.push_whole(chunk(b"abc"), 0), Pushed::Dropped(3));
+        assert_eq!(b.len(), 6);
+        // Partly written, the rest is kept
+        assert_eq!(b.push_whole(chunk(b"abcdef"), 1), Pushed::All);
+        assert_eq!(b.len(), 11);
+
+        let mut b = buffer(SlowClientPolicy::Disconnect);
+        assert_eq!(b.push_whole(chunk(b"0123456789"), 0), Pushed::Overflow);
+    }
+
+    #[test]
+    fn test_shared_chunks() {
+        let data = chunk(b"abcdef");
+        let mut b1 = buffer(SlowClientPolicy::DropData);
+        let mut b2 = buffer(SlowClientPolicy::DropData);
+        b1.push(data.clone(), 2);
+        b2.push(data.clone(), 0);
+        assert_eq!(Arc::strong_count(&data), 3);
+
+        let mut sink = Sink {
+            data: Vec::new(),
+            room: 100,
+        };
+        b1.write_to(&mut sink).unwrap();
+        assert_eq!(sink.data, b"cdef");
+        assert_eq!(Arc::strong_count(&data), 2);
+    }
+
+    #[test]
+    fn test_watermarks() {
+        let mut b = buffer(SlowClientPolicy::Block);
+        b.push(chunk(b"0123456"), 0);
+        assert!(!b.backlogged());
+        b.push(chunk(b"789"), 0);
+        assert!(b.backlogged());
+
+        // Still backlogged above the low watermark (4)
+        let mut sink = Sink {
+            data: Vec::new(),
+            room: 5,
+        };
+        b.write_to(&mut sink).unwrap();
+        assert_eq!(b.len(), 5);
+        assert!(b.backlogged());
+
+        sink.room = 1;
+        b.write_to(&mut sink).unwrap();
+        assert!(!b.backlogged());
+
+        sink.room = 100;
+        b.write_to(&mut sink).unwrap();
+        assert!(b.is_empty());
+        assert_eq!(sink.data, b"0123456789");
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let mut b = OutputBuffer::new(ClientPolicy {
+            slow: SlowClientPolicy::DropData,
+            buffer_limit: 1000,
+            rate_limit: Some(100),
+        });
+        let start = b.refilled;
+        let mut sink = Sink {
+            data: Vec::new(),
+            room: 1000,
+        };
+        assert!(b.rate_limited());
+        assert_eq!(b.next_write(), None);
+
+        // A second of output at once, then it is paced
+        b.push(chunk(&[b'x'; 300]), 0);
+        b.write_to_at(&mut sink, start).unwrap();
+        assert_eq!(sink.data.len(), 100);
+        assert_eq!(b.next_write(), Some(start + Duration::from_millis(50)));
+
+        b.write_to_at(&mut sink, start + Duration::from_millis(50))
+            .unwrap();
+        assert_eq!(sink.data.len(), 105);
+        // No more than a second of output after a pause
+        b.write_to_at(&mut sink, start + Duration::from_millis(1055))
+            .unwrap();
+        assert_eq!(sink.data.len(), 205);
+        // Half a byte is kept for the next refill
+        b.write_to_at(&mut sink, start + Duration::from_millis(1060))
+            .unwrap();
+        assert_eq!(sink.data.len(), 205);
+        b.write_to_at(&mut sink, start + Duration::from_millis(1065))
+            .unwrap();
+        assert_eq!(sink.data.len(), 206);
+        assert_eq!(b.len(), 94);
+
+        // Idle time fills the bucket up to a second
+        b.write_to_at(&mut sink, start + Duration::from_secs(10))
+            .unwrap();
+        assert!(b.is_empty());
+        assert_eq!(b.next_write(), None);
+    }
+}
@@ -0,0 +1,136 @@
+//! Parity and framing errors made visible (`--mark-errors`).
+//!
+//! With PARMRK and INPCK set on a serial port the driver passes a byte
+//! received with a parity or framing error (or a break) as `\xff \0 byte`,
+//! and a real `\xff` as `\xff \xff`. [`ErrorMarks`] turns the former into an
+//! inverse "?" and undoes the latter, so a wrong baudrate or bad wiring shows
+//! up instead of passing corrupted bytes on silently.
+
+use log::warn;
+use std::io::Result;
+#[cfg(unix)]
+use std::os::unix::io::RawFd;
+#[cfg(windows)]
+use std::os::windows::io::RawHandle;
+#[cfg(unix)]
+use termios::{IGNPAR, INPCK, ISTRIP, PARMRK, TCSANOW, Termios, tcsetattr};
+
+/// What a byte received with an error is shown as
+pub const MARKER: &[u8] = b"\x1b[7m?\x1b[27m";
+
+/// Have the driver of `fd` mark bytes received with errors.
+#[cfg(unix)]
+pub fn enable(fd: RawFd) -> Result<()> {
+    let mut t = Termios::from_fd(fd)?;
+    t.c_iflag |= PARMRK | INPCK;
+    t.c_iflag &= !(IGNPAR | ISTRIP);
+    tcsetattr(fd, TCSANOW, &t)
+}
+
+/// Windows drivers only count errors, they cannot mark the bytes.
+#[cfg(windows)]
+pub fn enable(_: RawHandle) -> Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "marking errors is not supported on Windows",
+    ))
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum State {
+    #[default]
+    Data,
+    /// After `\xff`
+    Mark,
+    /// After `\xff \0`
+    Error,
+}
+
+/// Decodes the PARMRK marking of a serial port's input. A sequence may be
+/// split over several reads.
+#[derive(Debug, Default)]
+pub struct ErrorMarks {
+    state: State,
+    /// Bytes received so far, for the offset of errors
+    offset: u64,
+    errors: u64,
+}
+
+impl ErrorMarks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Errors seen so far.
+    pub fn errors(&self) -> u64 {
+        self.errors
+    }
+
+    /// Decode `buf` read from the device `name`; errors are logged with
+    /// their offset in the device output.
+    pub fn decode(&mut self, name: &str, buf: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(buf.len());
+        for &b in buf {
+            self.state = match (self.state, b) {
+                (State::Data, 0xff) => State::Mark,
+                (State::Data, _) => {
+                    out.push(b);
+                    State::Data
+                }
+                (State::Mark, 0xff) => {
+                    out.push(0xff);
+                    State::Data
+                }
+                (State::Mark, 0) => State::Error,
+                // Not a marking, pass it on as it came
+                (State::Mark, _) => {
+                    out.extend_from_slice(&[0xff, b]);
+                    State::Data
+                }
+                (State::Error, _) => {
+                    self.errors += 1;
+                    warn!(
+                        "{}: parity/framing error at byte {} (0x{:02x})",
+                        name, self.offset, b
+                    );
+                    out.extend_from_slice(MARKER);
+                    State::Data
+                }
+            };
+            if self.state == State::Data {
+                self.offset += 1;
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        let mut m = ErrorMarks::new();
+        assert_eq!(m.decode("usb0", b"ab\xff\xffc"), b"ab\xffc");
+        assert_eq!(m.errors(), 0);
+
+        let mut expected = b"x".to_vec();
+        expected.extend_from_slice(MARKER);
+        expected.push(b'y');
+        assert_eq!(m.decode("usb0", b"x\xff\x00\xa5y"), expected);
+        assert_eq!(m.errors(), 1);
+        assert_eq!(m.offset, 7);
+    }
+
+    #[test]
+    fn test_split_sequence() {
+        let mut m = ErrorMarks::new();
+        assert_eq!(m.decode("usb0", b"a\xff"), b"a");
+        assert_eq!(m.decode("usb0", b"\x00"), b"");
+        assert_eq!(m.decode("usb0", b"\x00b"), [MARKER, b"b"].concat());
+        assert_eq!(m.decode("usb0", b"\xff"), b"");
+        assert_eq!(m.decode("usb0", b"\xff"), b"\xff");
+        assert_eq!(m.errors(), 1);
+    }
+}
@@ -0,0 +1,191 @@
+use mio::Token;
+use std::io::{Error, ErrorKind, IoSlice, Result};
+use std::time::Instant;
+
+use crate::poll::Poll;
+use crate::traits::{IoInstance, IoResult};
+
+/// The escape character of a QEMU chardev shared with the monitor
+const MUX_ESCAPE: u8 = 0x01;
+
+/// The serial port of a QEMU VM (`qemu:` devices): the unix socket, TCP
+/// port or pty of a chardev. Until the VM has created it, connecting
+/// reports that crabterm waits for QEMU; when the VM stops, the hub
+/// reconnects to the next one like to any other device.
+///
+/// With `mux`, the chardev is shared with the monitor and QEMU takes Ctrl-a
+/// as its escape, so a Ctrl-a typed for the guest is sent twice.
+pub struct QemuDevice {
+    inner: Box<dyn IoInstance>,
+    addr: String,
+    mux: bool,
+    /// The second half of an escaped Ctrl-a that could not be written yet
+    escape_pending: bool,
+}
+
+impl QemuDevice {
+    pub fn new(inner: Box<dyn IoInstance>, addr: String, mux: bool) -> Self {
+        QemuDevice {
+            inner,
+            addr,
+            mux,
+            escape_pending: false,
+        }
+    }
+
+    /// Write one Ctrl-a, doubled. Ok(false) when nothing could be written.
+    fn write_escape(&mut self) -> Result<bool> {
+        let escaped = [MUX_ESCAPE, MUX_ESCAPE];
+        match self.inner.write(&escaped)? {
+            IoResult::Data(d) if d.len() == 2 => Ok(true),
+            IoResult::Data(d) if d.len() == 1 => {
+                self.escape_pending = true;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+}
+
+impl IoInstance for QemuDevice {
+    fn connect(&mut self, poll: &mut Poll, token: Token) -> Result<()> {
+        self.escape_pending = false;
+        self.inner.connect(poll, token).map_err(|e| match e.kind() {
+            ErrorKind::NotFound | ErrorKind::ConnectionRefused => {
+                Error::new(e.kind(), format!("waiting for QEMU ({})", e))
+            }
+            _ => e,
+        })
+    }
+
+    fn connected(&self) -> bool {
+        self.inner.connected()
+    }
+
+    fn disconnect_needed(&self) -> bool {
+        self.inner.disconnect_needed()
+    }
+
+    fn disconnect(&mut self, poll: &mut Poll) {
+        self.inner.disconnect(poll)
+    }
+
+    fn read(&mut self) -> Result<IoResult> {
+        self.inner.read()
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<IoResult> {
+        if !self.mux {
+            return self.inner.write(buf);
+        }
+        if self.escape_pending {
+            match self.inner.write(&[MUX_ESCAPE])? {
+                IoResult::Data(d) if d.len() == 1 => self.escape_pending = false,
+                _ => return Ok(IoResult::None),
+            }
+        }
+        match buf.iter().position(|&b| b == MUX_ESCAPE) {
+            Some(0) => Ok(if self.write_escape()? {
+                IoResult::Data(buf[..1].into())
+            } else {
+                IoResult::None
+            }),
+            Some(n) => self.inner.write(&buf[..n]),
+            None => self.inner.write(buf),
+        }
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> Result<usize> {
+        if self.mux {
+            return match bufs.iter().find(|b| !b.is_empty()) {
+                Some(buf) => match self.write(buf)? {
+                    IoResult::Data(d) => Ok(d.len()),
+                    _ => Ok(0),
+                },
+                None => Ok(0),
+            };
+        }
+        self.inner.write_vectored(bufs)
+    }
+
+    fn flush(&mut self) {
+        self.inner.flush()
+    }
+
+    fn addr_as_string(&self) -> String {
+        self.addr.clone()
+    }
+
+    fn connected_announcement(&self) -> Option<String> {
+        Some(format!("{}: Connected", self.addr))
+    }
+
+    fn take_announcement(&mut self) -> Option<String> {
+        self.inner.take_announcement()
+    }
+
+    fn tick(&mut self) -> Result<IoResult> {
+        self.inner.tick()
+    }
+
+    fn next_tick(&self) -> Option<Instant> {
+        self.inner.next_tick()
+    }
+
+    fn resize(&mut self, cols: u16, rows: u16) {
+        self.inner.resize(cols, rows)
+    }
+
+    fn set_writable_interest(&mut self, poll: &mut Poll, writable: bool) -> Result<()> {
+        self.inner.set_writable_interest(poll, writable)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::EchoDevice;
+
+    fn mux_echo() -> (QemuDevice, Poll) {
+        let mut poll = Poll::new().unwrap();
+        let mut device = QemuDevice::new(
+            Box::new(EchoDevice::new().unwrap()),
+            "qemu:unix:/tmp/vm.sock".to_string(),
+            true,
+        );
+        device.connect(&mut poll, Token(0)).unwrap();
+        (device, poll)
+    }
+
+    fn read_all(device: &mut QemuDevice) -> Vec<u8> {
+        let mut out = Vec::new();
+        while let Ok(IoResult::Data(d)) = device.read() {
+            out.extend_from_slice(&d);
+        }
+        out
+    }
+
+    #[test]
+    fn test_mux_escape() {
+        let (mut device, _poll) = mux_echo();
+        assert_eq!(device.write_all(b"a\x01b\x01\x01"), 5);
+        assert_eq!(read_all(&mut device), b"a\x01\x01b\x01\x01\x01\x01");
+        assert_eq!(
+            device.connected_announcement().unwrap(),
+            "qemu:unix:/tmp/vm.sock: Connected"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_waiting() {
+        let mut poll = Poll::new().unwrap();
+        let mut device = QemuDevice::new(
+            Box::new(crate::io::UnixDevice::new("/nonexistent/vm.sock".into())),
+            "qemu:unix:/nonexistent/vm.sock".to_string(),
+            false,
+        );
+        let e = device.connect(&mut poll, Token(0)).unwrap_err();
+        assert!(e.to_string().starts_with("waiting for QEMU"), "{}", e);
+    }
+}
@@ -0,0 +1,66 @@
+//! The buffer an instance reads into, sized by `--io-buffer`.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+pub const SETTING: &str = "io-buffer";
+
+/// Default read size
+pub const DEFAULT_SIZE: usize = 16 * 1024;
+
+static SIZE: AtomicUsize = AtomicUsize::new(DEFAULT_SIZE);
+
+/// Set the size of buffers allocated from now on. Called once at startup,
+/// before the instances read.
+pub fn set_size(size: usize) {
+    SIZE.store(size.max(1), Ordering::Relaxed);
+}
+
+pub fn size() -> usize {
+    SIZE.load(Ordering::Relaxed)
+}
+
+/// Parse a size: bytes, or with a K or M suffix (1024 based).
+pub fn parse_size(s: &str) -> Option<usize> {
+    let s = s.trim();
+    let (num, mult) = match s.char_indices().last()? {
+        (i, 'k' | 'K') => (&s[..i], 1024),
+        (i, 'm' | 'M') => (&s[..i], 1024 * 1024),
+        _ => (s, 1),
+    };
+    let n: usize = num.parse().ok()?;
+    n.checked_mul(mult).filter(|&n| n > 0)
+}
+
+/// Allocated on first read and reused for every read after.
+#[derive(Debug, Default)]
+pub struct ReadBuffer {
+    buf: Vec<u8>,
+}
+
+impl ReadBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&mut self) -> &mut [u8] {
+        if self.buf.is_empty() {
+            self.buf = vec![0; size()];
+        }
+        &mut self.buf
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("4096"), Some(4096));
+        assert_eq!(parse_size("64K"), Some(64 * 1024));
+        assert_eq!(parse_size("1m"), Some(1024 * 1024));
+        assert_eq!(parse_size("0"), None);
+        assert_eq!(parse_size("K"), None);
+        assert_eq!(parse_size("12x"), None);
+    }
+}
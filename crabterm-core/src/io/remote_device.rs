@@ -0,0 +1,125 @@
+use log::info;
+use mio::Token;
+use std::collections::VecDeque;
+use std::io::{IoSlice, Result};
+use std::time::Instant;
+
+use crate::control::{self, Frame, FrameDecoder};
+use crate::poll::Poll;
+use crate::traits::{IoInstance, IoResult};
+
+/// The TCP port of another crabterm, as the device of `crabterm connect`.
+///
+/// Framed output is asked for on connect (see [`crate::control`]), so the
+/// announcements and device state changes of the server come out of
+/// [`IoInstance::take_announcement`] rather than the data, and are shown
+/// with the local template. Against an older server the output stays
+/// unframed.
+pub struct RemoteDevice {
+    device: Box<dyn IoInstance>,
+    decoder: FrameDecoder,
+    /// Framed output asked for on this connection
+    requested: bool,
+    /// Decoded but not returned yet
+    frames: VecDeque<Frame>,
+    announcements: VecDeque<String>,
+}
+
+impl RemoteDevice {
+    pub fn new(device: Box<dyn IoInstance>) -> Self {
+        RemoteDevice {
+            device,
+            decoder: FrameDecoder::new(),
+            requested: false,
+            frames: VecDeque::new(),
+            announcements: VecDeque::new(),
+        }
+    }
+}
+
+impl IoInstance for RemoteDevice {
+    fn connect(&mut self, poll: &mut Poll, token: Token) -> Result<()> {
+        self.device.connect(poll, token)?;
+        if !self.requested {
+            info!("{}: Asking for framed output", self.addr_as_string());
+            self.device.write_all(&control::encode(control::FRAMES));
+            self.requested = true;
+        }
+        Ok(())
+    }
+
+    fn connected(&self) -> bool {
+        self.device.connected()
+    }
+
+    fn disconnect_needed(&self) -> bool {
+        self.device.disconnect_needed()
+    }
+
+    fn disconnect(&mut self, poll: &mut Poll) {
+        self.device.disconnect(poll);
+        self.decoder = FrameDecoder::new();
+        self.requested = false;
+        self.frames.clear();
+    }
+
+    /// Data frames are returned one at a time, and an announcement is only
+    /// taken out once the data before it is returned, so the hub shows
+    /// both in the order they were sent.
+    fn read(&mut self) -> Result<IoResult> {
+        loop {
+            while let Some(frame) = self.frames.pop_front() {
+                match frame {
+                    Frame::Data(d) => return Ok(IoResult::Data(d.into())),
+                    Frame::Announce(msg) | Frame::State(msg) => self.announcements.push_back(msg),
+                }
+            }
+            match self.device.read()? {
+                IoResult::Data(d) => self.frames.extend(self.decoder.feed(&d)),
+                result => return Ok(result),
+            }
+        }
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<IoResult> {
+        self.device.write(buf)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> Result<usize> {
+        self.device.write_vectored(bufs)
+    }
+
+    fn flush(&mut self) {
+        self.device.flush()
+    }
+
+    fn addr_as_string(&self) -> String {
+        self.device.addr_as_string()
+    }
+
+    fn connected_announcement(&self) -> Option<String> {
+        self.device.connected_announcement()
+    }
+
+    fn take_announcement(&mut self) -> Option<String> {
+        self.announcements
+            .pop_front()
+            .or_else(|| self.device.take_announcement())
+    }
+
+    fn tick(&mut self) -> Result<IoResult> {
+        self.device.tick()
+    }
+
+    fn next_tick(&self) -> Option<Instant> {
+        self.device.next_tick()
+    }
+
+    fn resize(&mut self, cols: u16, rows: u16) {
+        self.device.resize(cols, rows)
+    }
+
+    fn set_writable_interest(&mut self, poll: &mut Poll, writable: bool) -> Result<()> {
+        self.device.set_writable_interest(poll, writable)
+    }
+}
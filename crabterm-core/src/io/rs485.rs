@@ -0,0 +1,131 @@
+//! RS-485 half-duplex mode for serial devices (`--rs485`).
+//!
+//! The transmitter of an RS-485 transceiver is switched on with RTS while
+//! sending. Where the driver supports it (TIOCSRS485) the kernel does this;
+//! for other adapters, e.g. most USB ones, SerialDevice toggles RTS around
+//! each write itself.
+
+use std::io::{Error, Result};
+#[cfg(unix)]
+use std::os::unix::io::RawFd;
+#[cfg(windows)]
+use std::os::windows::io::RawHandle;
+use std::time::Duration;
+
+#[cfg(unix)]
+const SER_RS485_ENABLED: u32 = 1 << 0;
+#[cfg(unix)]
+const SER_RS485_RTS_ON_SEND: u32 = 1 << 1;
+#[cfg(unix)]
+const SER_RS485_RTS_AFTER_SEND: u32 = 1 << 2;
+
+/// `struct serial_rs485` of linux/serial.h
+#[cfg(unix)]
+#[repr(C)]
+#[derive(Debug, Default)]
+struct SerialRs485 {
+    flags: u32,
+    delay_rts_before_send: u32,
+    delay_rts_after_send: u32,
+    padding: [u32; 5],
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Rs485 {
+    /// Time between switching the transmitter on and sending
+    pub delay_before: Duration,
+    /// Time between the last byte and switching the transmitter off
+    pub delay_after: Duration,
+    /// RTS is low, not high, while sending
+    pub rts_low_on_send: bool,
+}
+
+impl Rs485 {
+    /// The RTS level (true: asserted) while sending.
+    pub fn rts_on_send(&self) -> bool {
+        !self.rts_low_on_send
+    }
+
+    #[cfg(unix)]
+    fn config(&self) -> SerialRs485 {
+        let rts = if self.rts_low_on_send {
+            SER_RS485_RTS_AFTER_SEND
+        } else {
+            SER_RS485_RTS_ON_SEND
+        };
+        SerialRs485 {
+            flags: SER_RS485_ENABLED | rts,
+            delay_rts_before_send: self.delay_before.as_millis() as u32,
+            delay_rts_after_send: self.delay_after.as_millis() as u32,
+            ..Default::default()
+        }
+    }
+
+    /// Switch the driver of `fd` to RS-485 mode. Fails for drivers that do
+    /// not have one.
+    #[cfg(unix)]
+    pub fn enable(&self, fd: RawFd) -> Result<()> {
+        let config = self.config();
+        let res = unsafe { libc::ioctl(fd, libc::TIOCSRS485 as _, &config) };
+        if res < 0 {
+            Err(Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Windows has no RS-485 mode, RTS is always toggled by hand.
+    #[cfg(windows)]
+    pub fn enable(&self, _: RawHandle) -> Result<()> {
+        Err(Error::new(
+            std::io::ErrorKind::Unsupported,
+            "not supported on Windows",
+        ))
+    }
+}
+
+/// Parse the RTS level while sending, `high` or `low`, into
+/// [`Rs485::rts_low_on_send`].
+pub fn parse_rts(val: &str) -> std::result::Result<bool, String> {
+    match val {
+        "high" => Ok(false),
+        "low" => Ok(true),
+        _ => Err(format!("invalid RTS level: {} (high or low)", val)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_config() {
+        assert_eq!(std::mem::size_of::<SerialRs485>(), 32);
+
+        let rs485 = Rs485 {
+            delay_before: Duration::from_millis(2),
+            delay_after: Duration::from_millis(5),
+            rts_low_on_send: false,
+        };
+        let c = rs485.config();
+        assert_eq!(c.flags, SER_RS485_ENABLED | SER_RS485_RTS_ON_SEND);
+        assert_eq!((c.delay_rts_before_send, c.delay_rts_after_send), (2, 5));
+
+        let rs485 = Rs485 {
+            rts_low_on_send: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            rs485.config().flags,
+            SER_RS485_ENABLED | SER_RS485_RTS_AFTER_SEND
+        );
+    }
+
+    #[test]
+    fn test_parse_rts() {
+        assert_eq!(parse_rts("high"), Ok(false));
+        assert_eq!(parse_rts("low"), Ok(true));
+        assert!(parse_rts("up").is_err());
+    }
+}
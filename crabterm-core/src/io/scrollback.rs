@@ -0,0 +1,100 @@
+//! Recent output, kept for copying to the clipboard and for replaying to
+//! clients that connect.
+
+use std::collections::VecDeque;
+
+/// Default number of lines kept
+pub const DEFAULT_LINES: usize = 1000;
+
+/// Lines longer than this are split, so output without newlines cannot grow
+/// the buffer without bound.
+const MAX_LINE: usize = 64 * 1024;
+
+/// The last `max_lines` lines of output. The last line may be incomplete.
+#[derive(Debug)]
+pub struct Scrollback {
+    lines: VecDeque<Vec<u8>>,
+    max_lines: usize,
+}
+
+impl Scrollback {
+    pub fn new(max_lines: usize) -> Self {
+        Scrollback {
+            lines: VecDeque::new(),
+            max_lines: max_lines.max(1),
+        }
+    }
+
+    pub fn push(&mut self, mut buf: &[u8]) {
+        while !buf.is_empty() {
+            let open = matches!(self.lines.back(),
+                Some(line) if !line.ends_with(b"\n") && line.len() < MAX_LINE);
+            if !open {
+                self.lines.push_back(Vec::new());
+            }
+            let line = self.lines.back_mut().unwrap();
+            let room = MAX_LINE - line.len();
+            let end = match buf.iter().position(|&b| b == b'\n') {
+                Some(i) if i < room => i + 1,
+                _ => room.min(buf.len()),
+            };
+            line.extend_from_slice(&buf[..end]);
+            buf = &buf[end..];
+        }
+        while self.lines.len() > self.max_lines {
+            self.lines.pop_front();
+        }
+    }
+
+    /// The last `n` lines (an incomplete last line counts), with "\r" removed
+    /// so the text pastes cleanly.
+    pub fn last_lines(&self, n: usize) -> Vec<u8> {
+        let skip = self.lines.len().saturating_sub(n);
+        self.lines
+            .iter()
+            .skip(skip)
+            .flatten()
+            .copied()
+            .filter(|&b| b != b'\r')
+            .collect()
+    }
+
+    /// The last `n` lines as they came, for a terminal.
+    pub fn last_lines_raw(&self, n: usize) -> Vec<u8> {
+        let skip = self.lines.len().saturating_sub(n);
+        self.lines.iter().skip(skip).flatten().copied().collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_last_lines() {
+        let mut sb = Scrollback::new(3);
+        sb.push(b"one\r\ntw");
+        sb.push(b"o\r\nthree\r\nfour");
+        assert_eq!(sb.len(), 3);
+        assert_eq!(sb.last_lines(2), b"three\nfour");
+        assert_eq!(sb.last_lines(10), b"two\nthree\nfour");
+        sb.push(b"\n");
+        assert_eq!(sb.last_lines(1), b"four\n");
+        assert_eq!(sb.last_lines_raw(2), b"three\r\nfour\n");
+    }
+
+    #[test]
+    fn test_long_lines_are_split() {
+        let mut sb = Scrollback::new(10);
+        sb.push(&vec![b'x'; MAX_LINE + 1]);
+        assert_eq!(sb.len(), 2);
+    }
+}
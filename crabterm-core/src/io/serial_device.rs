@@ -0,0 +1,641 @@
+use log::info;
+use mio::{Interest, Token};
+use mio_serial::{
+    ClearBuffer, DataBits, Parity, SerialPort, SerialPortBuilderExt, SerialStream, StopBits,
+};
+use std::io::{Error, ErrorKind, IoSlice, Read, Result, Write};
+#[cfg(unix)]
+use std::os::unix::io::AsRawFd;
+#[cfg(windows)]
+use std::os::windows::io::AsRawHandle;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::autobaud::{Autobaud, Step};
+use super::by_id;
+use super::line_break::{self, BreakDetector};
+use super::parmrk::{self, ErrorMarks};
+use super::read_buffer::ReadBuffer;
+use super::rs485::Rs485;
+use crate::clock;
+use crate::poll::Poll;
+use crate::traits::{IoInstance, IoResult};
+
+/// How far the rate an adapter reports may be from the one asked for, as UART
+/// clocks rarely divide to custom rates exactly
+const BAUD_TOLERANCE: f64 = 0.03;
+
+/// Check the rate an adapter reports after setting `wanted`. Any rate can be
+/// asked for (Linux uses termios2 with BOTHER), but an adapter that cannot
+/// do it silently picks another one.
+fn check_baudrate(wanted: u32, actual: u32) -> Result<()> {
+    if (actual as f64 - wanted as f64).abs() <= wanted as f64 * BAUD_TOLERANCE {
+        Ok(())
+    } else {
+        Err(Error::new(
+            ErrorKind::Unsupported,
+            format!(
+                "{} baud not supported by the adapter (it set {})",
+                wanted, actual
+            ),
+        ))
+    }
+}
+
+/// Set the rate of `stream` and check that the adapter took it.
+fn set_baudrate(stream: &mut SerialStream, baudrate: u32) -> Result<()> {
+    stream
+        .set_baud_rate(baudrate)
+        .map_err(|e| Error::new(ErrorKind::Unsupported, format!("{} baud: {}", baudrate, e)))?;
+    match stream.baud_rate() {
+        Ok(actual) => check_baudrate(baudrate, actual),
+        // Not every driver reports it
+        Err(_) => Ok(()),
+    }
+}
+
+/// Default quarantine after opening a port
+pub const DEFAULT_QUARANTINE: Duration = Duration::from_millis(10);
+
+pub struct Connection {
+    stream: SerialStream,
+    /// The device node opened, e.g. /dev/ttyUSB1 for a replugged ttyUSB0
+    node: PathBuf,
+
+    // Some USB devices sends a lot of old charters at connect - this is used to discard those.
+    quarantine_until: Option<Instant>,
+    /// Bytes received in the quarantine, and those kept (`keep_quarantined`)
+    quarantined: usize,
+    held: Vec<u8>,
+
+    /// RS-485 by toggling RTS around writes, the driver has no RS-485 mode
+    rts_toggle: bool,
+
+    breaks: BreakDetector,
+}
+
+impl Connection {
+    /// End the quarantine if it is over at `now`. Returns the bytes kept.
+    fn end_quarantine(&mut self, now: Instant, keep: bool) -> Vec<u8> {
+        if self.quarantine_until.is_none_or(|t| now < t) {
+            return Vec::new();
+        }
+        self.quarantine_until = None;
+        if self.quarantined > 0 {
+            let what = if keep { "Kept" } else { "Discarded" };
+            info!("{} {} bytes received in quarantine", what, self.quarantined);
+        }
+        std::mem::take(&mut self.held)
+    }
+
+    /// Write with the RS-485 transmitter switched on by RTS.
+    fn send_with_rts(
+        &mut self,
+        rs485: &Rs485,
+        write: impl FnOnce(&mut SerialStream) -> Result<usize>,
+    ) -> Result<usize> {
+        self.stream.write_request_to_send(rs485.rts_on_send())?;
+        std::thread::sleep(rs485.delay_before);
+        let res = write(&mut self.stream);
+        // The bus is released once the bytes are on the wire
+        drain(&self.stream);
+        std::thread::sleep(rs485.delay_after);
+        self.stream.write_request_to_send(!rs485.rts_on_send())?;
+        res
+    }
+
+    /// Breaks received since the last call.
+    fn breaks(&mut self) -> u32 {
+        #[cfg(unix)]
+        let port = self.stream.as_raw_fd();
+        #[cfg(windows)]
+        let port = &self.stream;
+        self.breaks.check(port)
+    }
+}
+
+/// Wait until what was written to `stream` is on the wire.
+#[cfg(unix)]
+fn drain(stream: &SerialStream) {
+    unsafe { libc::tcdrain(stream.as_raw_fd()) };
+}
+
+#[cfg(windows)]
+fn drain(stream: &SerialStream) {
+    unsafe { windows_sys::Win32::Storage::FileSystem::FlushFileBuffers(stream.as_raw_handle()) };
+}
+
+/// The name mio-serial opens `node` by. On Windows it adds the `\\.\`
+/// prefix, which ports above COM9 are given by, itself.
+fn port_name(node: &Path) -> String {
+    let name = node.to_string_lossy();
+    if cfg!(windows)
+        && let Some(port) = name.strip_prefix(r"\\.\")
+    {
+        return port.to_string();
+    }
+    name.into_owned()
+}
+
+/// `writev()` on the fd of `stream`; SerialStream only writes the first
+/// buffer.
+#[cfg(unix)]
+fn writev(stream: &mut SerialStream, bufs: &[IoSlice<'_>]) -> Result<usize> {
+    // IoSlice is ABI compatible with iovec.
+    let count = bufs.len().min(libc::c_int::MAX as usize) as libc::c_int;
+    let n = unsafe {
+        libc::writev(
+            stream.as_raw_fd(),
+            bufs.as_ptr() as *const libc::iovec,
+            count,
+        )
+    };
+    if n >= 0 {
+        Ok(n as usize)
+    } else {
+        Err(Error::last_os_error())
+    }
+}
+
+/// Windows has no `writev()` for serial ports, the first buffer is written.
+#[cfg(windows)]
+fn writev(stream: &mut SerialStream, bufs: &[IoSlice<'_>]) -> Result<usize> {
+    stream.write_vectored(bufs)
+}
+
+pub struct SerialDevice {
+    path: String,
+    baudrate: u32,
+    zombie: bool,
+    connection: Option<Connection>,
+    read_buf: ReadBuffer,
+
+    /// Baudrate detection, until a rate is found
+    autobaud: Option<Autobaud>,
+    announcement: Option<String>,
+
+    rs485: Option<Rs485>,
+
+    data_bits: DataBits,
+    parity: Parity,
+    stop_bits: StopBits,
+
+    /// The /dev/serial/by-id link of the adapter, found on the first connect
+    by_id: Option<PathBuf>,
+
+    /// Time after opening the port in which received bytes are held back,
+    /// and whether they are passed on (rather than dropped) after it
+    quarantine: Duration,
+    keep_quarantined: bool,
+
+    /// Parity and framing errors are marked (`--mark-errors`)
+    marks: Option<ErrorMarks>,
+}
+
+impl SerialDevice {
+    pub fn new(path: String, baudrate: u32) -> Result<Self> {
+        Ok(SerialDevice {
+            path,
+            baudrate,
+            zombie: false,
+            connection: None,
+            read_buf: ReadBuffer::new(),
+            autobaud: None,
+            announcement: None,
+            rs485: None,
+            data_bits: DataBits::Eight,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+            by_id: None,
+            quarantine: DEFAULT_QUARANTINE,
+            keep_quarantined: false,
+            marks: None,
+        })
+    }
+
+    /// Hold back what is received in the first `duration` after opening the
+    /// port: stale bytes some USB adapters deliver at connect. They are
+    /// dropped, or with `keep` passed on once the time is over. Zero
+    /// disables it.
+    pub fn quarantine(mut self, duration: Duration, keep: bool) -> Self {
+        self.quarantine = duration;
+        self.keep_quarantined = keep;
+        self
+    }
+
+    /// Show bytes received with a parity or framing error: see
+    /// [`ErrorMarks`].
+    pub fn mark_errors(mut self) -> Self {
+        self.marks = Some(ErrorMarks::new());
+        self
+    }
+
+    /// Character format, 8N1 by default.
+    pub fn framing(mut self, data_bits: DataBits, parity: Parity, stop_bits: StopBits) -> Self {
+        self.data_bits = data_bits;
+        self.parity = parity;
+        self.stop_bits = stop_bits;
+        self
+    }
+
+    /// Talk to an RS-485 bus: see [`Rs485`].
+    pub fn rs485(mut self, rs485: Rs485) -> Self {
+        self.rs485 = Some(rs485);
+        self
+    }
+
+    /// Detect the baudrate instead of using the one given to `new()`: see
+    /// [`Autobaud`]. Output is held back until a rate is found.
+    pub fn autobaud(mut self, pattern: Option<Vec<u8>>) -> Self {
+        let autobaud = Autobaud::new(pattern, clock::now());
+        self.baudrate = autobaud.rate();
+        self.autobaud = Some(autobaud);
+        self
+    }
+
+    /// Apply the next step of baudrate detection. Returns the output read
+    /// at the rate locked onto.
+    fn autobaud_step(&mut self, step: Step) -> Result<Option<Vec<u8>>> {
+        match step {
+            Step::Wait => Ok(None),
+            Step::Try(rate) => {
+                info!("UART-Device: autobaud trying {}", rate);
+                if let Some(c) = &mut self.connection {
+                    c.stream.set_baud_rate(rate)?;
+                    // Drop what was received at the old rate
+                    let _ = c.stream.clear(ClearBuffer::Input);
+                }
+                self.baudrate = rate;
+                Ok(None)
+            }
+            Step::Lock(rate) => {
+                let sample = self.autobaud.take().map(|mut a| a.take_sample());
+                info!("UART-Device: autobaud found {}", rate);
+                self.announcement = Some(format!("{}: Autobaud {}", self.path, rate));
+                Ok(sample)
+            }
+        }
+    }
+
+    /// Turn bytes received into output: decode error marks, detect the
+    /// baudrate, show breaks. None when there is nothing to show yet.
+    fn process(&mut self, raw: &[u8]) -> Result<Option<Arc<[u8]>>> {
+        let decoded;
+        let data = match &mut self.marks {
+            Some(m) => {
+                decoded = m.decode(&self.path, raw);
+                &decoded[..]
+            }
+            None => raw,
+        };
+        if data.is_empty() {
+            // Only the start of an error marking
+            return Ok(None);
+        }
+        if let Some(a) = &mut self.autobaud {
+            let step = a.feed(data, clock::now());
+            return Ok(self.autobaud_step(step)?.map(|sample| sample.into()));
+        }
+        let breaks = match &mut self.connection {
+            Some(c) => c.breaks(),
+            None => 0,
+        };
+        if breaks > 0 {
+            info!("UART-Device: BREAK received");
+            Ok(Some([data, line_break::MESSAGE].concat().into()))
+        } else {
+            Ok(Some(data.into()))
+        }
+    }
+
+    fn err_handle_zombie(&mut self, method: &'static str, err: Error) -> Result<IoResult> {
+        info!("UART-Device/{}: {} -> zombie", method, err);
+        self.zombie = true;
+        Err(err)
+    }
+}
+
+impl IoInstance for SerialDevice {
+    fn connect(&mut self, poll: &mut Poll, token: Token) -> Result<()> {
+        // Once known, the adapter is opened by its by-id link, wherever it
+        // is now. The hub tries again as soon as a node or link appears (see
+        // `by_id::Hotplug`), so a replug is picked up at once.
+        let node = match &self.by_id {
+            Some(link) => by_id::resolve(link)
+                .ok_or_else(|| Error::new(ErrorKind::NotFound, "adapter unplugged"))?,
+            None => PathBuf::from(&self.path),
+        };
+        let mut serial = mio_serial::new(port_name(&node), self.baudrate)
+            .timeout(Duration::from_millis(250))
+            .data_bits(self.data_bits)
+            .parity(self.parity)
+            .stop_bits(self.stop_bits)
+            .open_native_async()?;
+        // Windows opens ports exclusively anyway
+        #[cfg(unix)]
+        serial.set_exclusive(true)?;
+        #[cfg(unix)]
+        let port = serial.as_raw_fd();
+        #[cfg(windows)]
+        let port = serial.as_raw_handle();
+        if self.by_id.is_none() {
+            self.by_id = by_id::find(Path::new(by_id::DIR), &node);
+            if let Some(link) = &self.by_id {
+                info!("UART-Device: {} is {}", self.path, link.display());
+            }
+        }
+        if self.autobaud.is_none() {
+            set_baudrate(&mut serial, self.baudrate)?;
+        }
+        if let Some(m) = &mut self.marks {
+            parmrk::enable(port)?;
+            *m = ErrorMarks::new();
+        }
+        let mut rts_toggle = false;
+        if let Some(rs485) = &self.rs485
+            && let Err(e) = rs485.enable(port)
+        {
+            info!("UART-Device: no RS-485 mode ({}), toggling RTS", e);
+            serial.write_request_to_send(!rs485.rts_on_send())?;
+            rts_toggle = true;
+        }
+
+        let mut c = Connection {
+            stream: serial,
+            node,
+            quarantine_until: (!self.quarantine.is_zero()).then(|| clock::now() + self.quarantine),
+            quarantined: 0,
+            held: Vec::new(),
+            rts_toggle,
+            breaks: BreakDetector::new(),
+        };
+        // Only breaks from now on
+        c.breaks();
+
+        poll.registry()
+            .register(&mut c.stream, token, Interest::READABLE)?;
+
+        // Must be done after register(), as the connection must be closed by RAII if register
+        // fails
+        self.connection = Some(c);
+        if let Some(a) = &mut self.autobaud {
+            a.restart(clock::now());
+        }
+
+        Ok(())
+    }
+
+    fn connected(&self) -> bool {
+        self.connection.is_some()
+    }
+
+    fn disconnect_needed(&self) -> bool {
+        self.zombie
+    }
+
+    /// Nodes in /dev, where the hub watches for them; ptys come and go with
+    /// programs, not hardware
+    fn hotplug(&self) -> bool {
+        let path = Path::new(&self.path);
+        cfg!(target_os = "linux") && path.starts_with("/dev") && !path.starts_with("/dev/pts")
+    }
+
+    fn disconnect(&mut self, poll: &mut Poll) {
+        if let Some(c) = &mut self.connection {
+            poll.registry()
+                .deregister(&mut c.stream)
+                .expect("BUG: Deregister failed!");
+        }
+        self.zombie = false;
+        self.connection = None;
+    }
+
+    fn read(&mut self) -> Result<IoResult> {
+        // Keep reading until there is output or WouldBlock (edge-triggered)
+        loop {
+            let Some(c) = &mut self.connection else {
+                return Err(Error::other("Device not connected".to_string()));
+            };
+            let tmp = self.read_buf.get();
+            let n = match c.stream.read(tmp) {
+                Ok(0) => {
+                    info!("uart EOF");
+                    self.zombie = true;
+                    return Err(Error::other("Device disconnected".to_string()));
+                }
+                Ok(n) => n,
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
+                    // Not ready yet — ignore and wait for next event
+                    return Ok(IoResult::None);
+                }
+                Err(e) => return self.err_handle_zombie("read", e),
+            };
+
+            let now = clock::now();
+            let mut raw = c.end_quarantine(now, self.keep_quarantined);
+            if c.quarantine_until.is_some() {
+                c.quarantined += n;
+                if self.keep_quarantined {
+                    c.held.extend_from_slice(&tmp[..n]);
+                }
+                continue;
+            }
+            raw.extend_from_slice(&tmp[..n]);
+            match self.process(&raw) {
+                Ok(Some(data)) => return Ok(IoResult::Data(data)),
+                Ok(None) => {}
+                Err(e) => return self.err_handle_zombie("read", e),
+            }
+        }
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<IoResult> {
+        if let Some(c) = &mut self.connection {
+            let res = match &self.rs485 {
+                Some(rs485) if c.rts_toggle => c.send_with_rts(rs485, |s| s.write(buf)),
+                _ => c.stream.write(buf),
+            };
+            match res {
+                Ok(n) => Ok(IoResult::Data(buf[..n].into())),
+
+                Err(e) => self.err_handle_zombie("write", e),
+            }
+        } else {
+            Err(Error::other("Device not connected".to_string()))
+        }
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> Result<usize> {
+        if let Some(c) = &mut self.connection {
+            let res = match &self.rs485 {
+                Some(rs485) if c.rts_toggle => c.send_with_rts(rs485, |s| writev(s, bufs)),
+                _ => writev(&mut c.stream, bufs),
+            };
+            res.or_else(|e| self.err_handle_zombie("write", e).map(|_| 0))
+        } else {
+            Err(Error::other("Device not connected".to_string()))
+        }
+    }
+
+    fn flush(&mut self) {
+        if let Some(c) = &mut self.connection
+            && let Err(e) = c.stream.flush()
+        {
+            let _ = self.err_handle_zombie("flush", e);
+        }
+    }
+
+    fn addr_as_string(&self) -> String {
+        self.path.clone()
+    }
+
+    fn connected_announcement(&self) -> Option<String> {
+        let mut msg = format!("{}: Connected", self.path);
+        if let Some(c) = &self.connection
+            && self.by_id.is_some()
+            && c.node != Path::new(&self.path)
+        {
+            msg.push_str(&format!(" as {}", c.node.display()));
+        }
+        if self.autobaud.is_some() {
+            msg.push_str(", detecting baudrate");
+        }
+        Some(msg)
+    }
+
+    fn take_announcement(&mut self) -> Option<String> {
+        self.announcement.take()
+    }
+
+    fn tick(&mut self) -> Result<IoResult> {
+        let Some(c) = &mut self.connection else {
+            return Ok(IoResult::None);
+        };
+        // Kept bytes of a quarantine the device was quiet after
+        let held = c.end_quarantine(clock::now(), self.keep_quarantined);
+        if let Some(a) = &mut self.autobaud {
+            let step = a.tick(clock::now());
+            self.autobaud_step(step)?;
+        }
+        if !held.is_empty()
+            && let Some(data) = self.process(&held)?
+        {
+            return Ok(IoResult::Data(data));
+        }
+        Ok(IoResult::None)
+    }
+
+    fn next_tick(&self) -> Option<Instant> {
+        let c = self.connection.as_ref()?;
+        let autobaud = self.autobaud.as_ref().map(|a| a.deadline());
+        [autobaud, c.quarantine_until].into_iter().flatten().min()
+    }
+
+    fn baudrate(&self) -> Option<u32> {
+        // Not known while it is detected
+        self.autobaud.is_none().then_some(self.baudrate)
+    }
+
+    fn set_baudrate(&mut self, baudrate: u32) -> Result<()> {
+        // A rate set by hand ends the detection
+        self.autobaud = None;
+        if let Some(c) = &mut self.connection
+            && let Err(e) = set_baudrate(&mut c.stream, baudrate)
+        {
+            let _ = c.stream.set_baud_rate(self.baudrate);
+            return Err(e);
+        }
+        // Also used for the next (re)connect
+        self.baudrate = baudrate;
+        info!("UART-Device: baudrate {}", baudrate);
+        Ok(())
+    }
+
+    fn set_quarantine(&mut self, duration: Duration) -> Result<()> {
+        // From the next (re)connect on
+        self.quarantine = duration;
+        Ok(())
+    }
+
+    /// A pty, e.g. of QEMU, gets the size of the terminal, so that the
+    /// program on the other side sees SIGWINCH; a UART has no size.
+    #[cfg(unix)]
+    fn resize(&mut self, cols: u16, rows: u16) {
+        let Some(c) = &self.connection else {
+            return;
+        };
+        if !is_pty(&c.node) {
+            return;
+        }
+        let ws = libc::winsize {
+            ws_row: rows,
+            ws_col: cols,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        if unsafe { libc::ioctl(c.stream.as_raw_fd(), libc::TIOCSWINSZ, &ws) } != 0 {
+            info!(
+                "UART-Device: {}: resize failed: {}",
+                self.path,
+                Error::last_os_error()
+            );
+        }
+    }
+}
+
+/// True for the slave side of a Unix 98 pty
+#[cfg(unix)]
+fn is_pty(node: &Path) -> bool {
+    std::fs::canonicalize(node).is_ok_and(|p| p.starts_with("/dev/pts"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_baudrate() {
+        assert!(check_baudrate(115200, 115200).is_ok());
+        // Close enough for a UART
+        assert!(check_baudrate(250000, 250000).is_ok());
+        assert!(check_baudrate(1500000, 1466666).is_ok());
+
+        let err = check_baudrate(250000, 230400).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Unsupported);
+        assert_eq!(
+            err.to_string(),
+            "250000 baud not supported by the adapter (it set 230400)"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_resize_pty() {
+        let (mut master, mut slave) = (-1, -1);
+        let ret = unsafe {
+            libc::openpty(
+                &mut master,
+                &mut slave,
+                std::ptr::null_mut(),
+                std::ptr::null(),
+                std::ptr::null(),
+            )
+        };
+        assert_eq!(ret, 0, "openpty failed");
+        let name = unsafe { std::ffi::CStr::from_ptr(libc::ttyname(slave)) };
+        let mut device = SerialDevice::new(name.to_string_lossy().to_string(), 115200)
+            .unwrap()
+            .quarantine(Duration::ZERO, false);
+        unsafe { libc::close(slave) };
+        let mut poll = Poll::new().unwrap();
+        device.connect(&mut poll, Token(0)).unwrap();
+
+        device.resize(132, 43);
+        let mut ws: libc::winsize = unsafe { std::mem::zeroed() };
+        assert_eq!(unsafe { libc::ioctl(master, libc::TIOCGWINSZ, &mut ws) }, 0);
+        assert_eq!((ws.ws_col, ws.ws_row), (132, 43));
+        device.disconnect(&mut poll);
+        unsafe { libc::close(master) };
+    }
+}
@@ -0,0 +1,184 @@
+//! stdin and stdout of the [`Console`](super::Console) as sources of the
+//! poll of the hub.
+//!
+//! On unix both are made non-blocking and polled directly, so a stopped
+//! terminal (Ctrl+S, a stuck ssh) does not stall the hub; their file status
+//! flags are put back on drop. The Windows console cannot be polled, so a
+//! thread reads stdin into a loopback socket that can, and stdout is written
+//! blocking.
+
+use mio::{Interest, Token};
+use std::fs::File;
+use std::io::Result;
+use std::mem::ManuallyDrop;
+
+use crate::poll::Registry;
+
+#[cfg(unix)]
+pub use self::unix::Stdio;
+#[cfg(windows)]
+pub use self::windows::Stdio;
+
+#[cfg(unix)]
+mod unix {
+    use super::*;
+    use crate::poll::Fd;
+    use std::io::Read;
+    use std::os::unix::io::{AsRawFd, FromRawFd};
+
+    pub struct Stdio {
+        fd_in: Fd,
+        fd_out: Fd,
+        stdout: ManuallyDrop<File>,
+        /// File status flags of stdin and stdout, restored on drop
+        saved_flags: [libc::c_int; 2],
+    }
+
+    impl Stdio {
+        pub fn new() -> Result<Self> {
+            // Both are globals, valid for the entire program
+            let fd_in = std::io::stdin().as_raw_fd();
+            let fd_out = std::io::stdout().as_raw_fd();
+            // mio uses edge-triggered epoll, so the fd must be non-blocking
+            // or read() will block the event loop when stdin has no more
+            // data. stdout likewise, or a terminal that does not take output
+            // blocks it.
+            let saved_flags = [fd_in, fd_out].map(|fd| unsafe {
+                let flags = libc::fcntl(fd, libc::F_GETFL, 0);
+                libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK);
+                flags
+            });
+            Ok(Stdio {
+                fd_in: Fd(fd_in),
+                fd_out: Fd(fd_out),
+                // stdout is a global, not to be closed when the console is
+                stdout: ManuallyDrop::new(unsafe { File::from_raw_fd(fd_out) }),
+                saved_flags,
+            })
+        }
+
+        pub fn register(&mut self, registry: &Registry, token: Token) -> Result<()> {
+            registry.register(&mut self.fd_in, token, Interest::READABLE)
+        }
+
+        pub fn deregister(&mut self, registry: &Registry) -> Result<()> {
+            registry.deregister(&mut self.fd_in)
+        }
+
+        /// Be told by `token` when stdout takes more output, or with None no
+        /// longer. Fails for stdout redirected to a file, which never blocks.
+        pub fn watch_writable(&mut self, registry: &Registry, token: Option<Token>) -> Result<()> {
+            match token {
+                Some(token) => registry.register(&mut self.fd_out, token, Interest::WRITABLE),
+                None => registry.deregister(&mut self.fd_out),
+            }
+        }
+
+        pub fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            std::io::stdin().read(buf)
+        }
+
+        pub fn stdout(&self) -> &File {
+            &self.stdout
+        }
+
+        /// Put the file status flags back as they were, e.g. for the shell
+        /// while the process is stopped.
+        pub fn restore_flags(&self) {
+            // stdout first: on a terminal both are the same file, and the
+            // flags of stdin were saved before any were changed
+            for (fd, flags) in [self.fd_out.0, self.fd_in.0]
+                .into_iter()
+                .zip(self.saved_flags.into_iter().rev())
+            {
+                unsafe { libc::fcntl(fd, libc::F_SETFL, flags) };
+            }
+        }
+
+        /// Make both non-blocking again after [`Stdio::restore_flags`].
+        pub fn set_nonblocking(&self) {
+            for fd in [self.fd_in.0, self.fd_out.0] {
+                unsafe {
+                    let flags = libc::fcntl(fd, libc::F_GETFL, 0);
+                    libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK);
+                }
+            }
+        }
+    }
+
+    impl Drop for Stdio {
+        fn drop(&mut self) {
+            self.restore_flags();
+        }
+    }
+}
+
+#[cfg(windows)]
+mod windows {
+    use super::*;
+    use crate::io::loopback;
+    use log::debug;
+    use std::io::{Read, Write};
+    use std::os::windows::io::{AsRawHandle, FromRawHandle};
+
+    pub struct Stdio {
+        /// What the reader thread read from stdin; closed at end of input
+        input: mio::net::TcpStream,
+        stdout: ManuallyDrop<File>,
+    }
+
+    impl Stdio {
+        pub fn new() -> Result<Self> {
+            let (mut writer, reader) = loopback::pair()?;
+            reader.set_nonblocking(true)?;
+            // Blocks in read() for as long as the program runs
+            std::thread::Builder::new()
+                .name("stdin".into())
+                .spawn(move || {
+                    let mut buf = [0u8; 4096];
+                    loop {
+                        match std::io::stdin().read(&mut buf) {
+                            Ok(0) => break,
+                            Ok(n) => {
+                                if writer.write_all(&buf[..n]).is_err() {
+                                    break;
+                                }
+                            }
+                            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                            Err(e) => {
+                                debug!("Console: stdin: {}", e);
+                                break;
+                            }
+                        }
+                    }
+                })?;
+            let handle = std::io::stdout().as_raw_handle();
+            Ok(Stdio {
+                input: mio::net::TcpStream::from_std(reader),
+                // stdout is a global, not to be closed when the console is
+                stdout: ManuallyDrop::new(unsafe { File::from_raw_handle(handle) }),
+            })
+        }
+
+        pub fn register(&mut self, registry: &Registry, token: Token) -> Result<()> {
+            registry.register(&mut self.input, token, Interest::READABLE)
+        }
+
+        pub fn deregister(&mut self, registry: &Registry) -> Result<()> {
+            registry.deregister(&mut self.input)
+        }
+
+        /// stdout is blocking, it always takes all output
+        pub fn watch_writable(&mut self, _: &Registry, _: Option<Token>) -> Result<()> {
+            Ok(())
+        }
+
+        pub fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            self.input.read(buf)
+        }
+
+        pub fn stdout(&self) -> &File {
+            &self.stdout
+        }
+    }
+}
@@ -1,8 +1,10 @@
 use log::info;
-use mio::{Interest, Poll, Token, net::TcpStream};
-use std::io::{Error, ErrorKind, Read, Result, Write};
+use mio::{Interest, Token, net::TcpStream};
+use std::io::{Error, ErrorKind, IoSlice, Read, Result, Write};
 use std::net::SocketAddr;
 
+use super::read_buffer::ReadBuffer;
+use crate::poll::Poll;
 use crate::traits::{IoInstance, IoResult};
 
 pub struct TcpDevice {
@@ -13,6 +15,9 @@ pub struct TcpDevice {
     connecting: bool,
     /// Token used for poll registration (needed for re-registration)
     token: Option<Token>,
+    read_buf: ReadBuffer,
+    /// Disable Nagle's algorithm (`nodelay=1`)
+    nodelay: bool,
 }
 
 impl TcpDevice {
@@ -23,9 +28,18 @@ impl TcpDevice {
             zombie: false,
             connecting: false,
             token: None,
+            read_buf: ReadBuffer::new(),
+            nodelay: false,
         })
     }
 
+    /// Send small writes such as keystrokes at once rather than
+    /// collecting them (TCP_NODELAY).
+    pub fn nodelay(mut self, nodelay: bool) -> Self {
+        self.nodelay = nodelay;
+        self
+    }
+
     fn err_handle_zombie(&mut self, method: &'static str, err: Error) -> Result<IoResult> {
         info!("{}: {} {} -> zombie", self.addr_as_string(), method, err);
         self.zombie = true;
@@ -60,6 +74,9 @@ impl IoInstance for TcpDevice {
 
         info!("{}: Try connect", self.addr_as_string());
         let mut s = TcpStream::connect(self.addr)?;
+        if self.nodelay {
+            s.set_nodelay(true)?;
+        }
 
         // Register for WRITABLE to detect connection completion, plus READABLE for data
         poll.registry()
@@ -104,7 +121,7 @@ impl IoInstance for TcpDevice {
     }
 
     fn read(&mut self) -> Result<IoResult> {
-        let mut tmp = [0u8; 1024];
+        let tmp = self.read_buf.get();
 
         // If still connecting, wait for connect() to verify
         if self.connecting {
@@ -112,14 +129,14 @@ impl IoInstance for TcpDevice {
         }
 
         if let Some(s) = &mut self.stream {
-            match s.read(&mut tmp) {
+            match s.read(tmp) {
                 Ok(0) => {
                     info!("{}: EOF", self.addr_as_string());
                     self.zombie = true;
                     Err(Error::other("Disconnected".to_string()))
                 }
 
-                Ok(n) => Ok(IoResult::Data(tmp[..n].to_vec())),
+                Ok(n) => Ok(IoResult::Data(tmp[..n].into())),
 
                 Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
                     // Not ready yet — ignore and wait for next event
@@ -136,7 +153,7 @@ impl IoInstance for TcpDevice {
     fn write(&mut self, buf: &[u8]) -> Result<IoResult> {
         if let Some(s) = &mut self.stream {
             match s.write(buf) {
-                Ok(n) => Ok(IoResult::Data(buf[..n].to_vec())),
+                Ok(n) => Ok(IoResult::Data(buf[..n].into())),
 
                 // Send buffer full — signal backpressure, not a fatal error
                 Err(ref e) if e.kind() == ErrorKind::WouldBlock => Ok(IoResult::None),
@@ -148,6 +165,18 @@ impl IoInstance for TcpDevice {
         }
     }
 
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> Result<usize> {
+        if let Some(s) = &mut self.stream {
+            match s.write_vectored(bufs) {
+                Ok(n) => Ok(n),
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => Ok(0),
+                Err(e) => self.err_handle_zombie("write", e).map(|_| 0),
+            }
+        } else {
+            Err(Error::other("Device not connected".to_string()))
+        }
+    }
+
     fn set_writable_interest(&mut self, poll: &mut Poll, writable: bool) -> Result<()> {
         if let (Some(s), Some(token)) = (&mut self.stream, self.token) {
             let interest = if writable {
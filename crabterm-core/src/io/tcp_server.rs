@@ -0,0 +1,414 @@
+use super::listener::ListenerRole;
+use super::output_buffer::{OutputBuffer, Pushed};
+use super::read_buffer::ReadBuffer;
+use crate::clock;
+use crate::control::{self, ControlParser, FrameKind, Input};
+use crate::keybind::{Action, KeybindConfig};
+use crate::poll::{Poll, Registry};
+use crate::traits::{IoInstance, IoResult};
+use log::{error, info};
+use mio::net::{TcpListener, TcpStream};
+use mio::{Interest, Token};
+use std::collections::VecDeque;
+use std::io::{ErrorKind, Read, Result, Write};
+use std::net::{IpAddr, Ipv4Addr, Shutdown, SocketAddr};
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Default for `--client-buffer-limit`
+pub const DEFAULT_CLIENT_BUFFER_LIMIT: usize = 64 * 1024;
+
+/// What to do with a client that does not keep up with the device output
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SlowClientPolicy {
+    /// Drop the output that does not fit in the client's buffer
+    DropData,
+    /// Disconnect the client
+    Disconnect,
+    /// Stop reading from the devices until the client has caught up
+    Block,
+}
+
+impl SlowClientPolicy {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "drop-data" => Some(SlowClientPolicy::DropData),
+            "disconnect" => Some(SlowClientPolicy::Disconnect),
+            "block" => Some(SlowClientPolicy::Block),
+            _ => None,
+        }
+    }
+}
+
+/// How the clients of a server are buffered
+#[derive(Debug, Clone, Copy)]
+pub struct ClientPolicy {
+    pub slow: SlowClientPolicy,
+    /// Output buffered per client before `slow` applies
+    pub buffer_limit: usize,
+    /// Bytes per second written to each client, if limited
+    pub rate_limit: Option<usize>,
+}
+
+impl Default for ClientPolicy {
+    fn default() -> Self {
+        ClientPolicy {
+            slow: SlowClientPolicy::Disconnect,
+            buffer_limit: DEFAULT_CLIENT_BUFFER_LIMIT,
+            rate_limit: None,
+        }
+    }
+}
+
+pub struct TcpServer {
+    listener: TcpListener,
+    policy: ClientPolicy,
+    /// Prefix keys of the clients are handled by the hub
+    client_keybinds: Option<KeybindConfig>,
+    role: ListenerRole,
+}
+
+impl TcpServer {
+    pub fn new(port: u16) -> Result<Self> {
+        Self::new_with_addr(SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), port))
+    }
+
+    pub fn new_with_addr(addr: SocketAddr) -> Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+
+        Ok(TcpServer {
+            listener,
+            policy: ClientPolicy::default(),
+            client_keybinds: None,
+            role: ListenerRole::default(),
+        })
+    }
+
+    pub fn set_client_policy(&mut self, policy: ClientPolicy) {
+        self.policy = policy;
+    }
+
+    /// Run the input of each client through a keybind processor of its own,
+    /// so that e.g. Ctrl+a q disconnects it and Ctrl+a t toggles its
+    /// timestamps.
+    pub fn set_client_keybinds(&mut self, config: KeybindConfig) {
+        self.client_keybinds = Some(config);
+    }
+
+    pub fn client_keybinds(&self) -> Option<&KeybindConfig> {
+        self.client_keybinds.as_ref()
+    }
+
+    /// What the clients may do, see [`ListenerRole`].
+    pub fn set_role(&mut self, role: ListenerRole) {
+        self.role = role;
+    }
+
+    pub fn role(&self) -> ListenerRole {
+        self.role
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn register(&mut self, poll: &mut Poll, token: Token) -> Result<()> {
+        poll.registry()
+            .register(&mut self.listener, token, Interest::READABLE)
+    }
+
+    pub fn accept(&mut self) -> Option<Box<dyn IoInstance>> {
+        match self.listener.accept() {
+            Ok((stream, addr)) => {
+                info!("{}: New client connected", addr);
+                let client = TcpClient {
+                    stream,
+                    addr,
+                    connected: true,
+                    out: OutputBuffer::new(self.policy),
+                    dropping: false,
+                    registry: None,
+                    writable_interest: false,
+                    read_buf: ReadBuffer::new(),
+                    control: ControlParser::new(),
+                    raw: self.role.gdb,
+                    pending: VecDeque::new(),
+                    framed: false,
+                };
+                Some(Box::new(client))
+            }
+
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => None,
+
+            Err(e) => {
+                error!("Accept error: {}", e);
+                None
+            }
+        }
+    }
+}
+
+pub struct TcpClient {
+    stream: TcpStream,
+    addr: SocketAddr,
+    connected: bool,
+    /// Output the socket did not accept yet, written on WRITABLE
+    out: OutputBuffer,
+    /// Output is being dropped (drop-data policy), logged once per episode
+    dropping: bool,
+    /// To add/remove WRITABLE interest as `out` fills and empties
+    registry: Option<(Registry, Token)>,
+    writable_interest: bool,
+    read_buf: ReadBuffer,
+    /// Commands from `crabterm connect` in the input
+    control: ControlParser,
+    /// Input is passed on as is, without looking for commands (`gdb`)
+    raw: bool,
+    /// Data and commands of the last read not returned yet
+    pending: VecDeque<IoResult>,
+    /// Output is sent as frames, asked for by `crabterm connect`
+    framed: bool,
+}
+
+impl TcpClient {
+    fn close(&mut self) {
+        self.connected = false;
+        if let Err(e) = self.stream.shutdown(Shutdown::Both) {
+            error!("{}: Shutdown error: {}", self.addr, e);
+        }
+    }
+
+    fn send(&mut self, buf: &Arc<[u8]>) -> Result<()> {
+        self.write_out()?;
+        let mut written = 0;
+        if self.out.is_empty() && !self.out.rate_limited() {
+            match self.stream.write(buf) {
+                Ok(n) => written = n,
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => {}
+                Err(e) => {
+                    info!("{}: Write error: {}", self.addr, e);
+                    self.close();
+                    return Err(e);
+                }
+            }
+        }
+
+        let pushed = if self.framed {
+            self.out.push_whole(buf.clone(), written)
+        } else {
+            self.out.push(buf.clone(), written)
+        };
+        match pushed {
+            Pushed::All => {}
+            Pushed::Dropped(_) => {
+                if !self.dropping {
+                    info!("{}: Client too slow, dropping output", self.addr);
+                    self.dropping = true;
+                }
+            }
+            Pushed::Overflow => {
+                info!("{}: Client too slow, disconnecting", self.addr);
+                self.close();
+                return Err(std::io::Error::new(
+                    ErrorKind::WouldBlock,
+                    "client buffer limit exceeded",
+                ));
+            }
+        }
+        self.write_out()
+    }
+
+    /// Send `payload` as a frame of `kind`, or as it is if the client has not
+    /// asked for frames.
+    fn send_framed(&mut self, kind: FrameKind, payload: &[u8]) -> Result<()> {
+        if self.framed {
+            self.send(&control::frame(kind, payload).into())
+        } else {
+            self.send(&payload.into())
+        }
+    }
+
+    /// Send an announcement or a state change. Framed clients get the
+    /// message alone, and apply their own template.
+    fn send_message(&mut self, kind: FrameKind, template: &str, source: &str, msg: &str) {
+        let msg = match self.framed {
+            true => msg.to_string(),
+            false => crate::announce::expand_template(template, source, msg),
+        };
+        let _ = self.send_framed(kind, msg.as_bytes());
+    }
+
+    /// Write as much of `out` as the socket accepts, and be told when it
+    /// accepts more only while there is something left.
+    fn write_out(&mut self) -> Result<()> {
+        if let Err(e) = self.out.write_to(&mut self.stream) {
+            info!("{}: Write error: {}", self.addr, e);
+            self.close();
+            return Err(e);
+        }
+        if self.out.is_empty() {
+            self.dropping = false;
+        }
+
+        let writable = !self.out.is_empty();
+        if writable != self.writable_interest
+            && let Some((registry, token)) = &self.registry
+        {
+            let interest = if writable {
+                Interest::READABLE | Interest::WRITABLE
+            } else {
+                Interest::READABLE
+            };
+            registry.reregister(&mut self.stream, *token, interest)?;
+            self.writable_interest = writable;
+        }
+        Ok(())
+    }
+}
+
+impl IoInstance for TcpClient {
+    fn connect(&mut self, poll: &mut Poll, token: Token) -> Result<()> {
+        poll.registry()
+            .register(&mut self.stream, token, Interest::READABLE)
+            .map_err(|e| {
+                error!("{}: Register error: {}", self.addr, e);
+                e
+            })?;
+        self.registry = Some((poll.registry().try_clone()?, token));
+        Ok(())
+    }
+
+    fn connected(&self) -> bool {
+        self.connected
+    }
+
+    fn addr_as_string(&self) -> String {
+        self.stream
+            .local_addr()
+            .map(|a| a.to_string())
+            .unwrap_or_else(|_| self.addr.to_string())
+    }
+
+    fn peer_as_string(&self) -> String {
+        self.addr.to_string()
+    }
+
+    fn disconnect(&mut self, poll: &mut Poll) {
+        self.close();
+
+        if let Err(e) = poll.registry().deregister(&mut self.stream) {
+            error!("{}: Deregister error: {}", self.addr, e);
+        }
+    }
+
+    fn read(&mut self) -> Result<IoResult> {
+        // A read that ends in a partial control string leaves nothing to
+        // return, read on as the socket is edge-triggered
+        loop {
+            if let Some(result) = self.pending.pop_front() {
+                return Ok(result);
+            }
+
+            let tmp = self.read_buf.get();
+
+            match self.stream.read(tmp) {
+                Ok(0) => return Ok(IoResult::None),
+
+                Ok(n) if self.raw => return Ok(IoResult::Data(tmp[..n].into())),
+
+                Ok(n) => {
+                    for input in self.control.feed(&tmp[..n]) {
+                        match input {
+                            Input::Data(d) => self.pending.push_back(IoResult::Data(d.into())),
+                            Input::Command(c) if c == control::FRAMES => {
+                                info!("{}: Framed output", self.addr);
+                                self.framed = true;
+                                self.send(&control::encode(control::FRAMES).into())?;
+                            }
+                            Input::Command(c) => {
+                                self.pending.push_back(IoResult::Action(Action::Remote(c)))
+                            }
+                        }
+                    }
+                }
+
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
+                    // Not ready yet — ignore and wait for next event
+                    return Ok(IoResult::None);
+                }
+
+                Err(e) => {
+                    info!("{}: Read error: {}", self.addr, e);
+                    self.close();
+                    return Err(e);
+                }
+            }
+        }
+    }
+
+    /// All of `buf` is taken: what the socket does not accept is buffered,
+    /// up to the buffer limit, beyond which the slow client policy applies.
+    fn write(&mut self, buf: &[u8]) -> Result<IoResult> {
+        let data: Arc<[u8]> = buf.into();
+        if self.framed {
+            self.send_framed(FrameKind::Data, &data)?;
+        } else {
+            self.send(&data)?;
+        }
+        Ok(IoResult::Data(data))
+    }
+
+    fn write_shared(&mut self, buf: &Arc<[u8]>) -> usize {
+        let sent = if self.framed {
+            self.send_framed(FrameKind::Data, buf)
+        } else {
+            self.send(buf)
+        };
+        match sent {
+            Ok(()) => buf.len(),
+            Err(_) => 0,
+        }
+    }
+
+    fn write_announce(&mut self, template: &str, source: &str, msg: &str) {
+        self.send_message(FrameKind::Announce, template, source, msg);
+    }
+
+    fn write_status(&mut self, template: &str, source: &str, msg: &str) {
+        self.send_message(FrameKind::State, template, source, msg);
+    }
+
+    fn flush(&mut self) {
+        let _ = self.write_out();
+    }
+
+    fn write_backlogged(&self) -> bool {
+        self.out.policy().slow == SlowClientPolicy::Block && self.out.backlogged()
+    }
+
+    fn write_pending(&self) -> bool {
+        self.connected && !self.out.is_empty()
+    }
+
+    fn write_queued(&self) -> usize {
+        self.out.len()
+    }
+
+    /// Writes what the rate limit held back
+    fn tick(&mut self) -> Result<IoResult> {
+        if self.out.next_write().is_some_and(|t| t <= clock::now()) {
+            let _ = self.write_out();
+        }
+        Ok(IoResult::None)
+    }
+
+    fn next_tick(&self) -> Option<Instant> {
+        self.out.next_write()
+    }
+}
+
+impl Drop for TcpClient {
+    fn drop(&mut self) {
+        info!("{}: dropped", self.addr);
+    }
+}
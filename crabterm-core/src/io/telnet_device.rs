@@ -0,0 +1,353 @@
+use log::{debug, info};
+use mio::Token;
+use std::io::Result;
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::poll::Poll;
+use crate::traits::{IoInstance, IoResult};
+
+const IAC: u8 = 255;
+const DONT: u8 = 254;
+const DO: u8 = 253;
+const WONT: u8 = 252;
+const WILL: u8 = 251;
+const SB: u8 = 250;
+const SE: u8 = 240;
+
+const BINARY: u8 = 0;
+const ECHO: u8 = 1;
+const SGA: u8 = 3;
+const NAWS: u8 = 31;
+
+/// Where the reader is in the protocol
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Data,
+    /// After a CR, whose NUL is dropped
+    Cr,
+    Iac,
+    /// After IAC and WILL, WONT, DO or DONT
+    Option(u8),
+    /// In a subnegotiation, ignored up to IAC SE
+    Sb,
+    SbIac,
+}
+
+/// A telnet server, e.g. a terminal server or ser2net (`telnet://` devices),
+/// over another device, usually TCP. IAC sequences are taken out of the
+/// output and a 0xff of the input is escaped. Crabterm lets the server echo
+/// and suppress go-ahead, and sends the window size (NAWS) when asked;
+/// other options are refused.
+pub struct TelnetDevice {
+    inner: Box<dyn IoInstance>,
+    state: State,
+    /// Options enabled on the server side (WILL) and on ours (DO)
+    remote: [bool; 256],
+    local: [bool; 256],
+    /// Replies and the second half of an escaped 0xff not written yet
+    pending: Vec<u8>,
+    /// The terminal size, for NAWS
+    size: Option<(u16, u16)>,
+}
+
+impl TelnetDevice {
+    pub fn new(inner: Box<dyn IoInstance>) -> Self {
+        TelnetDevice {
+            inner,
+            state: State::Data,
+            remote: [false; 256],
+            local: [false; 256],
+            pending: Vec::new(),
+            size: None,
+        }
+    }
+
+    /// Take the protocol out of `bytes`, queueing the replies; the data.
+    fn receive(&mut self, bytes: &[u8]) -> Vec<u8> {
+        let mut data = Vec::with_capacity(bytes.len());
+        for &b in bytes {
+            self.state = match (self.state, b) {
+                (State::Data | State::Cr, IAC) => State::Iac,
+                (State::Cr, 0) => State::Data,
+                (State::Data | State::Cr, b'\r') => {
+                    data.push(b);
+                    State::Cr
+                }
+                (State::Data | State::Cr, b) => {
+                    data.push(b);
+                    State::Data
+                }
+                (State::Iac, IAC) => {
+                    data.push(IAC);
+                    State::Data
+                }
+                (State::Iac, WILL | WONT | DO | DONT) => State::Option(b),
+                (State::Iac, SB) => State::Sb,
+                // NOP, go-ahead and the like
+                (State::Iac, _) => State::Data,
+                (State::Option(cmd), option) => {
+                    self.negotiate(cmd, option);
+                    State::Data
+                }
+                (State::Sb, IAC) => State::SbIac,
+                (State::Sb, _) => State::Sb,
+                (State::SbIac, SE) => State::Data,
+                (State::SbIac, _) => State::Sb,
+            };
+        }
+        data
+    }
+
+    /// Answer `IAC cmd option`; only changes are answered, so the two
+    /// sides do not loop.
+    fn negotiate(&mut self, cmd: u8, option: u8) {
+        debug!("Telnet: {} {}", command_name(cmd), option);
+        let o = option as usize;
+        match cmd {
+            WILL if !self.remote[o] => {
+                let accept = matches!(option, BINARY | ECHO | SGA);
+                self.remote[o] = accept;
+                self.pending
+                    .extend_from_slice(&[IAC, if accept { DO } else { DONT }, option]);
+            }
+            WONT if self.remote[o] => {
+                self.remote[o] = false;
+                self.pending.extend_from_slice(&[IAC, DONT, option]);
+            }
+            DO if !self.local[o] => {
+                let accept = matches!(option, BINARY | SGA | NAWS);
+                self.local[o] = accept;
+                self.pending
+                    .extend_from_slice(&[IAC, if accept { WILL } else { WONT }, option]);
+                if option == NAWS && accept {
+                    self.queue_size();
+                }
+            }
+            DONT if self.local[o] => {
+                self.local[o] = false;
+                self.pending.extend_from_slice(&[IAC, WONT, option]);
+            }
+            _ => {}
+        }
+    }
+
+    /// `IAC SB NAWS cols rows IAC SE`, if the size is known
+    fn queue_size(&mut self) {
+        let Some((cols, rows)) = self.size else {
+            return;
+        };
+        self.pending.extend_from_slice(&[IAC, SB, NAWS]);
+        for b in [cols.to_be_bytes(), rows.to_be_bytes()].concat() {
+            // A 255 in the size is doubled like in data
+            self.pending.push(b);
+            if b == IAC {
+                self.pending.push(IAC);
+            }
+        }
+        self.pending.extend_from_slice(&[IAC, SE]);
+    }
+
+    /// Write what is pending; true once all of it is written.
+    fn write_pending(&mut self) -> Result<bool> {
+        while !self.pending.is_empty() {
+            match self.inner.write(&self.pending)? {
+                IoResult::Data(d) if !d.is_empty() => {
+                    self.pending.drain(..d.len());
+                }
+                _ => return Ok(false),
+            }
+        }
+        Ok(true)
+    }
+}
+
+fn command_name(cmd: u8) -> &'static str {
+    match cmd {
+        WILL => "WILL",
+        WONT => "WONT",
+        DO => "DO",
+        _ => "DONT",
+    }
+}
+
+impl IoInstance for TelnetDevice {
+    fn connect(&mut self, poll: &mut Poll, token: Token) -> Result<()> {
+        self.inner.connect(poll, token)
+    }
+
+    fn connected(&self) -> bool {
+        self.inner.connected()
+    }
+
+    fn disconnect_needed(&self) -> bool {
+        self.inner.disconnect_needed()
+    }
+
+    fn disconnect(&mut self, poll: &mut Poll) {
+        self.inner.disconnect(poll);
+        // The next connection negotiates from scratch
+        self.state = State::Data;
+        self.remote = [false; 256];
+        self.local = [false; 256];
+        self.pending.clear();
+    }
+
+    fn read(&mut self) -> Result<IoResult> {
+        // Read on when all of a read was protocol, as the socket is
+        // edge-triggered
+        loop {
+            match self.inner.read()? {
+                IoResult::Data(d) => {
+                    let data = self.receive(&d);
+                    if !self.pending.is_empty() && !self.write_pending()? {
+                        info!("Telnet: reply to the server delayed");
+                    }
+                    if !data.is_empty() {
+                        return Ok(IoResult::Data(Arc::from(data)));
+                    }
+                }
+                other => return Ok(other),
+            }
+        }
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<IoResult> {
+        if !self.write_pending()? {
+            return Ok(IoResult::None);
+        }
+        // Up to and with the first IAC, which is sent twice
+        let end = buf
+            .iter()
+            .position(|&b| b == IAC)
+            .map_or(buf.len(), |i| i + 1);
+        match self.inner.write(&buf[..end])? {
+            IoResult::Data(d) => {
+                if d.last() == Some(&IAC) {
+                    self.pending.push(IAC);
+                    self.write_pending()?;
+                }
+                Ok(IoResult::Data(d))
+            }
+            other => Ok(other),
+        }
+    }
+
+    fn flush(&mut self) {
+        self.inner.flush()
+    }
+
+    fn addr_as_string(&self) -> String {
+        self.inner.addr_as_string()
+    }
+
+    fn connected_announcement(&self) -> Option<String> {
+        self.inner.connected_announcement()
+    }
+
+    fn take_announcement(&mut self) -> Option<String> {
+        self.inner.take_announcement()
+    }
+
+    fn tick(&mut self) -> Result<IoResult> {
+        self.inner.tick()
+    }
+
+    fn next_tick(&self) -> Option<Instant> {
+        self.inner.next_tick()
+    }
+
+    fn resize(&mut self, cols: u16, rows: u16) {
+        self.size = Some((cols, rows));
+        if self.local[NAWS as usize] {
+            self.queue_size();
+            if let Err(e) = self.write_pending() {
+                info!("Telnet: window size not sent: {}", e);
+            }
+        }
+    }
+
+    fn set_writable_interest(&mut self, poll: &mut Poll, writable: bool) -> Result<()> {
+        self.inner.set_writable_interest(poll, writable)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::loop_device::PeerStream;
+    use crate::io::{EchoDevice, LoopDevice};
+    use std::io::{Read, Write};
+
+    fn read_all(device: &mut TelnetDevice) -> Vec<u8> {
+        let mut out = Vec::new();
+        while let Ok(IoResult::Data(d)) = device.read() {
+            out.extend_from_slice(&d);
+        }
+        out
+    }
+
+    fn server() -> (TelnetDevice, PeerStream, Poll) {
+        let mut poll = Poll::new().unwrap();
+        let (inner, peer) = LoopDevice::with_peer().unwrap();
+        let mut device = TelnetDevice::new(Box::new(inner));
+        device.connect(&mut poll, Token(0)).unwrap();
+        peer.set_read_timeout(Some(std::time::Duration::from_secs(2)))
+            .unwrap();
+        (device, peer, poll)
+    }
+
+    fn recv(peer: &mut PeerStream, len: usize) -> Vec<u8> {
+        let mut buf = vec![0u8; len];
+        peer.read_exact(&mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn test_escape() {
+        let mut poll = Poll::new().unwrap();
+        let mut device = TelnetDevice::new(Box::new(EchoDevice::new().unwrap()));
+        device.connect(&mut poll, Token(0)).unwrap();
+        assert_eq!(device.write_all(b"a\xffb\xff\xff"), 5);
+        // Echoed doubled, and taken as data again
+        assert_eq!(read_all(&mut device), b"a\xffb\xff\xff");
+    }
+
+    #[test]
+    fn test_strip() {
+        let (mut device, mut peer, _poll) = server();
+        // A NOP, a subnegotiation and a CR NUL in the output
+        peer.write_all(b"lo\xff\xf1gin\xff\xfa\x18\x01\xff\xf0: \r\0x\r\n")
+            .unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        assert_eq!(read_all(&mut device), b"login: \rx\r\n");
+    }
+
+    #[test]
+    fn test_negotiate() {
+        let (mut device, mut peer, _poll) = server();
+        device.resize(80, 24);
+        peer.write_all(&[IAC, WILL, ECHO, IAC, WILL, 24, IAC, DO, NAWS, IAC, DO, 24])
+            .unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        assert_eq!(read_all(&mut device), b"");
+        assert_eq!(
+            recv(&mut peer, 21),
+            [
+                IAC, DO, ECHO, IAC, DONT, 24, IAC, WILL, NAWS, IAC, SB, NAWS, 0, 80, 0, 24, IAC,
+                SE, IAC, WONT, 24
+            ]
+        );
+
+        // Already on: not answered again
+        peer.write_all(&[IAC, WILL, ECHO, b'$']).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        assert_eq!(read_all(&mut device), b"$");
+
+        device.resize(255, 50);
+        assert_eq!(
+            recv(&mut peer, 10),
+            [IAC, SB, NAWS, 0, 255, 255, 0, 50, IAC, SE]
+        );
+    }
+}
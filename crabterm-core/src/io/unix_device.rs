@@ -0,0 +1,147 @@
+use log::info;
+use mio::{Interest, Token, net::UnixStream};
+use std::io::{Error, ErrorKind, IoSlice, Read, Result, Write};
+use std::path::PathBuf;
+
+use super::read_buffer::ReadBuffer;
+use crate::poll::Poll;
+use crate::traits::{IoInstance, IoResult};
+
+/// Connects to a unix socket, e.g. the socket of a detached session.
+pub struct UnixDevice {
+    stream: Option<UnixStream>,
+    path: PathBuf,
+    zombie: bool,
+    /// Token used for poll registration (needed for re-registration)
+    token: Option<Token>,
+    read_buf: ReadBuffer,
+}
+
+impl UnixDevice {
+    pub fn new(path: PathBuf) -> Self {
+        UnixDevice {
+            stream: None,
+            path,
+            zombie: false,
+            token: None,
+            read_buf: ReadBuffer::new(),
+        }
+    }
+
+    fn err_handle_zombie(&mut self, method: &'static str, err: Error) -> Result<IoResult> {
+        info!("{}: {} {} -> zombie", self.addr_as_string(), method, err);
+        self.zombie = true;
+        Err(err)
+    }
+}
+
+impl IoInstance for UnixDevice {
+    fn connect(&mut self, poll: &mut Poll, token: Token) -> Result<()> {
+        if self.stream.is_some() {
+            return Ok(());
+        }
+
+        info!("{}: Try connect", self.addr_as_string());
+        let mut s = UnixStream::connect(&self.path)?;
+        poll.registry()
+            .register(&mut s, token, Interest::READABLE)?;
+        self.stream = Some(s);
+        self.token = Some(token);
+        Ok(())
+    }
+
+    fn addr_as_string(&self) -> String {
+        self.path.display().to_string()
+    }
+
+    fn connected_announcement(&self) -> Option<String> {
+        // Like a TCP device this is a transport link; the session announces
+        // the status of its own devices.
+        None
+    }
+
+    fn connected(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    fn disconnect_needed(&self) -> bool {
+        self.zombie
+    }
+
+    fn disconnect(&mut self, poll: &mut Poll) {
+        if let Some(s) = &mut self.stream {
+            let _ = poll.registry().deregister(s);
+        }
+        self.zombie = false;
+        self.stream = None;
+    }
+
+    fn read(&mut self) -> Result<IoResult> {
+        let tmp = self.read_buf.get();
+
+        if let Some(s) = &mut self.stream {
+            match s.read(tmp) {
+                Ok(0) => {
+                    info!("{}: EOF", self.addr_as_string());
+                    self.zombie = true;
+                    Err(Error::other("Session closed".to_string()))
+                }
+
+                Ok(n) => Ok(IoResult::Data(tmp[..n].into())),
+
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => Ok(IoResult::None),
+
+                Err(e) => self.err_handle_zombie("read", e),
+            }
+        } else {
+            Err(Error::other("Device not connected".to_string()))
+        }
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<IoResult> {
+        if let Some(s) = &mut self.stream {
+            match s.write(buf) {
+                Ok(n) => Ok(IoResult::Data(buf[..n].into())),
+
+                // Send buffer full — signal backpressure, not a fatal error
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => Ok(IoResult::None),
+
+                Err(e) => self.err_handle_zombie("write", e),
+            }
+        } else {
+            Err(Error::other("Device not connected".to_string()))
+        }
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> Result<usize> {
+        if let Some(s) = &mut self.stream {
+            match s.write_vectored(bufs) {
+                Ok(n) => Ok(n),
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => Ok(0),
+                Err(e) => self.err_handle_zombie("write", e).map(|_| 0),
+            }
+        } else {
+            Err(Error::other("Device not connected".to_string()))
+        }
+    }
+
+    fn set_writable_interest(&mut self, poll: &mut Poll, writable: bool) -> Result<()> {
+        if let (Some(s), Some(token)) = (&mut self.stream, self.token) {
+            let interest = if writable {
+                Interest::READABLE | Interest::WRITABLE
+            } else {
+                Interest::READABLE
+            };
+            poll.registry().reregister(s, token, interest)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) {
+        if let Some(s) = &mut self.stream
+            && let Err(e) = s.flush()
+        {
+            let _ = self.err_handle_zombie("flush", e);
+        }
+    }
+}
@@ -0,0 +1,395 @@
+use super::listener::{ListenerRole, SocketAccess};
+use super::read_buffer::ReadBuffer;
+use crate::poll::Poll;
+use crate::traits::{IoInstance, IoResult};
+use log::{error, info, warn};
+use mio::net::{UnixListener, UnixStream};
+use mio::{Interest, Token};
+use std::ffi::CStr;
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::net::Shutdown;
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+/// Accepts clients on a unix socket, e.g. `crabterm attach` connecting to a
+/// detached session. The socket file is removed when the server is dropped.
+pub struct UnixServer {
+    listener: UnixListener,
+    path: PathBuf,
+    next_id: u64,
+    role: ListenerRole,
+    access: SocketAccess,
+    /// The members of `access.allow_groups`, looked up once rather than for
+    /// every client; later changes to the groups need a restart
+    group_members: Vec<u32>,
+}
+
+impl UnixServer {
+    /// Bind to `path`. A stale socket left behind by a process that is gone
+    /// is replaced; a socket somebody is listening on is an error.
+    pub fn new(path: &Path) -> Result<Self> {
+        Self::with_access(path, SocketAccess::default())
+    }
+
+    /// Bind to `path` with the mode and owner of the socket file, and who
+    /// may connect, as `access` says, see [`SocketAccess`].
+    pub fn with_access(path: &Path, access: SocketAccess) -> Result<Self> {
+        if path.exists() {
+            if std::os::unix::net::UnixStream::connect(path).is_ok() {
+                return Err(Error::new(
+                    ErrorKind::AddrInUse,
+                    format!("{} is in use", path.display()),
+                ));
+            }
+            std::fs::remove_file(path)?;
+        }
+        let group_members = access
+            .allow_groups
+            .iter()
+            .map(|&group| group_members(group))
+            .collect::<Result<Vec<_>>>()?
+            .concat();
+        let listener = bind(path, &access)?;
+
+        Ok(UnixServer {
+            listener,
+            path: path.to_path_buf(),
+            next_id: 1,
+            role: ListenerRole::default(),
+            access,
+            group_members,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// What the clients may do, see [`ListenerRole`].
+    pub fn set_role(&mut self, role: ListenerRole) {
+        self.role = role;
+    }
+
+    pub fn role(&self) -> ListenerRole {
+        self.role
+    }
+
+    /// Whether the peer of `stream` is one of the allowed users or groups.
+    fn allowed(&self, stream: &UnixStream) -> bool {
+        if !self.access.restricted() {
+            return true;
+        }
+        match peer_cred(stream) {
+            Ok((uid, gid)) => {
+                self.access.allow_users.contains(&uid)
+                    || self.access.allow_groups.contains(&gid)
+                    || self.group_members.contains(&uid)
+            }
+            Err(e) => {
+                warn!("{}: peer credentials: {}", self.path.display(), e);
+                false
+            }
+        }
+    }
+
+    pub fn register(&mut self, poll: &mut Poll, token: Token) -> Result<()> {
+        poll.registry()
+            .register(&mut self.listener, token, Interest::READABLE)
+    }
+
+    pub fn accept(&mut self) -> Option<Box<dyn IoInstance>> {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) if !self.allowed(&stream) => {
+                    warn!("{}: Client refused", self.path.display());
+                    let _ = stream.shutdown(Shutdown::Both);
+                }
+                Ok((stream, _)) => {
+                    let name = format!("attach-{}", self.next_id);
+                    self.next_id += 1;
+                    info!("{}: New client connected", name);
+                    return Some(Box::new(UnixClient {
+                        stream,
+                        name,
+                        connected: true,
+                        read_buf: ReadBuffer::new(),
+                    }));
+                }
+
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => return None,
+
+                Err(e) => {
+                    error!("Accept error: {}", e);
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+/// The uid and gid of the process at the other end of `stream`.
+#[cfg(target_os = "linux")]
+fn peer_cred(stream: &UnixStream) -> Result<(u32, u32)> {
+    let mut cred = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    // SAFETY: cred and len describe a ucred the kernel fills in
+    let ret = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut cred as *mut libc::ucred as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if ret != 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok((cred.uid, cred.gid))
+}
+
+/// The uid and gid of the process at the other end of `stream`.
+#[cfg(not(target_os = "linux"))]
+fn peer_cred(stream: &UnixStream) -> Result<(u32, u32)> {
+    let (mut uid, mut gid) = (0, 0);
+    // SAFETY: plain out parameters
+    if unsafe { libc::getpeereid(stream.as_raw_fd(), &mut uid, &mut gid) } != 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok((uid, gid))
+}
+
+/// Bind a socket at `path` with the mode and owner of `access`. It is bound
+/// in a directory only we can enter and moved into place once they are set,
+/// so nobody connects in between.
+fn bind(path: &Path, access: &SocketAccess) -> Result<UnixListener> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "no socket file name"))?;
+    let parent = path.parent().unwrap_or(Path::new("."));
+    let dir = parent.join(format!(".crabterm-{}", std::process::id()));
+    std::fs::DirBuilder::new().mode(0o700).create(&dir)?;
+    let tmp = dir.join(name);
+    let result = bind_as(&tmp, path, access);
+    let _ = std::fs::remove_file(&tmp);
+    let _ = std::fs::remove_dir(&dir);
+    result
+}
+
+/// Bind at `tmp`, set the mode and owner and move the socket to `path`.
+fn bind_as(tmp: &Path, path: &Path, access: &SocketAccess) -> Result<UnixListener> {
+    let listener = UnixListener::bind(tmp)?;
+    if access.owner.is_some() || access.group.is_some() {
+        std::os::unix::fs::chown(tmp, access.owner, access.group)?;
+    }
+    if let Some(mode) = access.mode {
+        std::fs::set_permissions(tmp, std::fs::Permissions::from_mode(mode))?;
+    }
+    std::fs::rename(tmp, path)?;
+    Ok(listener)
+}
+
+/// Call a getpw*_r or getgr*_r function with `buf`, grown while it is too
+/// small for the entry.
+fn lookup_r(
+    buf: &mut Vec<libc::c_char>,
+    mut call: impl FnMut(&mut [libc::c_char]) -> libc::c_int,
+) -> Result<()> {
+    loop {
+        match call(buf) {
+            0 => return Ok(()),
+            libc::ERANGE if buf.len() < 1 << 20 => buf.resize(buf.len() * 2, 0),
+            e => return Err(Error::from_raw_os_error(e)),
+        }
+    }
+}
+
+/// The uids of the users listed as members of `group`. Those with it as
+/// their primary group are not listed; the gid of the peer shows them.
+fn group_members(group: u32) -> Result<Vec<u32>> {
+    let mut buf = vec![0; 1024];
+    // SAFETY: a plain C struct, filled in by getgrgid_r
+    let mut gr: libc::group = unsafe { std::mem::zeroed() };
+    let mut found = std::ptr::null_mut();
+    // SAFETY: gr, the buffer and found are valid for the call to fill in
+    lookup_r(&mut buf, |buf| unsafe {
+        libc::getgrgid_r(group, &mut gr, buf.as_mut_ptr(), buf.len(), &mut found)
+    })?;
+    if found.is_null() {
+        return Ok(Vec::new());
+    }
+    let mut names = Vec::new();
+    // SAFETY: the member list is in buf and NULL terminated
+    unsafe {
+        let mut member = gr.gr_mem;
+        while !(*member).is_null() {
+            names.push(CStr::from_ptr(*member).to_owned());
+            member = member.add(1);
+        }
+    }
+    names
+        .iter()
+        .filter_map(|name| user_id(name).transpose())
+        .collect()
+}
+
+/// The uid of the user `name`, None for an unknown user.
+fn user_id(name: &CStr) -> Result<Option<u32>> {
+    let mut buf = vec![0; 1024];
+    // SAFETY: as in group_members
+    let mut pw: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut found = std::ptr::null_mut();
+    // SAFETY: as in group_members
+    lookup_r(&mut buf, |buf| unsafe {
+        libc::getpwnam_r(
+            name.as_ptr(),
+            &mut pw,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut found,
+        )
+    })?;
+    Ok((!found.is_null()).then_some(pw.pw_uid))
+}
+
+impl Drop for UnixServer {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+pub struct UnixClient {
+    stream: UnixStream,
+    name: String,
+    connected: bool,
+    read_buf: ReadBuffer,
+}
+
+impl UnixClient {
+    fn close(&mut self) {
+        self.connected = false;
+        if let Err(e) = self.stream.shutdown(Shutdown::Both) {
+            error!("{}: Shutdown error: {}", self.name, e);
+        }
+    }
+}
+
+impl IoInstance for UnixClient {
+    fn connect(&mut self, poll: &mut Poll, token: Token) -> Result<()> {
+        poll.registry()
+            .register(&mut self.stream, token, Interest::READABLE)
+    }
+
+    fn connected(&self) -> bool {
+        self.connected
+    }
+
+    fn addr_as_string(&self) -> String {
+        self.name.clone()
+    }
+
+    fn disconnect(&mut self, poll: &mut Poll) {
+        self.close();
+
+        if let Err(e) = poll.registry().deregister(&mut self.stream) {
+            error!("{}: Deregister error: {}", self.name, e);
+        }
+    }
+
+    fn read(&mut self) -> Result<IoResult> {
+        let tmp = self.read_buf.get();
+
+        match self.stream.read(tmp) {
+            Ok(0) => {
+                info!("{}: Detached", self.name);
+                self.close();
+                Ok(IoResult::None)
+            }
+
+            Ok(n) => Ok(IoResult::Data(tmp[..n].into())),
+
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => Ok(IoResult::None),
+
+            Err(e) => {
+                info!("{}: Read error: {}", self.name, e);
+                self.close();
+                Err(e)
+            }
+        }
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<IoResult> {
+        match self.stream.write(buf) {
+            Ok(n) => Ok(IoResult::Data(buf[..n].into())),
+
+            // Like TCP clients, a client that can not keep up is dropped
+            // rather than applying backpressure to the device.
+            Err(e) => {
+                info!("{}: Write error: {}", self.name, e);
+                self.close();
+                Err(e)
+            }
+        }
+    }
+
+    fn flush(&mut self) {
+        if let Err(e) = self.stream.flush() {
+            info!("{}: Flush error: {}", self.name, e);
+            self.close();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_access() {
+        let path =
+            std::env::temp_dir().join(format!("crabterm-access-{}.sock", std::process::id()));
+        let (uid, gid) = unsafe { (libc::geteuid(), libc::getegid()) };
+
+        // Somebody else only
+        let mut server = UnixServer::with_access(
+            &path,
+            SocketAccess {
+                mode: Some(0o660),
+                allow_users: vec![uid.wrapping_add(1)],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o660);
+        let mut refused = std::os::unix::net::UnixStream::connect(&path).unwrap();
+        assert!(server.accept().is_none());
+        assert_eq!(refused.read(&mut [0u8; 8]).unwrap(), 0);
+        drop(server);
+
+        // Our group
+        let mut server = UnixServer::with_access(
+            &path,
+            SocketAccess {
+                allow_groups: vec![gid],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let _client = std::os::unix::net::UnixStream::connect(&path).unwrap();
+        assert!(server.accept().is_some());
+    }
+
+    #[test]
+    fn test_group_members() {
+        // A group that does not exist has no members
+        assert_eq!(group_members(u32::MAX - 1).unwrap(), Vec::<u32>::new());
+        assert_eq!(user_id(c"root").unwrap(), Some(0));
+        assert_eq!(user_id(c"crabterm-nobody-at-all").unwrap(), None);
+    }
+}
@@ -1,12 +1,40 @@
 use std::collections::HashMap;
 
-use super::IoFilter;
+use super::{FilterChain, IoFilter};
 use crate::keybind::config::SettingValue;
 
 pub const NAME: &str = "charmap";
 pub const SETTING_IMAP: &str = "charmap-imap";
 pub const SETTING_OMAP: &str = "charmap-omap";
 
+/// Presets of the `charmap preset` action: name, imap, omap
+pub const PRESETS: &[(&str, &str, &str)] = &[
+    // LF line ends: shown as CR LF, Enter sent as LF
+    ("unix", "lfcrlf", "crlf"),
+    // CR LF line ends: Enter sent as CR LF
+    ("dos", "", "crcrlf"),
+    // CR line ends: shown as CR LF
+    ("mac", "crcrlf", ""),
+];
+
+/// Replace the charmap of `chain` with the preset `name`, or turn it off
+/// with `None`. The message to show, or the error.
+pub fn set_preset(chain: &mut FilterChain, name: Option<&str>) -> Result<String, String> {
+    let Some(name) = name else {
+        chain.register(Box::new(CharmapFilter::new()));
+        return Ok("Charmap off".to_string());
+    };
+    let (_, imap, omap) = PRESETS
+        .iter()
+        .find(|(preset, _, _)| *preset == name)
+        .ok_or_else(|| {
+            let names: Vec<&str> = PRESETS.iter().map(|(n, _, _)| *n).collect();
+            format!("Unknown charmap preset: {} ({})", name, names.join(", "))
+        })?;
+    chain.register(Box::new(CharmapFilter::with_mappings(imap, omap)));
+    Ok(format!("Charmap {}", name))
+}
+
 #[derive(Debug, Clone, Copy)]
 enum Mapping {
     CrLf,   // \r -> \n
@@ -84,21 +112,26 @@ impl CharmapFilter {
         }
     }
 
-    pub fn configure(&mut self, settings: &HashMap<String, SettingValue>) {
-        if let Some(value) = settings.get(SETTING_IMAP).and_then(|v| v.as_str()) {
-            self.imap = Self::parse_mappings(value);
-            // Auto-enable if mappings are configured
-            if !self.imap.is_empty() {
-                self.enabled = true;
-            }
+    /// A filter with the mappings `imap` and `omap` (as in the settings),
+    /// on if there are any.
+    pub fn with_mappings(imap: &str, omap: &str) -> Self {
+        let imap = Self::parse_mappings(imap);
+        let omap = Self::parse_mappings(omap);
+        CharmapFilter {
+            enabled: !imap.is_empty() || !omap.is_empty(),
+            imap,
+            omap,
         }
-        if let Some(value) = settings.get(SETTING_OMAP).and_then(|v| v.as_str()) {
-            self.omap = Self::parse_mappings(value);
-            // Auto-enable if mappings are configured
-            if !self.omap.is_empty() {
-                self.enabled = true;
+    }
+
+    /// Check a list of mappings, e.g. "crlf,delbs", for the command line.
+    pub fn check_mappings(value: &str) -> Result<String, String> {
+        for name in value.split(',').map(|s| s.trim()) {
+            if Mapping::from_str(name).is_none() {
+                return Err(format!("Unknown mapping: {}", name));
             }
         }
+        Ok(value.to_string())
     }
 
     fn parse_mappings(value: &str) -> Vec<Mapping> {
@@ -108,12 +141,12 @@ impl CharmapFilter {
             .collect()
     }
 
-    fn apply_mappings(mappings: &[Mapping], buf: &[u8]) -> Vec<u8> {
-        let mut output = Vec::with_capacity(buf.len());
+    fn apply_mappings(mappings: &[Mapping], buf: &[u8], output: &mut Vec<u8>) {
+        output.reserve(buf.len());
         for &byte in buf {
             let mut handled = false;
             for mapping in mappings {
-                if mapping.apply(byte, &mut output) {
+                if mapping.apply(byte, output) {
                     handled = true;
                     break;
                 }
@@ -122,7 +155,6 @@ impl CharmapFilter {
                 output.push(byte);
             }
         }
-        output
     }
 }
 
@@ -133,6 +165,31 @@ impl Default for CharmapFilter {
 }
 
 impl IoFilter for CharmapFilter {
+    fn name(&self) -> &str {
+        NAME
+    }
+
+    fn settings(&self) -> &[&str] {
+        &[SETTING_IMAP, SETTING_OMAP]
+    }
+
+    fn configure(&mut self, settings: &HashMap<String, SettingValue>) {
+        if let Some(value) = settings.get(SETTING_IMAP).and_then(|v| v.as_str()) {
+            self.imap = Self::parse_mappings(value);
+            // Auto-enable if mappings are configured
+            if !self.imap.is_empty() {
+                self.enabled = true;
+            }
+        }
+        if let Some(value) = settings.get(SETTING_OMAP).and_then(|v| v.as_str()) {
+            self.omap = Self::parse_mappings(value);
+            // Auto-enable if mappings are configured
+            if !self.omap.is_empty() {
+                self.enabled = true;
+            }
+        }
+    }
+
     fn enabled(&self) -> bool {
         self.enabled
     }
@@ -141,12 +198,12 @@ impl IoFilter for CharmapFilter {
         self.enabled = !self.enabled;
     }
 
-    fn filter_out(&mut self, buf: &[u8]) -> Vec<u8> {
-        Self::apply_mappings(&self.imap, buf)
+    fn filter_out(&mut self, buf: &[u8], out: &mut Vec<u8>) {
+        Self::apply_mappings(&self.imap, buf, out);
     }
 
-    fn filter_in(&mut self, buf: &[u8]) -> Vec<u8> {
-        Self::apply_mappings(&self.omap, buf)
+    fn filter_in(&mut self, buf: &[u8], out: &mut Vec<u8>) {
+        Self::apply_mappings(&self.omap, buf, out);
     }
 }
 
@@ -154,65 +211,89 @@ impl IoFilter for CharmapFilter {
 mod tests {
     use super::*;
 
+    fn apply(mappings: &[Mapping], buf: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        CharmapFilter::apply_mappings(mappings, buf, &mut out);
+        out
+    }
+
     #[test]
-    fn test_crlf_mapping() {
-        let mappings = vec![Mapping::CrLf];
+    fn test_check_mappings() {
+        assert_eq!(
+            CharmapFilter::check_mappings("crlf, delbs"),
+            Ok("crlf, delbs".to_string())
+        );
         assert_eq!(
-            CharmapFilter::apply_mappings(&mappings, b"hello\r\nworld"),
-            b"hello\n\nworld"
+            CharmapFilter::check_mappings("crlf,nope"),
+            Err("Unknown mapping: nope".to_string())
         );
     }
 
     #[test]
-    fn test_lfcrlf_mapping() {
-        let mappings = vec![Mapping::LfCrLf];
+    fn test_presets() {
+        let mut chain = FilterChain::default();
+        assert_eq!(
+            set_preset(&mut chain, Some("unix")),
+            Ok("Charmap unix".to_string())
+        );
+        assert!(chain.enabled(NAME));
+        assert_eq!(chain.filter_out(b"a\nb"), b"a\r\nb");
+        assert_eq!(chain.filter_in(b"ls\r"), b"ls\n");
+
         assert_eq!(
-            CharmapFilter::apply_mappings(&mappings, b"hello\nworld"),
-            b"hello\r\nworld"
+            set_preset(&mut chain, Some("dos")),
+            Ok("Charmap dos".to_string())
         );
+        assert_eq!(chain.filter_out(b"a\nb"), b"a\nb");
+        assert_eq!(chain.filter_in(b"ls\r"), b"ls\r\n");
+
+        assert!(set_preset(&mut chain, Some("amiga")).is_err());
+        assert_eq!(set_preset(&mut chain, None), Ok("Charmap off".to_string()));
+        assert!(!chain.enabled(NAME));
+        assert_eq!(chain.filter_in(b"ls\r"), b"ls\r");
+    }
+
+    #[test]
+    fn test_crlf_mapping() {
+        let mappings = vec![Mapping::CrLf];
+        assert_eq!(apply(&mappings, b"hello\r\nworld"), b"hello\n\nworld");
+    }
+
+    #[test]
+    fn test_lfcrlf_mapping() {
+        let mappings = vec![Mapping::LfCrLf];
+        assert_eq!(apply(&mappings, b"hello\nworld"), b"hello\r\nworld");
     }
 
     #[test]
     fn test_delbs_mapping() {
         let mappings = vec![Mapping::DelBs];
-        assert_eq!(
-            CharmapFilter::apply_mappings(&mappings, b"hello\x7fworld"),
-            b"hello\x08world"
-        );
+        assert_eq!(apply(&mappings, b"hello\x7fworld"), b"hello\x08world");
     }
 
     #[test]
     fn test_bsdel_mapping() {
         let mappings = vec![Mapping::BsDel];
-        assert_eq!(
-            CharmapFilter::apply_mappings(&mappings, b"hello\x08world"),
-            b"hello\x7fworld"
-        );
+        assert_eq!(apply(&mappings, b"hello\x08world"), b"hello\x7fworld");
     }
 
     #[test]
     fn test_igncr_mapping() {
         let mappings = vec![Mapping::IgnCr];
-        assert_eq!(
-            CharmapFilter::apply_mappings(&mappings, b"hello\r\nworld"),
-            b"hello\nworld"
-        );
+        assert_eq!(apply(&mappings, b"hello\r\nworld"), b"hello\nworld");
     }
 
     #[test]
     fn test_ignlf_mapping() {
         let mappings = vec![Mapping::IgnLf];
-        assert_eq!(
-            CharmapFilter::apply_mappings(&mappings, b"hello\r\nworld"),
-            b"hello\rworld"
-        );
+        assert_eq!(apply(&mappings, b"hello\r\nworld"), b"hello\rworld");
     }
 
     #[test]
     fn test_multiple_mappings() {
         let mappings = vec![Mapping::CrLf, Mapping::DelBs];
         assert_eq!(
-            CharmapFilter::apply_mappings(&mappings, b"hello\r\x7fworld"),
+            apply(&mappings, b"hello\r\x7fworld"),
             b"hello\n\x08world" // CrLf maps \r->\n, DelBs maps \x7f->\x08
         );
     }
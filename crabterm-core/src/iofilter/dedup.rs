@@ -0,0 +1,167 @@
+use std::io::Write;
+
+use super::IoFilter;
+
+pub const NAME: &str = "dedup";
+
+/// Lines longer than this are passed through and never collapsed
+const MAX_LINE: usize = 4096;
+
+/// Collapses runs of identical lines. The first line is shown at once, the
+/// repeats are counted and reported as `<line> (repeated N times)` when a
+/// different line arrives.
+pub struct DedupFilter {
+    enabled: bool,
+    // The last complete line, including "\n"
+    last: Vec<u8>,
+    // The current line so far
+    line: Vec<u8>,
+    // The current line has matched `last` so far and is held back
+    matching: bool,
+    overlong: bool,
+    repeats: usize,
+}
+
+impl DedupFilter {
+    pub fn new() -> Self {
+        DedupFilter {
+            enabled: false,
+            last: Vec::new(),
+            line: Vec::new(),
+            matching: true,
+            overlong: false,
+            repeats: 0,
+        }
+    }
+
+    fn flush_repeats(&mut self, output: &mut Vec<u8>) {
+        match self.repeats {
+            0 => {}
+            // Nothing is gained by collapsing a single repeat
+            1 => output.extend_from_slice(&self.last),
+            n => {
+                let text = self.last.trim_ascii_end();
+                output.extend_from_slice(text);
+                write!(output, " (repeated {} times)", n).unwrap();
+                output.extend_from_slice(&self.last[text.len()..]);
+            }
+        }
+        self.repeats = 0;
+    }
+
+    fn end_line(&mut self) {
+        // Blank lines are not collapsed
+        if self.overlong || self.line.trim_ascii().is_empty() {
+            self.last.clear();
+            self.line.clear();
+        } else {
+            std::mem::swap(&mut self.last, &mut self.line);
+            self.line.clear();
+        }
+        self.overlong = false;
+        self.matching = true;
+    }
+}
+
+impl Default for DedupFilter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IoFilter for DedupFilter {
+    fn name(&self) -> &str {
+        NAME
+    }
+
+    fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn toggle(&mut self) {
+        self.enabled = !self.enabled;
+        self.last.clear();
+        self.line.clear();
+        self.matching = true;
+        self.overlong = false;
+        self.repeats = 0;
+    }
+
+    fn filter_out(&mut self, buf: &[u8], output: &mut Vec<u8>) {
+        for &byte in buf {
+            if self.matching {
+                self.line.push(byte);
+                if byte == b'\n' && self.line == self.last {
+                    self.repeats += 1;
+                    self.line.clear();
+                    continue;
+                }
+                if byte != b'\n' && self.last.starts_with(&self.line) {
+                    continue;
+                }
+                // Not a repeat, show what was held back
+                self.flush_repeats(output);
+                output.extend_from_slice(&self.line);
+                self.matching = false;
+            } else {
+                output.push(byte);
+                if self.line.len() < MAX_LINE {
+                    self.line.push(byte);
+                } else {
+                    self.overlong = true;
+                }
+            }
+            if byte == b'\n' {
+                self.end_line();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(f: &mut DedupFilter, buf: &[u8]) -> String {
+        let mut out = Vec::new();
+        f.filter_out(buf, &mut out);
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_collapse() {
+        let mut f = DedupFilter::new();
+        assert_eq!(
+            apply(&mut f, b"boot\r\nwarn\r\nwarn\r\nwarn\r\nwarn\r\nok\r\n"),
+            "boot\r\nwarn\r\nwarn (repeated 3 times)\r\nok\r\n"
+        );
+        // A single repeat is shown as is
+        assert_eq!(apply(&mut f, b"ok\r\nend"), "ok\r\nend");
+        // Blank lines are kept
+        assert_eq!(apply(&mut f, b"\n\n\n"), "\n\n\n");
+    }
+
+    #[test]
+    fn test_split_reads() {
+        let mut f = DedupFilter::new();
+        assert_eq!(apply(&mut f, b"spam\n"), "spam\n");
+        // Held back while it could still be a repeat
+        assert_eq!(apply(&mut f, b"sp"), "");
+        assert_eq!(apply(&mut f, b"am\nspam\nsp"), "");
+        assert_eq!(apply(&mut f, b"ot\n"), "spam (repeated 2 times)\nspot\n");
+        // A prefix of the last line is not a repeat
+        assert_eq!(apply(&mut f, b"spo"), "");
+        assert_eq!(apply(&mut f, b"\n"), "spo\n");
+    }
+
+    #[test]
+    fn test_overlong_lines() {
+        let mut f = DedupFilter::new();
+        let long = vec![b'x'; MAX_LINE + 10];
+        for _ in 0..2 {
+            let mut line = long.clone();
+            line.push(b'\n');
+            assert_eq!(apply(&mut f, &line).len(), line.len());
+        }
+    }
+}
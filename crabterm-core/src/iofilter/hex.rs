@@ -0,0 +1,90 @@
+use std::io::Write;
+
+use super::IoFilter;
+
+pub const NAME: &str = "hex";
+
+const BYTES_PER_LINE: usize = 16;
+
+/// Shows the output as hex bytes, e.g. `48 69 0d 0a`, for binary protocols.
+/// A line ends after 16 bytes or after a LF of the device, so text stays in
+/// lines.
+pub struct HexFilter {
+    enabled: bool,
+    // Bytes on the current line
+    column: usize,
+}
+
+impl HexFilter {
+    pub fn new() -> Self {
+        HexFilter {
+            enabled: false,
+            column: 0,
+        }
+    }
+}
+
+impl Default for HexFilter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IoFilter for HexFilter {
+    fn name(&self) -> &str {
+        NAME
+    }
+
+    fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn toggle(&mut self) {
+        self.enabled = !self.enabled;
+        self.column = 0;
+    }
+
+    fn filter_out(&mut self, buf: &[u8], output: &mut Vec<u8>) {
+        for &byte in buf {
+            if self.column > 0 {
+                output.push(b' ');
+            }
+            write!(output, "{:02x}", byte).unwrap();
+            self.column += 1;
+            if byte == b'\n' || self.column == BYTES_PER_LINE {
+                output.extend_from_slice(b"\r\n");
+                self.column = 0;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(f: &mut HexFilter, buf: &[u8]) -> String {
+        let mut out = Vec::new();
+        f.filter_out(buf, &mut out);
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_lines() {
+        let mut f = HexFilter::new();
+        assert_eq!(apply(&mut f, b"Hi"), "48 69");
+        assert_eq!(apply(&mut f, b"\r\n\x00"), " 0d 0a\r\n00");
+        assert_eq!(
+            apply(&mut f, &[0xff; 16]),
+            " ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff\r\nff"
+        );
+    }
+
+    #[test]
+    fn test_input_unchanged() {
+        let mut f = HexFilter::new();
+        let mut out = Vec::new();
+        f.filter_in(b"ls\r", &mut out);
+        assert_eq!(out, b"ls\r");
+    }
+}
@@ -0,0 +1,167 @@
+use log::warn;
+use std::collections::HashMap;
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+use super::IoFilter;
+use crate::clock;
+use crate::keybind::config::SettingValue;
+
+pub const NAME: &str = "idle";
+pub const SETTING_GAP: &str = "idle-gap";
+
+const DEFAULT_GAP: Duration = Duration::from_secs(5);
+
+/// Marks gaps in the device output with a line like `--- idle 12.4 s ---`.
+/// The marker is written when data arrives again, so it always carries the
+/// full length of the gap.
+pub struct IdleFilter {
+    enabled: bool,
+    gap: Duration,
+    at_line_start: bool,
+    last_output: Option<Instant>,
+}
+
+/// The `idle-gap` setting, None without one.
+pub fn gap(settings: &HashMap<String, SettingValue>) -> Result<Option<Duration>, String> {
+    // "set idle-gap 1" is parsed as a boolean
+    let secs = match settings.get(SETTING_GAP) {
+        Some(SettingValue::String(s)) => s.parse::<f64>().ok(),
+        Some(SettingValue::Bool(true)) => Some(1.0),
+        _ => return Ok(None),
+    };
+    secs.filter(|s| *s > 0.0)
+        .and_then(|s| Duration::try_from_secs_f64(s).ok())
+        .map(Some)
+        .ok_or_else(|| format!("{}: expected a number of seconds", SETTING_GAP))
+}
+
+impl IdleFilter {
+    pub fn new() -> Self {
+        IdleFilter {
+            enabled: false,
+            gap: DEFAULT_GAP,
+            at_line_start: true,
+            last_output: None,
+        }
+    }
+
+    fn filter_out_at(&mut self, buf: &[u8], now: Instant, output: &mut Vec<u8>) {
+        if buf.is_empty() {
+            return;
+        }
+        if let Some(last) = self.last_output {
+            let idle = now.saturating_duration_since(last);
+            if idle >= self.gap {
+                if !self.at_line_start {
+                    output.extend_from_slice(b"\r\n");
+                }
+                write!(output, "--- idle {:.1} s ---\r\n", idle.as_secs_f64()).unwrap();
+            }
+        }
+        output.extend_from_slice(buf);
+        self.at_line_start = buf.ends_with(b"\n");
+        self.last_output = Some(now);
+    }
+}
+
+impl Default for IdleFilter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IoFilter for IdleFilter {
+    fn name(&self) -> &str {
+        NAME
+    }
+
+    fn settings(&self) -> &[&str] {
+        &[SETTING_GAP]
+    }
+
+    fn configure(&mut self, settings: &HashMap<String, SettingValue>) {
+        match gap(settings) {
+            Ok(Some(gap)) => {
+                self.gap = gap;
+                // Auto-enable if a gap is configured
+                self.enabled = true;
+            }
+            Ok(None) => {}
+            Err(e) => warn!("{}", e),
+        }
+    }
+
+    fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn toggle(&mut self) {
+        self.enabled = !self.enabled;
+        // Time spent disabled is not reported as idle
+        self.last_output = None;
+    }
+
+    fn filter_out(&mut self, buf: &[u8], output: &mut Vec<u8>) {
+        self.filter_out_at(buf, clock::now(), output);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feed(f: &mut IdleFilter, buf: &[u8], now: Instant) -> Vec<u8> {
+        let mut out = Vec::new();
+        f.filter_out_at(buf, now, &mut out);
+        out
+    }
+
+    #[test]
+    fn test_marker_after_gap() {
+        let mut f = IdleFilter::new();
+        let t0 = clock::now();
+        assert_eq!(feed(&mut f, b"boot\r\n", t0), b"boot\r\n");
+        assert_eq!(
+            feed(&mut f, b"more\r\n", t0 + Duration::from_secs(1)),
+            b"more\r\n"
+        );
+        assert_eq!(
+            feed(&mut f, b"late", t0 + Duration::from_millis(13_400)),
+            b"--- idle 12.4 s ---\r\nlate"
+        );
+        // Not at the start of a line, so the marker gets a line of its own
+        assert_eq!(
+            feed(&mut f, b"r\r\n", t0 + Duration::from_secs(20)),
+            b"\r\n--- idle 6.6 s ---\r\nr\r\n"
+        );
+    }
+
+    #[test]
+    fn test_configure() {
+        let mut f = IdleFilter::new();
+        f.configure(&HashMap::new());
+        assert!(!f.enabled());
+
+        let mut settings = HashMap::new();
+        settings.insert(
+            SETTING_GAP.to_string(),
+            SettingValue::String("0.5".to_string()),
+        );
+        f.configure(&settings);
+        assert!(f.enabled());
+        for bad in ["0", "-1", "1e30", "soon"] {
+            let settings = HashMap::from([(
+                SETTING_GAP.to_string(),
+                SettingValue::String(bad.to_string()),
+            )]);
+            assert!(gap(&settings).is_err(), "{}", bad);
+        }
+        let t0 = clock::now();
+        feed(&mut f, b"a\n", t0);
+        assert_eq!(
+            feed(&mut f, b"b\n", t0 + Duration::from_millis(600)),
+            b"--- idle 0.6 s ---\r\nb\n"
+        );
+    }
+}
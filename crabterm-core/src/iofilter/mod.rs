@@ -0,0 +1,372 @@
+pub mod charmap;
+pub mod dedup;
+pub mod hex;
+pub mod idle;
+pub mod timestamp;
+pub mod wrap;
+
+use log::warn;
+use std::collections::HashMap;
+
+use crate::keybind::config::SettingValue;
+pub use charmap::CharmapFilter;
+pub use dedup::DedupFilter;
+pub use hex::HexFilter;
+pub use idle::IdleFilter;
+pub use timestamp::TimestampFilter;
+pub use wrap::WrapFilter;
+
+/// Setting with the application order of the filters, e.g. "charmap,timestamp"
+pub const SETTING_ORDER: &str = "filter-order";
+/// Setting with the filters that are on from the start, e.g. "timestamp"
+pub const SETTING_ENABLED: &str = "filters";
+
+/// Trait for filters that transform data
+pub trait IoFilter {
+    /// Name used by `filter-toggle`, `filter-order` etc.
+    fn name(&self) -> &str;
+
+    /// Names of the settings the filter reads in `configure()`
+    fn settings(&self) -> &[&str] {
+        &[]
+    }
+
+    /// Apply settings from the configuration file
+    fn configure(&mut self, _settings: &HashMap<String, SettingValue>) {}
+
+    /// Returns whether the filter is currently enabled
+    fn enabled(&self) -> bool;
+
+    /// Toggle the filter on/off
+    fn toggle(&mut self);
+
+    /// Filter output data (device -> terminal), appending the result to
+    /// `out`. `out` is reused between calls, so filters must not clear it.
+    fn filter_out(&mut self, buf: &[u8], out: &mut Vec<u8>) {
+        out.extend_from_slice(buf);
+    }
+
+    /// Filter input data (terminal -> device), appending the result to `out`
+    fn filter_in(&mut self, buf: &[u8], out: &mut Vec<u8>) {
+        out.extend_from_slice(buf);
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Direction {
+    Out,
+    In,
+}
+
+/// Creates a fresh filter chain, e.g. one per connecting client.
+pub type FilterChainFactory = Box<dyn Fn() -> FilterChain>;
+
+/// The filters built into crabterm, in their default order.
+pub fn builtin_filters() -> Vec<Box<dyn IoFilter>> {
+    vec![
+        // First, so the output of the others is not dumped
+        Box::new(HexFilter::new()),
+        Box::new(IdleFilter::new()),
+        Box::new(DedupFilter::new()),
+        Box::new(TimestampFilter::new()),
+        Box::new(CharmapFilter::new()),
+        Box::new(WrapFilter::new()),
+    ]
+}
+
+/// An ordered list of filters. Output (device -> terminal) passes the
+/// filters first to last, input (terminal -> device) last to first.
+pub struct FilterChain {
+    filters: Vec<Box<dyn IoFilter>>,
+    // Intermediate results between filters, kept to avoid allocating per
+    // buffer
+    scratch: [Vec<u8>; 2],
+}
+
+impl FilterChain {
+    /// The built-in filters, configured and ordered by `settings`.
+    pub fn new(settings: &HashMap<String, SettingValue>) -> Self {
+        Self::with_filters(builtin_filters(), settings)
+    }
+
+    /// Configure `filters` with `settings`, apply `filter-order` and turn on
+    /// the filters of `filters`.
+    pub fn with_filters(
+        filters: Vec<Box<dyn IoFilter>>,
+        settings: &HashMap<String, SettingValue>,
+    ) -> Self {
+        let mut chain = FilterChain {
+            filters: Vec::new(),
+            scratch: [Vec::new(), Vec::new()],
+        };
+        for mut f in filters {
+            f.configure(settings);
+            chain.register(f);
+        }
+        if let Some(order) = settings.get(SETTING_ORDER).and_then(|v| v.as_str()) {
+            let names: Vec<&str> = order.split(',').map(|s| s.trim()).collect();
+            if let Err(e) = chain.set_order(&names) {
+                warn!("{}: {}", SETTING_ORDER, e);
+            }
+        }
+        if let Some(names) = settings.get(SETTING_ENABLED).and_then(|v| v.as_str()) {
+            for name in names.split(',').map(|s| s.trim()).filter(|s| !s.is_empty()) {
+                if !chain.names().contains(&name) {
+                    warn!("{}: unknown filter: {}", SETTING_ENABLED, name);
+                } else if !chain.enabled(name) {
+                    chain.toggle(name);
+                }
+            }
+        }
+        chain
+    }
+
+    /// Add a filter at the end of the chain. A filter with the same name is
+    /// replaced in place.
+    pub fn register(&mut self, filter: Box<dyn IoFilter>) {
+        match self.filters.iter().position(|f| f.name() == filter.name()) {
+            Some(i) => self.filters[i] = filter,
+            None => self.filters.push(filter),
+        }
+    }
+
+    /// Put the named filters first, in the given order; the others follow in
+    /// their current order. Unknown names are an error and change nothing.
+    pub fn set_order(&mut self, names: &[&str]) -> Result<(), String> {
+        if let Some(unknown) = names.iter().find(|n| !self.names().contains(n)) {
+            return Err(format!("unknown filter: {}", unknown));
+        }
+        self.filters.sort_by_key(|f| {
+            names
+                .iter()
+                .position(|n| *n == f.name())
+                .unwrap_or(names.len())
+        });
+        Ok(())
+    }
+
+    /// Enable exactly the named filters and disable all others. Unknown
+    /// names are an error and change nothing.
+    pub fn set_enabled(&mut self, names: &[&str]) -> Result<(), String> {
+        if let Some(unknown) = names.iter().find(|n| !self.names().contains(n)) {
+            return Err(format!("unknown filter: {}", unknown));
+        }
+        for f in self.filters.iter_mut() {
+            if f.enabled() != names.contains(&f.name()) {
+                f.toggle();
+            }
+        }
+        Ok(())
+    }
+
+    /// Names of the filters in application order.
+    pub fn names(&self) -> Vec<&str> {
+        self.filters.iter().map(|f| f.name()).collect()
+    }
+
+    /// Toggle a filter by name. Returns true if the filter exists.
+    pub fn toggle(&mut self, name: &str) -> bool {
+        match self.filters.iter_mut().find(|f| f.name() == name) {
+            Some(f) => {
+                f.toggle();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// True if the named filter exists and is enabled.
+    pub fn enabled(&self, name: &str) -> bool {
+        self.filters.iter().any(|f| f.name() == name && f.enabled())
+    }
+
+    /// Apply all active output filters (device -> terminal)
+    pub fn filter_out(&mut self, buf: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(buf.len());
+        self.filter_out_into(buf, &mut out);
+        out
+    }
+
+    /// Apply all active input filters (terminal -> device)
+    pub fn filter_in(&mut self, buf: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(buf.len());
+        self.filter_in_into(buf, &mut out);
+        out
+    }
+
+    /// Like [`Self::filter_out`], but appends to `out` so the caller can
+    /// reuse its buffer. The chain itself does not allocate once its
+    /// scratch buffers have grown to the size of the data.
+    pub fn filter_out_into(&mut self, buf: &[u8], out: &mut Vec<u8>) {
+        self.apply(Direction::Out, buf, out);
+    }
+
+    /// Like [`Self::filter_in`], but appends to `out`.
+    pub fn filter_in_into(&mut self, buf: &[u8], out: &mut Vec<u8>) {
+        self.apply(Direction::In, buf, out);
+    }
+
+    fn apply(&mut self, dir: Direction, buf: &[u8], out: &mut Vec<u8>) {
+        let active = self.filters.iter().filter(|f| f.enabled()).count();
+        if active == 0 {
+            out.extend_from_slice(buf);
+            return;
+        }
+
+        let FilterChain { filters, scratch } = self;
+        let [src, dst] = scratch;
+        let n = filters.len();
+        let mut i = 0;
+        for k in 0..n {
+            let f = match dir {
+                Direction::Out => &mut filters[k],
+                Direction::In => &mut filters[n - 1 - k],
+            };
+            if !f.enabled() {
+                continue;
+            }
+            let input: &[u8] = if i == 0 { buf } else { src };
+            // The last filter writes straight into the caller's buffer
+            let output = if i + 1 == active {
+                &mut *out
+            } else {
+                dst.clear();
+                &mut *dst
+            };
+            match dir {
+                Direction::Out => f.filter_out(input, output),
+                Direction::In => f.filter_in(input, output),
+            }
+            std::mem::swap(src, dst);
+            i += 1;
+        }
+    }
+}
+
+impl Default for FilterChain {
+    fn default() -> Self {
+        Self::new(&HashMap::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Wraps output in `tag` characters
+    struct TagFilter(&'static str);
+
+    impl IoFilter for TagFilter {
+        fn name(&self) -> &str {
+            self.0
+        }
+        fn enabled(&self) -> bool {
+            true
+        }
+        fn toggle(&mut self) {}
+        fn filter_out(&mut self, buf: &[u8], out: &mut Vec<u8>) {
+            out.extend_from_slice(self.0.as_bytes());
+            out.extend_from_slice(buf);
+            out.extend_from_slice(self.0.as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_register_and_order() {
+        let mut chain = FilterChain::with_filters(Vec::new(), &HashMap::new());
+        chain.register(Box::new(TagFilter("a")));
+        chain.register(Box::new(TagFilter("b")));
+        assert_eq!(chain.filter_out(b"x"), b"baxab");
+
+        chain.set_order(&["b"]).unwrap();
+        assert_eq!(chain.names(), vec!["b", "a"]);
+        assert_eq!(chain.filter_out(b"x"), b"abxba");
+
+        assert!(chain.set_order(&["b", "nope"]).is_err());
+        assert_eq!(chain.names(), vec!["b", "a"]);
+    }
+
+    #[test]
+    fn test_filter_into_appends() {
+        let mut chain = FilterChain::with_filters(Vec::new(), &HashMap::new());
+        let mut out = b"<".to_vec();
+        chain.filter_out_into(b"x", &mut out);
+        assert_eq!(out, b"<x");
+
+        chain.register(Box::new(TagFilter("a")));
+        chain.register(Box::new(TagFilter("b")));
+        chain.register(Box::new(TagFilter("c")));
+        chain.filter_out_into(b"y", &mut out);
+        assert_eq!(out, b"<xcbayabc");
+
+        // Scratch buffers from the previous call must not leak into the next
+        out.clear();
+        chain.filter_out_into(b"z", &mut out);
+        assert_eq!(out, b"cbazabc");
+        out.clear();
+        chain.filter_in_into(b"z", &mut out);
+        assert_eq!(out, b"z");
+    }
+
+    #[test]
+    fn test_set_enabled() {
+        let mut settings = HashMap::new();
+        settings.insert(
+            charmap::SETTING_IMAP.to_string(),
+            SettingValue::String("crlf".to_string()),
+        );
+        // charmap enables itself when configured
+        let mut chain = FilterChain::new(&settings);
+        assert_eq!(chain.filter_out(b"a\r"), b"a\n");
+
+        chain.set_enabled(&[]).unwrap();
+        assert_eq!(chain.filter_out(b"a\r"), b"a\r");
+        chain.set_enabled(&[charmap::NAME]).unwrap();
+        assert_eq!(chain.filter_out(b"a\r"), b"a\n");
+        assert!(chain.set_enabled(&["nope"]).is_err());
+    }
+
+    #[test]
+    fn test_enabled_setting() {
+        let mut settings = HashMap::new();
+        settings.insert(
+            SETTING_ENABLED.to_string(),
+            SettingValue::String("hex, nope".to_string()),
+        );
+        let mut chain = FilterChain::new(&settings);
+        assert!(chain.enabled(hex::NAME));
+        assert!(!chain.enabled(timestamp::NAME));
+        assert_eq!(chain.filter_out(b"a\n"), b"61 0a\r\n");
+    }
+
+    #[test]
+    fn test_order_setting() {
+        assert_eq!(
+            FilterChain::default().names(),
+            vec![
+                hex::NAME,
+                idle::NAME,
+                dedup::NAME,
+                timestamp::NAME,
+                charmap::NAME,
+                wrap::NAME
+            ]
+        );
+        let mut settings = HashMap::new();
+        settings.insert(
+            SETTING_ORDER.to_string(),
+            SettingValue::String("charmap, timestamp".to_string()),
+        );
+        assert_eq!(
+            FilterChain::new(&settings).names(),
+            vec![
+                charmap::NAME,
+                timestamp::NAME,
+                hex::NAME,
+                idle::NAME,
+                dedup::NAME,
+                wrap::NAME
+            ]
+        );
+    }
+}
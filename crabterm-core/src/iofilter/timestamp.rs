@@ -0,0 +1,291 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::time::Instant;
+
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Local, NaiveDate};
+use log::warn;
+
+use super::IoFilter;
+use crate::clock;
+use crate::keybind::config::SettingValue;
+
+pub const NAME: &str = "timestamp";
+pub const SETTING_ABS: &str = "timestamp-abs";
+pub const SETTING_REL: &str = "timestamp-rel";
+pub const SETTING_FORMAT: &str = "timestamp-format";
+pub const SETTING_EOL: &str = "timestamp-eol";
+
+pub const DEFAULT_FORMAT: &str = "%H:%M:%S%.3f";
+/// Used for `timestamp-format iso`
+pub const ISO_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.3f%:z";
+
+/// What ends a line, i.e. when the next output gets a timestamp
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LineEnd {
+    Lf,
+    Cr,
+    CrLf,
+    /// CR, LF or CRLF
+    Any,
+}
+
+impl LineEnd {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "lf" => Some(LineEnd::Lf),
+            "cr" => Some(LineEnd::Cr),
+            "crlf" => Some(LineEnd::CrLf),
+            "any" => Some(LineEnd::Any),
+            _ => None,
+        }
+    }
+}
+
+pub struct TimestampFilter {
+    enabled: bool,
+    show_abs: bool,
+    show_rel: bool,
+    format: String,
+    line_end: LineEnd,
+    at_line_start: bool,
+    // The previous byte (possibly from the previous read) was '\r'
+    after_cr: bool,
+    last_output: Option<Instant>,
+    // Date of the last absolute timestamp, a marker line is written when it
+    // changes
+    last_date: Option<NaiveDate>,
+}
+
+impl TimestampFilter {
+    pub fn new() -> Self {
+        TimestampFilter {
+            enabled: false,
+            show_abs: true,
+            show_rel: false,
+            format: DEFAULT_FORMAT.to_string(),
+            line_end: LineEnd::Any,
+            at_line_start: true,
+            after_cr: false,
+            last_output: None,
+            last_date: None,
+        }
+    }
+
+    /// Set the chrono format of absolute timestamps; "iso" selects
+    /// [`ISO_FORMAT`].
+    pub fn set_format(&mut self, format: &str) -> Result<(), String> {
+        let format = if format.eq_ignore_ascii_case("iso") {
+            ISO_FORMAT
+        } else {
+            format
+        };
+        // Formatting with an invalid specifier fails at write time
+        if StrftimeItems::new(format).any(|i| matches!(i, Item::Error)) {
+            return Err(format!("invalid format: {}", format));
+        }
+        self.format = format.to_string();
+        Ok(())
+    }
+
+    fn stamp(&mut self, now: DateTime<Local>, output: &mut Vec<u8>) {
+        if self.show_abs {
+            let date = now.date_naive();
+            if self.last_date.is_some_and(|d| d != date) {
+                write!(output, "--- {} ---\r\n", date.format("%Y-%m-%d")).unwrap();
+            }
+            self.last_date = Some(date);
+            write!(output, "{} ", now.format(&self.format)).unwrap();
+        }
+        if self.show_rel {
+            let elapsed = self
+                .last_output
+                .map(|t| clock::now().duration_since(t))
+                .unwrap_or_default();
+            write!(output, "+{:>6.3} ", elapsed.as_secs_f64()).unwrap();
+        }
+        self.last_output = Some(clock::now());
+    }
+
+    fn filter_out_at(&mut self, buf: &[u8], now: DateTime<Local>, output: &mut Vec<u8>) {
+        for &byte in buf {
+            if byte == b'\n' || byte == b'\r' {
+                output.push(byte);
+                let line_end = match (self.line_end, byte) {
+                    (LineEnd::Lf | LineEnd::Any, b'\n') => true,
+                    (LineEnd::Cr | LineEnd::Any, b'\r') => true,
+                    (LineEnd::CrLf, b'\n') => self.after_cr,
+                    _ => false,
+                };
+                if line_end {
+                    self.at_line_start = true;
+                }
+            } else {
+                if self.at_line_start {
+                    self.stamp(now, output);
+                    self.at_line_start = false;
+                }
+                output.push(byte);
+            }
+            self.after_cr = byte == b'\r';
+        }
+    }
+}
+
+impl Default for TimestampFilter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IoFilter for TimestampFilter {
+    fn name(&self) -> &str {
+        NAME
+    }
+
+    fn settings(&self) -> &[&str] {
+        &[SETTING_ABS, SETTING_REL, SETTING_FORMAT, SETTING_EOL]
+    }
+
+    fn configure(&mut self, settings: &HashMap<String, SettingValue>) {
+        if let Some(value) = settings.get(SETTING_ABS).and_then(|v| v.as_bool()) {
+            self.show_abs = value;
+        }
+        if let Some(value) = settings.get(SETTING_REL).and_then(|v| v.as_bool()) {
+            self.show_rel = value;
+        }
+        if let Some(value) = settings.get(SETTING_FORMAT).and_then(|v| v.as_str())
+            && let Err(e) = self.set_format(value)
+        {
+            warn!("{}: {}", SETTING_FORMAT, e);
+        }
+        if let Some(value) = settings.get(SETTING_EOL).and_then(|v| v.as_str()) {
+            match LineEnd::parse(value) {
+                Some(line_end) => self.line_end = line_end,
+                None => warn!("{}: unknown line end: {}", SETTING_EOL, value),
+            }
+        }
+    }
+
+    fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn toggle(&mut self) {
+        self.enabled = !self.enabled;
+    }
+
+    fn filter_out(&mut self, buf: &[u8], output: &mut Vec<u8>) {
+        self.filter_out_at(buf, Local::now(), output);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(d: u32, h: u32, m: u32, s: u32) -> DateTime<Local> {
+        Local
+            .with_ymd_and_hms(2024, 5, d, h, m, s)
+            .earliest()
+            .unwrap()
+    }
+
+    fn apply(f: &mut TimestampFilter, buf: &[u8], now: DateTime<Local>) -> String {
+        let mut out = Vec::new();
+        f.filter_out_at(buf, now, &mut out);
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_format() {
+        let mut f = TimestampFilter::new();
+        assert_eq!(
+            apply(&mut f, b"a\r\nb", at(1, 12, 0, 1)),
+            "12:00:01.000 a\r\n12:00:01.000 b"
+        );
+
+        let mut f = TimestampFilter::new();
+        f.set_format("[%d/%m %H:%M]").unwrap();
+        assert_eq!(apply(&mut f, b"a\n", at(1, 12, 0, 1)), "[01/05 12:00] a\n");
+
+        f.set_format("iso").unwrap();
+        assert!(apply(&mut f, b"a\n", at(1, 12, 0, 1)).starts_with("2024-05-01T12:00:01.000"));
+
+        assert!(f.set_format("%Q").is_err());
+        assert_eq!(f.format, ISO_FORMAT);
+    }
+
+    #[test]
+    fn test_date_rollover_marker() {
+        let mut f = TimestampFilter::new();
+        f.set_format("%H:%M").unwrap();
+        assert_eq!(apply(&mut f, b"a\n", at(1, 23, 59, 0)), "23:59 a\n");
+        assert_eq!(
+            apply(&mut f, b"b\nc\n", at(2, 0, 0, 1)),
+            "--- 2024-05-02 ---\r\n00:00 b\n00:00 c\n"
+        );
+    }
+
+    #[test]
+    fn test_line_ends() {
+        let now = at(1, 12, 0, 0);
+        let cases: &[(LineEnd, &[&[u8]], &str)] = &[
+            (LineEnd::Lf, &[b"a\rb\nc"], "12:00 a\rb\n12:00 c"),
+            (LineEnd::Cr, &[b"a\rb\nc"], "12:00 a\r12:00 b\nc"),
+            (
+                LineEnd::CrLf,
+                &[b"a\rb\nc\r\nd"],
+                "12:00 a\rb\nc\r\n12:00 d",
+            ),
+            (
+                LineEnd::Any,
+                &[b"a\rb\nc\r\nd"],
+                "12:00 a\r12:00 b\n12:00 c\r\n12:00 d",
+            ),
+            // Line ends split across reads
+            (
+                LineEnd::CrLf,
+                &[b"a\r", b"\nb\r", b"c"],
+                "12:00 a\r\n12:00 b\rc",
+            ),
+            (LineEnd::Cr, &[b"a\r", b"b"], "12:00 a\r12:00 b"),
+            (LineEnd::Any, &[b"a\r", b"\n", b"b"], "12:00 a\r\n12:00 b"),
+        ];
+        for (line_end, reads, expected) in cases {
+            let mut f = TimestampFilter::new();
+            f.set_format("%H:%M").unwrap();
+            f.line_end = *line_end;
+            let out: String = reads.iter().map(|r| apply(&mut f, r, now)).collect();
+            assert_eq!(out, *expected, "{:?}", line_end);
+        }
+    }
+
+    #[test]
+    fn test_configure() {
+        let mut f = TimestampFilter::new();
+        let mut settings = HashMap::new();
+        settings.insert(
+            SETTING_FORMAT.to_string(),
+            SettingValue::String("%H:%M".to_string()),
+        );
+        f.configure(&settings);
+        assert_eq!(f.format, "%H:%M");
+
+        // Invalid formats are ignored
+        settings.insert(
+            SETTING_FORMAT.to_string(),
+            SettingValue::String("%Q %H".to_string()),
+        );
+        f.configure(&settings);
+        assert_eq!(f.format, "%H:%M");
+
+        settings.insert(
+            SETTING_EOL.to_string(),
+            SettingValue::String("CRLF".to_string()),
+        );
+        f.configure(&settings);
+        assert_eq!(f.line_end, LineEnd::CrLf);
+    }
+}
@@ -0,0 +1,268 @@
+use std::collections::HashMap;
+
+use super::IoFilter;
+use crate::keybind::config::SettingValue;
+use crate::term::terminal_width;
+
+pub const NAME: &str = "wrap";
+pub const SETTING_TAB_WIDTH: &str = "tab-width";
+pub const SETTING_WRAP_WIDTH: &str = "wrap-width";
+
+const DEFAULT_TAB_WIDTH: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Width {
+    /// Width of the local terminal, no wrapping when stdout is not one
+    Terminal,
+    Columns(usize),
+    /// Only expand tabs
+    Off,
+}
+
+/// Where we are in an escape sequence; these take no space on screen.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Escape {
+    None,
+    Esc,
+    Csi,
+    Osc,
+}
+
+/// Expands tabs and hard-wraps lines longer than the terminal, so output
+/// from devices that assume 80 columns stays readable in narrow panes.
+pub struct WrapFilter {
+    enabled: bool,
+    tab_width: usize,
+    width: Width,
+    column: usize,
+    escape: Escape,
+}
+
+impl WrapFilter {
+    pub fn new() -> Self {
+        WrapFilter {
+            enabled: false,
+            tab_width: DEFAULT_TAB_WIDTH,
+            width: Width::Terminal,
+            column: 0,
+            escape: Escape::None,
+        }
+    }
+
+    fn wrap_at(&self) -> Option<usize> {
+        match self.width {
+            Width::Terminal => terminal_width().map(|w| w as usize),
+            Width::Columns(n) => Some(n),
+            Width::Off => None,
+        }
+    }
+
+    /// Start a new line if the current one is full.
+    fn wrap_if_full(&mut self, wrap_at: Option<usize>, output: &mut Vec<u8>) {
+        if let Some(w) = wrap_at
+            && self.column >= w
+        {
+            output.extend_from_slice(b"\r\n");
+            self.column = 0;
+        }
+    }
+}
+
+impl Default for WrapFilter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IoFilter for WrapFilter {
+    fn name(&self) -> &str {
+        NAME
+    }
+
+    fn settings(&self) -> &[&str] {
+        &[SETTING_TAB_WIDTH, SETTING_WRAP_WIDTH]
+    }
+
+    fn configure(&mut self, settings: &HashMap<String, SettingValue>) {
+        match settings.get(SETTING_TAB_WIDTH) {
+            Some(SettingValue::String(s)) => {
+                if let Ok(n) = s.parse() {
+                    self.tab_width = n;
+                }
+            }
+            // "0" and "1" are parsed as booleans
+            Some(SettingValue::Bool(b)) => self.tab_width = *b as usize,
+            None => {}
+        }
+        match settings.get(SETTING_WRAP_WIDTH) {
+            Some(SettingValue::String(s)) if s == "auto" => self.width = Width::Terminal,
+            Some(SettingValue::String(s)) => {
+                if let Ok(n) = s.parse::<usize>() {
+                    self.width = Width::Columns(n.max(1));
+                }
+            }
+            Some(SettingValue::Bool(false)) => self.width = Width::Off,
+            _ => {}
+        }
+    }
+
+    fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn toggle(&mut self) {
+        self.enabled = !self.enabled;
+    }
+
+    fn filter_out(&mut self, buf: &[u8], output: &mut Vec<u8>) {
+        let wrap_at = self.wrap_at();
+        output.reserve(buf.len());
+        for &byte in buf {
+            match self.escape {
+                Escape::Esc => {
+                    self.escape = match byte {
+                        b'[' => Escape::Csi,
+                        b']' => Escape::Osc,
+                        _ => Escape::None,
+                    };
+                    output.push(byte);
+                    continue;
+                }
+                Escape::Csi => {
+                    if (0x40..=0x7e).contains(&byte) {
+                        self.escape = Escape::None;
+                    }
+                    output.push(byte);
+                    continue;
+                }
+                // Terminated by BEL or ESC \
+                Escape::Osc => {
+                    match byte {
+                        0x07 => self.escape = Escape::None,
+                        0x1b => self.escape = Escape::Esc,
+                        _ => {}
+                    }
+                    output.push(byte);
+                    continue;
+                }
+                Escape::None => {}
+            }
+
+            match byte {
+                0x1b => {
+                    self.escape = Escape::Esc;
+                    output.push(byte);
+                }
+                b'\r' | b'\n' => {
+                    self.column = 0;
+                    output.push(byte);
+                }
+                b'\t' if self.tab_width > 0 => {
+                    self.wrap_if_full(wrap_at, output);
+                    let mut spaces = self.tab_width - self.column % self.tab_width;
+                    if let Some(w) = wrap_at {
+                        spaces = spaces.min(w - self.column);
+                    }
+                    output.resize(output.len() + spaces, b' ');
+                    self.column += spaces;
+                }
+                b'\t' => {
+                    self.column = (self.column / DEFAULT_TAB_WIDTH + 1) * DEFAULT_TAB_WIDTH;
+                    output.push(byte);
+                }
+                0x08 => {
+                    self.column = self.column.saturating_sub(1);
+                    output.push(byte);
+                }
+                // Other control characters and UTF-8 continuation bytes take
+                // no space
+                0x00..=0x1f | 0x7f | 0x80..=0xbf => output.push(byte),
+                _ => {
+                    self.wrap_if_full(wrap_at, output);
+                    self.column += 1;
+                    output.push(byte);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(width: Width, tab_width: usize) -> WrapFilter {
+        WrapFilter {
+            width,
+            tab_width,
+            ..WrapFilter::new()
+        }
+    }
+
+    fn apply(f: &mut WrapFilter, buf: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        f.filter_out(buf, &mut out);
+        out
+    }
+
+    #[test]
+    fn test_expand_tabs() {
+        let mut f = filter(Width::Off, 4);
+        assert_eq!(
+            apply(&mut f, b"a\tbc\tdefg\th\r\n\t"),
+            b"a   bc  defg    h\r\n    "
+        );
+        let mut f = filter(Width::Off, 0);
+        assert_eq!(apply(&mut f, b"a\tb"), b"a\tb");
+    }
+
+    #[test]
+    fn test_wrap() {
+        let mut f = filter(Width::Columns(4), 8);
+        assert_eq!(apply(&mut f, b"abcdefghij\r\n"), b"abcd\r\nefgh\r\nij\r\n");
+        // State is kept across reads, and a full line is only wrapped when
+        // more text follows
+        assert_eq!(apply(&mut f, b"abcd"), b"abcd");
+        assert_eq!(apply(&mut f, b"\r\nab"), b"\r\nab");
+        // Tabs stop at the edge
+        assert_eq!(apply(&mut f, b"\tx"), b"  \r\nx");
+    }
+
+    #[test]
+    fn test_escapes_and_utf8_take_no_space() {
+        let mut f = filter(Width::Columns(3), 8);
+        assert_eq!(
+            apply(&mut f, b"\x1b[31mab\x1b]0;title\x07c\x1b[0md"),
+            b"\x1b[31mab\x1b]0;title\x07c\x1b[0m\r\nd"
+        );
+        let mut f = filter(Width::Columns(3), 8);
+        // Escape sequence split across reads
+        assert_eq!(apply(&mut f, b"a\x1b["), b"a\x1b[");
+        assert_eq!(apply(&mut f, b"1;32mbcd"), b"1;32mbc\r\nd");
+        let mut f = filter(Width::Columns(3), 8);
+        assert_eq!(apply(&mut f, "æøåx".as_bytes()), "æøå\r\nx".as_bytes());
+    }
+
+    #[test]
+    fn test_configure() {
+        let mut f = WrapFilter::new();
+        let mut settings = HashMap::new();
+        settings.insert(
+            SETTING_TAB_WIDTH.to_string(),
+            SettingValue::String("4".to_string()),
+        );
+        settings.insert(
+            SETTING_WRAP_WIDTH.to_string(),
+            SettingValue::String("72".to_string()),
+        );
+        f.configure(&settings);
+        assert_eq!(f.tab_width, 4);
+        assert_eq!(f.width, Width::Columns(72));
+
+        settings.insert(SETTING_WRAP_WIDTH.to_string(), SettingValue::Bool(false));
+        f.configure(&settings);
+        assert_eq!(f.width, Width::Off);
+        // Not enabled by configuration alone
+        assert!(!f.enabled());
+    }
+}
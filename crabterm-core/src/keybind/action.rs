@@ -0,0 +1,130 @@
+use log::LevelFilter;
+use std::fmt;
+use std::path::PathBuf;
+
+use crate::power::PowerOp;
+
+/// Lines copied by `copy-output` without a count
+pub const DEFAULT_COPY_LINES: usize = 20;
+/// Lines printed again by `replay-output` without a count
+pub const DEFAULT_REPLAY_LINES: usize = 50;
+
+/// `level` as written in commands, e.g. "debug"
+pub fn level_name(level: LevelFilter) -> String {
+    level.as_str().to_lowercase()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    Quit,
+    Send(Vec<u8>),
+    FilterToggle(String),
+    Stats,
+    DeviceNext,
+    /// Show the next channel of the active device and send input to it
+    ChannelNext,
+    /// Open the ":" command prompt (handled by the console)
+    Command,
+    SetBaud(u32),
+    CaptureStart(PathBuf),
+    CaptureStop,
+    /// Stop/resume showing device output (handled by the console)
+    PauseOutput,
+    /// Copy the last N lines of output to the clipboard (handled by the
+    /// console)
+    CopyOutput(usize),
+    /// Print the last N lines of output again (handled by the console)
+    ReplayOutput(usize),
+    /// Open a prompt for bytes in hex to send (handled by the console)
+    HexInput,
+    /// Toggle local line editing with history (handled by the console)
+    LineEdit,
+    /// Compose one line locally and send it with the line terminator
+    /// (handled by the console)
+    SendLine,
+    /// Switch mouse events between the device and the local terminal
+    /// (handled by the console)
+    MouseToggle,
+    /// Suspend crabterm like Ctrl+Z in a shell, which in raw mode is sent to
+    /// the device instead (handled by the console)
+    Suspend,
+    /// Run a command on the crabterm server at the other end of
+    /// `crabterm connect` (handled by the console). From a TCP client the hub
+    /// runs the command for that client.
+    Remote(String),
+    /// Measure the round trip to the device with N probes
+    Latency(usize),
+    /// Change the level of the running logger
+    LogLevel(LevelFilter),
+    /// Switch the charmap filter to a preset, or off with `None`
+    Charmap(Option<String>),
+    /// Change a runtime option of the hub, e.g. ("announce", "off"); an
+    /// empty value toggles an on/off option
+    SetOption(String, String),
+    /// Run a power or reset hook of the config on the board
+    Power(PowerOp),
+    /// Write a snapshot of the hub state to the log, and with `true` to the
+    /// requester too
+    DebugDump(bool),
+    /// Give the write lock to the next client waiting for it
+    /// (`--write-lock`)
+    WriteRelease,
+    /// Write a note with a timestamp to the capture and the log; without
+    /// the text the console prompts for it
+    Note(Option<String>),
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::Quit => write!(f, "quit"),
+            Action::Send(bytes) => {
+                if let Ok(s) = std::str::from_utf8(bytes) {
+                    write!(f, "send {:?}", s)
+                } else {
+                    write!(f, "send-bytes {:02x?}", bytes)
+                }
+            }
+            Action::FilterToggle(name) => write!(f, "toggle {}", name),
+            Action::Stats => write!(f, "stats"),
+            Action::DeviceNext => write!(f, "device-next"),
+            Action::ChannelNext => write!(f, "channel-next"),
+            Action::Command => write!(f, "command"),
+            Action::SetBaud(baud) => write!(f, "baud {}", baud),
+            Action::CaptureStart(path) => write!(f, "capture-start {}", path.display()),
+            Action::CaptureStop => write!(f, "capture-stop"),
+            Action::PauseOutput => write!(f, "pause-output"),
+            Action::CopyOutput(lines) => write!(f, "copy-output {}", lines),
+            Action::ReplayOutput(lines) => write!(f, "replay-output {}", lines),
+            Action::HexInput => write!(f, "hex-input"),
+            Action::LineEdit => write!(f, "line-edit"),
+            Action::SendLine => write!(f, "send-line"),
+            Action::MouseToggle => write!(f, "mouse-toggle"),
+            Action::Suspend => write!(f, "suspend"),
+            Action::Remote(command) => write!(f, "remote {}", command),
+            Action::Latency(probes) => write!(f, "latency {}", probes),
+            Action::LogLevel(level) => write!(f, "log-level {}", level_name(*level)),
+            Action::Charmap(Some(preset)) => write!(f, "charmap-preset {}", preset),
+            Action::Charmap(None) => write!(f, "charmap-off"),
+            Action::SetOption(name, value) if value.is_empty() => write!(f, "set-option {}", name),
+            Action::SetOption(name, value) => write!(f, "set-option {} {}", name, value),
+            Action::Power(op) => write!(f, "{}", op),
+            Action::DebugDump(false) => write!(f, "debug-dump"),
+            Action::DebugDump(true) => write!(f, "debug-dump show"),
+            Action::WriteRelease => write!(f, "write-release"),
+            Action::Note(Some(text)) => write!(f, "note {}", text),
+            Action::Note(None) => write!(f, "note"),
+        }
+    }
+}
+
+/// Result of processing input through the keybind processor
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeybindResult {
+    /// Pass these bytes through to the device
+    Passthrough(Vec<u8>),
+    /// Execute this action
+    Action(Action),
+    /// Input was consumed (e.g., prefix key pressed, waiting for more input)
+    Consumed,
+}
@@ -0,0 +1,1088 @@
+use log::info;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+use super::action::{Action, DEFAULT_COPY_LINES, DEFAULT_REPLAY_LINES};
+use super::key::{KEYPAD_NAMES, Key, KeyEvent, Modifiers};
+use super::parser::shifted_function_key;
+use super::processor::key_event_to_bytes;
+use crate::latency;
+use crate::power::PowerOp;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SettingValue {
+    Bool(bool),
+    String(String),
+}
+
+impl SettingValue {
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            SettingValue::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            SettingValue::String(s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+/// A `bell-on "PATTERN" [notify]` directive
+/// What the `if` blocks of the config file are evaluated against
+#[derive(Debug, Clone, Default)]
+pub struct ConfigEnv {
+    /// $TERM
+    pub term: String,
+    /// The host name, without the domain
+    pub host: String,
+}
+
+impl ConfigEnv {
+    pub fn current() -> Self {
+        ConfigEnv {
+            term: std::env::var("TERM").unwrap_or_default(),
+            host: crate::mdns::hostname(),
+        }
+    }
+
+    /// Evaluate a condition: `term=screen*`, or `host!=lab*`.
+    fn matches(&self, cond: &str) -> Result<bool, String> {
+        let (name, negate, pattern) = match cond.split_once("!=") {
+            Some((name, pattern)) => (name, true, pattern),
+            None => {
+                let (name, pattern) = cond
+                    .split_once('=')
+                    .ok_or_else(|| format!("Invalid condition: {} (e.g. term=xterm*)", cond))?;
+                (name, false, pattern)
+            }
+        };
+        let value = match name.trim() {
+            "term" => &self.term,
+            "host" => &self.host,
+            name => return Err(format!("Unknown condition: {} (term, host)", name)),
+        };
+        Ok(glob_match(pattern.trim(), value) != negate)
+    }
+}
+
+/// Shell-style match of `text` against `pattern`, with `*` and `?`.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Position after the last `*`, and the text it matched up to
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p + 1, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((sp, st)) => {
+                    p = sp;
+                    t = st + 1;
+                    star = Some((sp, st + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BellRule {
+    pub pattern: String,
+    /// Also send a desktop notification (OSC 9)
+    pub notify: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct KeybindConfig {
+    pub prefix: Option<KeyEvent>,
+    pub prefix_bindings: HashMap<KeyEvent, Action>,
+    pub direct_bindings: HashMap<KeyEvent, Action>,
+    pub settings: HashMap<String, SettingValue>,
+    pub bells: Vec<BellRule>,
+}
+
+impl Default for KeybindConfig {
+    fn default() -> Self {
+        let mut config = KeybindConfig {
+            prefix: Some(KeyEvent::ctrl_char('a')),
+            prefix_bindings: HashMap::new(),
+            direct_bindings: HashMap::new(),
+            settings: HashMap::new(),
+            bells: Vec::new(),
+        };
+
+        // Default bindings
+        config
+            .direct_bindings
+            .insert(KeyEvent::ctrl_char('q'), Action::Quit);
+        config
+            .prefix_bindings
+            .insert(KeyEvent::char('q'), Action::Send(vec![0x11])); // Send Ctrl+Q
+        config
+            .prefix_bindings
+            .insert(KeyEvent::ctrl_char('a'), Action::Send(vec![0x01])); // Send literal Ctrl+A
+        config.prefix_bindings.insert(
+            KeyEvent::char('t'),
+            Action::FilterToggle("timestamp".to_string()),
+        );
+        config
+            .prefix_bindings
+            .insert(KeyEvent::char('s'), Action::Stats);
+        config
+            .prefix_bindings
+            .insert(KeyEvent::char('d'), Action::DeviceNext);
+        config
+            .prefix_bindings
+            .insert(KeyEvent::char('n'), Action::ChannelNext);
+        config
+            .prefix_bindings
+            .insert(KeyEvent::char('i'), Action::DebugDump(true));
+        config
+            .prefix_bindings
+            .insert(KeyEvent::char('p'), Action::PauseOutput);
+        config
+            .prefix_bindings
+            .insert(KeyEvent::char('y'), Action::CopyOutput(DEFAULT_COPY_LINES));
+        config.prefix_bindings.insert(
+            KeyEvent::char('r'),
+            Action::ReplayOutput(DEFAULT_REPLAY_LINES),
+        );
+        config
+            .prefix_bindings
+            .insert(KeyEvent::char('h'), Action::HexInput);
+        config
+            .prefix_bindings
+            .insert(KeyEvent::char('l'), Action::LineEdit);
+        config
+            .prefix_bindings
+            .insert(KeyEvent::char('e'), Action::SendLine);
+        config
+            .prefix_bindings
+            .insert(KeyEvent::char('a'), Action::Note(None));
+        config
+            .prefix_bindings
+            .insert(KeyEvent::char('m'), Action::MouseToggle);
+        config
+            .prefix_bindings
+            .insert(KeyEvent::ctrl_char('z'), Action::Suspend);
+        config
+            .prefix_bindings
+            .insert(KeyEvent::char(':'), Action::Command);
+
+        config
+    }
+}
+
+impl KeybindConfig {
+    pub fn new() -> Self {
+        Self {
+            prefix: None,
+            prefix_bindings: HashMap::new(),
+            direct_bindings: HashMap::new(),
+            settings: HashMap::new(),
+            bells: Vec::new(),
+        }
+    }
+
+    /// Use `key` as the prefix. A binding sending the old prefix literally
+    /// (Ctrl+a Ctrl+a) moves to the new one.
+    pub fn set_escape_char(&mut self, key: KeyEvent) {
+        if let Some(old) = self.prefix.replace(key)
+            && let Some(bytes) = key_event_to_bytes(&old)
+            && self.prefix_bindings.get(&old) == Some(&Action::Send(bytes))
+            && let Some(bytes) = key_event_to_bytes(&key)
+        {
+            self.prefix_bindings.remove(&old);
+            self.prefix_bindings.insert(key, Action::Send(bytes));
+        }
+    }
+
+    /// Drop the prefix and all bindings, so every key goes to the device
+    /// unchanged.
+    pub fn disable_escape(&mut self) {
+        self.prefix = None;
+        self.prefix_bindings.clear();
+        self.direct_bindings.clear();
+    }
+
+    /// The config at `path`, or ~/.crabterm; the defaults when there is
+    /// none. Err when it does not parse.
+    pub fn load(path: Option<PathBuf>) -> Result<Self, String> {
+        let config_path = if let Some(p) = path {
+            Some(p)
+        } else {
+            dirs::home_dir().map(|home| home.join(".crabterm"))
+        };
+
+        let config = if let Some(ref p) = config_path
+            && p.exists()
+        {
+            let config = KeybindConfig::load_from_file(p)
+                .map_err(|e| format!("Failed to parse {}: {}", p.display(), e))?;
+            info!("Loaded keybind config from {:?}", p);
+            config
+        } else {
+            if config_path.is_some() {
+                info!("Config file {:?} not found, using defaults", config_path);
+            } else {
+                info!("No config file found, using defaults");
+            }
+            KeybindConfig::default()
+        };
+
+        // Log the loaded configuration
+        info!("Keybind configuration:");
+        info!("  Prefix: {:?}", config.prefix);
+        info!(
+            "  Direct bindings: {} entries",
+            config.direct_bindings.len()
+        );
+        for (key, action) in &config.direct_bindings {
+            info!("    {:?} -> {:?}", key, action);
+        }
+        info!(
+            "  Prefix bindings: {} entries",
+            config.prefix_bindings.len()
+        );
+        for (key, action) in &config.prefix_bindings {
+            info!("    {:?} -> {:?}", key, action);
+        }
+
+        Ok(config)
+    }
+
+    pub fn load_from_file<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let content = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        Self::parse(&content)
+    }
+
+    pub fn parse(content: &str) -> Result<Self, String> {
+        Self::parse_for(content, &ConfigEnv::current())
+    }
+
+    /// Parse with the `if` blocks evaluated against `env`.
+    pub fn parse_for(content: &str, env: &ConfigEnv) -> Result<Self, String> {
+        let mut config = KeybindConfig::new();
+        // Lines of blocks that do not apply are checked here, and dropped
+        let mut skipped = KeybindConfig::new();
+        // Line and outcome of the open `if` blocks
+        let mut blocks: Vec<(usize, bool)> = Vec::new();
+
+        for (line_num, line) in content.lines().enumerate() {
+            let line = line.trim();
+
+            // Skip empty lines and comments
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let err = |e| format!("Line {}: {}", line_num + 1, e);
+            let mut body = line;
+            let mut inline = false;
+            if let Some(rest) = line.strip_prefix("if ") {
+                let (cond, rest) = rest
+                    .split_once('{')
+                    .ok_or_else(|| err("Missing { after if".to_string()))?;
+                blocks.push((line_num, env.matches(cond.trim()).map_err(err)?));
+                if rest.trim().is_empty() {
+                    continue;
+                }
+                // One line: if term=screen* { prefix Ctrl+b }
+                body = rest
+                    .trim()
+                    .strip_suffix('}')
+                    .ok_or_else(|| err("Missing } at the end of the line".to_string()))?
+                    .trim();
+                inline = true;
+            } else if line == "}" {
+                blocks
+                    .pop()
+                    .ok_or_else(|| err("} without if".to_string()))?;
+                continue;
+            }
+
+            if !body.is_empty() {
+                let target = if blocks.iter().all(|&(_, on)| on) {
+                    &mut config
+                } else {
+                    &mut skipped
+                };
+                target.parse_line(body).map_err(err)?;
+            }
+            if inline {
+                blocks.pop();
+            }
+        }
+
+        if let Some((line_num, _)) = blocks.last() {
+            return Err(format!("Line {}: Missing }} for if", line_num + 1));
+        }
+        Ok(config)
+    }
+
+    fn parse_line(&mut self, line: &str) -> Result<(), String> {
+        let mut parts = LineParser::new(line);
+
+        let directive = parts.next_word().ok_or("Empty directive")?;
+
+        match directive {
+            "prefix" => {
+                let key_str = parts.next_word().ok_or("Missing key for prefix")?;
+                self.prefix = Some(parse_key_event(key_str)?);
+            }
+            "map-prefix" => {
+                let key_str = parts.next_word().ok_or("Missing key for map-prefix")?;
+                let key = parse_key_event(key_str)?;
+                let action = parse_action(&mut parts)?;
+                self.prefix_bindings.insert(key, action);
+            }
+            "map" => {
+                let key_str = parts.next_word().ok_or("Missing key for map")?;
+                let key = parse_key_event(key_str)?;
+                let action = parse_action(&mut parts)?;
+                self.direct_bindings.insert(key, action);
+            }
+            "set" => {
+                let name = parts.next_word().ok_or("Missing setting name")?;
+                let value = if let Some(quoted) = parts.next_quoted_string() {
+                    SettingValue::String(quoted)
+                } else {
+                    let value_str = parts.rest();
+                    if value_str.is_empty() {
+                        return Err("Missing setting value".to_string());
+                    }
+                    match value_str.to_lowercase().as_str() {
+                        "on" | "true" | "yes" | "1" => SettingValue::Bool(true),
+                        "off" | "false" | "no" | "0" => SettingValue::Bool(false),
+                        _ => SettingValue::String(value_str.to_string()),
+                    }
+                };
+                self.settings.insert(name.to_string(), value);
+            }
+            "bell-on" => {
+                let pattern = parts
+                    .next_quoted_string()
+                    .ok_or("bell-on requires a quoted pattern")?;
+                if pattern.is_empty() {
+                    return Err("bell-on pattern is empty".to_string());
+                }
+                let notify = match parts.take_rest().trim() {
+                    "" => false,
+                    "notify" => true,
+                    rest => return Err(format!("Unexpected argument: {}", rest)),
+                };
+                self.bells.push(BellRule { pattern, notify });
+            }
+            _ => return Err(format!("Unknown directive: {}", directive)),
+        }
+
+        Ok(())
+    }
+}
+
+struct LineParser<'a> {
+    remaining: &'a str,
+}
+
+impl<'a> LineParser<'a> {
+    fn new(line: &'a str) -> Self {
+        Self { remaining: line }
+    }
+
+    fn next_word(&mut self) -> Option<&'a str> {
+        self.remaining = self.remaining.trim_start();
+        if self.remaining.is_empty() {
+            return None;
+        }
+
+        let end = self
+            .remaining
+            .find(char::is_whitespace)
+            .unwrap_or(self.remaining.len());
+        let word = &self.remaining[..end];
+        self.remaining = &self.remaining[end..];
+        Some(word)
+    }
+
+    fn next_quoted_string(&mut self) -> Option<String> {
+        self.remaining = self.remaining.trim_start();
+        if !self.remaining.starts_with('"') {
+            return None;
+        }
+
+        self.remaining = &self.remaining[1..]; // Skip opening quote
+
+        let mut result = String::new();
+        let mut chars = self.remaining.chars().peekable();
+        let mut consumed = 0;
+
+        while let Some(c) = chars.next() {
+            consumed += c.len_utf8();
+            if c == '"' {
+                self.remaining = &self.remaining[consumed..];
+                return Some(result);
+            } else if c == '\\' {
+                if let Some(&next) = chars.peek() {
+                    consumed += next.len_utf8();
+                    chars.next();
+                    match next {
+                        'n' => result.push('\n'),
+                        'r' => result.push('\r'),
+                        't' => result.push('\t'),
+                        '\\' => result.push('\\'),
+                        '"' => result.push('"'),
+                        'x' => {
+                            // Parse \xHH
+                            let mut hex = String::new();
+                            for _ in 0..2 {
+                                if let Some(&h) = chars.peek()
+                                    && h.is_ascii_hexdigit()
+                                {
+                                    hex.push(h);
+                                    consumed += h.len_utf8();
+                                    chars.next();
+                                }
+                            }
+                            if hex.len() == 2
+                                && let Ok(byte) = u8::from_str_radix(&hex, 16)
+                            {
+                                result.push(byte as char);
+                            }
+                        }
+                        _ => {
+                            result.push('\\');
+                            result.push(next);
+                        }
+                    }
+                }
+            } else {
+                result.push(c);
+            }
+        }
+
+        None // Unterminated string
+    }
+
+    fn rest(&self) -> &'a str {
+        self.remaining.trim()
+    }
+
+    fn take_rest(&mut self) -> &'a str {
+        let rest = self.rest();
+        self.remaining = "";
+        rest
+    }
+}
+
+/// The prefix key of `--escape-char`: a letter is taken as Ctrl+letter
+/// (`b` is Ctrl+b), anything else as in the config file.
+pub fn parse_escape_char(s: &str) -> Result<KeyEvent, String> {
+    let mut chars = s.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if c.is_ascii_alphabetic() => {
+            Ok(KeyEvent::ctrl_char(c.to_ascii_lowercase()))
+        }
+        _ => parse_key_event(s),
+    }
+}
+
+fn parse_key_event(s: &str) -> Result<KeyEvent, String> {
+    let mut modifiers = Modifiers::none();
+    let parts: Vec<&str> = s.split('+').collect();
+
+    if parts.is_empty() {
+        return Err("Empty key specification".to_string());
+    }
+
+    // Parse modifiers (all but the last part)
+    for part in &parts[..parts.len() - 1] {
+        match part.to_lowercase().as_str() {
+            "ctrl" | "control" | "c" => modifiers.ctrl = true,
+            "alt" | "meta" | "m" => modifiers.alt = true,
+            "shift" | "s" => modifiers.shift = true,
+            _ => return Err(format!("Unknown modifier: {}", part)),
+        }
+    }
+
+    // Parse the key (last part)
+    let key_str = parts.last().unwrap();
+    let key = parse_key(key_str)?;
+    let (key, modifiers) = shifted_function_key(key, modifiers);
+
+    Ok(KeyEvent::new(key, modifiers))
+}
+
+fn parse_key(s: &str) -> Result<Key, String> {
+    let lower = s.to_lowercase();
+
+    // Function keys
+    if lower.starts_with('f')
+        && lower.len() > 1
+        && let Ok(n) = lower[1..].parse::<u8>()
+        && (1..=24).contains(&n)
+    {
+        return Ok(Key::F(n));
+    }
+
+    // Keypad keys: KP0-KP9, KPEnter, KPPlus, ...
+    if let Some(name) = lower.strip_prefix("kp") {
+        if let [c @ '0'..='9'] = name.chars().collect::<Vec<_>>()[..] {
+            return Ok(Key::Keypad(c));
+        }
+        if let Some((c, _)) = KEYPAD_NAMES
+            .iter()
+            .find(|(_, n)| n.eq_ignore_ascii_case(name))
+        {
+            return Ok(Key::Keypad(*c));
+        }
+    }
+
+    // Named keys
+    match lower.as_str() {
+        "escape" | "esc" => return Ok(Key::Escape),
+        "enter" | "return" | "cr" => return Ok(Key::Enter),
+        "tab" => return Ok(Key::Tab),
+        "backspace" | "bs" => return Ok(Key::Backspace),
+        "up" => return Ok(Key::Up),
+        "down" => return Ok(Key::Down),
+        "left" => return Ok(Key::Left),
+        "right" => return Ok(Key::Right),
+        "home" => return Ok(Key::Home),
+        "end" => return Ok(Key::End),
+        "pageup" | "pgup" => return Ok(Key::PageUp),
+        "pagedown" | "pgdn" => return Ok(Key::PageDown),
+        "insert" | "ins" => return Ok(Key::Insert),
+        "delete" | "del" => return Ok(Key::Delete),
+        "space" => return Ok(Key::Char(' ')),
+        _ => {}
+    }
+
+    // Single character
+    let chars: Vec<char> = s.chars().collect();
+    if chars.len() == 1 {
+        return Ok(Key::Char(chars[0].to_ascii_lowercase()));
+    }
+
+    Err(format!("Unknown key: {}", s))
+}
+
+/// Parse a line typed at the ":" prompt. Accepts the same actions as the
+/// configuration file, and "filter toggle X" style for the two-word ones.
+pub fn parse_command(line: &str) -> Result<Action, String> {
+    let line = line.trim().trim_start_matches(':');
+    let mut parts = LineParser::new(line);
+    let first = parts.next_word().ok_or("Empty command")?;
+
+    let name = match first {
+        "filter" | "capture" | "device" | "channel" | "charmap" => {
+            let second = parts
+                .next_word()
+                .ok_or_else(|| format!("{} requires a sub-command", first))?;
+            format!("{}-{}", first, second)
+        }
+        _ => first.to_string(),
+    };
+    let action = parse_action_named(&name, &mut parts)?;
+    if !parts.rest().is_empty() {
+        return Err(format!("Unexpected argument: {}", parts.rest()));
+    }
+    Ok(action)
+}
+
+fn parse_action(parts: &mut LineParser) -> Result<Action, String> {
+    let action_name = parts.next_word().ok_or("Missing action")?;
+    parse_action_named(action_name, parts)
+}
+
+fn parse_action_named(action_name: &str, parts: &mut LineParser) -> Result<Action, String> {
+    match action_name {
+        "quit" => Ok(Action::Quit),
+        "stats" => Ok(Action::Stats),
+        "device-next" => Ok(Action::DeviceNext),
+        "channel-next" => Ok(Action::ChannelNext),
+        "command" => Ok(Action::Command),
+        "hex-input" => Ok(Action::HexInput),
+        "line-edit" => Ok(Action::LineEdit),
+        "send-line" => Ok(Action::SendLine),
+        "mouse-toggle" => Ok(Action::MouseToggle),
+        "suspend" => Ok(Action::Suspend),
+        "baud" => {
+            let baud = parts.next_word().ok_or("baud requires a baudrate")?;
+            let baud = baud
+                .parse()
+                .map_err(|_| format!("Invalid baudrate: {}", baud))?;
+            Ok(Action::SetBaud(baud))
+        }
+        "capture-start" => {
+            let path = parts
+                .next_quoted_string()
+                .or_else(|| parts.next_word().map(|s| s.to_string()))
+                .ok_or("capture-start requires a file name")?;
+            Ok(Action::CaptureStart(PathBuf::from(path)))
+        }
+        "capture-stop" => Ok(Action::CaptureStop),
+        "pause-output" => Ok(Action::PauseOutput),
+        "copy-output" => match parts.next_word() {
+            Some(n) => n
+                .parse()
+                .map(Action::CopyOutput)
+                .map_err(|_| format!("Invalid line count: {}", n)),
+            None => Ok(Action::CopyOutput(DEFAULT_COPY_LINES)),
+        },
+        "replay-output" => match parts.next_word() {
+            Some(n) => match n.parse() {
+                Ok(lines) if lines > 0 => Ok(Action::ReplayOutput(lines)),
+                _ => Err(format!("Invalid line count: {}", n)),
+            },
+            None => Ok(Action::ReplayOutput(DEFAULT_REPLAY_LINES)),
+        },
+        "latency" => match parts.next_word() {
+            Some(n) => match n.parse() {
+                Ok(probes) if probes > 0 => Ok(Action::Latency(probes)),
+                _ => Err(format!("Invalid probe count: {}", n)),
+            },
+            None => Ok(Action::Latency(latency::DEFAULT_PROBES)),
+        },
+        "log-level" => {
+            let level = parts.next_word().ok_or("log-level requires a level")?;
+            level
+                .parse()
+                .map(Action::LogLevel)
+                .map_err(|_| format!("Invalid log level: {}", level))
+        }
+        "filter-toggle" => {
+            let filter_name = parts
+                .next_word()
+                .ok_or("filter-toggle requires a filter name")?;
+            Ok(Action::FilterToggle(filter_name.to_string()))
+        }
+        "charmap-preset" => {
+            let preset = parts
+                .next_word()
+                .ok_or("charmap-preset requires a preset name")?;
+            Ok(Action::Charmap(Some(preset.to_string())))
+        }
+        "charmap-off" => Ok(Action::Charmap(None)),
+        "power" => {
+            let op = parts.next_word().ok_or("power requires on, off or cycle")?;
+            PowerOp::parse(op).map(Action::Power)
+        }
+        "reset" => Ok(Action::Power(PowerOp::Reset)),
+        "debug-dump" => match parts.next_word() {
+            Some("show") => Ok(Action::DebugDump(true)),
+            Some(word) => Err(format!("Invalid debug-dump argument: {} (show)", word)),
+            None => Ok(Action::DebugDump(false)),
+        },
+        "write-release" => Ok(Action::WriteRelease),
+        "note" => {
+            let text = parts.take_rest().trim();
+            Ok(Action::Note((!text.is_empty()).then(|| text.to_string())))
+        }
+        "set-option" => {
+            let name = parts
+                .next_word()
+                .ok_or("set-option requires an option name")?;
+            let value = parts.take_rest().trim();
+            Ok(Action::SetOption(name.to_string(), value.to_string()))
+        }
+        "send" => {
+            let string = parts
+                .next_quoted_string()
+                .ok_or("send requires a quoted string")?;
+            Ok(Action::Send(string.into_bytes()))
+        }
+        "send-bytes" => {
+            let mut bytes = Vec::new();
+            let rest = parts.take_rest();
+            for part in rest.split_whitespace() {
+                let byte = if part.starts_with("0x") || part.starts_with("0X") {
+                    u8::from_str_radix(&part[2..], 16)
+                        .map_err(|_| format!("Invalid hex byte: {}", part))?
+                } else {
+                    part.parse::<u8>()
+                        .map_err(|_| format!("Invalid byte: {}", part))?
+                };
+                bytes.push(byte);
+            }
+            if bytes.is_empty() {
+                return Err("send-bytes requires at least one byte".to_string());
+            }
+            Ok(Action::Send(bytes))
+        }
+        "remote" => {
+            let command = parts.take_rest().trim();
+            if command.is_empty() {
+                return Err("remote requires a command".to_string());
+            }
+            Ok(Action::Remote(command.to_string()))
+        }
+        _ => Err(format!("Unknown action: {}", action_name)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_simple_config() {
+        let config = KeybindConfig::parse(
+            r#"
+            # This is a comment
+            prefix Ctrl+a
+            map-prefix q quit
+            map Ctrl+q quit
+            map-prefix s stats
+            map-prefix d device-next
+            map-prefix n channel-next
+        "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.prefix, Some(KeyEvent::ctrl_char('a')));
+        assert_eq!(
+            config.prefix_bindings.get(&KeyEvent::char('q')),
+            Some(&Action::Quit)
+        );
+        assert_eq!(
+            config.direct_bindings.get(&KeyEvent::ctrl_char('q')),
+            Some(&Action::Quit)
+        );
+        assert_eq!(
+            config.prefix_bindings.get(&KeyEvent::char('s')),
+            Some(&Action::Stats)
+        );
+        assert_eq!(
+            config.prefix_bindings.get(&KeyEvent::char('d')),
+            Some(&Action::DeviceNext)
+        );
+        assert_eq!(
+            config.prefix_bindings.get(&KeyEvent::char('n')),
+            Some(&Action::ChannelNext)
+        );
+    }
+
+    #[test]
+    fn test_parse_send_action() {
+        let config = KeybindConfig::parse(
+            r#"
+            map-prefix s send "hello\r\n"
+        "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.prefix_bindings.get(&KeyEvent::char('s')),
+            Some(&Action::Send(b"hello\r\n".to_vec()))
+        );
+    }
+
+    #[test]
+    fn test_parse_bell_on() {
+        let config = KeybindConfig::parse(
+            r#"
+            bell-on "done"
+            bell-on "login:" notify
+        "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.bells,
+            vec![
+                BellRule {
+                    pattern: "done".to_string(),
+                    notify: false
+                },
+                BellRule {
+                    pattern: "login:".to_string(),
+                    notify: true
+                },
+            ]
+        );
+        assert!(KeybindConfig::parse("bell-on done").is_err());
+        assert!(KeybindConfig::parse("bell-on \"\"").is_err());
+        assert!(KeybindConfig::parse("bell-on \"done\" loudly").is_err());
+    }
+
+    #[test]
+    fn test_parse_send_bytes() {
+        let config = KeybindConfig::parse(
+            r#"
+            map-prefix e send-bytes 0x1b 0x4f
+        "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.prefix_bindings.get(&KeyEvent::char('e')),
+            Some(&Action::Send(vec![0x1b, 0x4f]))
+        );
+    }
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(parse_command("stats"), Ok(Action::Stats));
+        assert_eq!(parse_command(":baud 57600"), Ok(Action::SetBaud(57600)));
+        assert_eq!(
+            parse_command("capture start /tmp/x.log"),
+            Ok(Action::CaptureStart(PathBuf::from("/tmp/x.log")))
+        );
+        assert_eq!(parse_command("capture stop"), Ok(Action::CaptureStop));
+        assert_eq!(
+            parse_command("filter toggle timestamp"),
+            Ok(Action::FilterToggle("timestamp".to_string()))
+        );
+        assert_eq!(
+            parse_command("filter-toggle charmap"),
+            Ok(Action::FilterToggle("charmap".to_string()))
+        );
+        assert_eq!(
+            parse_command("send \"reboot\\r\""),
+            Ok(Action::Send(b"reboot\r".to_vec()))
+        );
+        assert_eq!(parse_command("copy-output 5"), Ok(Action::CopyOutput(5)));
+        assert_eq!(
+            parse_command("copy-output"),
+            Ok(Action::CopyOutput(DEFAULT_COPY_LINES))
+        );
+        assert_eq!(
+            parse_command("replay-output 5"),
+            Ok(Action::ReplayOutput(5))
+        );
+        assert_eq!(
+            parse_command("replay-output"),
+            Ok(Action::ReplayOutput(DEFAULT_REPLAY_LINES))
+        );
+        assert!(parse_command("replay-output 0").is_err());
+        assert_eq!(
+            parse_command("remote filter toggle dedup"),
+            Ok(Action::Remote("filter toggle dedup".to_string()))
+        );
+        assert!(parse_command("remote").is_err());
+        assert_eq!(
+            parse_command("note  started test 42 "),
+            Ok(Action::Note(Some("started test 42".to_string())))
+        );
+        assert_eq!(parse_command("note"), Ok(Action::Note(None)));
+        assert!(parse_command("copy-output all").is_err());
+        assert_eq!(parse_command("latency 10"), Ok(Action::Latency(10)));
+        assert_eq!(
+            parse_command("latency"),
+            Ok(Action::Latency(latency::DEFAULT_PROBES))
+        );
+        assert!(parse_command("latency 0").is_err());
+        assert_eq!(
+            parse_command("debug-dump show"),
+            Ok(Action::DebugDump(true))
+        );
+        assert!(parse_command("debug-dump all").is_err());
+        assert_eq!(
+            parse_command("power cycle"),
+            Ok(Action::Power(PowerOp::Cycle))
+        );
+        assert_eq!(parse_command("reset"), Ok(Action::Power(PowerOp::Reset)));
+        assert!(parse_command("power").is_err());
+        assert_eq!(
+            parse_command(":log-level debug"),
+            Ok(Action::LogLevel(log::LevelFilter::Debug))
+        );
+        assert_eq!(
+            Action::LogLevel(log::LevelFilter::Trace).to_string(),
+            "log-level trace"
+        );
+        assert!(parse_command("log-level loud").is_err());
+        assert!(parse_command("log-level").is_err());
+        assert_eq!(
+            parse_command("charmap preset unix"),
+            Ok(Action::Charmap(Some("unix".to_string())))
+        );
+        assert_eq!(parse_command(":charmap-off"), Ok(Action::Charmap(None)));
+        assert!(parse_command("charmap preset").is_err());
+        assert_eq!(
+            parse_command("set-option silence-timeout 300"),
+            Ok(Action::SetOption(
+                "silence-timeout".to_string(),
+                "300".to_string()
+            ))
+        );
+        assert_eq!(
+            Action::SetOption("announce".to_string(), String::new()).to_string(),
+            "set-option announce"
+        );
+        assert!(parse_command("set-option").is_err());
+        assert_eq!(parse_command("send-line"), Ok(Action::SendLine));
+        assert!(parse_command("baud fast").is_err());
+        assert!(parse_command("stats now").is_err());
+        assert!(parse_command("frobnicate").is_err());
+    }
+
+    #[test]
+    fn test_parse_key_with_modifiers() {
+        let key = parse_key_event("Ctrl+Shift+a").unwrap();
+        assert!(key.modifiers.ctrl);
+        assert!(key.modifiers.shift);
+        assert!(!key.modifiers.alt);
+        assert_eq!(key.key, Key::Char('a'));
+    }
+
+    #[test]
+    fn test_parse_extended_keys() {
+        let key = parse_key_event("F24").unwrap();
+        assert_eq!(key.key, Key::F(24));
+        // Shift+F1-F12 is what F13-F24 send
+        assert_eq!(
+            parse_key_event("Shift+F2").unwrap(),
+            parse_key_event("F14").unwrap()
+        );
+        assert!(parse_key_event("F25").is_err());
+
+        assert_eq!(parse_key_event("KP7").unwrap().key, Key::Keypad('7'));
+        assert_eq!(parse_key_event("kpenter").unwrap().key, Key::Keypad('\r'));
+        assert_eq!(parse_key_event("KPMinus").unwrap().key, Key::Keypad('-'));
+        assert!(parse_key_event("KPFoo").is_err());
+
+        assert_eq!(parse_key_event("Ctrl+]").unwrap(), KeyEvent::ctrl_char(']'));
+        assert_eq!(
+            parse_key_event("Ctrl+\\").unwrap(),
+            KeyEvent::ctrl_char('\\')
+        );
+    }
+
+    #[test]
+    fn test_parse_function_key() {
+        let key = parse_key_event("Alt+F1").unwrap();
+        assert!(key.modifiers.alt);
+        assert_eq!(key.key, Key::F(1));
+    }
+
+    #[test]
+    fn test_parse_quoted_setting() {
+        let config = KeybindConfig::parse(
+            r#"
+            set announce-template "MSG-%s: %t %m"
+            set other-setting value
+        "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.settings.get("announce-template"),
+            Some(&SettingValue::String("MSG-%s: %t %m".to_string()))
+        );
+        assert_eq!(
+            config.settings.get("other-setting"),
+            Some(&SettingValue::String("value".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_escape_char() {
+        assert_eq!(parse_escape_char("b"), Ok(KeyEvent::ctrl_char('b')));
+        assert_eq!(parse_escape_char("B"), Ok(KeyEvent::ctrl_char('b')));
+        assert_eq!(parse_escape_char("ctrl+]"), Ok(KeyEvent::ctrl_char(']')));
+
+        let mut config = KeybindConfig::default();
+        config.set_escape_char(KeyEvent::ctrl_char('b'));
+        assert_eq!(config.prefix, Some(KeyEvent::ctrl_char('b')));
+        assert_eq!(
+            config.prefix_bindings.get(&KeyEvent::ctrl_char('b')),
+            Some(&Action::Send(vec![0x02]))
+        );
+        assert!(
+            !config
+                .prefix_bindings
+                .contains_key(&KeyEvent::ctrl_char('a'))
+        );
+        assert_eq!(
+            config.prefix_bindings.get(&KeyEvent::char('q')),
+            Some(&Action::Send(vec![0x11]))
+        );
+
+        config.disable_escape();
+        assert_eq!(config.prefix, None);
+        assert!(config.prefix_bindings.is_empty() && config.direct_bindings.is_empty());
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("screen*", "screen-256color"));
+        assert!(glob_match("screen*", "screen"));
+        assert!(!glob_match("screen*", "xterm"));
+        assert!(glob_match("lab?-*x", "lab1-boxx"));
+        assert!(glob_match("*", ""));
+        assert!(!glob_match("lab", "labserver"));
+    }
+
+    #[test]
+    fn test_conditional_blocks() {
+        let content = r#"
+            prefix Ctrl+a
+            if term=screen* { prefix Ctrl+b }
+            if host=lab* {
+                set timestamp-abs on
+                if term!=xterm* {
+                    map-prefix x stats
+                }
+            }
+        "#;
+        let env = |term: &str, host: &str| ConfigEnv {
+            term: term.to_string(),
+            host: host.to_string(),
+        };
+
+        let config = KeybindConfig::parse_for(content, &env("screen-256color", "laptop")).unwrap();
+        assert_eq!(config.prefix, Some(KeyEvent::ctrl_char('b')));
+        assert!(config.settings.is_empty());
+
+        let config = KeybindConfig::parse_for(content, &env("xterm", "labserver")).unwrap();
+        assert_eq!(config.prefix, Some(KeyEvent::ctrl_char('a')));
+        assert_eq!(
+            config.settings.get("timestamp-abs"),
+            Some(&SettingValue::Bool(true))
+        );
+        assert!(config.prefix_bindings.is_empty());
+
+        let config = KeybindConfig::parse_for(content, &env("vt100", "labserver")).unwrap();
+        assert_eq!(
+            config.prefix_bindings.get(&KeyEvent::char('x')),
+            Some(&Action::Stats)
+        );
+    }
+
+    #[test]
+    fn test_conditional_errors() {
+        let env = ConfigEnv::default();
+        let parse = |content| KeybindConfig::parse_for(content, &env).unwrap_err();
+        assert_eq!(
+            parse("if term=x {\nprefix Ctrl+b"),
+            "Line 1: Missing } for if"
+        );
+        assert_eq!(parse("}"), "Line 1: } without if");
+        assert_eq!(
+            parse("if user=me {\n}"),
+            "Line 1: Unknown condition: user (term, host)"
+        );
+        assert_eq!(
+            parse("if term=x { prefix Ctrl+b"),
+            "Line 1: Missing } at the end of the line"
+        );
+        // Checked even where the block does not apply
+        assert!(parse("if host=elsewhere {\nmap-prefix x nope\n}").starts_with("Line 2: "));
+    }
+}
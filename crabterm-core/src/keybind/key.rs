@@ -47,10 +47,27 @@ impl fmt::Display for Modifiers {
     }
 }
 
+/// Names of the non-digit keypad keys, as used in the configuration after
+/// "KP", e.g. "KPEnter". Digits are "KP0" to "KP9".
+pub const KEYPAD_NAMES: &[(char, &str)] = &[
+    ('\r', "Enter"),
+    ('+', "Plus"),
+    ('-', "Minus"),
+    ('*', "Multiply"),
+    ('/', "Divide"),
+    ('.', "Decimal"),
+    (',', "Comma"),
+    ('=', "Equal"),
+];
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Key {
     Char(char),
+    /// F1-F24
     F(u8),
+    /// Keypad key in application mode, identified by the character it
+    /// normally produces ('\r' for Enter)
+    Keypad(char),
     Escape,
     Enter,
     Tab,
@@ -72,6 +89,10 @@ impl fmt::Display for Key {
         match self {
             Key::Char(c) => write!(f, "{}", c),
             Key::F(n) => write!(f, "F{}", n),
+            Key::Keypad(c) => match KEYPAD_NAMES.iter().find(|(k, _)| k == c) {
+                Some((_, name)) => write!(f, "KP{}", name),
+                None => write!(f, "KP{}", c),
+            },
             Key::Escape => write!(f, "Escape"),
             Key::Enter => write!(f, "Enter"),
             Key::Tab => write!(f, "Tab"),
@@ -90,6 +111,23 @@ impl fmt::Display for Key {
     }
 }
 
+/// A mouse report in SGR format (ESC [ < button ; x ; y M/m)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MouseEvent {
+    /// Button and modifier bits, as reported by the terminal
+    pub button: u16,
+    pub x: u16,
+    pub y: u16,
+    pub release: bool,
+}
+
+impl MouseEvent {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let end = if self.release { 'm' } else { 'M' };
+        format!("\x1b[<{};{};{}{}", self.button, self.x, self.y, end).into_bytes()
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct KeyEvent {
     pub key: Key,
@@ -5,5 +5,5 @@ pub mod parser;
 pub mod processor;
 
 pub use action::{Action, KeybindResult};
-pub use config::KeybindConfig;
+pub use config::{KeybindConfig, parse_command};
 pub use processor::KeybindProcessor;
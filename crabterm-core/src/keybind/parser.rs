@@ -1,11 +1,50 @@
-use super::key::{Key, KeyEvent, Modifiers};
+use super::key::{Key, KeyEvent, Modifiers, MouseEvent};
 use log::debug;
 
+/// SS3 final byte of each keypad key in application mode
+const KEYPAD_SS3: &[(u8, char)] = &[
+    (b'M', '\r'),
+    (b'k', '+'),
+    (b'm', '-'),
+    (b'j', '*'),
+    (b'o', '/'),
+    (b'n', '.'),
+    (b'l', ','),
+    (b'X', '='),
+    (b'p', '0'),
+    (b'q', '1'),
+    (b'r', '2'),
+    (b's', '3'),
+    (b't', '4'),
+    (b'u', '5'),
+    (b'v', '6'),
+    (b'w', '7'),
+    (b'x', '8'),
+    (b'y', '9'),
+];
+
+/// Final byte of the SS3 sequence sent by a keypad key
+pub fn keypad_ss3(c: char) -> Option<u8> {
+    KEYPAD_SS3.iter().find(|(_, k)| *k == c).map(|(b, _)| *b)
+}
+
+/// The key of a control character, e.g. 0x01 is Ctrl+a and 0x1d is Ctrl+]
+fn ctrl_char(byte: u8) -> Option<char> {
+    match byte {
+        0x00 => Some('@'),
+        0x01..=0x1a => Some((byte + b'a' - 1) as char),
+        0x1c..=0x1f => Some((byte + 0x40) as char),
+        _ => None,
+    }
+}
+
 /// Result of parsing bytes
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ParseResult {
     /// Successfully parsed a key event, consumed `bytes_consumed` bytes
     Key(KeyEvent, usize),
+    /// A mouse report, consumed `bytes_consumed` bytes
+    Mouse(MouseEvent, usize),
     /// Need more bytes to determine the key (e.g., after receiving ESC)
     NeedMore,
     /// No valid key sequence found, pass through first byte
@@ -51,6 +90,10 @@ impl KeyParser {
                 self.buffer.drain(..consumed);
                 ParseResult::Key(key, consumed)
             }
+            ParseResult::Mouse(event, consumed) => {
+                self.buffer.drain(..consumed);
+                ParseResult::Mouse(event, consumed)
+            }
             ParseResult::Passthrough(b) => {
                 self.buffer.remove(0);
                 ParseResult::Passthrough(b)
@@ -104,14 +147,13 @@ fn parse_bytes(bytes: &[u8]) -> ParseResult {
         return parse_escape_sequence(bytes);
     }
 
-    // Control characters (0x01-0x1A except some special ones)
-    if (0x01..=0x1a).contains(&first) {
+    // Control characters (0x00-0x1F except ESC and some special ones)
+    if let Some(c) = ctrl_char(first) {
         let result = match first {
             0x09 => ParseResult::Key(KeyEvent::new(Key::Tab, Modifiers::none()), 1),
             0x0d => ParseResult::Key(KeyEvent::new(Key::Enter, Modifiers::none()), 1),
             _ => {
-                // Ctrl+A = 0x01, Ctrl+B = 0x02, ..., Ctrl+Z = 0x1A
-                let c = (first + b'a' - 1) as char;
+                // Ctrl+A = 0x01, ..., Ctrl+Z = 0x1A, Ctrl+\ = 0x1C, ..., Ctrl+_ = 0x1F
                 debug!("Control char detected: 0x{:02x} -> Ctrl+{}", first, c);
                 ParseResult::Key(KeyEvent::new(Key::Char(c), Modifiers::ctrl()), 1)
             }
@@ -160,8 +202,7 @@ fn parse_escape_sequence(bytes: &[u8]) -> ParseResult {
     }
 
     // Alt+Ctrl+key: ESC followed by control character
-    if (0x01..=0x1a).contains(&second) && second != 0x1b {
-        let c = (second + b'a' - 1) as char;
+    if let Some(c) = ctrl_char(second) {
         let mut mods = Modifiers::ctrl();
         mods.alt = true;
         return ParseResult::Key(KeyEvent::new(Key::Char(c), mods), 2);
@@ -185,6 +226,9 @@ fn parse_csi_sequence(bytes: &[u8]) -> ParseResult {
             // Found final byte
             let params = &bytes[2..i];
             let final_byte = b;
+            if let Some(mouse) = params.strip_prefix(b"<") {
+                return interpret_sgr_mouse(mouse, final_byte, i + 1);
+            }
             return interpret_csi(params, final_byte, i + 1);
         }
         // Intermediate bytes are 0x20-0x2F, parameter bytes are 0x30-0x3F
@@ -217,6 +261,11 @@ fn interpret_csi(params: &[u8], final_byte: u8, consumed: usize) -> ParseResult
         b'D' => Some(Key::Left),
         b'H' => Some(Key::Home),
         b'F' => Some(Key::End),
+        // xterm sends modified F1-F4 as CSI 1;<mod> P-S
+        b'P' => Some(Key::F(1)),
+        b'Q' => Some(Key::F(2)),
+        b'R' => Some(Key::F(3)),
+        b'S' => Some(Key::F(4)),
         b'~' => {
             // Tilde sequences: ESC [ <number> ~
             match parts.first().and_then(|s| s.parse::<u8>().ok()) {
@@ -234,6 +283,15 @@ fn interpret_csi(params: &[u8], final_byte: u8, consumed: usize) -> ParseResult
                 Some(21) => Some(Key::F(10)),
                 Some(23) => Some(Key::F(11)),
                 Some(24) => Some(Key::F(12)),
+                // VT220 F13-F20
+                Some(25) => Some(Key::F(13)),
+                Some(26) => Some(Key::F(14)),
+                Some(28) => Some(Key::F(15)),
+                Some(29) => Some(Key::F(16)),
+                Some(31) => Some(Key::F(17)),
+                Some(32) => Some(Key::F(18)),
+                Some(33) => Some(Key::F(19)),
+                Some(34) => Some(Key::F(20)),
                 _ => None,
             }
         }
@@ -241,11 +299,31 @@ fn interpret_csi(params: &[u8], final_byte: u8, consumed: usize) -> ParseResult
     };
 
     match key {
-        Some(k) => ParseResult::Key(KeyEvent::new(k, modifier), consumed),
+        Some(k) => {
+            let (k, modifier) = shifted_function_key(k, modifier);
+            ParseResult::Key(KeyEvent::new(k, modifier), consumed)
+        }
         None => ParseResult::Key(KeyEvent::new(Key::Escape, Modifiers::none()), 1),
     }
 }
 
+fn interpret_sgr_mouse(params: &[u8], final_byte: u8, consumed: usize) -> ParseResult {
+    let params_str = std::str::from_utf8(params).unwrap_or("");
+    let parts: Option<Vec<u16>> = params_str.split(';').map(|s| s.parse().ok()).collect();
+    match (parts.as_deref().unwrap_or_default(), final_byte) {
+        (&[button, x, y], b'M' | b'm') => ParseResult::Mouse(
+            MouseEvent {
+                button,
+                x,
+                y,
+                release: final_byte == b'm',
+            },
+            consumed,
+        ),
+        _ => ParseResult::Key(KeyEvent::new(Key::Escape, Modifiers::none()), 1),
+    }
+}
+
 fn parse_ss3_sequence(bytes: &[u8]) -> ParseResult {
     if bytes.len() < 3 {
         return ParseResult::NeedMore;
@@ -258,7 +336,10 @@ fn parse_ss3_sequence(bytes: &[u8]) -> ParseResult {
         b'S' => Some(Key::F(4)),
         b'H' => Some(Key::Home),
         b'F' => Some(Key::End),
-        _ => None,
+        b => KEYPAD_SS3
+            .iter()
+            .find(|(f, _)| *f == b)
+            .map(|(_, c)| Key::Keypad(*c)),
     };
 
     match key {
@@ -267,6 +348,17 @@ fn parse_ss3_sequence(bytes: &[u8]) -> ParseResult {
     }
 }
 
+/// xterm sends F13-F24 as Shift+F1-F12 (see its terminfo kf13-kf24), report
+/// them as F13-F24 so both can be bound the same way.
+pub fn shifted_function_key(key: Key, modifiers: Modifiers) -> (Key, Modifiers) {
+    match key {
+        Key::F(n @ 1..=12) if modifiers.shift && !modifiers.ctrl && !modifiers.alt => {
+            (Key::F(n + 12), Modifiers::none())
+        }
+        _ => (key, modifiers),
+    }
+}
+
 fn parse_modifier_param(s: &str) -> Modifiers {
     let n: u8 = s.parse().unwrap_or(1);
     // Modifier encoding: 1 + (shift ? 1 : 0) + (alt ? 2 : 0) + (ctrl ? 4 : 0)
@@ -322,6 +414,78 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_parse_f13_f24() {
+        for (seq, n) in [
+            (&b"\x1b[1;2P"[..], 13),
+            (b"\x1b[1;2S", 16),
+            (b"\x1b[15;2~", 17),
+            (b"\x1b[24;2~", 24),
+            (b"\x1b[25~", 13),
+            (b"\x1b[34~", 20),
+        ] {
+            let mut parser = KeyParser::new();
+            parser.push(seq);
+            assert_eq!(
+                parser.parse_next(),
+                ParseResult::Key(KeyEvent::new(Key::F(n), Modifiers::none()), seq.len())
+            );
+        }
+        // Other modifiers are kept
+        let mut parser = KeyParser::new();
+        parser.push(b"\x1b[1;5P");
+        assert_eq!(
+            parser.parse_next(),
+            ParseResult::Key(KeyEvent::new(Key::F(1), Modifiers::ctrl()), 6)
+        );
+    }
+
+    #[test]
+    fn test_parse_keypad() {
+        let mut parser = KeyParser::new();
+        parser.push(b"\x1bOp\x1bOM\x1bOk");
+        for c in ['0', '\r', '+'] {
+            assert_eq!(
+                parser.parse_next(),
+                ParseResult::Key(KeyEvent::new(Key::Keypad(c), Modifiers::none()), 3)
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_ctrl_punctuation() {
+        let mut parser = KeyParser::new();
+        parser.push(&[0x1c, 0x1d, 0x1e, 0x1f, 0x00]);
+        for c in ['\\', ']', '^', '_', '@'] {
+            assert_eq!(
+                parser.parse_next(),
+                ParseResult::Key(KeyEvent::ctrl_char(c), 1)
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_sgr_mouse() {
+        let mut parser = KeyParser::new();
+        for seq in [&b"\x1b[<0;12;5M"[..], b"\x1b[<0;12;5m"] {
+            parser.push(seq);
+            let event = MouseEvent {
+                button: 0,
+                x: 12,
+                y: 5,
+                release: seq.ends_with(b"m"),
+            };
+            assert_eq!(parser.parse_next(), ParseResult::Mouse(event, seq.len()));
+            assert_eq!(event.to_bytes(), seq);
+        }
+
+        // Split across reads
+        parser.push(b"\x1b[<64;1");
+        assert_eq!(parser.parse_next(), ParseResult::NeedMore);
+        parser.push(b";2M");
+        assert!(matches!(parser.parse_next(), ParseResult::Mouse(e, 10) if e.button == 64));
+    }
+
     #[test]
     fn test_parse_alt_x() {
         let mut parser = KeyParser::new();
@@ -1,14 +1,37 @@
-use log::debug;
+use log::{debug, warn};
 use std::time::{Duration, Instant};
 
 use super::action::KeybindResult;
 use super::config::KeybindConfig;
 use super::key::KeyEvent;
 use super::parser::{KeyParser, ParseResult};
+use crate::clock;
 
 const ESCAPE_TIMEOUT: Duration = Duration::from_millis(50);
 const PREFIX_TIMEOUT: Duration = Duration::from_millis(2000);
 
+/// Setting selecting what is done with mouse events, see [`MouseMode`]
+pub const SETTING_MOUSE: &str = "mouse";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseMode {
+    /// Sent to the device, e.g. for ncurses programs running on it
+    Forward,
+    /// Dropped, and mouse reporting is turned off on the terminal so its own
+    /// selection and scrollback work
+    Local,
+}
+
+impl MouseMode {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "forward" => Some(MouseMode::Forward),
+            "local" => Some(MouseMode::Local),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum State {
     Normal,
@@ -21,12 +44,24 @@ pub struct KeybindProcessor {
     state: State,
     state_entered: Instant,
     last_input: Instant,
+    mouse: MouseMode,
 }
 
 impl KeybindProcessor {
     pub fn new(config: KeybindConfig) -> Self {
-        let now = Instant::now();
+        let now = clock::now();
+        let mouse = match config.settings.get(SETTING_MOUSE) {
+            Some(value) => {
+                let mode = value.as_str().and_then(MouseMode::parse);
+                if mode.is_none() {
+                    warn!("{}: expected forward or local", SETTING_MOUSE);
+                }
+                mode.unwrap_or(MouseMode::Forward)
+            }
+            None => MouseMode::Forward,
+        };
         Self {
+            mouse,
             config,
             parser: KeyParser::new(),
             state: State::Normal,
@@ -38,18 +73,47 @@ impl KeybindProcessor {
     /// Process input bytes and return results
     /// May return multiple results if input contains multiple keys
     pub fn process(&mut self, input: &[u8]) -> Vec<KeybindResult> {
-        self.last_input = Instant::now();
+        // Without any bindings there is nothing to look for, not even
+        // escape sequences
+        if self.config.prefix.is_none() && self.config.direct_bindings.is_empty() {
+            return vec![KeybindResult::Passthrough(input.to_vec())];
+        }
+        self.last_input = clock::now();
         self.parser.push(input);
         self.drain_results()
     }
 
+    pub fn mouse_mode(&self) -> MouseMode {
+        self.mouse
+    }
+
+    pub fn set_mouse_mode(&mut self, mode: MouseMode) {
+        self.mouse = mode;
+    }
+
+    /// The prefix key was pressed and the key after it is awaited
+    pub fn awaiting_prefix(&self) -> bool {
+        self.state == State::AwaitingPrefixCommand
+    }
+
+    /// When `tick()` has a timeout to handle, if any is pending
+    pub fn next_timeout(&self) -> Option<Instant> {
+        let escape = self
+            .parser
+            .has_pending()
+            .then(|| self.last_input + ESCAPE_TIMEOUT);
+        let prefix = (self.state == State::AwaitingPrefixCommand)
+            .then(|| self.state_entered + PREFIX_TIMEOUT);
+        escape.into_iter().chain(prefix).min()
+    }
+
     /// Check for timeouts and return any pending results
     pub fn tick(&mut self) -> Vec<KeybindResult> {
-        let now = Instant::now();
+        let now = clock::now();
         let mut results = Vec::new();
 
         // Check escape sequence timeout
-        if self.parser.has_pending() && now.duration_since(self.last_input) > ESCAPE_TIMEOUT {
+        if self.parser.has_pending() && now.duration_since(self.last_input) >= ESCAPE_TIMEOUT {
             // Force parse pending bytes
             while self.parser.has_pending() {
                 if let Some(parse_result) = self.parser.force_parse_first() {
@@ -64,7 +128,7 @@ impl KeybindProcessor {
 
         // Check prefix mode timeout
         if self.state == State::AwaitingPrefixCommand
-            && now.duration_since(self.state_entered) > PREFIX_TIMEOUT
+            && now.duration_since(self.state_entered) >= PREFIX_TIMEOUT
         {
             // Timeout - forward the original prefix key and reset
             if let Some(prefix) = &self.config.prefix
@@ -105,6 +169,11 @@ impl KeybindProcessor {
                 );
                 self.handle_key_event(key_event)
             }
+            // Mouse events do not interact with the prefix key
+            ParseResult::Mouse(event, _) => match self.mouse {
+                MouseMode::Forward => Some(KeybindResult::Passthrough(event.to_bytes())),
+                MouseMode::Local => Some(KeybindResult::Consumed),
+            },
             ParseResult::Passthrough(byte) => {
                 debug!("Passthrough byte: 0x{:02x}", byte);
                 Some(KeybindResult::Passthrough(vec![byte]))
@@ -141,7 +210,7 @@ impl KeybindProcessor {
         {
             debug!("Key matches prefix, entering prefix mode");
             self.state = State::AwaitingPrefixCommand;
-            self.state_entered = Instant::now();
+            self.state_entered = clock::now();
             return Some(KeybindResult::Consumed);
         }
 
@@ -183,7 +252,7 @@ impl KeybindProcessor {
 }
 
 /// Convert a KeyEvent back to terminal bytes (best effort)
-fn key_event_to_bytes(event: &KeyEvent) -> Option<Vec<u8>> {
+pub fn key_event_to_bytes(event: &KeyEvent) -> Option<Vec<u8>> {
     use super::key::Key;
 
     let mut bytes = Vec::new();
@@ -196,12 +265,12 @@ fn key_event_to_bytes(event: &KeyEvent) -> Option<Vec<u8>> {
     match &event.key {
         Key::Char(c) => {
             if event.modifiers.ctrl {
-                // Ctrl+A = 0x01, etc.
-                let code = c.to_ascii_lowercase() as u8;
-                if code.is_ascii_lowercase() {
-                    bytes.push(code - b'a' + 1);
-                } else {
-                    return None;
+                // Ctrl+A = 0x01, etc., Ctrl+\ = 0x1c, ..., Ctrl+_ = 0x1f
+                match c.to_ascii_lowercase() {
+                    c @ 'a'..='z' => bytes.push(c as u8 - b'a' + 1),
+                    '@' | ' ' => bytes.push(0x00),
+                    c @ ('\\' | ']' | '^' | '_') => bytes.push(c as u8 - 0x40),
+                    _ => return None,
                 }
             } else {
                 let mut buf = [0u8; 4];
@@ -236,8 +305,25 @@ fn key_event_to_bytes(event: &KeyEvent) -> Option<Vec<u8>> {
             10 => bytes.extend_from_slice(b"\x1b[21~"),
             11 => bytes.extend_from_slice(b"\x1b[23~"),
             12 => bytes.extend_from_slice(b"\x1b[24~"),
+            // As xterm, i.e. Shift+F1-F12
+            13 => bytes.extend_from_slice(b"\x1b[1;2P"),
+            14 => bytes.extend_from_slice(b"\x1b[1;2Q"),
+            15 => bytes.extend_from_slice(b"\x1b[1;2R"),
+            16 => bytes.extend_from_slice(b"\x1b[1;2S"),
+            17 => bytes.extend_from_slice(b"\x1b[15;2~"),
+            18 => bytes.extend_from_slice(b"\x1b[17;2~"),
+            19 => bytes.extend_from_slice(b"\x1b[18;2~"),
+            20 => bytes.extend_from_slice(b"\x1b[19;2~"),
+            21 => bytes.extend_from_slice(b"\x1b[20;2~"),
+            22 => bytes.extend_from_slice(b"\x1b[21;2~"),
+            23 => bytes.extend_from_slice(b"\x1b[23;2~"),
+            24 => bytes.extend_from_slice(b"\x1b[24;2~"),
             _ => return None,
         },
+        Key::Keypad(c) => {
+            bytes.extend_from_slice(b"\x1bO");
+            bytes.push(super::parser::keypad_ss3(*c)?);
+        }
     }
 
     Some(bytes)
@@ -247,6 +333,7 @@ fn key_event_to_bytes(event: &KeyEvent) -> Option<Vec<u8>> {
 mod tests {
     use super::*;
     use crate::keybind::Action;
+    use crate::keybind::config::SettingValue;
 
     fn make_config() -> KeybindConfig {
         let mut config = KeybindConfig::new();
@@ -274,10 +361,31 @@ mod tests {
         // Press prefix (Ctrl+A)
         let results = processor.process(&[0x01]);
         assert_eq!(results, vec![KeybindResult::Consumed]);
+        assert!(processor.awaiting_prefix());
 
         // Press q
         let results = processor.process(b"q");
         assert_eq!(results, vec![KeybindResult::Action(Action::Quit)]);
+        assert!(!processor.awaiting_prefix());
+    }
+
+    #[test]
+    fn test_next_timeout() {
+        let mut processor = KeybindProcessor::new(make_config());
+        assert_eq!(processor.next_timeout(), None);
+        processor.process(&[0x01]);
+        assert_eq!(
+            processor.next_timeout(),
+            Some(processor.state_entered + PREFIX_TIMEOUT)
+        );
+        processor.process(b"q");
+        assert_eq!(processor.next_timeout(), None);
+        // A lone ESC may start a sequence
+        processor.process(b"\x1b");
+        assert_eq!(
+            processor.next_timeout(),
+            Some(processor.last_input + ESCAPE_TIMEOUT)
+        );
     }
 
     #[test]
@@ -287,6 +395,31 @@ mod tests {
         assert_eq!(results, vec![KeybindResult::Passthrough(b"x".to_vec())]);
     }
 
+    #[test]
+    fn test_mouse_mode() {
+        let mut processor = KeybindProcessor::new(make_config());
+        let click = b"\x1b[<0;3;4M";
+        assert_eq!(
+            processor.process(click),
+            vec![KeybindResult::Passthrough(click.to_vec())]
+        );
+
+        let mut config = make_config();
+        config.settings.insert(
+            SETTING_MOUSE.to_string(),
+            SettingValue::String("local".to_string()),
+        );
+        let mut processor = KeybindProcessor::new(config);
+        assert_eq!(processor.mouse_mode(), MouseMode::Local);
+        // Does not cancel a pending prefix
+        assert_eq!(processor.process(&[0x01]), vec![KeybindResult::Consumed]);
+        assert_eq!(processor.process(click), vec![KeybindResult::Consumed]);
+        assert_eq!(
+            processor.process(b"q"),
+            vec![KeybindResult::Action(Action::Quit)]
+        );
+    }
+
     #[test]
     fn test_unbound_prefix_key() {
         let mut processor = KeybindProcessor::new(make_config());
@@ -300,4 +433,18 @@ mod tests {
         // Should forward both prefix bytes and the key
         assert_eq!(results, vec![KeybindResult::Passthrough(vec![0x01, b'x'])]);
     }
+
+    #[test]
+    fn test_no_escape() {
+        let mut config = make_config();
+        config.disable_escape();
+        let mut processor = KeybindProcessor::new(config);
+        // Bindings, a lone ESC and 8-bit bytes go through unchanged
+        let input = b"\x01q\x11\x1b\xff\x80";
+        assert_eq!(
+            processor.process(input),
+            vec![KeybindResult::Passthrough(input.to_vec())]
+        );
+        assert_eq!(processor.next_timeout(), None);
+    }
 }
@@ -0,0 +1,138 @@
+//! Round-trip latency of a device (the `latency` action): a marker is
+//! written to the device a number of times, and the time to the first byte
+//! of output after each is measured. The default marker, a space and a
+//! backspace, is echoed by shells and boot loaders without changing the
+//! command line.
+
+use std::time::{Duration, Instant};
+
+/// Written to the device for each probe
+pub const DEFAULT_MARKER: &[u8] = b" \x7f";
+/// Probes of the `latency` action without a count
+pub const DEFAULT_PROBES: usize = 5;
+
+/// A probe without an answer in this time is lost
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+/// Pause after an answer, so the rest of the echo is not taken for the
+/// answer to the next probe
+const PROBE_GAP: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Clone)]
+pub struct Latency {
+    /// Probes still to send
+    left: usize,
+    sent_at: Option<Instant>,
+    next_at: Instant,
+    samples: Vec<Duration>,
+    lost: usize,
+}
+
+impl Latency {
+    /// Measure with `probes` probes, the first at once.
+    pub fn new(probes: usize, now: Instant) -> Self {
+        Latency {
+            left: probes,
+            sent_at: None,
+            next_at: now,
+            samples: Vec::new(),
+            lost: 0,
+        }
+    }
+
+    /// When [`Self::tick`] has something to do.
+    pub fn due(&self) -> Instant {
+        self.sent_at.map_or(self.next_at, |t| t + PROBE_TIMEOUT)
+    }
+
+    /// Time out the probe in flight. True if the marker is to be written
+    /// now.
+    pub fn tick(&mut self, now: Instant) -> bool {
+        if let Some(t) = self.sent_at
+            && now >= t + PROBE_TIMEOUT
+        {
+            self.sent_at = None;
+            self.lost += 1;
+            self.next_at = now;
+        }
+        if self.sent_at.is_some() || self.left == 0 || now < self.next_at {
+            return false;
+        }
+        self.left -= 1;
+        self.sent_at = Some(now);
+        true
+    }
+
+    /// The device produced output.
+    pub fn output(&mut self, now: Instant) {
+        if let Some(t) = self.sent_at.take() {
+            self.samples.push(now.saturating_duration_since(t));
+            self.next_at = now + PROBE_GAP;
+        }
+    }
+
+    pub fn done(&self) -> bool {
+        self.left == 0 && self.sent_at.is_none()
+    }
+
+    pub fn summary(&self) -> String {
+        let probes = self.samples.len() + self.lost;
+        let (Some(min), Some(max)) = (self.samples.iter().min(), self.samples.iter().max()) else {
+            return format!("Latency: no answer to {} probe(s)", probes);
+        };
+        let avg = self.samples.iter().sum::<Duration>() / self.samples.len() as u32;
+        let ms = |d: &Duration| d.as_secs_f64() * 1000.0;
+        format!(
+            "Latency: min {:.1} ms, avg {:.1} ms, max {:.1} ms ({} probe(s), {} lost)",
+            ms(min),
+            ms(&avg),
+            ms(max),
+            probes,
+            self.lost
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_probes() {
+        let ms = Duration::from_millis;
+        let t0 = Instant::now();
+        let mut l = Latency::new(3, t0);
+        assert!(l.tick(t0));
+        assert!(!l.tick(t0 + ms(1)));
+        l.output(t0 + ms(10));
+        // More of the echo, and the gap before the next probe
+        l.output(t0 + ms(11));
+        assert_eq!(l.due(), t0 + ms(210));
+        assert!(!l.tick(t0 + ms(100)));
+
+        let t1 = t0 + ms(210);
+        assert!(l.tick(t1));
+        l.output(t1 + ms(30));
+
+        // The last one is lost
+        let t2 = t1 + ms(230);
+        assert!(l.tick(t2));
+        assert!(!l.done());
+        assert_eq!(l.due(), t2 + PROBE_TIMEOUT);
+        assert!(!l.tick(t2 + PROBE_TIMEOUT));
+        assert!(l.done());
+        assert_eq!(
+            l.summary(),
+            "Latency: min 10.0 ms, avg 20.0 ms, max 30.0 ms (3 probe(s), 1 lost)"
+        );
+    }
+
+    #[test]
+    fn test_no_answer() {
+        let t0 = Instant::now();
+        let mut l = Latency::new(1, t0);
+        assert!(l.tick(t0));
+        assert!(!l.tick(t0 + PROBE_TIMEOUT));
+        assert!(l.done());
+        assert_eq!(l.summary(), "Latency: no answer to 1 probe(s)");
+    }
+}
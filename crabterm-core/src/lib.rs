@@ -0,0 +1,64 @@
+//! The crabterm bridging engine.
+//!
+//! An [`IoHub`] connects one device (serial port, TCP endpoint, ...) to any
+//! number of clients (the local console, TCP clients, ...). Everything read
+//! from the device is broadcast to all clients, and everything a client sends
+//! is written to the device. The `crabterm` binary parses its arguments and
+//! hands them to [`cli::main`]; other programs can embed the same engine:
+//!
+//! ```no_run
+//! use crabterm_core::io::{EchoDevice, TcpServer};
+//! use crabterm_core::IoHub;
+//!
+//! fn main() -> std::io::Result<()> {
+//!     let mut hub = IoHub::builder(Box::new(EchoDevice::new()?))
+//!         .server(TcpServer::new(4000)?)
+//!         .build()?;
+//!     hub.run()
+//! }
+//! ```
+//!
+//! Devices and clients both implement [`IoInstance`]; output to a client can
+//! be transformed by filters implementing [`IoFilter`].
+
+pub mod announce;
+pub mod banner;
+pub mod capture;
+pub mod capture_db;
+pub mod cli;
+pub mod clock;
+pub mod control;
+#[cfg(unix)]
+pub mod ctl;
+pub mod device;
+pub mod events;
+pub mod health;
+pub mod hexdump;
+pub mod http;
+pub mod hub;
+pub mod io;
+pub mod iofilter;
+pub mod keybind;
+pub mod latency;
+pub mod mdns;
+pub mod metrics;
+pub mod monitor;
+pub mod notify;
+pub mod outcome;
+pub mod pcapng;
+pub mod pipe;
+pub mod poll;
+pub mod power;
+#[cfg(unix)]
+pub mod session;
+pub mod stats;
+pub mod term;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod traits;
+pub mod watchdog;
+pub mod write_queue;
+
+pub use hub::{IoHub, IoHubBuilder};
+pub use iofilter::{FilterChain, FilterChainFactory, IoFilter};
+pub use traits::{IoInstance, IoResult};
@@ -0,0 +1,605 @@
+//! DNS-SD advertisement of the console server over multicast DNS (`--mdns`).
+//!
+//! A minimal responder running on the hub's poll loop. It answers queries
+//! for `_crabterm._tcp.local` with the listening port, and TXT records with
+//! the device and baudrate, so `avahi-browse -r _crabterm._tcp` shows which
+//! machine exports which console. It shares port 5353 with a system
+//! responder, if there is one.
+
+use log::{debug, info, warn};
+use mio::net::UdpSocket;
+use mio::{Interest, Token};
+use std::io::{Error, ErrorKind, Result};
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+#[cfg(unix)]
+use std::os::unix::io::FromRawFd;
+use std::time::{Duration, Instant};
+
+use crate::clock;
+use crate::poll::Poll;
+
+/// The service type advertised
+pub const SERVICE: &str = "_crabterm._tcp.local";
+/// Service types of a host, for browsers listing all of them
+const SERVICES: &str = "_services._dns-sd._udp.local";
+
+const GROUP: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+const PORT: u16 = 5353;
+const TTL: u32 = 120;
+
+/// Announcements at startup, a second apart (RFC 6762, 8.3)
+const ANNOUNCEMENTS: u32 = 2;
+const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(1);
+
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_TXT: u16 = 16;
+const TYPE_SRV: u16 = 33;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+/// Set on records only this host has
+const CACHE_FLUSH: u16 = 0x8000;
+
+/// What is advertised
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Service {
+    /// Instance name, e.g. the board
+    pub name: String,
+    /// Host name, without `.local`
+    pub host: String,
+    pub addr: Ipv4Addr,
+    pub port: u16,
+    /// `key=value` strings
+    pub txt: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Record {
+    Services,
+    Ptr,
+    Srv,
+    Txt,
+    A,
+}
+
+impl Service {
+    fn instance(&self) -> String {
+        format!("{}.{}", self.name, SERVICE)
+    }
+
+    fn host_name(&self) -> String {
+        format!("{}.local", self.host)
+    }
+
+    /// The records for a question, and the additional records that go
+    /// with them.
+    fn answer(&self, name: &str, qtype: u16) -> (Vec<Record>, Vec<Record>) {
+        let wants = |t| qtype == t || qtype == TYPE_ANY;
+        if name.eq_ignore_ascii_case(SERVICES) && wants(TYPE_PTR) {
+            (vec![Record::Services], vec![])
+        } else if name.eq_ignore_ascii_case(SERVICE) && wants(TYPE_PTR) {
+            (vec![Record::Ptr], vec![Record::Srv, Record::Txt, Record::A])
+        } else if name.eq_ignore_ascii_case(&self.instance()) {
+            let answers: Vec<Record> = [(TYPE_SRV, Record::Srv), (TYPE_TXT, Record::Txt)]
+                .into_iter()
+                .filter(|(t, _)| wants(*t))
+                .map(|(_, r)| r)
+                .collect();
+            let additional = if answers.contains(&Record::Srv) {
+                vec![Record::A]
+            } else {
+                vec![]
+            };
+            (answers, additional)
+        } else if name.eq_ignore_ascii_case(&self.host_name()) && wants(TYPE_A) {
+            (vec![Record::A], vec![])
+        } else {
+            (vec![], vec![])
+        }
+    }
+
+    /// The response to the query `packet`, None if it asks nothing about
+    /// this service. A `legacy` response goes to a plain DNS client rather
+    /// than an mDNS one.
+    pub fn respond(&self, packet: &[u8], legacy: bool) -> Option<Vec<u8>> {
+        let query = parse_query(packet)?;
+        let mut answers: Vec<Record> = Vec::new();
+        let mut additional: Vec<Record> = Vec::new();
+        for (name, qtype) in &query.questions {
+            let (a, b) = self.answer(name, *qtype);
+            for r in a {
+                if !answers.contains(&r) {
+                    answers.push(r);
+                }
+            }
+            for r in b {
+                if !additional.contains(&r) {
+                    additional.push(r);
+                }
+            }
+        }
+        if answers.is_empty() {
+            return None;
+        }
+        additional.retain(|r| !answers.contains(r));
+        let mut out = Vec::new();
+        if legacy {
+            // The id and questions are repeated for plain DNS clients; the
+            // questions are copied as they came, so that compressed names
+            // in them still point to the right place.
+            out.extend_from_slice(&packet[..2]);
+            out.extend_from_slice(&0x8400u16.to_be_bytes());
+            out.extend_from_slice(&(query.questions.len() as u16).to_be_bytes());
+        } else {
+            out.extend_from_slice(&[0, 0, 0x84, 0, 0, 0]);
+        }
+        out.extend_from_slice(&(answers.len() as u16).to_be_bytes());
+        out.extend_from_slice(&[0, 0]);
+        out.extend_from_slice(&(additional.len() as u16).to_be_bytes());
+        if legacy {
+            out.extend_from_slice(&packet[12..query.end]);
+        }
+        for r in answers.iter().chain(&additional) {
+            self.write_record(&mut out, *r, TTL);
+        }
+        Some(out)
+    }
+
+    /// All records, unsolicited: an announcement, or a goodbye with `ttl` 0.
+    pub fn announcement(&self, ttl: u32) -> Vec<u8> {
+        let records = [
+            Record::Services,
+            Record::Ptr,
+            Record::Srv,
+            Record::Txt,
+            Record::A,
+        ];
+        let mut out = vec![0, 0, 0x84, 0, 0, 0];
+        out.extend_from_slice(&(records.len() as u16).to_be_bytes());
+        out.extend_from_slice(&[0, 0, 0, 0]);
+        for r in records {
+            self.write_record(&mut out, r, ttl);
+        }
+        out
+    }
+
+    fn write_record(&self, out: &mut Vec<u8>, record: Record, ttl: u32) {
+        let (name, rtype, class) = match record {
+            Record::Services => (SERVICES.to_string(), TYPE_PTR, CLASS_IN),
+            Record::Ptr => (SERVICE.to_string(), TYPE_PTR, CLASS_IN),
+            Record::Srv => (self.instance(), TYPE_SRV, CLASS_IN | CACHE_FLUSH),
+            Record::Txt => (self.instance(), TYPE_TXT, CLASS_IN | CACHE_FLUSH),
+            Record::A => (self.host_name(), TYPE_A, CLASS_IN | CACHE_FLUSH),
+        };
+        let mut data = Vec::new();
+        match record {
+            Record::Services => write_name(&mut data, SERVICE),
+            Record::Ptr => write_name(&mut data, &self.instance()),
+            Record::Srv => {
+                data.extend_from_slice(&[0, 0, 0, 0]);
+                data.extend_from_slice(&self.port.to_be_bytes());
+                write_name(&mut data, &self.host_name());
+            }
+            Record::Txt => {
+                for s in &self.txt {
+                    let s = &s.as_bytes()[..s.len().min(255)];
+                    data.push(s.len() as u8);
+                    data.extend_from_slice(s);
+                }
+                if data.is_empty() {
+                    data.push(0);
+                }
+            }
+            Record::A => data.extend_from_slice(&self.addr.octets()),
+        }
+        write_name(out, &name);
+        out.extend_from_slice(&rtype.to_be_bytes());
+        out.extend_from_slice(&class.to_be_bytes());
+        out.extend_from_slice(&ttl.to_be_bytes());
+        out.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        out.extend_from_slice(&data);
+    }
+}
+
+/// Write `name` uncompressed. The instance name is the first label even if
+/// it contains dots.
+fn write_name(out: &mut Vec<u8>, name: &str) {
+    let labels: Vec<&str> = match name.find("._") {
+        Some(i) if !name.starts_with('_') => {
+            let mut labels = vec![&name[..i]];
+            labels.extend(name[i + 1..].split('.'));
+            labels
+        }
+        _ => name.split('.').collect(),
+    };
+    for label in labels {
+        let label = &label.as_bytes()[..label.len().min(63)];
+        out.push(label.len() as u8);
+        out.extend_from_slice(label);
+    }
+    out.push(0);
+}
+
+struct Query {
+    /// Name and type of each question
+    questions: Vec<(String, u16)>,
+    /// End of the question section
+    end: usize,
+}
+
+fn parse_query(packet: &[u8]) -> Option<Query> {
+    let u16_at = |pos: usize| -> Option<u16> {
+        Some(u16::from_be_bytes(
+            packet.get(pos..pos + 2)?.try_into().ok()?,
+        ))
+    };
+    // Responses are not answered
+    if u16_at(2)? & 0x8000 != 0 {
+        return None;
+    }
+    let count = u16_at(4)?;
+    let mut pos = 12;
+    let mut questions = Vec::new();
+    for _ in 0..count {
+        let (name, next) = read_name(packet, pos)?;
+        questions.push((name, u16_at(next)?));
+        u16_at(next + 2)?;
+        pos = next + 4;
+    }
+    Some(Query {
+        questions,
+        end: pos,
+    })
+}
+
+/// The name at `pos`, and the position after it. Instance names keep their
+/// dots, as they are matched as a whole.
+fn read_name(packet: &[u8], mut pos: usize) -> Option<(String, usize)> {
+    let mut labels: Vec<String> = Vec::new();
+    let mut end = None;
+    for _ in 0..128 {
+        let len = *packet.get(pos)? as usize;
+        match len {
+            0 => {
+                return Some((labels.join("."), end.unwrap_or(pos + 1)));
+            }
+            l if l & 0xc0 == 0xc0 => {
+                let ptr = ((l & 0x3f) << 8) | *packet.get(pos + 1)? as usize;
+                end.get_or_insert(pos + 2);
+                pos = ptr;
+            }
+            l => {
+                let label = packet.get(pos + 1..pos + 1 + l)?;
+                labels.push(String::from_utf8_lossy(label).into_owned());
+                pos += 1 + l;
+            }
+        }
+    }
+    // A pointer loop
+    None
+}
+
+/// The first label of this machine's host name.
+pub fn hostname() -> String {
+    match system_hostname().split('.').next() {
+        Some(host) if !host.is_empty() => host.to_string(),
+        _ => "crabterm".to_string(),
+    }
+}
+
+#[cfg(unix)]
+fn system_hostname() -> String {
+    let mut buf = [0u8; 256];
+    let res = unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) };
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    if res == 0 {
+        String::from_utf8_lossy(&buf[..len]).into_owned()
+    } else {
+        String::new()
+    }
+}
+
+#[cfg(windows)]
+fn system_hostname() -> String {
+    std::env::var("COMPUTERNAME").unwrap_or_default()
+}
+
+/// The address of the interface multicast goes out on.
+pub fn local_addr() -> Result<Ipv4Addr> {
+    // Connecting a UDP socket sends nothing, it only picks the route
+    let socket = std::net::UdpSocket::bind("0.0.0.0:0")?;
+    socket.connect((GROUP, PORT))?;
+    match socket.local_addr()? {
+        SocketAddr::V4(a) if !a.ip().is_unspecified() => Ok(*a.ip()),
+        _ => Err(Error::new(ErrorKind::NotFound, "no IPv4 address")),
+    }
+}
+
+/// Bind port 5353 shared with other responders, and join the mDNS group.
+fn bind() -> Result<std::net::UdpSocket> {
+    let socket = bind_shared()?;
+    socket.join_multicast_v4(&GROUP, &Ipv4Addr::UNSPECIFIED)?;
+    socket.set_multicast_loop_v4(true)?;
+    Ok(socket)
+}
+
+/// Port 5353, non-blocking, with SO_REUSEADDR and SO_REUSEPORT set before
+/// binding it.
+#[cfg(unix)]
+fn bind_shared() -> Result<std::net::UdpSocket> {
+    let fd = unsafe {
+        libc::socket(
+            libc::AF_INET,
+            libc::SOCK_DGRAM | libc::SOCK_CLOEXEC | libc::SOCK_NONBLOCK,
+            0,
+        )
+    };
+    if fd < 0 {
+        return Err(Error::last_os_error());
+    }
+    // Owns the fd from here, closing it on errors
+    let socket = unsafe { std::net::UdpSocket::from_raw_fd(fd) };
+    let one: libc::c_int = 1;
+    for opt in [libc::SO_REUSEADDR, libc::SO_REUSEPORT] {
+        let res = unsafe {
+            libc::setsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                opt,
+                &one as *const libc::c_int as *const libc::c_void,
+                std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        if res < 0 {
+            return Err(Error::last_os_error());
+        }
+    }
+    let addr = libc::sockaddr_in {
+        sin_family: libc::AF_INET as libc::sa_family_t,
+        sin_port: PORT.to_be(),
+        sin_addr: libc::in_addr {
+            s_addr: libc::INADDR_ANY,
+        },
+        sin_zero: [0; 8],
+    };
+    let res = unsafe {
+        libc::bind(
+            fd,
+            &addr as *const libc::sockaddr_in as *const libc::sockaddr,
+            std::mem::size_of::<libc::sockaddr_in>() as libc::socklen_t,
+        )
+    };
+    if res < 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok(socket)
+}
+
+/// Without the socket options the port is not shared: this fails while
+/// another responder, e.g. that of Bonjour, has it.
+#[cfg(windows)]
+fn bind_shared() -> Result<std::net::UdpSocket> {
+    let socket = std::net::UdpSocket::bind((Ipv4Addr::UNSPECIFIED, PORT))?;
+    socket.set_nonblocking(true)?;
+    Ok(socket)
+}
+
+pub struct MdnsResponder {
+    socket: UdpSocket,
+    service: Service,
+    announcements_left: u32,
+    next_announcement: Option<Instant>,
+}
+
+impl MdnsResponder {
+    pub fn new(service: Service) -> Result<Self> {
+        let socket = UdpSocket::from_std(bind()?);
+        info!(
+            "mDNS: advertising {}.{} at {}:{}",
+            service.name, SERVICE, service.addr, service.port
+        );
+        Ok(MdnsResponder {
+            socket,
+            service,
+            announcements_left: ANNOUNCEMENTS,
+            next_announcement: Some(clock::now()),
+        })
+    }
+
+    pub fn register(&mut self, poll: &mut Poll, token: Token) -> Result<()> {
+        poll.registry()
+            .register(&mut self.socket, token, Interest::READABLE)
+    }
+
+    fn send(&self, packet: &[u8], to: SocketAddr) {
+        if let Err(e) = self.socket.send_to(packet, to) {
+            warn!("mDNS: send to {}: {}", to, e);
+        }
+    }
+
+    /// Answer the queries received.
+    pub fn handle(&mut self) {
+        let mut buf = [0u8; 9000];
+        loop {
+            match self.socket.recv_from(&mut buf) {
+                Ok((n, from)) => {
+                    // Queries from other ports than 5353 are from plain
+                    // DNS clients, which want the answer themselves
+                    let legacy = from.port() != PORT;
+                    if let Some(response) = self.service.respond(&buf[..n], legacy) {
+                        debug!("mDNS: answering {}", from);
+                        let to = if legacy {
+                            from
+                        } else {
+                            SocketAddrV4::new(GROUP, PORT).into()
+                        };
+                        self.send(&response, to);
+                    }
+                }
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => return,
+                Err(e) => {
+                    warn!("mDNS: receive: {}", e);
+                    return;
+                }
+            }
+        }
+    }
+
+    /// When the next announcement is due.
+    pub fn next_tick(&self) -> Option<Instant> {
+        self.next_announcement
+    }
+
+    /// Send the announcements at startup.
+    pub fn tick(&mut self) {
+        let now = clock::now();
+        if self.next_announcement.is_none_or(|t| t > now) {
+            return;
+        }
+        self.send(
+            &self.service.announcement(TTL),
+            SocketAddrV4::new(GROUP, PORT).into(),
+        );
+        self.announcements_left -= 1;
+        self.next_announcement = (self.announcements_left > 0).then(|| now + ANNOUNCE_INTERVAL);
+    }
+}
+
+impl Drop for MdnsResponder {
+    /// Tell the browsers the service is gone.
+    fn drop(&mut self) {
+        self.send(
+            &self.service.announcement(0),
+            SocketAddrV4::new(GROUP, PORT).into(),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn service() -> Service {
+        Service {
+            name: "imx8 board".to_string(),
+            host: "lab3".to_string(),
+            addr: Ipv4Addr::new(10, 0, 0, 7),
+            port: 4000,
+            txt: vec![
+                "device=/dev/ttyUSB0".to_string(),
+                "baudrate=115200".to_string(),
+            ],
+        }
+    }
+
+    fn query(name: &str, qtype: u16) -> Vec<u8> {
+        let mut q = vec![0x12, 0x34, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+        write_name(&mut q, name);
+        q.extend_from_slice(&qtype.to_be_bytes());
+        q.extend_from_slice(&CLASS_IN.to_be_bytes());
+        q
+    }
+
+    /// Names and types of the records of a response
+    fn records(packet: &[u8]) -> Vec<(String, u16)> {
+        let count = |pos: usize| u16::from_be_bytes([packet[pos], packet[pos + 1]]) as usize;
+        let questions = count(4);
+        let records = count(6) + count(8) + count(10);
+        let mut pos = 12;
+        for _ in 0..questions {
+            pos = read_name(packet, pos).unwrap().1 + 4;
+        }
+        let mut out = Vec::new();
+        for _ in 0..records {
+            let (name, next) = read_name(packet, pos).unwrap();
+            out.push((name, count(next) as u16));
+            pos = next + 10 + count(next + 8);
+        }
+        assert_eq!(pos, packet.len());
+        out
+    }
+
+    #[test]
+    fn test_browse() {
+        let s = service();
+        let response = s.respond(&query(SERVICE, TYPE_PTR), false).unwrap();
+        assert_eq!(&response[..4], &[0, 0, 0x84, 0]);
+        assert_eq!(
+            records(&response),
+            vec![
+                (SERVICE.to_string(), TYPE_PTR),
+                ("imx8 board._crabterm._tcp.local".to_string(), TYPE_SRV),
+                ("imx8 board._crabterm._tcp.local".to_string(), TYPE_TXT),
+                ("lab3.local".to_string(), TYPE_A),
+            ]
+        );
+        let txt = b"\x13device=/dev/ttyUSB0\x0fbaudrate=115200";
+        assert!(response.windows(txt.len()).any(|w| w == txt));
+        assert!(response.ends_with(&[0, 4, 10, 0, 0, 7]));
+
+        assert_eq!(
+            records(&s.respond(&query(SERVICES, TYPE_PTR), false).unwrap()),
+            vec![(SERVICES.to_string(), TYPE_PTR)]
+        );
+        assert_eq!(
+            records(&s.respond(&query("LAB3.local", TYPE_ANY), false).unwrap()),
+            vec![("lab3.local".to_string(), TYPE_A)]
+        );
+        assert!(
+            s.respond(&query("_http._tcp.local", TYPE_PTR), false)
+                .is_none()
+        );
+        assert!(s.respond(&query("lab3.local", TYPE_TXT), false).is_none());
+    }
+
+    #[test]
+    fn test_legacy_unicast() {
+        let s = service();
+        let q = query("imx8 board._crabterm._tcp.local", TYPE_TXT);
+        let response = s.respond(&q, true).unwrap();
+        // Same id, the question repeated
+        assert_eq!(&response[..2], &[0x12, 0x34]);
+        assert_eq!(&response[12..q.len()], &q[12..]);
+        assert_eq!(
+            records(&response),
+            vec![("imx8 board._crabterm._tcp.local".to_string(), TYPE_TXT)]
+        );
+    }
+
+    #[test]
+    fn test_parse_query() {
+        // Compressed second question, pointing into the first
+        let mut q = query(SERVICE, TYPE_PTR);
+        q[5] = 2;
+        q.extend_from_slice(&[4, b'l', b'a', b'b', b'3', 0xc0, 12 + 15]);
+        q.extend_from_slice(&TYPE_A.to_be_bytes());
+        q.extend_from_slice(&CLASS_IN.to_be_bytes());
+        let parsed = parse_query(&q).unwrap();
+        assert_eq!(
+            parsed.questions,
+            vec![
+                (SERVICE.to_string(), TYPE_PTR),
+                ("lab3.local".to_string(), TYPE_A)
+            ]
+        );
+        assert_eq!(parsed.end, q.len());
+
+        // Responses, truncated packets and pointer loops
+        let mut r = query(SERVICE, TYPE_PTR);
+        r[2] = 0x84;
+        assert!(parse_query(&r).is_none());
+        assert!(parse_query(&q[..q.len() - 3]).is_none());
+        let mut l = vec![0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0xc0, 12];
+        l.extend_from_slice(&[0, 1, 0, 1]);
+        assert!(parse_query(&l).is_none());
+    }
+
+    #[test]
+    fn test_goodbye() {
+        let s = service();
+        let bye = s.announcement(0);
+        assert_eq!(records(&bye).len(), 5);
+        // TTL of the A record, the last one
+        assert!(bye.ends_with(&[0, 0, 0, 0, 0, 4, 10, 0, 0, 7]));
+    }
+}
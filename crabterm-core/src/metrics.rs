@@ -0,0 +1,257 @@
+//! Prometheus metrics endpoint.
+//!
+//! A minimal HTTP server running on the hub's poll loop. Any request for
+//! `/metrics` is answered with the hub counters in the Prometheus text
+//! exposition format; the connection is closed after each response.
+
+use log::{debug, error, info};
+use mio::net::{TcpListener, TcpStream};
+use mio::{Interest, Token};
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io::{ErrorKind, Read, Result, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+use crate::poll::Poll;
+use crate::stats::HubStats;
+
+const MAX_REQUEST: usize = 8192;
+
+/// Gauges sampled by the hub at the time of the request.
+pub struct MetricsSnapshot<'a> {
+    pub stats: &'a HubStats,
+    pub clients_connected: usize,
+    pub devices_connected: usize,
+    /// Devices that answered the last health check, if checks are on
+    pub devices_healthy: Option<usize>,
+}
+
+struct Connection {
+    stream: TcpStream,
+    request: Vec<u8>,
+}
+
+pub struct MetricsServer {
+    listener: TcpListener,
+    connections: HashMap<Token, Connection>,
+    token_start: usize,
+}
+
+impl MetricsServer {
+    pub fn new(port: u16, token_start: usize) -> Result<Self> {
+        let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), port);
+        Ok(MetricsServer {
+            listener: TcpListener::bind(addr)?,
+            connections: HashMap::new(),
+            token_start,
+        })
+    }
+
+    pub fn register(&mut self, poll: &mut Poll, token: Token) -> Result<()> {
+        poll.registry()
+            .register(&mut self.listener, token, Interest::READABLE)
+    }
+
+    /// True if `token` is a scrape of `/metrics` that is still open.
+    pub fn owns(&self, token: Token) -> bool {
+        self.connections.contains_key(&token)
+    }
+
+    pub fn accept(&mut self, poll: &mut Poll) -> Result<()> {
+        loop {
+            match self.listener.accept() {
+                Ok((mut stream, addr)) => {
+                    let token = self.alloc_token();
+                    debug!("Metrics({:?}): {} connected", token, addr);
+                    poll.registry()
+                        .register(&mut stream, token, Interest::READABLE)?;
+                    self.connections.insert(
+                        token,
+                        Connection {
+                            stream,
+                            request: Vec::new(),
+                        },
+                    );
+                }
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => {
+                    error!("Metrics: accept error: {}", e);
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    fn alloc_token(&self) -> Token {
+        let mut token_id = self.token_start;
+        while self.connections.contains_key(&Token(token_id)) {
+            token_id += 1;
+        }
+        Token(token_id)
+    }
+
+    /// Read from a connection and answer once the request header is complete.
+    pub fn handle(&mut self, poll: &mut Poll, token: Token, snapshot: &MetricsSnapshot) {
+        let Some(conn) = self.connections.get_mut(&token) else {
+            return;
+        };
+
+        let mut tmp = [0u8; 1024];
+        let done = loop {
+            match conn.stream.read(&mut tmp) {
+                Ok(0) => break true,
+                Ok(n) => {
+                    conn.request.extend_from_slice(&tmp[..n]);
+                    if conn.request.windows(4).any(|w| w == b"\r\n\r\n")
+                        || conn.request.len() > MAX_REQUEST
+                    {
+                        let response = respond(&conn.request, snapshot);
+                        let _ = conn.stream.write_all(response.as_bytes());
+                        break true;
+                    }
+                }
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => break false,
+                Err(_) => break true,
+            }
+        };
+
+        if done && let Some(mut conn) = self.connections.remove(&token) {
+            let _ = poll.registry().deregister(&mut conn.stream);
+        }
+    }
+}
+
+fn respond(request: &[u8], snapshot: &MetricsSnapshot) -> String {
+    let request = String::from_utf8_lossy(request);
+    let mut words = request.split_whitespace();
+    let (method, path) = (words.next().unwrap_or(""), words.next().unwrap_or(""));
+    info!("Metrics: {} {}", method, path);
+
+    if method == "GET" && (path == "/metrics" || path.starts_with("/metrics?")) {
+        let body = render(snapshot);
+        format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        )
+    } else {
+        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+    }
+}
+
+/// Render the snapshot in the Prometheus text exposition format.
+pub fn render(snapshot: &MetricsSnapshot) -> String {
+    let s = snapshot.stats;
+    let mut metrics: Vec<(&str, &str, &str, u64)> = vec![
+        (
+            "crabterm_device_rx_bytes_total",
+            "counter",
+            "Bytes read from the device.",
+            s.device_rx_bytes,
+        ),
+        (
+            "crabterm_device_tx_bytes_total",
+            "counter",
+            "Bytes written to the device.",
+            s.device_tx_bytes,
+        ),
+        (
+            "crabterm_client_connections_total",
+            "counter",
+            "Clients accepted on the server port.",
+            s.client_connections,
+        ),
+        (
+            "crabterm_device_reconnects_total",
+            "counter",
+            "Device reconnects after the initial connect.",
+            s.device_reconnects,
+        ),
+        (
+            "crabterm_backpressure_events_total",
+            "counter",
+            "Times device writes blocked and client reads were paused.",
+            s.backpressure_events,
+        ),
+        (
+            "crabterm_slow_clients_dropped_total",
+            "counter",
+            "Clients disconnected for not keeping up with the device.",
+            s.slow_clients_dropped,
+        ),
+        (
+            "crabterm_clients_connected",
+            "gauge",
+            "Currently connected clients (including the local console).",
+            snapshot.clients_connected as u64,
+        ),
+        (
+            "crabterm_device_connected",
+            "gauge",
+            "Number of connected devices.",
+            snapshot.devices_connected as u64,
+        ),
+        (
+            "crabterm_health_failures_total",
+            "counter",
+            "Times a device stopped answering the health check with its prompt.",
+            s.health_failures,
+        ),
+    ];
+    if let Some(healthy) = snapshot.devices_healthy {
+        metrics.push((
+            "crabterm_device_healthy",
+            "gauge",
+            "Number of devices that answered the last health check.",
+            healthy as u64,
+        ));
+    }
+
+    let mut out = String::new();
+    for (name, kind, help, value) in metrics {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} {}", name, kind);
+        let _ = writeln!(out, "{} {}", name, value);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let stats = HubStats {
+            device_rx_bytes: 42,
+            slow_clients_dropped: 3,
+            ..Default::default()
+        };
+        let out = render(&MetricsSnapshot {
+            stats: &stats,
+            clients_connected: 2,
+            devices_connected: 1,
+            devices_healthy: Some(1),
+        });
+        assert!(out.contains("# TYPE crabterm_device_rx_bytes_total counter\n"));
+        assert!(out.contains("\ncrabterm_device_rx_bytes_total 42\n"));
+        assert!(out.contains("\ncrabterm_slow_clients_dropped_total 3\n"));
+        assert!(out.contains("\ncrabterm_clients_connected 2\n"));
+        assert!(out.contains("\ncrabterm_device_connected 1\n"));
+        assert!(out.contains("\ncrabterm_device_healthy 1\n"));
+    }
+
+    #[test]
+    fn test_respond_not_found() {
+        let stats = HubStats::default();
+        let snapshot = MetricsSnapshot {
+            stats: &stats,
+            clients_connected: 0,
+            devices_connected: 0,
+            devices_healthy: None,
+        };
+        assert!(respond(b"GET / HTTP/1.1\r\n\r\n", &snapshot).starts_with("HTTP/1.1 404"));
+        assert!(respond(b"GET /metrics HTTP/1.1\r\n\r\n", &snapshot).starts_with("HTTP/1.1 200"));
+    }
+}
@@ -1,7 +1,8 @@
 use crate::io::TcpServer;
+use crate::poll::Poll;
 use crate::traits::IoInstance;
 use chrono::Local;
-use mio::{Poll, Token};
+use mio::Token;
 use std::collections::HashMap;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
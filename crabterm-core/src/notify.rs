@@ -0,0 +1,326 @@
+//! Webhook notifications.
+//!
+//! Device connect/disconnect events and device output lines matching a
+//! configured pattern are POSTed as JSON to a HTTP endpoint. Requests are
+//! sent from a background thread so a slow endpoint never stalls the hub.
+
+use log::{error, info, warn};
+use regex::Regex;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Error, ErrorKind, Result, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, Sender};
+use std::time::{Duration, Instant};
+
+use crate::clock;
+use crate::keybind::config::SettingValue;
+
+pub const SETTING_URL: &str = "notify-url";
+pub const SETTING_PATTERN: &str = "notify-pattern";
+pub const SETTING_RATE_LIMIT: &str = "notify-rate-limit";
+
+const DEFAULT_RATE_LIMIT: Duration = Duration::from_secs(10);
+const HTTP_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_LINE: usize = 4096;
+
+/// A parsed `http://host[:port]/path` URL.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpUrl {
+    pub host: String,
+    pub port: u16,
+    pub path: String,
+}
+
+impl HttpUrl {
+    pub fn parse(url: &str) -> std::result::Result<Self, String> {
+        let rest = url
+            .strip_prefix("http://")
+            .ok_or_else(|| format!("Unsupported URL (only http:// is supported): {}", url))?;
+        let (authority, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((h, p)) => (
+                h,
+                p.parse::<u16>()
+                    .map_err(|_| format!("Invalid port in URL: {}", url))?,
+            ),
+            None => (authority, 80),
+        };
+        if host.is_empty() {
+            return Err(format!("Missing host in URL: {}", url));
+        }
+        Ok(HttpUrl {
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+}
+
+/// POST a JSON body and return the HTTP status code.
+pub fn post_json(url: &HttpUrl, body: &str) -> Result<u16> {
+    let addr = (url.host.as_str(), url.port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "host did not resolve"))?;
+    let mut stream = TcpStream::connect_timeout(&addr, HTTP_TIMEOUT)?;
+    stream.set_read_timeout(Some(HTTP_TIMEOUT))?;
+    stream.set_write_timeout(Some(HTTP_TIMEOUT))?;
+
+    write!(
+        stream,
+        "POST {} HTTP/1.1\r\nHost: {}:{}\r\nUser-Agent: crabterm\r\n\
+         Content-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        url.path,
+        url.host,
+        url.port,
+        body.len(),
+        body
+    )?;
+
+    let mut status_line = String::new();
+    BufReader::new(stream).read_line(&mut status_line)?;
+    status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "malformed HTTP response"))
+}
+
+/// Splits a byte stream into lines and reports the ones matching a pattern.
+pub struct LineMatcher {
+    pattern: Regex,
+    line: Vec<u8>,
+}
+
+impl LineMatcher {
+    pub fn new(pattern: Regex) -> Self {
+        LineMatcher {
+            pattern,
+            line: Vec::new(),
+        }
+    }
+
+    /// Feed device output, returning every completed line that matched.
+    pub fn feed(&mut self, buf: &[u8]) -> Vec<String> {
+        let mut matches = Vec::new();
+        for &b in buf {
+            match b {
+                b'\n' => {
+                    let line = String::from_utf8_lossy(&self.line).into_owned();
+                    if self.pattern.is_match(&line) {
+                        matches.push(line);
+                    }
+                    self.line.clear();
+                }
+                b'\r' => {}
+                _ if self.line.len() < MAX_LINE => self.line.push(b),
+                _ => {}
+            }
+        }
+        matches
+    }
+}
+
+/// Allows one event per interval, counting what was dropped in between.
+pub struct RateLimiter {
+    interval: Duration,
+    last: Option<Instant>,
+    suppressed: u64,
+}
+
+impl RateLimiter {
+    pub fn new(interval: Duration) -> Self {
+        RateLimiter {
+            interval,
+            last: None,
+            suppressed: 0,
+        }
+    }
+
+    /// Returns `Some(suppressed_count)` if an event may be sent now.
+    pub fn check(&mut self, now: Instant) -> Option<u64> {
+        if let Some(last) = self.last
+            && now.duration_since(last) < self.interval
+        {
+            self.suppressed += 1;
+            return None;
+        }
+        self.last = Some(now);
+        Some(std::mem::take(&mut self.suppressed))
+    }
+}
+
+pub struct Notifier {
+    tx: Sender<Value>,
+    pattern: Option<Regex>,
+    /// One line matcher per device, so lines from different devices are
+    /// never mixed up.
+    matchers: HashMap<String, LineMatcher>,
+    limiter: RateLimiter,
+}
+
+impl Notifier {
+    /// Build a notifier from the config settings. `url` overrides the
+    /// `notify-url` setting. Returns Ok(None) when no URL is configured.
+    pub fn from_settings(
+        url: Option<&str>,
+        settings: &HashMap<String, SettingValue>,
+    ) -> std::result::Result<Option<Self>, String> {
+        let Some(url) = url.or_else(|| settings.get(SETTING_URL).and_then(|v| v.as_str())) else {
+            return Ok(None);
+        };
+        let url = HttpUrl::parse(url)?;
+
+        let pattern = match settings.get(SETTING_PATTERN).and_then(|v| v.as_str()) {
+            Some(p) => {
+                Some(Regex::new(p).map_err(|e| format!("Invalid {}: {}", SETTING_PATTERN, e))?)
+            }
+            None => None,
+        };
+
+        let interval = match settings.get(SETTING_RATE_LIMIT).and_then(|v| v.as_str()) {
+            Some(s) => s
+                .parse::<f64>()
+                .ok()
+                .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+                .ok_or_else(|| format!("Invalid {}: {}", SETTING_RATE_LIMIT, s))?,
+            None => DEFAULT_RATE_LIMIT,
+        };
+
+        info!(
+            "Notifier: posting to http://{}:{}{} (rate limit {:?})",
+            url.host, url.port, url.path, interval
+        );
+
+        let (tx, rx) = mpsc::channel::<Value>();
+        std::thread::Builder::new()
+            .name("notifier".to_string())
+            .spawn(move || {
+                for event in rx {
+                    match post_json(&url, &event.to_string()) {
+                        Ok(status) if (200..300).contains(&status) => {}
+                        Ok(status) => warn!("Notifier: endpoint returned HTTP {}", status),
+                        Err(e) => error!("Notifier: POST failed: {}", e),
+                    }
+                }
+            })
+            .map_err(|e| e.to_string())?;
+
+        Ok(Some(Notifier {
+            tx,
+            pattern,
+            matchers: HashMap::new(),
+            limiter: RateLimiter::new(interval),
+        }))
+    }
+
+    fn send(&mut self, device: &str, mut event: Value) {
+        let Some(suppressed) = self.limiter.check(clock::now()) else {
+            return;
+        };
+        event["device"] = json!(device);
+        event["time"] = json!(chrono::Local::now().to_rfc3339());
+        event["suppressed"] = json!(suppressed);
+        let _ = self.tx.send(event);
+    }
+
+    /// Device output, checked against the trigger pattern.
+    pub fn rx(&mut self, device: &str, buf: &[u8]) {
+        let Some(pattern) = &self.pattern else {
+            return;
+        };
+        let lines = self
+            .matchers
+            .entry(device.to_string())
+            .or_insert_with(|| LineMatcher::new(pattern.clone()))
+            .feed(buf);
+        for line in lines {
+            self.send(device, json!({ "event": "pattern", "line": line }));
+        }
+    }
+
+    pub fn device_connected(&mut self, device: &str, msg: &str) {
+        self.send(device, json!({ "event": "connect", "message": msg }));
+    }
+
+    pub fn device_disconnected(&mut self, device: &str, msg: &str) {
+        self.send(device, json!({ "event": "disconnect", "message": msg }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_parse_url() {
+        assert_eq!(
+            HttpUrl::parse("http://example.com:8080/hook").unwrap(),
+            HttpUrl {
+                host: "example.com".to_string(),
+                port: 8080,
+                path: "/hook".to_string()
+            }
+        );
+        let url = HttpUrl::parse("http://example.com").unwrap();
+        assert_eq!(url.port, 80);
+        assert_eq!(url.path, "/");
+        assert!(HttpUrl::parse("https://example.com").is_err());
+        assert!(HttpUrl::parse("http://:80/").is_err());
+    }
+
+    #[test]
+    fn test_line_matcher_split_reads() {
+        let mut m = LineMatcher::new(Regex::new("Kernel panic").unwrap());
+        assert!(m.feed(b"ok\r\nKernel pa").is_empty());
+        assert_eq!(
+            m.feed(b"nic - not syncing\r\n"),
+            vec!["Kernel panic - not syncing"]
+        );
+    }
+
+    #[test]
+    fn test_rate_limiter() {
+        let mut l = RateLimiter::new(Duration::from_secs(10));
+        let t0 = clock::now();
+        assert_eq!(l.check(t0), Some(0));
+        assert_eq!(l.check(t0 + Duration::from_secs(1)), None);
+        assert_eq!(l.check(t0 + Duration::from_secs(2)), None);
+        assert_eq!(l.check(t0 + Duration::from_secs(11)), Some(2));
+    }
+
+    #[test]
+    fn test_post_json() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            let (mut s, _) = listener.accept().unwrap();
+            // The request may arrive in several segments; replying and
+            // closing before all of it is read would reset the connection.
+            let mut request = Vec::new();
+            let mut buf = [0u8; 1024];
+            while !request.ends_with(b"}") {
+                let n = s.read(&mut buf).unwrap();
+                if n == 0 {
+                    break;
+                }
+                request.extend_from_slice(&buf[..n]);
+            }
+            s.write_all(b"HTTP/1.1 204 No Content\r\n\r\n").unwrap();
+            String::from_utf8_lossy(&request).into_owned()
+        });
+
+        let url = HttpUrl::parse(&format!("http://127.0.0.1:{}/hook", port)).unwrap();
+        assert_eq!(post_json(&url, r#"{"event":"connect"}"#).unwrap(), 204);
+
+        let request = server.join().unwrap();
+        assert!(request.starts_with("POST /hook HTTP/1.1\r\n"));
+        assert!(request.ends_with(r#"{"event":"connect"}"#));
+    }
+}
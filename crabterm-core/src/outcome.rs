@@ -0,0 +1,133 @@
+//! Exit status patterns (`--success-pattern`, `--failure-pattern`): crabterm
+//! ends with status 0 or 1 when a line of device output matches, so it can
+//! be the last step of a flash-and-verify CI job.
+
+use regex::Regex;
+use std::fmt;
+
+/// Longest line matched; the rest is ignored
+const MAX_LINE: usize = 4096;
+
+#[derive(Debug, Clone, Default)]
+pub struct ExitPatterns {
+    pub success: Option<Regex>,
+    pub failure: Option<Regex>,
+}
+
+/// The run is over
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// The line that matched the success pattern
+    Success(String),
+    /// The line that matched the failure pattern
+    Failure(String),
+}
+
+/// The error the hub ends its run with when the failure pattern matched,
+/// so that it can be told from an I/O error.
+#[derive(Debug)]
+pub struct Failed(pub String);
+
+impl fmt::Display for Failed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for Failed {}
+
+/// Matches the output of one device against the patterns. Lines are
+/// matched as they come in, so a prompt without a line end counts too.
+#[derive(Debug, Clone)]
+pub struct OutcomeMatcher {
+    patterns: ExitPatterns,
+    line: Vec<u8>,
+}
+
+impl OutcomeMatcher {
+    pub fn new(patterns: ExitPatterns) -> Self {
+        OutcomeMatcher {
+            patterns,
+            line: Vec::new(),
+        }
+    }
+
+    /// Feed device output. The first match decides; the failure pattern
+    /// wins if a line matches both.
+    pub fn feed(&mut self, buf: &[u8]) -> Option<Outcome> {
+        for segment in buf.split_inclusive(|&b| b == b'\n') {
+            let room = MAX_LINE.saturating_sub(self.line.len());
+            self.line.extend(
+                segment
+                    .iter()
+                    .take(room)
+                    .filter(|&&b| b != b'\r' && b != b'\n'),
+            );
+            let line = String::from_utf8_lossy(&self.line);
+            let matches = |p: &Option<Regex>| p.as_ref().is_some_and(|p| p.is_match(&line));
+            let outcome = if matches(&self.patterns.failure) {
+                Some(Outcome::Failure(line.to_string()))
+            } else if matches(&self.patterns.success) {
+                Some(Outcome::Success(line.to_string()))
+            } else {
+                None
+            };
+            if segment.ends_with(b"\n") {
+                self.line.clear();
+            }
+            if outcome.is_some() {
+                return outcome;
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matcher() -> OutcomeMatcher {
+        OutcomeMatcher::new(ExitPatterns {
+            success: Some(Regex::new("login: $|PASS").unwrap()),
+            failure: Some(Regex::new("Kernel panic|FAIL").unwrap()),
+        })
+    }
+
+    #[test]
+    fn test_success_without_line_end() {
+        let mut m = matcher();
+        assert_eq!(m.feed(b"Welcome\r\nboard lo"), None);
+        assert_eq!(
+            m.feed(b"gin: "),
+            Some(Outcome::Success("board login: ".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_failure() {
+        let mut m = matcher();
+        assert_eq!(
+            m.feed(b"ok\r\n[ 1.2] Kernel panic - not syncing\r\nlogin: "),
+            Some(Outcome::Failure(
+                "[ 1.2] Kernel panic - not syncing".to_string()
+            ))
+        );
+        // Both in one line
+        let mut m = matcher();
+        assert_eq!(
+            m.feed(b"PASS 3 FAIL 1\n"),
+            Some(Outcome::Failure("PASS 3 FAIL 1".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_only_failure_pattern() {
+        let mut m = OutcomeMatcher::new(ExitPatterns {
+            success: None,
+            failure: Some(Regex::new("FAIL").unwrap()),
+        });
+        assert_eq!(m.feed(b"PASS\r\n"), None);
+        assert!(m.feed(b"FAIL\r\n").is_some());
+    }
+}
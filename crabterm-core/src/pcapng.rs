@@ -0,0 +1,189 @@
+//! pcapng encoding of console traffic, for captures named `*.pcapng`: each
+//! read from a device and each write to it is a packet with its direction
+//! and a nanosecond timestamp, so Wireshark and its dissectors can be used
+//! on a serial protocol.
+//!
+//! Each device is an interface named after it, with link type `USER0`
+//! (147); Wireshark's "DLT_USER" preferences map it to a dissector.
+
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const LINKTYPE_USER0: u16 = 147;
+
+const SHB: u32 = 0x0A0D_0D0A;
+const IDB: u32 = 0x0000_0001;
+const EPB: u32 = 0x0000_0006;
+const BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+
+const OPT_END: u16 = 0;
+const SHB_USERAPPL: u16 = 4;
+const IF_NAME: u16 = 2;
+const IF_TSRESOL: u16 = 9;
+const EPB_FLAGS: u16 = 2;
+
+/// Timestamps in nanoseconds
+const TSRESOL_NS: u8 = 9;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Read from the device
+    Rx,
+    /// Written to the device
+    Tx,
+}
+
+impl Direction {
+    /// The direction bits of `epb_flags`
+    fn flags(self) -> u32 {
+        match self {
+            Direction::Rx => 1,
+            Direction::Tx => 2,
+        }
+    }
+}
+
+fn pad4(out: &mut Vec<u8>) {
+    out.resize(out.len().next_multiple_of(4), 0);
+}
+
+fn option(out: &mut Vec<u8>, code: u16, value: &[u8]) {
+    out.extend_from_slice(&code.to_le_bytes());
+    out.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    out.extend_from_slice(value);
+    pad4(out);
+}
+
+/// Append a block of type `kind` with `body` (options included).
+fn block(out: &mut Vec<u8>, kind: u32, body: &[u8]) {
+    let len = (12 + body.len()) as u32;
+    out.extend_from_slice(&kind.to_le_bytes());
+    out.extend_from_slice(&len.to_le_bytes());
+    out.extend_from_slice(body);
+    out.extend_from_slice(&len.to_le_bytes());
+}
+
+/// Turns traffic into pcapng blocks: the section header before the first
+/// packet, and an interface description before the first packet of each
+/// device.
+#[derive(Debug, Default)]
+pub struct Pcapng {
+    started: bool,
+    interfaces: HashMap<String, u32>,
+}
+
+impl Pcapng {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append the blocks of `data` read from or written to `device` at
+    /// `time` to `out`.
+    pub fn packet(
+        &mut self,
+        out: &mut Vec<u8>,
+        device: &str,
+        direction: Direction,
+        time: SystemTime,
+        data: &[u8],
+    ) {
+        let mut body = Vec::new();
+        if !self.started {
+            self.started = true;
+            body.extend_from_slice(&BYTE_ORDER_MAGIC.to_le_bytes());
+            body.extend_from_slice(&1u16.to_le_bytes());
+            body.extend_from_slice(&0u16.to_le_bytes());
+            // Section length unknown
+            body.extend_from_slice(&(-1i64).to_le_bytes());
+            let appl = concat!("crabterm ", env!("CARGO_PKG_VERSION"));
+            option(&mut body, SHB_USERAPPL, appl.as_bytes());
+            option(&mut body, OPT_END, &[]);
+            block(out, SHB, &body);
+        }
+
+        let next = self.interfaces.len() as u32;
+        let interface = *self.interfaces.entry(device.to_string()).or_insert(next);
+        if interface == next {
+            body.clear();
+            body.extend_from_slice(&LINKTYPE_USER0.to_le_bytes());
+            body.extend_from_slice(&0u16.to_le_bytes());
+            // No snap length
+            body.extend_from_slice(&0u32.to_le_bytes());
+            option(&mut body, IF_NAME, device.as_bytes());
+            option(&mut body, IF_TSRESOL, &[TSRESOL_NS]);
+            option(&mut body, OPT_END, &[]);
+            block(out, IDB, &body);
+        }
+
+        let ns = time
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+        body.clear();
+        body.extend_from_slice(&interface.to_le_bytes());
+        body.extend_from_slice(&((ns >> 32) as u32).to_le_bytes());
+        body.extend_from_slice(&(ns as u32).to_le_bytes());
+        body.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        body.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        body.extend_from_slice(data);
+        pad4(&mut body);
+        option(&mut body, EPB_FLAGS, &direction.flags().to_le_bytes());
+        option(&mut body, OPT_END, &[]);
+        block(out, EPB, &body);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn u32_at(buf: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes(buf[at..at + 4].try_into().unwrap())
+    }
+
+    /// The type and length of each block, checking the trailing length
+    fn blocks(buf: &[u8]) -> Vec<(u32, usize)> {
+        let mut at = 0;
+        let mut found = Vec::new();
+        while at < buf.len() {
+            let len = u32_at(buf, at + 4) as usize;
+            assert_eq!(len % 4, 0);
+            assert_eq!(u32_at(buf, at + len - 4) as usize, len);
+            found.push((u32_at(buf, at), at));
+            at += len;
+        }
+        found
+    }
+
+    #[test]
+    fn test_blocks() {
+        let time = UNIX_EPOCH + Duration::from_nanos(0x1_0000_0002);
+        let mut p = Pcapng::new();
+        let mut out = Vec::new();
+        p.packet(&mut out, "usb0", Direction::Rx, time, b"hello");
+        p.packet(&mut out, "usb0", Direction::Tx, time, b"ls\r");
+        p.packet(&mut out, "usb1", Direction::Rx, time, b"x");
+
+        let found = blocks(&out);
+        let kinds: Vec<u32> = found.iter().map(|&(k, _)| k).collect();
+        assert_eq!(kinds, vec![SHB, IDB, EPB, EPB, IDB, EPB]);
+        assert_eq!(u32_at(&out, 8), BYTE_ORDER_MAGIC);
+
+        // The first packet: interface 0, the timestamp, the data, inbound
+        let epb = found[2].1;
+        assert_eq!(u32_at(&out, epb + 8), 0);
+        assert_eq!(u32_at(&out, epb + 12), 1);
+        assert_eq!(u32_at(&out, epb + 16), 2);
+        assert_eq!(u32_at(&out, epb + 20), 5);
+        assert_eq!(&out[epb + 28..epb + 33], b"hello");
+        assert_eq!(u32_at(&out, epb + 36), EPB_FLAGS as u32 | 4 << 16);
+        assert_eq!(u32_at(&out, epb + 40), 1);
+
+        // Outbound
+        let epb = found[3].1;
+        assert_eq!(u32_at(&out, epb + 36), 2);
+        // The second device is interface 1
+        assert_eq!(u32_at(&out, found[5].1 + 8), 1);
+    }
+}
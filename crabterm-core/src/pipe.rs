@@ -0,0 +1,177 @@
+//! Output tee to an external command (`pipe-to`): the lines of device
+//! output are written to the stdin of a long-running command, e.g. a log
+//! parser or an alerter, as they come. The command is started again when it
+//! exits.
+//!
+//! Writing happens on a thread of its own. A command that does not keep up
+//! loses lines rather than stalling the hub, and so does one that is being
+//! restarted. Its stdout and stderr are discarded; redirect them in the
+//! command if needed.
+
+use log::{info, warn};
+use std::io::Write;
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, RecvTimeoutError, SyncSender, TrySendError};
+use std::time::{Duration, Instant};
+
+pub const SETTING: &str = "pipe-to";
+
+/// Lines waiting for the command, beyond which lines are dropped
+const QUEUE_LINES: usize = 4096;
+/// Longest line written; longer ones are written in pieces
+const MAX_LINE: usize = 4096;
+/// Time between two starts of the command
+const RESTART_DELAY: Duration = Duration::from_secs(1);
+
+pub struct PipeTo {
+    tx: SyncSender<Vec<u8>>,
+    line: Vec<u8>,
+    /// Lines dropped since the command last kept up
+    dropped: u64,
+}
+
+impl PipeTo {
+    /// Start `command` with `sh -c`.
+    pub fn new(command: &str) -> std::io::Result<Self> {
+        let (tx, rx) = mpsc::sync_channel::<Vec<u8>>(QUEUE_LINES);
+        let command = command.to_string();
+        info!("Piping output to {:?}", command);
+        std::thread::Builder::new()
+            .name("pipe-to".to_string())
+            .spawn(move || {
+                let mut running: Option<(Child, ChildStdin)> = None;
+                let mut next_start = Instant::now();
+                loop {
+                    let line = match rx.recv_timeout(RESTART_DELAY) {
+                        Ok(line) => Some(line),
+                        Err(RecvTimeoutError::Timeout) => None,
+                        Err(RecvTimeoutError::Disconnected) => break,
+                    };
+                    if let Some((child, _)) = &mut running
+                        && let Ok(Some(status)) = child.try_wait()
+                    {
+                        warn!("pipe-to {:?} exited: {}", command, status);
+                        running = None;
+                    }
+                    if running.is_none() && Instant::now() >= next_start {
+                        next_start = Instant::now() + RESTART_DELAY;
+                        running = start(&command);
+                    }
+                    if let (Some(line), Some((child, stdin))) = (line, &mut running)
+                        && let Err(e) = stdin.write_all(&line)
+                    {
+                        warn!("pipe-to {:?}: {}", command, e);
+                        let _ = child.kill();
+                        let _ = child.wait();
+                        running = None;
+                    }
+                }
+                // End of input for the command
+                if let Some((mut child, stdin)) = running {
+                    drop(stdin);
+                    let _ = child.wait();
+                }
+            })?;
+        Ok(PipeTo {
+            tx,
+            line: Vec::new(),
+            dropped: 0,
+        })
+    }
+
+    /// Device output; complete lines go to the command, without CRs.
+    pub fn write(&mut self, buf: &[u8]) {
+        for &b in buf {
+            match b {
+                b'\r' => {}
+                b'\n' => {
+                    self.line.push(b);
+                    self.send();
+                }
+                _ => {
+                    self.line.push(b);
+                    if self.line.len() == MAX_LINE {
+                        self.send();
+                    }
+                }
+            }
+        }
+    }
+
+    fn send(&mut self) {
+        let line = std::mem::take(&mut self.line);
+        match self.tx.try_send(line) {
+            Ok(()) if self.dropped > 0 => {
+                warn!("pipe-to: {} lines dropped", self.dropped);
+                self.dropped = 0;
+            }
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => self.dropped += 1,
+            Err(TrySendError::Disconnected(_)) => {}
+        }
+    }
+}
+
+impl Drop for PipeTo {
+    fn drop(&mut self) {
+        // A line without a line end, e.g. a prompt
+        if !self.line.is_empty() {
+            self.send();
+        }
+    }
+}
+
+fn start(command: &str) -> Option<(Child, ChildStdin)> {
+    let child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn();
+    match child {
+        Ok(mut child) => {
+            let stdin = child.stdin.take()?;
+            Some((child, stdin))
+        }
+        Err(e) => {
+            warn!("pipe-to {:?}: {}", command, e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn wait_for(path: &Path, expected: &str) -> String {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            let text = std::fs::read_to_string(path).unwrap_or_default();
+            if text == expected || Instant::now() > deadline {
+                return text;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+    }
+
+    #[test]
+    fn test_lines_and_restart() {
+        let out = std::env::temp_dir().join(format!("crabterm_pipe_{}", std::process::id()));
+        let _ = std::fs::remove_file(&out);
+        // Takes one line, then exits and is started again
+        let mut pipe = PipeTo::new(&format!("head -n 1 >> {}", out.display())).unwrap();
+        pipe.write(b"U-Boot 2024.01\r\nStart");
+        assert_eq!(wait_for(&out, "U-Boot 2024.01\n"), "U-Boot 2024.01\n");
+
+        std::thread::sleep(RESTART_DELAY * 3 / 2);
+        pipe.write(b"ing kernel\r\n");
+        assert_eq!(
+            wait_for(&out, "U-Boot 2024.01\nStarting kernel\n"),
+            "U-Boot 2024.01\nStarting kernel\n"
+        );
+        let _ = std::fs::remove_file(&out);
+    }
+}
@@ -0,0 +1,709 @@
+//! Readiness of the sockets, pipes and terminals of the hub (`--backend`):
+//! mio, i.e. epoll, by default, or io_uring with the `io-uring` feature.
+//!
+//! Both report edge-triggered events by token, like mio, so the hub and the
+//! instances dispatch them the same way whichever is used. With io_uring
+//! each source has a multishot poll in the ring, and waiting for events and
+//! arming the polls of new sources is one `io_uring_enter()`.
+
+use std::io::Result;
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::str::FromStr;
+#[cfg(feature = "io-uring")]
+use std::sync::Arc;
+use std::time::Duration;
+
+pub use mio::{Interest, Token};
+
+/// The event loop to use
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Backend {
+    #[default]
+    Mio,
+    IoUring,
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, String> {
+        match s {
+            "mio" | "epoll" => Ok(Backend::Mio),
+            "io_uring" | "io-uring" => Ok(Backend::IoUring),
+            _ => Err(format!("Unknown backend: {} (mio or io_uring)", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for Backend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Backend::Mio => "mio",
+            Backend::IoUring => "io_uring",
+        })
+    }
+}
+
+/// A source that became ready
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Event {
+    token: Token,
+    readable: bool,
+    writable: bool,
+}
+
+impl Event {
+    pub fn token(&self) -> Token {
+        self.token
+    }
+
+    pub fn is_readable(&self) -> bool {
+        self.readable
+    }
+
+    pub fn is_writable(&self) -> bool {
+        self.writable
+    }
+}
+
+/// The events of one [`Poll::poll`]
+#[derive(Debug, Default)]
+pub struct Events {
+    events: Vec<Event>,
+}
+
+impl Events {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Events {
+            events: Vec::with_capacity(capacity),
+        }
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Event> {
+        self.events.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+}
+
+/// What a [`Registry`] takes: a mio source with, on unix, a file descriptor
+/// for io_uring to poll.
+#[cfg(unix)]
+pub trait Source: mio::event::Source + AsRawFd {}
+
+#[cfg(unix)]
+impl<S: mio::event::Source + AsRawFd + ?Sized> Source for S {}
+
+#[cfg(windows)]
+pub trait Source: mio::event::Source {}
+
+#[cfg(windows)]
+impl<S: mio::event::Source + ?Sized> Source for S {}
+
+/// A file descriptor owned elsewhere, e.g. stdin, as a source
+#[cfg(unix)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fd(pub RawFd);
+
+#[cfg(unix)]
+impl mio::event::Source for Fd {
+    fn register(
+        &mut self,
+        registry: &mio::Registry,
+        token: Token,
+        interest: Interest,
+    ) -> Result<()> {
+        mio::unix::SourceFd(&self.0).register(registry, token, interest)
+    }
+
+    fn reregister(
+        &mut self,
+        registry: &mio::Registry,
+        token: Token,
+        interest: Interest,
+    ) -> Result<()> {
+        mio::unix::SourceFd(&self.0).reregister(registry, token, interest)
+    }
+
+    fn deregister(&mut self, registry: &mio::Registry) -> Result<()> {
+        mio::unix::SourceFd(&self.0).deregister(registry)
+    }
+}
+
+#[cfg(unix)]
+impl AsRawFd for Fd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0
+    }
+}
+
+/// Registers sources with a [`Poll`], like `mio::Registry`.
+pub struct Registry {
+    inner: Inner,
+}
+
+enum Inner {
+    Mio(mio::Registry),
+    #[cfg(feature = "io-uring")]
+    IoUring(Arc<uring::Ring>),
+}
+
+impl Registry {
+    /// Another handle to the same registry, e.g. for an instance that
+    /// changes its interest when it writes.
+    pub fn try_clone(&self) -> Result<Registry> {
+        let inner = match &self.inner {
+            Inner::Mio(registry) => Inner::Mio(registry.try_clone()?),
+            #[cfg(feature = "io-uring")]
+            Inner::IoUring(ring) => Inner::IoUring(Arc::clone(ring)),
+        };
+        Ok(Registry { inner })
+    }
+
+    pub fn register<S>(&self, source: &mut S, token: Token, interest: Interest) -> Result<()>
+    where
+        S: Source + ?Sized,
+    {
+        match &self.inner {
+            Inner::Mio(registry) => registry.register(source, token, interest),
+            #[cfg(feature = "io-uring")]
+            Inner::IoUring(ring) => ring.register(source.as_raw_fd(), token, interest),
+        }
+    }
+
+    pub fn reregister<S>(&self, source: &mut S, token: Token, interest: Interest) -> Result<()>
+    where
+        S: Source + ?Sized,
+    {
+        match &self.inner {
+            Inner::Mio(registry) => registry.reregister(source, token, interest),
+            #[cfg(feature = "io-uring")]
+            Inner::IoUring(ring) => ring.reregister(source.as_raw_fd(), token, interest),
+        }
+    }
+
+    pub fn deregister<S>(&self, source: &mut S) -> Result<()>
+    where
+        S: Source + ?Sized,
+    {
+        match &self.inner {
+            Inner::Mio(registry) => registry.deregister(source),
+            #[cfg(feature = "io-uring")]
+            Inner::IoUring(ring) => ring.deregister(source.as_raw_fd()),
+        }
+    }
+
+    /// Register a source that has no file descriptor of its own, e.g. the
+    /// `Signals` of signal-hook; with io_uring it is polled through an
+    /// epoll instance in the ring.
+    pub fn register_indirect<S>(
+        &self,
+        source: &mut S,
+        token: Token,
+        interest: Interest,
+    ) -> Result<()>
+    where
+        S: mio::event::Source + ?Sized,
+    {
+        match &self.inner {
+            Inner::Mio(registry) => registry.register(source, token, interest),
+            #[cfg(feature = "io-uring")]
+            Inner::IoUring(ring) => ring.register_indirect(source, token, interest),
+        }
+    }
+}
+
+/// Waits for the sources registered with its [`Registry`], like
+/// `mio::Poll`.
+pub struct Poll {
+    waiter: Waiter,
+    registry: Registry,
+    mio_events: mio::Events,
+}
+
+enum Waiter {
+    Mio(mio::Poll),
+    #[cfg(feature = "io-uring")]
+    IoUring(Arc<uring::Ring>),
+}
+
+impl Poll {
+    /// With mio
+    pub fn new() -> Result<Self> {
+        Self::with_backend(Backend::Mio)
+    }
+
+    pub fn with_backend(backend: Backend) -> Result<Self> {
+        let (waiter, inner) = match backend {
+            Backend::Mio => {
+                let poll = mio::Poll::new()?;
+                let registry = poll.registry().try_clone()?;
+                (Waiter::Mio(poll), Inner::Mio(registry))
+            }
+            #[cfg(feature = "io-uring")]
+            Backend::IoUring => {
+                let ring = Arc::new(uring::Ring::new()?);
+                (Waiter::IoUring(Arc::clone(&ring)), Inner::IoUring(ring))
+            }
+            #[cfg(not(feature = "io-uring"))]
+            Backend::IoUring => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::Unsupported,
+                    "io_uring: crabterm was built without the io-uring feature",
+                ));
+            }
+        };
+        Ok(Poll {
+            waiter,
+            registry: Registry { inner },
+            mio_events: mio::Events::with_capacity(128),
+        })
+    }
+
+    pub fn backend(&self) -> Backend {
+        match self.waiter {
+            Waiter::Mio(_) => Backend::Mio,
+            #[cfg(feature = "io-uring")]
+            Waiter::IoUring(_) => Backend::IoUring,
+        }
+    }
+
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    /// Wait up to `timeout` (for ever with None) for events.
+    pub fn poll(&mut self, events: &mut Events, timeout: Option<Duration>) -> Result<()> {
+        events.events.clear();
+        match &mut self.waiter {
+            Waiter::Mio(poll) => {
+                poll.poll(&mut self.mio_events, timeout)?;
+                events.events.extend(self.mio_events.iter().map(|e| Event {
+                    token: e.token(),
+                    readable: e.is_readable(),
+                    writable: e.is_writable(),
+                }));
+                Ok(())
+            }
+            #[cfg(feature = "io-uring")]
+            Waiter::IoUring(ring) => ring.poll(&mut events.events, timeout),
+        }
+    }
+}
+
+#[cfg(feature = "io-uring")]
+mod uring {
+    use super::{Event, Interest, Token};
+    use io_uring::{IoUring, cqueue, opcode, squeue, types};
+    use log::debug;
+    use std::collections::HashMap;
+    use std::io::{Error, Result};
+    use std::os::unix::io::{AsRawFd, RawFd};
+    use std::sync::{Mutex, MutexGuard};
+    use std::time::Duration;
+
+    const ENTRIES: u32 = 256;
+    /// `user_data` of the completions of removed and updated polls
+    const REMOVED: u64 = 0;
+    /// `user_data` of the poll of `State::indirect`
+    const INDIRECT: u64 = 1;
+    const FIRST_ID: u64 = 2;
+
+    const READ_MASK: u32 = (libc::POLLIN | libc::POLLPRI | libc::POLLRDHUP) as u32;
+    const CLOSED_MASK: u32 = (libc::POLLHUP | libc::POLLERR) as u32;
+
+    /// Flags of `IORING_OP_POLL_REMOVE`, from `<linux/io_uring.h>`
+    const IORING_POLL_ADD_MULTI: u32 = 1;
+    const IORING_POLL_UPDATE_EVENTS: u32 = 2;
+
+    struct Registration {
+        token: Token,
+        interest: Interest,
+        /// The `user_data` of its poll, new for each one armed
+        id: u64,
+    }
+
+    /// The ring, shared by the poll and the registries. The submission and
+    /// completion queues are only touched with `state` locked, but the lock
+    /// is not held while waiting in `io_uring_enter()`, so that another
+    /// thread can register meanwhile.
+    pub(super) struct Ring {
+        ring: IoUring,
+        state: Mutex<State>,
+    }
+
+    struct State {
+        registrations: HashMap<RawFd, Registration>,
+        ids: HashMap<u64, RawFd>,
+        next_id: u64,
+        /// A poll() is waiting in `io_uring_enter()`: what is armed
+        /// meanwhile is submitted right away rather than by its next call
+        waiting: bool,
+        /// Sources without a file descriptor of their own
+        indirect: mio::Poll,
+        indirect_events: mio::Events,
+    }
+
+    impl Ring {
+        pub(super) fn new() -> Result<Self> {
+            let ring = Ring {
+                ring: IoUring::new(ENTRIES)?,
+                state: Mutex::new(State {
+                    registrations: HashMap::new(),
+                    ids: HashMap::new(),
+                    next_id: FIRST_ID,
+                    waiting: false,
+                    indirect: mio::Poll::new()?,
+                    indirect_events: mio::Events::with_capacity(16),
+                }),
+            };
+            let fd = ring.state().indirect.as_raw_fd();
+            ring.push(&poll_add(fd, libc::POLLIN as u32, INDIRECT))?;
+            Ok(ring)
+        }
+
+        fn state(&self) -> MutexGuard<'_, State> {
+            self.state.lock().unwrap_or_else(|e| e.into_inner())
+        }
+
+        /// Queue `entry`, with the state locked so there is one submission
+        /// queue at a time.
+        fn push(&self, entry: &squeue::Entry) -> Result<()> {
+            loop {
+                // SAFETY: the state is locked by the caller; the entries only
+                // refer to file descriptors and ids
+                let mut sq = unsafe { self.ring.submission_shared() };
+                if unsafe { sq.push(entry) }.is_ok() {
+                    return Ok(());
+                }
+                drop(sq);
+                self.ring.submit()?;
+            }
+        }
+
+        /// Submit what was queued if a poll() is waiting, which would only
+        /// submit it when it is done.
+        fn flush(&self, state: &State) -> Result<()> {
+            if state.waiting {
+                self.ring.submit()?;
+            }
+            Ok(())
+        }
+
+        /// Register `fd`, or change its registration. A registration of a
+        /// descriptor that was closed without being deregistered is
+        /// replaced, as epoll forgets closed descriptors.
+        pub(super) fn register(&self, fd: RawFd, token: Token, interest: Interest) -> Result<()> {
+            let mut state = self.state();
+            let id = state.next_id;
+            state.next_id += 1;
+            let new = Registration {
+                token,
+                interest,
+                id,
+            };
+            if let Some(old) = state.registrations.insert(fd, new) {
+                state.ids.remove(&old.id);
+                self.push(&opcode::PollRemove::new(old.id).build().user_data(REMOVED))?;
+            }
+            state.ids.insert(id, fd);
+            self.push(&poll_add(fd, mask(interest), id))?;
+            self.flush(&state)
+        }
+
+        /// Change the token and interest of `fd`, updating the events of its
+        /// poll in place.
+        pub(super) fn reregister(&self, fd: RawFd, token: Token, interest: Interest) -> Result<()> {
+            let mut state = self.state();
+            let Some(r) = state.registrations.get_mut(&fd) else {
+                drop(state);
+                return self.register(fd, token, interest);
+            };
+            r.token = token;
+            r.interest = interest;
+            // A poll the kernel ended meanwhile is not found, but its last
+            // completion arms it again with the new interest
+            self.push(&poll_update(r.id, mask(interest)))?;
+            self.flush(&state)
+        }
+
+        pub(super) fn deregister(&self, fd: RawFd) -> Result<()> {
+            let mut state = self.state();
+            let old = state
+                .registrations
+                .remove(&fd)
+                .ok_or_else(|| Error::from_raw_os_error(libc::ENOENT))?;
+            state.ids.remove(&old.id);
+            self.push(&opcode::PollRemove::new(old.id).build().user_data(REMOVED))?;
+            self.flush(&state)
+        }
+
+        pub(super) fn register_indirect<S>(
+            &self,
+            source: &mut S,
+            token: Token,
+            interest: Interest,
+        ) -> Result<()>
+        where
+            S: mio::event::Source + ?Sized,
+        {
+            self.state()
+                .indirect
+                .registry()
+                .register(source, token, interest)
+        }
+
+        /// Submit the polls armed since the last call and wait for
+        /// completions, in one `io_uring_enter()`.
+        pub(super) fn poll(
+            &self,
+            events: &mut Vec<Event>,
+            timeout: Option<Duration>,
+        ) -> Result<()> {
+            self.state().waiting = true;
+            let submitted = match timeout {
+                Some(timeout) => {
+                    let ts = types::Timespec::from(timeout);
+                    let args = types::SubmitArgs::new().timespec(&ts);
+                    self.ring.submitter().submit_with_args(1, &args)
+                }
+                None => self.ring.submitter().submit_and_wait(1),
+            };
+            let mut state = self.state();
+            state.waiting = false;
+            match submitted {
+                Ok(_) => {}
+                Err(e) if e.raw_os_error() == Some(libc::ETIME) => {}
+                Err(e) => return Err(e),
+            }
+
+            // SAFETY: the state is locked, and only here are completions read
+            let completions: Vec<_> = unsafe { self.ring.completion_shared() }.collect();
+            for cqe in completions {
+                let more = cqueue::more(cqe.flags());
+                match cqe.user_data() {
+                    REMOVED => {}
+                    INDIRECT => {
+                        state.indirect_ready(events)?;
+                        if !more {
+                            let fd = state.indirect.as_raw_fd();
+                            self.push(&poll_add(fd, libc::POLLIN as u32, INDIRECT))?;
+                        }
+                    }
+                    id => {
+                        // A multishot poll the kernel ended, e.g. when the
+                        // completion queue overflowed
+                        if let Some((fd, interest)) = state.ready(id, cqe.result(), events)
+                            && !more
+                        {
+                            self.push(&poll_add(fd, mask(interest), id))?;
+                        }
+                    }
+                }
+            }
+            Ok(())
+        }
+    }
+
+    fn mask(interest: Interest) -> u32 {
+        let mut mask = 0;
+        if interest.is_readable() {
+            mask |= READ_MASK;
+        }
+        if interest.is_writable() {
+            mask |= libc::POLLOUT as u32;
+        }
+        mask
+    }
+
+    fn poll_add(fd: RawFd, mask: u32, id: u64) -> squeue::Entry {
+        opcode::PollAdd::new(types::Fd(fd), mask)
+            .multi(true)
+            .build()
+            .user_data(id)
+    }
+
+    /// `IORING_OP_POLL_REMOVE` as an update of the events of the multishot
+    /// poll `id`, which the io-uring crate has no opcode for: `len` has the
+    /// flags and `poll32_events` the new events.
+    fn poll_update(id: u64, mask: u32) -> squeue::Entry {
+        let entry = opcode::PollRemove::new(id).build().user_data(REMOVED);
+        // SAFETY: an entry is the repr(C) `io_uring_sqe` of 64 bytes
+        let mut sqe: [u8; 64] = unsafe { std::mem::transmute(entry) };
+        let flags = IORING_POLL_UPDATE_EVENTS | IORING_POLL_ADD_MULTI;
+        sqe[24..28].copy_from_slice(&flags.to_ne_bytes());
+        sqe[28..32].copy_from_slice(&mask.to_ne_bytes());
+        unsafe { std::mem::transmute::<[u8; 64], squeue::Entry>(sqe) }
+    }
+
+    impl State {
+        /// A completion of the poll `id` of a registration: the descriptor
+        /// and interest of the registration, None for a poll removed
+        /// meanwhile.
+        fn ready(
+            &mut self,
+            id: u64,
+            result: i32,
+            events: &mut Vec<Event>,
+        ) -> Option<(RawFd, Interest)> {
+            let &fd = self.ids.get(&id)?;
+            let r = self.registrations.get(&fd).filter(|r| r.id == id)?;
+            let (token, interest) = (r.token, r.interest);
+            if result < 0 {
+                debug!(
+                    "io_uring: poll of {:?}: {}",
+                    token,
+                    Error::from_raw_os_error(-result)
+                );
+                return None;
+            }
+            let mask = result as u32;
+            events.push(Event {
+                token,
+                readable: interest.is_readable() && mask & (READ_MASK | CLOSED_MASK) != 0,
+                writable: interest.is_writable()
+                    && mask & (libc::POLLOUT as u32 | CLOSED_MASK) != 0,
+            });
+            Some((fd, interest))
+        }
+
+        fn indirect_ready(&mut self, events: &mut Vec<Event>) -> Result<()> {
+            self.indirect
+                .poll(&mut self.indirect_events, Some(Duration::ZERO))?;
+            events.extend(self.indirect_events.iter().map(|e| Event {
+                token: e.token(),
+                readable: e.is_readable(),
+                writable: e.is_writable(),
+            }));
+            Ok(())
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixStream;
+
+    fn backends() -> Vec<Backend> {
+        let mut backends = vec![Backend::Mio];
+        if cfg!(feature = "io-uring") {
+            backends.push(Backend::IoUring);
+        }
+        backends
+    }
+
+    fn tokens(poll: &mut Poll, timeout: Duration) -> Vec<(usize, bool, bool)> {
+        let mut events = Events::with_capacity(8);
+        poll.poll(&mut events, Some(timeout)).unwrap();
+        events
+            .iter()
+            .map(|e| (e.token().0, e.is_readable(), e.is_writable()))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_backend() {
+        assert_eq!("io_uring".parse(), Ok(Backend::IoUring));
+        assert_eq!("mio".parse(), Ok(Backend::Mio));
+        assert!("kqueue".parse::<Backend>().is_err());
+        assert_eq!(Backend::IoUring.to_string(), "io_uring");
+    }
+
+    #[test]
+    fn test_readiness() {
+        for backend in backends() {
+            let mut poll = Poll::with_backend(backend).unwrap();
+            assert_eq!(poll.backend(), backend);
+            let (a, mut b) = UnixStream::pair().unwrap();
+            a.set_nonblocking(true).unwrap();
+            let mut a = mio::net::UnixStream::from_std(a);
+            poll.registry()
+                .register(&mut a, Token(7), Interest::READABLE)
+                .unwrap();
+            assert_eq!(tokens(&mut poll, Duration::ZERO), vec![], "{}", backend);
+
+            b.write_all(b"x").unwrap();
+            assert_eq!(
+                tokens(&mut poll, Duration::from_secs(1)),
+                vec![(7, true, false)],
+                "{}",
+                backend
+            );
+            // Edge-triggered: nothing new until more arrives
+            assert_eq!(tokens(&mut poll, Duration::ZERO), vec![], "{}", backend);
+            a.read_exact(&mut [0u8; 1]).unwrap();
+
+            poll.registry()
+                .reregister(&mut a, Token(8), Interest::READABLE | Interest::WRITABLE)
+                .unwrap();
+            assert_eq!(
+                tokens(&mut poll, Duration::from_secs(1)),
+                vec![(8, false, true)],
+                "{}",
+                backend
+            );
+
+            poll.registry().deregister(&mut a).unwrap();
+            b.write_all(b"y").unwrap();
+            assert_eq!(
+                tokens(&mut poll, Duration::from_millis(20)),
+                vec![],
+                "{}",
+                backend
+            );
+        }
+    }
+
+    #[test]
+    fn test_register_while_waiting() {
+        for backend in backends() {
+            let mut poll = Poll::with_backend(backend).unwrap();
+            let registry = poll.registry().try_clone().unwrap();
+            let (a, mut b) = UnixStream::pair().unwrap();
+            a.set_nonblocking(true).unwrap();
+            b.write_all(b"x").unwrap();
+            let registering = std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(50));
+                let mut a = mio::net::UnixStream::from_std(a);
+                registry
+                    .register(&mut a, Token(5), Interest::READABLE)
+                    .unwrap();
+                a
+            });
+            let start = std::time::Instant::now();
+            assert_eq!(
+                tokens(&mut poll, Duration::from_secs(5)),
+                vec![(5, true, false)],
+                "{}",
+                backend
+            );
+            assert!(start.elapsed() < Duration::from_secs(2), "{}", backend);
+            registering.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_indirect() {
+        for backend in backends() {
+            let mut poll = Poll::with_backend(backend).unwrap();
+            let (a, mut b) = UnixStream::pair().unwrap();
+            a.set_nonblocking(true).unwrap();
+            let mut a = mio::net::UnixStream::from_std(a);
+            poll.registry()
+                .register_indirect(&mut a, Token(3), Interest::READABLE)
+                .unwrap();
+            b.write_all(b"x").unwrap();
+            assert_eq!(
+                tokens(&mut poll, Duration::from_secs(1)),
+                vec![(3, true, false)],
+                "{}",
+                backend
+            );
+        }
+    }
+}
@@ -0,0 +1,306 @@
+//! Power and reset control of the board (`power on|off|cycle`, `reset`):
+//! hooks in the config that run a shell command, e.g. for a YKUSH hub, or
+//! POST to the HTTP API of a PDU. Everybody attached is told when the board
+//! is power-cycled from within crabterm.
+//!
+//! ```text
+//! set power-on "ykushcmd -u 1"
+//! set power-off "ykushcmd -d 1"
+//! set power-reset "http://pdu.lab/outlet/3/reset"
+//! ```
+//!
+//! Commands get the device in `CRABTERM_DEVICE` and the operation (`on`,
+//! `off`, `cycle`, `reset`) in `CRABTERM_POWER`; URLs get both as JSON,
+//! `{"action": "cycle", "device": "usb0"}`. Without a `power-cycle` hook a
+//! cycle is `power-off`, `power-cycle-delay` seconds (default 2), then
+//! `power-on`.
+
+use log::info;
+use serde_json::json;
+use std::collections::HashMap;
+use std::fmt;
+use std::process::Command;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::time::{Duration, Instant};
+
+use crate::clock;
+use crate::keybind::config::SettingValue;
+use crate::notify::{self, HttpUrl};
+
+pub const SETTING_CYCLE_DELAY: &str = "power-cycle-delay";
+
+const DEFAULT_CYCLE_DELAY: Duration = Duration::from_secs(2);
+/// How often the hub looks for hooks that are done while some run
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PowerOp {
+    On,
+    Off,
+    Cycle,
+    Reset,
+}
+
+impl PowerOp {
+    /// Parse the argument of `power`: `on`, `off` or `cycle`.
+    pub fn parse(word: &str) -> Result<Self, String> {
+        match word {
+            "on" => Ok(PowerOp::On),
+            "off" => Ok(PowerOp::Off),
+            "cycle" => Ok(PowerOp::Cycle),
+            _ => Err(format!(
+                "Invalid power operation: {} (on, off, cycle)",
+                word
+            )),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            PowerOp::On => "on",
+            PowerOp::Off => "off",
+            PowerOp::Cycle => "cycle",
+            PowerOp::Reset => "reset",
+        }
+    }
+
+    /// The setting with the hook
+    pub fn setting(self) -> &'static str {
+        match self {
+            PowerOp::On => "power-on",
+            PowerOp::Off => "power-off",
+            PowerOp::Cycle => "power-cycle",
+            PowerOp::Reset => "power-reset",
+        }
+    }
+}
+
+impl fmt::Display for PowerOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PowerOp::Reset => write!(f, "reset"),
+            op => write!(f, "power {}", op.name()),
+        }
+    }
+}
+
+/// What a setting does: run a shell command, or POST to a URL
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Hook {
+    Exec(String),
+    Http(HttpUrl),
+}
+
+impl Hook {
+    fn parse(value: &str) -> Result<Self, String> {
+        if value.starts_with("http://") {
+            HttpUrl::parse(value).map(Hook::Http)
+        } else {
+            Ok(Hook::Exec(value.to_string()))
+        }
+    }
+
+    fn run(&self, op: PowerOp, device: &str) -> Result<(), String> {
+        info!("{} of {}: {:?}", op, device, self);
+        match self {
+            Hook::Exec(command) => {
+                let status = Command::new("sh")
+                    .arg("-c")
+                    .arg(command)
+                    .env("CRABTERM_DEVICE", device)
+                    .env("CRABTERM_POWER", op.name())
+                    .status()
+                    .map_err(|e| format!("{}: {}", command, e))?;
+                if status.success() {
+                    Ok(())
+                } else {
+                    Err(format!("{}: {}", command, status))
+                }
+            }
+            Hook::Http(url) => {
+                let body = json!({ "action": op.name(), "device": device });
+                match notify::post_json(url, &body.to_string()) {
+                    Ok(status) if (200..300).contains(&status) => Ok(()),
+                    Ok(status) => Err(format!("HTTP {}", status)),
+                    Err(e) => Err(e.to_string()),
+                }
+            }
+        }
+    }
+}
+
+/// A hook that is done: the operation, the device and how it went
+pub type Done = (PowerOp, String, Result<(), String>);
+
+/// The power hooks, run on threads of their own so a slow PDU does not
+/// stall the hub.
+pub struct PowerControl {
+    hooks: HashMap<PowerOp, Hook>,
+    cycle_delay: Duration,
+    tx: Sender<Done>,
+    rx: Receiver<Done>,
+    running: usize,
+}
+
+impl PowerControl {
+    /// The hooks of the config settings. Ok(None) when there are none.
+    pub fn from_settings(settings: &HashMap<String, SettingValue>) -> Result<Option<Self>, String> {
+        let mut hooks = HashMap::new();
+        for op in [PowerOp::On, PowerOp::Off, PowerOp::Cycle, PowerOp::Reset] {
+            if let Some(value) = settings.get(op.setting()).and_then(|v| v.as_str()) {
+                let hook =
+                    Hook::parse(value).map_err(|e| format!("Invalid {}: {}", op.setting(), e))?;
+                hooks.insert(op, hook);
+            }
+        }
+        if hooks.is_empty() {
+            return Ok(None);
+        }
+        let cycle_delay = match settings.get(SETTING_CYCLE_DELAY).and_then(|v| v.as_str()) {
+            Some(s) => s
+                .parse::<f64>()
+                .ok()
+                .and_then(|s| Duration::try_from_secs_f64(s).ok())
+                .ok_or_else(|| format!("Invalid {}: {}", SETTING_CYCLE_DELAY, s))?,
+            None => DEFAULT_CYCLE_DELAY,
+        };
+        let (tx, rx) = mpsc::channel();
+        Ok(Some(PowerControl {
+            hooks,
+            cycle_delay,
+            tx,
+            rx,
+            running: 0,
+        }))
+    }
+
+    /// Start `op` on `device`. Err when it is not configured.
+    pub fn start(&mut self, op: PowerOp, device: &str) -> Result<(), String> {
+        let steps = match (self.hooks.get(&op), op) {
+            (Some(hook), _) => vec![(op, hook.clone())],
+            (None, PowerOp::Cycle) => {
+                match (self.hooks.get(&PowerOp::Off), self.hooks.get(&PowerOp::On)) {
+                    (Some(off), Some(on)) => {
+                        vec![(PowerOp::Off, off.clone()), (PowerOp::On, on.clone())]
+                    }
+                    _ => {
+                        return Err(format!(
+                            "{}: set power-cycle, or power-off and power-on",
+                            op
+                        ));
+                    }
+                }
+            }
+            (None, _) => return Err(format!("{}: set {}", op, op.setting())),
+        };
+        let delay = self.cycle_delay;
+        let device = device.to_string();
+        let tx = self.tx.clone();
+        std::thread::Builder::new()
+            .name("power".to_string())
+            .spawn(move || {
+                let mut result = Ok(());
+                for (i, (step, hook)) in steps.iter().enumerate() {
+                    if i > 0 {
+                        std::thread::sleep(delay);
+                    }
+                    result = hook.run(*step, &device);
+                    if result.is_err() {
+                        break;
+                    }
+                }
+                let _ = tx.send((op, device, result));
+            })
+            .map_err(|e| e.to_string())?;
+        self.running += 1;
+        Ok(())
+    }
+
+    /// When to look for hooks that are done
+    pub fn next_tick(&self) -> Option<Instant> {
+        (self.running > 0).then(|| clock::now() + POLL_INTERVAL)
+    }
+
+    /// The hooks done since the last call.
+    pub fn finished(&mut self) -> Vec<Done> {
+        let done: Vec<Done> = self.rx.try_iter().collect();
+        self.running -= done.len();
+        done
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(pairs: &[(&str, &str)]) -> HashMap<String, SettingValue> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), SettingValue::String(v.to_string())))
+            .collect()
+    }
+
+    fn wait(power: &mut PowerControl) -> Vec<Done> {
+        let deadline = clock::now() + Duration::from_secs(5);
+        while power.next_tick().is_some() && clock::now() < deadline {
+            std::thread::sleep(POLL_INTERVAL);
+            let done = power.finished();
+            if !done.is_empty() {
+                return done;
+            }
+        }
+        Vec::new()
+    }
+
+    #[test]
+    fn test_cycle_off_on() {
+        let out = std::env::temp_dir().join(format!("crabterm_power_{}", std::process::id()));
+        let log = format!("echo $CRABTERM_POWER $CRABTERM_DEVICE >> {}", out.display());
+        let mut power = PowerControl::from_settings(&settings(&[
+            ("power-on", &log),
+            ("power-off", &log),
+            (SETTING_CYCLE_DELAY, "0.1"),
+        ]))
+        .unwrap()
+        .unwrap();
+
+        power.start(PowerOp::Cycle, "usb0").unwrap();
+        assert_eq!(
+            wait(&mut power),
+            vec![(PowerOp::Cycle, "usb0".to_string(), Ok(()))]
+        );
+        assert_eq!(
+            std::fs::read_to_string(&out).unwrap(),
+            "off usb0\non usb0\n"
+        );
+        assert!(power.next_tick().is_none());
+        let _ = std::fs::remove_file(&out);
+
+        assert!(power.start(PowerOp::Reset, "usb0").is_err());
+    }
+
+    #[test]
+    fn test_failure() {
+        let mut power = PowerControl::from_settings(&settings(&[("power-reset", "exit 3")]))
+            .unwrap()
+            .unwrap();
+        power.start(PowerOp::Reset, "usb0").unwrap();
+        let done = wait(&mut power);
+        assert!(done[0].2.as_ref().unwrap_err().contains("exit status: 3"));
+        assert!(power.start(PowerOp::Cycle, "usb0").is_err());
+    }
+
+    #[test]
+    fn test_settings() {
+        assert!(
+            PowerControl::from_settings(&settings(&[]))
+                .unwrap()
+                .is_none()
+        );
+        assert!(PowerControl::from_settings(&settings(&[("power-on", "http://:80/on")])).is_err());
+        assert_eq!(PowerOp::parse("cycle"), Ok(PowerOp::Cycle));
+        assert!(PowerOp::parse("reset").is_err());
+        assert_eq!(PowerOp::Reset.to_string(), "reset");
+        assert_eq!(PowerOp::Off.to_string(), "power off");
+    }
+}
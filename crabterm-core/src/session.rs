@@ -0,0 +1,114 @@
+//! Named sessions: a detached crabterm keeps its devices open and accepts
+//! clients on a unix socket, `crabterm attach NAME` connects to it.
+
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+use std::path::{Path, PathBuf};
+
+const SOCKET_EXT: &str = "sock";
+
+/// Directory holding the session sockets: `$XDG_RUNTIME_DIR/crabterm`, or
+/// `/tmp/crabterm-UID` when that is not set.
+pub fn socket_dir() -> PathBuf {
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir).join("crabterm"),
+        _ => PathBuf::from(format!("/tmp/crabterm-{}", unsafe { libc::getuid() })),
+    }
+}
+
+/// Refuse `dir` unless it is a directory (not a link to one) of ours that
+/// nobody else may enter. /tmp is shared: another user could have created
+/// /tmp/crabterm-UID first, to see the sessions or to stand in for them.
+fn check_dir(dir: &Path) -> Result<()> {
+    let meta = fs::symlink_metadata(dir)?;
+    let uid = unsafe { libc::getuid() };
+    if !meta.is_dir() || meta.uid() != uid || meta.mode() & 0o077 != 0 {
+        return Err(Error::new(
+            ErrorKind::PermissionDenied,
+            format!(
+                "{} is not a directory of uid {} with mode 0700",
+                dir.display(),
+                uid
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Socket path of session `name`, creating the directory if needed.
+pub fn socket_path(name: &str) -> Result<PathBuf> {
+    let dir = socket_dir();
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(&dir)?;
+    check_dir(&dir)?;
+    Ok(dir.join(format!("{}.{}", name, SOCKET_EXT)))
+}
+
+/// Names of the sessions with a socket in [`socket_dir`], sorted; none
+/// when it is not there.
+pub fn list() -> Result<Vec<String>> {
+    let dir = socket_dir();
+    match check_dir(&dir) {
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        result => result?,
+    }
+    let mut names: Vec<String> = fs::read_dir(dir)?
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| p.extension().is_some_and(|e| e == SOCKET_EXT))
+        .filter_map(|p| p.file_stem().map(|s| s.to_string_lossy().into_owned()))
+        .collect();
+    names.sort();
+    Ok(names)
+}
+
+/// Session name derived from a device label, e.g. "usb0"; characters that do
+/// not belong in a file name are replaced by '_'.
+pub fn name_from_label(label: &str) -> String {
+    label
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_name_from_label() {
+        assert_eq!(name_from_label("usb0"), "usb0");
+        assert_eq!(name_from_label("192.168.1.10:4000"), "192.168.1.10_4000");
+    }
+
+    #[test]
+    fn test_check_dir() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("crabterm-check-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::DirBuilder::new().mode(0o700).create(&dir).unwrap();
+        assert!(check_dir(&dir).is_ok());
+
+        let link = dir.with_extension("link");
+        let _ = fs::remove_file(&link);
+        std::os::unix::fs::symlink(&dir, &link).unwrap();
+        assert!(check_dir(&link).is_err());
+
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o755)).unwrap();
+        let e = check_dir(&dir).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::PermissionDenied);
+
+        fs::remove_file(&link).unwrap();
+        fs::remove_dir(&dir).unwrap();
+    }
+}
@@ -0,0 +1,132 @@
+use std::time::{Duration, Instant};
+
+/// Counters maintained by the hub for the lifetime of the process.
+#[derive(Debug, Default, Clone)]
+pub struct HubStats {
+    /// Bytes read from the device
+    pub device_rx_bytes: u64,
+    /// Bytes written to the device
+    pub device_tx_bytes: u64,
+    /// Number of clients accepted on the server port
+    pub client_connections: u64,
+    /// Number of times the device was connected again after the first time
+    pub device_reconnects: u64,
+    /// Number of times a device write blocked and backpressure was applied
+    pub backpressure_events: u64,
+    /// Number of clients disconnected for not keeping up with the device
+    pub slow_clients_dropped: u64,
+    /// Number of times a device stopped answering the health check
+    pub health_failures: u64,
+    /// Number of successful device connects (including the first)
+    pub device_connects: u64,
+    /// Time a device was connected, not counting the current connection
+    pub connected_total: Duration,
+    /// Since when at least one device has been connected
+    pub connected_since: Option<Instant>,
+    /// Number of devices currently connected
+    pub devices_connected: usize,
+}
+
+impl HubStats {
+    /// A device connected; `reconnect` is true if it was connected before.
+    pub fn device_up(&mut self, now: Instant, reconnect: bool) {
+        if reconnect {
+            self.device_reconnects += 1;
+        }
+        self.device_connects += 1;
+        if self.devices_connected == 0 {
+            self.connected_since = Some(now);
+        }
+        self.devices_connected += 1;
+    }
+
+    pub fn device_down(&mut self, now: Instant) {
+        self.devices_connected = self.devices_connected.saturating_sub(1);
+        if self.devices_connected == 0
+            && let Some(since) = self.connected_since.take()
+        {
+            self.connected_total += now.saturating_duration_since(since);
+        }
+    }
+
+    /// Total time at least one device has been connected, including the
+    /// current connection.
+    pub fn connected_time(&self, now: Instant) -> Duration {
+        self.connected_total
+            + self
+                .connected_since
+                .map_or(Duration::ZERO, |s| now.saturating_duration_since(s))
+    }
+
+    /// Human readable summary, one line per entry.
+    pub fn summary(&self, session: Duration, now: Instant) -> Vec<String> {
+        vec![
+            format!(
+                "Session {}, device connected {}, {} reconnect(s)",
+                format_duration(session),
+                format_duration(self.connected_time(now)),
+                self.device_reconnects
+            ),
+            format!(
+                "Device bytes in: {}, out: {}",
+                self.device_rx_bytes, self.device_tx_bytes
+            ),
+            format!("Clients served: {}", self.client_connections),
+        ]
+    }
+}
+
+/// Format as "1h02m03s", "2m03s" or "3s".
+pub fn format_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    let (h, m, s) = (secs / 3600, secs / 60 % 60, secs % 60);
+    if h > 0 {
+        format!("{}h{:02}m{:02}s", h, m, s)
+    } else if m > 0 {
+        format!("{}m{:02}s", m, s)
+    } else {
+        format!("{}s", s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(Duration::from_secs(3)), "3s");
+        assert_eq!(format_duration(Duration::from_secs(123)), "2m03s");
+        assert_eq!(format_duration(Duration::from_secs(3723)), "1h02m03s");
+    }
+
+    #[test]
+    fn test_connected_time() {
+        let t0 = Instant::now();
+        let mut stats = HubStats::default();
+
+        stats.device_up(t0, false);
+        stats.device_down(t0 + Duration::from_secs(10));
+        assert_eq!(stats.device_reconnects, 0);
+        assert_eq!(
+            stats.connected_time(t0 + Duration::from_secs(20)),
+            Duration::from_secs(10)
+        );
+
+        stats.device_up(t0 + Duration::from_secs(20), true);
+        assert_eq!(stats.device_reconnects, 1);
+        assert_eq!(
+            stats.connected_time(t0 + Duration::from_secs(25)),
+            Duration::from_secs(15)
+        );
+
+        // Overlapping connections of a second device count once
+        stats.device_up(t0 + Duration::from_secs(25), false);
+        stats.device_down(t0 + Duration::from_secs(30));
+        assert_eq!(stats.device_reconnects, 1);
+        assert_eq!(
+            stats.connected_time(t0 + Duration::from_secs(30)),
+            Duration::from_secs(20)
+        );
+    }
+}
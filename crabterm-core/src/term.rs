@@ -0,0 +1,273 @@
+use std::io::IsTerminal;
+#[cfg(unix)]
+use std::os::fd::{AsRawFd, RawFd};
+#[cfg(windows)]
+use std::os::windows::io::AsRawHandle;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+#[cfg(unix)]
+use termios::{TCSANOW, Termios, cfmakeraw, tcsetattr};
+#[cfg(windows)]
+use windows_sys::Win32::{
+    Foundation::HANDLE,
+    System::Console::{
+        CONSOLE_MODE, CONSOLE_SCREEN_BUFFER_INFO, DISABLE_NEWLINE_AUTO_RETURN, ENABLE_ECHO_INPUT,
+        ENABLE_LINE_INPUT, ENABLE_PROCESSED_INPUT, ENABLE_VIRTUAL_TERMINAL_INPUT,
+        ENABLE_VIRTUAL_TERMINAL_PROCESSING, GetConsoleMode, GetConsoleScreenBufferInfo,
+        SetConsoleMode,
+    },
+};
+
+#[cfg(unix)]
+static ORIGINAL_TERMIOS: OnceLock<Termios> = OnceLock::new();
+/// Console modes of stdin and stdout (None when it is not a console) before
+/// raw mode
+#[cfg(windows)]
+static ORIGINAL_MODES: OnceLock<(CONSOLE_MODE, Option<CONSOLE_MODE>)> = OnceLock::new();
+static RAW_MODE_ACTIVE: AtomicBool = AtomicBool::new(false);
+
+// Size of the local terminal as columns << 16 | rows: 0 until first
+// queried, NO_TERMINAL when there is none.
+static TERMINAL_SIZE: AtomicU32 = AtomicU32::new(0);
+const NO_TERMINAL: u32 = u32::MAX;
+
+/// Returns true if `fd` refers to a terminal.
+#[cfg(unix)]
+pub fn is_tty(fd: RawFd) -> bool {
+    unsafe { libc::isatty(fd) == 1 }
+}
+
+pub fn stdin_is_tty() -> bool {
+    std::io::stdin().is_terminal()
+}
+
+pub fn stdout_is_tty() -> bool {
+    std::io::stdout().is_terminal()
+}
+
+pub fn stderr_is_tty() -> bool {
+    std::io::stderr().is_terminal()
+}
+
+/// Columns and rows of the local terminal, None when there is none. Cached;
+/// the hub calls [`refresh_terminal_size`] on SIGWINCH.
+/// Windows has no SIGWINCH, so there it is queried every time.
+pub fn terminal_size() -> Option<(u16, u16)> {
+    let mut size = TERMINAL_SIZE.load(Ordering::Relaxed);
+    if size == 0 || cfg!(windows) {
+        refresh_terminal_size();
+        size = TERMINAL_SIZE.load(Ordering::Relaxed);
+    }
+    match size {
+        NO_TERMINAL => None,
+        s => Some(((s >> 16) as u16, s as u16)),
+    }
+}
+
+/// Width in columns of the local terminal, see [`terminal_size`].
+pub fn terminal_width() -> Option<u16> {
+    terminal_size().map(|(cols, _)| cols)
+}
+
+/// Query the terminal size again, e.g. after the window was resized.
+pub fn refresh_terminal_size() {
+    let size = match query_terminal_size() {
+        Some((cols, rows)) => (cols as u32) << 16 | rows as u32,
+        None => NO_TERMINAL,
+    };
+    TERMINAL_SIZE.store(size, Ordering::Relaxed);
+}
+
+/// The terminal typed on: stdin, or the controlling terminal when stdin is
+/// redirected. Not stdout, which is a pipe for `crabterm ... | tee log`.
+#[cfg(unix)]
+fn query_terminal_size() -> Option<(u16, u16)> {
+    let query = |fd: RawFd| {
+        let mut ws: libc::winsize = unsafe { std::mem::zeroed() };
+        let ok = unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, &mut ws) } == 0;
+        (ok && ws.ws_col > 0).then_some((ws.ws_col, ws.ws_row))
+    };
+    query(std::io::stdin().as_raw_fd()).or_else(|| {
+        let tty = std::fs::File::open("/dev/tty").ok()?;
+        query(tty.as_raw_fd())
+    })
+}
+
+/// The visible window of the console buffer, not the whole buffer
+#[cfg(windows)]
+fn query_terminal_size() -> Option<(u16, u16)> {
+    let mut info: CONSOLE_SCREEN_BUFFER_INFO = unsafe { std::mem::zeroed() };
+    let handle = std::io::stdout().as_raw_handle() as HANDLE;
+    let ok = unsafe { GetConsoleScreenBufferInfo(handle, &mut info) } != 0;
+    let window = info.srWindow;
+    let cols = (window.Right - window.Left + 1) as u16;
+    let rows = (window.Bottom - window.Top + 1) as u16;
+    (ok && cols > 0).then_some((cols, rows))
+}
+
+/// True while the controlling terminal is in raw mode, i.e. while output to it
+/// needs explicit "\r" before "\n".
+pub fn raw_mode_active() -> bool {
+    RAW_MODE_ACTIVE.load(Ordering::Relaxed)
+}
+
+#[cfg(unix)]
+pub fn enable_raw_mode() -> std::io::Result<()> {
+    let fd = std::io::stdin().as_raw_fd();
+    let mut termios = Termios::from_fd(fd)?;
+    ORIGINAL_TERMIOS.set(termios).ok(); // ignore if already set
+    cfmakeraw(&mut termios);
+    tcsetattr(fd, TCSANOW, &termios)?;
+    RAW_MODE_ACTIVE.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Raw mode of the Windows console: no line editing, echo or Ctrl-C
+/// handling on input, which comes as VT sequences like on a unix terminal,
+/// and no carriage return added to "\n" on output.
+#[cfg(windows)]
+pub fn enable_raw_mode() -> std::io::Result<()> {
+    let stdin = std::io::stdin().as_raw_handle() as HANDLE;
+    let stdout = std::io::stdout().as_raw_handle() as HANDLE;
+    let input = console_mode(stdin)?;
+    let output = console_mode(stdout).ok();
+    ORIGINAL_MODES.set((input, output)).ok(); // ignore if already set
+    let (input, output) = *ORIGINAL_MODES.get().unwrap();
+    set_console_mode(
+        stdin,
+        input & !(ENABLE_LINE_INPUT | ENABLE_ECHO_INPUT | ENABLE_PROCESSED_INPUT)
+            | ENABLE_VIRTUAL_TERMINAL_INPUT,
+    )?;
+    if let Some(output) = output {
+        set_console_mode(
+            stdout,
+            output | ENABLE_VIRTUAL_TERMINAL_PROCESSING | DISABLE_NEWLINE_AUTO_RETURN,
+        )?;
+    }
+    RAW_MODE_ACTIVE.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+#[cfg(windows)]
+fn console_mode(handle: HANDLE) -> std::io::Result<CONSOLE_MODE> {
+    let mut mode = 0;
+    if unsafe { GetConsoleMode(handle, &mut mode) } == 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(mode)
+}
+
+#[cfg(windows)]
+fn set_console_mode(handle: HANDLE, mode: CONSOLE_MODE) -> std::io::Result<()> {
+    if unsafe { SetConsoleMode(handle, mode) } == 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Stop the process as the default SIGTSTP action would, with the terminal
+/// back in its original mode while stopped. Returns once continued; see
+/// [`resume`] for SIGCONT. The console puts back the rest, see
+/// [`IoInstance::suspend`](crate::IoInstance::suspend).
+#[cfg(unix)]
+pub fn suspend() {
+    let was_raw = raw_mode_active();
+    if was_raw {
+        let _ = disable_raw_mode();
+    }
+    unsafe {
+        libc::raise(libc::SIGSTOP);
+    }
+    if was_raw {
+        let _ = enable_raw_mode();
+    }
+}
+
+/// After SIGCONT: the shell may have reset the terminal while we were
+/// stopped, so raw mode is applied again if it was on.
+pub fn resume() {
+    if raw_mode_active() {
+        let _ = enable_raw_mode();
+    }
+}
+
+#[cfg(unix)]
+pub fn disable_raw_mode() -> std::io::Result<()> {
+    let fd = std::io::stdin().as_raw_fd();
+    if let Some(original) = ORIGINAL_TERMIOS.get() {
+        tcsetattr(fd, TCSANOW, original)?;
+    }
+    RAW_MODE_ACTIVE.store(false, Ordering::Relaxed);
+    Ok(())
+}
+
+#[cfg(windows)]
+pub fn disable_raw_mode() -> std::io::Result<()> {
+    if let Some(&(input, output)) = ORIGINAL_MODES.get() {
+        set_console_mode(std::io::stdin().as_raw_handle() as HANDLE, input)?;
+        if let Some(output) = output {
+            set_console_mode(std::io::stdout().as_raw_handle() as HANDLE, output)?;
+        }
+    }
+    RAW_MODE_ACTIVE.store(false, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Turn on mouse reporting in SGR format (button presses and drags)
+pub const MOUSE_REPORTING_ON: &[u8] = b"\x1b[?1000h\x1b[?1002h\x1b[?1006h";
+/// Turn off all mouse reporting modes
+pub const MOUSE_REPORTING_OFF: &[u8] = b"\x1b[?1000l\x1b[?1002l\x1b[?1003l\x1b[?1006l";
+
+/// Escape sequence that asks the terminal to put `data` on the clipboard
+/// (OSC 52). Works over SSH, as it is the local terminal that acts on it.
+pub fn osc52_copy(data: &[u8]) -> Vec<u8> {
+    let mut out = b"\x1b]52;c;".to_vec();
+    base64_encode(data, &mut out);
+    out.push(0x07);
+    out
+}
+
+fn base64_encode(data: &[u8], out: &mut Vec<u8>) {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    for chunk in data.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize]);
+            } else {
+                out.push(b'=');
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_base64() {
+        for (data, expected) in [
+            (&b""[..], ""),
+            (b"f", "Zg=="),
+            (b"fo", "Zm8="),
+            (b"foo", "Zm9v"),
+            (b"foob", "Zm9vYg=="),
+            (b"\xff\x00\xfe", "/wD+"),
+        ] {
+            let mut out = Vec::new();
+            base64_encode(data, &mut out);
+            assert_eq!(String::from_utf8(out).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_osc52_copy() {
+        assert_eq!(osc52_copy(b"hi\n"), b"\x1b]52;c;aGkK\x07");
+    }
+}
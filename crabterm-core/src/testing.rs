@@ -0,0 +1,153 @@
+//! In-process test harness (`testing` feature): an [`IoHub`] driven turn
+//! by turn from the test's own thread, with virtual devices and scripted
+//! clients, and a clock that the test moves forward. Timeouts, keepalives
+//! and keybind timeouts are tested without waiting for them, and without
+//! spawning crabterm or opening ports.
+//!
+//! ```no_run
+//! use crabterm_core::IoHub;
+//! use crabterm_core::testing::{Harness, virtual_device};
+//! use std::time::Duration;
+//!
+//! let (device, mut board) = virtual_device("/dev/ttyUSB0").unwrap();
+//! let hub = IoHub::builder(device)
+//!     .keepalive(b"\r".to_vec(), Duration::from_secs(30))
+//!     .build()
+//!     .unwrap();
+//! let mut harness = Harness::new(hub);
+//! harness.step().unwrap();
+//! harness.advance(Duration::from_secs(30)).unwrap();
+//! assert_eq!(board.recv(), b"\r");
+//! ```
+//!
+//! The clock is that of the thread (see [`crate::clock`]), so tests may
+//! run in parallel.
+
+use std::io::{ErrorKind, Read, Result, Write};
+use std::time::Duration;
+
+use crate::clock;
+use crate::hub::IoHub;
+use crate::io::LoopDevice;
+use crate::io::listener::ListenerRole;
+use crate::io::loop_device::PeerStream;
+use crate::keybind::KeybindConfig;
+use crate::poll::Events;
+use crate::traits::IoInstance;
+
+/// Turns of one step at most, in case the hub keeps finding events
+const MAX_TURNS: usize = 64;
+
+/// The far end of a virtual device or client, played by the test. Nothing
+/// blocks: what was sent to it so far is taken with [`Peer::recv`].
+pub struct Peer {
+    stream: PeerStream,
+}
+
+impl Peer {
+    fn new(stream: PeerStream) -> Result<Self> {
+        stream.set_nonblocking(true)?;
+        Ok(Peer { stream })
+    }
+
+    /// Send `data`, e.g. output of the board or input of a user; the hub
+    /// reads it on the next step.
+    pub fn send(&mut self, data: &[u8]) {
+        self.stream
+            .write_all(data)
+            .expect("peer: socket buffer full, step the hub first");
+    }
+
+    /// Everything the hub wrote to this end since the last call.
+    pub fn recv(&mut self) -> Vec<u8> {
+        let mut received = Vec::new();
+        let mut buf = [0u8; 4096];
+        loop {
+            match self.stream.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => received.extend_from_slice(&buf[..n]),
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(_) => break,
+            }
+        }
+        received
+    }
+}
+
+/// A device named `name` for the hub, and the board at the other end.
+pub fn virtual_device(name: &str) -> Result<(Box<dyn IoInstance>, Peer)> {
+    let (device, board) = LoopDevice::with_peer()?;
+    Ok((Box::new(device.with_name(name)), Peer::new(board)?))
+}
+
+/// Drives an [`IoHub`] from the test: [`Harness::step`] handles what is
+/// ready without waiting, [`Harness::advance`] moves the clock first.
+pub struct Harness {
+    hub: IoHub,
+    events: Events,
+}
+
+impl Harness {
+    pub fn new(hub: IoHub) -> Self {
+        Harness {
+            hub,
+            events: Events::with_capacity(128),
+        }
+    }
+
+    pub fn hub(&mut self) -> &mut IoHub {
+        &mut self.hub
+    }
+
+    /// Add a local client, like the console.
+    pub fn client(&mut self) -> Result<Peer> {
+        let (client, user) = LoopDevice::with_peer()?;
+        self.hub.add(Box::new(client.with_name("client")))?;
+        Peer::new(user)
+    }
+
+    /// Add a remote client, like one of a TCP server with `role`.
+    pub fn remote_client(&mut self, role: ListenerRole) -> Result<Peer> {
+        let (client, user) = LoopDevice::with_peer()?;
+        self.hub
+            .add_tcp_client(Box::new(client.with_name("client")), None, None, role)?;
+        Peer::new(user)
+    }
+
+    /// Add a remote client with keybinds, like one of a TCP server with
+    /// client keybinds.
+    pub fn keybind_client(&mut self, config: KeybindConfig) -> Result<Peer> {
+        let (client, user) = LoopDevice::with_peer()?;
+        self.hub.add_tcp_client(
+            Box::new(client.with_name("client")),
+            None,
+            Some(config),
+            ListenerRole::default(),
+        )?;
+        Peer::new(user)
+    }
+
+    /// Handle what the peers sent and the timers that are due, until the
+    /// hub finds nothing more to do. True when the hub has quit; Err when it
+    /// quit with an error, as `run()` returns it.
+    pub fn step(&mut self) -> Result<bool> {
+        for _ in 0..MAX_TURNS {
+            if self.hub.turn(&mut self.events, false)? {
+                return match self.hub.take_exit_error() {
+                    Some(e) => Err(e),
+                    None => Ok(true),
+                };
+            }
+            if self.events.is_empty() {
+                break;
+            }
+        }
+        Ok(false)
+    }
+
+    /// Move the clock forward by `by`, then step.
+    pub fn advance(&mut self, by: Duration) -> Result<bool> {
+        clock::advance(by);
+        self.step()
+    }
+}
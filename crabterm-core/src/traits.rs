@@ -0,0 +1,244 @@
+use mio::Token;
+use std::io::{IoSlice, Result};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::iofilter::FilterChain;
+use crate::keybind::Action;
+use crate::poll::Poll;
+
+pub const TOKEN_HTTP_SERVER: Token = Token(0);
+pub const TOKEN_MDNS: Token = Token(1);
+pub const TOKEN_SIGNAL: Token = Token(2);
+pub const TOKEN_MONITOR_SERVER: Token = Token(3);
+pub const TOKEN_METRICS_SERVER: Token = Token(4);
+pub const TOKEN_SESSION_SERVER: Token = Token(5);
+pub const TOKEN_DYNAMIC_START: Token = Token(6);
+pub const TOKEN_MONITOR_CLIENT_START: Token = Token(1000);
+pub const TOKEN_METRICS_CLIENT_START: Token = Token(2000);
+pub const TOKEN_DEVICE_START: Token = Token(3000);
+pub const TOKEN_DEVICE_SERVER_START: Token = Token(4000);
+pub const TOKEN_LISTENER_START: Token = Token(5000);
+pub const TOKEN_HTTP_CLIENT_START: Token = Token(6000);
+pub const TOKEN_CTL_SERVER: Token = Token(7000);
+pub const TOKEN_CTL_CLIENT_START: Token = Token(7001);
+pub const TOKEN_HOTPLUG: Token = Token(8000);
+
+/// Result of an I/O operation
+#[derive(Debug)]
+pub enum IoResult {
+    /// Data, shared so it can be passed on to several clients without copying
+    Data(Arc<[u8]>),
+    /// Action to be performed by the hub
+    Action(Action),
+    /// Nothing
+    None,
+}
+
+/// A device or a client attached to the hub.
+///
+/// Implementations are non-blocking: sources are registered with the hub's
+/// (edge-triggered) mio poll instance in `connect()`, and `read()` is called
+/// until it returns `IoResult::None`.
+pub trait IoInstance {
+    /// Open the connection (if needed) and register with `poll` using `token`.
+    /// For devices this is retried by the hub until it succeeds; WouldBlock
+    /// means the connection is still in progress.
+    fn connect(&mut self, poll: &mut Poll, token: Token) -> Result<()>;
+
+    fn connected(&self) -> bool;
+
+    /// True when the connection has failed and the hub should call
+    /// `disconnect()` before trying to connect again.
+    fn disconnect_needed(&self) -> bool {
+        false
+    }
+
+    /// True for a device node that comes and goes with the hardware, e.g.
+    /// a USB serial adapter: while it is not connected, the hub tries again
+    /// when a node appears rather than all the time.
+    fn hotplug(&self) -> bool {
+        false
+    }
+
+    /// Deregister from `poll` and drop the connection.
+    fn disconnect(&mut self, poll: &mut Poll);
+
+    /// Read available data. Returns `IoResult::None` when nothing more is
+    /// available (WouldBlock).
+    fn read(&mut self) -> Result<IoResult>;
+
+    /// Write from `buf`. Returns `IoResult::Data` with the bytes written.
+    fn write(&mut self, buf: &[u8]) -> Result<IoResult>;
+
+    fn flush(&mut self);
+
+    /// Human readable address used in logs and announcements.
+    fn addr_as_string(&self) -> String;
+
+    /// Who is at the other end, e.g. the address of a TCP client, for
+    /// audit records. Defaults to [`Self::addr_as_string`].
+    fn peer_as_string(&self) -> String {
+        self.addr_as_string()
+    }
+
+    /// Return an announcement message to be sent to clients when the device
+    /// connects. Default is "address: Connected".
+    fn connected_announcement(&self) -> Option<String> {
+        Some(format!("{}: Connected", self.addr_as_string()))
+    }
+
+    /// A status message to announce to clients now, e.g. the rate found by
+    /// baudrate detection. Polled by the hub after reads and ticks.
+    fn take_announcement(&mut self) -> Option<String> {
+        None
+    }
+
+    /// Called after events and when `next_tick()` is due, to handle
+    /// timeouts etc. Data returned by the tick of a device is handled like
+    /// data read from it.
+    fn tick(&mut self) -> Result<IoResult> {
+        Ok(IoResult::None)
+    }
+
+    /// When `tick()` next has something to do. The hub sleeps until the
+    /// earliest of these, or until the next event if there are none.
+    fn next_tick(&self) -> Option<Instant> {
+        None
+    }
+
+    /// Write all bytes. Returns the number of bytes actually written.
+    /// A short write indicates backpressure (e.g. WouldBlock).
+    fn write_all(&mut self, buf: &[u8]) -> usize {
+        let mut written = 0;
+        while written < buf.len() {
+            match self.write(&buf[written..]) {
+                Ok(IoResult::Data(d)) if !d.is_empty() => written += d.len(),
+                // Break on empty data (socket can't accept more) or error
+                // to avoid spinning when no progress is made.
+                _ => break,
+            }
+        }
+        self.flush();
+        written
+    }
+
+    /// Write from several buffers in one go where the instance can, like
+    /// `std::io::Write::write_vectored`. Returns the number of bytes written;
+    /// the default writes (part of) the first non-empty buffer.
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> Result<usize> {
+        match bufs.iter().find(|b| !b.is_empty()) {
+            Some(buf) => match self.write(buf)? {
+                IoResult::Data(d) => Ok(d.len()),
+                _ => Ok(0),
+            },
+            None => Ok(0),
+        }
+    }
+
+    /// `write_all()` for several buffers. Returns the number of bytes
+    /// written, a short write indicates backpressure.
+    fn write_all_vectored(&mut self, mut bufs: &mut [IoSlice<'_>]) -> usize {
+        let mut written = 0;
+        while !bufs.is_empty() {
+            match self.write_vectored(bufs) {
+                Ok(n) if n > 0 => {
+                    written += n;
+                    IoSlice::advance_slices(&mut bufs, n);
+                }
+                _ => break,
+            }
+        }
+        self.flush();
+        written
+    }
+
+    /// Write data that is also written to other instances. Returns the
+    /// number of bytes taken, like `write_all()`. Instances that keep output
+    /// for later can hold on to `buf` rather than copy it.
+    fn write_shared(&mut self, buf: &Arc<[u8]>) -> usize {
+        self.write_all(buf)
+    }
+
+    /// Write an announcement message using a template.
+    /// %m -> message, %s -> source, %t -> time(hh:mm:ss), %d -> date(yyyy-mm-dd), %% -> %
+    fn write_announce(&mut self, template: &str, source: &str, msg: &str) {
+        let expanded = crate::announce::expand_template(template, source, msg);
+        self.write_all(expanded.as_bytes());
+        self.flush();
+    }
+
+    /// Write a state change of a device, e.g. that it connected. The default
+    /// writes it as an announcement.
+    fn write_status(&mut self, template: &str, source: &str, msg: &str) {
+        self.write_announce(template, source, msg);
+    }
+
+    /// The line speed, for devices that have one.
+    fn baudrate(&self) -> Option<u32> {
+        None
+    }
+
+    /// Change the line speed. Only meaningful for serial devices; the default
+    /// reports that it is not supported.
+    fn set_baudrate(&mut self, _baudrate: u32) -> Result<()> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "baudrate not supported by this device",
+        ))
+    }
+
+    /// Change the quarantine after (re)connecting, see
+    /// [`SerialDevice::quarantine`](crate::io::SerialDevice::quarantine);
+    /// the default reports that it is not supported.
+    fn set_quarantine(&mut self, _duration: Duration) -> Result<()> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "quarantine not supported by this device",
+        ))
+    }
+
+    /// True while a client holds more unsent output than it may; the hub
+    /// stops reading from the devices until it has caught up.
+    fn write_backlogged(&self) -> bool {
+        false
+    }
+
+    /// True while output is buffered that the other end has not taken yet;
+    /// the hub waits for it when quitting.
+    fn write_pending(&self) -> bool {
+        false
+    }
+
+    /// Bytes of output buffered for the other end, for `debug-dump`
+    fn write_queued(&self) -> usize {
+        0
+    }
+
+    /// The filters applied by the instance itself, e.g. those of the
+    /// console, for `debug-dump`
+    fn filters(&self) -> Option<&FilterChain> {
+        None
+    }
+
+    /// The process is about to stop (Ctrl+z, SIGTSTP): put the local
+    /// terminal back as the shell expects it, until [`IoInstance::redraw`]
+    /// once continued. Default is a no-op.
+    fn suspend(&mut self) {}
+
+    /// Draw again what is shown on the local terminal (prompt, status line),
+    /// e.g. after being continued from a suspend. Default is a no-op.
+    fn redraw(&mut self) {}
+
+    /// The local terminal now has `cols` x `rows`, called on connect and when
+    /// the window is resized. For devices that have a window size (a PTY,
+    /// telnet NAWS, SSH); the default ignores it.
+    fn resize(&mut self, _cols: u16, _rows: u16) {}
+
+    /// Request WRITABLE interest from the poll loop so that the caller is
+    /// notified when the underlying socket can accept data again.
+    /// Default is a no-op for devices that don't support this.
+    fn set_writable_interest(&mut self, _poll: &mut Poll, _writable: bool) -> Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,78 @@
+//! Silence watchdog: what to do when a device has produced no output for a
+//! while (`--silence-timeout`), e.g. to catch hung boards in soak tests.
+
+use log::{error, info};
+use std::process::Command;
+
+/// Action of the silence watchdog
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SilenceAction {
+    /// Tell the clients
+    Announce,
+    /// Run a shell command, with the device in `CRABTERM_DEVICE`
+    Exec(String),
+    /// Close the device and open it again
+    Reconnect,
+    /// End crabterm with an error
+    Exit,
+}
+
+impl SilenceAction {
+    /// Parse `announce`, `reconnect`, `exit` or `exec`; the latter runs
+    /// `command`.
+    pub fn parse(name: &str, command: Option<&str>) -> Result<Self, String> {
+        match name {
+            "announce" => Ok(SilenceAction::Announce),
+            "reconnect" => Ok(SilenceAction::Reconnect),
+            "exit" => Ok(SilenceAction::Exit),
+            "exec" => match command {
+                Some(c) if !c.trim().is_empty() => Ok(SilenceAction::Exec(c.to_string())),
+                _ => Err("silence-action exec requires silence-exec".to_string()),
+            },
+            _ => Err(format!(
+                "Invalid silence-action: {} (expected announce, exec, reconnect or exit)",
+                name
+            )),
+        }
+    }
+}
+
+/// Run `command` with `sh -c` without waiting for it. A thread reaps it and
+/// logs how it ended.
+pub fn exec(command: &str, device: &str) {
+    let child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .env("CRABTERM_DEVICE", device)
+        .spawn();
+    match child {
+        Ok(mut child) => {
+            let command = command.to_string();
+            std::thread::spawn(move || match child.wait() {
+                Ok(status) => info!("silence-exec {:?}: {}", command, status),
+                Err(e) => error!("silence-exec {:?}: {}", command, e),
+            });
+        }
+        Err(e) => error!("silence-exec {:?}: {}", command, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            SilenceAction::parse("announce", None),
+            Ok(SilenceAction::Announce)
+        );
+        assert_eq!(SilenceAction::parse("exit", None), Ok(SilenceAction::Exit));
+        assert_eq!(
+            SilenceAction::parse("exec", Some("reset-board")),
+            Ok(SilenceAction::Exec("reset-board".to_string()))
+        );
+        assert!(SilenceAction::parse("exec", None).is_err());
+        assert!(SilenceAction::parse("reboot", None).is_err());
+    }
+}
@@ -0,0 +1,115 @@
+//! Write lock of a board (`--write-lock`): one remote client at a time may
+//! type, the others are read-only and queued for the lock, as with
+//! conserver. The lock passes to the first in the queue when its holder
+//! disconnects or gives it up with `write-release`.
+
+use std::collections::VecDeque;
+
+use mio::Token;
+
+/// The holder of a write lock and the clients waiting for it
+#[derive(Debug, Default)]
+pub struct WriteQueue {
+    holder: Option<Token>,
+    waiting: VecDeque<Token>,
+}
+
+impl WriteQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn holder(&self) -> Option<Token> {
+        self.holder
+    }
+
+    pub fn waiting(&self) -> impl Iterator<Item = Token> + '_ {
+        self.waiting.iter().copied()
+    }
+
+    /// Where `token` is: Some(0) for the holder, Some(n) for the nth in the
+    /// queue, None when it is in neither.
+    pub fn position(&self, token: Token) -> Option<usize> {
+        if self.holder == Some(token) {
+            return Some(0);
+        }
+        self.waiting.iter().position(|&t| t == token).map(|i| i + 1)
+    }
+
+    /// Add `token`: it gets the lock if it is free, else it is queued.
+    /// Returns its position.
+    pub fn join(&mut self, token: Token) -> usize {
+        if let Some(position) = self.position(token) {
+            return position;
+        }
+        if self.holder.is_none() {
+            self.holder = Some(token);
+            return 0;
+        }
+        self.waiting.push_back(token);
+        self.waiting.len()
+    }
+
+    /// Remove `token`, e.g. when it disconnects. Returns the new holder if
+    /// the lock passed on.
+    pub fn leave(&mut self, token: Token) -> Option<Token> {
+        if self.holder != Some(token) {
+            self.waiting.retain(|&t| t != token);
+            return None;
+        }
+        self.holder = self.waiting.pop_front();
+        self.holder
+    }
+
+    /// The holder `token` gives up the lock to the first in the queue and
+    /// joins its end. Returns the new holder; None when `token` does not
+    /// hold the lock or nobody is waiting.
+    pub fn release(&mut self, token: Token) -> Option<Token> {
+        if self.holder != Some(token) || self.waiting.is_empty() {
+            return None;
+        }
+        let next = self.leave(token);
+        self.waiting.push_back(token);
+        next
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_queue() {
+        let mut q = WriteQueue::new();
+        assert_eq!(q.join(Token(1)), 0);
+        assert_eq!(q.join(Token(2)), 1);
+        assert_eq!(q.join(Token(3)), 2);
+        assert_eq!(q.join(Token(2)), 1);
+
+        // Somebody waiting leaves, the holder stays
+        assert_eq!(q.leave(Token(2)), None);
+        assert_eq!(q.position(Token(3)), Some(1));
+
+        // The holder leaves, the next gets the lock
+        assert_eq!(q.leave(Token(1)), Some(Token(3)));
+        assert_eq!(q.holder(), Some(Token(3)));
+        assert_eq!(q.position(Token(1)), None);
+        assert_eq!(q.leave(Token(3)), None);
+        assert_eq!(q.holder(), None);
+    }
+
+    #[test]
+    fn test_release() {
+        let mut q = WriteQueue::new();
+        q.join(Token(1));
+        // Nobody to give it to
+        assert_eq!(q.release(Token(1)), None);
+        assert_eq!(q.holder(), Some(Token(1)));
+
+        q.join(Token(2));
+        q.join(Token(3));
+        assert_eq!(q.release(Token(2)), None);
+        assert_eq!(q.release(Token(1)), Some(Token(2)));
+        assert_eq!(q.waiting().collect::<Vec<_>>(), vec![Token(3), Token(1)]);
+    }
+}
@@ -0,0 +1,1138 @@
+//! Drive the library API in-process, using LoopDevice pairs instead of PTYs.
+//! What depends on time is in harness.rs, on the mock clock.
+
+#![cfg(unix)]
+
+use crabterm_core::capture::{Capture, Format};
+use crabterm_core::control;
+use crabterm_core::ctl::{self, CtlServer};
+use crabterm_core::http::HttpServer;
+use crabterm_core::hub::LogChange;
+use crabterm_core::io::listener::{ListenerRole, Newline};
+use crabterm_core::io::tcp_server::{ClientPolicy, SlowClientPolicy};
+use crabterm_core::io::{LoopDevice, RemoteDevice, TcpDevice, TcpServer};
+use crabterm_core::iofilter::charmap;
+use crabterm_core::keybind::KeybindConfig;
+use crabterm_core::keybind::config::SettingValue;
+use crabterm_core::power::PowerControl;
+use crabterm_core::{FilterChain, IoHub, IoHubBuilder};
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::os::unix::net::UnixStream;
+use std::sync::{Arc, Mutex, mpsc};
+use std::time::Duration;
+
+fn read_until(stream: &mut impl Read, expected: &[u8]) -> Vec<u8> {
+    let mut received = Vec::new();
+    let mut buf = [0u8; 256];
+    while !received.ends_with(expected) {
+        match stream.read(&mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => received.extend_from_slice(&buf[..n]),
+        }
+    }
+    received
+}
+
+fn set_timeouts(streams: &[&UnixStream]) {
+    for s in streams {
+        s.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+    }
+}
+
+/// Run the hub that `setup` makes on a thread of its own, as the hub is not
+/// Send: what `setup` returns besides it, and the result of the run.
+fn spawn_hub<T: Send + 'static>(
+    setup: impl FnOnce() -> (IoHub, T) + Send + 'static,
+) -> (T, mpsc::Receiver<std::io::Result<()>>) {
+    let (tx, rx) = mpsc::channel();
+    let (done_tx, done_rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        let (mut hub, peers) = setup();
+        tx.send(peers).unwrap();
+        let _ = done_tx.send(hub.run());
+    });
+    (rx.recv().unwrap(), done_rx)
+}
+
+/// Run the hub that `build` makes around a loop device: the board end.
+fn hub_with_board(build: impl FnOnce(LoopDevice) -> IoHubBuilder + Send + 'static) -> UnixStream {
+    let (board, _) = spawn_hub(move || {
+        let (device, board) = LoopDevice::with_peer().unwrap();
+        (build(device).build().unwrap(), board)
+    });
+    set_timeouts(&[&board]);
+    board
+}
+
+/// Like [`hub_with_board`], with a loop client attached: the board end and
+/// the user end.
+fn hub_with_client(
+    build: impl FnOnce(LoopDevice) -> IoHubBuilder + Send + 'static,
+) -> (UnixStream, UnixStream) {
+    let ((board, user), _) = spawn_hub(move || {
+        let (device, board) = LoopDevice::with_peer().unwrap();
+        let (client, user) = LoopDevice::with_peer().unwrap();
+        let mut hub = build(device).build().unwrap();
+        hub.add(Box::new(client)).unwrap();
+        (hub, (board, user))
+    });
+    set_timeouts(&[&board, &user]);
+    (board, user)
+}
+
+/// A TCP server on a free port of localhost, and the port.
+fn local_server() -> (TcpServer, u16) {
+    let server = TcpServer::new_with_addr("127.0.0.1:0".parse().unwrap()).unwrap();
+    let port = server.local_addr().unwrap().port();
+    (server, port)
+}
+
+fn connect(port: u16) -> TcpStream {
+    let stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(2)))
+        .unwrap();
+    stream
+}
+
+fn ctl_path(name: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!(
+        "crabterm-embed-{}.{}.ctl",
+        std::process::id(),
+        name
+    ))
+}
+
+#[test]
+fn test_hub_with_loop_devices() {
+    let (mut board, mut user) =
+        hub_with_client(|device| IoHub::builder(Box::new(device)).announce(false));
+
+    board.write_all(b"U-Boot 2024.01\r\n").unwrap();
+    assert_eq!(read_until(&mut user, b"\r\n"), b"U-Boot 2024.01\r\n");
+
+    user.write_all(b"boot\r").unwrap();
+    assert_eq!(read_until(&mut board, b"\r"), b"boot\r");
+}
+
+#[cfg(feature = "io-uring")]
+#[test]
+fn test_hub_io_uring() {
+    use crabterm_core::poll::Backend;
+
+    let (server, port) = local_server();
+    let mut board = hub_with_board(move |device| {
+        IoHub::builder(Box::new(device))
+            .server(server)
+            .announce(false)
+            .backend(Backend::IoUring)
+    });
+    let mut user = connect(port);
+    std::thread::sleep(Duration::from_millis(300));
+
+    board.write_all(b"U-Boot 2024.01\r\n").unwrap();
+    assert_eq!(read_until(&mut user, b"\r\n"), b"U-Boot 2024.01\r\n");
+
+    user.write_all(b"boot\r").unwrap();
+    assert_eq!(read_until(&mut board, b"\r"), b"boot\r");
+}
+
+#[test]
+fn test_hub_with_multiple_devices() {
+    let ((mut board_a, mut board_b, mut user), _) = spawn_hub(|| {
+        let (dev_a, board_a) = LoopDevice::with_peer().unwrap();
+        let (dev_b, board_b) = LoopDevice::with_peer().unwrap();
+        let (client, user) = LoopDevice::with_peer().unwrap();
+        let mut hub = IoHub::builder(Box::new(dev_a.with_name("/dev/ttyUSB0")))
+            .device(Box::new(dev_b.with_name("/dev/ttyUSB1")))
+            .announce(false)
+            .build()
+            .unwrap();
+        hub.add(Box::new(client)).unwrap();
+        (hub, (board_a, board_b, user))
+    });
+    set_timeouts(&[&board_a, &board_b, &user]);
+
+    board_a.write_all(b"from a\r\n").unwrap();
+    assert_eq!(read_until(&mut user, b"\r\n"), b"[usb0] from a\r\n");
+    board_b.write_all(b"from b\r\n").unwrap();
+    assert_eq!(read_until(&mut user, b"\r\n"), b"[usb1] from b\r\n");
+
+    // Input goes to the first device until switched
+    user.write_all(b"hello\r").unwrap();
+    assert_eq!(read_until(&mut board_a, b"\r"), b"hello\r");
+}
+
+#[test]
+fn test_hub_channels() {
+    let path = ctl_path("channels");
+    let ctl = CtlServer::new(&path, 7001).unwrap();
+    let ((mut vm_serial, mut vm_monitor, mut user), _) = spawn_hub(|| {
+        let (serial, vm_serial) = LoopDevice::with_peer().unwrap();
+        let (monitor, vm_monitor) = LoopDevice::with_peer().unwrap();
+        let (client, user) = LoopDevice::with_peer().unwrap();
+        let mut hub = IoHub::builder(Box::new(serial.with_name("qemu:unix:/tmp/vm.sock")))
+            .channel(
+                0,
+                "monitor",
+                Box::new(monitor.with_name("qemu:unix:/tmp/mon.sock")),
+            )
+            .ctl(ctl)
+            .announce(false)
+            .build()
+            .unwrap();
+        hub.add(Box::new(client)).unwrap();
+        (hub, (vm_serial, vm_monitor, user))
+    });
+    set_timeouts(&[&vm_serial, &vm_monitor, &user]);
+
+    // The monitor is not shown until switched to
+    vm_monitor.write_all(b"(qemu) ").unwrap();
+    vm_serial.write_all(b"login: ").unwrap();
+    assert_eq!(read_until(&mut user, b"login: "), b"[vm.sock] login: ");
+
+    assert_eq!(
+        ctl::send(&path, "channel next").unwrap().unwrap(),
+        vec!["Channel [monitor] qemu:unix:/tmp/mon.sock"]
+    );
+    vm_serial.write_all(b"root\r\n").unwrap();
+    vm_monitor.write_all(b"\r\n(qemu) ").unwrap();
+    assert_eq!(read_until(&mut user, b"(qemu) "), b"\r\n[monitor] (qemu) ");
+    user.write_all(b"info status\r").unwrap();
+    assert_eq!(read_until(&mut vm_monitor, b"\r"), b"info status\r");
+
+    // And back
+    ctl::send(&path, "channel next").unwrap().unwrap();
+    user.write_all(b"ls\r").unwrap();
+    assert_eq!(read_until(&mut vm_serial, b"\r"), b"ls\r");
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn test_hub_with_device_servers() {
+    let (server_a, port_a) = local_server();
+    let (server_b, port_b) = local_server();
+    let ((mut board_a, mut board_b), _) = spawn_hub(|| {
+        let (dev_a, board_a) = LoopDevice::with_peer().unwrap();
+        let (dev_b, board_b) = LoopDevice::with_peer().unwrap();
+        let hub = IoHub::builder(Box::new(dev_a.with_name("/dev/ttyUSB0")))
+            .device(Box::new(dev_b.with_name("/dev/ttyUSB1")))
+            .device_server(0, server_a)
+            .device_server(1, server_b)
+            .announce(false)
+            .build()
+            .unwrap();
+        (hub, (board_a, board_b))
+    });
+    set_timeouts(&[&board_a, &board_b]);
+    let mut user_a = connect(port_a);
+    let mut user_b = connect(port_b);
+    // Let the hub accept both clients before the devices talk
+    std::thread::sleep(Duration::from_millis(300));
+
+    // Each client only sees its own device, without line prefixes
+    board_a.write_all(b"from a\r\n").unwrap();
+    board_b.write_all(b"from b\r\n").unwrap();
+    assert_eq!(read_until(&mut user_a, b"\r\n"), b"from a\r\n");
+    assert_eq!(read_until(&mut user_b, b"\r\n"), b"from b\r\n");
+
+    // Input goes to the client's device, not the active one
+    user_b.write_all(b"to b\r").unwrap();
+    assert_eq!(read_until(&mut board_b, b"\r"), b"to b\r");
+    user_a.write_all(b"to a\r").unwrap();
+    assert_eq!(read_until(&mut board_a, b"\r"), b"to a\r");
+}
+
+#[test]
+fn test_hub_with_client_filters() {
+    let (server, port) = local_server();
+    let (mut board, mut local) = hub_with_client(move |device| {
+        IoHub::builder(Box::new(device))
+            .server(server)
+            .client_filters(|| {
+                let mut settings = HashMap::new();
+                settings.insert(
+                    charmap::SETTING_IMAP.to_string(),
+                    SettingValue::String("crlf".to_string()),
+                );
+                FilterChain::new(&settings)
+            })
+            .announce(false)
+    });
+    let mut remote = connect(port);
+    std::thread::sleep(Duration::from_millis(300));
+
+    // TCP clients get their own filters, other clients the raw output
+    board.write_all(b"boot\r\n").unwrap();
+    assert_eq!(read_until(&mut remote, b"\n\n"), b"boot\n\n");
+    assert_eq!(read_until(&mut local, b"\r\n"), b"boot\r\n");
+}
+
+#[test]
+fn test_hub_remote_commands() {
+    let (server, port) = local_server();
+    let mut board = hub_with_board(move |device| {
+        IoHub::builder(Box::new(device))
+            .server(server)
+            .announce(false)
+            .announce_template("[%m]\r\n")
+    });
+    let mut remote = connect(port);
+
+    // Commands are taken out of the data on the way to the device
+    let mut input = b"ab".to_vec();
+    input.extend(control::encode("stats"));
+    input.extend(b"cd");
+    remote.write_all(&input).unwrap();
+    assert_eq!(read_until(&mut board, b"cd"), b"abcd");
+    let reply = read_until(&mut remote, b"]\r\n");
+    assert!(reply.starts_with(b"[Session "), "{:?}", reply);
+
+    remote.write_all(&control::encode("baud 9600")).unwrap();
+    assert_eq!(
+        read_until(&mut remote, b"]\r\n"),
+        b"[baud 9600: not allowed remotely]\r\n"
+    );
+
+    // Filters are toggled for this client only
+    remote
+        .write_all(&control::encode("filter toggle timestamp"))
+        .unwrap();
+    assert_eq!(
+        read_until(&mut remote, b"]\r\n"),
+        b"[Filter timestamp on]\r\n"
+    );
+    board.write_all(b"boot\r\n").unwrap();
+    let out = read_until(&mut remote, b"boot\r\n");
+    assert!(out.len() > b"boot\r\n".len(), "{:?}", out);
+    remote
+        .write_all(&control::encode("filter toggle timestamp"))
+        .unwrap();
+    assert_eq!(
+        read_until(&mut remote, b"]\r\n"),
+        b"[Filter timestamp off]\r\n"
+    );
+
+    // And the charmap, to a preset
+    remote
+        .write_all(&control::encode("charmap preset unix"))
+        .unwrap();
+    assert_eq!(read_until(&mut remote, b"]\r\n"), b"[Charmap unix]\r\n");
+    board.write_all(b"ls\n").unwrap();
+    assert_eq!(read_until(&mut remote, b"ls\r\n"), b"ls\r\n");
+    remote.write_all(&control::encode("charmap off")).unwrap();
+    assert_eq!(read_until(&mut remote, b"]\r\n"), b"[Charmap off]\r\n");
+}
+
+#[test]
+fn test_hub_framed_announcements() {
+    let (server, port) = local_server();
+    let mut board = hub_with_board(move |device| {
+        IoHub::builder(Box::new(device))
+            .server(server)
+            .announce_template("[%m]\r\n")
+    });
+
+    // Like `crabterm connect`, with its own template
+    let (mut user, _) = spawn_hub(move || {
+        let addr = ([127, 0, 0, 1], port).into();
+        let remote = RemoteDevice::new(Box::new(TcpDevice::new(addr).unwrap()));
+        let (client, user) = LoopDevice::with_peer().unwrap();
+        let mut hub = IoHub::builder(Box::new(remote))
+            .announce_template("<%m>\r\n")
+            .build()
+            .unwrap();
+        hub.add(Box::new(client)).unwrap();
+        (hub, user)
+    });
+    set_timeouts(&[&user]);
+
+    // The state before the answer is data, in the template of the server
+    let out = read_until(&mut user, b"]\r\n");
+    assert!(out.ends_with(b"[Loop: Connected]\r\n"), "{:?}", out);
+
+    // Announcements come apart from the data, in the local template
+    user.write_all(&control::encode("stats")).unwrap();
+    std::thread::sleep(Duration::from_millis(100));
+    board.write_all(b"boot\r\n").unwrap();
+    let out = read_until(&mut user, b"boot\r\n");
+    assert!(out.starts_with(b"<Session "), "{:?}", out);
+    assert!(out.ends_with(b">\r\nboot\r\n"), "{:?}", out);
+    assert!(!out.contains(&b'['), "{:?}", out);
+
+    // A state change of the device on the server
+    drop(board);
+    let out = read_until(&mut user, b">\r\n");
+    assert!(out.starts_with(b"<Loop: EOF>\r\n"), "{:?}", out);
+}
+
+#[test]
+fn test_hub_client_keybinds() {
+    let (mut server, port) = local_server();
+    server.set_client_keybinds(KeybindConfig::default());
+    let mut board = hub_with_board(move |device| {
+        IoHub::builder(Box::new(device))
+            .server(server)
+            .announce(false)
+            .announce_template("[%m]\r\n")
+    });
+    let mut remote = connect(port);
+
+    // Ctrl+a Ctrl+a sends a literal Ctrl+a
+    remote.write_all(b"ab\x01\x01cd").unwrap();
+    assert_eq!(read_until(&mut board, b"cd"), b"ab\x01cd");
+
+    remote.write_all(b"\x01t").unwrap();
+    assert_eq!(
+        read_until(&mut remote, b"]\r\n"),
+        b"[Filter timestamp on]\r\n"
+    );
+
+    // Actions on the hub as a whole are refused
+    remote.write_all(b"\x01d").unwrap();
+    assert_eq!(
+        read_until(&mut remote, b"]\r\n"),
+        b"[device-next: not allowed remotely]\r\n"
+    );
+
+    // Quit disconnects the client only
+    remote.write_all(b"\x11").unwrap();
+    assert_eq!(read_until(&mut remote, b"]\r\n"), b"[Detached]\r\n");
+    let mut buf = [0u8; 16];
+    assert_eq!(remote.read(&mut buf).unwrap(), 0);
+
+    let mut other = connect(port);
+    std::thread::sleep(Duration::from_millis(300));
+    other.write_all(b"ef").unwrap();
+    assert_eq!(read_until(&mut board, b"ef"), b"ef");
+}
+
+#[test]
+fn test_hub_audit_input() {
+    let path = std::env::temp_dir().join(format!("crabterm-audit-{}.log", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let capture_path = path.clone();
+    let (server, port) = local_server();
+    let (mut board, _) = spawn_hub(move || {
+        let (device, board) = LoopDevice::with_peer().unwrap();
+        let mut hub = IoHub::builder(Box::new(device))
+            .server(server)
+            .announce(false)
+            .audit_input(true)
+            .build()
+            .unwrap();
+        hub.set_capture(Some(Capture::new(&capture_path).unwrap()));
+        (hub, board)
+    });
+    set_timeouts(&[&board]);
+    let mut remote = connect(port);
+    let addr = remote.local_addr().unwrap().to_string();
+
+    board.write_all(b"login: ").unwrap();
+    assert_eq!(read_until(&mut remote, b": "), b"login: ");
+    remote.write_all(b"reboot\r").unwrap();
+    assert_eq!(read_until(&mut board, b"\r"), b"reboot\r");
+    board.write_all(b"bye\r\n").unwrap();
+    assert_eq!(read_until(&mut remote, b"\r\n"), b"bye\r\n");
+
+    // The input is recorded on a line of its own, output goes on after it
+    let captured = std::fs::read_to_string(&path).unwrap();
+    let _ = std::fs::remove_file(&path);
+    let lines: Vec<&str> = captured.lines().collect();
+    assert_eq!(lines.len(), 3, "{:?}", captured);
+    assert_eq!(lines[0], "login: ");
+    assert!(
+        lines[1].starts_with("[input ") && lines[1].ends_with(&format!(" {}] \"reboot\\r\"", addr)),
+        "{:?}",
+        lines[1]
+    );
+    assert_eq!(lines[2], "bye");
+}
+
+/// A fresh directory for the captures of a test.
+fn capture_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("crabterm-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn test_hub_capture_auto() {
+    let dir = capture_dir("auto");
+    let capture_dir = dir.clone();
+    let (mut board, mut user) = hub_with_client(move |device| {
+        IoHub::builder(Box::new(device.with_name("/dev/ttyUSB0")))
+            .capture_auto(capture_dir)
+            .announce(false)
+    });
+
+    board.write_all(b"boot\r\n").unwrap();
+    assert_eq!(read_until(&mut user, b"\r\n"), b"boot\r\n");
+
+    // The device connected once, so there is one file, named after it
+    let files: Vec<_> = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .collect();
+    assert_eq!(files.len(), 1, "{:?}", files);
+    let captured = std::fs::read(&files[0]).unwrap();
+    let _ = std::fs::remove_dir_all(&dir);
+    assert_eq!(captured, b"boot\r\n");
+    let name = files[0].file_name().unwrap().to_str().unwrap();
+    assert!(
+        name.starts_with("usb0-") && name.ends_with(".log"),
+        "{}",
+        name
+    );
+}
+
+#[test]
+fn test_hub_capture_pcapng() {
+    let dir = capture_dir("pcapng");
+    let capture_dir = dir.clone();
+    let (mut board, mut user) = hub_with_client(move |device| {
+        IoHub::builder(Box::new(device.with_name("/dev/ttyUSB0")))
+            .capture_auto(capture_dir)
+            .capture_format(Format::Pcapng)
+            .announce(false)
+    });
+
+    board.write_all(b"login: ").unwrap();
+    assert_eq!(read_until(&mut user, b": "), b"login: ");
+    user.write_all(b"root\r").unwrap();
+    assert_eq!(read_until(&mut board, b"\r"), b"root\r");
+
+    // Both directions, raw, in one file
+    let files: Vec<_> = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .collect();
+    assert_eq!(files.len(), 1, "{:?}", files);
+    assert!(files[0].to_str().unwrap().ends_with(".pcapng"));
+    let captured = std::fs::read(&files[0]).unwrap();
+    let _ = std::fs::remove_dir_all(&dir);
+    assert_eq!(&captured[..4], &[0x0a, 0x0d, 0x0d, 0x0a]);
+    for needle in [&b"usb0"[..], b"login: ", b"root\r"] {
+        assert!(
+            captured.windows(needle.len()).any(|w| w == needle),
+            "{:?}",
+            needle
+        );
+    }
+}
+
+#[test]
+fn test_hub_capture_split() {
+    let dir = capture_dir("split");
+    let capture_dir = dir.clone();
+    let (mut board, mut user) = hub_with_client(move |device| {
+        IoHub::builder(Box::new(device.with_name("/dev/ttyUSB0")))
+            .capture_auto(capture_dir)
+            .capture_split(regex::Regex::new("U-Boot SPL").unwrap())
+            .announce(false)
+    });
+
+    board
+        .write_all(b"login: \r\nU-Boot SPL 1\r\nboot one\r\n")
+        .unwrap();
+    assert_eq!(
+        read_until(&mut user, b"one\r\n"),
+        b"login: \r\nU-Boot SPL 1\r\nboot one\r\n"
+    );
+    board.write_all(b"U-Boot SPL 2\r\n").unwrap();
+    assert_eq!(read_until(&mut user, b"\r\n"), b"U-Boot SPL 2\r\n");
+
+    // The file of the connect, and one per boot
+    let mut files: Vec<_> = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .collect();
+    files.sort();
+    let captured: Vec<_> = files.iter().map(|f| std::fs::read(f).unwrap()).collect();
+    let _ = std::fs::remove_dir_all(&dir);
+    let names: Vec<_> = files
+        .iter()
+        .map(|f| f.file_name().unwrap().to_str().unwrap().to_string())
+        .collect();
+    assert_eq!(names.len(), 3, "{:?}", names);
+    assert!(names[0].starts_with("usb0-20"), "{:?}", names);
+    assert!(names[1].starts_with("usb0-boot1-"), "{:?}", names);
+    assert!(names[2].starts_with("usb0-boot2-"), "{:?}", names);
+    assert_eq!(captured[0], b"login: \r\n");
+    assert_eq!(captured[1], b"U-Boot SPL 1\r\nboot one\r\n");
+    assert_eq!(captured[2], b"U-Boot SPL 2\r\n");
+}
+
+#[test]
+fn test_hub_listener_roles() {
+    let (rw, rw_port) = local_server();
+    let (mut ro, ro_port) = local_server();
+    ro.set_role(ListenerRole {
+        read_only: true,
+        newline: Some(Newline::Lf),
+        ..Default::default()
+    });
+    let (mut auth, auth_port) = local_server();
+    auth.set_role(ListenerRole {
+        auth: true,
+        ..Default::default()
+    });
+    let mut board = hub_with_board(move |device| {
+        IoHub::builder(Box::new(device))
+            .server(rw)
+            .server(ro)
+            .server(auth)
+            .password("secret")
+            .announce(false)
+            .announce_template("[%m]\r\n")
+    });
+    let mut rw = connect(rw_port);
+    let mut ro = connect(ro_port);
+
+    // Read-only clients see the output, their input is dropped
+    board.write_all(b"login: ").unwrap();
+    assert_eq!(read_until(&mut ro, b": "), b"login: ");
+    assert_eq!(read_until(&mut rw, b": "), b"login: ");
+    ro.write_all(b"x").unwrap();
+    std::thread::sleep(Duration::from_millis(100));
+    rw.write_all(b"root\r").unwrap();
+    assert_eq!(read_until(&mut board, b"\r"), b"root\r");
+
+    // A wrong password disconnects
+    let mut intruder = connect(auth_port);
+    assert_eq!(
+        read_until(&mut intruder, b"]\r\n"),
+        b"[Password required]\r\n"
+    );
+    intruder.write_all(b"guess\r\n").unwrap();
+    assert_eq!(
+        read_until(&mut intruder, b"]\r\n"),
+        b"[Authentication failed]\r\n"
+    );
+    assert_eq!(intruder.read(&mut [0u8; 16]).unwrap_or(0), 0);
+
+    // Nothing is seen or sent before the password
+    let mut admin = connect(auth_port);
+    assert_eq!(read_until(&mut admin, b"]\r\n"), b"[Password required]\r\n");
+    board.write_all(b"hidden\r\n").unwrap();
+    assert_eq!(read_until(&mut rw, b"\r\n"), b"hidden\r\n");
+    // The line ends of the listener
+    assert_eq!(read_until(&mut ro, b"\n"), b"hidden\n");
+    admin.write_all(b"secret\r\nreboot\r").unwrap();
+    assert_eq!(read_until(&mut admin, b"]\r\n"), b"[Authenticated]\r\n");
+    assert_eq!(read_until(&mut board, b"\r"), b"reboot\r");
+    board.write_all(b"bye\r\n").unwrap();
+    assert_eq!(read_until(&mut admin, b"\r\n"), b"bye\r\n");
+}
+
+/// Send an HTTP request, the status and body of the response.
+fn http(port: u16, request: &str) -> (u16, String) {
+    let mut s = connect(port);
+    s.write_all(request.as_bytes()).unwrap();
+    let mut response = String::new();
+    s.read_to_string(&mut response).unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let status = head.split_whitespace().nth(1).unwrap().parse().unwrap();
+    (status, body.to_string())
+}
+
+/// An HTTP server on a free port, with a token, and the port.
+fn http_server() -> (HttpServer, u16) {
+    let http = HttpServer::new(0, 6000).unwrap().with_token("s3cret");
+    let port = http.local_addr().unwrap().port();
+    (http, port)
+}
+
+#[test]
+fn test_hub_http_api() {
+    let (server, port) = local_server();
+    let (http_api, http_port) = http_server();
+    let mut board = hub_with_board(move |device| {
+        IoHub::builder(Box::new(device))
+            .server(server)
+            .http(http_api)
+            .announce(false)
+            .announce_template("[%m]\r\n")
+    });
+
+    let (status, body) = http(http_port, "GET /status HTTP/1.1\r\n\r\n");
+    assert_eq!(status, 200);
+    let json: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(json["devices"][0]["connected"], true);
+    assert_eq!(json["clients"], 0);
+
+    let mut remote = connect(port);
+    board.write_all(b"ready\r\n").unwrap();
+    assert_eq!(read_until(&mut remote, b"\r\n"), b"ready\r\n");
+    let (_, body) = http(http_port, "GET /clients HTTP/1.1\r\n\r\n");
+    let clients: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(clients[0]["local"], false);
+    let id = clients[0]["id"].as_u64().unwrap();
+
+    let (status, _) = http(
+        http_port,
+        "POST /send HTTP/1.1\r\nContent-Length: 7\r\n\r\nreboot\r",
+    );
+    assert_eq!(status, 401);
+    let (status, body) = http(
+        http_port,
+        "POST /send HTTP/1.1\r\nAuthorization: Bearer s3cret\r\nContent-Length: 7\r\n\r\nreboot\r",
+    );
+    assert_eq!(
+        (status, body.as_str()),
+        (200, r#"{"device":"Loop","sent":7}"#)
+    );
+    assert_eq!(read_until(&mut board, b"\r"), b"reboot\r");
+
+    let (status, body) = http(
+        http_port,
+        "POST /marker HTTP/1.1\r\nAuthorization: Bearer s3cret\r\nContent-Length: 21\r\n\r\n\"flashing build 1234\"",
+    );
+    assert_eq!((status, body.as_str()), (200, r#"{"clients":1}"#));
+    let marker = read_until(&mut remote, b"===\r\n");
+    assert!(
+        marker.starts_with(b"\r\n=== MARKER ")
+            && marker.ends_with(b": flashing build 1234 ===\r\n"),
+        "{:?}",
+        String::from_utf8_lossy(&marker)
+    );
+    let (status, _) = http(
+        http_port,
+        "POST /marker HTTP/1.1\r\nAuthorization: Bearer s3cret\r\n\r\n",
+    );
+    assert_eq!(status, 400);
+
+    let (status, _) = http(
+        http_port,
+        &format!(
+            "POST /disconnect-client?id={} HTTP/1.1\r\nAuthorization: Bearer s3cret\r\n\r\n",
+            id
+        ),
+    );
+    assert_eq!(status, 200);
+    assert_eq!(read_until(&mut remote, b"]\r\n"), b"[Detached]\r\n");
+    assert_eq!(remote.read(&mut [0u8; 16]).unwrap_or(0), 0);
+
+    let (status, _) = http(
+        http_port,
+        "POST /disconnect-client?id=1 HTTP/1.1\r\nAuthorization: Bearer s3cret\r\n\r\n",
+    );
+    assert_eq!(status, 404);
+    let (status, _) = http(http_port, "GET /send HTTP/1.1\r\n\r\n");
+    assert_eq!(status, 405);
+    let (status, body) = http(http_port, "GET / HTTP/1.1\r\n\r\n");
+    assert_eq!(status, 200);
+    assert!(body.contains("<td>Loop</td>"), "{}", body);
+}
+
+#[test]
+fn test_hub_http_write_lock() {
+    let (server, port) = local_server();
+    let (http_api, http_port) = http_server();
+    let mut board = hub_with_board(move |device| {
+        IoHub::builder(Box::new(device))
+            .server(server)
+            .http(http_api)
+            .write_lock(true)
+            .announce(false)
+            .announce_template("[%m]\r\n")
+    });
+    let send =
+        "POST /send HTTP/1.1\r\nAuthorization: Bearer s3cret\r\nContent-Length: 3\r\n\r\nls\r";
+
+    let mut remote = connect(port);
+    assert_eq!(
+        read_until(&mut remote, b"]\r\n"),
+        b"[You have the write lock]\r\n"
+    );
+    let (status, _) = http(http_port, send);
+    assert_eq!(status, 409);
+
+    // The hub notices that the holder is gone when it writes to it
+    drop(remote);
+    let mut status = 0;
+    for _ in 0..50 {
+        board.write_all(b".").unwrap();
+        status = http(http_port, send).0;
+        if status == 200 {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    assert_eq!(status, 200);
+    assert_eq!(read_until(&mut board, b"\r"), b"ls\r");
+
+    let (status, _) = http(
+        http_port,
+        "GET /status HTTP/1.1\r\nHost: 127.0.0.1\r\nOrigin: http://evil.example\r\n\r\n",
+    );
+    assert_eq!(status, 403);
+}
+
+/// A debugger listener on a free port, and the port.
+fn gdb_server() -> (TcpServer, u16) {
+    let (mut gdb, port) = local_server();
+    gdb.set_role(ListenerRole {
+        gdb: true,
+        announce: false,
+        ..Default::default()
+    });
+    (gdb, port)
+}
+
+#[test]
+fn test_hub_gdb_listener() {
+    let (mut gdb, port) = gdb_server();
+    // Not for gdb clients
+    gdb.set_client_keybinds(KeybindConfig::default());
+    let (mut board, mut user) = hub_with_client(move |device| {
+        IoHub::builder(Box::new(device))
+            .server(gdb)
+            .announce_template("[%m]\r\n")
+    });
+    let mut gdb = connect(port);
+
+    // Raw both ways, Ctrl+a included
+    gdb.write_all(b"$m0,4#fd\x01s").unwrap();
+    assert_eq!(read_until(&mut board, b"\x01s"), b"$m0,4#fd\x01s");
+    // The console waits for the debugger to be done
+    user.write_all(b"help\r").unwrap();
+    board.write_all(b"+$00000000#80").unwrap();
+    assert_eq!(read_until(&mut gdb, b"#80"), b"+$00000000#80");
+    assert_eq!(read_until(&mut board, b"\r"), b"help\r");
+    // And still sees the device
+    assert!(read_until(&mut user, b"#80").ends_with(b"+$00000000#80"));
+}
+
+#[test]
+fn test_hub_gdb_multiple_devices() {
+    let (gdb, port) = gdb_server();
+    let ((mut board_a, mut board_b), _) = spawn_hub(|| {
+        let (dev_a, board_a) = LoopDevice::with_peer().unwrap();
+        let (dev_b, board_b) = LoopDevice::with_peer().unwrap();
+        let hub = IoHub::builder(Box::new(dev_a.with_name("/dev/ttyUSB0")))
+            .device(Box::new(dev_b.with_name("/dev/ttyUSB1")))
+            .server(gdb)
+            .announce(false)
+            .build()
+            .unwrap();
+        (hub, (board_a, board_b))
+    });
+    set_timeouts(&[&board_a, &board_b]);
+    let mut gdb = connect(port);
+    std::thread::sleep(Duration::from_millis(100));
+
+    // Only the device its input goes to, without the [usb0] labels
+    board_b.write_all(b"from b\r\n").unwrap();
+    board_a.write_all(b"+$OK#9a").unwrap();
+    assert_eq!(read_until(&mut gdb, b"#9a"), b"+$OK#9a");
+}
+
+#[test]
+fn test_hub_power_cycle() {
+    let path = ctl_path("power");
+    let ctl = CtlServer::new(&path, 7001).unwrap();
+    let (_board, mut user) = hub_with_client(move |device| {
+        let settings: HashMap<String, SettingValue> = [
+            ("power-off", "true"),
+            ("power-on", "test $CRABTERM_DEVICE = nosuch"),
+            ("power-cycle-delay", "0"),
+        ]
+        .iter()
+        .map(|(k, v)| (k.to_string(), SettingValue::String(v.to_string())))
+        .collect();
+        IoHub::builder(Box::new(device.with_name("/dev/ttyUSB0")))
+            .power(PowerControl::from_settings(&settings).unwrap().unwrap())
+            .ctl(ctl)
+            .announce_template("[%m]\r\n")
+    });
+    read_until(&mut user, b"Connected]\r\n");
+
+    assert_eq!(
+        ctl::send(&path, "power cycle").unwrap().unwrap(),
+        vec!["usb0: power cycle (by control socket)"]
+    );
+    assert!(ctl::send(&path, "reset").unwrap().is_err());
+    // Everybody attached hears of it, and how it went
+    assert_eq!(
+        read_until(&mut user, b"]\r\n"),
+        b"[usb0: power cycle (by control socket)]\r\n"
+    );
+    let done = read_until(&mut user, b"]\r\n");
+    assert!(
+        done.starts_with(b"[usb0: power cycle failed: test "),
+        "{:?}",
+        String::from_utf8_lossy(&done)
+    );
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn test_hub_control_socket() {
+    let path = ctl_path("control");
+    let ctl = CtlServer::new(&path, 7001).unwrap();
+    let (server, port) = local_server();
+    let mut board = hub_with_board(move |device| {
+        IoHub::builder(Box::new(device))
+            .server(server)
+            .ctl(ctl)
+            .announce(false)
+            .announce_template("[%m]\r\n")
+    });
+    let mut remote = connect(port);
+    board.write_all(b"ready\r\n").unwrap();
+    assert_eq!(read_until(&mut remote, b"\r\n"), b"ready\r\n");
+
+    let stats = ctl::send(&path, "stats").unwrap().unwrap();
+    assert!(
+        stats.iter().any(|l| l == "Clients served: 1"),
+        "{:?}",
+        stats
+    );
+
+    ctl::send(&path, r#"send "reboot\r""#).unwrap().unwrap();
+    assert_eq!(read_until(&mut board, b"\r"), b"reboot\r");
+
+    assert_eq!(
+        ctl::send(&path, "marker flashing build 1234").unwrap(),
+        Ok(vec!["Marker shown to 1 client(s)".to_string()])
+    );
+    assert!(read_until(&mut remote, b"===\r\n").ends_with(b": flashing build 1234 ===\r\n"));
+    assert!(ctl::send(&path, "marker").unwrap().is_err());
+
+    let dump = ctl::send(&path, "debug-dump").unwrap().unwrap();
+    assert!(dump[0].starts_with("Hub: active device 0,"), "{:?}", dump);
+    assert!(
+        dump.iter()
+            .any(|l| l.contains(" client 127.0.0.1:") && l.contains("backlogged: no")),
+        "{:?}",
+        dump
+    );
+
+    let clients = ctl::send(&path, "clients").unwrap().unwrap();
+    assert_eq!(clients.len(), 1);
+    let id = clients[0].split(' ').next().unwrap();
+    assert_eq!(
+        ctl::send(&path, &format!("kick {}", id)).unwrap(),
+        Ok(vec![format!("Client {} disconnected", id)])
+    );
+    assert_eq!(read_until(&mut remote, b"]\r\n"), b"[Detached]\r\n");
+    assert_eq!(remote.read(&mut [0u8; 16]).unwrap_or(0), 0);
+
+    assert_eq!(
+        ctl::send(&path, "kick 1").unwrap(),
+        Err("No such client: 1".to_string())
+    );
+    assert!(ctl::send(&path, "filter nosuch").unwrap().is_err());
+    assert!(ctl::send(&path, "bogus").unwrap().is_err());
+    // No logger to change
+    assert!(ctl::send(&path, "log-level debug").unwrap().is_err());
+}
+
+#[test]
+fn test_hub_marker_not_to_gdb() {
+    let path = ctl_path("mk");
+    let ctl = CtlServer::new(&path, 7001).unwrap();
+    let (gdb, port) = gdb_server();
+    let (mut board, mut user) = hub_with_client(move |device| {
+        IoHub::builder(Box::new(device))
+            .server(gdb)
+            .ctl(ctl)
+            .announce(false)
+    });
+    let mut gdb = connect(port);
+    board.write_all(b"+").unwrap();
+    assert_eq!(read_until(&mut gdb, b"+"), b"+");
+
+    // The console gets it, the debugger nothing
+    assert_eq!(
+        ctl::send(&path, "marker flashing build 1234").unwrap(),
+        Ok(vec!["Marker shown to 1 client(s)".to_string()])
+    );
+    assert!(read_until(&mut user, b"===\r\n").ends_with(b": flashing build 1234 ===\r\n"));
+    board.write_all(b"$OK#9a").unwrap();
+    assert_eq!(read_until(&mut gdb, b"#9a"), b"$OK#9a");
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn test_hub_set_option() {
+    let path = ctl_path("opt");
+    let ctl = CtlServer::new(&path, 7001).unwrap();
+    let (_board, mut user) = hub_with_client(move |device| {
+        IoHub::builder(Box::new(device))
+            .ctl(ctl)
+            .announce(false)
+            .announce_template("[%m]\r\n")
+    });
+
+    assert_eq!(
+        ctl::send(&path, "set-option announce").unwrap(),
+        Ok(vec!["Announcements on".to_string()])
+    );
+    assert_eq!(
+        ctl::send(&path, "set-option silence-timeout 0.2").unwrap(),
+        Ok(vec!["Silence watchdog 0.2 s".to_string()])
+    );
+    let out = read_until(&mut user, b" s]\r\n");
+    assert!(out.ends_with(b": no output for 0.2 s]\r\n"), "{:?}", out);
+    assert_eq!(
+        ctl::send(&path, "set-option silence-timeout off").unwrap(),
+        Ok(vec!["Silence watchdog off".to_string()])
+    );
+
+    let capture = std::env::temp_dir().join(format!("crabterm_option_{}.log", std::process::id()));
+    assert_eq!(
+        ctl::send(&path, "set-option capture on").unwrap(),
+        Err("capture: no capture to continue, give a file".to_string())
+    );
+    assert_eq!(
+        ctl::send(&path, &format!("set-option capture {}", capture.display())).unwrap(),
+        Ok(vec![format!("Capturing to {}", capture.display())])
+    );
+    assert!(
+        ctl::send(&path, "set-option capture").unwrap().unwrap()[0]
+            .starts_with("Capture stopped: ")
+    );
+    assert_eq!(
+        ctl::send(&path, "set-option capture on").unwrap(),
+        Ok(vec![format!("Capturing to {}", capture.display())])
+    );
+    let _ = std::fs::remove_file(&capture);
+    // Only serial devices have one
+    assert_eq!(
+        ctl::send(&path, "set-option quarantine 50").unwrap(),
+        Err("quarantine: no serial device".to_string())
+    );
+
+    assert!(
+        ctl::send(&path, "set-option announce maybe")
+            .unwrap()
+            .is_err()
+    );
+    assert!(
+        ctl::send(&path, "set-option write-coalesce soon")
+            .unwrap()
+            .is_err()
+    );
+    assert!(
+        ctl::send(&path, "set-option nosuch on")
+            .unwrap()
+            .unwrap_err()
+            .starts_with("Unknown option: nosuch")
+    );
+}
+
+#[test]
+fn test_hub_log_level() {
+    let path = ctl_path("log");
+    let ctl = CtlServer::new(&path, 7001).unwrap();
+    let changes = Arc::new(Mutex::new(Vec::new()));
+    let seen = changes.clone();
+    let _board = hub_with_board(move |device| {
+        IoHub::builder(Box::new(device))
+            .ctl(ctl)
+            .log_control(Box::new(move |change| {
+                seen.lock().unwrap().push(change);
+                Ok(())
+            }))
+            .announce(false)
+    });
+
+    assert_eq!(
+        ctl::send(&path, "log-level debug").unwrap(),
+        Ok(vec!["Log level debug".to_string()])
+    );
+    assert!(ctl::send(&path, "log-level loud").unwrap().is_err());
+    assert_eq!(
+        *changes.lock().unwrap(),
+        vec![LogChange::Set(log::LevelFilter::Debug)]
+    );
+}
+
+/// A client with a small receive buffer, so that the output it does not
+/// read stays in the hub rather than in the kernel.
+fn connect_small_window(port: u16) -> TcpStream {
+    use std::os::fd::FromRawFd;
+    unsafe {
+        let fd = libc::socket(libc::AF_INET, libc::SOCK_STREAM, 0);
+        assert!(fd >= 0);
+        let size: libc::c_int = 4096;
+        libc::setsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_RCVBUF,
+            &size as *const _ as *const libc::c_void,
+            std::mem::size_of_val(&size) as libc::socklen_t,
+        );
+        let addr = libc::sockaddr_in {
+            sin_family: libc::AF_INET as libc::sa_family_t,
+            sin_port: port.to_be(),
+            sin_addr: libc::in_addr {
+                s_addr: u32::from_be_bytes([127, 0, 0, 1]).to_be(),
+            },
+            sin_zero: [0; 8],
+        };
+        let len = std::mem::size_of_val(&addr) as libc::socklen_t;
+        assert_eq!(
+            libc::connect(fd, &addr as *const _ as *const libc::sockaddr, len),
+            0
+        );
+        TcpStream::from_raw_fd(fd)
+    }
+}
+
+#[test]
+fn test_hub_drain_on_quit() {
+    let path = ctl_path("drain");
+    let ctl = CtlServer::new(&path, 7001).unwrap();
+    let (mut server, port) = local_server();
+    server.set_client_policy(ClientPolicy {
+        slow: SlowClientPolicy::Block,
+        buffer_limit: 256 * 1024,
+        rate_limit: None,
+    });
+    let (mut board, done) = spawn_hub(move || {
+        let (device, board) = LoopDevice::with_peer().unwrap();
+        let hub = IoHub::builder(Box::new(device))
+            .server(server)
+            .ctl(ctl)
+            .announce_template("[%m]\r\n")
+            .drain_timeout(Duration::from_secs(5))
+            .build()
+            .unwrap();
+        (hub, board)
+    });
+    let mut remote = connect_small_window(port);
+    remote
+        .set_read_timeout(Some(Duration::from_secs(2)))
+        .unwrap();
+    assert_eq!(read_until(&mut remote, b"]\r\n"), b"[Loop: Connected]\r\n");
+
+    // The client does not read, so output piles up in its buffer until
+    // the hub stops reading the board
+    std::thread::spawn(move || {
+        let _ = board.write_all(&vec![b'x'; 16 * 1024 * 1024]);
+    });
+    let mut stats = None;
+    loop {
+        std::thread::sleep(Duration::from_millis(200));
+        let now = ctl::send(&path, "stats").unwrap().unwrap();
+        if stats.as_ref() == Some(&now) {
+            break;
+        }
+        stats = Some(now);
+    }
+    ctl::send(&path, "quit").unwrap().unwrap();
+
+    // All of it, then the goodbye
+    let out = read_until(&mut remote, b"]\r\n");
+    assert!(out.ends_with(b"[Shutting down]\r\n"));
+    let data = &out[..out.len() - b"[Shutting down]\r\n".len()];
+    assert!(data.len() > 256 * 1024, "{} bytes", data.len());
+    assert!(data.iter().all(|&b| b == b'x'));
+    assert!(done.recv_timeout(Duration::from_secs(2)).unwrap().is_ok());
+}
@@ -0,0 +1,539 @@
+//! The in-process harness: timers fire when the clock is moved forward,
+//! without real waits.
+
+use std::time::Duration;
+
+use crabterm_core::IoHub;
+use crabterm_core::banner::Banner;
+use crabterm_core::capture::Capture;
+use crabterm_core::health::HealthConfig;
+use crabterm_core::io::listener::{ListenerRole, Newline};
+use crabterm_core::keybind::key::KeyEvent;
+use crabterm_core::keybind::{Action, KeybindConfig};
+use crabterm_core::testing::{Harness, virtual_device};
+use crabterm_core::watchdog::SilenceAction;
+
+#[test]
+fn test_keepalive_and_silence() {
+    let (device, mut board) = virtual_device("/dev/ttyUSB0").unwrap();
+    let hub = IoHub::builder(device)
+        .keepalive(b"\r".to_vec(), Duration::from_secs(30))
+        .silence(Duration::from_secs(60), SilenceAction::Announce)
+        .announce_template("[%m]\r\n")
+        .build()
+        .unwrap();
+    let mut harness = Harness::new(hub);
+    let mut user = harness.client().unwrap();
+    assert!(!harness.step().unwrap());
+    assert_eq!(user.recv(), b"[/dev/ttyUSB0: Connected]\r\n");
+
+    board.send(b"login: ");
+    harness.step().unwrap();
+    assert_eq!(user.recv(), b"login: ");
+
+    harness.advance(Duration::from_secs(29)).unwrap();
+    assert_eq!(board.recv(), b"");
+    harness.advance(Duration::from_secs(1)).unwrap();
+    assert_eq!(board.recv(), b"\r");
+
+    harness.advance(Duration::from_secs(30)).unwrap();
+    assert_eq!(user.recv(), b"[/dev/ttyUSB0: no output for 60.0 s]\r\n");
+}
+
+#[test]
+fn test_prefix_timeout() {
+    let (device, mut board) = virtual_device("/dev/ttyUSB0").unwrap();
+    let hub = IoHub::builder(device).announce(false).build().unwrap();
+    let mut harness = Harness::new(hub);
+    let mut user = harness.keybind_client(KeybindConfig::default()).unwrap();
+    harness.step().unwrap();
+
+    // The prefix waits for the next key, then goes to the device alone
+    user.send(b"\x01");
+    harness.step().unwrap();
+    assert_eq!(board.recv(), b"");
+    harness.advance(Duration::from_secs(2)).unwrap();
+    assert_eq!(board.recv(), b"\x01");
+}
+
+#[test]
+fn test_silence_exit() {
+    let (device, _board) = virtual_device("/dev/ttyUSB0").unwrap();
+    let hub = IoHub::builder(device)
+        .silence(Duration::from_secs(5), SilenceAction::Exit)
+        .drain_timeout(Duration::ZERO)
+        .build()
+        .unwrap();
+    let mut harness = Harness::new(hub);
+    assert!(!harness.step().unwrap());
+    let e = harness.advance(Duration::from_secs(5)).unwrap_err();
+    assert!(e.to_string().contains("no output for 5.0 s"), "{}", e);
+}
+
+#[test]
+fn test_replay() {
+    let (device, mut board) = virtual_device("/dev/ttyUSB0").unwrap();
+    let hub = IoHub::builder(device)
+        .replay_lines(2)
+        .announce(false)
+        .announce_template("[%m]\r\n")
+        .build()
+        .unwrap();
+    let mut harness = Harness::new(hub);
+    harness.step().unwrap();
+    board.send(b"Kernel panic\r\nCPU: 0\r\nend trace\r\n=> ");
+    harness.step().unwrap();
+
+    // The last lines, the prompt included, then what comes next
+    let mut late = harness.remote_client(ListenerRole::default()).unwrap();
+    harness.step().unwrap();
+    assert_eq!(
+        late.recv(),
+        b"[Replay of the last 2 lines of usb0]\r\nend trace\r\n=> \r\n[End of replay]\r\n"
+    );
+    board.send(b"reset\r\n");
+    harness.step().unwrap();
+    assert_eq!(late.recv(), b"reset\r\n");
+
+    // Not for a debugger
+    let mut gdb = harness
+        .remote_client(ListenerRole {
+            gdb: true,
+            ..Default::default()
+        })
+        .unwrap();
+    harness.step().unwrap();
+    assert_eq!(gdb.recv(), b"");
+}
+
+#[test]
+fn test_replay_keybind() {
+    let (device, mut board) = virtual_device("/dev/ttyUSB0").unwrap();
+    let hub = IoHub::builder(device)
+        .replay_lines(10)
+        .announce(false)
+        .announce_template("[%m]\r\n")
+        .build()
+        .unwrap();
+    let mut harness = Harness::new(hub);
+    let mut user = harness.keybind_client(KeybindConfig::default()).unwrap();
+    harness.step().unwrap();
+    board.send(b"one\r\ntwo\r\n");
+    harness.step().unwrap();
+    assert_eq!(user.recv(), b"one\r\ntwo\r\n");
+
+    user.send(b"\x01r");
+    harness.step().unwrap();
+    assert_eq!(
+        user.recv(),
+        b"[Replay of the last 2 lines of usb0]\r\none\r\ntwo\r\n[End of replay]\r\n"
+    );
+}
+
+#[test]
+fn test_banner() {
+    let (device, mut board) = virtual_device("/dev/ttyUSB0").unwrap();
+    let hub = IoHub::builder(device)
+        .banner(Banner::new(Some("Board %l (%a, %b baud), %r\\n".into()), None).unwrap())
+        .announce(false)
+        .build()
+        .unwrap();
+    let mut harness = Harness::new(hub);
+    let mut user = harness
+        .remote_client(ListenerRole {
+            read_only: true,
+            ..Default::default()
+        })
+        .unwrap();
+    harness.step().unwrap();
+    // First, and even with the announcements off
+    assert_eq!(
+        user.recv(),
+        b"Board usb0 (/dev/ttyUSB0, - baud), read-only\r\n"
+    );
+    board.send(b"login: ");
+    harness.step().unwrap();
+    assert_eq!(user.recv(), b"login: ");
+}
+
+#[test]
+fn test_banner_roles() {
+    let (device, mut board) = virtual_device("/dev/ttyUSB0").unwrap();
+    let hub = IoHub::builder(device)
+        .banner(Banner::new(Some("Board %l".into()), None).unwrap())
+        .password("secret")
+        .announce(false)
+        .announce_template("[%m]\r\n")
+        .build()
+        .unwrap();
+    let mut harness = Harness::new(hub);
+    let mut gdb = harness
+        .remote_client(ListenerRole {
+            gdb: true,
+            auth: true,
+            announce: false,
+            ..Default::default()
+        })
+        .unwrap();
+    let mut script = harness
+        .remote_client(ListenerRole {
+            newline: Some(Newline::Lf),
+            ..Default::default()
+        })
+        .unwrap();
+    harness.step().unwrap();
+    assert_eq!(gdb.recv(), b"[Password required]\r\n");
+    assert_eq!(script.recv(), b"Board usb0\n");
+
+    // Not after authenticating either
+    gdb.send(b"secret\r");
+    harness.step().unwrap();
+    assert_eq!(gdb.recv(), b"[Authenticated]\r\n");
+    board.send(b"+$OK#9a");
+    harness.step().unwrap();
+    assert_eq!(gdb.recv(), b"+$OK#9a");
+}
+
+#[test]
+fn test_note() {
+    let path = std::env::temp_dir().join(format!("crabterm-note-{}.log", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let (device, mut board) = virtual_device("/dev/ttyUSB0").unwrap();
+    let mut hub = IoHub::builder(device)
+        .announce(false)
+        .announce_template("[%m]\r\n")
+        .build()
+        .unwrap();
+    hub.set_capture(Some(Capture::new(&path).unwrap()));
+    let mut harness = Harness::new(hub);
+    let mut keys = KeybindConfig::default();
+    keys.prefix_bindings.insert(
+        KeyEvent::char('n'),
+        Action::Note(Some("started test 42".to_string())),
+    );
+    let mut user = harness.keybind_client(keys).unwrap();
+    harness.step().unwrap();
+    board.send(b"U-Boot> ");
+    harness.step().unwrap();
+    assert_eq!(user.recv(), b"U-Boot> ");
+
+    user.send(b"\x01n");
+    harness.step().unwrap();
+    let reply = String::from_utf8(user.recv()).unwrap();
+    assert!(
+        reply.starts_with("[Note written to the log and"),
+        "{}",
+        reply
+    );
+    // Without the text, which only the console prompts for
+    user.send(b"\x01a");
+    harness.step().unwrap();
+    assert_eq!(user.recv(), b"[note requires a text]\r\n");
+
+    drop(harness);
+    let captured = std::fs::read_to_string(&path).unwrap();
+    let _ = std::fs::remove_file(&path);
+    let lines: Vec<&str> = captured.lines().collect();
+    assert_eq!(lines[0], "U-Boot> ");
+    assert!(lines[1].starts_with("=== NOTE "), "{:?}", captured);
+    assert!(
+        lines[1].ends_with(": started test 42 ==="),
+        "{:?}",
+        captured
+    );
+}
+
+#[test]
+fn test_write_lock() {
+    let (device, mut board) = virtual_device("/dev/ttyUSB0").unwrap();
+    let hub = IoHub::builder(device)
+        .write_lock(true)
+        .announce(false)
+        .announce_template("[%m]\r\n")
+        .build()
+        .unwrap();
+    let mut harness = Harness::new(hub);
+    let mut keys = KeybindConfig::default();
+    keys.prefix_bindings
+        .insert(KeyEvent::char('w'), Action::WriteRelease);
+    let mut alice = harness.keybind_client(keys).unwrap();
+    let mut bob = harness.remote_client(ListenerRole::default()).unwrap();
+    let mut carol = harness.remote_client(ListenerRole::default()).unwrap();
+    harness.step().unwrap();
+    assert_eq!(alice.recv(), b"[You have the write lock]\r\n");
+    assert_eq!(
+        bob.recv(),
+        b"[Read-only, number 1 in the queue for the write lock]\r\n"
+    );
+    assert_eq!(
+        carol.recv(),
+        b"[Read-only, number 2 in the queue for the write lock]\r\n"
+    );
+
+    // Only the holder writes
+    alice.send(b"ls\r");
+    bob.send(b"reboot\r");
+    harness.step().unwrap();
+    assert_eq!(board.recv(), b"ls\r");
+
+    // Given up, the lock goes to the first waiting
+    alice.send(b"\x01w");
+    harness.step().unwrap();
+    assert_eq!(bob.recv(), b"[You have the write lock]\r\n");
+    assert_eq!(
+        carol.recv(),
+        b"[Read-only, number 1 in the queue for the write lock]\r\n"
+    );
+    assert_eq!(
+        alice.recv(),
+        b"[Read-only, number 2 in the queue for the write lock]\r\n"
+    );
+
+    // And to the next when its holder leaves
+    drop(bob);
+    harness.step().unwrap();
+    assert_eq!(carol.recv(), b"[You have the write lock]\r\n");
+    assert_eq!(
+        alice.recv(),
+        b"[Read-only, number 1 in the queue for the write lock]\r\n"
+    );
+    carol.send(b"uptime\r");
+    harness.step().unwrap();
+    assert_eq!(board.recv(), b"uptime\r");
+}
+
+#[test]
+fn test_write_coalesce() {
+    let (device, mut board) = virtual_device("/dev/ttyUSB0").unwrap();
+    let hub = IoHub::builder(device)
+        .write_coalesce(Duration::from_secs(1))
+        .announce(false)
+        .build()
+        .unwrap();
+    let mut harness = Harness::new(hub);
+    let mut user = harness.client().unwrap();
+    harness.step().unwrap();
+
+    // A keystroke after a quiet spell is written at once, what follows
+    // within the window is written together
+    user.send(b"a");
+    harness.step().unwrap();
+    assert_eq!(board.recv(), b"a");
+    for c in [b"b", b"c", b"d"] {
+        user.send(c);
+        harness.advance(Duration::from_millis(100)).unwrap();
+    }
+    assert_eq!(board.recv(), b"");
+    harness.advance(Duration::from_millis(700)).unwrap();
+    assert_eq!(board.recv(), b"bcd");
+}
+
+#[test]
+fn test_frame_gap() {
+    let (device, mut board) = virtual_device("/dev/ttyUSB0").unwrap();
+    let hub = IoHub::builder(device)
+        .frame_gap(Duration::from_secs(1))
+        .announce(false)
+        .build()
+        .unwrap();
+    let mut harness = Harness::new(hub);
+    let mut user = harness.client().unwrap();
+    harness.step().unwrap();
+
+    // Nothing is delivered while the frame goes on
+    board.send(b"ab");
+    harness.step().unwrap();
+    harness.advance(Duration::from_millis(600)).unwrap();
+    board.send(b"cd");
+    harness.step().unwrap();
+    harness.advance(Duration::from_millis(500)).unwrap();
+    assert_eq!(user.recv(), b"");
+    harness.advance(Duration::from_millis(500)).unwrap();
+    assert_eq!(user.recv(), b"abcd");
+}
+
+#[test]
+fn test_keepalive_postponed() {
+    let (device, mut board) = virtual_device("/dev/ttyUSB0").unwrap();
+    let hub = IoHub::builder(device)
+        .keepalive(b"\r\n".to_vec(), Duration::from_secs(30))
+        .announce(false)
+        .build()
+        .unwrap();
+    let mut harness = Harness::new(hub);
+    let mut user = harness.client().unwrap();
+    harness.step().unwrap();
+
+    // Traffic in either direction postpones the keepalive
+    harness.advance(Duration::from_secs(20)).unwrap();
+    board.send(b"x");
+    harness.step().unwrap();
+    harness.advance(Duration::from_secs(20)).unwrap();
+    user.send(b"a");
+    harness.step().unwrap();
+    assert_eq!(board.recv(), b"a");
+    harness.advance(Duration::from_secs(29)).unwrap();
+    assert_eq!(board.recv(), b"");
+    harness.advance(Duration::from_secs(1)).unwrap();
+    assert_eq!(board.recv(), b"\r\n");
+}
+
+#[test]
+fn test_silence_watchdog() {
+    let (device, mut board) = virtual_device("/dev/ttyUSB0").unwrap();
+    let hub = IoHub::builder(device)
+        .silence(Duration::from_secs(60), SilenceAction::Exit)
+        .announce_template("[%m]\r\n")
+        .build()
+        .unwrap();
+    let mut harness = Harness::new(hub);
+    let mut user = harness.client().unwrap();
+    harness.step().unwrap();
+    assert_eq!(user.recv(), b"[/dev/ttyUSB0: Connected]\r\n");
+
+    // Output keeps the watchdog quiet
+    for _ in 0..3 {
+        harness.advance(Duration::from_secs(40)).unwrap();
+        board.send(b"x");
+    }
+    assert!(!harness.step().unwrap());
+    assert_eq!(user.recv(), b"xxx");
+
+    let e = harness.advance(Duration::from_secs(60)).unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::TimedOut);
+    assert_eq!(
+        user.recv(),
+        b"[/dev/ttyUSB0: no output for 60.0 s]\r\n[Shutting down]\r\n"
+    );
+}
+
+#[test]
+fn test_health_check() {
+    let (device, mut board) = virtual_device("/dev/ttyUSB0").unwrap();
+    let hub = IoHub::builder(device)
+        .health(HealthConfig {
+            prompt: regex::Regex::new("login: $").unwrap(),
+            probe: b"\r".to_vec(),
+            interval: Duration::from_secs(20),
+            timeout: Duration::from_secs(5),
+        })
+        .announce_template("[%m]\r\n")
+        .build()
+        .unwrap();
+    let mut harness = Harness::new(hub);
+    let mut user = harness.client().unwrap();
+    harness.step().unwrap();
+    assert_eq!(user.recv(), b"[/dev/ttyUSB0: Connected]\r\n");
+
+    // Probed at once, and answered
+    assert_eq!(board.recv(), b"\r");
+    board.send(b"\r\nlogin: ");
+    harness.step().unwrap();
+    assert_eq!(
+        user.recv(),
+        b"[/dev/ttyUSB0: prompt seen, board up]\r\n\r\nlogin: "
+    );
+
+    // The next probe goes unanswered
+    harness.advance(Duration::from_secs(20)).unwrap();
+    assert_eq!(board.recv(), b"\r");
+    harness.advance(Duration::from_secs(5)).unwrap();
+    assert_eq!(
+        user.recv(),
+        b"[/dev/ttyUSB0: no prompt within 5.0 s, board down]\r\n"
+    );
+
+    // Back up once the board answers the next
+    harness.advance(Duration::from_secs(20)).unwrap();
+    assert_eq!(board.recv(), b"\r");
+    board.send(b"login: ");
+    harness.step().unwrap();
+    assert_eq!(
+        user.recv(),
+        b"[/dev/ttyUSB0: prompt seen, board up]\r\nlogin: "
+    );
+}
+
+#[test]
+fn test_latency() {
+    let (device, mut board) = virtual_device("/dev/ttyUSB0").unwrap();
+    let hub = IoHub::builder(device)
+        .latency_marker(b"?".to_vec())
+        .announce(false)
+        .announce_template("[%m]\r\n")
+        .build()
+        .unwrap();
+    let mut harness = Harness::new(hub);
+    let mut keys = KeybindConfig::default();
+    keys.prefix_bindings
+        .insert(KeyEvent::char('l'), Action::Latency(3));
+    let mut user = harness.keybind_client(keys).unwrap();
+    harness.step().unwrap();
+
+    user.send(b"\x01l");
+    harness.step().unwrap();
+    assert_eq!(
+        user.recv(),
+        b"[/dev/ttyUSB0: Measuring latency (3 probe(s))]\r\n"
+    );
+    // Each answered after its own time, the next probe a while later
+    for ms in [20, 30, 40] {
+        assert_eq!(board.recv(), b"?");
+        board.send(b"?");
+        harness.advance(Duration::from_millis(ms)).unwrap();
+        harness.advance(Duration::from_millis(200)).unwrap();
+    }
+    let out = String::from_utf8(user.recv()).unwrap();
+    assert!(
+        out.starts_with("???[/dev/ttyUSB0: Latency: min ")
+            && out.ends_with(" ms (3 probe(s), 0 lost)]\r\n"),
+        "{}",
+        out
+    );
+    // The mock clock is the real one moved forward, so at least as long
+    let [min, avg, max] = ["min ", "avg ", "max "].map(|label| {
+        let shown: f64 = out
+            .split(label)
+            .nth(1)
+            .unwrap()
+            .split(' ')
+            .next()
+            .unwrap()
+            .parse()
+            .unwrap();
+        shown
+    });
+    assert!(min >= 20.0 && max >= 40.0, "{}", out);
+    assert!(min <= avg && avg <= max, "{}", out);
+}
+
+#[test]
+fn test_gdb_priority() {
+    let (device, mut board) = virtual_device("/dev/ttyUSB0").unwrap();
+    let hub = IoHub::builder(device).announce(false).build().unwrap();
+    let mut harness = Harness::new(hub);
+    let mut user = harness.client().unwrap();
+    let mut gdb = harness
+        .remote_client(ListenerRole {
+            gdb: true,
+            announce: false,
+            ..Default::default()
+        })
+        .unwrap();
+    harness.step().unwrap();
+
+    // The console waits for the debugger to be done
+    gdb.send(b"$m0,4#fd");
+    harness.step().unwrap();
+    user.send(b"help\r");
+    board.send(b"+$00000000#80");
+    harness.step().unwrap();
+    assert_eq!(board.recv(), b"$m0,4#fd");
+    assert_eq!(gdb.recv(), b"+$00000000#80");
+    harness.advance(Duration::from_millis(250)).unwrap();
+    assert_eq!(board.recv(), b"");
+    harness.advance(Duration::from_millis(250)).unwrap();
+    assert_eq!(board.recv(), b"help\r");
+    // And still sees the device
+    assert_eq!(user.recv(), b"+$00000000#80");
+}
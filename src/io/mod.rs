@@ -1,11 +0,0 @@
-pub mod console;
-pub mod echo_device;
-pub mod serial_device;
-pub mod tcp_device;
-pub mod tcp_server;
-
-pub use console::Console;
-pub use echo_device::EchoDevice;
-pub use serial_device::SerialDevice;
-pub use tcp_device::TcpDevice;
-pub use tcp_server::TcpServer;
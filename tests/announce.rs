@@ -1,8 +1,10 @@
+#![cfg(unix)]
+
 #[macro_use]
 mod common;
 
-use common::{CrabtermProcess, find_available_port, wait_for_port};
-use std::io::Read;
+use common::{CrabtermProcess, LogLevel, find_available_port, wait_for_port};
+use std::io::{Read, Write};
 use std::net::TcpStream;
 use std::path::PathBuf;
 use std::time::Duration;
@@ -280,3 +282,55 @@ async fn test_template_without_newline() {
     let _ = std::fs::remove_file(&config_path);
     let _ = std::fs::remove_file(&log_file);
 }
+
+#[tokio::test]
+async fn test_signals_toggle_announce_and_log_level() {
+    let crabterm_port = find_available_port().await;
+    let config = empty_config();
+
+    let mut crabterm = CrabtermProcess::builder()
+        .echo_device()
+        .listen(crabterm_port)
+        .no_announce(false)
+        .log_level(LogLevel::Debug)
+        .config(config.clone())
+        .spawn();
+
+    assert!(
+        wait_for_port(crabterm_port, 2000).await,
+        "Crabterm server should start"
+    );
+
+    // SIGUSR1: no announcement, only the echo
+    crabterm.signal(libc::SIGUSR1);
+    // SIGUSR2: debug is raised to trace
+    crabterm.signal(libc::SIGUSR2);
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    let mut client =
+        TcpStream::connect(format!("127.0.0.1:{}", crabterm_port)).expect("Failed to connect");
+    client
+        .set_read_timeout(Some(Duration::from_secs(2)))
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    client.write_all(b"hi").unwrap();
+
+    let mut buf = [0u8; 1024];
+    let n = client.read(&mut buf).expect("Failed to read");
+    assert_eq!(&buf[..n], b"hi");
+
+    crabterm.signal(libc::SIGUSR2);
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    let log = crabterm.read_log();
+    assert!(log.contains("SIGUSR1: announcements off"), "{}", log);
+    assert!(log.contains("SIGUSR2: log level raised"), "{}", log);
+    assert!(log.contains("SIGUSR2: log level lowered"), "{}", log);
+    let raised = log.split("log level raised").nth(1).unwrap();
+    let (during, after) = raised.split_once("log level lowered").unwrap();
+    assert!(during.contains(" TRACE "), "{}", log);
+    assert!(!after.contains(" TRACE "), "{}", log);
+
+    crabterm.stop();
+    let _ = std::fs::remove_file(config);
+}
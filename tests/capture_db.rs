@@ -0,0 +1,43 @@
+//! `--capture-db` and `crabterm search`: lines of a run stored by boot, and
+//! found again.
+
+#![cfg(unix)]
+
+mod common;
+
+use common::{run_crabterm, scripted_board};
+
+#[test]
+fn test_capture_db_search() {
+    let db = std::env::temp_dir().join(format!("crabterm_search_{}.db", std::process::id()));
+    let db = db.to_str().unwrap();
+    let addr = scripted_board(b"Hello\r\nU-Boot SPL\r\nlogin: ");
+    let output = run_crabterm(&[
+        &addr,
+        "--headless",
+        "--capture-db",
+        db,
+        "--capture-split",
+        "U-Boot SPL",
+        "--success-pattern",
+        "login: $",
+    ]);
+    assert_eq!(output.status.code(), Some(0));
+
+    let output = run_crabterm(&["search", "--db", db, "Hello|SPL"]);
+    assert_eq!(output.status.code(), Some(0));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 2, "{}", stdout);
+    assert!(lines[0].ends_with("boot 1: Hello"), "{}", stdout);
+    assert!(lines[1].ends_with("boot 2: U-Boot SPL"), "{}", stdout);
+
+    let output = run_crabterm(&["search", "--db", db, "--boot", "2", "login"]);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("login: "));
+    let output = run_crabterm(&["search", "--db", db, "panic"]);
+    assert_eq!(output.status.code(), Some(1));
+    // Like grep, a file that is not there is not a miss
+    let output = run_crabterm(&["search", "--db", "/nonexistent/capture.db", "panic"]);
+    assert_eq!(output.status.code(), Some(2));
+    let _ = std::fs::remove_file(db);
+}
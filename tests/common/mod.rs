@@ -330,6 +330,13 @@ impl CrabtermProcess {
         let _ = self.child.wait();
     }
 
+    /// Send a signal to the process
+    pub fn signal(&self, signal: i32) {
+        unsafe {
+            libc::kill(self.child.id() as i32, signal);
+        }
+    }
+
     /// Force kill the process immediately (SIGKILL)
     pub fn kill(&mut self) {
         let _ = self.child.kill();
@@ -380,3 +387,25 @@ pub fn spawn_crabterm(device_addr: &str, listen_port: Option<u16>) -> (Child, Op
 
     (child, Some(log_file))
 }
+
+/// Run crabterm with `args` to the end.
+pub fn run_crabterm(args: &[&str]) -> std::process::Output {
+    Command::new(env!("CARGO_BIN_EXE_crabterm"))
+        .args(args)
+        .output()
+        .expect("Failed to run crabterm")
+}
+
+/// A device at the returned address that writes `output` and stays open.
+pub fn scripted_board(output: &'static [u8]) -> String {
+    use std::io::Write;
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    std::thread::spawn(move || {
+        if let Ok((mut stream, _)) = listener.accept() {
+            let _ = stream.write_all(output);
+            std::thread::sleep(Duration::from_secs(10));
+        }
+    });
+    addr
+}
@@ -1,3 +1,5 @@
+#![cfg(unix)]
+
 #[macro_use]
 mod common;
 
@@ -408,3 +410,442 @@ async fn test_console_keypress_does_not_block_device_output() {
 
     tprintln!("Test passed: device output flows normally after a console keypress");
 }
+
+/// With stdout redirected to a pipe (e.g. `crabterm ... | tee log`) device
+/// output must still reach stdout, and keybinds typed on the (TTY) stdin must
+/// still be honored.
+#[tokio::test]
+#[serial_test::serial]
+async fn test_console_stdout_pipe() {
+    let (device_master, device_slave) = create_pty().expect("Failed to create device PTY");
+    let device_path = get_pty_path(device_slave).expect("Failed to get device path");
+    let (console_master, console_slave) = create_pty().expect("Failed to create console PTY");
+
+    let mut cmd = Command::new(env!("CARGO_BIN_EXE_crabterm"));
+    cmd.arg("-d").arg(&device_path).arg("--no-announce");
+    unsafe {
+        cmd.stdin(Stdio::from_raw_fd(libc::dup(console_slave)));
+        cmd.stderr(Stdio::from_raw_fd(libc::dup(console_slave)));
+    }
+    cmd.stdout(Stdio::piped());
+
+    tprintln!("Spawning crabterm: {:?}", cmd);
+    let mut crabterm = cmd.spawn().expect("Failed to spawn crabterm");
+    let mut stdout = crabterm.stdout.take().unwrap();
+
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    write_fd(device_master, b"piped\r\n").expect("Failed to write to device");
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    // Ctrl+Q from the terminal must still quit, which also closes the pipe
+    write_fd(console_master, &[0x11]).expect("Failed to write Ctrl+Q");
+
+    let deadline = std::time::Instant::now() + Duration::from_secs(2);
+    let mut exited = false;
+    while std::time::Instant::now() < deadline {
+        if let Ok(Some(_)) = crabterm.try_wait() {
+            exited = true;
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    if !exited {
+        let _ = crabterm.kill();
+    }
+    let _ = crabterm.wait();
+
+    let mut output = Vec::new();
+    std::io::Read::read_to_end(&mut stdout, &mut output).unwrap();
+    let output = String::from_utf8_lossy(&output);
+    tprintln!("Piped stdout: {:?}", output);
+
+    unsafe {
+        libc::close(device_master);
+        libc::close(device_slave);
+        libc::close(console_master);
+        libc::close(console_slave);
+    }
+
+    assert!(
+        exited,
+        "Crabterm should exit after Ctrl+Q with stdout piped"
+    );
+    assert!(
+        output.contains("piped"),
+        "Device output should reach the piped stdout, got: {:?}",
+        output
+    );
+}
+
+#[tokio::test]
+#[serial_test::serial]
+async fn test_console_stats_keybind() {
+    let mut harness = ConsoleTestHarness::start(LogLevel::Info).await;
+
+    write_fd(harness.device_master, b"hello").expect("Failed to write to device");
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // Ctrl+A s shows the statistics, even with --no-announce
+    write_fd(harness.console_master, &[0x01, b's']).expect("Failed to write Ctrl+A s");
+
+    unsafe {
+        let flags = libc::fcntl(harness.console_master, libc::F_GETFL);
+        libc::fcntl(
+            harness.console_master,
+            libc::F_SETFL,
+            flags | libc::O_NONBLOCK,
+        );
+    }
+
+    let deadline = std::time::Instant::now() + Duration::from_millis(1000);
+    let mut received = String::new();
+    let mut buf = [0u8; 1024];
+    while std::time::Instant::now() < deadline && !received.contains("Clients served") {
+        if let Ok(n) = read_fd(harness.console_master, &mut buf) {
+            received.push_str(&String::from_utf8_lossy(&buf[..n]));
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    tprintln!("Console received: {:?}", received);
+    assert!(received.contains("Device bytes in: 5, out: 0"));
+    assert!(received.contains("Clients served: 0"));
+    assert!(harness.is_running(), "Stats must not quit crabterm");
+}
+
+#[tokio::test]
+#[serial_test::serial]
+async fn test_console_command_prompt() {
+    let mut harness = ConsoleTestHarness::start(LogLevel::Info).await;
+    let capture = harness.log_file.with_extension("capture");
+
+    // Ctrl+A : opens the prompt, Enter runs the command
+    write_fd(harness.console_master, &[0x01, b':']).expect("Failed to write Ctrl+A :");
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let command = format!("capture start {}\r", capture.display());
+    write_fd(harness.console_master, command.as_bytes()).expect("Failed to write command");
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    write_fd(harness.device_master, b"captured\r\n").expect("Failed to write to device");
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    write_fd(harness.console_master, &[0x01, b':']).expect("Failed to write Ctrl+A :");
+    write_fd(harness.console_master, b"capture stop\r").expect("Failed to write command");
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let content = std::fs::read_to_string(&capture).unwrap_or_default();
+    let _ = std::fs::remove_file(&capture);
+    assert_eq!(content, "captured\r\n");
+
+    // The command text goes to the prompt, not to the device
+    unsafe {
+        let flags = libc::fcntl(harness.device_master, libc::F_GETFL);
+        libc::fcntl(
+            harness.device_master,
+            libc::F_SETFL,
+            flags | libc::O_NONBLOCK,
+        );
+    }
+    let mut buf = [0u8; 256];
+    let sent = read_fd(harness.device_master, &mut buf).unwrap_or(0);
+    assert!(
+        !String::from_utf8_lossy(&buf[..sent]).contains("capture"),
+        "command leaked to the device"
+    );
+    assert!(harness.is_running());
+}
+
+#[tokio::test]
+#[serial_test::serial]
+async fn test_console_pause_output() {
+    let mut harness = ConsoleTestHarness::start(LogLevel::Info).await;
+    unsafe {
+        let flags = libc::fcntl(harness.console_master, libc::F_GETFL);
+        libc::fcntl(
+            harness.console_master,
+            libc::F_SETFL,
+            flags | libc::O_NONBLOCK,
+        );
+    }
+    let mut buf = [0u8; 1024];
+    let mut read_console = |received: &mut String| {
+        while let Ok(n) = read_fd(harness.console_master, &mut buf) {
+            if n == 0 {
+                break;
+            }
+            received.push_str(&String::from_utf8_lossy(&buf[..n]));
+        }
+    };
+
+    // Ctrl+A p pauses, device output is held back
+    write_fd(harness.console_master, &[0x01, b'p']).expect("Failed to write Ctrl+A p");
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    write_fd(harness.device_master, b"while paused").expect("Failed to write to device");
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let mut received = String::new();
+    read_console(&mut received);
+    tprintln!("Console received while paused: {:?}", received);
+    assert!(received.contains("PAUSED"));
+    assert!(!received.contains("while paused"));
+
+    // Ctrl+A p again resumes and shows what was held
+    write_fd(harness.console_master, &[0x01, b'p']).expect("Failed to write Ctrl+A p");
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let mut received = String::new();
+    read_console(&mut received);
+    tprintln!("Console received after resume: {:?}", received);
+    assert!(received.contains("while paused"));
+    assert!(harness.is_running());
+}
+
+#[tokio::test]
+#[serial_test::serial]
+async fn test_console_hex_input() {
+    let mut harness = ConsoleTestHarness::start(LogLevel::Info).await;
+
+    // Ctrl+A h opens the hex prompt, Enter sends the bytes
+    write_fd(harness.console_master, &[0x01, b'h']).expect("Failed to write Ctrl+A h");
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    write_fd(harness.console_master, b"68 0x690a\r").expect("Failed to write hex");
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    unsafe {
+        let flags = libc::fcntl(harness.device_master, libc::F_GETFL);
+        libc::fcntl(
+            harness.device_master,
+            libc::F_SETFL,
+            flags | libc::O_NONBLOCK,
+        );
+    }
+    let mut buf = [0u8; 256];
+    let sent = read_fd(harness.device_master, &mut buf).unwrap_or(0);
+    assert_eq!(&buf[..sent], b"hi\n");
+    assert!(harness.is_running());
+}
+
+#[tokio::test]
+#[serial_test::serial]
+async fn test_console_line_edit_history() {
+    let dir = std::env::temp_dir().join(format!("crabterm-line-edit-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let history = dir.join("history");
+    let config = dir.join("config");
+    std::fs::write(
+        &config,
+        format!(
+            "prefix Ctrl+a\nset line-edit on\nset history-file \"{}\"\n",
+            history.display()
+        ),
+    )
+    .unwrap();
+
+    let mut harness = ConsoleTestHarness::start_with_args(
+        LogLevel::Info,
+        &["--config", config.to_str().unwrap()],
+    )
+    .await;
+    unsafe {
+        let flags = libc::fcntl(harness.device_master, libc::F_GETFL);
+        libc::fcntl(
+            harness.device_master,
+            libc::F_SETFL,
+            flags | libc::O_NONBLOCK,
+        );
+    }
+    let mut buf = [0u8; 256];
+
+    // Nothing reaches the device until Enter
+    write_fd(harness.console_master, b"versio").expect("Failed to write");
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(read_fd(harness.device_master, &mut buf).is_err());
+    write_fd(harness.console_master, b"n\r").expect("Failed to write");
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let sent = read_fd(harness.device_master, &mut buf).unwrap_or(0);
+    assert_eq!(&buf[..sent], b"version\r");
+
+    // Up recalls the line
+    write_fd(harness.console_master, b"\x1b[A\r").expect("Failed to write");
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let sent = read_fd(harness.device_master, &mut buf).unwrap_or(0);
+    assert_eq!(&buf[..sent], b"version\r");
+
+    assert_eq!(std::fs::read_to_string(&history).unwrap(), "version\n");
+    assert!(harness.is_running());
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+fn process_state(pid: i32) -> char {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).unwrap_or_default();
+    // pid (comm) S ...
+    stat.rsplit(')')
+        .next()
+        .and_then(|rest| rest.trim_start().chars().next())
+        .unwrap_or('?')
+}
+
+fn is_canonical(fd: i32) -> bool {
+    let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+    unsafe { libc::tcgetattr(fd, &mut termios) };
+    termios.c_lflag & libc::ICANON != 0
+}
+
+#[tokio::test]
+#[serial_test::serial]
+async fn test_console_suspend_resume() {
+    let mut harness = ConsoleTestHarness::start(LogLevel::Info).await;
+    let pid = harness.crabterm.id() as i32;
+    assert!(!is_canonical(harness.console_slave));
+    unsafe {
+        let flags = libc::fcntl(harness.console_master, libc::F_GETFL);
+        libc::fcntl(
+            harness.console_master,
+            libc::F_SETFL,
+            flags | libc::O_NONBLOCK,
+        );
+    }
+
+    // Pause, so there is a status line to redraw
+    write_fd(harness.console_master, &[0x01, b'p']).expect("Failed to write Ctrl+A p");
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let mut buf = [0u8; 4096];
+    let _ = read_fd(harness.console_master, &mut buf);
+
+    // Stopped with the terminal back in its original mode
+    unsafe { libc::kill(pid, libc::SIGTSTP) };
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(process_state(pid), 'T');
+    assert!(is_canonical(harness.console_slave));
+
+    // Raw mode again and the status line redrawn
+    unsafe { libc::kill(pid, libc::SIGCONT) };
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_ne!(process_state(pid), 'T');
+    assert!(!is_canonical(harness.console_slave));
+    let n = read_fd(harness.console_master, &mut buf).unwrap_or(0);
+    let received = String::from_utf8_lossy(&buf[..n]);
+    tprintln!("Console received after continue: {:?}", received);
+    assert!(received.contains("PAUSED"));
+    assert!(harness.is_running());
+}
+
+/// Spawn crabterm with stdin a pipe that is closed at once, and return
+/// whether it exited within 1s.
+fn exits_on_stdin_eof(extra_args: &[&str]) -> bool {
+    let (device_master, device_slave) = create_pty().expect("Failed to create device PTY");
+    let device_path = get_pty_path(device_slave).expect("Failed to get device path");
+
+    let mut cmd = Command::new(env!("CARGO_BIN_EXE_crabterm"));
+    cmd.arg("-d").arg(&device_path).arg("--no-announce");
+    cmd.args(extra_args);
+    cmd.stdin(Stdio::piped()).stdout(Stdio::null());
+    tprintln!("Spawning crabterm: {:?}", cmd);
+    let mut crabterm = cmd.spawn().expect("Failed to spawn crabterm");
+    // Piped input ends
+    drop(crabterm.stdin.take());
+
+    let deadline = std::time::Instant::now() + Duration::from_secs(1);
+    let mut exited = false;
+    while std::time::Instant::now() < deadline {
+        if let Ok(Some(_)) = crabterm.try_wait() {
+            exited = true;
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    let _ = crabterm.kill();
+    let _ = crabterm.wait();
+    unsafe {
+        libc::close(device_master);
+        libc::close(device_slave);
+    }
+    exited
+}
+
+#[test]
+#[serial_test::serial]
+fn test_console_stdin_eof() {
+    assert!(exits_on_stdin_eof(&[]), "Crabterm should quit on stdin EOF");
+
+    let config = std::env::temp_dir().join(format!("crabterm-eof-{}", std::process::id()));
+    std::fs::write(&config, "set stdin-eof headless\n").unwrap();
+    let exited = exits_on_stdin_eof(&["--config", config.to_str().unwrap()]);
+    let _ = std::fs::remove_file(&config);
+    assert!(
+        !exited,
+        "Crabterm should keep running headless on stdin EOF"
+    );
+}
+
+/// A stdout that is not read, like a terminal stopped with Ctrl+S, must not
+/// stall the hub: with drop-data the output that does not fit is dropped,
+/// and Ctrl+Q still quits.
+#[test]
+#[serial_test::serial]
+fn test_console_stdout_not_read() {
+    let (device_master, device_slave) = create_pty().expect("Failed to create device PTY");
+    let device_path = get_pty_path(device_slave).expect("Failed to get device path");
+    let (console_master, console_slave) = create_pty().expect("Failed to create console PTY");
+
+    let mut cmd = Command::new(env!("CARGO_BIN_EXE_crabterm"));
+    cmd.arg("-d")
+        .arg(&device_path)
+        .arg("--no-announce")
+        .arg("--slow-client-policy")
+        .arg("drop-data")
+        .arg("--drain-timeout")
+        .arg("0.2");
+    unsafe {
+        cmd.stdin(Stdio::from_raw_fd(libc::dup(console_slave)));
+        cmd.stderr(Stdio::from_raw_fd(libc::dup(console_slave)));
+    }
+    cmd.stdout(Stdio::piped());
+
+    tprintln!("Spawning crabterm: {:?}", cmd);
+    let mut crabterm = cmd.spawn().expect("Failed to spawn crabterm");
+    let stdout = crabterm.stdout.take().unwrap();
+    std::thread::sleep(Duration::from_millis(500));
+
+    // Far more than the pipe takes
+    unsafe {
+        let flags = libc::fcntl(device_master, libc::F_GETFL);
+        libc::fcntl(device_master, libc::F_SETFL, flags | libc::O_NONBLOCK);
+    }
+    let chunk = [b'x'; 4096];
+    let mut written = 0;
+    let deadline = std::time::Instant::now() + Duration::from_secs(2);
+    while written < 1024 * 1024 && std::time::Instant::now() < deadline {
+        match write_fd(device_master, &chunk) {
+            Ok(n) => written += n,
+            Err(_) => std::thread::sleep(Duration::from_millis(5)),
+        }
+    }
+    tprintln!("Wrote {} bytes to the device", written);
+
+    write_fd(console_master, &[0x11]).expect("Failed to write Ctrl+Q");
+    let deadline = std::time::Instant::now() + Duration::from_secs(2);
+    let mut exited = false;
+    while std::time::Instant::now() < deadline {
+        if let Ok(Some(_)) = crabterm.try_wait() {
+            exited = true;
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    let _ = crabterm.kill();
+    let _ = crabterm.wait();
+    drop(stdout);
+    unsafe {
+        libc::close(device_master);
+        libc::close(device_slave);
+        libc::close(console_master);
+        libc::close(console_slave);
+    }
+
+    assert!(written >= 1024 * 1024, "The device should be read on");
+    assert!(
+        exited,
+        "Crabterm should exit on Ctrl+Q with stdout not read"
+    );
+}
@@ -0,0 +1,102 @@
+//! Lock files of serial devices: a second crabterm on a port is refused
+//! with the owner, and `--steal` has the owner release it.
+
+#![cfg(unix)]
+
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// The path of the slave of a new pty, and the master to keep it open
+fn pty() -> (i32, String) {
+    let mut master = -1;
+    let mut slave = -1;
+    let ret = unsafe {
+        libc::openpty(
+            &mut master,
+            &mut slave,
+            std::ptr::null_mut(),
+            std::ptr::null(),
+            std::ptr::null(),
+        )
+    };
+    assert_eq!(ret, 0, "openpty failed");
+    let name = unsafe { std::ffi::CStr::from_ptr(libc::ttyname(slave)) };
+    let path = name.to_string_lossy().to_string();
+    unsafe { libc::close(slave) };
+    (master, path)
+}
+
+fn crabterm(device: &str, config: &Path, extra: &[&str]) -> Command {
+    let mut cmd = Command::new(env!("CARGO_BIN_EXE_crabterm"));
+    cmd.args([
+        device,
+        "--headless",
+        "-p",
+        "0",
+        "-c",
+        config.to_str().unwrap(),
+    ])
+    .args(extra)
+    .stdout(Stdio::piped());
+    cmd
+}
+
+/// Wait for the lock file to name `pid`
+fn wait_for_lock(lock: &Path, pid: u32) -> bool {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while Instant::now() < deadline {
+        let text = std::fs::read_to_string(lock).unwrap_or_default();
+        if text.lines().next().map(|l| l.trim()) == Some(&pid.to_string()) {
+            return true;
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    false
+}
+
+fn kill(child: &mut Child) {
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+#[test]
+fn test_refuse_and_steal() {
+    let (master, device) = pty();
+    let dir = std::env::temp_dir().join(format!("crabterm_device_lock_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir(&dir).unwrap();
+    let config = dir.join("config");
+    std::fs::write(&config, format!("set lock-dir {}\n", dir.display())).unwrap();
+    let control: PathBuf = dir.join("ctl.sock");
+    let lock = dir.join(format!(
+        "LCK..{}",
+        Path::new(&device).file_name().unwrap().to_string_lossy()
+    ));
+
+    let mut first = crabterm(&device, &config, &["--control", control.to_str().unwrap()])
+        .spawn()
+        .unwrap();
+    assert!(wait_for_lock(&lock, first.id()));
+
+    let second = crabterm(&device, &config, &[]).output().unwrap();
+    let stdout = String::from_utf8_lossy(&second.stdout);
+    assert_eq!(second.status.code(), Some(3), "{}", stdout);
+    assert!(
+        stdout.contains(&format!("in use by pid {}", first.id())),
+        "{}",
+        stdout
+    );
+    assert!(stdout.contains("--steal"), "{}", stdout);
+
+    let mut third = crabterm(&device, &config, &["--steal"]).spawn().unwrap();
+    let stolen = wait_for_lock(&lock, third.id());
+    // Only the device is given up, the first crabterm carries on without it
+    let running = first.try_wait().unwrap().is_none();
+    kill(&mut third);
+    kill(&mut first);
+    unsafe { libc::close(master) };
+    let _ = std::fs::remove_dir_all(&dir);
+    assert!(stolen);
+    assert!(running);
+}
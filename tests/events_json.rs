@@ -0,0 +1,27 @@
+//! `--events-json`: the events of a run as JSON lines on stdout.
+
+#![cfg(unix)]
+
+mod common;
+
+use common::{run_crabterm, scripted_board};
+
+#[test]
+fn test_events_json() {
+    let addr = scripted_board(b"Booting\r\nlogin: ");
+    let output = run_crabterm(&[&addr, "--events-json", "--success-pattern", "login: $"]);
+    assert_eq!(output.status.code(), Some(0));
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let events: Vec<serde_json::Value> = stdout
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap_or_else(|e| panic!("{}: {}", e, l)))
+        .collect();
+    assert_eq!(events[0]["type"], "connect", "{}", stdout);
+    let text: String = events
+        .iter()
+        .filter(|e| e["type"] == "data")
+        .map(|e| e["text"].as_str().unwrap())
+        .collect();
+    assert_eq!(text, "Booting\r\nlogin: ");
+}
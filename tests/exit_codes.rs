@@ -0,0 +1,77 @@
+//! Exit codes for the different kinds of start-up failure.
+
+use std::net::TcpListener;
+use std::process::{Command, Output};
+
+fn crabterm(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_crabterm"))
+        .args(args)
+        .output()
+        .expect("Failed to run crabterm")
+}
+
+#[test]
+fn test_bad_args() {
+    let output = crabterm(&["--headless"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Error: No device specified"));
+
+    let output = crabterm(&["echo", "--headless"]);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn test_seconds_out_of_range() {
+    for arg in ["--silence-timeout=1e30", "--failover-timeout=-1"] {
+        let output = crabterm(&["echo", "--headless", "-p", "0", "--fallback", "echo", arg]);
+        assert_eq!(output.status.code(), Some(2), "{}", arg);
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(stdout.contains("invalid seconds"), "{}", stdout);
+    }
+}
+
+#[test]
+fn test_port_in_use() {
+    let listener = TcpListener::bind("0.0.0.0:0").unwrap();
+    let port = listener.local_addr().unwrap().port().to_string();
+    let output = crabterm(&["echo", "--headless", "-p", &port]);
+    assert_eq!(output.status.code(), Some(4));
+    assert!(String::from_utf8_lossy(&output.stdout).contains(&format!("Error: port {}", port)));
+}
+
+#[test]
+fn test_config_error() {
+    let config = std::env::temp_dir().join(format!("crabterm_exit_codes_{}", std::process::id()));
+    std::fs::write(&config, "set slow-client-policy sometimes\n").unwrap();
+    let output = crabterm(&[
+        "echo",
+        "--headless",
+        "-p",
+        "0",
+        "-c",
+        config.to_str().unwrap(),
+    ]);
+    let _ = std::fs::remove_file(&config);
+    assert_eq!(output.status.code(), Some(5));
+}
+
+#[test]
+fn test_malformed_config() {
+    let config = std::env::temp_dir().join(format!("crabterm_malformed_{}", std::process::id()));
+    std::fs::write(&config, "map-prefix x no-such-action\n").unwrap();
+    let output = crabterm(&[
+        "echo",
+        "--headless",
+        "-p",
+        "0",
+        "-c",
+        config.to_str().unwrap(),
+    ]);
+    let _ = std::fs::remove_file(&config);
+    assert_eq!(output.status.code(), Some(5));
+    assert!(
+        String::from_utf8_lossy(&output.stdout).contains("Failed to parse"),
+        "{}",
+        String::from_utf8_lossy(&output.stdout)
+    );
+}
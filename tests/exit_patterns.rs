@@ -0,0 +1,32 @@
+//! `--success-pattern` and `--failure-pattern`: the exit status of a run
+//! follows the output of the device.
+
+#![cfg(unix)]
+
+mod common;
+
+use common::{run_crabterm, scripted_board};
+
+#[test]
+fn test_exit_patterns() {
+    let args = [
+        "--headless",
+        "--success-pattern",
+        "login: $",
+        "--failure-pattern",
+        "Kernel panic",
+    ];
+
+    let addr = scripted_board(b"U-Boot 2024.01\r\nStarting kernel\r\nboard login: ");
+    let output = run_crabterm(&[&[addr.as_str()], &args[..]].concat());
+    assert_eq!(output.status.code(), Some(0));
+
+    let addr = scripted_board(b"Starting kernel\r\nKernel panic - not syncing\r\n");
+    let output = run_crabterm(&[&[addr.as_str()], &args[..]].concat());
+    assert_eq!(output.status.code(), Some(1));
+    assert!(
+        String::from_utf8_lossy(&output.stdout).contains("failure: Kernel panic - not syncing"),
+        "{}",
+        String::from_utf8_lossy(&output.stdout)
+    );
+}
@@ -0,0 +1,114 @@
+#![cfg(unix)]
+
+#[macro_use]
+mod common;
+
+use common::{find_available_port, wait_for_port};
+use std::net::TcpStream;
+use std::time::Duration;
+
+#[tokio::test]
+async fn test_log_format_json() {
+    let port = find_available_port().await;
+    let log_file = std::env::temp_dir().join(format!(
+        "crabterm_log_format_test_{}.log",
+        std::process::id()
+    ));
+    let _ = std::fs::remove_file(&log_file);
+
+    let mut child = std::process::Command::new(env!("CARGO_BIN_EXE_crabterm"))
+        .arg("echo")
+        .arg("-p")
+        .arg(port.to_string())
+        .arg("--headless")
+        .arg("--log-file")
+        .arg(&log_file)
+        .arg("--log-format")
+        .arg("json")
+        .stdout(std::process::Stdio::null())
+        .spawn()
+        .expect("Failed to spawn");
+
+    assert!(wait_for_port(port, 2000).await);
+    let client = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    drop(client);
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+
+    let log = std::fs::read_to_string(&log_file).unwrap();
+    let _ = std::fs::remove_file(&log_file);
+    tprintln!("Log:\n{}", log);
+
+    let lines: Vec<serde_json::Value> = log
+        .lines()
+        .map(|l| serde_json::from_str(l).expect("every log line must be JSON"))
+        .collect();
+    assert!(
+        lines
+            .iter()
+            .any(|l| l["message"] == "Starting crabterm" && l["level"] == "INFO")
+    );
+    assert!(lines.iter().all(|l| l["timestamp"].is_string()));
+
+    let events: Vec<&str> = lines
+        .iter()
+        .filter_map(|l| l["fields"]["event"].as_str())
+        .collect();
+    assert!(events.contains(&"device_connect"), "events: {:?}", events);
+    assert!(events.contains(&"client_connect"), "events: {:?}", events);
+    assert!(
+        events.contains(&"client_disconnect"),
+        "events: {:?}",
+        events
+    );
+    let connect = lines
+        .iter()
+        .find(|l| l["fields"]["event"] == "client_connect")
+        .unwrap();
+    assert!(connect["fields"]["token"].is_u64());
+}
+
+#[tokio::test]
+async fn test_trace_io() {
+    let port = find_available_port().await;
+    let log_file =
+        std::env::temp_dir().join(format!("crabterm_trace_io_test_{}.log", std::process::id()));
+    let _ = std::fs::remove_file(&log_file);
+
+    // Log level stays at info, --trace-io enables the dumps on their own
+    let mut child = std::process::Command::new(env!("CARGO_BIN_EXE_crabterm"))
+        .arg("echo")
+        .arg("-p")
+        .arg(port.to_string())
+        .arg("--headless")
+        .arg("--log-file")
+        .arg(&log_file)
+        .arg("--trace-io")
+        .stdout(std::process::Stdio::null())
+        .spawn()
+        .expect("Failed to spawn");
+
+    assert!(wait_for_port(port, 2000).await);
+    let mut client = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
+    std::io::Write::write_all(&mut client, b"hello\n").unwrap();
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+
+    let log = std::fs::read_to_string(&log_file).unwrap();
+    let _ = std::fs::remove_file(&log_file);
+    tprintln!("Log:\n{}", log);
+
+    assert!(log.contains("TX 6 bytes"), "missing TX dump");
+    assert!(log.contains("RX 6 bytes"), "missing RX dump");
+    assert!(log.contains("0000: 68 65 6c 6c 6f 0a "));
+    assert!(log.contains("|hello.|"));
+    assert!(
+        !log.contains("handle_event"),
+        "only the I/O trace is enabled"
+    );
+}
@@ -0,0 +1,86 @@
+#![cfg(unix)]
+
+#[macro_use]
+mod common;
+
+use common::{find_available_port, wait_for_port};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+
+fn scrape(port: u16) -> String {
+    let mut s = TcpStream::connect(format!("127.0.0.1:{}", port)).expect("connect metrics");
+    s.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+    s.write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    let mut response = String::new();
+    let _ = s.read_to_string(&mut response);
+    response
+}
+
+fn metric(response: &str, name: &str) -> Option<u64> {
+    response
+        .lines()
+        .find_map(|l| l.strip_prefix(name)?.strip_prefix(' ')?.parse().ok())
+}
+
+#[tokio::test]
+async fn test_metrics_counters() {
+    let crabterm_port = find_available_port().await;
+    let metrics_port = find_available_port().await;
+
+    let mut child = std::process::Command::new(env!("CARGO_BIN_EXE_crabterm"))
+        .arg("echo")
+        .arg("-p")
+        .arg(crabterm_port.to_string())
+        .arg("--metrics-port")
+        .arg(metrics_port.to_string())
+        .arg("--headless")
+        .arg("--no-announce")
+        .stdout(std::process::Stdio::null())
+        .spawn()
+        .expect("Failed to spawn");
+
+    assert!(wait_for_port(crabterm_port, 2000).await);
+    assert!(wait_for_port(metrics_port, 2000).await);
+
+    let mut client =
+        TcpStream::connect(format!("127.0.0.1:{}", crabterm_port)).expect("connect client");
+    client
+        .set_read_timeout(Some(Duration::from_millis(500)))
+        .unwrap();
+    client.write_all(b"hello\n").unwrap();
+
+    let mut echoed = Vec::new();
+    let mut buf = [0u8; 64];
+    let start = Instant::now();
+    while echoed.len() < 6 && start.elapsed() < Duration::from_secs(2) {
+        if let Ok(n) = client.read(&mut buf) {
+            echoed.extend_from_slice(&buf[..n]);
+        }
+    }
+    assert_eq!(echoed, b"hello\n");
+
+    let response = scrape(metrics_port);
+    tprintln!("Metrics response:\n{}", response);
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.contains("# TYPE crabterm_device_rx_bytes_total counter"));
+    assert_eq!(metric(&response, "crabterm_device_tx_bytes_total"), Some(6));
+    assert_eq!(metric(&response, "crabterm_device_rx_bytes_total"), Some(6));
+    // wait_for_port() also opens a connection to the server port
+    assert_eq!(
+        metric(&response, "crabterm_client_connections_total"),
+        Some(2)
+    );
+    assert_eq!(metric(&response, "crabterm_device_connected"), Some(1));
+
+    let mut s = TcpStream::connect(format!("127.0.0.1:{}", metrics_port)).unwrap();
+    s.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+    s.write_all(b"GET /other HTTP/1.1\r\n\r\n").unwrap();
+    let mut response = String::new();
+    let _ = s.read_to_string(&mut response);
+    assert!(response.starts_with("HTTP/1.1 404"));
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+}
@@ -1,3 +1,5 @@
+#![cfg(unix)]
+
 #[macro_use]
 mod common;
 
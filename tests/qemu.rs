@@ -0,0 +1,31 @@
+//! `qemu:` devices: waiting for the chardev of a VM that is not started yet.
+
+#![cfg(unix)]
+
+use std::io::Write;
+use std::os::unix::net::UnixListener;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+#[test]
+fn test_waits_for_socket() {
+    let path = std::env::temp_dir().join(format!("crabterm_qemu_{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let device = format!("qemu:unix:{},server=on,wait=off?mux=1", path.display());
+    let mut crabterm = Command::new(env!("CARGO_BIN_EXE_crabterm"))
+        .args([&device, "--headless", "--success-pattern", "login: $"])
+        .stdout(Stdio::null())
+        .spawn()
+        .expect("Failed to run crabterm");
+
+    // The VM starts later
+    std::thread::sleep(Duration::from_millis(300));
+    let listener = UnixListener::bind(&path).unwrap();
+    let (mut vm, _) = listener.accept().unwrap();
+    vm.write_all(b"Booting\r\nlogin: ").unwrap();
+
+    let status = crabterm.wait().unwrap();
+    let _ = std::fs::remove_file(&path);
+    // It connected, and saw the prompt
+    assert_eq!(status.code(), Some(0));
+}
@@ -0,0 +1,108 @@
+#![cfg(unix)]
+
+use std::io::{Read, Write};
+use std::os::unix::fs::DirBuilderExt;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+fn runtime_dir(test: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("crabterm-{}-{}", test, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn read_until(stream: &mut UnixStream, expected: &[u8]) -> Vec<u8> {
+    stream
+        .set_read_timeout(Some(Duration::from_millis(200)))
+        .unwrap();
+    let mut received = Vec::new();
+    let mut buf = [0u8; 256];
+    let start = Instant::now();
+    while !received.ends_with(expected) && start.elapsed() < Duration::from_secs(2) {
+        if let Ok(n) = stream.read(&mut buf) {
+            received.extend_from_slice(&buf[..n]);
+        }
+    }
+    received
+}
+
+#[test]
+fn test_detach_and_attach() {
+    let dir = runtime_dir("detach");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_crabterm"))
+        .env("XDG_RUNTIME_DIR", &dir)
+        .args(["echo", "--detach", "--session", "board", "--no-announce"])
+        .output()
+        .expect("Failed to run");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}", stdout);
+    assert!(stdout.contains("crabterm attach board"), "{}", stdout);
+
+    // The session keeps running after the starting process exited
+    let socket = dir.join("crabterm/board.sock");
+    assert!(Path::new(&socket).exists());
+    let mut client = UnixStream::connect(&socket).expect("connect session");
+    client.write_all(b"hello\n").unwrap();
+    assert_eq!(read_until(&mut client, b"hello\n"), b"hello\n");
+
+    // A second session of the same name is refused
+    let output = Command::new(env!("CARGO_BIN_EXE_crabterm"))
+        .env("XDG_RUNTIME_DIR", &dir)
+        .args(["echo", "--detach", "--session", "board"])
+        .output()
+        .expect("Failed to run");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("already running"));
+
+    // Clients can detach and attach again
+    drop(client);
+    let mut client = UnixStream::connect(&socket).expect("reconnect session");
+    client.write_all(b"again\n").unwrap();
+    assert_eq!(read_until(&mut client, b"again\n"), b"again\n");
+
+    let pid = stdout
+        .split("pid ")
+        .nth(1)
+        .and_then(|s| s.split(')').next())
+        .and_then(|s| s.parse::<i32>().ok())
+        .expect("pid in output");
+    unsafe { libc::kill(pid, libc::SIGTERM) };
+    let start = Instant::now();
+    while socket.exists() && start.elapsed() < Duration::from_secs(2) {
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    assert!(!socket.exists(), "socket removed on exit");
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_attach_errors() {
+    let dir = runtime_dir("attach");
+    let attach = |dir: &Path| {
+        Command::new(env!("CARGO_BIN_EXE_crabterm"))
+            .env("XDG_RUNTIME_DIR", dir)
+            .args(["attach", "board"])
+            .output()
+            .expect("Failed to run")
+    };
+
+    let output = attach(&dir);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(2), "{}", stdout);
+    assert!(stdout.contains("no detached sessions"), "{}", stdout);
+
+    // A session directory others may enter is not trusted
+    std::fs::DirBuilder::new()
+        .mode(0o755)
+        .create(dir.join("crabterm"))
+        .unwrap();
+    let output = attach(&dir);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(6), "{}", stdout);
+    assert!(stdout.contains("with mode 0700"), "{}", stdout);
+    let _ = std::fs::remove_dir_all(&dir);
+}
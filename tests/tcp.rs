@@ -1,3 +1,5 @@
+#![cfg(unix)]
+
 #[macro_use]
 mod common;
 
@@ -20,15 +22,28 @@ struct TestHarness {
 
 impl TestHarness {
     async fn start(log_level: LogLevel) -> Self {
+        Self::start_with_config(log_level, None).await
+    }
+
+    async fn start_with_config(log_level: LogLevel, config: Option<&str>) -> Self {
         let device_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
         let device_port = device_listener.local_addr().unwrap().port();
 
         let crabterm_port = find_available_port().await;
-        let crabterm = CrabtermProcess::builder()
+        let mut builder = CrabtermProcess::builder()
             .device(&format!("127.0.0.1:{}", device_port))
             .listen(crabterm_port)
-            .log_level(log_level)
-            .spawn();
+            .log_level(log_level);
+        if let Some(config) = config {
+            let path = std::env::temp_dir().join(format!(
+                "crabterm_tcp_test_{}_{}.conf",
+                std::process::id(),
+                crabterm_port
+            ));
+            std::fs::write(&path, config).unwrap();
+            builder = builder.config(path);
+        }
+        let crabterm = builder.spawn();
 
         let (device_socket, _) = timeout(Duration::from_secs(2), device_listener.accept())
             .await
@@ -131,7 +146,6 @@ async fn test_tcp_reconnects_after_server_disconnect() {
     drop(device_listener);
 
     // Give crabterm time to detect disconnection (needs to attempt read/write)
-    // The hub polls every 100ms, so we need at least a couple of ticks
     tokio::time::sleep(Duration::from_millis(300)).await;
 
     // Trigger crabterm to notice disconnect by sending data through client
@@ -142,7 +156,7 @@ async fn test_tcp_reconnects_after_server_disconnect() {
     // Start a new server on the SAME port
     let device_listener2 = TcpListener::bind(&device_addr).await.unwrap();
 
-    // Crabterm should reconnect (give it more time - reconnect happens on 100ms ticks)
+    // Crabterm should reconnect (give it more time - reconnects are retried every 100ms)
     let reconnect_result = timeout(Duration::from_secs(10), device_listener2.accept()).await;
 
     assert!(
@@ -683,13 +697,123 @@ async fn test_slow_client_does_not_block_fast_client() {
     assert!(
         fast_received > total_bytes_sent / 2,
         "FAILED: Fast client only received {}% of data (expected >50%)",
-        if total_bytes_sent > 0 {
-            100 * fast_received / total_bytes_sent
-        } else {
-            0
-        }
+        (100 * fast_received)
+            .checked_div(total_bytes_sent)
+            .unwrap_or(0)
     );
 
     // Keep fast_writer alive until end of test (dropping it causes EOF on server)
     drop(fast_writer);
 }
+
+/// Send `total` bytes from the device in 1 KiB chunks, and return how many were
+/// accepted before a write stalled for 500ms.
+async fn send_from_device(device_socket: &mut tokio::net::TcpStream, total: usize) -> usize {
+    let chunk = vec![b'X'; 1024];
+    let mut sent = 0;
+    while sent < total {
+        match timeout(Duration::from_millis(500), device_socket.write_all(&chunk)).await {
+            Ok(Ok(())) => sent += chunk.len(),
+            _ => break,
+        }
+    }
+    sent
+}
+
+/// With drop-data a slow client loses output but stays connected.
+#[tokio::test]
+async fn test_slow_client_policy_drop_data() {
+    let TestHarness {
+        mut device_socket,
+        crabterm_port,
+        mut crabterm,
+        ..
+    } = TestHarness::start_with_config(
+        LogLevel::Info,
+        Some("set slow-client-policy drop-data\nset client-buffer-limit 65536\n"),
+    )
+    .await;
+
+    let mut slow_client = TcpStream::connect(format!("127.0.0.1:{}", crabterm_port)).unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let total = 8 * 1024 * 1024;
+    assert_eq!(send_from_device(&mut device_socket, total).await, total);
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    slow_client
+        .set_read_timeout(Some(Duration::from_millis(500)))
+        .unwrap();
+    let mut total_read = 0;
+    let mut buf = [0u8; 65536];
+    loop {
+        match slow_client.read(&mut buf) {
+            Ok(0) => panic!("Slow client was disconnected after {} bytes", total_read),
+            Ok(n) => total_read += n,
+            Err(_) => break,
+        }
+    }
+    tprintln!("Slow client received {} bytes", total_read);
+    assert!(total_read > 0 && total_read < total);
+
+    // Still connected: new output arrives
+    device_socket.write_all(b"after").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let n = slow_client.read(&mut buf).unwrap();
+    assert!(buf[..n].ends_with(b"after"));
+
+    assert!(crabterm.is_running());
+    crabterm.stop();
+}
+
+/// With block a slow client holds up the device until it reads again, and
+/// nothing is lost.
+#[tokio::test]
+async fn test_slow_client_policy_block() {
+    let TestHarness {
+        mut device_socket,
+        crabterm_port,
+        mut crabterm,
+        ..
+    } = TestHarness::start_with_config(
+        LogLevel::Info,
+        Some("set slow-client-policy block\nset client-buffer-limit 65536\n"),
+    )
+    .await;
+
+    let mut slow_client = TcpStream::connect(format!("127.0.0.1:{}", crabterm_port)).unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let total = 32 * 1024 * 1024;
+    let sent = send_from_device(&mut device_socket, total).await;
+    tprintln!("Device sent {} bytes before blocking", sent);
+    assert!(
+        sent < total,
+        "The device should be blocked by the slow client"
+    );
+
+    // Reading everything unblocks the device, and all of it arrives
+    let reader = std::thread::spawn(move || {
+        slow_client
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let mut total_read = 0;
+        let mut buf = [0u8; 65536];
+        while let Ok(n) = slow_client.read(&mut buf) {
+            if n == 0 {
+                break;
+            }
+            total_read += n;
+        }
+        total_read
+    });
+    let rest = send_from_device(&mut device_socket, total - sent).await;
+    assert_eq!(sent + rest, total, "The device should be unblocked");
+    let total_read = reader.join().unwrap();
+    tprintln!("Slow client received {} bytes", total_read);
+    // Announcements come on top
+    assert!(total_read >= total, "Output was lost");
+
+    assert!(crabterm.is_running());
+    crabterm.stop();
+}